@@ -0,0 +1,441 @@
+//! Per-day activity for `/api/v1/stats/daily`, `/api/v1/stats/languages`
+//! and the sparkline on the index. Finished days are rolled up into
+//! `daily_stats` and `daily_languages` in the background, so only today is
+//! counted from the event log on request.
+
+use crate::db;
+use crate::models::{AppState, DailyActivity, DailyLanguages, DayCounts, DayLanguageCount};
+use crate::utils::{format_iso8601, parse_utc_offset};
+use sqlx::SqlitePool;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// How long a computed series is served before the event log is asked
+/// again.
+const CACHE_SECS: i64 = 300;
+
+/// The longest series `/api/v1/stats/daily` returns.
+pub const MAX_DAYS: i64 = 366;
+
+/// How often finished days are rolled up. Rolling up is idempotent, so
+/// checking more often than daily only means yesterday lands sooner.
+const ROLLUP_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// A series and when it was computed.
+type CachedActivity = (i64, Vec<DailyActivity>);
+
+/// A language series, by every language seen, and when it was computed.
+type CachedLanguages = (i64, Vec<DailyLanguages>);
+
+/// Recent series, by length and UTC offset.
+#[derive(Default)]
+pub struct ActivityCache {
+    entries: Mutex<HashMap<(i64, i32), CachedActivity>>,
+    languages: Mutex<HashMap<(i64, i32), CachedLanguages>>,
+}
+
+impl ActivityCache {
+    /// The last `days` days up to `now`, from the cache while it is fresh.
+    pub async fn get(
+        &self,
+        pool: &SqlitePool,
+        days: i64,
+        offset_secs: i32,
+        now: i64,
+    ) -> Result<Vec<DailyActivity>, sqlx::Error> {
+        let key = (days, offset_secs);
+        if let Some((at, activity)) = self.entries.lock().unwrap().get(&key)
+            && now - at < CACHE_SECS
+        {
+            return Ok(activity.clone());
+        }
+        let activity = daily_activity(pool, days, offset_secs, now).await?;
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, (at, _)| now - *at < CACHE_SECS);
+        entries.insert(key, (now, activity.clone()));
+        Ok(activity)
+    }
+
+    /// The last `days` days of creations per language up to `now`, from
+    /// the cache while it is fresh.
+    pub async fn get_languages(
+        &self,
+        pool: &SqlitePool,
+        days: i64,
+        offset_secs: i32,
+        now: i64,
+    ) -> Result<Vec<DailyLanguages>, sqlx::Error> {
+        let key = (days, offset_secs);
+        if let Some((at, languages)) = self.languages.lock().unwrap().get(&key)
+            && now - at < CACHE_SECS
+        {
+            return Ok(languages.clone());
+        }
+        let languages = language_activity(pool, days, offset_secs, now).await?;
+        let mut entries = self.languages.lock().unwrap();
+        entries.retain(|_, (at, _)| now - *at < CACHE_SECS);
+        entries.insert(key, (now, languages.clone()));
+        Ok(languages)
+    }
+}
+
+fn by_day(counts: Vec<DayCounts>) -> HashMap<i64, DayCounts> {
+    counts
+        .into_iter()
+        .map(|counts| (counts.day, counts))
+        .collect()
+}
+
+/// One entry per local day (at `offset_secs` from UTC) for the last `days`
+/// days, oldest first. Days without events are zeros. Rolled-up days come
+/// from `daily_stats`; the rest, today at least, from the event log, where
+/// only days still in its retention window can have counts.
+pub async fn daily_activity(
+    pool: &SqlitePool,
+    days: i64,
+    offset_secs: i32,
+    now: i64,
+) -> Result<Vec<DailyActivity>, sqlx::Error> {
+    let offset = i64::from(offset_secs);
+    let today = (now + offset).div_euclid(86400);
+    let first = today - (days - 1);
+    let rolled = by_day(db::rolled_up_days(pool, offset, first, today - 1).await?);
+    let live_from = (first..=today)
+        .find(|day| !rolled.contains_key(day))
+        .unwrap_or(today);
+    let live = by_day(
+        db::counts_by_day(
+            pool,
+            live_from * 86400 - offset,
+            (today + 1) * 86400 - offset,
+            offset,
+        )
+        .await?,
+    );
+    Ok((first..=today)
+        .map(|day| {
+            let counts = rolled
+                .get(&day)
+                .or_else(|| live.get(&day))
+                .copied()
+                .unwrap_or_default();
+            DailyActivity {
+                date: format_iso8601(day * 86400, 0)[..10].to_string(),
+                created: counts.created,
+                expired: counts.expired,
+                views: counts.views,
+                burns: counts.burns,
+                evictions: counts.evictions,
+                bytes_added: counts.bytes_added,
+            }
+        })
+        .collect())
+}
+
+fn languages_by_day(counts: Vec<DayLanguageCount>) -> HashMap<i64, BTreeMap<String, i64>> {
+    let mut days: HashMap<i64, BTreeMap<String, i64>> = HashMap::new();
+    for count in counts {
+        *days
+            .entry(count.day)
+            .or_default()
+            .entry(count.language)
+            .or_default() += count.created;
+    }
+    days
+}
+
+/// Creations per language for the last `days` local days, oldest first,
+/// by the language each paste was stored as. Days are read like
+/// `daily_activity`'s; today is marked partial.
+pub async fn language_activity(
+    pool: &SqlitePool,
+    days: i64,
+    offset_secs: i32,
+    now: i64,
+) -> Result<Vec<DailyLanguages>, sqlx::Error> {
+    let offset = i64::from(offset_secs);
+    let today = (now + offset).div_euclid(86400);
+    let first = today - (days - 1);
+    let rolled = by_day(db::rolled_up_days(pool, offset, first, today - 1).await?);
+    let stored = languages_by_day(db::rolled_up_languages(pool, offset, first, today - 1).await?);
+    let live_from = (first..=today)
+        .find(|day| !rolled.contains_key(day))
+        .unwrap_or(today);
+    let mut live = languages_by_day(
+        db::languages_by_day(
+            pool,
+            live_from * 86400 - offset,
+            (today + 1) * 86400 - offset,
+            offset,
+        )
+        .await?,
+    );
+    Ok((first..=today)
+        .map(|day| {
+            let counts = if rolled.contains_key(&day) {
+                stored.get(&day).cloned()
+            } else {
+                live.remove(&day)
+            };
+            DailyLanguages {
+                date: format_iso8601(day * 86400, 0)[..10].to_string(),
+                partial: day == today,
+                counts: counts.unwrap_or_default(),
+            }
+        })
+        .collect())
+}
+
+/// `day` with its counts gathered under `languages`, every one of them
+/// present, and `other` for the rest.
+pub fn bucket_languages(day: DailyLanguages, languages: &[String]) -> DailyLanguages {
+    let mut counts: BTreeMap<String, i64> = languages
+        .iter()
+        .map(|language| (language.clone(), 0))
+        .collect();
+    let mut other = 0;
+    for (language, created) in day.counts {
+        match counts.get_mut(&language) {
+            Some(count) => *count += created,
+            None => other += created,
+        }
+    }
+    counts.insert("other".to_string(), other);
+    DailyLanguages { counts, ..day }
+}
+
+/// Store the counts of every finished local day not yet in `daily_stats`,
+/// going back as far as the event log still holds whole days, up to
+/// `MAX_DAYS`. Days already stored are never counted again. Returns how
+/// many days were added.
+pub async fn rollup(
+    pool: &SqlitePool,
+    offset_secs: i32,
+    event_retention_secs: i64,
+    now: i64,
+) -> Result<u64, sqlx::Error> {
+    let offset = i64::from(offset_secs);
+    let today = (now + offset).div_euclid(86400);
+    let first_whole = (now.saturating_sub(event_retention_secs) + offset).div_euclid(86400) + 1;
+    let first = first_whole.max(today - MAX_DAYS);
+    if first >= today {
+        return Ok(0);
+    }
+    let rolled = by_day(db::rolled_up_days(pool, offset, first, today - 1).await?);
+    let missing: Vec<i64> = (first..today)
+        .filter(|day| !rolled.contains_key(day))
+        .collect();
+    let Some(&since) = missing.first() else {
+        return Ok(0);
+    };
+    let counts = by_day(
+        db::counts_by_day(pool, since * 86400 - offset, today * 86400 - offset, offset).await?,
+    );
+    let days: Vec<DayCounts> = missing
+        .into_iter()
+        .map(|day| {
+            counts.get(&day).copied().unwrap_or(DayCounts {
+                day,
+                ..DayCounts::default()
+            })
+        })
+        .collect();
+    let missing: HashSet<i64> = days.iter().map(|counts| counts.day).collect();
+    let languages: Vec<DayLanguageCount> =
+        db::languages_by_day(pool, since * 86400 - offset, today * 86400 - offset, offset)
+            .await?
+            .into_iter()
+            .filter(|count| missing.contains(&count.day))
+            .collect();
+    db::store_daily_stats(pool, offset, &days, &languages).await
+}
+
+/// Roll up finished days in the display timezone for as long as the
+/// process runs. A changed `display_timezone` starts a series of its own.
+pub async fn run_rollup(state: AppState) {
+    loop {
+        let (offset, retention) = {
+            let config = state.config.load();
+            (
+                parse_utc_offset(&config.server.display_timezone).unwrap_or(0),
+                config.paste.event_retention_secs,
+            )
+        };
+        if let Err(err) = rollup(&state.pool, offset, retention, state.clock.now()).await {
+            eprintln!("Rolling up daily stats failed: {}", err);
+        }
+        tokio::time::sleep(ROLLUP_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::EventType;
+    use crate::test_support::{PasteBuilder, test_state};
+
+    #[tokio::test]
+    async fn test_daily_activity_fills_gaps_in_local_days() {
+        let state = test_state().await;
+        let pool = &state.pool;
+        // 2024-05-02 00:30 UTC is still May 1st at UTC-1.
+        let now = 1_714_609_800;
+        let log = |event_type: EventType, at: i64| async move {
+            sqlx::query("INSERT INTO events (event_type, token, created_at) VALUES (?, 't', ?)")
+                .bind(event_type.name())
+                .bind(at)
+                .execute(pool)
+                .await
+                .unwrap();
+        };
+        log(EventType::Created, now - 60).await;
+        log(EventType::Viewed, now - 60).await;
+        log(EventType::Expired, now - 2 * 86400).await;
+        // Too old for a three-day series.
+        log(EventType::Created, now - 5 * 86400).await;
+
+        let utc = daily_activity(pool, 3, 0, now).await.unwrap();
+        let dates: Vec<&str> = utc.iter().map(|day| day.date.as_str()).collect();
+        assert_eq!(dates, ["2024-04-30", "2024-05-01", "2024-05-02"]);
+        let counts: Vec<_> = utc
+            .iter()
+            .map(|day| (day.created, day.expired, day.views))
+            .collect();
+        assert_eq!(counts, [(0, 1, 0), (0, 0, 0), (1, 0, 1)]);
+
+        let behind = daily_activity(pool, 3, -3600, now).await.unwrap();
+        assert_eq!(behind[2].date, "2024-05-01");
+        assert_eq!((behind[2].created, behind[2].views), (1, 1));
+        assert_eq!(behind[0].expired, 1);
+    }
+
+    #[tokio::test]
+    async fn test_activity_cache_serves_until_stale() {
+        let state = test_state().await;
+        let cache = ActivityCache::default();
+        let now = crate::utils::now_ts();
+        let created = |activity: Vec<DailyActivity>| activity.last().unwrap().created;
+        assert_eq!(created(cache.get(&state.pool, 7, 0, now).await.unwrap()), 0);
+        PasteBuilder::new().insert(&state.pool).await;
+        assert_eq!(created(cache.get(&state.pool, 7, 0, now).await.unwrap()), 0);
+        let later = now + CACHE_SECS;
+        assert_eq!(
+            created(cache.get(&state.pool, 7, 0, later).await.unwrap()),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rollup_buckets_local_days_once() {
+        let state = test_state().await;
+        let pool = &state.pool;
+        let log = |event_type: EventType, at: i64, bytes: Option<i64>| async move {
+            sqlx::query(
+                "INSERT INTO events (event_type, token, created_at, bytes) VALUES (?, 't', ?, ?)",
+            )
+            .bind(event_type.name())
+            .bind(at)
+            .bind(bytes)
+            .execute(pool)
+            .await
+            .unwrap();
+        };
+        let beijing = 8 * 3600;
+        // 2024-05-03 12:00 UTC, 20:00 at UTC+8.
+        let now = 1_714_737_600;
+        // May 1st 23:30 and May 2nd 00:30 at UTC+8, both May 1st in UTC.
+        log(EventType::Created, 1_714_577_400, Some(10)).await;
+        log(EventType::Created, 1_714_581_000, Some(5)).await;
+        log(EventType::Viewed, 1_714_581_000, None).await;
+        log(EventType::Burned, 1_714_581_000, None).await;
+        log(EventType::Created, now - 60, Some(1)).await;
+
+        let month = 30 * 86400;
+        assert_eq!(rollup(pool, beijing, month, now).await.unwrap(), 29);
+        let days = daily_activity(pool, 3, beijing, now).await.unwrap();
+        let counts: Vec<_> = days
+            .iter()
+            .map(|day| {
+                (
+                    day.date.as_str(),
+                    day.created,
+                    day.views,
+                    day.burns,
+                    day.bytes_added,
+                )
+            })
+            .collect();
+        assert_eq!(
+            counts,
+            [
+                ("2024-05-01", 1, 0, 0, 10),
+                ("2024-05-02", 1, 1, 1, 5),
+                ("2024-05-03", 1, 0, 0, 1),
+            ]
+        );
+
+        // A late event for a rolled-up day is not counted again, while
+        // today is still live.
+        log(EventType::Created, 1_714_586_400, Some(7)).await;
+        log(EventType::Created, now - 30, Some(1)).await;
+        assert_eq!(rollup(pool, beijing, month, now).await.unwrap(), 0);
+        let days = daily_activity(pool, 3, beijing, now).await.unwrap();
+        assert_eq!((days[1].created, days[1].bytes_added), (1, 5));
+        assert_eq!(days[2].created, 2);
+
+        // UTC days are a series of their own, still read from the log.
+        let utc = daily_activity(pool, 3, 0, now).await.unwrap();
+        assert_eq!(utc[1].date, "2024-05-02");
+        assert_eq!(utc[0].created, 3);
+        assert_eq!(utc[0].bytes_added, 22);
+        assert_eq!(rollup(pool, 0, month, now).await.unwrap(), 29);
+        assert_eq!(daily_activity(pool, 3, 0, now).await.unwrap(), utc);
+    }
+
+    #[tokio::test]
+    async fn test_languages_roll_up_with_the_day() {
+        let state = test_state().await;
+        let pool = &state.pool;
+        let log = |language: Option<&'static str>, at: i64| async move {
+            sqlx::query(
+                "INSERT INTO events (event_type, token, created_at, language) VALUES ('created', 't', ?, ?)",
+            )
+            .bind(at)
+            .bind(language)
+            .execute(pool)
+            .await
+            .unwrap();
+        };
+        // 2024-05-03 12:00 UTC.
+        let now = 1_714_737_600;
+        log(Some("rust"), now - 86400).await;
+        log(Some("rust"), now - 86400).await;
+        log(None, now - 86400).await;
+        log(Some("go"), now - 60).await;
+
+        assert_eq!(rollup(pool, 0, 30 * 86400, now).await.unwrap(), 29);
+        // Rolled up, a late event no longer counts for yesterday.
+        log(Some("rust"), now - 86400).await;
+        let days = language_activity(pool, 3, 0, now).await.unwrap();
+        let partial: Vec<_> = days.iter().map(|day| day.partial).collect();
+        assert_eq!(partial, [false, false, true]);
+        assert!(days[0].counts.is_empty());
+        assert_eq!(
+            days[1].counts,
+            BTreeMap::from([("auto".to_string(), 1), ("rust".to_string(), 2)])
+        );
+
+        let languages = ["rust".to_string(), "python".to_string()];
+        let today = bucket_languages(days[2].clone(), &languages);
+        assert_eq!(today.date, "2024-05-03");
+        assert_eq!(
+            today.counts,
+            BTreeMap::from([
+                ("other".to_string(), 1),
+                ("python".to_string(), 0),
+                ("rust".to_string(), 0),
+            ])
+        );
+    }
+}
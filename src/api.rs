@@ -0,0 +1,443 @@
+//! Request and response bodies of the JSON API under `/api/v1`, and the
+//! OpenAPI document describing it, served at `/api/v1/openapi.json`.
+
+use crate::handlers;
+use crate::models::{
+    ArchiveDay, BackupStatus, CacheStatus, Capacity, DailyActivity, DailyEvents, DailyLanguages,
+    DisplayTime, LifeStage, Neighbor, PasteForm, PasteMeta, Reaction, Stats, TokenSpace,
+    WebhookStatus,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use utoipa::{OpenApi, ToSchema};
+
+/// Every error the API answers with. `error` is a stable code such as
+/// `not_found` or `content_too_long`; `message` is for people, in the
+/// request's language, and only some errors have one.
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct ApiError {
+    pub error: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    /// The response's `X-Request-Id`, to quote when asking for help; only
+    /// some errors carry it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+/// `POST /api/v1/paste`: the fields of `PasteForm`, typed for JSON.
+#[derive(Default, Deserialize, ToSchema)]
+pub struct ApiPasteForm {
+    pub title: Option<String>,
+    pub content: String,
+    pub expires_in: Option<i64>,
+    /// RFC 3339; wins over `expires_in`.
+    pub expires_at: Option<String>,
+    pub token_length: Option<usize>,
+    pub language: Option<String>,
+    pub max_views: Option<i64>,
+    pub destroy_after_first_view_secs: Option<i64>,
+    /// Unix timestamp.
+    pub visible_after: Option<i64>,
+    #[serde(default)]
+    pub is_public: bool,
+    #[serde(default)]
+    pub is_e2e: bool,
+    #[serde(default)]
+    pub trim_trailing: bool,
+    /// Nickname to sign the paste with.
+    pub author: Option<String>,
+    /// Custom slug to use as the token, e.g. `meeting-notes`.
+    pub slug: Option<String>,
+    /// Token of the paste this one is a fork of.
+    pub forked_from: Option<String>,
+    /// Name of the first file, `content`.
+    pub filename: Option<String>,
+    /// Further files, in order.
+    #[serde(default)]
+    pub files: Vec<ApiPasteFile>,
+    /// Turnstile or hCaptcha token, when `[captcha]` is configured.
+    pub captcha_response: Option<String>,
+}
+
+/// One further file of a multi-file paste.
+#[derive(Clone, Default, Deserialize, ToSchema)]
+pub struct ApiPasteFile {
+    /// `file-{n}` when left out.
+    #[serde(default)]
+    pub filename: String,
+    /// Worked out from the filename, then the content, when left out.
+    pub language: Option<String>,
+    pub content: String,
+}
+
+impl From<ApiPasteForm> for PasteForm {
+    fn from(form: ApiPasteForm) -> Self {
+        let flag = |set: bool| set.then(|| "on".to_string());
+        PasteForm {
+            title: form.title,
+            content: form.content,
+            expires_in: form.expires_in,
+            token_length: form.token_length,
+            language: form.language,
+            max_views: form.max_views.map(|views| views.to_string()),
+            destroy_after_first_view_secs: form
+                .destroy_after_first_view_secs
+                .map(|secs| secs.to_string()),
+            expires_at_abs: form.expires_at,
+            visible_after: form.visible_after.map(|ts| ts.to_string()),
+            is_public: flag(form.is_public),
+            is_e2e: flag(form.is_e2e),
+            trim_trailing: flag(form.trim_trailing),
+            author: form.author,
+            slug: form.slug,
+            forked_from: form.forked_from,
+            filename: form.filename,
+            file_name: form
+                .files
+                .iter()
+                .map(|file| file.filename.clone())
+                .collect(),
+            file_language: form
+                .files
+                .iter()
+                .map(|file| file.language.clone().unwrap_or_default())
+                .collect(),
+            file_content: form.files.into_iter().map(|file| file.content).collect(),
+            turnstile_response: form.captcha_response,
+            hcaptcha_response: None,
+        }
+    }
+}
+
+/// `POST /api/v1/paste/validate`: the create body, where `content` may be
+/// left out and its length given instead.
+#[derive(Deserialize, ToSchema)]
+pub struct ApiValidateForm {
+    #[serde(flatten)]
+    pub paste: ApiPasteForm,
+    /// Characters of content, when `content` is left out; `files` count
+    /// on top of it.
+    pub content_length: Option<usize>,
+}
+
+/// Body of `POST /api/v1/paste/validate`.
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct PasteValidation {
+    /// Whether creating the paste would succeed, captcha aside.
+    pub valid: bool,
+    /// Every check, in the order creating the paste makes them.
+    pub checks: Vec<ValidationCheck>,
+    /// The language the paste would get; unknown ones fall back to `auto`.
+    pub language: String,
+    pub expires_at: i64,
+    pub slug: Option<String>,
+    /// `is_public` as stored: burn-on-read and self-destructing pastes
+    /// stay private.
+    pub is_public: bool,
+}
+
+/// One check a dry run made: `content_length`, `expires_at`, `slug`,
+/// `visible_after` or `capacity`. A failed one carries the error creating
+/// the paste would answer with.
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct ValidationCheck {
+    pub check: String,
+    pub ok: bool,
+    pub error: Option<String>,
+    pub message: Option<String>,
+}
+
+/// The 201 body for a paste created by an API client. The URLs are
+/// absolute when `base_url` is set.
+#[derive(Deserialize, Serialize, ToSchema)]
+pub struct CreatedPasteResponse {
+    pub token: String,
+    pub url: String,
+    /// Opens `/m/{manage_token}`, where the paste can be renewed or deleted.
+    pub manage_token: String,
+    pub manage_url: String,
+    pub expires_at: i64,
+    /// The same client sent the same content within `repeat_window_secs`,
+    /// and this is the paste it created then rather than a new one.
+    pub reused: bool,
+}
+
+/// One public paste, as `GET /api/v1/explore` pages through them.
+#[derive(Deserialize, Serialize, ToSchema)]
+pub struct ExplorePaste {
+    /// The locale `created`, `life_stage_label`, `remaining` and
+    /// `language_label` are in.
+    pub lang: String,
+    pub token: String,
+    pub title: String,
+    pub content_length: i64,
+    /// Whether `preview` stops short of the content.
+    pub truncated: bool,
+    pub created_at: i64,
+    pub created: DisplayTime,
+    pub expires_at: i64,
+    pub life_stage: LifeStage,
+    pub life_stage_label: String,
+    /// Share of the original lifetime left, from 0 to 1; a paste renewed
+    /// past its original duration stays at 1.
+    pub life_fraction: f64,
+    pub original_duration: i64,
+    /// As in `Renewal`, whether and from when `POST /p/{token}/renew`
+    /// works.
+    pub renew_eligible: bool,
+    pub renew_available_at: Option<i64>,
+    /// Localized time left, e.g. "~ 3 hours".
+    pub remaining: String,
+    pub language: String,
+    pub language_label: String,
+    /// Nickname the paste was signed with.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    /// The beginning of the content; absent with `full=1`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preview: Option<String>,
+    /// The whole content with `full=1`, or its first `preview_chars`
+    /// characters.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+    /// With `preview_chars`: whether `content` stops short of the content.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_truncated: Option<bool>,
+    /// Position of this paste among `total`.
+    pub index: i64,
+    pub total: i64,
+}
+
+/// Every name `fields` may pick for `GET /api/v1/explore`, one per field
+/// of `ExplorePaste`.
+pub const EXPLORE_FIELDS: [&str; 23] = [
+    "lang",
+    "token",
+    "title",
+    "content_length",
+    "truncated",
+    "created_at",
+    "created",
+    "expires_at",
+    "life_stage",
+    "life_stage_label",
+    "life_fraction",
+    "original_duration",
+    "renew_eligible",
+    "renew_available_at",
+    "remaining",
+    "language",
+    "language_label",
+    "author",
+    "preview",
+    "content",
+    "content_truncated",
+    "index",
+    "total",
+];
+
+/// The comma-separated `fields` of `GET /api/v1/explore`, or the first
+/// name that is not in `EXPLORE_FIELDS`.
+pub fn parse_explore_fields(raw: &str) -> Result<Vec<&'static str>, String> {
+    raw.split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| {
+            EXPLORE_FIELDS
+                .iter()
+                .find(|field| **field == name)
+                .copied()
+                .ok_or_else(|| name.to_string())
+        })
+        .collect()
+}
+
+impl ExplorePaste {
+    /// The paste as JSON with only `fields` kept. Fields that are absent
+    /// anyway, such as `preview` with `full=1`, stay absent.
+    pub fn select(&self, fields: &[&str]) -> serde_json::Map<String, serde_json::Value> {
+        let serde_json::Value::Object(mut object) =
+            serde_json::to_value(self).expect("Failed to serialize paste")
+        else {
+            unreachable!("a struct serializes to an object");
+        };
+        object.retain(|key, _| fields.contains(&key.as_str()));
+        object
+    }
+}
+
+/// Body of `GET /api/v1/p/{token}/neighbors`: the public pastes created
+/// just before and just after, `null` at either end. The URLs are absolute
+/// when `base_url` is set.
+#[derive(Deserialize, Serialize, ToSchema)]
+pub struct PasteNeighbors {
+    pub prev: Option<Neighbor>,
+    pub next: Option<Neighbor>,
+    pub prev_url: Option<String>,
+    pub next_url: Option<String>,
+}
+
+/// Body of `GET /api/v1/archive`: a month of pastes explore lists, by
+/// local day, newest first.
+#[derive(Deserialize, Serialize, ToSchema)]
+pub struct ArchiveMonth {
+    /// `YYYY-MM`.
+    pub month: String,
+    pub total: i64,
+    pub days: Vec<ArchiveDay>,
+    /// The month before, if anything listed is older.
+    pub earlier: Option<String>,
+    /// The month after, unless this is the current month.
+    pub later: Option<String>,
+}
+
+/// What `POST /p/{token}/react` answers JSON clients with.
+#[derive(Deserialize, Serialize, ToSchema)]
+pub struct PasteReactions {
+    pub reactions: Vec<Reaction>,
+}
+
+/// Body of `GET /api/v1/slug-available`. `reason` is `taken`, `reserved`,
+/// `invalid_charset` or `length` when the slug cannot be had.
+#[derive(Debug, Deserialize, PartialEq, Serialize, ToSchema)]
+pub struct SlugAvailability {
+    pub available: bool,
+    pub reason: Option<String>,
+}
+
+/// Body of `GET /api/v1/stats`.
+#[derive(Serialize, ToSchema)]
+pub struct ApiStats {
+    #[serde(flatten)]
+    pub stats: Stats,
+    pub evictions: u64,
+    /// Size-limit evictions, by the policy that chose them.
+    pub evictions_by_policy: BTreeMap<&'static str, u64>,
+    pub backup: BackupStatus,
+    pub webhooks: WebhookStatus,
+    pub cache: CacheStatus,
+    /// Event counts per day over the retention window, from the event log.
+    pub daily: Vec<DailyEvents>,
+    pub capacity: Capacity,
+    /// How crowded generated tokens can get, and how often they collided.
+    pub tokens: TokenSpace,
+    /// Writes retried after finding the database busy, since startup.
+    pub busy_retries: u64,
+}
+
+/// Body of `GET /api/v1/stats/languages`.
+#[derive(Serialize, ToSchema)]
+pub struct LanguageTimeline {
+    /// The configured languages, then `other`, which also holds pastes
+    /// left to `auto` and those created before languages were counted.
+    pub languages: Vec<String>,
+    /// One entry per day, oldest first, with a count for every language.
+    pub days: Vec<DailyLanguages>,
+}
+
+/// Body of `GET /api/v1/limits`: what a create request may ask for on
+/// this instance, so clients can check before sending.
+#[derive(Serialize, ToSchema)]
+pub struct ApiLimits {
+    /// Characters, counting every file.
+    pub max_content_length: usize,
+    pub max_title_length: usize,
+    /// 0 when pastes cannot be signed.
+    pub max_author_length: usize,
+    pub expiry: ExpiryLimits,
+    pub tokens: TokenLimits,
+    /// `auto`, then the configured languages.
+    pub languages: Vec<String>,
+    /// The highest `max_views` kept; larger ones are brought down to it.
+    pub max_views_limit: i64,
+    /// Self-destruct timers offered after the first view, in seconds.
+    pub destroy_after_options: Vec<i64>,
+    pub features: FeatureLimits,
+    pub capacity: Capacity,
+    /// How much more fits before the oldest pastes are evicted.
+    pub headroom: Headroom,
+}
+
+/// Expiries `expires_in` may pick, and how far `expires_at` may reach.
+#[derive(Serialize, ToSchema)]
+pub struct ExpiryLimits {
+    pub options: Vec<i64>,
+    pub default: i64,
+    /// `expires_at` further out than this from now is brought in to it.
+    pub max_secs: i64,
+}
+
+/// Generated token lengths `token_length` may pick, and custom slugs.
+#[derive(Serialize, ToSchema)]
+pub struct TokenLimits {
+    pub lengths: Vec<usize>,
+    pub default: usize,
+    pub case_insensitive: bool,
+    pub slug_min_length: usize,
+    pub slug_max_length: usize,
+}
+
+/// Which optional parts of a create request are honoured.
+#[derive(Serialize, ToSchema)]
+pub struct FeatureLimits {
+    pub custom_slugs: bool,
+    /// Every paste expires; there is no keeping one forever.
+    pub never_expire: bool,
+    /// `max_views` and self-destruct timers.
+    pub burn_after_reading: bool,
+    pub public: bool,
+    pub author: bool,
+    pub related_links: bool,
+    /// `/r/` may render HTML pastes when asked.
+    pub html_rendering: bool,
+    /// Creating pastes is switched off.
+    pub read_only: bool,
+}
+
+/// Room left under `max_pastes` and `max_total_content_length`.
+#[derive(Serialize, ToSchema)]
+pub struct Headroom {
+    pub pastes: i64,
+    pub content_length: i64,
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "mayfile", description = "Create and read pastes."),
+    paths(
+        handlers::api_create_paste,
+        handlers::api_validate_paste,
+        handlers::api_explore,
+        handlers::api_archive,
+        handlers::api_paste_meta,
+        handlers::api_paste_hash,
+        handlers::api_paste_neighbors,
+        handlers::api_recent,
+        handlers::api_own_recent,
+        handlers::api_slug_available,
+        handlers::api_stats,
+        handlers::api_stats_daily,
+        handlers::api_stats_languages,
+        handlers::api_limits,
+    ),
+    components(schemas(
+        ApiError,
+        ApiPasteForm,
+        ApiValidateForm,
+        PasteValidation,
+        ValidationCheck,
+        CreatedPasteResponse,
+        ExplorePaste,
+        ArchiveMonth,
+        ApiStats,
+        PasteMeta,
+        PasteNeighbors,
+        SlugAvailability,
+        DailyActivity,
+        DailyLanguages,
+        LanguageTimeline,
+        ApiLimits,
+    ))
+)]
+pub struct ApiDoc;
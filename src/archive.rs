@@ -0,0 +1,156 @@
+//! The public archive: listed pastes by month, grouped by local day.
+
+use crate::models::{ArchiveDay, ListedPaste};
+use crate::utils::{format_iso8601, parse_local_datetime};
+
+/// A calendar month, as `?month=2024-06` names it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Month {
+    pub year: i64,
+    pub month: i64,
+}
+
+impl Month {
+    /// `YYYY-MM`, years 1970 to 9999.
+    pub fn parse(value: &str) -> Option<Month> {
+        let (year, month) = value.trim().split_once('-')?;
+        if year.len() != 4 || month.len() != 2 {
+            return None;
+        }
+        let year: i64 = year.parse().ok()?;
+        let month: i64 = month.parse().ok()?;
+        ((1970..=9999).contains(&year) && (1..=12).contains(&month))
+            .then_some(Month { year, month })
+    }
+
+    /// The month `ts` falls in at `offset_secs` from UTC.
+    pub fn containing(ts: i64, offset_secs: i32) -> Month {
+        Month::parse(&format_iso8601(ts, offset_secs)[..7]).unwrap_or(Month {
+            year: 1970,
+            month: 1,
+        })
+    }
+
+    pub fn prev(self) -> Month {
+        match self.month {
+            1 => Month {
+                year: self.year - 1,
+                month: 12,
+            },
+            month => Month {
+                year: self.year,
+                month: month - 1,
+            },
+        }
+    }
+
+    pub fn next(self) -> Month {
+        match self.month {
+            12 => Month {
+                year: self.year + 1,
+                month: 1,
+            },
+            month => Month {
+                year: self.year,
+                month: month + 1,
+            },
+        }
+    }
+
+    /// When the month starts at `offset_secs` from UTC.
+    pub fn start(self, offset_secs: i32) -> i64 {
+        parse_local_datetime(&format!("{}-01T00:00", self), offset_secs).unwrap_or(0)
+    }
+}
+
+impl std::fmt::Display for Month {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:04}-{:02}", self.year, self.month)
+    }
+}
+
+/// Group pastes, newest first, into local days at `offset_secs`, newest
+/// day first.
+pub fn group_by_day(pastes: Vec<ListedPaste>, offset_secs: i32) -> Vec<ArchiveDay> {
+    let mut days: Vec<ArchiveDay> = Vec::new();
+    for paste in pastes {
+        let date = &format_iso8601(paste.created_at, offset_secs)[..10];
+        match days.last_mut() {
+            Some(day) if day.date == date => {
+                day.count += 1;
+                day.pastes.push(paste);
+            }
+            _ => days.push(ArchiveDay {
+                date: date.to_string(),
+                count: 1,
+                pastes: vec![paste],
+            }),
+        }
+    }
+    days
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn listed(token: &str, created_at: i64) -> ListedPaste {
+        ListedPaste {
+            token: token.to_string(),
+            title: token.to_string(),
+            language: "plaintext".to_string(),
+            created_at,
+            author: None,
+            language_label: String::new(),
+            time: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_month_parse_and_step() {
+        let june = Month::parse("2024-06").unwrap();
+        assert_eq!(june.to_string(), "2024-06");
+        assert_eq!(june.prev().to_string(), "2024-05");
+        assert_eq!(
+            Month::parse("2024-12").unwrap().next().to_string(),
+            "2025-01"
+        );
+        assert_eq!(
+            Month::parse("2024-01").unwrap().prev().to_string(),
+            "2023-12"
+        );
+        for bad in [
+            "2024-13", "2024-00", "2024-6", "24-06", "1969-12", "june", "",
+        ] {
+            assert_eq!(Month::parse(bad), None, "{}", bad);
+        }
+        // 2024-06-01T00:00 at +08:00 is 2024-05-31T16:00Z.
+        assert_eq!(june.start(8 * 3600), 1717171200);
+        assert_eq!(Month::containing(1717171200, 8 * 3600), june);
+        assert_eq!(Month::containing(1717171200 - 1, 8 * 3600), june.prev());
+    }
+
+    #[test]
+    fn test_group_by_day_follows_the_offset() {
+        // 2024-06-02T01:00Z, 2024-06-01T23:00Z and 2024-06-01T10:00Z.
+        let pastes = vec![
+            listed("c", 1717290000),
+            listed("b", 1717282800),
+            listed("a", 1717236000),
+        ];
+        let days = group_by_day(pastes.clone(), 0);
+        let summary: Vec<(&str, i64)> = days
+            .iter()
+            .map(|day| (day.date.as_str(), day.count))
+            .collect();
+        assert_eq!(summary, [("2024-06-02", 1), ("2024-06-01", 2)]);
+
+        let days = group_by_day(pastes, 8 * 3600);
+        let summary: Vec<(&str, i64)> = days
+            .iter()
+            .map(|day| (day.date.as_str(), day.count))
+            .collect();
+        assert_eq!(summary, [("2024-06-02", 2), ("2024-06-01", 1)]);
+        assert!(group_by_day(Vec::new(), 0).is_empty());
+    }
+}
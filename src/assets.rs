@@ -0,0 +1,53 @@
+//! Static files compiled into the binary.
+
+use rust_embed::RustEmbed;
+use std::collections::HashMap;
+use std::sync::LazyLock;
+
+/// Everything under `assets/`, served at `/assets/`.
+#[derive(RustEmbed)]
+#[folder = "assets/"]
+pub struct Assets;
+
+/// Locale files used when the configured ones are missing.
+pub const DEFAULT_ZH: &str = include_str!("../config/i18n/zh.toml");
+pub const DEFAULT_EN: &str = include_str!("../config/i18n/en.toml");
+pub const DEFAULT_JA: &str = include_str!("../config/i18n/ja.toml");
+
+/// Configuration used when `config/app.toml` is missing.
+pub const DEFAULT_CONFIG: &str = include_str!("../config/app.toml.example");
+
+/// Short content hash of each embedded asset, appended to asset URLs so
+/// browsers refetch after an upgrade.
+static VERSIONS: LazyLock<HashMap<String, String>> = LazyLock::new(|| {
+    Assets::iter()
+        .filter_map(|name| {
+            let file = Assets::get(&name)?;
+            let version = hex::encode(&file.metadata.sha256_hash()[..4]);
+            Some((name.into_owned(), version))
+        })
+        .collect()
+});
+
+pub fn version(name: &str) -> Option<&'static str> {
+    VERSIONS.get(name).map(String::as_str)
+}
+
+/// URL of an asset under `/assets/`, versioned when it is embedded.
+pub fn asset_url(name: &str) -> String {
+    match version(name) {
+        Some(version) => format!("/assets/{}?v={}", name, version),
+        None => format!("/assets/{}", name),
+    }
+}
+
+pub mod filters {
+    /// `{{ "style.css"|asset_url }}`
+    #[askama::filter_fn]
+    pub fn asset_url(
+        name: impl std::fmt::Display,
+        _: &dyn askama::Values,
+    ) -> askama::Result<String> {
+        Ok(super::asset_url(&name.to_string()))
+    }
+}
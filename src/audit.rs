@@ -0,0 +1,260 @@
+//! An audit trail for operators of public instances: admin API calls,
+//! bans, reports unpublishing a paste, purges and refused requests, as
+//! `tracing` events under `TARGET`. Nothing is logged unless
+//! `server.log_security_events` is set, and then only tokens, hashes and
+//! sizes: paste content and client addresses never appear.
+
+use crate::crypto::client_hash;
+use crate::models::{AppState, BannedIp, PurgeFilter};
+use crate::utils::sha256_hex;
+use axum::http::{Method, StatusCode};
+use std::net::IpAddr;
+use tracing::{Level, info};
+
+/// The target every security event is logged under.
+pub const TARGET: &str = "mayfile::security";
+
+/// Why a request was turned away, by class.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Refusal {
+    RateLimit,
+    /// The client is banned.
+    Blocklist,
+    Size,
+    Captcha,
+}
+
+impl Refusal {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Refusal::RateLimit => "rate_limit",
+            Refusal::Blocklist => "blocklist",
+            Refusal::Size => "size",
+            Refusal::Captcha => "captcha",
+        }
+    }
+}
+
+/// Write security events to stdout as JSON lines. Everything else traced,
+/// by us or by dependencies, is dropped; the rest of the logging stays
+/// plain text.
+pub fn init() {
+    use tracing_subscriber::filter::Targets;
+    use tracing_subscriber::prelude::*;
+    let json = tracing_subscriber::fmt::layer()
+        .json()
+        .with_writer(std::io::stdout)
+        .with_filter(Targets::new().with_target(TARGET, Level::INFO));
+    tracing_subscriber::registry().with(json).init();
+}
+
+fn enabled(state: &AppState) -> bool {
+    state.config.load().server.log_security_events
+}
+
+/// A call to `/admin` or `/admin/api/*`, whether or not it was let in.
+/// `credential` is how the admin token came: `bearer` or `session`.
+pub fn admin_call(
+    state: &AppState,
+    method: &Method,
+    path: &str,
+    credential: Option<&str>,
+    status: StatusCode,
+) {
+    if enabled(state) {
+        info!(
+            target: TARGET,
+            event = "admin_call",
+            method = %method,
+            path,
+            credential = credential.unwrap_or("none"),
+            status = status.as_u16(),
+        );
+    }
+}
+
+pub fn ban_added(state: &AppState, ban: &BannedIp) {
+    if enabled(state) {
+        info!(
+            target: TARGET,
+            event = "ban_added",
+            ban_id = ban.id,
+            cidr = %ban.cidr,
+            expires_at = ban.expires_at.unwrap_or(0),
+        );
+    }
+}
+
+pub fn ban_removed(state: &AppState, id: i64) {
+    if enabled(state) {
+        info!(target: TARGET, event = "ban_removed", ban_id = id);
+    }
+}
+
+/// The report that brought `token` to `report_threshold`.
+pub fn report_threshold(state: &AppState, token: &str, reports: i64) {
+    if enabled(state) {
+        info!(
+            target: TARGET,
+            event = "report_threshold",
+            token,
+            reports,
+            threshold = state.config.load().paste.report_threshold,
+        );
+    }
+}
+
+/// A purge, dry run or not. The content filters are logged as digests so
+/// what they matched cannot be read back from the log.
+pub fn purge(state: &AppState, filter: &PurgeFilter, matched: i64, deleted: i64) {
+    if !enabled(state) {
+        return;
+    }
+    let summary = serde_json::json!({
+        "content_regex_sha256": filter.content_regex.as_deref().map(sha256_hex),
+        "content_contains_sha256": filter.content_contains.as_deref().map(sha256_hex),
+        "created_before": filter.created_before,
+        "created_after": filter.created_after,
+        "language": filter.language,
+        "is_public": filter.is_public,
+        "min_length": filter.min_length,
+        "max_length": filter.max_length,
+    });
+    info!(
+        target: TARGET,
+        event = "purge",
+        filter = %summary,
+        dry_run = !filter.confirm,
+        matched,
+        deleted,
+    );
+}
+
+/// A request to `action` turned away for `refusal`, with the bytes sent
+/// when size was the reason.
+pub fn refused(state: &AppState, ip: IpAddr, action: &str, refusal: Refusal, bytes: Option<usize>) {
+    if enabled(state) {
+        info!(
+            target: TARGET,
+            event = "refused",
+            action,
+            reason = refusal.as_str(),
+            client = %client_hash(&state.signing_key, ip),
+            bytes = bytes.unwrap_or(0),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{PasteBuilder, body_string, send, test_state, update_config};
+    use axum::body::Body;
+    use axum::http::Request;
+    use std::sync::{Arc, Mutex};
+
+    /// Where the test subscriber writes, one JSON object per line.
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Captured {
+        fn events(&self) -> Vec<serde_json::Value> {
+            String::from_utf8(self.0.lock().unwrap().clone())
+                .unwrap()
+                .lines()
+                .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+                .filter(|event| event["target"] == TARGET)
+                .collect()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_purges_and_refusals_are_logged_without_content() {
+        let captured = Captured::default();
+        let writer = captured.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .json()
+            .with_writer(move || writer.clone())
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let state = test_state().await;
+        let app = crate::build_router(state.clone());
+        PasteBuilder::new()
+            .content("buy cheap watches")
+            .insert(&state.pool)
+            .await;
+        let purge = || {
+            Request::post("/admin/api/purge")
+                .header("authorization", "Bearer sesame")
+                .header("content-type", "application/json")
+                .body(Body::from(
+                    r#"{"content_contains": "cheap watches", "confirm": true}"#,
+                ))
+                .unwrap()
+        };
+        update_config(&state, |config| {
+            config.server.admin_token = Some("sesame".to_string());
+        });
+        send(&app, purge()).await;
+        assert!(captured.events().is_empty(), "off by default");
+
+        update_config(&state, |config| config.server.log_security_events = true);
+        PasteBuilder::new()
+            .content("buy cheap watches")
+            .insert(&state.pool)
+            .await;
+        let response = send(&app, purge()).await;
+        assert!(body_string(response).await.contains(r#""deleted":1"#));
+        let report = || {
+            Request::post("/p/missing/report")
+                .header("content-type", "application/x-www-form-urlencoded")
+                .body(Body::empty())
+                .unwrap()
+        };
+        for _ in 0..=state.config.load().paste.reports_per_hour {
+            send(&app, report()).await;
+        }
+
+        let events = captured.events();
+        let logged = serde_json::to_string(&events).unwrap();
+        assert!(!logged.contains("cheap watches"));
+        assert!(!logged.contains("sesame"));
+        let fields: Vec<_> = events.iter().map(|event| &event["fields"]).collect();
+
+        let purge = fields.iter().find(|f| f["event"] == "purge").unwrap();
+        assert_eq!(purge["matched"], 1);
+        assert_eq!(purge["deleted"], 1);
+        assert_eq!(purge["dry_run"], false);
+        let filter: serde_json::Value =
+            serde_json::from_str(purge["filter"].as_str().unwrap()).unwrap();
+        assert_eq!(
+            filter["content_contains_sha256"],
+            sha256_hex("cheap watches")
+        );
+
+        let call = fields.iter().find(|f| f["event"] == "admin_call").unwrap();
+        assert_eq!(call["path"], "/admin/api/purge");
+        assert_eq!(call["method"], "POST");
+        assert_eq!(call["credential"], "bearer");
+        assert_eq!(call["status"], 200);
+
+        let refused: Vec<_> = fields.iter().filter(|f| f["event"] == "refused").collect();
+        assert_eq!(refused.len(), 1);
+        assert_eq!(refused[0]["action"], "report");
+        assert_eq!(refused[0]["reason"], "rate_limit");
+        let client = client_hash(&state.signing_key, [127, 0, 0, 1].into());
+        assert_eq!(refused[0]["client"], client);
+    }
+}
@@ -0,0 +1,635 @@
+//! JSON Lines exports of every alive paste, and scheduled snapshots of
+//! the whole database.
+
+use crate::crypto::{ContentCipher, open_content};
+use crate::db;
+use crate::detect::detect_language;
+use crate::models::{AppState, BackupConfig, BackupLine, BackupPaste, ImportSummary, Snapshot};
+use crate::utils::sha256_hex;
+use futures_util::{Stream, stream};
+use sqlx::SqlitePool;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Bumped whenever a backup line changes shape. Version 2 added the files
+/// of multi-file pastes and the syntax check; version 1 lines still import.
+pub const FORMAT_VERSION: u32 = 2;
+
+/// Pastes read from the database per round trip.
+const EXPORT_BATCH: i64 = 500;
+
+/// The backup as a stream of chunks, one per batch of pastes, so memory
+/// stays flat however large the database is. Content encrypted at rest
+/// stays ciphertext unless `include_plaintext` is set and `cipher` opens it.
+/// Pastes count as alive if they were at `now`, when the export began.
+pub fn export(
+    pool: SqlitePool,
+    cipher: Option<ContentCipher>,
+    include_plaintext: bool,
+    now: i64,
+) -> impl Stream<Item = Result<String, sqlx::Error>> {
+    stream::try_unfold(Some(0), move |after_id| {
+        let pool = pool.clone();
+        let cipher = cipher.clone();
+        async move {
+            let Some(after_id) = after_id else {
+                return Ok(None);
+            };
+            let pastes = db::export_page(&pool, after_id, EXPORT_BATCH, now).await?;
+            let Some(last) = pastes.last() else {
+                return Ok(None);
+            };
+            let next = (pastes.len() as i64 == EXPORT_BATCH).then_some(last.id);
+            let chunk: String = pastes
+                .into_iter()
+                .map(|paste| {
+                    let paste = if include_plaintext {
+                        decrypt(paste, cipher.as_ref())
+                    } else {
+                        paste
+                    };
+                    export_line(paste)
+                })
+                .collect();
+            Ok(Some((chunk, next)))
+        }
+    })
+}
+
+fn decrypt(mut paste: BackupPaste, cipher: Option<&ContentCipher>) -> BackupPaste {
+    open_stored(
+        cipher,
+        &mut paste.content,
+        &mut paste.encoding,
+        &mut paste.nonce,
+    );
+    for file in &mut paste.files {
+        open_stored(
+            cipher,
+            &mut file.content,
+            &mut file.encoding,
+            &mut file.nonce,
+        );
+    }
+    paste
+}
+
+/// Replace `content` with its plain text if it is encrypted and `cipher`
+/// opens it.
+fn open_stored(
+    cipher: Option<&ContentCipher>,
+    content: &mut String,
+    encoding: &mut String,
+    nonce: &mut Option<String>,
+) {
+    if encoding != "encrypted" {
+        return;
+    }
+    if let Some(plain) = open_content(cipher, content.clone(), true, nonce.as_deref()) {
+        *content = plain;
+        *encoding = "plain".to_string();
+        *nonce = None;
+    }
+}
+
+fn export_line(paste: BackupPaste) -> String {
+    let line = BackupLine {
+        version: FORMAT_VERSION,
+        paste,
+    };
+    let mut json = serde_json::to_string(&line).expect("Failed to serialize paste");
+    json.push('\n');
+    json
+}
+
+/// Restore the pastes in `lines`, a backup as written by `export`. Tokens
+/// and timestamps are kept; `original_duration` and `content_hash` are
+/// recomputed when missing. A paste that would push storage past
+/// `max_total_content_length` is refused with a warning instead of
+/// evicting others.
+pub async fn import(
+    pool: &SqlitePool,
+    cipher: Option<&ContentCipher>,
+    lines: impl IntoIterator<Item = std::io::Result<String>>,
+    overwrite: bool,
+    max_total_content_length: i64,
+    now: i64,
+) -> ImportSummary {
+    let mut summary = ImportSummary::default();
+    let mut total = db::total_content_length(pool).await;
+    for (index, line) in lines.into_iter().enumerate() {
+        let number = index + 1;
+        let line = match line {
+            Ok(line) => line,
+            Err(err) => {
+                eprintln!("Import stopped at line {}: {}", number, err);
+                summary.failed += 1;
+                break;
+            }
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let mut paste = match parse_line(&line) {
+            Ok(paste) => paste,
+            Err(err) => {
+                eprintln!("Import line {}: {}", number, err);
+                summary.failed += 1;
+                continue;
+            }
+        };
+        if paste.expires_at <= now {
+            summary.skipped += 1;
+            continue;
+        }
+        if let Err(err) = prepare(&mut paste, cipher) {
+            eprintln!("Import line {}: {}", number, err);
+            summary.failed += 1;
+            continue;
+        }
+
+        let length = paste.content.chars().count() as i64
+            + paste
+                .files
+                .iter()
+                .map(|file| file.content.chars().count() as i64)
+                .sum::<i64>();
+        let replaced = if overwrite {
+            db::content_length(pool, &paste.token)
+                .await
+                .ok()
+                .flatten()
+                .unwrap_or(0)
+        } else {
+            0
+        };
+        if total - replaced + length > max_total_content_length {
+            eprintln!(
+                "Import line {}: paste {} would exceed max_total_content_length, not imported",
+                number, paste.token
+            );
+            summary.failed += 1;
+            continue;
+        }
+        match db::restore_paste(pool, &paste, overwrite).await {
+            Ok(true) => {
+                summary.inserted += 1;
+                total += length - replaced;
+            }
+            Ok(false) => summary.skipped += 1,
+            Err(err) => {
+                eprintln!("Import line {}: {}", number, err);
+                summary.failed += 1;
+            }
+        }
+    }
+    summary
+}
+
+fn parse_line(line: &str) -> Result<BackupPaste, String> {
+    let value: serde_json::Value = serde_json::from_str(line).map_err(|err| err.to_string())?;
+    let version = value.get("version").and_then(serde_json::Value::as_u64);
+    if !version.is_some_and(|version| (1..=FORMAT_VERSION as u64).contains(&version)) {
+        return Err(format!(
+            "format version {} is not supported (expected at most {})",
+            value.get("version").unwrap_or(&serde_json::Value::Null),
+            FORMAT_VERSION
+        ));
+    }
+    let line: BackupLine = serde_json::from_value(value).map_err(|err| err.to_string())?;
+    Ok(line.paste)
+}
+
+/// Fill in derived columns and bring the content into the form this
+/// server stores: encrypted if it has an `encryption_key`.
+fn prepare(paste: &mut BackupPaste, cipher: Option<&ContentCipher>) -> Result<(), String> {
+    paste
+        .original_duration
+        .get_or_insert(paste.expires_at - paste.created_at);
+    match paste.encoding.as_str() {
+        "plain" => {
+            paste
+                .content_hash
+                .get_or_insert_with(|| sha256_hex(&paste.content));
+            if paste.language == "auto" && !paste.is_e2e {
+                paste
+                    .detected_language
+                    .get_or_insert_with(|| detect_language(&paste.content).to_string());
+            }
+            seal(
+                cipher,
+                &mut paste.content,
+                &mut paste.encoding,
+                &mut paste.nonce,
+            );
+        }
+        "encrypted" => {
+            let Some(nonce) = paste.nonce.as_deref() else {
+                return Err(format!(
+                    "paste {} is encrypted but has no nonce",
+                    paste.token
+                ));
+            };
+            if paste.content_hash.is_none() {
+                paste.content_hash = open_content(cipher, paste.content.clone(), true, Some(nonce))
+                    .map(|content| sha256_hex(&content));
+            }
+        }
+        other => return Err(format!("unknown encoding {}", other)),
+    }
+    for file in &mut paste.files {
+        match file.encoding.as_str() {
+            "plain" => seal(
+                cipher,
+                &mut file.content,
+                &mut file.encoding,
+                &mut file.nonce,
+            ),
+            "encrypted" if file.nonce.is_none() => {
+                return Err(format!(
+                    "file {} of paste {} is encrypted but has no nonce",
+                    file.filename, paste.token
+                ));
+            }
+            "encrypted" => {}
+            other => return Err(format!("unknown encoding {}", other)),
+        }
+    }
+    Ok(())
+}
+
+/// Encrypt plain `content` under `cipher`, if there is one.
+fn seal(
+    cipher: Option<&ContentCipher>,
+    content: &mut String,
+    encoding: &mut String,
+    nonce: &mut Option<String>,
+) {
+    *nonce = None;
+    if let Some(cipher) = cipher {
+        let (ciphertext, sealed) = cipher.encrypt(content);
+        *content = ciphertext;
+        *nonce = Some(sealed);
+        *encoding = "encrypted".to_string();
+    }
+}
+
+/// Checkpoint the WAL and copy the database to a new file in
+/// `config.dir` with `VACUUM INTO`, then delete all but the newest
+/// `config.keep` snapshots.
+pub async fn snapshot(pool: &SqlitePool, config: &BackupConfig) -> Result<Snapshot, String> {
+    let started = Instant::now();
+    fs::create_dir_all(&config.dir)
+        .map_err(|err| format!("Failed to create {}: {}", config.dir, err))?;
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis();
+    let path = Path::new(&config.dir).join(format!("mayfile-{}.db", millis));
+    let path = path.to_string_lossy().into_owned();
+
+    sqlx::query("PRAGMA wal_checkpoint(TRUNCATE)")
+        .execute(pool)
+        .await
+        .map_err(|err| format!("Checkpoint failed: {}", err))?;
+    sqlx::query("VACUUM INTO ?")
+        .bind(&path)
+        .execute(pool)
+        .await
+        .map_err(|err| format!("Failed to write {}: {}", path, err))?;
+    let size = fs::metadata(&path).map(|meta| meta.len()).unwrap_or(0);
+    prune_snapshots(&config.dir, config.keep)?;
+
+    Ok(Snapshot {
+        path,
+        size,
+        duration_ms: started.elapsed().as_millis(),
+    })
+}
+
+/// Snapshot names embed their creation time, so sorting by name sorts by age.
+fn prune_snapshots(dir: &str, keep: usize) -> Result<(), String> {
+    let mut snapshots: Vec<_> = fs::read_dir(dir)
+        .map_err(|err| format!("Failed to list {}: {}", dir, err))?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with("mayfile-") && name.ends_with(".db"))
+        })
+        .collect();
+    snapshots.sort();
+    let excess = snapshots.len().saturating_sub(keep);
+    for path in &snapshots[..excess] {
+        fs::remove_file(path)
+            .map_err(|err| format!("Failed to remove {}: {}", path.display(), err))?;
+    }
+    Ok(())
+}
+
+/// Take a snapshot with the running `[backup]` settings, logging the
+/// outcome and recording it for `/api/v1/stats`. `None` if backups are off.
+pub async fn backup_now(state: &AppState) -> Option<Result<Snapshot, String>> {
+    let config = state.config.load().backup.clone()?;
+    let result = snapshot(&state.pool, &config).await;
+    match &result {
+        Ok(snapshot) => println!(
+            "Backup written to {} ({} bytes in {} ms)",
+            snapshot.path, snapshot.size, snapshot.duration_ms
+        ),
+        Err(err) => eprintln!("Backup failed: {}", err),
+    }
+    state
+        .metrics
+        .record_backup(result.is_ok(), state.clock.now());
+    Some(result)
+}
+
+/// How often `run_schedule` looks again while `[backup]` is absent.
+const IDLE_POLL: Duration = Duration::from_secs(60);
+
+/// Back up every `interval_secs`, re-reading the settings each time so a
+/// reload can change them. Runs for the life of the server: while
+/// `[backup]` is absent it waits for a reload to add it.
+pub async fn run_schedule(state: AppState) {
+    loop {
+        let interval_secs = state
+            .config
+            .load()
+            .backup
+            .as_ref()
+            .map(|backup| backup.interval_secs);
+        match interval_secs {
+            Some(interval_secs) => {
+                tokio::time::sleep(Duration::from_secs(interval_secs.max(1))).await;
+                backup_now(&state).await;
+            }
+            None => tokio::time::sleep(IDLE_POLL).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{PasteBuilder, test_state};
+    use crate::utils::now_ts;
+    use futures_util::TryStreamExt;
+
+    async fn export_string(pool: &SqlitePool, cipher: Option<&ContentCipher>) -> String {
+        let chunks: Vec<String> = export(pool.clone(), cipher.cloned(), false, now_ts())
+            .try_collect()
+            .await
+            .unwrap();
+        chunks.concat()
+    }
+
+    fn lines(backup: &str) -> Vec<std::io::Result<String>> {
+        backup.lines().map(|line| Ok(line.to_string())).collect()
+    }
+
+    #[tokio::test]
+    async fn test_export_streams_every_alive_paste() {
+        let state = test_state().await;
+        let cipher = ContentCipher::from_hex(&"ab".repeat(32)).unwrap();
+        for (content, expires_in) in [("secret", 60), ("gone", -1)] {
+            PasteBuilder::new()
+                .content(content)
+                .expires_in(expires_in)
+                .encrypted(&cipher)
+                .insert(&state.pool)
+                .await;
+        }
+
+        let lines = |include_plaintext| {
+            let stream = export(
+                state.pool.clone(),
+                Some(cipher.clone()),
+                include_plaintext,
+                now_ts(),
+            );
+            async move {
+                let body: Vec<String> = stream.try_collect().await.unwrap();
+                body.concat()
+                    .lines()
+                    .map(|line| serde_json::from_str::<BackupLine>(line).unwrap())
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        let sealed = lines(false).await;
+        assert_eq!(sealed.len(), 1);
+        assert_eq!(sealed[0].version, FORMAT_VERSION);
+        assert_eq!(sealed[0].paste.encoding, "encrypted");
+        assert_ne!(sealed[0].paste.content, "secret");
+
+        let open = lines(true).await;
+        assert_eq!(open[0].paste.encoding, "plain");
+        assert_eq!(open[0].paste.content, "secret");
+        assert_eq!(open[0].paste.nonce, None);
+        assert_eq!(open[0].paste.token, sealed[0].paste.token);
+    }
+
+    #[tokio::test]
+    async fn test_import_round_trip() {
+        let state = test_state().await;
+        let cipher = ContentCipher::from_hex(&"cd".repeat(32)).unwrap();
+        PasteBuilder::new()
+            .content("burn after reading")
+            .expires_in(600)
+            .max_views(2)
+            .public(true)
+            .encrypted(&cipher)
+            .insert(&state.pool)
+            .await;
+        PasteBuilder::new()
+            .expires_in(60)
+            .encrypted(&cipher)
+            .insert(&state.pool)
+            .await;
+        let before = export_string(&state.pool, Some(&cipher)).await;
+
+        sqlx::query("DELETE FROM pastes")
+            .execute(&state.pool)
+            .await
+            .unwrap();
+        let summary = import(
+            &state.pool,
+            Some(&cipher),
+            lines(&before),
+            false,
+            i64::MAX,
+            now_ts(),
+        )
+        .await;
+        assert_eq!(
+            summary,
+            ImportSummary {
+                inserted: 2,
+                skipped: 0,
+                failed: 0
+            }
+        );
+        assert_eq!(export_string(&state.pool, Some(&cipher)).await, before);
+
+        let again = import(
+            &state.pool,
+            Some(&cipher),
+            lines(&before),
+            false,
+            i64::MAX,
+            now_ts(),
+        )
+        .await;
+        assert_eq!(again.skipped, 2);
+        let replaced = import(
+            &state.pool,
+            Some(&cipher),
+            lines(&before),
+            true,
+            i64::MAX,
+            now_ts(),
+        )
+        .await;
+        assert_eq!(replaced.inserted, 2);
+    }
+
+    #[tokio::test]
+    async fn test_import_round_trip_keeps_files() {
+        let state = test_state().await;
+        let cipher = ContentCipher::from_hex(&"ef".repeat(32)).unwrap();
+        let created = PasteBuilder::new()
+            .content("fn main() {}")
+            .file("notes.md", "# notes")
+            .file("data.json", "{\"a\": 1")
+            .language("json")
+            .encrypted(&cipher)
+            .insert(&state.pool)
+            .await;
+        let before = export_string(&state.pool, Some(&cipher)).await;
+        let stored = |pool: SqlitePool| async move {
+            sqlx::query_as::<_, (Option<String>, i64, Option<bool>, i64)>(
+                r#"
+                SELECT filename, files_length, syntax_valid,
+                       (SELECT COUNT(*) FROM paste_files)
+                FROM pastes
+                "#,
+            )
+            .fetch_one(&pool)
+            .await
+            .unwrap()
+        };
+        let columns = stored(state.pool.clone()).await;
+        assert_eq!(columns.0.as_deref(), Some("main.txt"));
+        assert_eq!(columns.3, 2);
+
+        sqlx::query("DELETE FROM pastes")
+            .execute(&state.pool)
+            .await
+            .unwrap();
+        let summary = import(
+            &state.pool,
+            Some(&cipher),
+            lines(&before),
+            false,
+            i64::MAX,
+            now_ts(),
+        )
+        .await;
+        assert_eq!(summary.inserted, 1);
+        assert_eq!(stored(state.pool.clone()).await, columns);
+        assert_eq!(export_string(&state.pool, Some(&cipher)).await, before);
+        // Replacing it does not leave the old files behind.
+        let replaced = import(
+            &state.pool,
+            Some(&cipher),
+            lines(&before),
+            true,
+            i64::MAX,
+            now_ts(),
+        )
+        .await;
+        assert_eq!(replaced.inserted, 1);
+        assert_eq!(stored(state.pool.clone()).await, columns);
+
+        let paste = db::fetch_paste(&state.pool, &created.token, Some(&cipher), now_ts())
+            .await
+            .unwrap()
+            .unwrap();
+        let files: Vec<_> = paste
+            .files
+            .iter()
+            .map(|file| (file.filename.as_str(), file.content.as_str()))
+            .collect();
+        assert_eq!(files, [("notes.md", "# notes"), ("data.json", "{\"a\": 1")]);
+    }
+
+    #[tokio::test]
+    async fn test_import_checks_version_and_storage_limit() {
+        let state = test_state().await;
+        let line = |version: u32, token: &str, content: &str| {
+            format!(
+                r#"{{"version":{},"token":"{}","title":"t","content":"{}","encoding":"plain","nonce":null,"language":"plaintext","created_at":{},"expires_at":{},"views":0,"max_views":null,"is_public":false,"is_e2e":false,"flagged":false,"owner_id":null}}"#,
+                version,
+                token,
+                content,
+                now_ts(),
+                now_ts() + 300
+            )
+        };
+        let backup = [
+            line(FORMAT_VERSION, "fits", "12345"),
+            line(FORMAT_VERSION + 1, "future", "1"),
+            line(FORMAT_VERSION, "toolong", "123456"),
+            "not json".to_string(),
+        ]
+        .join("\n");
+        let summary = import(&state.pool, None, lines(&backup), false, 10, now_ts()).await;
+        assert_eq!(
+            summary,
+            ImportSummary {
+                inserted: 1,
+                skipped: 0,
+                failed: 3
+            }
+        );
+
+        let meta = db::fetch_paste(&state.pool, "fits", None, now_ts())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(meta.content, "12345");
+        let (hash, duration): (Option<String>, i64) = sqlx::query_as(
+            "SELECT content_hash, original_duration FROM pastes WHERE token = 'fits'",
+        )
+        .fetch_one(&state.pool)
+        .await
+        .unwrap();
+        assert_eq!(hash, Some(sha256_hex("12345")));
+        assert_eq!(duration, 300);
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_keeps_newest() {
+        // `VACUUM INTO` from the in-memory test database writes nowhere.
+        let dir = tempfile::tempdir().unwrap();
+        let pool = db::connect(dir.path().join("live.db").to_str().unwrap()).await;
+        let config = BackupConfig {
+            dir: dir.path().join("backups").to_str().unwrap().to_string(),
+            interval_secs: 60,
+            keep: 2,
+        };
+        let mut paths = Vec::new();
+        for _ in 0..3 {
+            let snapshot = snapshot(&pool, &config).await.unwrap();
+            assert!(snapshot.size > 0);
+            paths.push(snapshot.path);
+            tokio::time::sleep(Duration::from_millis(2)).await;
+        }
+        assert!(!Path::new(&paths[0]).exists());
+        assert!(Path::new(&paths[1]).exists());
+        assert!(Path::new(&paths[2]).exists());
+    }
+}
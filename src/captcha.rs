@@ -0,0 +1,84 @@
+//! CAPTCHA verification for paste creation.
+
+use crate::models::{CaptchaConfig, CaptchaProvider};
+use serde::Deserialize;
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+
+pub type VerifyFuture<'a> = Pin<Box<dyn Future<Output = Result<bool, String>> + Send + 'a>>;
+
+/// Checks a response token produced by the CAPTCHA widget. `Ok(false)`
+/// means the provider rejected it; `Err` means it could not be asked.
+pub trait CaptchaVerifier: Send + Sync {
+    fn verify<'a>(&'a self, response: &'a str) -> VerifyFuture<'a>;
+}
+
+/// Asks the provider's siteverify endpoint.
+pub struct SiteVerifier {
+    client: reqwest::Client,
+    endpoint: &'static str,
+    secret: String,
+}
+
+#[derive(Deserialize)]
+struct SiteVerifyResponse {
+    success: bool,
+}
+
+impl SiteVerifier {
+    pub fn new(config: &CaptchaConfig) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.timeout_secs))
+            .build()
+            .expect("Failed to build HTTP client");
+        let endpoint = match config.provider {
+            CaptchaProvider::Turnstile => {
+                "https://challenges.cloudflare.com/turnstile/v0/siteverify"
+            }
+            CaptchaProvider::Hcaptcha => "https://api.hcaptcha.com/siteverify",
+        };
+        Self {
+            client,
+            endpoint,
+            secret: config.secret.clone(),
+        }
+    }
+}
+
+impl CaptchaVerifier for SiteVerifier {
+    fn verify<'a>(&'a self, response: &'a str) -> VerifyFuture<'a> {
+        Box::pin(async move {
+            let reply: SiteVerifyResponse = self
+                .client
+                .post(self.endpoint)
+                .form(&[("secret", self.secret.as_str()), ("response", response)])
+                .send()
+                .await
+                .map_err(|err| err.to_string())?
+                .json()
+                .await
+                .map_err(|err| err.to_string())?;
+            Ok(reply.success)
+        })
+    }
+}
+
+/// Whether a creation request may go ahead. Provider outages are decided by
+/// `fail_open`.
+pub async fn check(
+    verifier: &dyn CaptchaVerifier,
+    response: Option<&str>,
+    fail_open: bool,
+) -> bool {
+    let Some(response) = response.filter(|response| !response.is_empty()) else {
+        return false;
+    };
+    match verifier.verify(response).await {
+        Ok(passed) => passed,
+        Err(err) => {
+            eprintln!("CAPTCHA verification failed: {}", err);
+            fail_open
+        }
+    }
+}
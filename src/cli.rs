@@ -0,0 +1,265 @@
+//! Command-line interface: `mayfile [serve|check-config|cleanup|stats]`.
+
+use crate::backup;
+use crate::config::{self, validate_config};
+use crate::crypto::ContentCipher;
+use crate::db;
+use crate::models::{AppConfig, ImportSummary, PasteConfig, Stats};
+use clap::{Parser, Subcommand};
+use futures_util::TryStreamExt;
+use sqlx::SqlitePool;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::pin::pin;
+
+#[derive(Parser)]
+#[command(version, about = "A pastebin for short-lived moments")]
+pub struct Cli {
+    /// Path to the config file [default: config/app.toml, or built-in
+    /// defaults when that is missing]
+    #[arg(long, global = true)]
+    pub config: Option<String>,
+    /// Override `server.host`
+    #[arg(long, global = true)]
+    pub host: Option<String>,
+    /// Override `server.port`
+    #[arg(long, global = true)]
+    pub port: Option<u16>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Clone, Debug, PartialEq, Subcommand)]
+pub enum Command {
+    /// Run the web server (the default)
+    Serve,
+    /// Validate the config and locale files, then exit
+    CheckConfig,
+    /// Delete expired pastes and enforce the size limits once, then exit
+    Cleanup,
+    /// Print what the database holds
+    Stats,
+    /// Write every alive paste as JSON Lines
+    Export {
+        /// Decrypt content stored under `encryption_key`
+        #[arg(long)]
+        include_plaintext: bool,
+        /// File to write instead of stdout
+        #[arg(long, short)]
+        output: Option<String>,
+    },
+    /// Restore pastes from a file written by `export`
+    Import {
+        file: String,
+        /// Replace existing pastes with the same token instead of skipping them
+        #[arg(long)]
+        overwrite: bool,
+    },
+}
+
+impl Cli {
+    /// The config file to read. Only the default path may be missing.
+    pub fn config_path(&self) -> Result<String, String> {
+        match &self.config {
+            Some(path) if !Path::new(path).exists() => {
+                Err(format!("Config file {} not found", path))
+            }
+            Some(path) => Ok(path.clone()),
+            None => Ok(config::CONFIG_PATH.to_string()),
+        }
+    }
+
+    pub fn apply_overrides(&self, config: &mut AppConfig) {
+        if let Some(host) = &self.host {
+            config.server.host = host.clone();
+        }
+        if let Some(port) = self.port {
+            config.server.port = port;
+        }
+    }
+}
+
+/// Everything `serve` would refuse to start with.
+pub fn check_config(config: &AppConfig) -> Result<(), Vec<String>> {
+    let mut errors = validate_config(config).err().unwrap_or_default();
+    if let Err(err) = config::load_i18n(&config.i18n) {
+        errors.push(err);
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// Pastes removed by one `cleanup` run.
+#[derive(Debug, PartialEq)]
+pub struct CleanupReport {
+    pub expired: u64,
+    pub evicted: u64,
+}
+
+pub async fn cleanup(pool: &SqlitePool, config: &AppConfig, now: i64) -> CleanupReport {
+    let webhooks = config.webhooks.is_some();
+    let config = &config.paste;
+    let expired = db::cleanup_expired(pool, config.event_retention_secs, webhooks, now).await;
+    let policy = config.eviction_policy;
+    let evicted = db::enforce_size_limit(pool, config.max_pastes, 0, policy, now)
+        .await
+        .deleted
+        + db::enforce_total_content_length(pool, config.max_total_content_length, 0, policy, now)
+            .await
+            .deleted;
+    CleanupReport {
+        expired: expired.deleted,
+        evicted,
+    }
+}
+
+/// Write the backup stream to `out`.
+pub async fn export(
+    pool: SqlitePool,
+    cipher: Option<ContentCipher>,
+    include_plaintext: bool,
+    now: i64,
+    out: &mut impl Write,
+) -> Result<(), String> {
+    let mut chunks = pin!(backup::export(pool, cipher, include_plaintext, now));
+    while let Some(chunk) = chunks.try_next().await.map_err(|err| err.to_string())? {
+        out.write_all(chunk.as_bytes())
+            .map_err(|err| err.to_string())?;
+    }
+    out.flush().map_err(|err| err.to_string())
+}
+
+pub async fn import(
+    pool: &SqlitePool,
+    cipher: Option<&ContentCipher>,
+    path: &str,
+    overwrite: bool,
+    config: &PasteConfig,
+    now: i64,
+) -> Result<ImportSummary, String> {
+    let file = File::open(path).map_err(|err| format!("Failed to open {}: {}", path, err))?;
+    Ok(backup::import(
+        pool,
+        cipher,
+        BufReader::new(file).lines(),
+        overwrite,
+        config.max_total_content_length,
+        now,
+    )
+    .await)
+}
+
+pub fn format_stats(stats: &Stats) -> String {
+    [
+        ("pastes", stats.pastes),
+        ("public", stats.public),
+        ("encrypted", stats.encrypted),
+        ("burn_after_reading", stats.burn_after_reading),
+        ("created", stats.created),
+        ("content_length", stats.content_length),
+        ("reported", stats.reported),
+        ("bans", stats.bans),
+    ]
+    .iter()
+    .map(|(name, value)| format!("{:<20}{}", name, value))
+    .collect::<Vec<_>>()
+    .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{PasteBuilder, test_config, test_state};
+    use crate::utils::now_ts;
+
+    #[test]
+    fn test_cli_overrides() {
+        let cli = Cli::try_parse_from(["mayfile", "--port", "9000", "cleanup"]).unwrap();
+        assert_eq!(cli.command, Some(Command::Cleanup));
+        assert_eq!(cli.config_path().unwrap(), config::CONFIG_PATH);
+        let mut config = test_config();
+        cli.apply_overrides(&mut config);
+        assert_eq!(config.server.port, 9000);
+        assert_eq!(config.server.host, "0.0.0.0");
+
+        let cli = Cli::try_parse_from(["mayfile", "stats", "--config", "other.toml"]).unwrap();
+        assert_eq!(cli.config.as_deref(), Some("other.toml"));
+        assert!(cli.config_path().is_err());
+        assert_eq!(Cli::try_parse_from(["mayfile"]).unwrap().command, None);
+    }
+
+    #[test]
+    fn test_check_config_lists_violations() {
+        let mut config = test_config();
+        assert!(check_config(&config).is_ok());
+        config.server.trusted_proxies = vec!["nope".to_string()];
+        config.paste.encryption_key = Some("short".to_string());
+        assert_eq!(check_config(&config).unwrap_err().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_cleanup_and_stats() {
+        let state = test_state().await;
+        for expires_in in [-1, 10, 20, 30] {
+            PasteBuilder::new()
+                .expires_in(expires_in)
+                .insert(&state.pool)
+                .await;
+        }
+        let mut config = AppConfig::clone(&state.config.load());
+        config.paste.max_pastes = 2;
+        assert_eq!(
+            cleanup(&state.pool, &config, now_ts()).await,
+            CleanupReport {
+                expired: 1,
+                evicted: 1
+            }
+        );
+
+        let stats = db::stats(&state.pool).await.unwrap();
+        assert_eq!(stats.pastes, 2);
+        assert_eq!(stats.created, 4);
+        assert_eq!(stats.content_length, 10);
+        assert!(format_stats(&stats).contains("pastes              2"));
+    }
+
+    #[tokio::test]
+    async fn test_export_then_import() {
+        let state = test_state().await;
+        for content in ["one", "two"] {
+            PasteBuilder::new()
+                .content(content)
+                .insert(&state.pool)
+                .await;
+        }
+        let mut out = Vec::new();
+        export(state.pool.clone(), None, false, now_ts(), &mut out)
+            .await
+            .unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out.lines().count(), 2);
+        assert!(out.lines().all(|line| line.starts_with(r#"{"version":2,"#)));
+
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), &out).unwrap();
+        sqlx::query("DELETE FROM pastes")
+            .execute(&state.pool)
+            .await
+            .unwrap();
+        let config = state.config.load().paste.clone();
+        let path = file.path().to_str().unwrap();
+        let summary = import(&state.pool, None, path, false, &config, now_ts())
+            .await
+            .unwrap();
+        assert_eq!(summary.inserted, 2);
+        assert!(
+            import(&state.pool, None, "missing.jsonl", false, &config, now_ts())
+                .await
+                .is_err()
+        );
+    }
+}
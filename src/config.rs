@@ -1,27 +1,402 @@
-use crate::models::{AppConfig, I18n, I18nConfig};
-use serde::Deserialize;
+use crate::assets;
+use crate::crypto::{ContentCipher, signing_key};
+use crate::markdown;
+use crate::models::{
+    AboutConfig, AboutPages, AppConfig, AppState, I18n, I18nConfig, Lang, PasteConfig,
+    ReloadSummary, TokenSpace,
+};
+use crate::net::Cidr;
+use crate::utils::parse_utc_offset;
+use axum::http::HeaderValue;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::fs;
+use std::path::Path;
+use std::sync::Arc;
 
-pub fn load_config() -> AppConfig {
-    read_toml("config/app.toml")
+pub const CONFIG_PATH: &str = "config/app.toml";
+
+pub fn load_config(path: &str) -> Result<AppConfig, String> {
+    read_toml_or(path, assets::DEFAULT_CONFIG)
 }
 
-pub fn load_i18n(config: &I18nConfig) -> I18n {
-    I18n {
-        zh: read_toml(&config.zh),
-        en: read_toml(&config.en),
+/// Read the strings of every locale. A locale missing from `[i18n]` uses
+/// the built-in file, and keys missing from a file are taken from English,
+/// or for English from the built-in file.
+pub fn load_i18n(config: &I18nConfig) -> Result<I18n, String> {
+    let builtin_en: toml::Table =
+        toml::from_str(assets::DEFAULT_EN).expect("Failed to parse built-in defaults");
+    let english = with_fallback(read_locale(config, Lang::En)?, &builtin_en);
+    let mut locales = HashMap::new();
+    for lang in Lang::ALL {
+        let mut table = match lang {
+            Lang::En => english.clone(),
+            _ => with_fallback(read_locale(config, lang)?, &english),
+        };
+        table.insert("lang".to_string(), lang.code().into());
+        let strings = toml::Value::Table(table)
+            .try_into()
+            .map_err(|err| format!("Failed to read the {} strings: {}", lang.code(), err))?;
+        locales.insert(lang, strings);
     }
+    Ok(I18n { locales })
+}
+
+fn read_locale(config: &I18nConfig, lang: Lang) -> Result<toml::Table, String> {
+    match config.locales.get(lang.code()) {
+        Some(path) => read_toml_or(path, lang.default_strings()),
+        None => {
+            Ok(toml::from_str(lang.default_strings()).expect("Failed to parse built-in defaults"))
+        }
+    }
+}
+
+/// `table` with the keys it lacks copied from `fallback`.
+fn with_fallback(mut table: toml::Table, fallback: &toml::Table) -> toml::Table {
+    for (key, value) in fallback {
+        table.entry(key).or_insert_with(|| value.clone());
+    }
+    table
+}
+
+/// Read and render the `/about` Markdown for each locale. A file that
+/// cannot be read leaves that locale without a page instead of failing.
+pub fn load_about(config: Option<&AboutConfig>) -> AboutPages {
+    let Some(config) = config else {
+        return AboutPages::default();
+    };
+    let load = |path: Option<&String>| {
+        let path = path.or(config.path.as_ref())?;
+        match fs::read_to_string(path) {
+            Ok(source) => Some(markdown::render(&source)),
+            Err(err) => {
+                eprintln!("Not serving /about from {}: {}", path, err);
+                None
+            }
+        }
+    };
+    let pages = Lang::ALL
+        .into_iter()
+        .filter_map(|lang| {
+            let page = load(config.locales.get(lang.code()))?;
+            Some((lang.code().to_string(), page))
+        })
+        .collect();
+    AboutPages { pages }
 }
 
+/// Check the loaded configuration for values the server cannot run with.
+pub fn validate_config(config: &AppConfig) -> Result<(), Vec<String>> {
+    let mut errors = Vec::new();
+    if let Some(key) = &config.paste.encryption_key
+        && let Err(err) = ContentCipher::from_hex(key)
+    {
+        errors.push(err);
+    }
+    if let Err(err) = signing_key(config.server.signing_key.as_deref()) {
+        errors.push(err);
+    }
+    for proxy in &config.server.trusted_proxies {
+        if let Err(err) = proxy.parse::<Cidr>() {
+            errors.push(format!("trusted_proxies: {}", err));
+        }
+    }
+    let known = || Lang::ALL.map(Lang::code).join(", ");
+    for code in config.i18n.locales.keys() {
+        if Lang::from_code(code).is_none() {
+            errors.push(format!(
+                "i18n: unknown locale {}, known are {}",
+                code,
+                known()
+            ));
+        }
+    }
+    if let Some(about) = &config.about {
+        for code in about.locales.keys() {
+            if Lang::from_code(code).is_none() {
+                errors.push(format!(
+                    "about: unknown locale {}, known are {}",
+                    code,
+                    known()
+                ));
+            }
+        }
+    }
+    if let Some(announcement) = &config.announcement {
+        for code in announcement.texts.keys() {
+            if Lang::from_code(code).is_none() {
+                errors.push(format!(
+                    "announcement: unknown locale {}, known are {}",
+                    code,
+                    known()
+                ));
+            }
+        }
+    }
+    for language in &config.languages {
+        for code in language.labels.keys() {
+            if Lang::from_code(code).is_none() {
+                errors.push(format!(
+                    "languages: unknown locale {} for {}, known are {}",
+                    code,
+                    language.value,
+                    known()
+                ));
+            }
+        }
+    }
+    if let Err(err) = parse_utc_offset(&config.server.display_timezone) {
+        errors.push(format!("display_timezone: {}", err));
+    }
+    if let Some(base_url) = &config.server.base_url
+        && !base_url.starts_with("http://")
+        && !base_url.starts_with("https://")
+    {
+        errors.push("base_url must start with http:// or https://".to_string());
+    }
+    if !(0..=100).contains(&config.paste.capacity_warning_percent) {
+        errors.push("capacity_warning_percent must be between 0 and 100".to_string());
+    }
+    if config.paste.event_retention_secs < 0 {
+        errors.push("event_retention_secs must not be negative".to_string());
+    }
+    if config.paste.repeat_window_secs < 0 {
+        errors.push("repeat_window_secs must not be negative".to_string());
+    }
+    if config.paste.max_views_limit < 1 {
+        errors.push("max_views_limit must be at least 1".to_string());
+    }
+    if !(1..=50).contains(&config.paste.recent_on_index_count) {
+        errors.push("recent_on_index_count must be between 1 and 50".to_string());
+    }
+    if !(1..=100_000).contains(&config.paste.explore_preview_chars) {
+        errors.push("explore_preview_chars must be between 1 and 100000".to_string());
+    }
+    if config.paste.max_diff_chars == 0 {
+        errors.push("max_diff_chars must be at least 1".to_string());
+    }
+    if let Some(popularity) = &config.paste.popularity
+        && (popularity.views < 1 || popularity.bonus_secs < 1 || popularity.max_extensions < 0)
+    {
+        errors.push(
+            "popularity: views and bonus_secs must be positive and max_extensions not negative"
+                .to_string(),
+        );
+    }
+    if let Some(cache) = &config.paste.cache
+        && (cache.max_entries < 1 || cache.max_bytes < 1 || cache.flush_secs < 1)
+    {
+        errors.push("cache: max_entries, max_bytes and flush_secs must be positive".to_string());
+    }
+    let mut seen = HashSet::new();
+    for language in &config.languages {
+        let value = &language.value;
+        let is_token = |text: &str| {
+            !text.is_empty()
+                && text
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "+#._-".contains(c))
+        };
+        if !is_token(value) || value == "auto" {
+            errors.push(format!(
+                "languages: {:?} is not a valid language value",
+                value
+            ));
+        } else if !seen.insert(value) {
+            errors.push(format!("languages: {} is listed twice", value));
+        }
+        for alias in &language.aliases {
+            if !is_token(alias) || alias == "auto" {
+                errors.push(format!("languages: {:?} is not a valid alias", alias));
+            } else if !seen.insert(alias) {
+                errors.push(format!("languages: {} is listed twice", alias));
+            }
+        }
+        if !is_token(&language.extension) {
+            errors.push(format!("languages: {} has an invalid extension", value));
+        }
+        if HeaderValue::from_str(&language.content_type).is_err() {
+            errors.push(format!("languages: {} has an invalid content_type", value));
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+#[cfg(test)]
 pub fn read_toml<T: for<'de> Deserialize<'de>>(path: &str) -> T {
-    let content = fs::read_to_string(path).expect(&format!("Failed to read {}", path));
-    toml::from_str(&content).expect(&format!("Failed to parse {}", path))
+    let content = fs::read_to_string(path).unwrap_or_else(|_| panic!("Failed to read {}", path));
+    toml::from_str(&content).unwrap_or_else(|_| panic!("Failed to parse {}", path))
+}
+
+/// Like `read_toml`, but a missing file falls back to the built-in
+/// `default`. A file that exists and does not parse is an error.
+pub fn read_toml_or<T: for<'de> Deserialize<'de>>(path: &str, default: &str) -> Result<T, String> {
+    if !Path::new(path).exists() {
+        println!("{} not found, using built-in defaults", path);
+        return Ok(toml::from_str(default).expect("Failed to parse built-in defaults"));
+    }
+    let content =
+        fs::read_to_string(path).map_err(|err| format!("Failed to read {}: {}", path, err))?;
+    toml::from_str(&content).map_err(|err| format!("Failed to parse {}: {}", path, err))
+}
+
+/// Re-read the config file, locales and about pages and swap them into
+/// `state`. Settings that are only read at startup keep their running
+/// values and are reported as ignored. Nothing changes if the new config is
+/// invalid.
+pub fn reload(state: &AppState) -> Result<ReloadSummary, Vec<String>> {
+    let mut config = load_config(&state.config_path).map_err(|err| vec![err])?;
+    validate_config(&config)?;
+    let i18n = load_i18n(&config.i18n).map_err(|err| vec![err])?;
+    let about = load_about(config.about.as_ref());
+
+    let current = state.config.load_full();
+    let ignored = changed_keys("", &restart_only(&current), &restart_only(&config));
+    for key in &ignored {
+        eprintln!("Ignoring change to {} until restart", key);
+    }
+    config.server.host = current.server.host.clone();
+    config.server.port = current.server.port;
+    config.server.signing_key = current.server.signing_key.clone();
+    config.server.assets_dir = current.server.assets_dir.clone();
+    config.server.compression = current.server.compression;
+    config.paste.db_path = current.paste.db_path.clone();
+    config.paste.encryption_key = current.paste.encryption_key.clone();
+    config.paste.reports_per_hour = current.paste.reports_per_hour;
+    config.paste.reactions_per_minute = current.paste.reactions_per_minute;
+    config.captcha = current.captcha.clone();
+
+    let mut changed = changed_keys("", current.as_ref(), &config);
+    let current_i18n = state.i18n.load_full();
+    for lang in Lang::ALL {
+        let prefix = format!("i18n.{}", lang.code());
+        changed.extend(changed_keys(
+            &prefix,
+            &current_i18n.strings(lang),
+            &i18n.strings(lang),
+        ));
+    }
+    if **state.about.load() != about {
+        changed.push("about".to_string());
+    }
+
+    if let Some(warning) = token_saturation_warning(&config.paste) {
+        eprintln!("{}", warning);
+    }
+    state.config.store(Arc::new(config));
+    state.i18n.store(Arc::new(i18n));
+    state.about.store(Arc::new(about));
+    if changed.is_empty() {
+        println!("Reloaded configuration: no changes");
+    } else {
+        println!("Reloaded configuration: {}", changed.join(", "));
+    }
+    Ok(ReloadSummary { changed, ignored })
+}
+
+/// A warning to log when `max_pastes` pastes would crowd the shortest
+/// tokens enough that new pastes often draw one already taken.
+pub fn token_saturation_warning(config: &PasteConfig) -> Option<String> {
+    let space = TokenSpace::new(config, 0);
+    space.saturated.then(|| {
+        format!(
+            "WARNING: max_pastes = {} would take {:.2}% of the {}-character tokens, so new \
+             pastes will often draw one already taken; offer only longer token_lengths or \
+             lower max_pastes",
+            config.max_pastes,
+            space.occupancy * 100.0,
+            space.min_token_length
+        )
+    })
+}
+
+/// The settings `reload` cannot apply: they are baked into the listener,
+/// the database pool or state built once in `main`.
+fn restart_only(config: &AppConfig) -> toml::Table {
+    let mut table = toml::Table::new();
+    let mut put = |key: &str, value: Option<toml::Value>| {
+        if let Some(value) = value {
+            table.insert(key.to_string(), value);
+        }
+    };
+    let server = &config.server;
+    let paste = &config.paste;
+    put("server.host", Some(server.host.clone().into()));
+    put("server.port", Some(i64::from(server.port).into()));
+    put(
+        "server.signing_key",
+        server.signing_key.clone().map(Into::into),
+    );
+    put(
+        "server.assets_dir",
+        server.assets_dir.clone().map(Into::into),
+    );
+    put("server.compression", Some(server.compression.into()));
+    put("paste.db_path", Some(paste.db_path.clone().into()));
+    put(
+        "paste.encryption_key",
+        paste.encryption_key.clone().map(Into::into),
+    );
+    put(
+        "paste.reports_per_hour",
+        Some(i64::from(paste.reports_per_hour).into()),
+    );
+    put(
+        "paste.reactions_per_minute",
+        Some(i64::from(paste.reactions_per_minute).into()),
+    );
+    put(
+        "captcha",
+        config
+            .captcha
+            .as_ref()
+            .and_then(|captcha| toml::Value::try_from(captcha).ok()),
+    );
+    table
+}
+
+/// Dotted keys whose values differ between `old` and `new`.
+fn changed_keys<T: Serialize>(prefix: &str, old: &T, new: &T) -> Vec<String> {
+    let old = toml::Value::try_from(old).expect("Failed to serialize config");
+    let new = toml::Value::try_from(new).expect("Failed to serialize config");
+    let mut keys = Vec::new();
+    diff_values(prefix, &old, &new, &mut keys);
+    keys
+}
+
+fn diff_values(prefix: &str, old: &toml::Value, new: &toml::Value, keys: &mut Vec<String>) {
+    let (toml::Value::Table(old), toml::Value::Table(new)) = (old, new) else {
+        if old != new {
+            keys.push(prefix.to_string());
+        }
+        return;
+    };
+    let mut names: Vec<&String> = old.keys().chain(new.keys()).collect();
+    names.sort();
+    names.dedup();
+    for name in names {
+        let key = if prefix.is_empty() {
+            name.clone()
+        } else {
+            format!("{}.{}", prefix, name)
+        };
+        match (old.get(name), new.get(name)) {
+            (Some(old), Some(new)) => diff_values(&key, old, new, keys),
+            _ => keys.push(key),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::models::{AnnouncementConfig, Severity};
     use serde::Deserialize;
+    use std::collections::BTreeMap;
     use std::io::Write;
     use tempfile::NamedTempFile;
 
@@ -39,4 +414,99 @@ mod tests {
         let config: TestConfig = read_toml(tmp_file.path().to_str().unwrap());
         assert_eq!(config, TestConfig { key: "hello".to_string(), value: 42 });
     }
+
+    #[test]
+    fn test_read_toml_or_falls_back_when_missing() {
+        let config: AppConfig =
+            read_toml_or("does/not/exist.toml", assets::DEFAULT_CONFIG).unwrap();
+        assert_eq!(config.server.port, 8080);
+        let zh: crate::models::Strings =
+            read_toml_or("does/not/exist.toml", assets::DEFAULT_ZH).unwrap();
+        assert_eq!(zh.lang, "zh");
+    }
+
+    #[test]
+    fn test_locales_fall_back_to_english() {
+        let mut ja = NamedTempFile::new().unwrap();
+        writeln!(ja, "detail_copy = 'コピーする'").unwrap();
+        let config = I18nConfig {
+            locales: BTreeMap::from([
+                ("ja".to_string(), ja.path().to_str().unwrap().to_string()),
+                ("en".to_string(), "does/not/exist.toml".to_string()),
+            ]),
+        };
+        let i18n = load_i18n(&config).unwrap();
+        let ja = i18n.strings(Lang::Ja);
+        assert_eq!(ja.lang, "ja");
+        assert_eq!(ja.detail_copy, "コピーする");
+        assert_eq!(ja.button_create, "Create");
+        assert_eq!(ja.labels["language_plaintext"], "Plain Text");
+        // Left out of the config, a locale uses its built-in file
+        assert_eq!(i18n.strings(Lang::Zh).button_create, "创建");
+        assert_eq!(i18n.strings(Lang::En).button_create, "Create");
+    }
+
+    #[test]
+    fn test_inline_texts_are_keyed_by_locale() {
+        let example = assets::DEFAULT_CONFIG.to_string()
+            + r#"
+[announcement]
+ja = "メンテナンス"
+en = "Maintenance"
+severity = "warn"
+expires_at = 1767225600
+
+[[languages]]
+value = "toml"
+ja = "TOML 形式"
+"#;
+        let config: AppConfig = toml::from_str(&example).unwrap();
+        let announcement = config.announcement.as_ref().unwrap();
+        assert_eq!(announcement.expires_at, Some(1767225600));
+        assert_eq!(announcement.for_lang("ja", 0).unwrap().text, "メンテナンス");
+        assert_eq!(announcement.for_lang("zh", 0).unwrap().text, "Maintenance");
+        let language = config.languages.last().unwrap();
+        assert_eq!(language.labels["ja"], "TOML 形式");
+        assert!(validate_config(&config).is_ok());
+    }
+
+    #[test]
+    fn test_unknown_locale_is_rejected() {
+        let mut config = crate::test_support::test_config();
+        config
+            .i18n
+            .locales
+            .insert("xx".to_string(), "config/i18n/xx.toml".to_string());
+        config.announcement = Some(AnnouncementConfig {
+            severity: Severity::Info,
+            expires_at: None,
+            texts: BTreeMap::from([("xx".to_string(), "Hello".to_string())]),
+        });
+        config.languages[0]
+            .labels
+            .insert("xx".to_string(), "Text".to_string());
+        let errors = validate_config(&config).unwrap_err();
+        assert_eq!(
+            errors,
+            [
+                "i18n: unknown locale xx, known are en, zh, ja",
+                "announcement: unknown locale xx, known are en, zh, ja",
+                "languages: unknown locale xx for plaintext, known are en, zh, ja",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_explore_preview_chars_is_bounded() {
+        let mut config = crate::test_support::test_config();
+        for chars in [0, 100_001] {
+            config.paste.explore_preview_chars = chars;
+            assert_eq!(
+                validate_config(&config).unwrap_err(),
+                ["explore_preview_chars must be between 1 and 100000"]
+            );
+        }
+        config.paste.explore_preview_chars = 100_000;
+        assert!(validate_config(&config).is_ok());
+    }
 }
@@ -0,0 +1,91 @@
+//! How many pastes explore lists, shared by the explore page, each swipe
+//! through `/api/v1/explore` and the index. The count is kept for a few
+//! seconds so swiping does not cost a second query per paste. The same
+//! goes for how much is stored, which `/api/v1/limits` reports.
+
+use crate::db;
+use crate::models::PublicFilter;
+use sqlx::SqlitePool;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// How long a count is served before it is taken again.
+const CACHE_SECS: i64 = 10;
+
+/// Recent counts by filter, with when they were taken.
+#[derive(Default)]
+pub struct PublicCountCache {
+    entries: Mutex<HashMap<Option<u64>, (i64, i64)>>,
+}
+
+impl PublicCountCache {
+    /// The number of pastes `filter` lets through, from the cache while it
+    /// is fresh.
+    pub async fn get(
+        &self,
+        pool: &SqlitePool,
+        filter: &PublicFilter,
+        now: i64,
+    ) -> Result<i64, sqlx::Error> {
+        let key = filter.expiring_below.map(f64::to_bits);
+        if let Some((at, count)) = self.entries.lock().unwrap().get(&key)
+            && now - at < CACHE_SECS
+        {
+            return Ok(*count);
+        }
+        let count = db::count_public_pastes(pool, filter, now).await?;
+        self.entries.lock().unwrap().insert(key, (now, count));
+        Ok(count)
+    }
+}
+
+/// Pastes and characters stored, with when they were counted.
+#[derive(Default)]
+pub struct UsageCache {
+    entry: Mutex<Option<(i64, (i64, i64))>>,
+}
+
+impl UsageCache {
+    /// `db::usage`, from the cache while it is fresh.
+    pub async fn get(&self, pool: &SqlitePool, now: i64) -> Result<(i64, i64), sqlx::Error> {
+        if let Some((at, usage)) = *self.entry.lock().unwrap()
+            && now - at < CACHE_SECS
+        {
+            return Ok(usage);
+        }
+        let usage = db::usage(pool).await?;
+        *self.entry.lock().unwrap() = Some((now, usage));
+        Ok(usage)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{PasteBuilder, test_state};
+    use crate::utils::now_ts;
+
+    #[tokio::test]
+    async fn test_count_is_cached_per_filter() {
+        let state = test_state().await;
+        let cache = PublicCountCache::default();
+        PasteBuilder::new()
+            .public(true)
+            .lifespan(1000)
+            .expires_in(100)
+            .insert(&state.pool)
+            .await;
+        let all = PublicFilter::default();
+        let expiring = PublicFilter {
+            expiring_below: Some(0.25),
+        };
+        let now = now_ts();
+        assert_eq!(cache.get(&state.pool, &all, now).await.unwrap(), 1);
+
+        PasteBuilder::new().public(true).insert(&state.pool).await;
+        assert_eq!(cache.get(&state.pool, &all, now).await.unwrap(), 1);
+        assert_eq!(cache.get(&state.pool, &expiring, now).await.unwrap(), 1);
+        let later = now + CACHE_SECS;
+        assert_eq!(cache.get(&state.pool, &all, later).await.unwrap(), 2);
+    }
+}
@@ -0,0 +1,314 @@
+use base64::{Engine, engine::general_purpose::STANDARD};
+use chacha20poly1305::{
+    XChaCha20Poly1305, XNonce,
+    aead::{Aead, AeadCore, KeyInit, OsRng, rand_core::RngCore},
+};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::net::IpAddr;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Encrypts paste content at rest with XChaCha20-Poly1305.
+#[derive(Clone)]
+pub struct ContentCipher {
+    cipher: XChaCha20Poly1305,
+}
+
+impl ContentCipher {
+    /// Build a cipher from a 32-byte key written as 64 hex characters.
+    pub fn from_hex(key: &str) -> Result<Self, String> {
+        let bytes = hex::decode(key.trim())
+            .map_err(|err| format!("encryption_key is not valid hex: {}", err))?;
+        let cipher = XChaCha20Poly1305::new_from_slice(&bytes).map_err(|_| {
+            format!(
+                "encryption_key must be 32 bytes (64 hex characters), got {} bytes",
+                bytes.len()
+            )
+        })?;
+        Ok(Self { cipher })
+    }
+
+    /// Encrypt with a fresh random nonce. Returns `(ciphertext, nonce)`,
+    /// both base64-encoded so they fit the existing TEXT columns.
+    pub fn encrypt(&self, plaintext: &str) -> (String, String) {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, plaintext.as_bytes())
+            .expect("XChaCha20-Poly1305 encryption failed");
+        (STANDARD.encode(ciphertext), STANDARD.encode(nonce))
+    }
+
+    pub fn decrypt(&self, ciphertext: &str, nonce: &str) -> Option<String> {
+        let ciphertext = STANDARD.decode(ciphertext).ok()?;
+        let nonce = STANDARD.decode(nonce).ok()?;
+        if nonce.len() != 24 {
+            return None;
+        }
+        let plaintext = self
+            .cipher
+            .decrypt(XNonce::from_slice(&nonce), ciphertext.as_ref())
+            .ok()?;
+        String::from_utf8(plaintext).ok()
+    }
+}
+
+/// Recover the stored content of a row, decrypting it if it was written
+/// encrypted. Plaintext rows pass through untouched whether or not a key is
+/// configured; encrypted rows without a (matching) key yield `None`.
+pub fn open_content(
+    cipher: Option<&ContentCipher>,
+    content: String,
+    is_encrypted: bool,
+    nonce: Option<&str>,
+) -> Option<String> {
+    if !is_encrypted {
+        return Some(content);
+    }
+    let plaintext = cipher.zip(nonce).and_then(|(c, n)| c.decrypt(&content, n));
+    if plaintext.is_none() {
+        eprintln!("Failed to decrypt paste content; is encryption_key configured?");
+    }
+    plaintext
+}
+
+/// Decode the configured signing key, or generate a random one. The server
+/// keeps the one it generates in the database; see `db::stored_signing_key`.
+pub fn signing_key(configured: Option<&str>) -> Result<Vec<u8>, String> {
+    match configured {
+        Some(key) => {
+            let bytes = hex::decode(key.trim())
+                .map_err(|err| format!("signing_key is not valid hex: {}", err))?;
+            if bytes.len() < 16 {
+                return Err("signing_key must be at least 16 bytes".to_string());
+            }
+            Ok(bytes)
+        }
+        None => {
+            let mut bytes = vec![0u8; 32];
+            OsRng.fill_bytes(&mut bytes);
+            Ok(bytes)
+        }
+    }
+}
+
+/// Signature for a share link granting access to `token` until `exp`.
+pub fn sign_share_link(key: &[u8], token: &str, exp: i64) -> String {
+    let mut mac =
+        <HmacSha256 as Mac>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(format!("{}:{}", token, exp).as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// A share link is valid while `now < exp` and the signature matches.
+pub fn verify_share_link(key: &[u8], token: &str, exp: i64, sig: &str, now: i64) -> bool {
+    if now >= exp {
+        return false;
+    }
+    let Ok(sig) = hex::decode(sig) else {
+        return false;
+    };
+    let mut mac =
+        <HmacSha256 as Mac>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(format!("{}:{}", token, exp).as_bytes());
+    mac.verify_slice(&sig).is_ok()
+}
+
+/// Proof, kept in a cookie, that the client `client_hash` already spent a
+/// view on `token`: `{exp}.{nonce}.{mac}`, good until `exp`. The nonce
+/// lets the server take it only once.
+pub fn view_receipt(key: &[u8], token: &str, client_hash: &str, nonce: &str, exp: i64) -> String {
+    let mut mac =
+        <HmacSha256 as Mac>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(format!("view:{}:{}:{}:{}", token, client_hash, nonce, exp).as_bytes());
+    format!(
+        "{}.{}.{}",
+        exp,
+        nonce,
+        hex::encode(mac.finalize().into_bytes())
+    )
+}
+
+/// The nonce and expiry of `receipt` if `view_receipt` gave it for `token`
+/// to `client_hash` and it is still good at `now`. The signature is
+/// compared in constant time.
+pub fn verify_view_receipt(
+    key: &[u8],
+    token: &str,
+    client_hash: &str,
+    receipt: &str,
+    now: i64,
+) -> Option<(String, i64)> {
+    let mut parts = receipt.splitn(3, '.');
+    let exp: i64 = parts.next()?.parse().ok()?;
+    let nonce = parts.next()?;
+    let sig = parts.next()?;
+    let message = format!("view:{}:{}:{}:{}", token, client_hash, nonce, exp);
+    (now < exp && verify_mac(key, &message, sig)).then(|| (nonce.to_string(), exp))
+}
+
+/// Proof, kept in a cookie, of which reactions (bits of `mask`) this client
+/// left on `token`.
+pub fn reaction_receipt(key: &[u8], token: &str, mask: u8) -> String {
+    let mut mac =
+        <HmacSha256 as Mac>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(format!("reaction:{}:{}", token, mask).as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Whether `receipt` is the one `reaction_receipt` gives `token` and
+/// `mask`, compared in constant time.
+pub fn verify_reaction_receipt(key: &[u8], token: &str, mask: u8, receipt: &str) -> bool {
+    verify_mac(key, &format!("reaction:{}:{}", token, mask), receipt)
+}
+
+/// Keyed hash of a client address for the event log: one client's entries
+/// can be told apart without the address being stored.
+pub fn client_hash(key: &[u8], ip: IpAddr) -> String {
+    let mut mac =
+        <HmacSha256 as Mac>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(format!("client:{}", ip).as_bytes());
+    hex::encode(&mac.finalize().into_bytes()[..8])
+}
+
+/// Signature in the `owner` cookie, so the id it carries cannot be forged
+/// or swapped for another creator's.
+pub fn owner_signature(key: &[u8], owner_id: &str) -> String {
+    let mut mac =
+        <HmacSha256 as Mac>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(format!("owner:{}", owner_id).as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Whether `signature` is the one `owner_signature` gives `owner_id`,
+/// compared in constant time.
+pub fn verify_owner_signature(key: &[u8], owner_id: &str, signature: &str) -> bool {
+    verify_mac(key, &format!("owner:{}", owner_id), signature)
+}
+
+/// Whether hex `sig` is the HMAC of `message` under `key`. The comparison
+/// takes the same time however much of it matches.
+fn verify_mac(key: &[u8], message: &str, sig: &str) -> bool {
+    let Ok(sig) = hex::decode(sig) else {
+        return false;
+    };
+    let mut mac =
+        <HmacSha256 as Mac>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(message.as_bytes());
+    mac.verify_slice(&sig).is_ok()
+}
+
+/// Session cookie value proving the holder signed in with `admin_token`.
+pub fn admin_session(key: &[u8], admin_token: &str) -> String {
+    let mut mac =
+        <HmacSha256 as Mac>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(format!("admin:{}", admin_token).as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Whether `session` is the cookie value `admin_session` gives
+/// `admin_token`, compared in constant time.
+pub fn verify_admin_session(key: &[u8], admin_token: &str, session: &str) -> bool {
+    verify_mac(key, &format!("admin:{}", admin_token), session)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+
+    #[test]
+    fn test_from_hex_validates_key() {
+        assert!(ContentCipher::from_hex(KEY).is_ok());
+        assert!(ContentCipher::from_hex("abcd").is_err());
+        assert!(ContentCipher::from_hex("zz").is_err());
+    }
+
+    #[test]
+    fn test_encrypt_round_trip() {
+        let cipher = ContentCipher::from_hex(KEY).unwrap();
+        let (ciphertext, nonce) = cipher.encrypt("fn main() {}");
+        assert_ne!(ciphertext, "fn main() {}");
+        assert_eq!(cipher.decrypt(&ciphertext, &nonce).unwrap(), "fn main() {}");
+
+        let other = ContentCipher::from_hex(&KEY.replace("00", "ff")).unwrap();
+        assert!(other.decrypt(&ciphertext, &nonce).is_none());
+    }
+
+    #[test]
+    fn test_open_content_passes_plaintext_through() {
+        assert_eq!(
+            open_content(None, "plain".to_string(), false, None).unwrap(),
+            "plain"
+        );
+        assert!(open_content(None, "c2VjcmV0".to_string(), true, Some("bm9uY2U=")).is_none());
+    }
+
+    #[test]
+    fn test_share_link_signature() {
+        let key = signing_key(None).unwrap();
+        let sig = sign_share_link(&key, "abcd", 1_000);
+        assert!(verify_share_link(&key, "abcd", 1_000, &sig, 999));
+        assert!(!verify_share_link(&key, "abce", 1_000, &sig, 999));
+        assert!(!verify_share_link(&key, "abcd", 1_001, &sig, 999));
+
+        let mut tampered = sig.clone();
+        tampered.replace_range(0..1, if sig.starts_with('0') { "1" } else { "0" });
+        assert!(!verify_share_link(&key, "abcd", 1_000, &tampered, 999));
+        assert!(!verify_share_link(&key, "abcd", 1_000, "not-hex", 999));
+    }
+
+    #[test]
+    fn test_share_link_expiry_edge() {
+        let key = signing_key(Some(KEY)).unwrap();
+        let sig = sign_share_link(&key, "abcd", 1_000);
+        assert!(verify_share_link(&key, "abcd", 1_000, &sig, 999));
+        assert!(!verify_share_link(&key, "abcd", 1_000, &sig, 1_000));
+        assert!(!verify_share_link(&key, "abcd", 1_000, &sig, 1_001));
+    }
+
+    #[test]
+    fn test_owner_signature_verifies() {
+        let key = signing_key(None).unwrap();
+        let sig = owner_signature(&key, "alice");
+        assert!(verify_owner_signature(&key, "alice", &sig));
+        assert!(!verify_owner_signature(&key, "bob", &sig));
+        assert!(!verify_owner_signature(&key, "alice", &sig[..32]));
+        assert!(!verify_owner_signature(&key, "alice", "not-hex"));
+    }
+
+    #[test]
+    fn test_view_receipt_verifies() {
+        let key = signing_key(None).unwrap();
+        let receipt = view_receipt(&key, "abcd", "client", "nonce", 100);
+        assert_eq!(
+            verify_view_receipt(&key, "abcd", "client", &receipt, 99),
+            Some(("nonce".to_string(), 100))
+        );
+        assert!(verify_view_receipt(&key, "abce", "client", &receipt, 99).is_none());
+        // Another client, or the same one once it has run out, has no receipt.
+        assert!(verify_view_receipt(&key, "abcd", "other", &receipt, 99).is_none());
+        assert!(verify_view_receipt(&key, "abcd", "client", &receipt, 100).is_none());
+        let stretched = receipt.replacen("100.", "200.", 1);
+        assert!(verify_view_receipt(&key, "abcd", "client", &stretched, 150).is_none());
+    }
+
+    #[test]
+    fn test_reaction_receipt_verifies() {
+        let key = signing_key(None).unwrap();
+        let receipt = reaction_receipt(&key, "abcd", 3);
+        assert!(verify_reaction_receipt(&key, "abcd", 3, &receipt));
+        assert!(!verify_reaction_receipt(&key, "abcd", 1, &receipt));
+    }
+
+    #[test]
+    fn test_admin_session_verifies() {
+        let key = signing_key(None).unwrap();
+        let session = admin_session(&key, "sesame");
+        assert!(verify_admin_session(&key, "sesame", &session));
+        assert!(!verify_admin_session(&key, "other", &session));
+        assert!(!verify_admin_session(&key, "sesame", "forged"));
+    }
+}
@@ -1,12 +1,51 @@
-use crate::utils::generate_token;
-use sqlx::{Row, SqlitePool};
+use crate::crypto::{ContentCipher, open_content};
+use crate::detect::detect_language;
+use crate::metrics;
+use crate::models::{
+    BackupFile, BackupPaste, BannedIp, CreatedPaste, DailyEvents, DailyViews, DayCounts,
+    DayLanguageCount, DbFileSizes, Event, EventType, EvictionPolicy, LanguageUsage, LargestPaste,
+    ListedPaste, ManagedPaste, Neighbor, Neighbors, NewFile, NewPaste, Paste, PasteFile, PasteMeta,
+    PendingView, PopularityConfig, PublicFilter, PublicPaste, PurgeFilter, RelatedLink,
+    ReportedPaste, SchemaReport, ShareLink, Stats, SubmittedPaste, Sweep, UsageTotals, ViewCount,
+    WebhookDelivery,
+};
+use crate::net::Cidr;
+use crate::syntax::{self, SyntaxError};
+use crate::utils::{generate_token, resolve_path, sha256_hex, token_occupancy};
+use regex::Regex;
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions};
+use sqlx::{Row, SqliteConnection, SqlitePool};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::net::IpAddr;
+use std::time::Duration;
+
+/// Open (creating if needed) the database at `db_path` and bring its schema
+/// up to date.
+pub async fn connect(db_path: &str) -> SqlitePool {
+    let db_path = resolve_path(db_path);
+    if let Some(parent) = db_path.parent() {
+        fs::create_dir_all(parent).unwrap();
+    }
+    let connect_options = SqliteConnectOptions::new()
+        .filename(db_path)
+        .create_if_missing(true)
+        .journal_mode(SqliteJournalMode::Wal);
+    let pool = SqlitePoolOptions::new()
+        .max_connections(5)
+        .connect_with(connect_options)
+        .await
+        .expect("Failed to connect to database");
+    ensure_schema(&pool).await;
+    pool
+}
 
 pub async fn ensure_schema(pool: &SqlitePool) {
     sqlx::query(
         r#"
         CREATE TABLE IF NOT EXISTS pastes (
             id INTEGER PRIMARY KEY AUTOINCREMENT,
-            token TEXT,
+            token TEXT COLLATE BINARY,
             title TEXT NOT NULL,
             content TEXT NOT NULL,
             language TEXT,
@@ -58,10 +97,13 @@ pub async fn ensure_schema(pool: &SqlitePool) {
             .await
             .unwrap();
     }
-    sqlx::query("CREATE UNIQUE INDEX IF NOT EXISTS idx_pastes_token ON pastes(token)")
-        .execute(pool)
-        .await
-        .unwrap();
+    verify_token_collation(pool).await;
+    sqlx::query(
+        "CREATE UNIQUE INDEX IF NOT EXISTS idx_pastes_token ON pastes(token COLLATE BINARY)",
+    )
+    .execute(pool)
+    .await
+    .unwrap();
     sqlx::query("UPDATE pastes SET expires_at = strftime('%s','now') WHERE expires_at IS NULL")
         .execute(pool)
         .await
@@ -134,184 +176,5064 @@ pub async fn ensure_schema(pool: &SqlitePool) {
             .await
             .unwrap();
     }
-}
 
-pub async fn cleanup_expired(pool: &SqlitePool) {
-    sqlx::query("DELETE FROM pastes WHERE expires_at <= strftime('%s','now')")
-        .execute(pool)
+    // Encryption at rest: ciphertext and nonce are stored base64-encoded
+    let columns = sqlx::query("PRAGMA table_info(pastes)")
+        .fetch_all(pool)
         .await
         .unwrap();
-}
+    let mut has_is_encrypted = false;
+    let mut has_nonce = false;
+    for column in columns {
+        let name: String = column.get("name");
+        if name == "is_encrypted" {
+            has_is_encrypted = true;
+        }
+        if name == "nonce" {
+            has_nonce = true;
+        }
+    }
+    if !has_is_encrypted {
+        sqlx::query("ALTER TABLE pastes ADD COLUMN is_encrypted INTEGER NOT NULL DEFAULT 0")
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+    if !has_nonce {
+        sqlx::query("ALTER TABLE pastes ADD COLUMN nonce TEXT")
+            .execute(pool)
+            .await
+            .unwrap();
+    }
 
-pub async fn enforce_size_limit(pool: &SqlitePool, max: i64, reserve: i64) {
-    let allowed = (max - reserve).max(0);
-    let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM pastes")
-        .fetch_one(pool)
+    // Client-side (end-to-end) encrypted pastes
+    let columns = sqlx::query("PRAGMA table_info(pastes)")
+        .fetch_all(pool)
         .await
-        .unwrap_or(0);
-    if count > allowed {
-        let overflow = count - allowed;
-        sqlx::query(
-            r#"
-            DELETE FROM pastes
-            WHERE id IN (
-                SELECT id FROM pastes
-                ORDER BY expires_at ASC, id ASC
-                LIMIT ?
-            )
-            "#,
-        )
-        .bind(overflow)
-        .execute(pool)
+        .unwrap();
+    let mut has_is_e2e = false;
+    for column in columns {
+        let name: String = column.get("name");
+        if name == "is_e2e" {
+            has_is_e2e = true;
+        }
+    }
+    if !has_is_e2e {
+        sqlx::query("ALTER TABLE pastes ADD COLUMN is_e2e INTEGER NOT NULL DEFAULT 0")
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+
+    // SHA-256 of the plaintext content, backfilled by `backfill_content_hashes`
+    let columns = sqlx::query("PRAGMA table_info(pastes)")
+        .fetch_all(pool)
         .await
         .unwrap();
+    let mut has_content_hash = false;
+    for column in columns {
+        let name: String = column.get("name");
+        if name == "content_hash" {
+            has_content_hash = true;
+        }
+    }
+    if !has_content_hash {
+        sqlx::query("ALTER TABLE pastes ADD COLUMN content_hash TEXT")
+            .execute(pool)
+            .await
+            .unwrap();
     }
-}
 
-pub async fn enforce_total_content_length(pool: &SqlitePool, max: i64, reserve: i64) {
-    let allowed = (max - reserve).max(0);
-    let mut total: i64 = sqlx::query_scalar("SELECT COALESCE(SUM(LENGTH(content)), 0) FROM pastes")
-        .fetch_one(pool)
+    // Opaque id from the creator's `owner` cookie
+    let columns = sqlx::query("PRAGMA table_info(pastes)")
+        .fetch_all(pool)
         .await
-        .unwrap_or(0);
-    if total <= allowed {
-        return;
+        .unwrap();
+    let mut has_owner_id = false;
+    for column in columns {
+        let name: String = column.get("name");
+        if name == "owner_id" {
+            has_owner_id = true;
+        }
     }
-    let rows = sqlx::query(
+    if !has_owner_id {
+        sqlx::query("ALTER TABLE pastes ADD COLUMN owner_id TEXT")
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+
+    // Named links to a paste, each with its own view budget
+    sqlx::query(
         r#"
-        SELECT id, LENGTH(content) AS len
-        FROM pastes
-        ORDER BY expires_at ASC, id ASC
+        CREATE TABLE IF NOT EXISTS share_links (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            paste_id INTEGER NOT NULL REFERENCES pastes(id) ON DELETE CASCADE,
+            share_token TEXT NOT NULL UNIQUE,
+            label TEXT NOT NULL,
+            max_views INTEGER,
+            views INTEGER NOT NULL DEFAULT 0,
+            created_at INTEGER NOT NULL DEFAULT (strftime('%s','now'))
+        )
         "#,
     )
-    .fetch_all(pool)
+    .execute(pool)
     .await
     .unwrap();
-    for row in rows {
-        if total <= allowed {
-            break;
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_share_links_paste ON share_links(paste_id)")
+        .execute(pool)
+        .await
+        .unwrap();
+
+    // Abuse reports; `flagged` marks pastes unpublished by them
+    let columns = sqlx::query("PRAGMA table_info(pastes)")
+        .fetch_all(pool)
+        .await
+        .unwrap();
+    let mut has_flagged = false;
+    for column in columns {
+        let name: String = column.get("name");
+        if name == "flagged" {
+            has_flagged = true;
         }
-        let id: i64 = row.get("id");
-        let len: i64 = row.get("len");
-        sqlx::query("DELETE FROM pastes WHERE id = ?")
-            .bind(id)
+    }
+    if !has_flagged {
+        sqlx::query("ALTER TABLE pastes ADD COLUMN flagged BOOLEAN NOT NULL DEFAULT 0")
             .execute(pool)
             .await
             .unwrap();
-        total -= len;
     }
-}
-
-pub async fn insert_paste(
-    pool: &SqlitePool,
-    title: String,
-    content: String,
-    expires_at: i64,
-    original_duration: i64,
-    token_length: usize,
-    language: String,
-    max_views: Option<i64>,
-    is_public: bool,
-) -> Result<String, sqlx::Error> {
-    let mut token = generate_token(token_length);
-    for _ in 0..5 {
-        let result = sqlx::query(
-            r#"
-            INSERT INTO pastes (token, title, content, expires_at, original_duration, language, max_views, is_public)
-            VALUES (?, ?, ?, ?, ?, ?, ?, ?)
-            "#,
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS reports (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            paste_id INTEGER NOT NULL REFERENCES pastes(id) ON DELETE CASCADE,
+            reporter TEXT NOT NULL,
+            reason TEXT,
+            created_at INTEGER NOT NULL DEFAULT (strftime('%s','now')),
+            UNIQUE (paste_id, reporter)
         )
-        .bind(&token)
-        .bind(&title)
-        .bind(&content)
-        .bind(expires_at)
-        .bind(original_duration)
-        .bind(&language)
-        .bind(max_views)
-        .bind(is_public)
-        .execute(pool)
-        .await;
+        "#,
+    )
+    .execute(pool)
+    .await
+    .unwrap();
 
-        match result {
-            Ok(_) => return Ok(token),
-            Err(err) => {
-                if err
-                    .as_database_error()
-                    .map(|db_err| db_err.is_unique_violation())
-                    .unwrap_or(false)
-                {
-                    token = generate_token(token_length);
-                    continue;
-                }
-                return Err(err);
-            }
+    // Pinned pastes are never evicted to make room
+    let columns = sqlx::query("PRAGMA table_info(pastes)")
+        .fetch_all(pool)
+        .await
+        .unwrap();
+    let mut has_pinned = false;
+    for column in columns {
+        let name: String = column.get("name");
+        if name == "pinned" {
+            has_pinned = true;
         }
     }
-    Err(sqlx::Error::Protocol("token collision".into()))
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::utils::now_ts;
-    use sqlx::sqlite::SqlitePoolOptions;
-
-    async fn setup_test_db() -> SqlitePool {
-        let pool = SqlitePoolOptions::new()
-            .connect("sqlite::memory:")
+    if !has_pinned {
+        sqlx::query("ALTER TABLE pastes ADD COLUMN pinned BOOLEAN NOT NULL DEFAULT 0")
+            .execute(pool)
             .await
             .unwrap();
-        ensure_schema(&pool).await;
-        pool
     }
 
-    #[tokio::test]
-    async fn test_insert_and_retrieve_paste() {
-        let pool = setup_test_db().await;
-        let token = insert_paste(
-            &pool,
-            "Test Title".to_string(),
-            "Test Content".to_string(),
-            now_ts() + 3600,
-            3600,
-            8,
-            "rust".to_string(),
-            None,
-            true,
-        )
+    // What `auto` resolved to when the paste was created
+    let columns = sqlx::query("PRAGMA table_info(pastes)")
+        .fetch_all(pool)
         .await
         .unwrap();
+    let mut has_detected_language = false;
+    for column in columns {
+        let name: String = column.get("name");
+        if name == "detected_language" {
+            has_detected_language = true;
+        }
+    }
+    if !has_detected_language {
+        sqlx::query("ALTER TABLE pastes ADD COLUMN detected_language TEXT")
+            .execute(pool)
+            .await
+            .unwrap();
+    }
 
-        let row: (String, String) =
-            sqlx::query_as("SELECT title, content FROM pastes WHERE token = ?")
-                .bind(&token)
-                .fetch_one(&pool)
-                .await
-                .unwrap();
+    // Expiry extensions earned by popularity
+    let columns = sqlx::query("PRAGMA table_info(pastes)")
+        .fetch_all(pool)
+        .await
+        .unwrap();
+    let mut has_auto_renewals = false;
+    for column in columns {
+        let name: String = column.get("name");
+        if name == "auto_renewals" {
+            has_auto_renewals = true;
+        }
+    }
+    if !has_auto_renewals {
+        sqlx::query("ALTER TABLE pastes ADD COLUMN auto_renewals INTEGER NOT NULL DEFAULT 0")
+            .execute(pool)
+            .await
+            .unwrap();
+    }
 
-        assert_eq!(row.0, "Test Title");
-        assert_eq!(row.1, "Test Content");
+    // Secret that opens a paste's management page
+    let columns = sqlx::query("PRAGMA table_info(pastes)")
+        .fetch_all(pool)
+        .await
+        .unwrap();
+    let mut has_manage_token = false;
+    for column in columns {
+        let name: String = column.get("name");
+        if name == "manage_token" {
+            has_manage_token = true;
+        }
     }
+    if !has_manage_token {
+        sqlx::query("ALTER TABLE pastes ADD COLUMN manage_token TEXT")
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+    sqlx::query(
+        "CREATE UNIQUE INDEX IF NOT EXISTS idx_pastes_manage_token ON pastes(manage_token)",
+    )
+    .execute(pool)
+    .await
+    .unwrap();
 
-    #[tokio::test]
-    async fn test_cleanup_expired() {
-        let pool = setup_test_db().await;
-        // Insert expired paste
-        sqlx::query("INSERT INTO pastes (token, title, content, expires_at) VALUES (?, ?, ?, ?)")
-            .bind("old")
-            .bind("Old")
-            .bind("Old content")
-            .bind(now_ts() - 3600)
-            .execute(&pool)
+    // Timer that deletes a paste a while after its first view
+    let columns = sqlx::query("PRAGMA table_info(pastes)")
+        .fetch_all(pool)
+        .await
+        .unwrap();
+    let mut has_destroy_after = false;
+    let mut has_first_viewed_at = false;
+    for column in columns {
+        let name: String = column.get("name");
+        if name == "destroy_after_first_view_secs" {
+            has_destroy_after = true;
+        }
+        if name == "first_viewed_at" {
+            has_first_viewed_at = true;
+        }
+    }
+    if !has_destroy_after {
+        sqlx::query("ALTER TABLE pastes ADD COLUMN destroy_after_first_view_secs INTEGER")
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+    if !has_first_viewed_at {
+        sqlx::query("ALTER TABLE pastes ADD COLUMN first_viewed_at INTEGER")
+            .execute(pool)
             .await
             .unwrap();
+    }
 
-        cleanup_expired(&pool).await;
+    // Time before which only the owner can reach a paste
+    let columns = sqlx::query("PRAGMA table_info(pastes)")
+        .fetch_all(pool)
+        .await
+        .unwrap();
+    let mut has_visible_after = false;
+    for column in columns {
+        let name: String = column.get("name");
+        if name == "visible_after" {
+            has_visible_after = true;
+        }
+    }
+    if !has_visible_after {
+        sqlx::query("ALTER TABLE pastes ADD COLUMN visible_after INTEGER")
+            .execute(pool)
+            .await
+            .unwrap();
+    }
 
-        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM pastes WHERE token = 'old'")
-            .fetch_one(&pool)
+    // The nickname a paste was signed with
+    let columns = sqlx::query("PRAGMA table_info(pastes)")
+        .fetch_all(pool)
+        .await
+        .unwrap();
+    let mut has_author = false;
+    for column in columns {
+        let name: String = column.get("name");
+        if name == "author" {
+            has_author = true;
+        }
+    }
+    if !has_author {
+        sqlx::query("ALTER TABLE pastes ADD COLUMN author TEXT")
+            .execute(pool)
             .await
             .unwrap();
-        assert_eq!(count, 0);
+    }
+
+    // The token of the paste a fork was made from
+    let columns = sqlx::query("PRAGMA table_info(pastes)")
+        .fetch_all(pool)
+        .await
+        .unwrap();
+    let mut has_forked_from = false;
+    for column in columns {
+        let name: String = column.get("name");
+        if name == "forked_from" {
+            has_forked_from = true;
+        }
+    }
+    if !has_forked_from {
+        sqlx::query("ALTER TABLE pastes ADD COLUMN forked_from TEXT")
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+
+    // The first file's name, and the stored length of the files after it,
+    // counted with `content` against `max_total_content_length`
+    let columns = sqlx::query("PRAGMA table_info(pastes)")
+        .fetch_all(pool)
+        .await
+        .unwrap();
+    let mut has_filename = false;
+    let mut has_files_length = false;
+    for column in columns {
+        let name: String = column.get("name");
+        if name == "filename" {
+            has_filename = true;
+        } else if name == "files_length" {
+            has_files_length = true;
+        }
+    }
+    if !has_filename {
+        sqlx::query("ALTER TABLE pastes ADD COLUMN filename TEXT")
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+    if !has_files_length {
+        sqlx::query("ALTER TABLE pastes ADD COLUMN files_length INTEGER NOT NULL DEFAULT 0")
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+
+    // Whether a JSON or YAML paste parsed on creation, and where it broke;
+    // NULL for pastes that were not checked
+    let columns = sqlx::query("PRAGMA table_info(pastes)")
+        .fetch_all(pool)
+        .await
+        .unwrap();
+    let has_syntax_valid = columns
+        .iter()
+        .any(|column| column.get::<String, _>("name") == "syntax_valid");
+    if !has_syntax_valid {
+        for column in [
+            "syntax_valid INTEGER",
+            "syntax_error TEXT",
+            "syntax_error_line INTEGER",
+        ] {
+            sqlx::query(&format!("ALTER TABLE pastes ADD COLUMN {}", column))
+                .execute(pool)
+                .await
+                .unwrap();
+        }
+    }
+
+    // Views per UTC day, shown to the owner
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS paste_views (
+            paste_id INTEGER NOT NULL REFERENCES pastes(id) ON DELETE CASCADE,
+            day TEXT NOT NULL,
+            count INTEGER NOT NULL DEFAULT 0,
+            PRIMARY KEY (paste_id, day)
+        )
+        "#,
+    )
+    .execute(pool)
+    .await
+    .unwrap();
+
+    // Append-only log of what happened to pastes, pruned after
+    // `event_retention_secs`. Tokens are kept as text so entries outlive
+    // their pastes.
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS events (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            event_type TEXT NOT NULL,
+            token TEXT NOT NULL,
+            created_at INTEGER NOT NULL DEFAULT (strftime('%s','now')),
+            client_hash TEXT
+        )
+        "#,
+    )
+    .execute(pool)
+    .await
+    .unwrap();
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_events_token ON events(token, created_at)")
+        .execute(pool)
+        .await
+        .unwrap();
+
+    // Views through /r/ rather than the paste page
+    let columns = sqlx::query("PRAGMA table_info(events)")
+        .fetch_all(pool)
+        .await
+        .unwrap();
+    if !columns
+        .iter()
+        .any(|column| column.get::<String, _>("name") == "raw")
+    {
+        sqlx::query("ALTER TABLE events ADD COLUMN raw INTEGER NOT NULL DEFAULT 0")
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+    // Size of the content a `created` event added
+    if !columns
+        .iter()
+        .any(|column| column.get::<String, _>("name") == "bytes")
+    {
+        sqlx::query("ALTER TABLE events ADD COLUMN bytes INTEGER")
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+    // Language a `created` event's paste was stored as, `auto` resolved
+    if !columns
+        .iter()
+        .any(|column| column.get::<String, _>("name") == "language")
+    {
+        sqlx::query("ALTER TABLE events ADD COLUMN language TEXT")
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+
+    // Finished days of the event log, per display-timezone offset, so the
+    // daily stats need not scan it
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS daily_stats (
+            date TEXT NOT NULL,
+            utc_offset INTEGER NOT NULL,
+            created INTEGER NOT NULL,
+            expired INTEGER NOT NULL,
+            views INTEGER NOT NULL,
+            burns INTEGER NOT NULL,
+            evictions INTEGER NOT NULL,
+            bytes_added INTEGER NOT NULL,
+            PRIMARY KEY (date, utc_offset)
+        )
+        "#,
+    )
+    .execute(pool)
+    .await
+    .unwrap();
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_events_created ON events(created_at)")
+        .execute(pool)
+        .await
+        .unwrap();
+    // Creations per language on the days in `daily_stats`, stored with them
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS daily_languages (
+            date TEXT NOT NULL,
+            utc_offset INTEGER NOT NULL,
+            language TEXT NOT NULL,
+            created INTEGER NOT NULL,
+            PRIMARY KEY (date, utc_offset, language)
+        )
+        "#,
+    )
+    .execute(pool)
+    .await
+    .unwrap();
+
+    // Blocked client addresses and ranges
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS banned_ips (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            cidr TEXT NOT NULL UNIQUE,
+            reason TEXT,
+            created_at INTEGER NOT NULL DEFAULT (strftime('%s','now')),
+            expires_at INTEGER
+        )
+        "#,
+    )
+    .execute(pool)
+    .await
+    .unwrap();
+
+    // Pastes explore may list, in creation order, for stepping from one
+    // to the next without an OFFSET scan.
+    sqlx::query(
+        r#"
+        CREATE INDEX IF NOT EXISTS idx_pastes_explore ON pastes(created_at, id)
+        WHERE is_public = 1 AND max_views IS NULL AND is_e2e = 0
+        "#,
+    )
+    .execute(pool)
+    .await
+    .unwrap();
+
+    // Reaction counts on listed pastes, by emoji
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS reactions (
+            paste_id INTEGER NOT NULL REFERENCES pastes(id) ON DELETE CASCADE,
+            emoji TEXT NOT NULL,
+            count INTEGER NOT NULL DEFAULT 0,
+            PRIMARY KEY (paste_id, emoji)
+        )
+        "#,
+    )
+    .execute(pool)
+    .await
+    .unwrap();
+
+    // Files of a multi-file paste after the first, which is the paste's
+    // own content; encrypted like it
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS paste_files (
+            paste_id INTEGER NOT NULL REFERENCES pastes(id) ON DELETE CASCADE,
+            position INTEGER NOT NULL,
+            filename TEXT NOT NULL,
+            language TEXT NOT NULL,
+            content TEXT NOT NULL,
+            nonce TEXT,
+            PRIMARY KEY (paste_id, position)
+        )
+        "#,
+    )
+    .execute(pool)
+    .await
+    .unwrap();
+
+    // Pastes the owner linked to as related, by token, so a link outlives
+    // its target and shows as a tombstone
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS paste_links (
+            paste_id INTEGER NOT NULL REFERENCES pastes(id) ON DELETE CASCADE,
+            position INTEGER NOT NULL,
+            target TEXT NOT NULL,
+            PRIMARY KEY (paste_id, position)
+        )
+        "#,
+    )
+    .execute(pool)
+    .await
+    .unwrap();
+
+    // The admin's pick for the paste of the day; at most one row.
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS featured (
+            id INTEGER PRIMARY KEY CHECK (id = 1),
+            paste_id INTEGER NOT NULL REFERENCES pastes(id) ON DELETE CASCADE,
+            set_at INTEGER NOT NULL DEFAULT (strftime('%s','now'))
+        )
+        "#,
+    )
+    .execute(pool)
+    .await
+    .unwrap();
+
+    // Values the server generates once and keeps, such as the signing key
+    // when none is configured
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS settings (
+            name TEXT PRIMARY KEY,
+            value TEXT NOT NULL
+        )
+        "#,
+    )
+    .execute(pool)
+    .await
+    .unwrap();
+
+    // Webhook deliveries waiting to be sent, written in the same
+    // transaction as the deletion they report
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS webhook_queue (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            event TEXT NOT NULL,
+            token TEXT NOT NULL,
+            created_at INTEGER NOT NULL DEFAULT (strftime('%s','now')),
+            attempts INTEGER NOT NULL DEFAULT 0,
+            next_attempt_at INTEGER NOT NULL DEFAULT (strftime('%s','now')),
+            last_error TEXT,
+            dead INTEGER NOT NULL DEFAULT 0
+        )
+        "#,
+    )
+    .execute(pool)
+    .await
+    .unwrap();
+    sqlx::query(
+        "CREATE INDEX IF NOT EXISTS idx_webhook_queue_due ON webhook_queue(dead, next_attempt_at)",
+    )
+    .execute(pool)
+    .await
+    .unwrap();
+
+    // What is left of a deleted paste, however it went, so its manage link
+    // can still read its view history until the event log is pruned
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS paste_tombstones (
+            manage_token TEXT PRIMARY KEY,
+            token TEXT NOT NULL,
+            created_at INTEGER NOT NULL,
+            deleted_at INTEGER NOT NULL
+        )
+        "#,
+    )
+    .execute(pool)
+    .await
+    .unwrap();
+    // The trigger only keeps the paste's identity; `record_events` stamps
+    // the deletion time from the app's clock in the same transaction, and
+    // until then the tombstone reads as deleted when the paste would expire
+    sqlx::query("DROP TRIGGER IF EXISTS paste_tombstone")
+        .execute(pool)
+        .await
+        .unwrap();
+    sqlx::query(
+        r#"
+        CREATE TRIGGER paste_tombstone AFTER DELETE ON pastes
+        WHEN OLD.manage_token IS NOT NULL
+        BEGIN
+            INSERT OR REPLACE INTO paste_tombstones (manage_token, token, created_at, deleted_at)
+            VALUES (OLD.manage_token, OLD.token, OLD.created_at, OLD.expires_at);
+        END
+        "#,
+    )
+    .execute(pool)
+    .await
+    .unwrap();
+
+    // Lets `largest_pastes` read the top of an index instead of sorting
+    // every paste; the expression must match its ORDER BY exactly
+    sqlx::query(
+        "CREATE INDEX IF NOT EXISTS idx_pastes_content_length ON pastes(LENGTH(content) + files_length)",
+    )
+    .execute(pool)
+    .await
+    .unwrap();
+
+    // The token lowercased, which `case_insensitive_tokens` looks pastes up
+    // by; generated, so it cannot fall out of step with `token`
+    let columns = sqlx::query("PRAGMA table_xinfo(pastes)")
+        .fetch_all(pool)
+        .await
+        .unwrap();
+    let has_token_key = columns
+        .iter()
+        .any(|column| column.get::<String, _>("name") == "token_key");
+    if !has_token_key {
+        sqlx::query(
+            "ALTER TABLE pastes ADD COLUMN token_key TEXT GENERATED ALWAYS AS (lower(token)) VIRTUAL",
+        )
+        .execute(pool)
+        .await
+        .unwrap();
+    }
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_pastes_token_key ON pastes(token_key)")
+        .execute(pool)
+        .await
+        .unwrap();
+
+    sqlx::query(&format!("PRAGMA user_version = {}", SCHEMA_VERSION))
+        .execute(pool)
+        .await
+        .unwrap();
+}
+
+/// Recorded in `PRAGMA user_version` once `ensure_schema` has run. Bump it
+/// with every table or column added above, and list the addition in
+/// `EXPECTED_COLUMNS`.
+pub const SCHEMA_VERSION: i64 = 2;
+
+/// The columns the queries in this module read or write, by table.
+const EXPECTED_COLUMNS: &[(&str, &[&str])] = &[
+    (
+        "pastes",
+        &[
+            "id",
+            "token",
+            "title",
+            "content",
+            "language",
+            "created_at",
+            "expires_at",
+            "views",
+            "max_views",
+            "is_public",
+            "original_duration",
+            "is_encrypted",
+            "nonce",
+            "is_e2e",
+            "content_hash",
+            "owner_id",
+            "flagged",
+            "pinned",
+            "detected_language",
+            "auto_renewals",
+            "manage_token",
+            "destroy_after_first_view_secs",
+            "first_viewed_at",
+            "visible_after",
+            "author",
+            "forked_from",
+            "filename",
+            "files_length",
+            "syntax_valid",
+            "syntax_error",
+            "syntax_error_line",
+            "token_key",
+        ],
+    ),
+    (
+        "share_links",
+        &[
+            "id",
+            "paste_id",
+            "share_token",
+            "label",
+            "max_views",
+            "views",
+            "created_at",
+        ],
+    ),
+    (
+        "reports",
+        &["id", "paste_id", "reporter", "reason", "created_at"],
+    ),
+    ("paste_views", &["paste_id", "day", "count"]),
+    (
+        "events",
+        &[
+            "id",
+            "event_type",
+            "token",
+            "created_at",
+            "client_hash",
+            "raw",
+            "bytes",
+            "language",
+        ],
+    ),
+    (
+        "daily_stats",
+        &[
+            "date",
+            "utc_offset",
+            "created",
+            "expired",
+            "views",
+            "burns",
+            "evictions",
+            "bytes_added",
+        ],
+    ),
+    (
+        "daily_languages",
+        &["date", "utc_offset", "language", "created"],
+    ),
+    (
+        "banned_ips",
+        &["id", "cidr", "reason", "created_at", "expires_at"],
+    ),
+    ("reactions", &["paste_id", "emoji", "count"]),
+    (
+        "paste_files",
+        &[
+            "paste_id", "position", "filename", "language", "content", "nonce",
+        ],
+    ),
+    ("paste_links", &["paste_id", "position", "target"]),
+    ("featured", &["id", "paste_id", "set_at"]),
+    ("settings", &["name", "value"]),
+    (
+        "webhook_queue",
+        &[
+            "id",
+            "event",
+            "token",
+            "created_at",
+            "attempts",
+            "next_attempt_at",
+            "last_error",
+            "dead",
+        ],
+    ),
+    (
+        "paste_tombstones",
+        &["manage_token", "token", "created_at", "deleted_at"],
+    ),
+];
+
+/// Check that the database can serve requests: its schema is no newer
+/// than this build's, every table has the columns in `EXPECTED_COLUMNS`,
+/// and a write commits. Run at startup after `ensure_schema`, so a
+/// read-only mount or a hand-edited schema stops the server with a clear
+/// message rather than failing the first request, and by `/readyz`.
+pub async fn self_check(pool: &SqlitePool) -> Result<SchemaReport, String> {
+    let version: i64 = sqlx::query_scalar("PRAGMA user_version")
+        .fetch_one(pool)
+        .await
+        .map_err(|err| format!("Cannot read the schema version: {}", err))?;
+    if version > SCHEMA_VERSION {
+        return Err(format!(
+            "The database is at schema version {}, but this build only knows up to {}",
+            version, SCHEMA_VERSION
+        ));
+    }
+    let mut tables = BTreeMap::new();
+    for (table, expected) in EXPECTED_COLUMNS {
+        let columns: Vec<String> = sqlx::query(&format!("PRAGMA table_xinfo({})", table))
+            .fetch_all(pool)
+            .await
+            .map_err(|err| format!("Cannot read the columns of {}: {}", table, err))?
+            .iter()
+            .map(|column| column.get("name"))
+            .collect();
+        if columns.is_empty() {
+            return Err(format!("Table {} is missing", table));
+        }
+        let missing: Vec<&str> = expected
+            .iter()
+            .copied()
+            .filter(|name| !columns.iter().any(|column| column == name))
+            .collect();
+        if !missing.is_empty() {
+            return Err(format!(
+                "Table {} is missing columns: {}",
+                table,
+                missing.join(", ")
+            ));
+        }
+        tables.insert(table.to_string(), columns);
+    }
+    write_sentinel(pool)
+        .await
+        .map_err(|err| format!("Cannot write to the database: {}", err))?;
+    Ok(SchemaReport { version, tables })
+}
+
+/// Insert and delete a row of `settings` in one committed transaction.
+async fn write_sentinel(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("INSERT OR REPLACE INTO settings (name, value) VALUES ('self_check', '')")
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("DELETE FROM settings WHERE name = 'self_check'")
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await
+}
+
+/// Make sure tokens compare byte for byte, whatever created the table:
+/// refuse to start on a `token` column declared with another collation,
+/// and rebuild a token index that folds case so `Ab3` and `aB3` can both
+/// exist. Case-insensitive lookups are `case_insensitive_tokens`' job.
+async fn verify_token_collation(pool: &SqlitePool) {
+    let table: String = sqlx::query_scalar(
+        "SELECT sql FROM sqlite_master WHERE type = 'table' AND name = 'pastes'",
+    )
+    .fetch_one(pool)
+    .await
+    .unwrap();
+    let declared = Regex::new(r"(?i)\btoken\s+TEXT\s+COLLATE\s+(\w+)").unwrap();
+    if let Some(collation) = declared
+        .captures(&table)
+        .map(|captures| captures[1].to_string())
+        && !collation.eq_ignore_ascii_case("BINARY")
+    {
+        panic!(
+            "pastes.token is declared COLLATE {}, but tokens must compare byte for byte; \
+             set paste.case_insensitive_tokens for forgiving lookups instead",
+            collation
+        );
+    }
+    let keys = sqlx::query("PRAGMA index_xinfo(idx_pastes_token)")
+        .fetch_all(pool)
+        .await
+        .unwrap();
+    let folds_case = keys.iter().any(|key| {
+        key.get::<i64, _>("key") == 1
+            && key.get::<Option<String>, _>("name").as_deref() == Some("token")
+            && !key
+                .get::<Option<String>, _>("coll")
+                .is_some_and(|coll| coll.eq_ignore_ascii_case("BINARY"))
+    });
+    if folds_case {
+        sqlx::query("DROP INDEX idx_pastes_token")
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+}
+
+/// The signing key to use when `server.signing_key` is not configured:
+/// generated on first boot and kept in the database, so owner cookies and
+/// share links survive restarts.
+pub async fn stored_signing_key(pool: &SqlitePool) -> Result<Vec<u8>, sqlx::Error> {
+    let fresh = hex::encode(crate::crypto::signing_key(None).unwrap_or_default());
+    sqlx::query("INSERT OR IGNORE INTO settings (name, value) VALUES ('signing_key', ?)")
+        .bind(&fresh)
+        .execute(pool)
+        .await?;
+    let stored: String =
+        sqlx::query_scalar("SELECT value FROM settings WHERE name = 'signing_key'")
+            .fetch_one(pool)
+            .await?;
+    Ok(hex::decode(stored).unwrap_or_else(|_| hex::decode(fresh).unwrap_or_default()))
+}
+
+/// Move every paste created under `old` to the owner id `new`, returning
+/// how many there were.
+pub async fn reassign_owner(pool: &SqlitePool, old: &str, new: &str) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query("UPDATE pastes SET owner_id = ? WHERE owner_id = ?")
+        .bind(new)
+        .bind(old)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+/// Compute `content_hash` for rows written before the column existed.
+/// SQLite has no SHA-256, so this runs in Rust; encrypted rows are skipped
+/// until the key that sealed them is configured.
+pub async fn backfill_content_hashes(pool: &SqlitePool, cipher: Option<&ContentCipher>) {
+    let rows = sqlx::query(
+        "SELECT id, content, is_encrypted, nonce FROM pastes WHERE content_hash IS NULL",
+    )
+    .fetch_all(pool)
+    .await
+    .unwrap();
+    for row in rows {
+        let id: i64 = row.get("id");
+        let nonce: Option<String> = row.get("nonce");
+        let Some(content) = open_content(
+            cipher,
+            row.get("content"),
+            row.get("is_encrypted"),
+            nonce.as_deref(),
+        ) else {
+            continue;
+        };
+        sqlx::query("UPDATE pastes SET content_hash = ? WHERE id = ?")
+            .bind(sha256_hex(&content))
+            .bind(id)
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+}
+
+/// First wait before retrying a write that found the database busy; each
+/// further wait doubles.
+const BUSY_RETRY_FIRST_DELAY: Duration = Duration::from_millis(10);
+
+/// The most time spent waiting between attempts before giving up.
+const BUSY_RETRY_MAX_DELAY: Duration = Duration::from_millis(1500);
+
+/// Whether `err` is SQLite's `SQLITE_BUSY` or `SQLITE_LOCKED`, in any of
+/// their extended forms.
+fn is_busy(err: &sqlx::Error) -> bool {
+    err.as_database_error()
+        .and_then(|err| err.code())
+        .and_then(|code| code.parse::<i32>().ok())
+        .is_some_and(|code| matches!(code & 0xff, 5 | 6))
+}
+
+/// Run `op` again while it fails with a busy or locked database, backing
+/// off exponentially until `BUSY_RETRY_MAX_DELAY` has been spent. Any
+/// other error, or the last busy one, is returned as is. `op` must be safe
+/// to repeat: a failed attempt's transaction has been rolled back.
+pub async fn retry_busy<T, F, Fut>(what: &str, mut op: F) -> Result<T, sqlx::Error>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, sqlx::Error>>,
+{
+    let mut delay = BUSY_RETRY_FIRST_DELAY;
+    let mut waited = Duration::ZERO;
+    loop {
+        match op().await {
+            Err(err) if is_busy(&err) && waited + delay <= BUSY_RETRY_MAX_DELAY => {
+                eprintln!(
+                    "Database busy during {}, retrying in {} ms",
+                    what,
+                    delay.as_millis()
+                );
+                metrics::record_busy_retry();
+                tokio::time::sleep(delay).await;
+                waited += delay;
+                delay *= 2;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Delete expired pastes, including those whose self-destruct timer ran
+/// out, and bans. Returns the pastes removed. Cleanup runs again on the
+/// next request, so a database that stays busy is only logged.
+pub async fn cleanup_expired(
+    pool: &SqlitePool,
+    event_retention_secs: i64,
+    webhooks: bool,
+    now: i64,
+) -> Sweep {
+    match retry_busy("cleanup", || {
+        delete_expired(pool, event_retention_secs, webhooks, now)
+    })
+    .await
+    {
+        Ok(sweep) => {
+            if sweep.deleted > 0 {
+                println!(
+                    "Expired {} pastes: {}",
+                    sweep.deleted,
+                    sweep.tokens.join(", ")
+                );
+            }
+            sweep
+        }
+        Err(err) => {
+            eprintln!("Cleanup failed: {}", err);
+            Sweep::new("expired", None, Vec::new())
+        }
+    }
+}
+
+async fn delete_expired(
+    pool: &SqlitePool,
+    event_retention_secs: i64,
+    webhooks: bool,
+    now: i64,
+) -> Result<Sweep, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let removed: Vec<(String, i64)> = sqlx::query_as(
+        r#"
+        DELETE FROM pastes
+        WHERE expires_at <= ?1
+           OR first_viewed_at + destroy_after_first_view_secs <= ?1
+        RETURNING token, LENGTH(content) + files_length
+        "#,
+    )
+    .bind(now)
+    .fetch_all(&mut *tx)
+    .await?;
+    let sweep = Sweep::new("expired", None, removed);
+    record_events(&mut tx, EventType::Expired, &sweep.tokens, None, now).await?;
+    if webhooks {
+        enqueue_webhooks(&mut tx, "expired", &sweep.tokens, now).await?;
+    }
+    tx.commit().await?;
+    sqlx::query("DELETE FROM banned_ips WHERE expires_at <= ?")
+        .bind(now)
+        .execute(pool)
+        .await?;
+    sqlx::query("DELETE FROM events WHERE created_at <= ?")
+        .bind(now - event_retention_secs)
+        .execute(pool)
+        .await?;
+    sqlx::query("DELETE FROM paste_tombstones WHERE deleted_at <= ?")
+        .bind(now - event_retention_secs)
+        .execute(pool)
+        .await?;
+    Ok(sweep)
+}
+
+/// Queue one webhook delivery of `event` per token, for `webhooks` to send.
+pub async fn enqueue_webhooks(
+    conn: &mut SqliteConnection,
+    event: &str,
+    tokens: &[String],
+    now: i64,
+) -> Result<(), sqlx::Error> {
+    if tokens.is_empty() {
+        return Ok(());
+    }
+    sqlx::query(
+        r#"
+        INSERT INTO webhook_queue (event, token, created_at, next_attempt_at)
+        SELECT ?1, value, ?2, ?2 FROM json_each(?3)
+        "#,
+    )
+    .bind(event)
+    .bind(now)
+    .bind(serde_json::to_string(tokens).unwrap())
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Deliveries due by `now`, oldest first. A delivery waits while an older
+/// one for the same token is still pending, so each paste's events arrive
+/// in order.
+pub async fn due_webhooks(
+    pool: &SqlitePool,
+    now: i64,
+    limit: i64,
+) -> Result<Vec<WebhookDelivery>, sqlx::Error> {
+    sqlx::query_as(
+        r#"
+        SELECT id, event, token, created_at, attempts, next_attempt_at, last_error, dead
+        FROM webhook_queue q
+        WHERE dead = 0 AND next_attempt_at <= ?
+          AND NOT EXISTS (
+              SELECT 1 FROM webhook_queue earlier
+              WHERE earlier.token = q.token AND earlier.id < q.id AND earlier.dead = 0
+          )
+        ORDER BY id
+        LIMIT ?
+        "#,
+    )
+    .bind(now)
+    .bind(limit)
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn delete_webhook(pool: &SqlitePool, id: i64) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM webhook_queue WHERE id = ?")
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Note a failed attempt: retry at `next_attempt_at`, or give up when
+/// `dead`.
+pub async fn fail_webhook(
+    pool: &SqlitePool,
+    id: i64,
+    error: &str,
+    next_attempt_at: i64,
+    dead: bool,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        UPDATE webhook_queue
+        SET attempts = attempts + 1, last_error = ?, next_attempt_at = ?, dead = ?
+        WHERE id = ?
+        "#,
+    )
+    .bind(error)
+    .bind(next_attempt_at)
+    .bind(dead)
+    .bind(id)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn list_dead_webhooks(pool: &SqlitePool) -> Result<Vec<WebhookDelivery>, sqlx::Error> {
+    sqlx::query_as(
+        r#"
+        SELECT id, event, token, created_at, attempts, next_attempt_at, last_error, dead
+        FROM webhook_queue WHERE dead = 1 ORDER BY id
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Give a dead-lettered delivery a fresh set of attempts, due now. False
+/// if there is no such dead delivery.
+pub async fn retry_webhook(pool: &SqlitePool, id: i64, now: i64) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        r#"
+        UPDATE webhook_queue
+        SET dead = 0, attempts = 0, next_attempt_at = ?
+        WHERE id = ? AND dead = 1
+        "#,
+    )
+    .bind(now)
+    .bind(id)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Drop every queued delivery, for when no webhook is configured.
+pub async fn clear_webhooks(pool: &SqlitePool) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM webhook_queue")
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+/// Log one `event_type` entry per token in a single statement, at `now`.
+/// For events that remove pastes, `now` also becomes their tombstones'
+/// deletion time.
+pub async fn record_events(
+    conn: &mut SqliteConnection,
+    event_type: EventType,
+    tokens: &[String],
+    client_hash: Option<&str>,
+    now: i64,
+) -> Result<(), sqlx::Error> {
+    if tokens.is_empty() {
+        return Ok(());
+    }
+    let tokens = serde_json::to_string(tokens).unwrap();
+    sqlx::query(
+        r#"
+        INSERT INTO events (event_type, token, client_hash, created_at)
+        SELECT ?, value, ?, ? FROM json_each(?)
+        "#,
+    )
+    .bind(event_type.name())
+    .bind(client_hash)
+    .bind(now)
+    .bind(&tokens)
+    .execute(&mut *conn)
+    .await?;
+    if matches!(
+        event_type,
+        EventType::Expired | EventType::Evicted | EventType::Burned | EventType::Deleted
+    ) {
+        sqlx::query(
+            "UPDATE paste_tombstones SET deleted_at = ? WHERE token IN (SELECT value FROM json_each(?))",
+        )
+        .bind(now)
+        .bind(&tokens)
+        .execute(conn)
+        .await?;
+    }
+    Ok(())
+}
+
+/// The latest `limit` events for `token`, newest first.
+pub async fn list_events(
+    pool: &SqlitePool,
+    token: &str,
+    limit: i64,
+) -> Result<Vec<Event>, sqlx::Error> {
+    sqlx::query_as(
+        r#"
+        SELECT event_type, token, created_at, client_hash
+        FROM events
+        WHERE token = ?
+        ORDER BY created_at DESC, id DESC
+        LIMIT ?
+        "#,
+    )
+    .bind(token)
+    .bind(limit)
+    .fetch_all(pool)
+    .await
+}
+
+/// Counts per day from the event log in `since..until`, with days counted
+/// from midnight at `offset_secs` from UTC. Days are numbered from the
+/// epoch; days without events are left out.
+pub async fn counts_by_day(
+    pool: &SqlitePool,
+    since: i64,
+    until: i64,
+    offset_secs: i64,
+) -> Result<Vec<DayCounts>, sqlx::Error> {
+    sqlx::query_as(
+        r#"
+        SELECT (created_at + ?3) / 86400 AS day,
+               SUM(event_type = 'created') AS created,
+               SUM(event_type = 'expired') AS expired,
+               SUM(event_type = 'viewed') AS views,
+               SUM(event_type = 'burned') AS burns,
+               SUM(event_type = 'evicted') AS evictions,
+               COALESCE(SUM(CASE WHEN event_type = 'created' THEN bytes END), 0) AS bytes_added
+        FROM events
+        WHERE created_at >= ?1 AND created_at < ?2
+        GROUP BY day
+        "#,
+    )
+    .bind(since)
+    .bind(until)
+    .bind(offset_secs)
+    .fetch_all(pool)
+    .await
+}
+
+/// Creations per language per day from the event log in `since..until`,
+/// days counted as in `counts_by_day`. Creations logged before the log
+/// kept the language count as `auto`.
+pub async fn languages_by_day(
+    pool: &SqlitePool,
+    since: i64,
+    until: i64,
+    offset_secs: i64,
+) -> Result<Vec<DayLanguageCount>, sqlx::Error> {
+    sqlx::query_as(
+        r#"
+        SELECT (created_at + ?3) / 86400 AS day,
+               COALESCE(language, 'auto') AS language,
+               COUNT(*) AS created
+        FROM events
+        WHERE event_type = 'created' AND created_at >= ?1 AND created_at < ?2
+        GROUP BY day, language
+        "#,
+    )
+    .bind(since)
+    .bind(until)
+    .bind(offset_secs)
+    .fetch_all(pool)
+    .await
+}
+
+/// The language counts of days in `first..=last` rolled up for
+/// `offset_secs`.
+pub async fn rolled_up_languages(
+    pool: &SqlitePool,
+    offset_secs: i64,
+    first: i64,
+    last: i64,
+) -> Result<Vec<DayLanguageCount>, sqlx::Error> {
+    sqlx::query_as(
+        r#"
+        SELECT CAST(julianday(date) - 2440587.5 AS INTEGER) AS day, language, created
+        FROM daily_languages
+        WHERE utc_offset = ?1
+          AND date BETWEEN date(?2 * 86400, 'unixepoch') AND date(?3 * 86400, 'unixepoch')
+        "#,
+    )
+    .bind(offset_secs)
+    .bind(first)
+    .bind(last)
+    .fetch_all(pool)
+    .await
+}
+
+/// The days in `first..=last` already rolled up for `offset_secs`.
+pub async fn rolled_up_days(
+    pool: &SqlitePool,
+    offset_secs: i64,
+    first: i64,
+    last: i64,
+) -> Result<Vec<DayCounts>, sqlx::Error> {
+    sqlx::query_as(
+        r#"
+        SELECT CAST(julianday(date) - 2440587.5 AS INTEGER) AS day,
+               created, expired, views, burns, evictions, bytes_added
+        FROM daily_stats
+        WHERE utc_offset = ?1
+          AND date BETWEEN date(?2 * 86400, 'unixepoch') AND date(?3 * 86400, 'unixepoch')
+        ORDER BY date
+        "#,
+    )
+    .bind(offset_secs)
+    .bind(first)
+    .bind(last)
+    .fetch_all(pool)
+    .await
+}
+
+/// Keep `days` as the counts for `offset_secs`, with `languages` as their
+/// creations per language. Days already stored are left as they are, so
+/// rolling up the same day twice changes nothing. Returns how many were
+/// new.
+pub async fn store_daily_stats(
+    pool: &SqlitePool,
+    offset_secs: i64,
+    days: &[DayCounts],
+    languages: &[DayLanguageCount],
+) -> Result<u64, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    for count in languages {
+        sqlx::query(
+            r#"
+            INSERT OR IGNORE INTO daily_languages (date, utc_offset, language, created)
+            VALUES (date(? * 86400, 'unixepoch'), ?, ?, ?)
+            "#,
+        )
+        .bind(count.day)
+        .bind(offset_secs)
+        .bind(&count.language)
+        .bind(count.created)
+        .execute(&mut *tx)
+        .await?;
+    }
+    let mut stored = 0;
+    for counts in days {
+        let result = sqlx::query(
+            r#"
+            INSERT OR IGNORE INTO daily_stats
+                (date, utc_offset, created, expired, views, burns, evictions, bytes_added)
+            VALUES (date(? * 86400, 'unixepoch'), ?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(counts.day)
+        .bind(offset_secs)
+        .bind(counts.created)
+        .bind(counts.expired)
+        .bind(counts.views)
+        .bind(counts.burns)
+        .bind(counts.evictions)
+        .bind(counts.bytes_added)
+        .execute(&mut *tx)
+        .await?;
+        stored += result.rows_affected();
+    }
+    tx.commit().await?;
+    Ok(stored)
+}
+
+/// Events of each type per UTC day, oldest first, over what the log still
+/// holds.
+pub async fn daily_events(pool: &SqlitePool) -> Result<Vec<DailyEvents>, sqlx::Error> {
+    sqlx::query_as(
+        r#"
+        SELECT date(created_at, 'unixepoch') AS day,
+               SUM(event_type = 'created') AS created,
+               SUM(event_type = 'viewed') AS viewed,
+               SUM(event_type = 'deleted') AS deleted,
+               SUM(event_type = 'expired') AS expired,
+               SUM(event_type = 'evicted') AS evicted
+        FROM events
+        GROUP BY day
+        ORDER BY day
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Whether `ip` falls in any ban that has not expired.
+pub async fn is_banned(pool: &SqlitePool, ip: IpAddr, now: i64) -> Result<bool, sqlx::Error> {
+    let bans: Vec<String> = sqlx::query_scalar(
+        r#"
+        SELECT cidr FROM banned_ips
+        WHERE expires_at IS NULL OR expires_at > ?
+        "#,
+    )
+    .bind(now)
+    .fetch_all(pool)
+    .await?;
+    Ok(bans
+        .iter()
+        .filter_map(|cidr| cidr.parse::<Cidr>().ok())
+        .any(|cidr| cidr.contains(ip)))
+}
+
+pub async fn list_bans(pool: &SqlitePool) -> Result<Vec<BannedIp>, sqlx::Error> {
+    sqlx::query_as("SELECT id, cidr, reason, created_at, expires_at FROM banned_ips ORDER BY id")
+        .fetch_all(pool)
+        .await
+}
+
+/// Ban a range, replacing any existing ban on the same range.
+pub async fn insert_ban(
+    pool: &SqlitePool,
+    cidr: &Cidr,
+    reason: Option<&str>,
+    expires_at: Option<i64>,
+) -> Result<BannedIp, sqlx::Error> {
+    sqlx::query_as(
+        r#"
+        INSERT OR REPLACE INTO banned_ips (cidr, reason, expires_at)
+        VALUES (?, ?, ?)
+        RETURNING id, cidr, reason, created_at, expires_at
+        "#,
+    )
+    .bind(cidr.to_string())
+    .bind(reason)
+    .bind(expires_at)
+    .fetch_one(pool)
+    .await
+}
+
+/// Lift a ban. Returns whether it existed.
+pub async fn delete_ban(pool: &SqlitePool, id: i64) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM banned_ips WHERE id = ?")
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// `ORDER BY` terms putting the first pastes to evict under `policy` first.
+fn eviction_order(policy: EvictionPolicy) -> &'static str {
+    match policy {
+        EvictionPolicy::SoonestExpiring => "expires_at ASC, id ASC",
+        EvictionPolicy::OldestCreated => "created_at ASC, id ASC",
+        EvictionPolicy::LargestFirst => "LENGTH(content) + files_length DESC, id ASC",
+        EvictionPolicy::LeastViewed => "views ASC, id ASC",
+    }
+}
+
+fn log_evictions(policy: EvictionPolicy, sweep: &Sweep) {
+    if sweep.deleted > 0 {
+        println!(
+            "Evicted {} pastes ({}, {}): {}",
+            sweep.deleted,
+            sweep.reason,
+            policy.name(),
+            sweep.tokens.join(", ")
+        );
+    }
+}
+
+/// Pastes and their length per language, largest share first.
+pub async fn usage_by_language(pool: &SqlitePool) -> Result<Vec<LanguageUsage>, sqlx::Error> {
+    sqlx::query_as(
+        r#"
+        SELECT CASE WHEN language = 'auto' THEN COALESCE(detected_language, 'auto')
+                    ELSE COALESCE(language, 'plaintext') END AS language,
+               COUNT(*) AS pastes,
+               SUM(LENGTH(content) + files_length) AS content_length
+        FROM pastes
+        GROUP BY 1
+        ORDER BY content_length DESC, language
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Pastes and their length, public first, then private.
+pub async fn usage_by_visibility(
+    pool: &SqlitePool,
+) -> Result<(UsageTotals, UsageTotals), sqlx::Error> {
+    let rows: Vec<(bool, i64, i64)> = sqlx::query_as(
+        r#"
+        SELECT is_public, COUNT(*), SUM(LENGTH(content) + files_length)
+        FROM pastes
+        GROUP BY is_public
+        "#,
+    )
+    .fetch_all(pool)
+    .await?;
+    let totals = |public: bool| {
+        rows.iter()
+            .find(|(is_public, _, _)| *is_public == public)
+            .map(|&(_, pastes, content_length)| UsageTotals {
+                pastes,
+                content_length,
+            })
+            .unwrap_or_default()
+    };
+    Ok((totals(true), totals(false)))
+}
+
+/// The `limit` longest pastes, longest first, read off
+/// `idx_pastes_content_length`.
+pub async fn largest_pastes(
+    pool: &SqlitePool,
+    limit: i64,
+) -> Result<Vec<LargestPaste>, sqlx::Error> {
+    sqlx::query_as(LARGEST_PASTES)
+        .bind(limit)
+        .fetch_all(pool)
+        .await
+}
+
+const LARGEST_PASTES: &str = r#"
+    SELECT token, title, LENGTH(content) + files_length AS content_length, expires_at
+    FROM pastes
+    ORDER BY LENGTH(content) + files_length DESC
+    LIMIT ?
+"#;
+
+/// Sizes of the database at `db_path` and its `-wal` file.
+pub fn file_sizes(db_path: &str) -> DbFileSizes {
+    let db = resolve_path(db_path);
+    let mut wal = db.clone().into_os_string();
+    wal.push("-wal");
+    let size = |path: &std::path::Path| std::fs::metadata(path).ok().map(|meta| meta.len());
+    DbFileSizes {
+        db: size(&db),
+        wal: size(std::path::Path::new(&wal)),
+    }
+}
+
+/// Evict unpinned pastes in `policy` order until at most `max - reserve`
+/// remain. Returns the pastes evicted.
+pub async fn enforce_size_limit(
+    pool: &SqlitePool,
+    max: i64,
+    reserve: i64,
+    policy: EvictionPolicy,
+    now: i64,
+) -> Sweep {
+    let evicted = retry_busy("eviction", || async {
+        let mut tx = pool.begin().await?;
+        let sweep = evict_over_count(&mut tx, max, reserve, policy, now).await?;
+        tx.commit().await?;
+        Ok(sweep)
+    })
+    .await
+    .unwrap();
+    log_evictions(policy, &evicted);
+    evicted
+}
+
+/// `enforce_size_limit` within an open transaction.
+async fn evict_over_count(
+    conn: &mut SqliteConnection,
+    max: i64,
+    reserve: i64,
+    policy: EvictionPolicy,
+    now: i64,
+) -> Result<Sweep, sqlx::Error> {
+    let allowed = (max - reserve).max(0);
+    let (count, pinned): (i64, i64) =
+        sqlx::query_as("SELECT COUNT(*), COALESCE(SUM(pinned), 0) FROM pastes")
+            .fetch_one(&mut *conn)
+            .await?;
+    if pinned > max {
+        eprintln!(
+            "WARNING: {} pinned pastes exceed max_pastes ({}); unpin some to make room",
+            pinned, max
+        );
+    }
+    if count <= allowed {
+        return Ok(Sweep::new("max_pastes", Some(policy), Vec::new()));
+    }
+    let removed: Vec<(String, i64)> = sqlx::query_as(&format!(
+        r#"
+        DELETE FROM pastes
+        WHERE id IN (
+            SELECT id FROM pastes
+            WHERE pinned = 0
+            ORDER BY {}
+            LIMIT ?
+        )
+        RETURNING token, LENGTH(content) + files_length
+        "#,
+        eviction_order(policy)
+    ))
+    .bind(count - allowed)
+    .fetch_all(&mut *conn)
+    .await?;
+    let sweep = Sweep::new("max_pastes", Some(policy), removed);
+    record_events(conn, EventType::Evicted, &sweep.tokens, None, now).await?;
+    Ok(sweep)
+}
+
+/// Up to `limit` alive pastes with `id > after_id`, in id order, exactly as
+/// stored, each with its further files.
+pub async fn export_page(
+    pool: &SqlitePool,
+    after_id: i64,
+    limit: i64,
+    now: i64,
+) -> Result<Vec<BackupPaste>, sqlx::Error> {
+    let mut pastes: Vec<BackupPaste> = sqlx::query_as(
+        r#"
+        SELECT id, token, title, content,
+               CASE WHEN is_encrypted = 1 THEN 'encrypted' ELSE 'plain' END AS encoding,
+               nonce, language, created_at, expires_at, original_duration, views, max_views,
+               is_public, is_e2e, flagged, owner_id, content_hash, pinned, detected_language,
+               auto_renewals, manage_token, destroy_after_first_view_secs, first_viewed_at,
+               visible_after, author, forked_from, filename, syntax_valid, syntax_error,
+               syntax_error_line
+        FROM pastes
+        WHERE id > ? AND expires_at > ?
+        ORDER BY id
+        LIMIT ?
+        "#,
+    )
+    .bind(after_id)
+    .bind(now)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+    let Some(last) = pastes.last().map(|paste| paste.id) else {
+        return Ok(pastes);
+    };
+    let files: Vec<BackupFile> = sqlx::query_as(
+        r#"
+        SELECT paste_id, filename, language, content,
+               CASE WHEN nonce IS NOT NULL THEN 'encrypted' ELSE 'plain' END AS encoding,
+               nonce
+        FROM paste_files
+        WHERE paste_id > ? AND paste_id <= ?
+        ORDER BY paste_id, position
+        "#,
+    )
+    .bind(after_id)
+    .bind(last)
+    .fetch_all(pool)
+    .await?;
+    for file in files {
+        if let Some(paste) = pastes.iter_mut().find(|paste| paste.id == file.paste_id) {
+            paste.files.push(file);
+        }
+    }
+    Ok(pastes)
+}
+
+/// Insert a paste from a backup as-is with its further files, keeping its
+/// token and timestamps. `content` and the files' must already be in stored
+/// form (`encoding` says which). On a token conflict the existing paste is
+/// replaced when `overwrite` is set and kept otherwise; returns whether the
+/// row was written.
+pub async fn restore_paste(
+    pool: &SqlitePool,
+    paste: &BackupPaste,
+    overwrite: bool,
+) -> Result<bool, sqlx::Error> {
+    let verb = if overwrite {
+        "INSERT OR REPLACE"
+    } else {
+        "INSERT OR IGNORE"
+    };
+    let files_length: i64 = paste
+        .files
+        .iter()
+        .map(|file| file.content.chars().count() as i64)
+        .sum();
+    let mut tx = pool.begin().await?;
+    if overwrite {
+        // The files of the paste being replaced go with it.
+        sqlx::query(
+            "DELETE FROM paste_files WHERE paste_id = (SELECT id FROM pastes WHERE token = ?)",
+        )
+        .bind(&paste.token)
+        .execute(&mut *tx)
+        .await?;
+    }
+    let result = sqlx::query(&format!(
+        r#"
+        {} INTO pastes (token, title, content, is_encrypted, nonce, language, created_at, expires_at, original_duration, views, max_views, is_public, is_e2e, flagged, owner_id, content_hash, pinned, detected_language, auto_renewals, manage_token, destroy_after_first_view_secs, first_viewed_at, visible_after, author, forked_from, filename, files_length, syntax_valid, syntax_error, syntax_error_line)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+        "#,
+        verb
+    ))
+    .bind(&paste.token)
+    .bind(&paste.title)
+    .bind(&paste.content)
+    .bind(paste.encoding == "encrypted")
+    .bind(&paste.nonce)
+    .bind(&paste.language)
+    .bind(paste.created_at)
+    .bind(paste.expires_at)
+    .bind(paste.original_duration)
+    .bind(paste.views)
+    .bind(paste.max_views)
+    .bind(paste.is_public)
+    .bind(paste.is_e2e)
+    .bind(paste.flagged)
+    .bind(&paste.owner_id)
+    .bind(&paste.content_hash)
+    .bind(paste.pinned)
+    .bind(&paste.detected_language)
+    .bind(paste.auto_renewals)
+    .bind(&paste.manage_token)
+    .bind(paste.destroy_after_first_view_secs)
+    .bind(paste.first_viewed_at)
+    .bind(paste.visible_after)
+    .bind(&paste.author)
+    .bind(&paste.forked_from)
+    .bind(&paste.filename)
+    .bind(files_length)
+    .bind(paste.syntax_valid)
+    .bind(&paste.syntax_error)
+    .bind(paste.syntax_error_line)
+    .execute(&mut *tx)
+    .await?;
+    if result.rows_affected() == 0 {
+        return Ok(false);
+    }
+    let paste_id = result.last_insert_rowid();
+    for (position, file) in paste.files.iter().enumerate() {
+        sqlx::query(
+            r#"
+            INSERT INTO paste_files (paste_id, position, filename, language, content, nonce)
+            VALUES (?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(paste_id)
+        .bind(position as i64 + 1)
+        .bind(&file.filename)
+        .bind(&file.language)
+        .bind(&file.content)
+        .bind(&file.nonce)
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(true)
+}
+
+/// Stored length of a paste's content and further files, as counted against
+/// `max_total_content_length`.
+pub async fn content_length(pool: &SqlitePool, token: &str) -> Result<Option<i64>, sqlx::Error> {
+    sqlx::query_scalar("SELECT LENGTH(content) + files_length FROM pastes WHERE token = ?")
+        .bind(token)
+        .fetch_optional(pool)
+        .await
+}
+
+/// Counts describing what is stored right now.
+pub async fn stats(pool: &SqlitePool) -> Result<Stats, sqlx::Error> {
+    sqlx::query_as(
+        r#"
+        SELECT
+            (SELECT COUNT(*) FROM pastes) AS pastes,
+            (SELECT COUNT(*) FROM pastes WHERE is_public = 1) AS public,
+            (SELECT COUNT(*) FROM pastes WHERE is_e2e = 1) AS encrypted,
+            (SELECT COUNT(*) FROM pastes WHERE max_views IS NOT NULL) AS burn_after_reading,
+            (SELECT COALESCE(MAX(id), 0) FROM pastes) AS created,
+            (SELECT COALESCE(SUM(LENGTH(content) + files_length), 0) FROM pastes) AS content_length,
+            (SELECT COUNT(DISTINCT paste_id) FROM reports) AS reported,
+            (SELECT COUNT(*) FROM banned_ips) AS bans
+        "#,
+    )
+    .fetch_one(pool)
+    .await
+}
+
+/// Pastes stored and the characters they hold, as counted against
+/// `max_pastes` and `max_total_content_length`.
+pub async fn usage(pool: &SqlitePool) -> Result<(i64, i64), sqlx::Error> {
+    sqlx::query_as("SELECT COUNT(*), COALESCE(SUM(LENGTH(content) + files_length), 0) FROM pastes")
+        .fetch_one(pool)
+        .await
+}
+
+/// Characters stored across all pastes, as counted against
+/// `max_total_content_length`.
+pub async fn total_content_length(pool: &SqlitePool) -> i64 {
+    sqlx::query_scalar("SELECT COALESCE(SUM(LENGTH(content) + files_length), 0) FROM pastes")
+        .fetch_one(pool)
+        .await
+        .unwrap_or(0)
+}
+
+/// Evict unpinned pastes in `policy` order until at most `max - reserve`
+/// characters remain. Returns the pastes evicted.
+pub async fn enforce_total_content_length(
+    pool: &SqlitePool,
+    max: i64,
+    reserve: i64,
+    policy: EvictionPolicy,
+    now: i64,
+) -> Sweep {
+    let evicted = retry_busy("eviction", || async {
+        let mut tx = pool.begin().await?;
+        let sweep = evict_over_length(&mut tx, max, reserve, policy, now).await?;
+        tx.commit().await?;
+        Ok(sweep)
+    })
+    .await
+    .unwrap();
+    log_evictions(policy, &evicted);
+    evicted
+}
+
+/// `enforce_total_content_length` within an open transaction.
+async fn evict_over_length(
+    conn: &mut SqliteConnection,
+    max: i64,
+    reserve: i64,
+    policy: EvictionPolicy,
+    now: i64,
+) -> Result<Sweep, sqlx::Error> {
+    let allowed = (max - reserve).max(0);
+    let (mut total, pinned): (i64, i64) = sqlx::query_as(
+        r#"
+        SELECT COALESCE(SUM(LENGTH(content) + files_length), 0),
+               COALESCE(SUM(CASE WHEN pinned = 1 THEN LENGTH(content) + files_length END), 0)
+        FROM pastes
+        "#,
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+    if total <= allowed {
+        return Ok(Sweep::new(
+            "max_total_content_length",
+            Some(policy),
+            Vec::new(),
+        ));
+    }
+    if pinned > max {
+        eprintln!(
+            "WARNING: pinned pastes hold {} characters, over max_total_content_length ({}); unpin some to make room",
+            pinned, max
+        );
+    }
+    let rows = sqlx::query(&format!(
+        r#"
+        SELECT id, token, LENGTH(content) + files_length AS len
+        FROM pastes
+        WHERE pinned = 0
+        ORDER BY {}
+        "#,
+        eviction_order(policy)
+    ))
+    .fetch_all(&mut *conn)
+    .await?;
+    let mut removed = Vec::new();
+    for row in rows {
+        if total <= allowed {
+            break;
+        }
+        let id: i64 = row.get("id");
+        let len: i64 = row.get("len");
+        sqlx::query("DELETE FROM pastes WHERE id = ?")
+            .bind(id)
+            .execute(&mut *conn)
+            .await?;
+        total -= len;
+        removed.push((row.get("token"), len));
+    }
+    let sweep = Sweep::new("max_total_content_length", Some(policy), removed);
+    record_events(conn, EventType::Evicted, &sweep.tokens, None, now).await?;
+    Ok(sweep)
+}
+
+/// Load an alive paste with its content decrypted, even one that is not
+/// visible yet. Does not count a view.
+pub async fn fetch_paste(
+    pool: &SqlitePool,
+    token: &str,
+    cipher: Option<&ContentCipher>,
+    now: i64,
+) -> Result<Option<Paste>, sqlx::Error> {
+    let item: Option<Paste> = sqlx::query_as(
+        r#"
+        SELECT title, content, created_at, expires_at, language, views, max_views, is_public,
+               original_duration, is_encrypted, nonce, is_e2e, owner_id, detected_language,
+               auto_renewals, destroy_after_first_view_secs, first_viewed_at, visible_after,
+               author, forked_from, filename, syntax_valid, syntax_error, syntax_error_line,
+               content_hash
+        FROM pastes
+        WHERE token = ? AND expires_at > ?
+        "#,
+    )
+    .bind(token)
+    .bind(now)
+    .fetch_optional(pool)
+    .await?;
+    let Some(mut item) = item.and_then(|mut p| {
+        p.content = open_content(cipher, p.content, p.is_encrypted, p.nonce.as_deref())?;
+        Some(p)
+    }) else {
+        return Ok(None);
+    };
+    item.files = paste_files(pool, token, cipher).await?;
+    item.related = related_links(pool, token, now).await?;
+    Ok(Some(item))
+}
+
+/// The files of a paste after the first, in order and decrypted. Files
+/// the configured key cannot open are left out.
+pub async fn paste_files(
+    pool: &SqlitePool,
+    token: &str,
+    cipher: Option<&ContentCipher>,
+) -> Result<Vec<PasteFile>, sqlx::Error> {
+    let files: Vec<PasteFile> = sqlx::query_as(
+        r#"
+        SELECT f.filename, f.language, f.content, f.nonce
+        FROM paste_files f JOIN pastes p ON p.id = f.paste_id
+        WHERE p.token = ?
+        ORDER BY f.position
+        "#,
+    )
+    .bind(token)
+    .fetch_all(pool)
+    .await?;
+    Ok(files
+        .into_iter()
+        .filter_map(|mut file| {
+            let encrypted = file.nonce.is_some();
+            file.content = open_content(cipher, file.content, encrypted, file.nonce.as_deref())?;
+            Some(file)
+        })
+        .collect())
+}
+
+/// Count one view of a paste and of today's bucket, deleting the paste
+/// once `max_views` is reached. The first view arms a self-destruct timer,
+/// pulling `expires_at` in to its deadline so every alive check honours
+/// it. With `popularity` set, a public paste that reaches the next multiple
+/// of `popularity.views` within its original lifespan has its expiry pushed
+/// back, capped at `max_expires_secs` from now; self-destructing pastes are
+/// never extended. `raw` marks the view in the event log as a fetch of
+/// `/r/` rather than of the paste page. Returns the new counters, or `None`
+/// if the paste is gone.
+pub async fn consume_view(
+    pool: &SqlitePool,
+    token: &str,
+    rules: &ViewRules<'_>,
+    client_hash: Option<&str>,
+    raw: bool,
+    now: i64,
+) -> Result<Option<ViewCount>, sqlx::Error> {
+    retry_busy("consume_view", || {
+        count_view(pool, token, rules, client_hash, raw, now)
+    })
+    .await
+}
+
+/// The settings a view is counted under.
+#[derive(Default)]
+pub struct ViewRules<'a> {
+    /// Extends popular public pastes; see `[paste.popularity]`.
+    pub popularity: Option<&'a PopularityConfig>,
+    /// How far ahead of now an extension may move the expiry.
+    pub max_expires_secs: i64,
+    /// Queue a `burned` webhook for a paste its last view deletes.
+    pub webhooks: bool,
+}
+
+async fn count_view(
+    pool: &SqlitePool,
+    token: &str,
+    rules: &ViewRules<'_>,
+    client_hash: Option<&str>,
+    raw: bool,
+    now: i64,
+) -> Result<Option<ViewCount>, sqlx::Error> {
+    let ViewRules {
+        popularity,
+        max_expires_secs,
+        webhooks,
+    } = *rules;
+    let mut tx = pool.begin().await?;
+    let row = sqlx::query(
+        r#"
+        UPDATE pastes SET views = views + 1 WHERE token = ? AND expires_at > ?
+        RETURNING id, views, max_views, created_at, expires_at, is_public, original_duration,
+                  auto_renewals, destroy_after_first_view_secs, first_viewed_at
+        "#,
+    )
+    .bind(token)
+    .bind(now)
+    .fetch_optional(&mut *tx)
+    .await?;
+    let Some(row) = row else {
+        return Ok(None);
+    };
+    let id: i64 = row.get("id");
+    let views: i64 = row.get("views");
+    let max_views: Option<i64> = row.get("max_views");
+    let mut expires_at: i64 = row.get("expires_at");
+    let mut auto_renewals: i64 = row.get("auto_renewals");
+    let destroy_after: Option<i64> = row.get("destroy_after_first_view_secs");
+    let mut first_viewed_at: Option<i64> = row.get("first_viewed_at");
+    let in_first_lifespan =
+        now < row.get::<i64, _>("created_at") + row.get::<i64, _>("original_duration");
+    let is_public: bool = row.get("is_public");
+    sqlx::query(
+        r#"
+        INSERT INTO paste_views (paste_id, day, count) VALUES (?, date(?, 'unixepoch'), 1)
+        ON CONFLICT (paste_id, day) DO UPDATE SET count = count + 1
+        "#,
+    )
+    .bind(id)
+    .bind(now)
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query(
+        "INSERT INTO events (event_type, token, client_hash, raw, created_at) VALUES (?, ?, ?, ?, ?)",
+    )
+    .bind(EventType::Viewed.name())
+    .bind(token)
+    .bind(client_hash)
+    .bind(raw)
+    .bind(now)
+    .execute(&mut *tx)
+    .await?;
+    if let Some(max) = max_views
+        && max > 0
+        && views >= max
+    {
+        sqlx::query("DELETE FROM pastes WHERE id = ?")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        record_events(&mut tx, EventType::Burned, &[token.to_string()], None, now).await?;
+        if webhooks {
+            enqueue_webhooks(&mut tx, "burned", &[token.to_string()], now).await?;
+        }
+    } else if let Some(destroy_after) = destroy_after {
+        if first_viewed_at.is_none() {
+            first_viewed_at = Some(now);
+            expires_at = expires_at.min(now + destroy_after);
+            sqlx::query("UPDATE pastes SET first_viewed_at = ?, expires_at = ? WHERE id = ?")
+                .bind(now)
+                .bind(expires_at)
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+        }
+    } else if let Some(popularity) = popularity
+        && is_public
+        && max_views.is_none()
+        && auto_renewals < popularity.max_extensions
+        && views >= popularity.views * (auto_renewals + 1)
+        && in_first_lifespan
+    {
+        let extended = (expires_at + popularity.bonus_secs).min(now + max_expires_secs);
+        if extended > expires_at {
+            expires_at = extended;
+            auto_renewals += 1;
+            sqlx::query("UPDATE pastes SET expires_at = ?, auto_renewals = ? WHERE id = ?")
+                .bind(expires_at)
+                .bind(auto_renewals)
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+        }
+    }
+    tx.commit().await?;
+    Ok(Some(ViewCount {
+        views,
+        expires_at,
+        auto_renewals,
+        first_viewed_at,
+    }))
+}
+
+/// Write the views the paste cache counted for `token` in one
+/// transaction: the total, the daily buckets and an event each, then any
+/// popularity extensions they earned, as `consume_view` would have one by
+/// one. Only pastes without a view limit or self-destruct timer are
+/// batched, so nothing burns here. Returns false if the paste is gone.
+pub async fn record_views(
+    pool: &SqlitePool,
+    token: &str,
+    views: &[PendingView],
+    popularity: Option<&PopularityConfig>,
+    max_expires_secs: i64,
+) -> Result<bool, sqlx::Error> {
+    retry_busy("record_views", || {
+        write_views(pool, token, views, popularity, max_expires_secs)
+    })
+    .await
+}
+
+async fn write_views(
+    pool: &SqlitePool,
+    token: &str,
+    views: &[PendingView],
+    popularity: Option<&PopularityConfig>,
+    max_expires_secs: i64,
+) -> Result<bool, sqlx::Error> {
+    let Some(last) = views.last() else {
+        return Ok(true);
+    };
+    let mut tx = pool.begin().await?;
+    let row = sqlx::query(
+        r#"
+        UPDATE pastes SET views = views + ? WHERE token = ?
+        RETURNING id, views, max_views, created_at, expires_at, is_public, original_duration,
+                  auto_renewals
+        "#,
+    )
+    .bind(views.len() as i64)
+    .bind(token)
+    .fetch_optional(&mut *tx)
+    .await?;
+    let Some(row) = row else {
+        return Ok(false);
+    };
+    let id: i64 = row.get("id");
+    for view in views {
+        sqlx::query(
+            r#"
+            INSERT INTO paste_views (paste_id, day, count) VALUES (?, date(?, 'unixepoch'), 1)
+            ON CONFLICT (paste_id, day) DO UPDATE SET count = count + 1
+            "#,
+        )
+        .bind(id)
+        .bind(view.at)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query(
+            "INSERT INTO events (event_type, token, client_hash, raw, created_at) VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind(EventType::Viewed.name())
+        .bind(token)
+        .bind(&view.client_hash)
+        .bind(view.raw)
+        .bind(view.at)
+        .execute(&mut *tx)
+        .await?;
+    }
+    let total: i64 = row.get("views");
+    let was_renewed: i64 = row.get("auto_renewals");
+    let mut expires_at: i64 = row.get("expires_at");
+    let mut auto_renewals = was_renewed;
+    let in_first_lifespan =
+        last.at < row.get::<i64, _>("created_at") + row.get::<i64, _>("original_duration");
+    if let Some(popularity) = popularity
+        && row.get::<bool, _>("is_public")
+        && row.get::<Option<i64>, _>("max_views").is_none()
+        && in_first_lifespan
+    {
+        // Each multiple of `popularity.views` passed earns one extension.
+        while auto_renewals < popularity.max_extensions
+            && total >= popularity.views * (auto_renewals + 1)
+        {
+            let extended = (expires_at + popularity.bonus_secs).min(last.at + max_expires_secs);
+            if extended <= expires_at {
+                break;
+            }
+            expires_at = extended;
+            auto_renewals += 1;
+        }
+    }
+    if auto_renewals > was_renewed {
+        sqlx::query("UPDATE pastes SET expires_at = ?, auto_renewals = ? WHERE id = ?")
+            .bind(expires_at)
+            .bind(auto_renewals)
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+    }
+    tx.commit().await?;
+    Ok(true)
+}
+
+/// Views of a paste per UTC day, oldest first. Days without views are
+/// left out.
+pub async fn list_daily_views(
+    pool: &SqlitePool,
+    token: &str,
+) -> Result<Vec<DailyViews>, sqlx::Error> {
+    sqlx::query_as(
+        r#"
+        SELECT v.day, v.count
+        FROM paste_views v
+        JOIN pastes p ON p.id = v.paste_id
+        WHERE p.token = ?
+        ORDER BY v.day
+        "#,
+    )
+    .bind(token)
+    .fetch_all(pool)
+    .await
+}
+
+/// Record a report against an alive paste, once per reporter. When the
+/// distinct report count reaches `threshold` a public paste is unpublished
+/// and flagged for review. Returns the report count and whether this
+/// report was the one that reached `threshold`, or `None` if there is no
+/// such paste.
+pub async fn insert_report(
+    pool: &SqlitePool,
+    token: &str,
+    reporter: &str,
+    reason: Option<&str>,
+    threshold: i64,
+    now: i64,
+) -> Result<Option<(i64, bool)>, sqlx::Error> {
+    let paste_id: Option<i64> =
+        sqlx::query_scalar("SELECT id FROM pastes WHERE token = ? AND expires_at > ?")
+            .bind(token)
+            .bind(now)
+            .fetch_optional(pool)
+            .await?;
+    let Some(paste_id) = paste_id else {
+        return Ok(None);
+    };
+    let added = sqlx::query(
+        "INSERT OR IGNORE INTO reports (paste_id, reporter, reason, created_at) VALUES (?, ?, ?, ?)",
+    )
+    .bind(paste_id)
+    .bind(reporter)
+    .bind(reason)
+    .bind(now)
+    .execute(pool)
+    .await?
+    .rows_affected()
+        > 0;
+    let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM reports WHERE paste_id = ?")
+        .bind(paste_id)
+        .fetch_one(pool)
+        .await?;
+    if count >= threshold {
+        sqlx::query("UPDATE pastes SET is_public = 0, flagged = 1 WHERE id = ? AND is_public = 1")
+            .bind(paste_id)
+            .execute(pool)
+            .await?;
+    }
+    Ok(Some((count, added && count == threshold)))
+}
+
+/// Pastes with open reports, most reported first.
+pub async fn list_reported_pastes(pool: &SqlitePool) -> Result<Vec<ReportedPaste>, sqlx::Error> {
+    let rows: Vec<(String, String, bool, bool, Option<String>)> = sqlx::query_as(
+        r#"
+        SELECT p.token, p.title, p.flagged, p.pinned, r.reason
+        FROM reports r
+        JOIN pastes p ON p.id = r.paste_id
+        ORDER BY (SELECT COUNT(*) FROM reports c WHERE c.paste_id = p.id) DESC, p.id, r.id
+        "#,
+    )
+    .fetch_all(pool)
+    .await?;
+    let mut pastes: Vec<ReportedPaste> = Vec::new();
+    for (token, title, flagged, pinned, reason) in rows {
+        if pastes.last().is_none_or(|last| last.token != token) {
+            pastes.push(ReportedPaste {
+                token,
+                title,
+                flagged,
+                pinned,
+                reports: 0,
+                reasons: Vec::new(),
+            });
+        }
+        let last = pastes.last_mut().unwrap();
+        last.reports += 1;
+        last.reasons.extend(reason);
+    }
+    Ok(pastes)
+}
+
+/// Dismiss a paste's reports, republishing it if reports had unpublished it.
+/// Returns whether the paste exists.
+pub async fn clear_reports(pool: &SqlitePool, token: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE pastes SET is_public = (is_public OR flagged), flagged = 0 WHERE token = ?",
+    )
+    .bind(token)
+    .execute(pool)
+    .await?;
+    sqlx::query("DELETE FROM reports WHERE paste_id = (SELECT id FROM pastes WHERE token = ?)")
+        .bind(token)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Rows scanned, and deleted per transaction, by `purge_pastes`.
+const PURGE_BATCH: i64 = 500;
+
+/// Find the pastes matching `filter` and, if it is confirmed, delete them.
+/// Column filters run in SQL; content filters run here since they need
+/// the decrypted text. Each batch scanned has its matches deleted in a
+/// short transaction of its own, so a large purge neither holds the write
+/// lock for long nor keeps every match in memory. Returns the number
+/// matched and the number actually deleted, which is lower if some went
+/// in the meantime.
+pub async fn purge_pastes(
+    pool: &SqlitePool,
+    cipher: Option<&ContentCipher>,
+    filter: &PurgeFilter,
+    content_regex: Option<&Regex>,
+    now: i64,
+) -> Result<(i64, i64), sqlx::Error> {
+    let (mut matched, mut deleted) = (0, 0);
+    let mut last_id = 0;
+    loop {
+        let rows: Vec<(i64, String, bool, Option<String>)> = sqlx::query_as(
+            r#"
+            SELECT id, content, is_encrypted, nonce
+            FROM pastes
+            WHERE id > ?1
+              AND (?2 IS NULL OR created_at < ?2)
+              AND (?3 IS NULL OR created_at > ?3)
+              AND (?4 IS NULL OR language = ?4)
+              AND (?5 IS NULL OR is_public = ?5)
+            ORDER BY id
+            LIMIT ?6
+            "#,
+        )
+        .bind(last_id)
+        .bind(filter.created_before)
+        .bind(filter.created_after)
+        .bind(&filter.language)
+        .bind(filter.is_public)
+        .bind(PURGE_BATCH)
+        .fetch_all(pool)
+        .await?;
+        let Some((id, ..)) = rows.last() else {
+            break;
+        };
+        last_id = *id;
+        let mut batch = Vec::new();
+        for (id, content, is_encrypted, nonce) in rows {
+            let Some(content) = open_content(cipher, content, is_encrypted, nonce.as_deref())
+            else {
+                continue;
+            };
+            let length = content.chars().count() as i64;
+            if filter.min_length.is_some_and(|min| length < min)
+                || filter.max_length.is_some_and(|max| length > max)
+                || filter
+                    .content_contains
+                    .as_deref()
+                    .is_some_and(|needle| !content.contains(needle))
+                || content_regex.is_some_and(|regex| !regex.is_match(&content))
+            {
+                continue;
+            }
+            batch.push(id);
+        }
+        matched += batch.len() as i64;
+        if !filter.confirm || batch.is_empty() {
+            continue;
+        }
+        let mut tx = pool.begin().await?;
+        let mut tokens = Vec::new();
+        for id in batch {
+            let token: Option<String> =
+                sqlx::query_scalar("DELETE FROM pastes WHERE id = ? RETURNING token")
+                    .bind(id)
+                    .fetch_optional(&mut *tx)
+                    .await?;
+            tokens.extend(token);
+        }
+        record_events(&mut tx, EventType::Deleted, &tokens, None, now).await?;
+        tx.commit().await?;
+        deleted += tokens.len() as i64;
+    }
+    Ok((matched, deleted))
+}
+
+/// Take a paste off explore. Returns whether it exists.
+pub async fn unpublish_paste(pool: &SqlitePool, token: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("UPDATE pastes SET is_public = 0 WHERE token = ?")
+        .bind(token)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Pin or unpin a paste. Returns whether it exists.
+pub async fn set_pinned(pool: &SqlitePool, token: &str, pinned: bool) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("UPDATE pastes SET pinned = ? WHERE token = ?")
+        .bind(pinned)
+        .bind(token)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Delete a paste outright. Returns whether it existed.
+pub async fn delete_paste(pool: &SqlitePool, token: &str, now: i64) -> Result<bool, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let deleted: Vec<String> =
+        sqlx::query_scalar("DELETE FROM pastes WHERE token = ? RETURNING token")
+            .bind(token)
+            .fetch_all(&mut *tx)
+            .await?;
+    record_events(&mut tx, EventType::Deleted, &deleted, None, now).await?;
+    tx.commit().await?;
+    Ok(!deleted.is_empty())
+}
+
+/// An alive paste as its management page shows it, looked up by its
+/// manage token.
+pub async fn fetch_managed_paste(
+    pool: &SqlitePool,
+    manage_token: &str,
+    now: i64,
+) -> Result<Option<ManagedPaste>, sqlx::Error> {
+    sqlx::query_as(
+        r#"
+        SELECT token, title, created_at, expires_at, views, max_views, is_public, flagged,
+               destroy_after_first_view_secs, visible_after
+        FROM pastes
+        WHERE manage_token = ? AND expires_at > ?
+        "#,
+    )
+    .bind(manage_token)
+    .bind(now)
+    .fetch_optional(pool)
+    .await
+}
+
+/// The token, creation time and, once it is gone, deletion time of the
+/// paste `manage_token` opens, kept past deletion by `paste_tombstones`.
+pub async fn view_history_span(
+    pool: &SqlitePool,
+    manage_token: &str,
+) -> Result<Option<(String, i64, Option<i64>)>, sqlx::Error> {
+    sqlx::query_as(
+        r#"
+        SELECT token, created_at, NULL FROM pastes WHERE manage_token = ?1
+        UNION ALL
+        SELECT token, created_at, deleted_at FROM paste_tombstones WHERE manage_token = ?1
+        LIMIT 1
+        "#,
+    )
+    .bind(manage_token)
+    .fetch_optional(pool)
+    .await
+}
+
+/// Page views and raw fetches of `token` per hour in `since..until`,
+/// hours numbered from the epoch; hours without views are left out.
+pub async fn views_by_hour(
+    pool: &SqlitePool,
+    token: &str,
+    since: i64,
+    until: i64,
+) -> Result<Vec<(i64, i64, i64)>, sqlx::Error> {
+    sqlx::query_as(
+        r#"
+        SELECT created_at / 3600 AS hour, SUM(raw = 0), SUM(raw = 1)
+        FROM events
+        WHERE token = ? AND event_type = 'viewed' AND created_at >= ? AND created_at < ?
+        GROUP BY hour
+        "#,
+    )
+    .bind(token)
+    .bind(since)
+    .bind(until)
+    .fetch_all(pool)
+    .await
+}
+
+/// Delete the paste `manage_token` opens. Returns its token, if it existed.
+pub async fn delete_managed_paste(
+    pool: &SqlitePool,
+    manage_token: &str,
+    client_hash: Option<&str>,
+    now: i64,
+) -> Result<Option<String>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let deleted: Vec<String> =
+        sqlx::query_scalar("DELETE FROM pastes WHERE manage_token = ? RETURNING token")
+            .bind(manage_token)
+            .fetch_all(&mut *tx)
+            .await?;
+    record_events(&mut tx, EventType::Deleted, &deleted, client_hash, now).await?;
+    tx.commit().await?;
+    Ok(deleted.into_iter().next())
+}
+
+/// Give the paste a full original lifespan from now, unless it already
+/// has longer left. Returns the new expiry, or `None` if there is no such
+/// alive paste or it self-destructs.
+pub async fn renew_managed_paste(
+    pool: &SqlitePool,
+    manage_token: &str,
+    now: i64,
+) -> Result<Option<i64>, sqlx::Error> {
+    retry_busy("renew", || {
+        sqlx::query_scalar(
+            r#"
+            UPDATE pastes
+            SET expires_at = MAX(expires_at, ?1 + original_duration)
+            WHERE manage_token = ?2 AND expires_at > ?1
+              AND destroy_after_first_view_secs IS NULL
+            RETURNING expires_at
+            "#,
+        )
+        .bind(now)
+        .bind(manage_token)
+        .fetch_optional(pool)
+    })
+    .await
+}
+
+/// Publish or unpublish the paste. Burn-after-reading and self-destructing
+/// pastes stay private, and a paste unpublished by reports cannot be
+/// published again. Returns
+/// whether the paste changed.
+pub async fn set_managed_public(
+    pool: &SqlitePool,
+    manage_token: &str,
+    is_public: bool,
+    now: i64,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        r#"
+        UPDATE pastes SET is_public = ?1
+        WHERE manage_token = ?2 AND expires_at > ?3
+          AND (?1 = 0 OR (max_views IS NULL AND destroy_after_first_view_secs IS NULL
+                          AND flagged = 0))
+        "#,
+    )
+    .bind(is_public)
+    .bind(manage_token)
+    .bind(now)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Replace the manage token, so the old management link stops working.
+/// Returns the new token, or `None` if there is no such alive paste.
+pub async fn regenerate_manage_token(
+    pool: &SqlitePool,
+    manage_token: &str,
+    now: i64,
+) -> Result<Option<String>, sqlx::Error> {
+    let replacement = generate_token(MANAGE_TOKEN_LENGTH);
+    let result = sqlx::query(
+        r#"
+        UPDATE pastes SET manage_token = ?
+        WHERE manage_token = ? AND expires_at > ?
+        "#,
+    )
+    .bind(&replacement)
+    .bind(manage_token)
+    .bind(now)
+    .execute(pool)
+    .await?;
+    Ok((result.rows_affected() > 0).then_some(replacement))
+}
+
+/// Length of the secret in a management link.
+pub const MANAGE_TOKEN_LENGTH: usize = 32;
+
+/// Add a share link to an alive paste. Returns `None` if there is no such paste.
+pub async fn insert_share_link(
+    pool: &SqlitePool,
+    token: &str,
+    label: &str,
+    max_views: Option<i64>,
+    now: i64,
+) -> Result<Option<ShareLink>, sqlx::Error> {
+    let paste_id: Option<i64> =
+        sqlx::query_scalar("SELECT id FROM pastes WHERE token = ? AND expires_at > ?")
+            .bind(token)
+            .bind(now)
+            .fetch_optional(pool)
+            .await?;
+    let Some(paste_id) = paste_id else {
+        return Ok(None);
+    };
+    for _ in 0..5 {
+        let share_token = generate_token(12);
+        let result: Result<ShareLink, sqlx::Error> = sqlx::query_as(
+            r#"
+            INSERT INTO share_links (paste_id, share_token, label, max_views, created_at)
+            VALUES (?, ?, ?, ?, ?)
+            RETURNING share_token, label, max_views, views, created_at
+            "#,
+        )
+        .bind(paste_id)
+        .bind(&share_token)
+        .bind(label)
+        .bind(max_views)
+        .bind(now)
+        .fetch_one(pool)
+        .await;
+        match result {
+            Ok(link) => return Ok(Some(link)),
+            Err(err) => {
+                if err
+                    .as_database_error()
+                    .map(|db_err| db_err.is_unique_violation())
+                    .unwrap_or(false)
+                {
+                    continue;
+                }
+                return Err(err);
+            }
+        }
+    }
+    Err(sqlx::Error::Protocol("token collision".into()))
+}
+
+pub async fn list_share_links(
+    pool: &SqlitePool,
+    token: &str,
+) -> Result<Vec<ShareLink>, sqlx::Error> {
+    sqlx::query_as(
+        r#"
+        SELECT s.share_token, s.label, s.max_views, s.views, s.created_at
+        FROM share_links s
+        JOIN pastes p ON p.id = s.paste_id
+        WHERE p.token = ?
+        ORDER BY s.id
+        "#,
+    )
+    .bind(token)
+    .fetch_all(pool)
+    .await
+}
+
+/// Revoke a share link. Returns whether it existed.
+pub async fn delete_share_link(
+    pool: &SqlitePool,
+    token: &str,
+    share_token: &str,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        r#"
+        DELETE FROM share_links
+        WHERE share_token = ? AND paste_id = (SELECT id FROM pastes WHERE token = ?)
+        "#,
+    )
+    .bind(share_token)
+    .bind(token)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// The token of the paste a share link points to, if the link still has
+/// views left and the paste is alive. Counts nothing; see
+/// `consume_share_link`.
+pub async fn share_link_target(
+    pool: &SqlitePool,
+    share_token: &str,
+    now: i64,
+) -> Result<Option<String>, sqlx::Error> {
+    sqlx::query_scalar(
+        r#"
+        SELECT pastes.token FROM share_links
+        JOIN pastes ON pastes.id = share_links.paste_id
+        WHERE share_links.share_token = ?
+          AND (share_links.max_views IS NULL OR share_links.views < share_links.max_views)
+          AND pastes.expires_at > ?
+        "#,
+    )
+    .bind(share_token)
+    .bind(now)
+    .fetch_optional(pool)
+    .await
+}
+
+/// Count one view against a share link and return the token of the paste it
+/// points to. The check and increment are a single statement, so concurrent
+/// viewers cannot overdraw the budget. The paste's own counter is untouched.
+pub async fn consume_share_link(
+    pool: &SqlitePool,
+    share_token: &str,
+    now: i64,
+) -> Result<Option<String>, sqlx::Error> {
+    sqlx::query_scalar(
+        r#"
+        UPDATE share_links SET views = views + 1
+        WHERE share_token = ?
+          AND (max_views IS NULL OR views < max_views)
+          AND paste_id IN (SELECT id FROM pastes WHERE expires_at > ?)
+        RETURNING (SELECT token FROM pastes WHERE pastes.id = share_links.paste_id)
+        "#,
+    )
+    .bind(share_token)
+    .bind(now)
+    .fetch_optional(pool)
+    .await
+}
+
+/// The pastes explore lists just before and just after `token` in creation
+/// order, or `None` if explore does not list `token`. Both are one step
+/// along `idx_pastes_explore` from the paste's own position.
+pub async fn paste_neighbors(
+    pool: &SqlitePool,
+    token: &str,
+    now: i64,
+) -> Result<Option<Neighbors>, sqlx::Error> {
+    let position: Option<(i64, i64)> = sqlx::query_as(
+        r#"
+        SELECT created_at, id FROM pastes
+        WHERE token = ?1 AND is_public = 1 AND max_views IS NULL AND is_e2e = 0
+          AND expires_at > ?2
+          AND (visible_after IS NULL OR visible_after <= ?2)
+        "#,
+    )
+    .bind(token)
+    .bind(now)
+    .fetch_optional(pool)
+    .await?;
+    let Some((created_at, id)) = position else {
+        return Ok(None);
+    };
+    let step = |sql: &'static str| {
+        sqlx::query_as::<_, Neighbor>(sql)
+            .bind(created_at)
+            .bind(id)
+            .bind(now)
+            .fetch_optional(pool)
+    };
+    let prev = step(
+        r#"
+        SELECT token, title FROM pastes
+        WHERE is_public = 1 AND max_views IS NULL AND is_e2e = 0
+          AND (created_at, id) < (?1, ?2)
+          AND expires_at > ?3
+          AND (visible_after IS NULL OR visible_after <= ?3)
+        ORDER BY created_at DESC, id DESC
+        LIMIT 1
+        "#,
+    )
+    .await?;
+    let next = step(
+        r#"
+        SELECT token, title FROM pastes
+        WHERE is_public = 1 AND max_views IS NULL AND is_e2e = 0
+          AND (created_at, id) > (?1, ?2)
+          AND expires_at > ?3
+          AND (visible_after IS NULL OR visible_after <= ?3)
+        ORDER BY created_at, id
+        LIMIT 1
+        "#,
+    )
+    .await?;
+    Ok(Some(Neighbors { prev, next }))
+}
+
+/// Look up the public metadata of an alive, visible paste. Never touches
+/// `views`.
+pub async fn fetch_paste_meta(
+    pool: &SqlitePool,
+    token: &str,
+    now: i64,
+) -> Result<Option<PasteMeta>, sqlx::Error> {
+    query_paste_meta(pool, token, now, false).await
+}
+
+/// `fetch_paste_meta`, but also for a paste still waiting for its
+/// `visible_after`, as `fetch_paste` loads it for the creator's preview.
+pub async fn fetch_scheduled_paste_meta(
+    pool: &SqlitePool,
+    token: &str,
+    now: i64,
+) -> Result<Option<PasteMeta>, sqlx::Error> {
+    query_paste_meta(pool, token, now, true).await
+}
+
+async fn query_paste_meta(
+    pool: &SqlitePool,
+    token: &str,
+    now: i64,
+    scheduled: bool,
+) -> Result<Option<PasteMeta>, sqlx::Error> {
+    sqlx::query_as(
+        r#"
+        SELECT title, language, created_at, expires_at, views, max_views, is_public, is_e2e,
+               CASE WHEN is_encrypted = 1
+                    THEN LENGTH(content) / 4 * 3 - (substr(content, -1) = '=')
+                         - (substr(content, -2) = '==') - 16
+                    ELSE LENGTH(CAST(content AS BLOB))
+               END AS content_length,
+               content_hash, original_duration, destroy_after_first_view_secs,
+               first_viewed_at + destroy_after_first_view_secs AS destroys_at,
+               CASE WHEN is_public = 1 THEN author END AS author, detected_language, filename,
+               owner_id, visible_after
+        FROM pastes
+        WHERE token = ?1 AND expires_at > ?2
+          AND (?3 OR visible_after IS NULL OR visible_after <= ?2)
+        "#,
+    )
+    .bind(token)
+    .bind(now)
+    .bind(scheduled)
+    .fetch_optional(pool)
+    .await
+}
+
+/// List alive, visible public pastes, newest first, with content cut to `preview_chars`
+/// characters (SQLite `substr` counts characters, not bytes, for TEXT).
+/// `None` returns the full content. Encrypted rows are cut after decryption.
+/// With `expiring_below`, only pastes with less than that share of their
+/// lifespan left are listed, soonest to expire first.
+pub async fn list_public_pastes(
+    pool: &SqlitePool,
+    cipher: Option<&ContentCipher>,
+    preview_chars: Option<i64>,
+    filter: &PublicFilter,
+    limit: i64,
+    offset: i64,
+    now: i64,
+) -> Result<Vec<PublicPaste>, sqlx::Error> {
+    let pastes: Vec<PublicPaste> = sqlx::query_as(
+        r#"
+        SELECT token, title,
+               CASE WHEN ?1 IS NULL OR is_encrypted = 1 THEN content
+                    ELSE substr(content, 1, ?1)
+               END AS preview,
+               LENGTH(content) AS content_length,
+               (?1 IS NOT NULL AND LENGTH(content) > ?1) AS truncated,
+               created_at, expires_at, language, original_duration, is_encrypted, nonce, pinned,
+               author, destroy_after_first_view_secs IS NULL AS renewable
+        FROM pastes
+        WHERE is_public = 1 AND max_views IS NULL AND is_e2e = 0
+          AND expires_at > ?5
+          AND (visible_after IS NULL OR visible_after <= ?5)
+          AND (?4 IS NULL OR expires_at - ?5 < ?4 * original_duration)
+        ORDER BY CASE WHEN ?4 IS NULL THEN 0 ELSE expires_at END, created_at DESC
+        LIMIT ?2 OFFSET ?3
+        "#,
+    )
+    .bind(preview_chars)
+    .bind(limit)
+    .bind(offset)
+    .bind(filter.expiring_below)
+    .bind(now)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(pastes
+        .into_iter()
+        .filter_map(|paste| open_preview(paste, cipher, preview_chars))
+        .collect())
+}
+
+/// How many pastes `list_public_pastes` would page through with `filter`.
+pub async fn count_public_pastes(
+    pool: &SqlitePool,
+    filter: &PublicFilter,
+    now: i64,
+) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar(
+        r#"
+        SELECT COUNT(*) FROM pastes
+        WHERE is_public = 1 AND max_views IS NULL AND is_e2e = 0
+          AND expires_at > ?2
+          AND (visible_after IS NULL OR visible_after <= ?2)
+          AND (?1 IS NULL OR expires_at - ?2 < ?1 * original_duration)
+        "#,
+    )
+    .bind(filter.expiring_below)
+    .bind(now)
+    .fetch_one(pool)
+    .await
+}
+
+/// The latest `limit` pastes explore lists, newest first, without their
+/// content.
+pub async fn list_recent_public(
+    pool: &SqlitePool,
+    limit: i64,
+    now: i64,
+) -> Result<Vec<ListedPaste>, sqlx::Error> {
+    sqlx::query_as(
+        r#"
+        SELECT token, title, language, created_at, author FROM pastes
+        WHERE is_public = 1 AND max_views IS NULL AND is_e2e = 0
+          AND expires_at > ?1
+          AND (visible_after IS NULL OR visible_after <= ?1)
+        ORDER BY created_at DESC, id DESC
+        LIMIT ?2
+        "#,
+    )
+    .bind(now)
+    .bind(limit)
+    .fetch_all(pool)
+    .await
+}
+
+/// Token, title and expiry of the latest alive pastes `owner_id` created,
+/// newest first.
+pub async fn list_owned_recent(
+    pool: &SqlitePool,
+    owner_id: &str,
+    limit: i64,
+    now: i64,
+) -> Result<Vec<(String, String, i64)>, sqlx::Error> {
+    sqlx::query_as(
+        r#"
+        SELECT token, title, expires_at FROM pastes
+        WHERE owner_id = ? AND expires_at > ?
+        ORDER BY id DESC
+        LIMIT ?
+        "#,
+    )
+    .bind(owner_id)
+    .bind(now)
+    .bind(limit)
+    .fetch_all(pool)
+    .await
+}
+
+/// Pastes explore lists that were created in `[from, to)`, newest first,
+/// without their content.
+pub async fn list_public_between(
+    pool: &SqlitePool,
+    from: i64,
+    to: i64,
+    now: i64,
+) -> Result<Vec<ListedPaste>, sqlx::Error> {
+    sqlx::query_as(
+        r#"
+        SELECT token, title, language, created_at, author FROM pastes
+        WHERE is_public = 1 AND max_views IS NULL AND is_e2e = 0
+          AND created_at >= ?1 AND created_at < ?2
+          AND expires_at > ?3
+          AND (visible_after IS NULL OR visible_after <= ?3)
+        ORDER BY created_at DESC, id DESC
+        "#,
+    )
+    .bind(from)
+    .bind(to)
+    .bind(now)
+    .fetch_all(pool)
+    .await
+}
+
+/// When the oldest paste explore lists was created.
+pub async fn oldest_public_created_at(
+    pool: &SqlitePool,
+    now: i64,
+) -> Result<Option<i64>, sqlx::Error> {
+    sqlx::query_scalar(
+        r#"
+        SELECT MIN(created_at) FROM pastes
+        WHERE is_public = 1 AND max_views IS NULL AND is_e2e = 0
+          AND expires_at > ?1
+          AND (visible_after IS NULL OR visible_after <= ?1)
+        "#,
+    )
+    .bind(now)
+    .fetch_one(pool)
+    .await
+}
+
+/// Decrypt an encrypted row read for `list_public_pastes` and cut it to
+/// `preview_chars`. `None` if it cannot be opened.
+fn open_preview(
+    mut paste: PublicPaste,
+    cipher: Option<&ContentCipher>,
+    preview_chars: Option<i64>,
+) -> Option<PublicPaste> {
+    if !paste.is_encrypted {
+        return Some(paste);
+    }
+    let content = open_content(cipher, paste.preview, true, paste.nonce.as_deref())?;
+    paste.content_length = content.chars().count() as i64;
+    paste.truncated = preview_chars.is_some_and(|n| paste.content_length > n);
+    paste.preview = match preview_chars {
+        Some(n) => content.chars().take(n.max(0) as usize).collect(),
+        None => content,
+    };
+    Some(paste)
+}
+
+/// The paste of the day: the admin's pick while it is still listed on
+/// explore, otherwise the listed paste at `seed` modulo their number, in
+/// creation order. `None` when nothing is listed.
+pub async fn featured_paste(
+    pool: &SqlitePool,
+    cipher: Option<&ContentCipher>,
+    preview_chars: i64,
+    seed: u64,
+    now: i64,
+) -> Result<Option<PublicPaste>, sqlx::Error> {
+    let chosen: Option<PublicPaste> = sqlx::query_as(
+        r#"
+        SELECT token, title,
+               CASE WHEN is_encrypted = 1 THEN content ELSE substr(content, 1, ?1) END AS preview,
+               LENGTH(content) AS content_length, LENGTH(content) > ?1 AS truncated,
+               created_at, expires_at, language, original_duration, is_encrypted, nonce, pinned,
+               author, destroy_after_first_view_secs IS NULL AS renewable
+        FROM pastes
+        WHERE id = (SELECT paste_id FROM featured)
+          AND is_public = 1 AND max_views IS NULL AND is_e2e = 0
+          AND expires_at > ?2
+          AND (visible_after IS NULL OR visible_after <= ?2)
+        "#,
+    )
+    .bind(preview_chars)
+    .bind(now)
+    .fetch_optional(pool)
+    .await?;
+    if let Some(paste) = chosen {
+        return Ok(open_preview(paste, cipher, Some(preview_chars)));
+    }
+
+    let listed = count_public_pastes(pool, &PublicFilter::default(), now).await?;
+    if listed == 0 {
+        return Ok(None);
+    }
+    let picked: Option<PublicPaste> = sqlx::query_as(
+        r#"
+        SELECT token, title,
+               CASE WHEN is_encrypted = 1 THEN content ELSE substr(content, 1, ?1) END AS preview,
+               LENGTH(content) AS content_length, LENGTH(content) > ?1 AS truncated,
+               created_at, expires_at, language, original_duration, is_encrypted, nonce, pinned,
+               author, destroy_after_first_view_secs IS NULL AS renewable
+        FROM pastes
+        WHERE is_public = 1 AND max_views IS NULL AND is_e2e = 0
+          AND expires_at > ?3
+          AND (visible_after IS NULL OR visible_after <= ?3)
+        ORDER BY created_at, id
+        LIMIT 1 OFFSET ?2
+        "#,
+    )
+    .bind(preview_chars)
+    .bind((seed % listed as u64) as i64)
+    .bind(now)
+    .fetch_optional(pool)
+    .await?;
+    Ok(picked.and_then(|paste| open_preview(paste, cipher, Some(preview_chars))))
+}
+
+/// Add one `emoji` reaction to a paste explore lists, or take one back.
+/// Returns the paste's counts afterwards, or `None` if it is not listed.
+pub async fn toggle_reaction(
+    pool: &SqlitePool,
+    token: &str,
+    emoji: &str,
+    add: bool,
+    now: i64,
+) -> Result<Option<Vec<(String, i64)>>, sqlx::Error> {
+    let sql = if add {
+        r#"
+        INSERT INTO reactions (paste_id, emoji, count) VALUES (?, ?, 1)
+        ON CONFLICT (paste_id, emoji) DO UPDATE SET count = count + 1
+        "#
+    } else {
+        "UPDATE reactions SET count = MAX(count - 1, 0) WHERE paste_id = ? AND emoji = ?"
+    };
+    retry_busy("toggle_reaction", || async {
+        let mut tx = pool.begin_with("BEGIN IMMEDIATE").await?;
+        let id: Option<i64> = sqlx::query_scalar(
+            r#"
+            SELECT id FROM pastes
+            WHERE token = ?1 AND is_public = 1 AND max_views IS NULL AND is_e2e = 0
+              AND expires_at > ?2
+              AND (visible_after IS NULL OR visible_after <= ?2)
+            "#,
+        )
+        .bind(token)
+        .bind(now)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(id) = id else {
+            return Ok(None);
+        };
+        sqlx::query(sql)
+            .bind(id)
+            .bind(emoji)
+            .execute(&mut *tx)
+            .await?;
+        let counts = sqlx::query_as("SELECT emoji, count FROM reactions WHERE paste_id = ?")
+            .bind(id)
+            .fetch_all(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(Some(counts))
+    })
+    .await
+}
+
+/// Reaction counts by emoji for each of `tokens` that has any.
+pub async fn reaction_counts(
+    pool: &SqlitePool,
+    tokens: &[&str],
+) -> Result<HashMap<String, Vec<(String, i64)>>, sqlx::Error> {
+    let rows: Vec<(String, String, i64)> = sqlx::query_as(
+        r#"
+        SELECT p.token, r.emoji, r.count FROM reactions r
+        JOIN pastes p ON p.id = r.paste_id
+        WHERE p.token IN (SELECT value FROM json_each(?)) AND r.count > 0
+        "#,
+    )
+    .bind(serde_json::to_string(tokens).unwrap_or_default())
+    .fetch_all(pool)
+    .await?;
+    let mut counts: HashMap<String, Vec<(String, i64)>> = HashMap::new();
+    for (token, emoji, count) in rows {
+        counts.entry(token).or_default().push((emoji, count));
+    }
+    Ok(counts)
+}
+
+/// Make a paste the paste of the day until cleared. Returns whether it is
+/// listed on explore; others cannot be featured.
+pub async fn set_featured(pool: &SqlitePool, token: &str, now: i64) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        r#"
+        INSERT OR REPLACE INTO featured (id, paste_id)
+        SELECT 1, id FROM pastes
+        WHERE token = ?1 AND is_public = 1 AND max_views IS NULL AND is_e2e = 0
+          AND expires_at > ?2
+          AND (visible_after IS NULL OR visible_after <= ?2)
+        "#,
+    )
+    .bind(token)
+    .bind(now)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Go back to the daily pick. Returns whether a paste was chosen.
+pub async fn clear_featured(pool: &SqlitePool) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM featured").execute(pool).await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Whether an alive paste has `token`.
+pub async fn is_alive(pool: &SqlitePool, token: &str, now: i64) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM pastes WHERE token = ? AND expires_at > ?)")
+        .bind(token)
+        .bind(now)
+        .fetch_one(pool)
+        .await
+}
+
+/// Whether `token` belonged to a paste that has since expired or been
+/// deleted: its row is past `expires_at`, or a tombstone is left of it.
+/// False for a live paste and for a token never used.
+pub async fn paste_gone(pool: &SqlitePool, token: &str, now: i64) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar(
+        r#"
+        SELECT EXISTS (SELECT 1 FROM pastes WHERE token = ?1 AND expires_at <= ?2)
+            OR EXISTS (SELECT 1 FROM paste_tombstones WHERE token = ?1)
+        "#,
+    )
+    .bind(token)
+    .bind(now)
+    .fetch_one(pool)
+    .await
+}
+
+/// Whether a paste, live or not yet cleaned up, holds `token`, or with
+/// `case_insensitive` the same letters in any case.
+pub async fn token_taken(
+    pool: &SqlitePool,
+    token: &str,
+    case_insensitive: bool,
+) -> Result<bool, sqlx::Error> {
+    let sql = if case_insensitive {
+        "SELECT EXISTS (SELECT 1 FROM pastes WHERE token_key = lower(?))"
+    } else {
+        "SELECT EXISTS (SELECT 1 FROM pastes WHERE token = ?)"
+    };
+    sqlx::query_scalar(sql).bind(token).fetch_one(pool).await
+}
+
+/// The stored token with the same letters as `token` in any case, the
+/// exact match first. `None` if no paste has them.
+pub async fn token_by_key(pool: &SqlitePool, token: &str) -> Result<Option<String>, sqlx::Error> {
+    sqlx::query_scalar(
+        "SELECT token FROM pastes WHERE token_key = lower(?1) ORDER BY token = ?1 DESC LIMIT 1",
+    )
+    .bind(token)
+    .fetch_optional(pool)
+    .await
+}
+
+/// The pastes `token` links to as related, in the order given, with the
+/// titles of those still alive and visible.
+pub async fn related_links(
+    pool: &SqlitePool,
+    token: &str,
+    now: i64,
+) -> Result<Vec<RelatedLink>, sqlx::Error> {
+    sqlx::query_as(
+        r#"
+        SELECT l.target AS token, t.title AS title
+        FROM paste_links l
+        JOIN pastes p ON p.id = l.paste_id
+        LEFT JOIN pastes t ON t.token = l.target
+            AND t.expires_at > ?1
+            AND (t.visible_after IS NULL OR t.visible_after <= ?1)
+        WHERE p.token = ?2
+        ORDER BY l.position
+        "#,
+    )
+    .bind(now)
+    .bind(token)
+    .fetch_all(pool)
+    .await
+}
+
+/// Whether an alive, visible paste `token` may be linked to: `Some(false)`
+/// if so, `Some(true)` if it is burn-on-read, `None` if there is none.
+pub async fn link_target(
+    pool: &SqlitePool,
+    token: &str,
+    now: i64,
+) -> Result<Option<bool>, sqlx::Error> {
+    sqlx::query_scalar(
+        r#"
+        SELECT max_views IS NOT NULL FROM pastes
+        WHERE token = ?1 AND expires_at > ?2
+          AND (visible_after IS NULL OR visible_after <= ?2)
+        "#,
+    )
+    .bind(token)
+    .bind(now)
+    .fetch_optional(pool)
+    .await
+}
+
+/// Replace the related links of `token` with `targets`. Returns whether
+/// the paste exists.
+pub async fn set_related_links(
+    pool: &SqlitePool,
+    token: &str,
+    targets: &[String],
+) -> Result<bool, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let paste_id: Option<i64> = sqlx::query_scalar("SELECT id FROM pastes WHERE token = ?")
+        .bind(token)
+        .fetch_optional(&mut *tx)
+        .await?;
+    let Some(paste_id) = paste_id else {
+        return Ok(false);
+    };
+    sqlx::query("DELETE FROM paste_links WHERE paste_id = ?")
+        .bind(paste_id)
+        .execute(&mut *tx)
+        .await?;
+    for (position, target) in targets.iter().enumerate() {
+        sqlx::query("INSERT INTO paste_links (paste_id, position, target) VALUES (?, ?, ?)")
+            .bind(paste_id)
+            .bind(position as i64)
+            .bind(target)
+            .execute(&mut *tx)
+            .await?;
+    }
+    tx.commit().await?;
+    Ok(true)
+}
+
+/// Store a paste without looking at any limits, for test fixtures;
+/// requests go through `create_paste_checked`.
+#[cfg(test)]
+pub async fn insert_paste(
+    pool: &SqlitePool,
+    paste: NewPaste,
+    cipher: Option<&ContentCipher>,
+) -> Result<CreatedPaste, sqlx::Error> {
+    let row = StoredPaste::new(paste, cipher);
+    retry_busy("insert_paste", || async {
+        let mut tx = pool.begin().await?;
+        let created = insert_row(&mut tx, &row, TOKEN_ATTEMPTS, crate::utils::now_ts())
+            .await?
+            .ok_or_else(|| sqlx::Error::Protocol("slug taken".into()))?;
+        tx.commit().await?;
+        Ok(created)
+    })
+    .await
+}
+
+/// The latest paste the same owner created from the same address at or
+/// after `since` that `paste` would duplicate: same content, title and
+/// settings, and still there. What a repeated submission gets back
+/// instead of a copy. Pastes with further files are never matched.
+pub async fn find_repeat(
+    pool: &SqlitePool,
+    paste: &NewPaste,
+    since: i64,
+    now: i64,
+) -> Result<Option<SubmittedPaste>, sqlx::Error> {
+    let (Some(owner_id), Some(client_hash)) = (&paste.owner_id, &paste.client_hash) else {
+        return Ok(None);
+    };
+    if !paste.files.is_empty() {
+        return Ok(None);
+    }
+    sqlx::query_as(
+        r#"
+        SELECT p.token, p.manage_token, p.expires_at, p.language, p.max_views
+        FROM events e
+        JOIN pastes p ON p.token = e.token
+        WHERE e.event_type = ? AND e.client_hash = ? AND e.created_at >= ?
+            AND p.owner_id = ? AND p.content_hash = ? AND p.title = ?
+            AND p.language = ? AND p.original_duration = ? AND p.max_views IS ?
+            AND p.is_public = ? AND p.is_e2e = ? AND p.destroy_after_first_view_secs IS ?
+            AND p.visible_after IS ? AND p.author IS ? AND p.forked_from IS ?
+            AND p.filename IS ? AND p.files_length = 0 AND (? IS NULL OR p.token = ?)
+            AND p.manage_token IS NOT NULL AND p.expires_at > ?
+        ORDER BY e.created_at DESC, e.id DESC
+        LIMIT 1
+        "#,
+    )
+    .bind(EventType::Created.name())
+    .bind(client_hash)
+    .bind(since)
+    .bind(owner_id)
+    .bind(sha256_hex(&paste.content))
+    .bind(&paste.title)
+    .bind(&paste.language)
+    .bind(paste.original_duration)
+    .bind(paste.max_views)
+    .bind(paste.is_public)
+    .bind(paste.is_e2e)
+    .bind(paste.destroy_after_first_view_secs)
+    .bind(paste.visible_after)
+    .bind(&paste.author)
+    .bind(&paste.forked_from)
+    .bind(&paste.filename)
+    .bind(&paste.slug)
+    .bind(&paste.slug)
+    .bind(now)
+    .fetch_optional(pool)
+    .await
+}
+
+/// Why `create_paste_checked` stored nothing.
+#[derive(Debug)]
+pub enum CreateError {
+    /// The paste alone holds more than `max_total_content_length`
+    /// characters as stored, so no amount of eviction makes room for it.
+    TooLarge,
+    /// Another paste already has the requested slug.
+    SlugTaken,
+    Database(sqlx::Error),
+}
+
+impl From<sqlx::Error> for CreateError {
+    fn from(err: sqlx::Error) -> Self {
+        CreateError::Database(err)
+    }
+}
+
+/// Make room under `max_pastes` and `max_total_content_length` by evicting
+/// in `policy` order, then store the paste, all in one write transaction so
+/// concurrent creates cannot both squeeze in. Returns the new paste and the
+/// evictions made for it, by limit.
+pub async fn create_paste_checked(
+    pool: &SqlitePool,
+    paste: NewPaste,
+    cipher: Option<&ContentCipher>,
+    max_pastes: i64,
+    max_total_content_length: i64,
+    policy: EvictionPolicy,
+    now: i64,
+) -> Result<(CreatedPaste, [Sweep; 2]), CreateError> {
+    let attempts = token_attempts(max_pastes, paste.token_length);
+    let row = StoredPaste::new(paste, cipher);
+    // Measured as stored, ciphertext included, like the pastes it makes
+    // room among.
+    let length = row.paste.content.chars().count() as i64 + row.files_length;
+    if length > max_total_content_length {
+        return Err(CreateError::TooLarge);
+    }
+    let (created, evicted) = retry_busy("create_paste", || async {
+        // Take the write lock up front: a deferred transaction would count
+        // the pastes under a read snapshot another writer may invalidate.
+        let mut tx = pool.begin_with("BEGIN IMMEDIATE").await?;
+        let evicted = [
+            evict_over_count(&mut tx, max_pastes, 1, policy, now).await?,
+            evict_over_length(&mut tx, max_total_content_length, length, policy, now).await?,
+        ];
+        // Dropping the transaction undoes the evictions made for it.
+        let Some(created) = insert_row(&mut tx, &row, attempts, now).await? else {
+            return Ok(None);
+        };
+        tx.commit().await?;
+        Ok(Some((created, evicted)))
+    })
+    .await?
+    .ok_or(CreateError::SlugTaken)?;
+    for sweep in &evicted {
+        log_evictions(policy, sweep);
+    }
+    Ok((created, evicted))
+}
+
+/// A `NewPaste` ready for its row: `paste.content` is what gets stored,
+/// encrypted if there is a cipher, and the rest is worked out from the
+/// plain text. Further files are sealed the same way, each under its own
+/// nonce, with `auto` resolved to what was detected.
+struct StoredPaste {
+    paste: NewPaste,
+    nonce: Option<String>,
+    content_hash: String,
+    detected_language: Option<&'static str>,
+    /// The JSON or YAML parse check, if the paste got one.
+    syntax: Option<Result<(), SyntaxError>>,
+    files: Vec<(NewFile, Option<String>)>,
+    files_length: i64,
+    /// Size of the content and every file, before encryption.
+    bytes: i64,
+}
+
+impl StoredPaste {
+    fn new(mut paste: NewPaste, cipher: Option<&ContentCipher>) -> Self {
+        let content_hash = sha256_hex(&paste.content);
+        let detected_language =
+            (paste.language == "auto" && !paste.is_e2e).then(|| detect_language(&paste.content));
+        let syntax = if paste.is_e2e {
+            None
+        } else {
+            syntax::check(detected_language.unwrap_or(&paste.language), &paste.content)
+        };
+        let seal = |content: &mut String| {
+            cipher.map(|cipher| {
+                let (ciphertext, nonce) = cipher.encrypt(content);
+                *content = ciphertext;
+                nonce
+            })
+        };
+        let bytes = (paste.content.len()
+            + paste
+                .files
+                .iter()
+                .map(|file| file.content.len())
+                .sum::<usize>()) as i64;
+        let nonce = seal(&mut paste.content);
+        let files: Vec<(NewFile, Option<String>)> = std::mem::take(&mut paste.files)
+            .into_iter()
+            .map(|mut file| {
+                if file.language == "auto" {
+                    file.language = detect_language(&file.content).to_string();
+                }
+                let nonce = seal(&mut file.content);
+                (file, nonce)
+            })
+            .collect();
+        let files_length = files
+            .iter()
+            .map(|(file, _)| file.content.chars().count() as i64)
+            .sum();
+        StoredPaste {
+            paste,
+            nonce,
+            content_hash,
+            detected_language,
+            syntax,
+            files,
+            files_length,
+            bytes,
+        }
+    }
+}
+
+/// Draws of a generated token `insert_paste` makes before giving up.
+const TOKEN_ATTEMPTS: usize = 5;
+
+/// The most draws `token_attempts` allows, however crowded the tokens.
+const MAX_TOKEN_ATTEMPTS: usize = 64;
+
+/// Draws enough that a paste finds a free token of `length` all but once
+/// in a million, even with `max_pastes` pastes already holding tokens of
+/// that length; never fewer than `TOKEN_ATTEMPTS`.
+pub fn token_attempts(max_pastes: i64, length: usize) -> usize {
+    let occupancy = token_occupancy(max_pastes, length);
+    if occupancy >= 1.0 {
+        return MAX_TOKEN_ATTEMPTS;
+    }
+    if occupancy <= 0.0 {
+        return TOKEN_ATTEMPTS;
+    }
+    let needed = (1e-6f64.ln() / occupancy.ln()).ceil() as usize;
+    needed.clamp(TOKEN_ATTEMPTS, MAX_TOKEN_ATTEMPTS)
+}
+
+/// Insert `row` under its slug, or a generated token drawn up to
+/// `attempts` times until one is free, and log its creation. `None` if the
+/// slug is taken.
+async fn insert_row(
+    conn: &mut SqliteConnection,
+    row: &StoredPaste,
+    attempts: usize,
+    now: i64,
+) -> Result<Option<CreatedPaste>, sqlx::Error> {
+    let paste = &row.paste;
+    let syntax_error = row.syntax.as_ref().and_then(|check| check.as_ref().err());
+    for _ in 0..attempts {
+        let token = match &paste.slug {
+            Some(slug) => slug.clone(),
+            None => generate_token(paste.token_length),
+        };
+        // The unique index only stops exact repeats; the same letters in
+        // another case are checked here, inside the caller's transaction.
+        if paste.case_insensitive_token {
+            let folded: bool = sqlx::query_scalar(
+                "SELECT EXISTS (SELECT 1 FROM pastes WHERE token_key = lower(?))",
+            )
+            .bind(&token)
+            .fetch_one(&mut *conn)
+            .await?;
+            if folded {
+                if paste.slug.is_some() {
+                    return Ok(None);
+                }
+                metrics::record_token_collision();
+                continue;
+            }
+        }
+        let manage_token = generate_token(MANAGE_TOKEN_LENGTH);
+        let result = sqlx::query(
+            r#"
+            INSERT INTO pastes (token, title, content, expires_at, original_duration, language, max_views, is_public, is_encrypted, nonce, is_e2e, content_hash, owner_id, detected_language, manage_token, destroy_after_first_view_secs, visible_after, author, forked_from, filename, files_length, syntax_valid, syntax_error, syntax_error_line, created_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(&token)
+        .bind(&paste.title)
+        .bind(&paste.content)
+        .bind(paste.expires_at)
+        .bind(paste.original_duration)
+        .bind(&paste.language)
+        .bind(paste.max_views)
+        .bind(paste.is_public)
+        .bind(row.nonce.is_some())
+        .bind(&row.nonce)
+        .bind(paste.is_e2e)
+        .bind(&row.content_hash)
+        .bind(&paste.owner_id)
+        .bind(row.detected_language)
+        .bind(&manage_token)
+        .bind(paste.destroy_after_first_view_secs)
+        .bind(paste.visible_after)
+        .bind(&paste.author)
+        .bind(&paste.forked_from)
+        .bind(&paste.filename)
+        .bind(row.files_length)
+        .bind(row.syntax.as_ref().map(Result::is_ok))
+        .bind(syntax_error.map(|err| &err.message))
+        .bind(syntax_error.and_then(|err| err.line))
+        .bind(now)
+        .execute(&mut *conn)
+        .await;
+
+        match result {
+            Ok(result) => {
+                let paste_id = result.last_insert_rowid();
+                for (position, (file, nonce)) in row.files.iter().enumerate() {
+                    sqlx::query(
+                        r#"
+                        INSERT INTO paste_files (paste_id, position, filename, language, content, nonce)
+                        VALUES (?, ?, ?, ?, ?, ?)
+                        "#,
+                    )
+                    .bind(paste_id)
+                    .bind(position as i64 + 1)
+                    .bind(&file.filename)
+                    .bind(&file.language)
+                    .bind(&file.content)
+                    .bind(nonce)
+                    .execute(&mut *conn)
+                    .await?;
+                }
+                sqlx::query(
+                    "INSERT INTO events (event_type, token, client_hash, bytes, language, created_at) VALUES (?, ?, ?, ?, ?, ?)",
+                )
+                .bind(EventType::Created.name())
+                .bind(&token)
+                .bind(&paste.client_hash)
+                .bind(row.bytes)
+                .bind(row.detected_language.unwrap_or(&paste.language))
+                .bind(now)
+                .execute(&mut *conn)
+                .await?;
+                return Ok(Some(CreatedPaste {
+                    token,
+                    manage_token,
+                }));
+            }
+            Err(err) => {
+                if err
+                    .as_database_error()
+                    .map(|db_err| db_err.is_unique_violation())
+                    .unwrap_or(false)
+                {
+                    if paste.slug.is_some() {
+                        return Ok(None);
+                    }
+                    metrics::record_token_collision();
+                    continue;
+                }
+                return Err(err);
+            }
+        }
+    }
+    Err(sqlx::Error::Protocol("token collision".into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::PasteBuilder;
+    use crate::utils::now_ts;
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    async fn setup_test_db() -> SqlitePool {
+        let pool = SqlitePoolOptions::new()
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        ensure_schema(&pool).await;
+        pool
+    }
+
+    #[tokio::test]
+    async fn test_insert_and_retrieve_paste() {
+        let pool = setup_test_db().await;
+        let token = PasteBuilder::new()
+            .title("Test Title")
+            .content("Test Content")
+            .language("rust")
+            .public(true)
+            .insert(&pool)
+            .await
+            .token;
+
+        let row: (String, String) =
+            sqlx::query_as("SELECT title, content FROM pastes WHERE token = ?")
+                .bind(&token)
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+
+        assert_eq!(row.0, "Test Title");
+        assert_eq!(row.1, "Test Content");
+    }
+
+    #[tokio::test]
+    async fn test_fetch_paste_meta() {
+        let pool = setup_test_db().await;
+        let token = PasteBuilder::new()
+            .title("Meta")
+            .content("你好")
+            .max_views(3)
+            .insert(&pool)
+            .await
+            .token;
+
+        let meta = fetch_paste_meta(&pool, &token, now_ts())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(meta.title, "Meta");
+        assert_eq!(meta.content_length, 6);
+        assert_eq!(meta.max_views, Some(3));
+
+        // Fetching metadata must not consume a view.
+        let meta = fetch_paste_meta(&pool, &token, now_ts())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(meta.views, 0);
+
+        assert!(
+            fetch_paste_meta(&pool, "missing", now_ts())
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_list_public_pastes_truncates_by_chars() {
+        let pool = setup_test_db().await;
+        PasteBuilder::new()
+            .content("蜉蝣朝生暮死")
+            .public(true)
+            .insert(&pool)
+            .await;
+
+        let pastes = list_public_pastes(
+            &pool,
+            None,
+            Some(4),
+            &PublicFilter::default(),
+            10,
+            0,
+            now_ts(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(pastes.len(), 1);
+        assert_eq!(pastes[0].preview, "蜉蝣朝生");
+        assert_eq!(pastes[0].content_length, 6);
+        assert!(pastes[0].truncated);
+
+        let pastes =
+            list_public_pastes(&pool, None, None, &PublicFilter::default(), 10, 0, now_ts())
+                .await
+                .unwrap();
+        assert_eq!(pastes[0].preview, "蜉蝣朝生暮死");
+        assert!(!pastes[0].truncated);
+    }
+
+    #[tokio::test]
+    async fn test_list_public_pastes_expiring_soon() {
+        let pool = setup_test_db().await;
+        // Seconds left out of a 1000 second lifespan.
+        for (title, left) in [("fresh", 900), ("late", 100), ("later", 50), ("half", 500)] {
+            PasteBuilder::new()
+                .title(title)
+                .expires_in(left)
+                .lifespan(1000)
+                .public(true)
+                .insert(&pool)
+                .await;
+        }
+        let titles = |pastes: Vec<PublicPaste>| {
+            pastes
+                .into_iter()
+                .map(|paste| paste.title)
+                .collect::<Vec<_>>()
+        };
+        let filter = PublicFilter {
+            expiring_below: Some(0.25),
+        };
+        let expiring = list_public_pastes(&pool, None, None, &filter, 10, 0, now_ts())
+            .await
+            .unwrap();
+        assert_eq!(titles(expiring), ["later", "late"]);
+        assert_eq!(
+            count_public_pastes(&pool, &filter, now_ts()).await.unwrap(),
+            2
+        );
+        let all = list_public_pastes(&pool, None, None, &PublicFilter::default(), 10, 0, now_ts())
+            .await
+            .unwrap();
+        assert_eq!(all.len(), 4);
+        assert_eq!(
+            count_public_pastes(&pool, &PublicFilter::default(), now_ts())
+                .await
+                .unwrap(),
+            4
+        );
+    }
+
+    #[tokio::test]
+    async fn test_backfill_content_hashes() {
+        let pool = setup_test_db().await;
+        PasteBuilder::new()
+            .token("legacy")
+            .content("abc")
+            .insert(&pool)
+            .await;
+        // Stored before pastes had a hash.
+        sqlx::query("UPDATE pastes SET content_hash = NULL")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        backfill_content_hashes(&pool, None).await;
+
+        let meta = fetch_paste_meta(&pool, "legacy", now_ts())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(meta.content_hash.unwrap(), sha256_hex("abc"));
+    }
+
+    #[tokio::test]
+    async fn test_cleanup_expired() {
+        let pool = setup_test_db().await;
+        PasteBuilder::new()
+            .token("old")
+            .expires_in(-3600)
+            .insert(&pool)
+            .await;
+
+        cleanup_expired(&pool, 86400, false, now_ts()).await;
+
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM pastes WHERE token = 'old'")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(count, 0);
+    }
+
+    #[tokio::test]
+    async fn test_eviction_spares_pinned_pastes() {
+        let pool = setup_test_db().await;
+        let mut tokens = Vec::new();
+        for i in 0..5 {
+            let token = PasteBuilder::new()
+                .content("0123456789")
+                .expires_in(60 + i)
+                .insert(&pool)
+                .await
+                .token;
+            tokens.push(token);
+        }
+        // The soonest to expire would go first.
+        assert!(set_pinned(&pool, &tokens[0], true).await.unwrap());
+        assert!(!set_pinned(&pool, "missing", true).await.unwrap());
+
+        let policy = EvictionPolicy::SoonestExpiring;
+        assert_eq!(
+            enforce_size_limit(&pool, 3, 0, policy, now_ts())
+                .await
+                .deleted,
+            2
+        );
+        assert_eq!(
+            enforce_total_content_length(&pool, 15, 0, policy, now_ts())
+                .await
+                .deleted,
+            2
+        );
+        let left: Vec<String> = sqlx::query_scalar("SELECT token FROM pastes")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(left, vec![tokens[0].clone()]);
+
+        // Pinned content over budget on its own is kept, with a warning.
+        assert_eq!(
+            enforce_total_content_length(&pool, 5, 0, policy, now_ts())
+                .await
+                .deleted,
+            0
+        );
+        assert_eq!(
+            enforce_size_limit(&pool, 0, 0, policy, now_ts())
+                .await
+                .deleted,
+            0
+        );
+        sqlx::query("UPDATE pastes SET expires_at = ?")
+            .bind(now_ts() - 1)
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert_eq!(
+            cleanup_expired(&pool, 86400, false, now_ts()).await.deleted,
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn test_eviction_policies_pick_victims_in_order() {
+        async fn seed() -> SqlitePool {
+            let pool = setup_test_db().await;
+            let now = now_ts();
+            // token, age, time left, content, views
+            for (token, age, left, content, views) in [
+                ("a", 400, 400, "1", 0),
+                ("b", 100, 100, "22", 5),
+                ("c", 300, 300, "4444", 3),
+                ("d", 200, 200, "333", 1),
+            ] {
+                PasteBuilder::new()
+                    .token(token)
+                    .content(content)
+                    .created_at(now - age)
+                    .expires_in(left)
+                    .views(views)
+                    .insert(&pool)
+                    .await;
+            }
+            pool
+        }
+
+        for (policy, expected) in [
+            (EvictionPolicy::SoonestExpiring, ["b", "d", "c", "a"]),
+            (EvictionPolicy::OldestCreated, ["a", "c", "d", "b"]),
+            (EvictionPolicy::LargestFirst, ["c", "d", "b", "a"]),
+            (EvictionPolicy::LeastViewed, ["a", "d", "c", "b"]),
+        ] {
+            let pool = seed().await;
+            let mut victims = Vec::new();
+            for max in (0..4).rev() {
+                let before: Vec<String> = sqlx::query_scalar("SELECT token FROM pastes")
+                    .fetch_all(&pool)
+                    .await
+                    .unwrap();
+                assert_eq!(
+                    enforce_size_limit(&pool, max, 0, policy, now_ts())
+                        .await
+                        .deleted,
+                    1
+                );
+                let after: Vec<String> = sqlx::query_scalar("SELECT token FROM pastes")
+                    .fetch_all(&pool)
+                    .await
+                    .unwrap();
+                victims.extend(before.into_iter().filter(|token| !after.contains(token)));
+            }
+            assert_eq!(victims, expected, "{:?}", policy);
+        }
+
+        let pool = seed().await;
+        let evicted =
+            enforce_total_content_length(&pool, 5, 0, EvictionPolicy::LargestFirst, now_ts()).await;
+        assert_eq!(evicted.deleted, 2);
+        assert_eq!(evicted.tokens, ["c", "d"]);
+        assert_eq!(evicted.reclaimed_length, 7);
+        assert_eq!(evicted.reason, "max_total_content_length");
+        assert_eq!(total_content_length(&pool).await, 3);
+    }
+
+    #[tokio::test]
+    async fn test_share_links_cascade_with_paste() {
+        let pool = setup_test_db().await;
+        let token = PasteBuilder::new().insert(&pool).await.token;
+        let link = insert_share_link(&pool, &token, "bob", Some(2), now_ts())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(
+            insert_share_link(&pool, "missing", "bob", None, now_ts())
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        for _ in 0..2 {
+            let target = consume_share_link(&pool, &link.share_token, now_ts())
+                .await
+                .unwrap();
+            assert_eq!(target.as_deref(), Some(token.as_str()));
+        }
+        assert!(
+            consume_share_link(&pool, &link.share_token, now_ts())
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        sqlx::query("DELETE FROM pastes WHERE token = ?")
+            .bind(&token)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let remaining: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM share_links")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(remaining, 0);
+    }
+
+    #[tokio::test]
+    async fn test_daily_views_roll_up_and_cascade() {
+        let pool = setup_test_db().await;
+        let token = PasteBuilder::new().max_views(3).insert(&pool).await.token;
+        let views = |count: Option<ViewCount>| count.map(|count| count.views);
+        assert_eq!(
+            views(
+                consume_view(&pool, &token, &ViewRules::default(), None, false, now_ts())
+                    .await
+                    .unwrap()
+            ),
+            Some(1)
+        );
+        assert_eq!(
+            views(
+                consume_view(&pool, &token, &ViewRules::default(), None, false, now_ts())
+                    .await
+                    .unwrap()
+            ),
+            Some(2)
+        );
+        assert_eq!(
+            consume_view(
+                &pool,
+                "missing",
+                &ViewRules::default(),
+                None,
+                false,
+                now_ts()
+            )
+            .await
+            .unwrap(),
+            None
+        );
+
+        let today: String = sqlx::query_scalar("SELECT date('now')")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(
+            list_daily_views(&pool, &token).await.unwrap(),
+            vec![DailyViews {
+                day: today,
+                count: 2
+            }]
+        );
+
+        // The last view burns the paste and its rollup with it.
+        assert_eq!(
+            views(
+                consume_view(&pool, &token, &ViewRules::default(), None, false, now_ts())
+                    .await
+                    .unwrap()
+            ),
+            Some(3)
+        );
+        let remaining: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM paste_views")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(remaining, 0);
+    }
+
+    #[tokio::test]
+    async fn test_deletion_is_stamped_with_the_given_time() {
+        let pool = setup_test_db().await;
+        let paste = PasteBuilder::new().insert(&pool).await;
+        let deleted_at = now_ts() + 5000;
+        delete_managed_paste(&pool, &paste.manage_token, None, deleted_at)
+            .await
+            .unwrap();
+        let span = view_history_span(&pool, &paste.manage_token)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(span.2, Some(deleted_at));
+        let events = list_events(&pool, &paste.token, 1).await.unwrap();
+        assert_eq!(events[0].created_at, deleted_at);
+    }
+
+    #[tokio::test]
+    async fn test_each_change_logs_one_event() {
+        let pool = setup_test_db().await;
+        let create = |content: &str, expires_in: i64| {
+            PasteBuilder::new()
+                .content(content)
+                .expires_in(expires_in)
+                .client_hash("creator")
+                .insert(&pool)
+        };
+        let events = |token: String| {
+            let pool = pool.clone();
+            async move {
+                let events = list_events(&pool, &token, 10).await.unwrap();
+                events
+                    .into_iter()
+                    .rev()
+                    .map(|event| (event.event_type, event.client_hash))
+                    .collect::<Vec<_>>()
+            }
+        };
+        let entry = |event_type: &str, client: Option<&str>| {
+            (event_type.to_string(), client.map(str::to_string))
+        };
+        let created = || entry("created", Some("creator"));
+
+        let viewed = create("viewed", 3600).await;
+        consume_view(
+            &pool,
+            &viewed.token,
+            &ViewRules::default(),
+            Some("viewer"),
+            false,
+            now_ts(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            events(viewed.token.clone()).await,
+            [created(), entry("viewed", Some("viewer"))]
+        );
+
+        let deleted = create("deleted", 3600).await;
+        assert!(delete_paste(&pool, &deleted.token, now_ts()).await.unwrap());
+        let managed = create("managed", 3600).await;
+        delete_managed_paste(&pool, &managed.manage_token, Some("owner"), now_ts())
+            .await
+            .unwrap();
+        assert_eq!(
+            events(deleted.token).await,
+            [created(), entry("deleted", None)]
+        );
+        assert_eq!(
+            events(managed.token).await,
+            [created(), entry("deleted", Some("owner"))]
+        );
+
+        let expired = create("expired", -1).await;
+        assert_eq!(
+            cleanup_expired(&pool, 86400, false, now_ts()).await.deleted,
+            1
+        );
+        assert_eq!(
+            events(expired.token).await,
+            [created(), entry("expired", None)]
+        );
+
+        let policy = EvictionPolicy::OldestCreated;
+        sqlx::query("UPDATE pastes SET created_at = created_at - 10")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let counted = create("counted", 3600).await;
+        assert_eq!(
+            enforce_size_limit(&pool, 1, 0, policy, now_ts())
+                .await
+                .deleted,
+            1
+        );
+        assert_eq!(
+            events(viewed.token.clone()).await.last(),
+            Some(&entry("evicted", None))
+        );
+        let large = create(&"x".repeat(50), 3600).await;
+        let policy = EvictionPolicy::LargestFirst;
+        assert_eq!(
+            enforce_total_content_length(&pool, 20, 0, policy, now_ts())
+                .await
+                .deleted,
+            1
+        );
+        assert_eq!(
+            events(large.token).await,
+            [created(), entry("evicted", None)]
+        );
+
+        let filter = PurgeFilter {
+            confirm: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            purge_pastes(&pool, None, &filter, None, now_ts())
+                .await
+                .unwrap(),
+            (1, 1)
+        );
+        assert_eq!(
+            events(counted.token).await,
+            [created(), entry("deleted", None)]
+        );
+
+        let daily = daily_events(&pool).await.unwrap();
+        assert_eq!(daily.len(), 1);
+        let day = &daily[0];
+        assert_eq!(
+            (
+                day.created,
+                day.viewed,
+                day.deleted,
+                day.expired,
+                day.evicted
+            ),
+            (6, 1, 3, 1, 2)
+        );
+
+        // Entries past the retention window are pruned with the pastes.
+        sqlx::query("UPDATE events SET created_at = created_at - 100")
+            .execute(&pool)
+            .await
+            .unwrap();
+        cleanup_expired(&pool, 50, false, now_ts()).await;
+        assert!(daily_events(&pool).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_popular_pastes_are_extended() {
+        let pool = setup_test_db().await;
+        let popularity = PopularityConfig {
+            views: 2,
+            bonus_secs: 600,
+            max_extensions: 2,
+        };
+        let token = PasteBuilder::new().public(true).insert(&pool).await.token;
+        let expires_at = fetch_paste(&pool, &token, None, now_ts())
+            .await
+            .unwrap()
+            .unwrap()
+            .expires_at;
+        let view = async || {
+            consume_view(
+                &pool,
+                &token,
+                &ViewRules {
+                    popularity: Some(&popularity),
+                    max_expires_secs: 86400,
+                    webhooks: false,
+                },
+                None,
+                false,
+                now_ts(),
+            )
+            .await
+            .unwrap()
+            .unwrap()
+        };
+
+        let first = view().await;
+        assert_eq!((first.expires_at, first.auto_renewals), (expires_at, 0));
+        // The second and fourth views each earn a bonus; after that the
+        // extensions are used up.
+        let second = view().await;
+        assert_eq!(
+            (second.expires_at, second.auto_renewals),
+            (expires_at + 600, 1)
+        );
+        view().await;
+        let fourth = view().await;
+        assert_eq!(
+            (fourth.expires_at, fourth.auto_renewals),
+            (expires_at + 1200, 2)
+        );
+        for _ in 0..4 {
+            assert_eq!(view().await.expires_at, expires_at + 1200);
+        }
+        let stored = fetch_paste(&pool, &token, None, now_ts())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            (stored.expires_at, stored.auto_renewals),
+            (expires_at + 1200, 2)
+        );
+
+        // Never past the longest expiry option from now.
+        let token = PasteBuilder::new().public(true).insert(&pool).await.token;
+        consume_view(
+            &pool,
+            &token,
+            &ViewRules {
+                popularity: Some(&popularity),
+                max_expires_secs: 3900,
+                webhooks: false,
+            },
+            None,
+            false,
+            now_ts(),
+        )
+        .await
+        .unwrap();
+        let capped = consume_view(
+            &pool,
+            &token,
+            &ViewRules {
+                popularity: Some(&popularity),
+                max_expires_secs: 3900,
+                webhooks: false,
+            },
+            None,
+            false,
+            now_ts(),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert!(capped.expires_at <= now_ts() + 3900 && capped.expires_at > expires_at);
+
+        // Private and burn-after-reading pastes are left alone.
+        for paste in [
+            PasteBuilder::new(),
+            PasteBuilder::new().public(true).max_views(10),
+        ] {
+            let token = paste.insert(&pool).await.token;
+            for _ in 0..4 {
+                let count = consume_view(
+                    &pool,
+                    &token,
+                    &ViewRules {
+                        popularity: Some(&popularity),
+                        max_expires_secs: 86400,
+                        webhooks: false,
+                    },
+                    None,
+                    false,
+                    now_ts(),
+                )
+                .await
+                .unwrap()
+                .unwrap();
+                assert_eq!(count.auto_renewals, 0);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_writes_retry_while_database_is_locked() {
+        let dir = tempfile::tempdir().unwrap();
+        // No busy handler, so every attempt made under the lock fails at once.
+        let options = SqliteConnectOptions::new()
+            .filename(dir.path().join("busy.db"))
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Wal)
+            .busy_timeout(Duration::ZERO);
+        let pool = SqlitePoolOptions::new()
+            .connect_with(options.clone())
+            .await
+            .unwrap();
+        ensure_schema(&pool).await;
+        let other = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect_with(options)
+            .await
+            .unwrap();
+        let mut writer = other.acquire().await.unwrap();
+        sqlx::query("BEGIN IMMEDIATE")
+            .execute(&mut *writer)
+            .await
+            .unwrap();
+        let release = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            sqlx::query("COMMIT").execute(&mut *writer).await.unwrap();
+        });
+
+        let retries = metrics::busy_retries();
+        let token = insert_paste(
+            &pool,
+            NewPaste {
+                content: "waited".to_string(),
+                expires_at: now_ts() + 3600,
+                token_length: 6,
+                slug: None,
+                forked_from: None,
+                filename: None,
+                files: Vec::new(),
+                ..Default::default()
+            },
+            None,
+        )
+        .await
+        .unwrap()
+        .token;
+        release.await.unwrap();
+        assert!(metrics::busy_retries() > retries);
+        let paste = fetch_paste(&pool, &token, None, now_ts())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(paste.content, "waited");
+
+        // Anything but a busy database is not worth another attempt.
+        let mut attempts = 0;
+        let result: Result<(), _> = retry_busy("test", || {
+            attempts += 1;
+            async { Err(sqlx::Error::RowNotFound) }
+        })
+        .await;
+        assert!(matches!(result, Err(sqlx::Error::RowNotFound)));
+        assert_eq!(attempts, 1);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_creates_stay_within_max_pastes() {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = SqlitePoolOptions::new()
+            .max_connections(5)
+            .connect_with(
+                SqliteConnectOptions::new()
+                    .filename(dir.path().join("quota.db"))
+                    .create_if_missing(true)
+                    .journal_mode(SqliteJournalMode::Wal),
+            )
+            .await
+            .unwrap();
+        ensure_schema(&pool).await;
+        // Any moment with more than three rows fails the insert behind it.
+        sqlx::query(
+            r#"
+            CREATE TRIGGER over_quota AFTER INSERT ON pastes
+            WHEN (SELECT COUNT(*) FROM pastes) > 3
+            BEGIN SELECT RAISE(ABORT, 'over max_pastes'); END
+            "#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let creates = (0..20).map(|i| {
+            let pool = pool.clone();
+            tokio::spawn(async move {
+                let paste = NewPaste {
+                    content: format!("paste {}", i),
+                    expires_at: now_ts() + 3600,
+                    token_length: 8,
+                    slug: None,
+                    forked_from: None,
+                    filename: None,
+                    files: Vec::new(),
+                    ..Default::default()
+                };
+                create_paste_checked(
+                    &pool,
+                    paste,
+                    None,
+                    3,
+                    1000,
+                    EvictionPolicy::OldestCreated,
+                    now_ts(),
+                )
+                .await
+                .map(|(_, evicted)| evicted.iter().map(|sweep| sweep.deleted).sum::<u64>())
+            })
+        });
+        let mut evicted = 0;
+        for create in creates.collect::<Vec<_>>() {
+            evicted += create.await.unwrap().unwrap();
+        }
+        assert_eq!(evicted, 17);
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM pastes")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(count, 3);
+
+        let paste = NewPaste {
+            content: "x".repeat(1001),
+            expires_at: now_ts() + 3600,
+            token_length: 8,
+            slug: None,
+            forked_from: None,
+            filename: None,
+            files: Vec::new(),
+            ..Default::default()
+        };
+        let result = create_paste_checked(
+            &pool,
+            paste,
+            None,
+            3,
+            1000,
+            EvictionPolicy::OldestCreated,
+            now_ts(),
+        )
+        .await;
+        assert!(matches!(result, Err(CreateError::TooLarge)));
+    }
+
+    #[tokio::test]
+    async fn test_content_quota_counts_stored_ciphertext() {
+        let pool = setup_test_db().await;
+        let cipher = ContentCipher::from_hex(&"ab".repeat(32)).unwrap();
+        PasteBuilder::new()
+            .content(&"x".repeat(300))
+            .encrypted(&cipher)
+            .insert(&pool)
+            .await;
+        // 300 characters are stored as some 420 of base64.
+        let paste = NewPaste {
+            content: "y".repeat(300),
+            expires_at: now_ts() + 3600,
+            token_length: 8,
+            ..Default::default()
+        };
+        let (_, evicted) = create_paste_checked(
+            &pool,
+            paste,
+            Some(&cipher),
+            10,
+            800,
+            EvictionPolicy::OldestCreated,
+            now_ts(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(evicted[1].deleted, 1);
+        assert!(total_content_length(&pool).await <= 800);
+    }
+
+    #[tokio::test]
+    async fn test_self_destruct_timer() {
+        let pool = setup_test_db().await;
+        let popularity = PopularityConfig {
+            views: 1,
+            bonus_secs: 600,
+            max_extensions: 3,
+        };
+        let timed = || {
+            PasteBuilder::new()
+                .public(true)
+                .destroy_after_first_view(600)
+        };
+        let created = timed().insert(&pool).await;
+        let token = created.token;
+        let meta = fetch_paste_meta(&pool, &token, now_ts())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(meta.destroy_after_first_view_secs, Some(600));
+        assert_eq!(meta.destroys_at, None);
+
+        // The first view arms the timer and pulls the expiry in; later
+        // views and popularity leave it where it is.
+        let view = async || {
+            consume_view(
+                &pool,
+                &token,
+                &ViewRules {
+                    popularity: Some(&popularity),
+                    max_expires_secs: 86400,
+                    webhooks: false,
+                },
+                None,
+                false,
+                now_ts(),
+            )
+            .await
+            .unwrap()
+            .unwrap()
+        };
+        let first = view().await;
+        let armed_at = first.first_viewed_at.unwrap();
+        assert!(armed_at >= now_ts() - 1);
+        assert_eq!((first.expires_at, first.auto_renewals), (armed_at + 600, 0));
+        let second = view().await;
+        assert_eq!(second.first_viewed_at, Some(armed_at));
+        assert_eq!(second.expires_at, armed_at + 600);
+        let meta = fetch_paste_meta(&pool, &token, now_ts())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(meta.destroys_at, Some(armed_at + 600));
+
+        // The owner can neither renew nor publish it.
+        let manage_token = created.manage_token;
+        assert_eq!(
+            renew_managed_paste(&pool, &manage_token, now_ts())
+                .await
+                .unwrap(),
+            None
+        );
+        assert!(
+            !set_managed_public(&pool, &manage_token, true, now_ts())
+                .await
+                .unwrap()
+        );
+
+        // Cleanup goes by the timer.
+        sqlx::query("UPDATE pastes SET first_viewed_at = first_viewed_at - 600")
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert_eq!(
+            cleanup_expired(&pool, 86400, false, now_ts()).await.deleted,
+            1
+        );
+        assert!(
+            fetch_paste(&pool, &token, None, now_ts())
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        // With `max_views` too, whichever runs out first wins.
+        let token = timed().max_views(1).insert(&pool).await.token;
+        consume_view(&pool, &token, &ViewRules::default(), None, false, now_ts())
+            .await
+            .unwrap();
+        assert!(
+            fetch_paste(&pool, &token, None, now_ts())
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_purge_filters_compose() {
+        let pool = setup_test_db().await;
+        let seed = [
+            ("a", "visit http://spam.example", "plaintext", true, 1_000),
+            ("b", "visit http://spam.example", "rust", true, 1_000),
+            ("c", "visit http://spam.example", "plaintext", true, 5_000),
+            ("d", "visit http://spam.example", "plaintext", false, 1_000),
+            ("e", "hello", "plaintext", true, 1_000),
+        ];
+        for (title, content, language, is_public, created_at) in seed {
+            PasteBuilder::new()
+                .title(title)
+                .content(content)
+                .language(language)
+                .public(is_public)
+                .created_at(created_at)
+                .insert(&pool)
+                .await;
+        }
+        let remaining = || async {
+            let titles: Vec<String> = sqlx::query_scalar("SELECT title FROM pastes ORDER BY title")
+                .fetch_all(&pool)
+                .await
+                .unwrap();
+            titles.join("")
+        };
+
+        let mut filter = PurgeFilter {
+            content_contains: Some("spam.example".to_string()),
+            language: Some("plaintext".to_string()),
+            is_public: Some(true),
+            created_before: Some(2_000),
+            ..Default::default()
+        };
+        assert_eq!(
+            purge_pastes(&pool, None, &filter, None, now_ts())
+                .await
+                .unwrap(),
+            (1, 0)
+        );
+        assert_eq!(remaining().await, "abcde");
+
+        filter.confirm = true;
+        assert_eq!(
+            purge_pastes(&pool, None, &filter, None, now_ts())
+                .await
+                .unwrap(),
+            (1, 1)
+        );
+        assert_eq!(remaining().await, "bcde");
+
+        let regex = Regex::new(r"https?://spam\.").unwrap();
+        let filter = PurgeFilter {
+            max_length: Some(30),
+            created_after: Some(2_000),
+            confirm: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            purge_pastes(&pool, None, &filter, Some(&regex), now_ts())
+                .await
+                .unwrap(),
+            (1, 1)
+        );
+        assert_eq!(remaining().await, "bde");
+    }
+
+    #[tokio::test]
+    async fn test_paste_neighbors_follow_creation_order() {
+        let pool = setup_test_db().await;
+        // "b" and "c" were created in the same second; the id breaks the tie.
+        for (token, created_at, is_public) in [
+            ("a", 100, true),
+            ("hidden", 150, false),
+            ("b", 200, true),
+            ("c", 200, true),
+            ("d", 300, true),
+        ] {
+            PasteBuilder::new()
+                .token(token)
+                .title(&token.to_uppercase())
+                .public(is_public)
+                .created_at(created_at)
+                .insert(&pool)
+                .await;
+        }
+        PasteBuilder::new()
+            .token("burn")
+            .public(true)
+            .max_views(1)
+            .created_at(250)
+            .insert(&pool)
+            .await;
+        PasteBuilder::new()
+            .token("gone")
+            .public(true)
+            .expires_in(-1)
+            .created_at(120)
+            .insert(&pool)
+            .await;
+
+        let tokens = |neighbors: Neighbors| {
+            (
+                neighbors.prev.map(|paste| paste.token),
+                neighbors.next.map(|paste| paste.token),
+            )
+        };
+        let around = async |token: &str| paste_neighbors(&pool, token, now_ts()).await.unwrap();
+        assert_eq!(
+            tokens(around("a").await.unwrap()),
+            (None, Some("b".to_string()))
+        );
+        assert_eq!(
+            tokens(around("b").await.unwrap()),
+            (Some("a".to_string()), Some("c".to_string()))
+        );
+        assert_eq!(
+            tokens(around("c").await.unwrap()),
+            (Some("b".to_string()), Some("d".to_string()))
+        );
+        let last = around("d").await.unwrap();
+        assert_eq!(last.prev.as_ref().unwrap().title, "C");
+        assert_eq!(last.next, None);
+        for unlisted in ["hidden", "burn", "gone", "missing"] {
+            assert_eq!(around(unlisted).await, None, "{}", unlisted);
+        }
+
+        // EXPLAIN QUERY PLAN rows are (id, parent, notused, detail).
+        let plan: Vec<String> = sqlx::query_as::<_, (i64, i64, i64, String)>(
+            r#"
+            EXPLAIN QUERY PLAN
+            SELECT token, title FROM pastes
+            WHERE is_public = 1 AND max_views IS NULL AND is_e2e = 0
+              AND (created_at, id) > (200, 3)
+              AND expires_at > ?1
+              AND (visible_after IS NULL OR visible_after <= ?1)
+            ORDER BY created_at, id
+            LIMIT 1
+            "#,
+        )
+        .bind(now_ts())
+        .fetch_all(&pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|(_, _, _, detail)| detail)
+        .collect();
+        assert!(plan.iter().any(|step| step.contains("idx_pastes_explore")));
+        assert!(!plan.iter().any(|step| step.contains("TEMP B-TREE")));
+    }
+
+    #[tokio::test]
+    async fn test_reactions_count_and_cascade() {
+        let pool = setup_test_db().await;
+        let token = PasteBuilder::new().public(true).insert(&pool).await.token;
+        let burn = PasteBuilder::new()
+            .public(true)
+            .max_views(5)
+            .insert(&pool)
+            .await
+            .token;
+        assert_eq!(
+            toggle_reaction(&pool, &token, "🔥", true, now_ts())
+                .await
+                .unwrap(),
+            Some(vec![("🔥".to_string(), 1)])
+        );
+        toggle_reaction(&pool, &token, "🔥", true, now_ts())
+            .await
+            .unwrap();
+        toggle_reaction(&pool, &token, "👀", false, now_ts())
+            .await
+            .unwrap();
+        assert_eq!(
+            toggle_reaction(&pool, &burn, "🔥", true, now_ts())
+                .await
+                .unwrap(),
+            None
+        );
+
+        let counts = reaction_counts(&pool, &[&token, &burn]).await.unwrap();
+        assert_eq!(counts.len(), 1);
+        assert_eq!(counts[&token], [("🔥".to_string(), 2)]);
+
+        delete_paste(&pool, &token, now_ts()).await.unwrap();
+        let remaining: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM reactions")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(remaining, 0);
+    }
+
+    #[tokio::test]
+    async fn test_storage_usage_groups() {
+        let pool = setup_test_db().await;
+        for (language, is_public, content) in [
+            ("rust", true, "a".repeat(100)),
+            ("rust", false, "b".repeat(50)),
+            ("python", false, "c".repeat(30)),
+            // Counted in characters, as the budget counts them.
+            ("plaintext", true, "你好".repeat(10)),
+        ] {
+            PasteBuilder::new()
+                .language(language)
+                .public(is_public)
+                .content(&content)
+                .title(language)
+                .insert(&pool)
+                .await;
+        }
+
+        let by_language: Vec<(String, i64, i64)> = usage_by_language(&pool)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|usage| (usage.language, usage.pastes, usage.content_length))
+            .collect();
+        assert_eq!(
+            by_language,
+            [
+                ("rust".to_string(), 2, 150),
+                ("python".to_string(), 1, 30),
+                ("plaintext".to_string(), 1, 20),
+            ]
+        );
+        let (public, private) = usage_by_visibility(&pool).await.unwrap();
+        assert_eq!((public.pastes, public.content_length), (2, 120));
+        assert_eq!((private.pastes, private.content_length), (2, 80));
+        assert_eq!(
+            public.content_length + private.content_length,
+            total_content_length(&pool).await
+        );
+
+        let largest: Vec<i64> = largest_pastes(&pool, 2)
+            .await
+            .unwrap()
+            .iter()
+            .map(|paste| paste.content_length)
+            .collect();
+        assert_eq!(largest, [100, 50]);
+
+        // Read off the index rather than sorting every paste.
+        let plan: Vec<String> = sqlx::query(&format!("EXPLAIN QUERY PLAN {}", LARGEST_PASTES))
+            .bind(10)
+            .fetch_all(&pool)
+            .await
+            .unwrap()
+            .iter()
+            .map(|row| row.get("detail"))
+            .collect();
+        let plan = plan.join("\n");
+        assert!(plan.contains("idx_pastes_content_length"), "{}", plan);
+        assert!(!plan.contains("TEMP B-TREE"), "{}", plan);
+    }
+
+    #[tokio::test]
+    async fn test_tokens_differing_in_case() {
+        let paste = |slug: &str, case_insensitive_token: bool| NewPaste {
+            title: slug.to_string(),
+            content: "hello".to_string(),
+            expires_at: now_ts() + 3600,
+            original_duration: 3600,
+            token_length: 6,
+            slug: Some(slug.to_string()),
+            language: "plaintext".to_string(),
+            case_insensitive_token,
+            ..Default::default()
+        };
+
+        // Exact: both exist, and each token finds only its own paste
+        let pool = setup_test_db().await;
+        for slug in ["Ab3cDe", "aB3cDe"] {
+            insert_paste(&pool, paste(slug, false), None).await.unwrap();
+        }
+        for slug in ["Ab3cDe", "aB3cDe"] {
+            let found = fetch_paste(&pool, slug, None, now_ts()).await.unwrap();
+            assert_eq!(found.unwrap().title, slug);
+        }
+        assert!(!token_taken(&pool, "AB3CDE", false).await.unwrap());
+        assert!(token_taken(&pool, "AB3CDE", true).await.unwrap());
+        assert_eq!(
+            token_by_key(&pool, "aB3cDe").await.unwrap().as_deref(),
+            Some("aB3cDe")
+        );
+
+        // Forgiving: the second is taken, and any case finds the first
+        let pool = setup_test_db().await;
+        insert_paste(&pool, paste("Ab3cDe", true), None)
+            .await
+            .unwrap();
+        assert!(
+            insert_paste(&pool, paste("aB3cDe", true), None)
+                .await
+                .is_err()
+        );
+        assert_eq!(
+            token_by_key(&pool, "AB3CDE").await.unwrap().as_deref(),
+            Some("Ab3cDe")
+        );
+        assert_eq!(token_by_key(&pool, "xyz").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_token_index_is_rebuilt_to_compare_bytes() {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        for statement in [
+            "CREATE TABLE pastes (id INTEGER PRIMARY KEY AUTOINCREMENT, token TEXT, title TEXT NOT NULL, content TEXT NOT NULL, created_at INTEGER NOT NULL DEFAULT 0)",
+            "CREATE UNIQUE INDEX idx_pastes_token ON pastes(token COLLATE NOCASE)",
+            "INSERT INTO pastes (token, title, content) VALUES ('Ab3', 'A', 'a')",
+        ] {
+            sqlx::query(statement).execute(&pool).await.unwrap();
+        }
+        ensure_schema(&pool).await;
+
+        let collation: String = sqlx::query_scalar(
+            "SELECT coll FROM pragma_index_xinfo('idx_pastes_token') WHERE name = 'token'",
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(collation, "BINARY");
+        sqlx::query("INSERT INTO pastes (token, title, content) VALUES ('aB3', 'B', 'b')")
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert!(
+            sqlx::query("INSERT INTO pastes (token, title, content) VALUES ('aB3', 'C', 'c')")
+                .execute(&pool)
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_self_check_names_what_is_broken() {
+        async fn checked(breakage: &str) -> Result<SchemaReport, String> {
+            let pool = SqlitePoolOptions::new()
+                .max_connections(1)
+                .connect("sqlite::memory:")
+                .await
+                .unwrap();
+            ensure_schema(&pool).await;
+            if !breakage.is_empty() {
+                sqlx::query(breakage).execute(&pool).await.unwrap();
+            }
+            self_check(&pool).await
+        }
+
+        let report = checked("").await.unwrap();
+        assert_eq!(report.version, SCHEMA_VERSION);
+        assert!(report.tables["pastes"].contains(&"token_key".to_string()));
+        assert_eq!(report.tables["settings"], ["name", "value"]);
+
+        assert_eq!(
+            checked("ALTER TABLE pastes DROP COLUMN author")
+                .await
+                .unwrap_err(),
+            "Table pastes is missing columns: author"
+        );
+        assert_eq!(
+            checked("DROP TABLE reactions").await.unwrap_err(),
+            "Table reactions is missing"
+        );
+        assert_eq!(
+            checked("PRAGMA user_version = 7").await.unwrap_err(),
+            format!(
+                "The database is at schema version 7, but this build only knows up to {}",
+                SCHEMA_VERSION
+            )
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let options = SqliteConnectOptions::new()
+            .filename(dir.path().join("mayfile.db"))
+            .create_if_missing(true);
+        let pool = SqlitePoolOptions::new()
+            .connect_with(options.clone())
+            .await
+            .unwrap();
+        ensure_schema(&pool).await;
+        pool.close().await;
+        let pool = SqlitePoolOptions::new()
+            .connect_with(options.read_only(true))
+            .await
+            .unwrap();
+        let error = self_check(&pool).await.unwrap_err();
+        assert!(
+            error.starts_with("Cannot write to the database: "),
+            "{}",
+            error
+        );
+    }
+
+    #[tokio::test]
+    async fn test_taken_tokens_are_drawn_again() {
+        assert_eq!(token_attempts(100_000, 8), TOKEN_ATTEMPTS);
+        assert_eq!(token_attempts(0, 1), TOKEN_ATTEMPTS);
+        assert_eq!(token_attempts(31, 1), 20);
+        assert_eq!(token_attempts(1000, 1), MAX_TOKEN_ATTEMPTS);
+
+        // One-character tokens leave 62 to draw from, so forty pastes all
+        // but certainly collide on the way.
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        ensure_schema(&pool).await;
+        let collisions = metrics::token_collisions();
+        for i in 0..40 {
+            let paste = NewPaste {
+                content: format!("paste {}", i),
+                expires_at: now_ts() + 3600,
+                token_length: 1,
+                ..Default::default()
+            };
+            create_paste_checked(
+                &pool,
+                paste,
+                None,
+                1000,
+                1000,
+                EvictionPolicy::OldestCreated,
+                now_ts(),
+            )
+            .await
+            .unwrap();
+        }
+        assert!(metrics::token_collisions() > collisions);
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(DISTINCT token) FROM pastes")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(count, 40);
     }
 }
@@ -0,0 +1,351 @@
+//! Server-side guess at the language of a paste created with `auto`.
+
+use regex::Regex;
+use std::sync::LazyLock;
+
+/// Only the start of a paste is inspected, so detection stays cheap however
+/// large the paste is.
+const SAMPLE_BYTES: usize = 16 * 1024;
+
+/// A language needs at least this many points of keyword evidence.
+const MIN_SCORE: usize = 3;
+
+/// Average characters per line past which a paste of unknown language
+/// reads as paragraphs rather than code.
+const PROSE_LINE_CHARS: usize = 120;
+
+/// Patterns that hint at a language, with how much each counts.
+type Evidence = Vec<(Regex, usize)>;
+
+/// Weighted patterns per language. Earlier languages win ties, so
+/// TypeScript (which repeats the JavaScript patterns) only wins with
+/// something JavaScript lacks.
+static KEYWORDS: LazyLock<Vec<(&'static str, Evidence)>> = LazyLock::new(|| {
+    let javascript: &[(&str, usize)] = &[
+        (r"\bfunction\s*\w*\s*\(", 2),
+        (r"\b(const|let|var)\s+\w+\s*=", 1),
+        (r"=>", 1),
+        (r"\bconsole\.\w+\(", 3),
+        (r"\b(document|window)\.\w+", 2),
+        (r"\brequire\(|\bmodule\.exports\b", 3),
+        (r"===|!==", 2),
+    ];
+    let typescript: Vec<(&str, usize)> = javascript
+        .iter()
+        .copied()
+        .chain([
+            (r":\s*(string|number|boolean|any|void|unknown)\b", 3),
+            (r"(?m)^\s*(export\s+)?interface\s+\w+", 3),
+            (r"(?m)^\s*(export\s+)?type\s+\w+\s*=", 2),
+        ])
+        .collect();
+    let table: Vec<(&str, Vec<(&str, usize)>)> = vec![
+        (
+            "rust",
+            vec![
+                (r"\bfn\s+\w+\s*[(<]", 3),
+                (r"\blet\s+mut\b", 3),
+                (r"(?m)^\s*impl\b", 2),
+                (r"\bpub\s+(fn|struct|enum|mod|trait)\b", 3),
+                (r"(?m)^\s*use\s+\w+(::\w+)+", 3),
+                (r"\b(println|vec|format)!", 3),
+                (r"&mut\b|&self\b", 2),
+            ],
+        ),
+        (
+            "python",
+            vec![
+                (r"(?m)^\s*def\s+\w+\(.*\)\s*(->.*)?:\s*$", 3),
+                (r"(?m)^\s*class\s+\w+.*:\s*$", 2),
+                (r"(?m)^\s*from\s+[\w.]+\s+import\b", 3),
+                (r"\bself\.", 2),
+                (r"(?m)^\s*(elif|except|with)\b.*:\s*$", 2),
+                (r"\b__\w+__\b", 2),
+                (r"\b(None|True|False)\b", 1),
+            ],
+        ),
+        ("javascript", javascript.to_vec()),
+        ("typescript", typescript),
+        (
+            "go",
+            vec![
+                (r"(?m)^package\s+\w+", 3),
+                (r"\bfunc\s+(\(\w+\s+\*?\w+\)\s*)?\w+\(", 3),
+                (r":=", 2),
+                (r"\bfmt\.\w+", 3),
+                (r"\berr\s*!=\s*nil\b", 3),
+                (r"\b(chan|defer)\b|\bgo\s+func\b", 2),
+            ],
+        ),
+        (
+            "java",
+            vec![
+                (
+                    r"\bpublic\s+(static\s+)?(final\s+)?(class|void|interface)\b",
+                    3,
+                ),
+                (r"\bSystem\.(out|err)\.print", 3),
+                (r"(?m)^import\s+java\.", 3),
+                (r"@Override\b", 3),
+                (r"\bString\[\]", 2),
+                (r"\b(private|protected)\s+\w+(<[^>]*>)?\s+\w+\s*[;=(]", 2),
+            ],
+        ),
+        (
+            "cpp",
+            vec![
+                (r#"(?m)^\s*#include\s*[<"]"#, 4),
+                (r"\bstd::", 3),
+                (r"\bcout\s*<<|\bcin\s*>>", 3),
+                (r"\btemplate\s*<", 3),
+                (r"(?m)^\s*#define\b", 2),
+                (r"\bnullptr\b", 3),
+                (r"\bint\s+main\s*\(", 2),
+            ],
+        ),
+        (
+            "html",
+            vec![
+                (r"(?i)<!doctype\s+html", 5),
+                (
+                    r"(?i)</?(html|head|body|div|span|p|a|ul|li|table|script|form)\b[^>]*>",
+                    2,
+                ),
+            ],
+        ),
+        (
+            "css",
+            vec![
+                (r"@(media|import|keyframes|font-face)\b", 3),
+                (
+                    r"(?m)^\s*(color|margin|padding|display|font-size|background|border|width|height)\s*:[^;]+;",
+                    2,
+                ),
+                (r"(?m)^\s*[.#][\w-]+[^{;]*\{", 2),
+                (r"\d(px|em|rem|vh|vw)\b", 1),
+            ],
+        ),
+        (
+            "sql",
+            vec![
+                (r"(?im)^\s*select\s+.+\s+from\s+\w+", 3),
+                (r"(?i)\binsert\s+into\b", 3),
+                (r"(?i)\bcreate\s+(table|index|view)\b", 3),
+                (r"(?i)\bupdate\s+\w+\s+set\b|\bdelete\s+from\b", 3),
+                (
+                    r"(?i)\b(inner\s+join|left\s+join|group\s+by|order\s+by)\b",
+                    2,
+                ),
+                (r"(?i)\bwhere\b", 1),
+            ],
+        ),
+        (
+            "bash",
+            vec![
+                (r"(?m)^\s*(if|while|for)\b.*;\s*(then|do)\s*$", 3),
+                (r"(?m)^\s*(fi|done|esac)\s*$", 3),
+                (r"(?m)^\s*echo\b", 2),
+                (r"(?m)^\s*export\s+\w+=", 2),
+                (r"\|\s*(grep|awk|sed|xargs)\b", 3),
+                (r"(?m)^\s*(sudo|apt|apt-get|cd|mkdir|rm|curl|git)\s", 2),
+                (r"\$\{?\w+\}?", 1),
+            ],
+        ),
+    ];
+    table
+        .into_iter()
+        .map(|(language, patterns)| {
+            let patterns = patterns
+                .into_iter()
+                .map(|(pattern, weight)| (Regex::new(pattern).unwrap(), weight))
+                .collect();
+            (language, patterns)
+        })
+        .collect()
+});
+
+/// `key: value`, `- item` or `---` lines.
+static YAML_LINE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^\s*(-\s+|-$|---$|(-\s+)?[\w.-]+:(\s|$))").unwrap());
+
+/// The supported language `content` most likely is, falling back to
+/// `plaintext`.
+pub fn detect_language(content: &str) -> &'static str {
+    let truncated = content.len() > SAMPLE_BYTES;
+    let sample = &content[..content.floor_char_boundary(SAMPLE_BYTES)];
+    if let Some(language) = from_shebang(sample) {
+        return language;
+    }
+    if looks_like_json(sample.trim(), truncated) {
+        return "json";
+    }
+    if looks_like_yaml(sample) {
+        return "yaml";
+    }
+    let mut best = ("plaintext", MIN_SCORE - 1);
+    for (language, patterns) in KEYWORDS.iter() {
+        let score: usize = patterns
+            .iter()
+            .map(|(pattern, weight)| pattern.find_iter(sample).count().min(3) * weight)
+            .sum();
+        if score > best.1 {
+            best = (language, score);
+        }
+    }
+    best.0
+}
+
+/// Whether a paste shown as `language` reads better wrapped at a readable
+/// width than in a code box: `plaintext` and `markdown` always do, a paste
+/// nothing was detected for only if its lines run long on average.
+pub fn reads_as_prose(language: &str, content: &str) -> bool {
+    match language {
+        "plaintext" | "markdown" => true,
+        "auto" => {
+            let sample = &content[..content.floor_char_boundary(SAMPLE_BYTES)];
+            let (lines, chars) = sample
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .fold((0, 0), |(lines, chars), line| {
+                    (lines + 1, chars + line.chars().count())
+                });
+            lines > 0 && chars / lines >= PROSE_LINE_CHARS
+        }
+        _ => false,
+    }
+}
+
+fn from_shebang(sample: &str) -> Option<&'static str> {
+    let line = sample.lines().next()?.strip_prefix("#!")?;
+    // `#!/usr/bin/env python3` names the interpreter last.
+    let interpreter = line.split_whitespace().last()?.rsplit('/').next()?;
+    match interpreter.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.') {
+        "sh" | "bash" | "zsh" | "dash" | "ksh" => Some("bash"),
+        "python" => Some("python"),
+        "node" | "nodejs" => Some("javascript"),
+        "deno" | "ts-node" | "bun" => Some("typescript"),
+        _ => None,
+    }
+}
+
+/// An object or array that parses, or would if it had not been cut off.
+fn looks_like_json(sample: &str, truncated: bool) -> bool {
+    if !(sample.starts_with('{') || sample.starts_with('[')) {
+        return false;
+    }
+    match serde_json::from_str::<serde_json::Value>(sample) {
+        Ok(_) => true,
+        Err(err) => truncated && err.is_eof(),
+    }
+}
+
+/// At least two meaningful lines, nearly all of them YAML-shaped, with at
+/// least one mapping key.
+fn looks_like_yaml(sample: &str) -> bool {
+    let lines: Vec<&str> = sample
+        .lines()
+        .filter(|line| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+        .collect();
+    if lines.len() < 2 || !lines.iter().any(|line| line.contains(':')) {
+        return false;
+    }
+    let matching = lines.iter().filter(|line| YAML_LINE.is_match(line)).count();
+    matching * 10 >= lines.len() * 9
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reads_as_prose() {
+        let paragraph = "这是一段很长的中文段落，".repeat(20);
+        assert!(reads_as_prose("plaintext", "a\nb\n"));
+        assert!(reads_as_prose("markdown", "# Title\n"));
+        assert!(reads_as_prose(
+            "auto",
+            &format!("{}\n\n{}\n", paragraph, paragraph)
+        ));
+        assert!(!reads_as_prose("auto", "short\nlines\n"));
+        assert!(!reads_as_prose("auto", ""));
+        assert!(!reads_as_prose("rust", &paragraph));
+    }
+
+    #[test]
+    fn test_detect_language() {
+        let cases = [
+            (
+                "rust",
+                "use std::collections::HashMap;\n\nfn main() {\n    let mut counts = HashMap::new();\n    println!(\"{:?}\", counts);\n}\n",
+            ),
+            (
+                "python",
+                "import os\n\ndef walk(path):\n    for name in os.listdir(path):\n        if name.startswith('.'):\n            continue\n        print(name)\n",
+            ),
+            (
+                "javascript",
+                "const fs = require('fs');\nfunction read(path) {\n  return fs.readFileSync(path) === null;\n}\nconsole.log(read('a'));\n",
+            ),
+            (
+                "typescript",
+                "interface User {\n  name: string;\n  age: number;\n}\n\nconst greet = (user: User): void => {\n  console.log(user.name);\n};\n",
+            ),
+            (
+                "go",
+                "package main\n\nimport \"fmt\"\n\nfunc main() {\n\tx := 1\n\tfmt.Println(x)\n}\n",
+            ),
+            (
+                "java",
+                "public class Hello {\n    public static void main(String[] args) {\n        System.out.println(\"hi\");\n    }\n}\n",
+            ),
+            (
+                "cpp",
+                "#include <iostream>\n\nint main() {\n    std::cout << \"hi\" << std::endl;\n    return 0;\n}\n",
+            ),
+            (
+                "html",
+                "<!DOCTYPE html>\n<html>\n<body>\n  <div class=\"x\">Hello</div>\n</body>\n</html>\n",
+            ),
+            (
+                "css",
+                ".card {\n  margin: 0 auto;\n  padding: 8px;\n}\n\n@media (max-width: 600px) {\n  .card { display: none; }\n}\n",
+            ),
+            ("json", "{\"name\": \"mayfile\", \"tags\": [1, 2, 3]}"),
+            (
+                "yaml",
+                "version: 2\nservices:\n  web:\n    image: nginx\n    ports:\n      - \"80:80\"\n",
+            ),
+            (
+                "sql",
+                "SELECT id, title FROM pastes\nWHERE expires_at > 0\nORDER BY id;\n",
+            ),
+            ("bash", "#!/usr/bin/env bash\nset -e\nls -la\n"),
+            (
+                "bash",
+                "for f in *.log; do\n  grep error \"$f\" | sed 's/x/y/'\ndone\n",
+            ),
+            ("python", "#!/usr/bin/python3\nx = 1\n"),
+            (
+                "plaintext",
+                "Meeting notes for Tuesday.\nWe agreed to ship the release next week and let everyone know.\n",
+            ),
+            ("plaintext", "Just a grocery list:\nmilk\neggs\nbread\n"),
+        ];
+        for (expected, content) in cases {
+            assert_eq!(detect_language(content), expected, "{}", content);
+        }
+    }
+
+    #[test]
+    fn test_detection_is_bounded() {
+        // Cut off inside the sample, the array still reads as JSON.
+        let json = format!("[{}1]", "1, ".repeat(SAMPLE_BYTES));
+        assert_eq!(detect_language(&json), "json");
+        // Evidence past the sample is never seen.
+        let late = format!(
+            "{}\nfn main() {{ println!(\"hi\"); }}",
+            "é".repeat(SAMPLE_BYTES)
+        );
+        assert_eq!(detect_language(&late), "plaintext");
+    }
+}
@@ -0,0 +1,85 @@
+//! Line diffs between two pastes, for `/diff/{a}/{b}`.
+
+use crate::models::{DiffHunk, DiffLine};
+use similar::{ChangeTag, TextDiff};
+use std::time::Duration;
+
+/// Unchanged lines shown around each change.
+const CONTEXT_LINES: usize = 3;
+
+/// Past this the diff settles for a coarser, still correct, result.
+const TIMEOUT: Duration = Duration::from_millis(500);
+
+fn text_diff<'a>(old: &'a str, new: &'a str) -> TextDiff<'a, 'a, str> {
+    TextDiff::configure().timeout(TIMEOUT).diff_lines(old, new)
+}
+
+/// A unified diff from `old` to `new`, as `diff -u` or `git apply` read it.
+/// Empty when they are the same.
+pub fn unified(old: &str, new: &str, old_name: &str, new_name: &str) -> String {
+    text_diff(old, new)
+        .unified_diff()
+        .context_radius(CONTEXT_LINES)
+        .header(old_name, new_name)
+        .to_string()
+}
+
+/// The hunks of `unified`, line by line, for the diff page.
+pub fn hunks(old: &str, new: &str) -> Vec<DiffHunk> {
+    let diff = text_diff(old, new);
+    let mut unified = diff.unified_diff();
+    unified.context_radius(CONTEXT_LINES);
+    unified
+        .iter_hunks()
+        .map(|hunk| DiffHunk {
+            header: hunk.header().to_string(),
+            lines: hunk
+                .iter_changes()
+                .map(|change| DiffLine {
+                    kind: match change.tag() {
+                        ChangeTag::Delete => "removed",
+                        ChangeTag::Insert => "added",
+                        ChangeTag::Equal => "context",
+                    },
+                    sign: change.tag().to_string(),
+                    text: change.value().trim_end_matches(['\n', '\r']).to_string(),
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unified_and_hunks_agree() {
+        let old = "a\nb\nc\n";
+        let new = "a\nB\nc\nd\n";
+        assert_eq!(
+            unified(old, new, "old", "new"),
+            "--- old\n+++ new\n@@ -1,3 +1,4 @@\n a\n-b\n+B\n c\n+d\n"
+        );
+        let hunks = hunks(old, new);
+        assert_eq!(hunks.len(), 1);
+        assert_eq!(hunks[0].header, "@@ -1,3 +1,4 @@");
+        let lines: Vec<(&str, &str)> = hunks[0]
+            .lines
+            .iter()
+            .map(|line| (line.kind, line.text.as_str()))
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                ("context", "a"),
+                ("removed", "b"),
+                ("added", "B"),
+                ("context", "c"),
+                ("added", "d"),
+            ]
+        );
+        assert_eq!(unified(old, old, "old", "new"), "");
+        assert!(super::hunks(old, old).is_empty());
+    }
+}
@@ -0,0 +1,54 @@
+//! Failures handlers hand back to axum as responses.
+
+use axum::http::StatusCode;
+use axum::response::{Html, IntoResponse, Response};
+
+/// Served when a page cannot be rendered, the error page included. Kept
+/// free of templates and stylesheets so it cannot fail in turn.
+pub const FALLBACK_PAGE: &str = r#"<!doctype html>
+<html>
+<head><meta charset="utf-8" /><title>Mayfile</title></head>
+<body style="font-family: sans-serif; text-align: center; margin-top: 20vh; color: #1f2430;">
+<h1>Mayfile</h1>
+<p>出了点问题，请稍后再试。</p>
+<p>Something went wrong. Please try again later.</p>
+<p><a href="/">Mayfile</a></p>
+</body>
+</html>
+"#;
+
+#[derive(Debug)]
+pub enum AppError {
+    /// A template failed to render.
+    Render(askama::Error),
+}
+
+impl From<askama::Error> for AppError {
+    fn from(err: askama::Error) -> Self {
+        AppError::Render(err)
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        match self {
+            AppError::Render(err) => {
+                eprintln!("Failed to render page: {}", err);
+                (StatusCode::INTERNAL_SERVER_ERROR, Html(FALLBACK_PAGE)).into_response()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::body_string;
+
+    #[tokio::test]
+    async fn test_render_error_serves_fallback_page() {
+        let response = AppError::from(askama::Error::Fmt).into_response();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(body_string(response).await, FALLBACK_PAGE);
+    }
+}
@@ -0,0 +1,123 @@
+//! The paste of the day on the index.
+
+use crate::crypto::ContentCipher;
+use crate::db;
+use crate::models::PublicPaste;
+use crate::utils::{format_iso8601, sha256_hex};
+use sqlx::SqlitePool;
+use std::sync::Mutex;
+
+/// How long a pick is served before the database is asked again, so a
+/// paste that expired or was deleted drops off soon after.
+const CACHE_SECS: i64 = 300;
+
+/// Characters of content shown under the title.
+pub const PREVIEW_CHARS: i64 = 240;
+
+/// The local day a pick was made for, when, and the pick.
+type CachedPick = (String, i64, Option<PublicPaste>);
+
+#[derive(Default)]
+pub struct FeaturedCache {
+    entry: Mutex<Option<CachedPick>>,
+}
+
+impl FeaturedCache {
+    /// Today's paste (at `offset_secs` from UTC), from the cache while it
+    /// is fresh and still for today.
+    pub async fn get(
+        &self,
+        pool: &SqlitePool,
+        cipher: Option<&ContentCipher>,
+        offset_secs: i32,
+        now: i64,
+    ) -> Result<Option<PublicPaste>, sqlx::Error> {
+        let date = format_iso8601(now, offset_secs)[..10].to_string();
+        if let Some((day, at, pick)) = self.entry.lock().unwrap().as_ref()
+            && *day == date
+            && now - at < CACHE_SECS
+        {
+            return Ok(pick.clone());
+        }
+        let pick = db::featured_paste(pool, cipher, PREVIEW_CHARS, day_seed(&date), now).await?;
+        *self.entry.lock().unwrap() = Some((date, now, pick.clone()));
+        Ok(pick)
+    }
+
+    /// Forget the pick, after the admin chose or cleared one.
+    pub fn invalidate(&self) {
+        *self.entry.lock().unwrap() = None;
+    }
+}
+
+/// A number fixed for `date` (`YYYY-MM-DD`) but unrelated between days.
+pub fn day_seed(date: &str) -> u64 {
+    u64::from_str_radix(&sha256_hex(date)[..16], 16).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{PasteBuilder, test_state};
+    use crate::utils::now_ts;
+
+    fn token(pick: Option<PublicPaste>) -> Option<String> {
+        pick.map(|paste| paste.token)
+    }
+
+    #[tokio::test]
+    async fn test_featured_pick_is_stable_and_can_be_overridden() {
+        let state = test_state().await;
+        let cache = FeaturedCache::default();
+        let now = now_ts();
+        assert_eq!(
+            token(cache.get(&state.pool, None, 0, now).await.unwrap()),
+            None
+        );
+
+        for name in ["one", "two", "three"] {
+            PasteBuilder::new()
+                .token(name)
+                .public(true)
+                .insert(&state.pool)
+                .await;
+        }
+        PasteBuilder::new()
+            .token("private")
+            .insert(&state.pool)
+            .await;
+        // Still the empty pick until the cache goes stale.
+        assert_eq!(
+            token(cache.get(&state.pool, None, 0, now).await.unwrap()),
+            None
+        );
+
+        let later = now + CACHE_SECS;
+        let pick = token(cache.get(&state.pool, None, 0, later).await.unwrap()).unwrap();
+        assert!(["one", "two", "three"].contains(&pick.as_str()));
+        cache.invalidate();
+        assert_eq!(
+            token(cache.get(&state.pool, None, 0, later).await.unwrap()),
+            Some(pick)
+        );
+
+        assert!(!db::set_featured(&state.pool, "private", now).await.unwrap());
+        assert!(db::set_featured(&state.pool, "two", now).await.unwrap());
+        cache.invalidate();
+        assert_eq!(
+            token(cache.get(&state.pool, None, 0, later).await.unwrap()).as_deref(),
+            Some("two")
+        );
+
+        db::delete_paste(&state.pool, "two", now_ts())
+            .await
+            .unwrap();
+        assert!(!db::clear_featured(&state.pool).await.unwrap());
+    }
+
+    #[test]
+    fn test_day_seed_changes_with_the_date() {
+        assert_eq!(day_seed("2024-05-03"), day_seed("2024-05-03"));
+        assert_ne!(day_seed("2024-05-03"), day_seed("2024-05-04"));
+    }
+}
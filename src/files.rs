@@ -0,0 +1,134 @@
+//! Multi-file pastes, gist-style: the paste's own content is its first
+//! file and the rest are stored alongside it in `paste_files`.
+
+/// Files a single paste may hold, its own content included.
+pub const MAX_FILES: usize = 20;
+
+/// How long a filename may be, in characters.
+pub const MAX_FILENAME_LENGTH: usize = 100;
+
+/// Why the files of a paste cannot be stored.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FileError {
+    /// More than `MAX_FILES`.
+    TooMany,
+    /// Too long, a path, or holding quotes or control characters.
+    InvalidName,
+    /// Two files with the same name.
+    DuplicateName,
+    /// Client-side encrypted pastes hold a single file.
+    Encrypted,
+}
+
+impl FileError {
+    /// The error code creating the paste answers with.
+    pub fn code(self) -> &'static str {
+        match self {
+            FileError::TooMany => "files_too_many",
+            FileError::InvalidName => "filename_invalid",
+            FileError::DuplicateName => "filename_duplicate",
+            FileError::Encrypted => "files_e2e",
+        }
+    }
+}
+
+/// One further file as submitted, content already normalized.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FileInput {
+    pub filename: String,
+    pub language: Option<String>,
+    pub content: String,
+}
+
+/// Check a filename so it can name a `/r/{token}/{filename}` URL.
+pub fn validate_filename(raw: &str) -> Result<String, FileError> {
+    let name = raw.trim();
+    if name.is_empty()
+        || name.chars().count() > MAX_FILENAME_LENGTH
+        || name == "."
+        || name == ".."
+        || name
+            .chars()
+            .any(|c| c.is_control() || matches!(c, '/' | '\\' | '"'))
+    {
+        return Err(FileError::InvalidName);
+    }
+    Ok(name.to_string())
+}
+
+/// Name the files of a paste: `first` is the name given to its own
+/// content, `extra` the files after it. Extra files without content are
+/// dropped, as the form sends empty rows. Once there is more than one file,
+/// every file gets a name, `file-{n}` for those left blank.
+pub fn name_files(
+    first: Option<&str>,
+    mut extra: Vec<FileInput>,
+) -> Result<(Option<String>, Vec<FileInput>), FileError> {
+    extra.retain(|file| !file.content.is_empty());
+    if extra.len() + 1 > MAX_FILES {
+        return Err(FileError::TooMany);
+    }
+    let first = first.map(str::trim).filter(|name| !name.is_empty());
+    if extra.is_empty() {
+        return Ok((first.map(validate_filename).transpose()?, extra));
+    }
+    let name = |raw: Option<&str>, position: usize| match raw.map(str::trim) {
+        Some(raw) if !raw.is_empty() => validate_filename(raw),
+        _ => Ok(format!("file-{}", position + 1)),
+    };
+    let first = name(first, 0)?;
+    let mut names = vec![first.clone()];
+    for (index, file) in extra.iter_mut().enumerate() {
+        file.filename = name(Some(&file.filename), index + 1)?;
+        if names.contains(&file.filename) {
+            return Err(FileError::DuplicateName);
+        }
+        names.push(file.filename.clone());
+    }
+    Ok((Some(first), extra))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(filename: &str, content: &str) -> FileInput {
+        FileInput {
+            filename: filename.to_string(),
+            language: None,
+            content: content.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_name_files() {
+        assert_eq!(name_files(None, vec![]), Ok((None, vec![])));
+        assert_eq!(
+            name_files(Some(" main.rs "), vec![file("", "")]),
+            Ok((Some("main.rs".to_string()), vec![]))
+        );
+        assert_eq!(
+            name_files(None, vec![file("", "b"), file("notes.md", "c")]),
+            Ok((
+                Some("file-1".to_string()),
+                vec![file("file-2", "b"), file("notes.md", "c")]
+            ))
+        );
+        assert_eq!(
+            name_files(Some("a.txt"), vec![file("a.txt", "b")]),
+            Err(FileError::DuplicateName)
+        );
+        for bad in ["../etc", "a\\b", "say \"hi\"", "..", "tab\there"] {
+            assert_eq!(
+                name_files(None, vec![file(bad, "b")]),
+                Err(FileError::InvalidName),
+                "{}",
+                bad
+            );
+        }
+        assert_eq!(
+            name_files(None, vec![file("", "b"); MAX_FILES]),
+            Err(FileError::TooMany)
+        );
+    }
+}
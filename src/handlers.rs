@@ -1,54 +1,113 @@
+use crate::activity;
+use crate::api::{
+    ApiDoc, ApiError, ApiLimits, ApiPasteForm, ApiStats, ApiValidateForm, ArchiveMonth,
+    CreatedPasteResponse, EXPLORE_FIELDS, ExpiryLimits, ExplorePaste, FeatureLimits, Headroom,
+    LanguageTimeline, PasteNeighbors, PasteReactions, PasteValidation, SlugAvailability,
+    TokenLimits, ValidationCheck, parse_explore_fields,
+};
+use crate::archive::{self, Month};
+use crate::assets::{self, Assets};
+use crate::audit::{self, Refusal};
+use crate::backup;
+use crate::captcha;
+use crate::config;
+use crate::crypto::{
+    admin_session, client_hash, open_content, owner_signature, reaction_receipt, sign_share_link,
+    verify_admin_session, verify_owner_signature, verify_reaction_receipt, verify_share_link,
+    verify_view_receipt, view_receipt,
+};
 use crate::db;
+use crate::detect;
+use crate::diff;
+use crate::error::{AppError, FALLBACK_PAGE};
+use crate::files::{self, FileError, FileInput};
+use crate::metrics;
 use crate::models::*;
-use crate::utils::{now_ts};
+use crate::net::{Cidr, ClientIp};
+use crate::paste_cache::PasteCache;
+use crate::qr;
+use crate::slug::{self, SlugError};
+use crate::syntax;
+use crate::text_stats::content_stats;
+use crate::utils::{
+    format_bytes, format_iso8601, generate_token, parse_local_datetime, parse_rfc3339,
+    parse_utc_offset, sha256_hex,
+};
 use askama::Template;
 use axum::{
-    extract::{Form, Path, Query, State},
+    body::{Body, Bytes},
+    extract::{
+        FromRequest, FromRequestParts, Path, Query, RawForm, Request, State,
+        rejection::JsonRejection,
+    },
     http::{
-        HeaderMap, HeaderValue, StatusCode,
-        header::{CONTENT_DISPOSITION, CONTENT_TYPE, COOKIE, SET_COOKIE},
+        HeaderMap, HeaderValue, Method, StatusCode,
+        header::{
+            ACCEPT, AUTHORIZATION, CACHE_CONTROL, CONTENT_DISPOSITION, CONTENT_ENCODING,
+            CONTENT_LENGTH, CONTENT_SECURITY_POLICY, CONTENT_TYPE, COOKIE, ETAG, IF_NONE_MATCH,
+            LOCATION, RETRY_AFTER, SET_COOKIE, VARY,
+        },
+        request::Parts,
     },
-    response::{Html, IntoResponse, Redirect},
+    middleware::Next,
+    response::{Html, IntoResponse, Redirect, Response},
 };
+use http_body_util::Limited;
+use percent_encoding::{NON_ALPHANUMERIC, percent_decode_str, utf8_percent_encode};
+use serde::de::DeserializeOwned;
 use std::collections::HashMap;
+use std::net::IpAddr;
+use std::num::IntErrorKind;
+use std::ops::RangeInclusive;
+use utoipa::OpenApi;
 
 pub async fn renew_paste(
     State(state): State<AppState>,
     headers: HeaderMap,
-    Path(token): Path<String>,
+    PasteToken(token): PasteToken,
 ) -> impl IntoResponse {
-    let params = HashMap::new();
-    let (lang, _) = select_language(&headers, &params);
-    let strings = state.i18n.strings(lang);
+    let Some(token) = normalize_token(&token, &state.config.load().paste) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let (lang, _) = select_language(&headers, None);
+    let strings = state.i18n.load().strings(lang);
 
-    let item: Option<(i64, i64, i64, bool, Option<i64>)> = sqlx::query_as(
-        "SELECT created_at, expires_at, original_duration, is_public, max_views FROM pastes WHERE token = ?"
+    // Burn-after-reading and self-destructing pastes are never renewed.
+    let item: Option<(i64, i64, bool, bool)> = sqlx::query_as(
+        "SELECT expires_at, original_duration, is_public, max_views IS NOT NULL OR destroy_after_first_view_secs IS NOT NULL FROM pastes WHERE token = ?"
     )
     .bind(&token)
     .fetch_optional(&state.pool)
     .await
     .unwrap_or(None);
 
-    if let Some((_, expires_at, original_duration, is_public, max_views)) = item {
-        if !is_public || max_views.is_some() {
+    if let Some((expires_at, original_duration, is_public, self_destructs)) = item {
+        if !is_public || self_destructs {
             return (StatusCode::FORBIDDEN, "Not allowed").into_response();
         }
 
-        let now = now_ts();
-        let remaining = expires_at - now;
-
-        if remaining < (original_duration / 2) {
+        let now = state.clock.now();
+        if renewal(true, original_duration, expires_at, now).renew_eligible {
             let new_expires_at = now + original_duration;
-            sqlx::query("UPDATE pastes SET expires_at = ? WHERE token = ?")
-                .bind(new_expires_at)
-                .bind(&token)
-                .execute(&state.pool)
-                .await
-                .ok();
+            db::retry_busy("renew", || {
+                sqlx::query("UPDATE pastes SET expires_at = ? WHERE token = ?")
+                    .bind(new_expires_at)
+                    .bind(&token)
+                    .execute(&state.pool)
+            })
+            .await
+            .ok();
+            state.paste_cache.invalidate(&token);
 
+            let renew_at = renewal(true, original_duration, new_expires_at, now).renew_available_at;
             let mut headers = HeaderMap::new();
-            let trigger_val = format!(r#"{{"renewed": {{"token": "{}", "expires": {}}}}}"#, token, new_expires_at);
-            headers.insert("HX-Trigger", HeaderValue::from_str(&trigger_val).unwrap());
+            let trigger_val = serde_json::json!({
+                "renewed": {"token": token, "expires": new_expires_at, "renew_at": renew_at}
+            });
+            headers.insert(
+                "HX-Trigger",
+                HeaderValue::from_str(&trigger_val.to_string()).unwrap(),
+            );
 
             return (
                 StatusCode::OK,
@@ -65,21 +124,268 @@ pub async fn renew_paste(
     StatusCode::BAD_REQUEST.into_response()
 }
 
+/// `/m/{manage_token}`: the paste's stats and the owner's actions. Whoever
+/// holds the link is the owner; there is no cookie involved.
+pub async fn manage_page(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(manage_token): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Response, AppError> {
+    let now = state.clock.now();
+    let (lang, set_cookie) = select_page_language(&headers, &params);
+    let strings = state.i18n.load().strings(lang);
+    let Some(manage_token) = normalize_manage_token(&manage_token) else {
+        return not_found_page(&state, strings, String::new());
+    };
+    let item = db::fetch_managed_paste(&state.pool, &manage_token, now)
+        .await
+        .unwrap_or(None);
+    let mut response = match item {
+        Some(item) => {
+            let config = state.config.load();
+            let remaining_views = item.max_views.map(|max| {
+                strings.plural(&strings.detail_remaining_views, (max - item.views).max(0))
+            });
+            let stats = db::fetch_paste(&state.pool, &item.token, state.cipher.as_ref(), now)
+                .await
+                .unwrap_or(None)
+                .as_ref()
+                .and_then(paste_stats)
+                .map(|stats| stats_label(stats, &strings));
+            let share_links = db::list_share_links(&state.pool, &item.token)
+                .await
+                .unwrap_or_default()
+                .into_iter()
+                .map(|link| ManagedShareLink {
+                    views: strings.plural(&strings.detail_views, link.views),
+                    remaining: link.max_views.map(|max| {
+                        strings.plural(&strings.detail_remaining_views, (max - link.views).max(0))
+                    }),
+                    share_token: link.share_token,
+                    label: link.label,
+                })
+                .collect();
+            let body = ManageTemplate {
+                views: strings.plural(&strings.detail_views, item.views),
+                expires_in: format_duration(item.expires_at, now, &strings),
+                created: display_time(item.created_at, now, &config, &strings),
+                remaining_views,
+                can_publish: item.max_views.is_none()
+                    && item.destroy_after_first_view_secs.is_none()
+                    && !item.flagged,
+                can_renew: item.destroy_after_first_view_secs.is_none(),
+                scheduled: scheduled_label(item.visible_after, &config, &strings, now),
+                stats,
+                share_links,
+                item,
+                manage_token,
+                strings,
+            }
+            .render()?;
+            Html(body).into_response()
+        }
+        None => render_not_found(&state, strings).await?,
+    };
+    if let Some(cookie) = set_cookie {
+        response.headers_mut().insert(SET_COOKIE, cookie);
+    }
+    Ok(response)
+}
+
+/// The most hours `/m/{manage_token}/views` covers, counting back from now
+/// or the paste's deletion.
+const VIEW_TIMELINE_HOURS: i64 = 30 * 24;
+
+/// Views of a paste per hour since it was created, split into page views
+/// and raw fetches. Still answers for a paste that burned or was otherwise
+/// deleted, until the event log is pruned; `?hours=` shortens the range.
+pub async fn manage_views(
+    State(state): State<AppState>,
+    Path(manage_token): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Response {
+    let Some(manage_token) = normalize_manage_token(&manage_token) else {
+        return json_error(StatusCode::NOT_FOUND, "not_found");
+    };
+    let (token, created_at, deleted_at) =
+        match db::view_history_span(&state.pool, &manage_token).await {
+            Ok(Some(span)) => span,
+            Ok(None) => return json_error(StatusCode::NOT_FOUND, "not_found"),
+            Err(_) => return json_error(StatusCode::INTERNAL_SERVER_ERROR, "internal"),
+        };
+    let hours = params
+        .get("hours")
+        .and_then(|hours| hours.parse::<i64>().ok())
+        .unwrap_or(VIEW_TIMELINE_HOURS)
+        .clamp(1, VIEW_TIMELINE_HOURS);
+    let last = deleted_at.unwrap_or_else(|| state.clock.now()) / 3600;
+    let first = (created_at / 3600).max(last - hours + 1).min(last);
+    let counts = match db::views_by_hour(&state.pool, &token, first * 3600, (last + 1) * 3600).await
+    {
+        Ok(counts) => counts,
+        Err(_) => return json_error(StatusCode::INTERNAL_SERVER_ERROR, "internal"),
+    };
+    let hours: Vec<HourlyViews> = (first..=last)
+        .map(|hour| {
+            let (page, raw) = counts
+                .iter()
+                .find(|(counted, _, _)| *counted == hour)
+                .map_or((0, 0), |(_, page, raw)| (*page, *raw));
+            HourlyViews {
+                at: hour * 3600,
+                page,
+                raw,
+            }
+        })
+        .collect();
+    (
+        [(CACHE_CONTROL, "no-store")],
+        axum::Json(ViewTimeline {
+            token,
+            created_at,
+            deleted_at,
+            page_views: hours.iter().map(|hour| hour.page).sum(),
+            raw_views: hours.iter().map(|hour| hour.raw).sum(),
+            hours,
+        }),
+    )
+        .into_response()
+}
+
+pub async fn manage_delete(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ClientIp(ip): ClientIp,
+    Path(manage_token): Path<String>,
+) -> Result<Response, AppError> {
+    let Some(manage_token) = normalize_manage_token(&manage_token) else {
+        return invalid_token(&state, &headers);
+    };
+    let client = client_hash(&state.signing_key, ip);
+    let deleted =
+        db::delete_managed_paste(&state.pool, &manage_token, Some(&client), state.clock.now())
+            .await;
+    match deleted {
+        Ok(Some(token)) => {
+            // Views still waiting to be written must not go to a paste
+            // that takes the token next.
+            state.paste_cache.forget(&token);
+            Ok(Redirect::to("/").into_response())
+        }
+        Ok(None) => manage_not_found(&state, &headers).await,
+        Err(_) => Ok(StatusCode::INTERNAL_SERVER_ERROR.into_response()),
+    }
+}
+
+pub async fn manage_renew(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(manage_token): Path<String>,
+) -> Result<Response, AppError> {
+    let Some(manage_token) = normalize_manage_token(&manage_token) else {
+        return invalid_token(&state, &headers);
+    };
+    let renewed = db::renew_managed_paste(&state.pool, &manage_token, state.clock.now()).await;
+    state.paste_cache.clear();
+    match renewed {
+        Ok(Some(_)) => Ok(Redirect::to(&format!("/m/{}", manage_token)).into_response()),
+        Ok(None) => manage_not_found(&state, &headers).await,
+        Err(_) => Ok(StatusCode::INTERNAL_SERVER_ERROR.into_response()),
+    }
+}
+
+pub async fn manage_publish(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(manage_token): Path<String>,
+) -> Result<Response, AppError> {
+    set_managed_public(&state, &headers, &manage_token, true).await
+}
+
+pub async fn manage_unpublish(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(manage_token): Path<String>,
+) -> Result<Response, AppError> {
+    set_managed_public(&state, &headers, &manage_token, false).await
+}
+
+async fn set_managed_public(
+    state: &AppState,
+    headers: &HeaderMap,
+    manage_token: &str,
+    is_public: bool,
+) -> Result<Response, AppError> {
+    let Some(manage_token) = normalize_manage_token(manage_token) else {
+        return invalid_token(state, headers);
+    };
+    let manage_token = manage_token.as_str();
+    let changed =
+        db::set_managed_public(&state.pool, manage_token, is_public, state.clock.now()).await;
+    state.paste_cache.clear();
+    match changed {
+        Ok(true) => Ok(Redirect::to(&format!("/m/{}", manage_token)).into_response()),
+        Ok(false) => {
+            match db::fetch_managed_paste(&state.pool, manage_token, state.clock.now()).await {
+                // Burn-after-reading and reported pastes stay private.
+                Ok(Some(_)) => Ok((StatusCode::FORBIDDEN, "Not allowed").into_response()),
+                Ok(None) => manage_not_found(state, headers).await,
+                Err(_) => Ok(StatusCode::INTERNAL_SERVER_ERROR.into_response()),
+            }
+        }
+        Err(_) => Ok(StatusCode::INTERNAL_SERVER_ERROR.into_response()),
+    }
+}
+
+/// Swap the manage token for a new one and move to the new link; the old
+/// one stops working.
+pub async fn manage_regenerate(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(manage_token): Path<String>,
+) -> Result<Response, AppError> {
+    let Some(manage_token) = normalize_manage_token(&manage_token) else {
+        return invalid_token(&state, &headers);
+    };
+    match db::regenerate_manage_token(&state.pool, &manage_token, state.clock.now()).await {
+        Ok(Some(replacement)) => Ok(Redirect::to(&format!("/m/{}", replacement)).into_response()),
+        Ok(None) => manage_not_found(&state, &headers).await,
+        Err(_) => Ok(StatusCode::INTERNAL_SERVER_ERROR.into_response()),
+    }
+}
+
+async fn manage_not_found(state: &AppState, headers: &HeaderMap) -> Result<Response, AppError> {
+    let (lang, _) = select_language(headers, None);
+    let strings = state.i18n.load().strings(lang);
+    render_not_found(state, strings).await
+}
+
+/// The 404 page for a token that could never exist, rendered without
+/// asking the database anything.
+fn invalid_token(state: &AppState, headers: &HeaderMap) -> Result<Response, AppError> {
+    let (lang, _) = select_language(headers, None);
+    let strings = state.i18n.load().strings(lang);
+    not_found_page(state, strings, String::new())
+}
+
 pub async fn index(
     State(state): State<AppState>,
     headers: HeaderMap,
     Query(query): Query<IndexQuery>,
-) -> impl IntoResponse {
-    db::cleanup_expired(&state.pool).await;
-    db::enforce_size_limit(&state.pool, state.config.paste.max_pastes, 0).await;
-    
+) -> Result<Response, AppError> {
+    let config = state.config.load_full();
+    let now = state.clock.now();
+    cleanup_expired(&state).await;
+    enforce_size_limit(&state, 0).await;
+
     let mut params = HashMap::new();
     if let Some(lang) = query.lang.clone() {
         params.insert("lang".to_string(), lang);
     }
-    
-    let (lang, set_cookie) = select_language(&headers, &params);
-    let strings = state.i18n.strings(lang);
+
+    let (lang, set_cookie) = select_page_language(&headers, &params);
+    let strings = state.i18n.load().strings(lang);
 
     let mut fork_title = None;
     let mut fork_content = None;
@@ -87,13 +393,10 @@ pub async fn index(
     let mut fork_token_val = None;
 
     if let Some(token) = query.fork {
-        let item: Option<Paste> = sqlx::query_as(
-            "SELECT title, content, created_at, expires_at, language, views, max_views, is_public, original_duration FROM pastes WHERE token = ? AND expires_at > strftime('%s','now')"
-        )
-        .bind(&token)
-        .fetch_optional(&state.pool)
-        .await
-        .unwrap_or(None);
+        let item = db::fetch_paste(&state.pool, &token, state.cipher.as_ref(), now)
+            .await
+            .unwrap_or(None)
+            .filter(|p| !p.is_e2e);
 
         if let Some(p) = item {
             fork_title = Some(p.title);
@@ -103,9 +406,11 @@ pub async fn index(
         }
     }
 
-    let expires_options = build_expires_options(&state.config.paste, &strings);
-    let token_length_options = build_token_length_options(&state.config.paste, &strings);
-    let language_options = build_language_options(&strings, fork_language.as_deref());
+    let expires_options = build_expires_options(&config.paste, &strings);
+    let destroy_after_options = build_destroy_after_options(&config.paste, &strings);
+    let token_length_options = build_token_length_options(&config.paste, &strings);
+    let language_options =
+        build_language_options(&config.languages, &strings, fork_language.as_deref());
 
     let max_id: i64 = sqlx::query_scalar("SELECT MAX(id) FROM pastes")
         .fetch_one(&state.pool)
@@ -113,563 +418,6794 @@ pub async fn index(
         .unwrap_or(Some(0))
         .unwrap_or(0);
 
-    let total_pastes = strings.stat_total_pastes.replace("{}", &max_id.to_string());
+    let total_pastes = strings.plural(&strings.stat_total_pastes, max_id);
 
-    let public_count: i64 = sqlx::query_scalar(
-        "SELECT COUNT(*) FROM pastes WHERE is_public = 1 AND expires_at > strftime('%s','now')",
-    )
-    .fetch_one(&state.pool)
-    .await
-    .unwrap_or(0);
+    let public_count = state
+        .public_counts
+        .get(&state.pool, &PublicFilter::default(), now)
+        .await
+        .unwrap_or(0);
+
+    let (pastes, content_length) = db::usage(&state.pool).await.unwrap_or((0, 0));
+    let capacity = Capacity::new(pastes, content_length, &config.paste);
+    let capacity_warning = capacity.percent() >= config.paste.capacity_warning_percent;
+    let capacity = strings
+        .index_capacity
+        .replace("{}", &capacity.percent().min(100).to_string());
+
+    let offset = parse_utc_offset(&config.server.display_timezone).unwrap_or(0);
+    let activity = state
+        .activity
+        .get(&state.pool, SPARKLINE_DAYS, offset, now)
+        .await
+        .map(|activity| sparkline(&activity))
+        .unwrap_or_default();
+    let show_recent = config.paste.show_recent_on_index;
+    let recent = if show_recent {
+        recent_items(&state, &config, &strings).await
+    } else {
+        Vec::new()
+    };
+    let own_recent = own_recent(&state, &headers, &strings).await;
+    let featured = state
+        .featured
+        .get(&state.pool, state.cipher.as_ref(), offset, now)
+        .await
+        .unwrap_or(None)
+        .map(|paste| FeaturedPaste {
+            language_label: language_label(&config.languages, &strings, &paste.language),
+            token: paste.token,
+            title: paste.title,
+            preview: paste.preview,
+            truncated: paste.truncated,
+        });
 
     let body = IndexTemplate {
+        announcement: announcement(&state, &strings),
+        has_about: state.about.load().get(&strings.lang).is_some(),
         strings,
         expires_options,
+        destroy_after_options,
         token_length_options,
         language_options,
         total_pastes,
         public_count,
+        activity,
+        featured,
+        show_recent,
+        recent,
+        own_recent,
+        capacity,
+        capacity_warning,
+        read_only: config.server.read_only,
+        captcha: config.captcha.as_ref().map(CaptchaConfig::widget),
+        allow_author: config.paste.allow_author,
+        author: get_cookie(&headers, "author")
+            .map(|value| percent_decode_str(&value).decode_utf8_lossy().into_owned())
+            .and_then(|author| normalize_author(Some(author), &config.paste)),
         fork_title,
         fork_content,
         fork_token: fork_token_val,
     }
-    .render()
-    .unwrap();
+    .render()?;
 
     let mut response = Html(body).into_response();
     if let Some(cookie) = set_cookie {
         response.headers_mut().insert(SET_COOKIE, cookie);
     }
-    response
+    Ok(response)
 }
 
-pub async fn create_paste(
-    State(state): State<AppState>,
-    headers: HeaderMap,
-    Form(form): Form<PasteForm>,
-) -> impl IntoResponse {
-    db::cleanup_expired(&state.pool).await;
-    db::enforce_size_limit(&state.pool, state.config.paste.max_pastes, 1).await;
-    let params = HashMap::new();
-    let (lang, _) = select_language(&headers, &params);
-    let strings = state.i18n.strings(lang);
-    let content_length = form.content.chars().count();
-    if content_length > state.config.paste.max_content_length {
-        let message = strings
-            .content_too_long
-            .replace("{}", &state.config.paste.max_content_length.to_string());
-        return (StatusCode::BAD_REQUEST, Html(message)).into_response();
-    }
-    if (content_length as i64) > state.config.paste.max_total_content_length {
-        let message = strings.content_too_long.replace(
-            "{}",
-            &state.config.paste.max_total_content_length.to_string(),
-        );
-        return (StatusCode::BAD_REQUEST, Html(message)).into_response();
-    }
-    db::enforce_total_content_length(
-        &state.pool,
-        state.config.paste.max_total_content_length,
-        content_length as i64,
-    )
-    .await;
-    let expires_in = normalize_expires_in(form.expires_in, &state.config.paste);
-    let token_length = normalize_token_length(form.token_length, &state.config.paste);
-    let language = normalize_language(form.language);
-    let max_views = normalize_max_views(form.max_views.clone());
-    let is_public =
-        form.is_public.as_ref().map(|s| s == "on").unwrap_or(false) && max_views.is_none();
-    let expires_at = now_ts() + expires_in;
-    let title = normalize_title(form.title, &form.content);
-    let token = match db::insert_paste(
-        &state.pool,
-        title,
-        form.content,
-        expires_at,
-        expires_in,
-        token_length,
-        language.clone(),
-        max_views,
-        is_public,
-    )
-    .await
-    {
-        Ok(result) => result,
-        Err(_) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Html("Failed".to_string()),
-            )
-                .into_response();
-        }
-    };
-    let expires_in_text = format_duration(expires_at, &strings);
-
-    let language_label = build_language_options(&strings, Some(&language))
+/// The latest public pastes under the create form, newest first.
+async fn recent_items(state: &AppState, config: &AppConfig, strings: &Strings) -> Vec<RecentItem> {
+    let now = state.clock.now();
+    db::list_recent_public(&state.pool, config.paste.recent_on_index_count, now)
+        .await
+        .unwrap_or_default()
         .into_iter()
-        .find(|opt| opt.value == language)
-        .map(|opt| opt.label)
-        .unwrap_or_else(|| language.clone());
+        .map(|paste| RecentItem {
+            language_label: language_label(&config.languages, strings, &paste.language),
+            age: format_relative_time(paste.created_at, now, strings),
+            token: paste.token,
+            title: paste.title,
+        })
+        .collect()
+}
 
-    let max_id: i64 = sqlx::query_scalar("SELECT MAX(id) FROM pastes")
-        .fetch_one(&state.pool)
-        .await
-        .unwrap_or(Some(0))
-        .unwrap_or(0);
-    let total_pastes = strings.stat_total_pastes.replace("{}", &max_id.to_string());
+/// Pastes shown as "your recent moments".
+const OWN_RECENT_COUNT: i64 = 5;
 
-    let remaining_views = if let Some(max) = max_views {
-        Some(
-            strings
-                .detail_remaining_views
-                .replace("{}", &max.to_string()),
-        )
-    } else {
-        None
-    };
+/// The latest pastes the `owner` cookie created, or `None` when there is no
+/// cookie or nothing alive under it.
+async fn own_recent(
+    state: &AppState,
+    headers: &HeaderMap,
+    strings: &Strings,
+) -> Option<Vec<RecentPaste>> {
+    let owner_id = verify_owner_cookie(headers, &state.signing_key)?;
+    let pastes = db::list_owned_recent(&state.pool, &owner_id, OWN_RECENT_COUNT, state.clock.now())
+        .await
+        .unwrap_or_default();
+    let pastes: Vec<RecentPaste> = pastes
+        .into_iter()
+        .map(|(token, title, expires_at)| RecentPaste {
+            token,
+            title,
+            expires_in: format_duration(expires_at, state.clock.now(), strings),
+        })
+        .collect();
+    (!pastes.is_empty()).then_some(pastes)
+}
 
-    if headers.contains_key("hx-request") {
-        let body = ResultTemplate {
-            path: format!("/p/{}", token),
-            expires_in: expires_in_text,
-            strings,
-            language_label,
-            remaining_views,
-            total_pastes,
-        }
-        .render()
-        .unwrap();
-        Html(body).into_response()
-    } else {
-        Redirect::to(&format!("/p/{}", token)).into_response()
+/// `GET /api/v1/recent/mine`: the visitor's own latest pastes as an HTML
+/// fragment, which the index swaps in again after each htmx create.
+/// Empty for a visitor without any.
+#[utoipa::path(
+    get,
+    path = "/api/v1/recent/mine",
+    params(("lang" = Option<String>, Query, description = "`zh`, `en` or `ja`")),
+    responses(
+        (status = 200, description = "The pastes this `owner` cookie created", content_type = "text/html", body = String),
+    )
+)]
+pub async fn api_own_recent(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Response, AppError> {
+    let (lang, _) = select_page_language(&headers, &params);
+    let strings = state.i18n.load().strings(lang);
+    let own_recent = own_recent(&state, &headers, &strings).await;
+    let body = OwnRecentTemplate {
+        strings,
+        own_recent,
     }
+    .render()?;
+    Ok(([(CACHE_CONTROL, "no-store")], Html(body)).into_response())
 }
 
-pub async fn view_paste(
+/// `GET /api/v1/recent`: the index's latest list as an HTML fragment, for
+/// htmx to swap in. 404 when `show_recent_on_index` is off.
+#[utoipa::path(
+    get,
+    path = "/api/v1/recent",
+    params(("lang" = Option<String>, Query, description = "`zh`, `en` or `ja`")),
+    responses(
+        (status = 200, description = "The latest public pastes", content_type = "text/html", body = String),
+        (status = 404, description = "The list is turned off"),
+    )
+)]
+pub async fn api_recent(
     State(state): State<AppState>,
     headers: HeaderMap,
-    Path(token): Path<String>,
     Query(params): Query<HashMap<String, String>>,
-) -> impl IntoResponse {
-    db::cleanup_expired(&state.pool).await;
-    db::enforce_size_limit(&state.pool, state.config.paste.max_pastes, 0).await;
-    let (lang, set_cookie) = select_language(&headers, &params);
-    let strings = state.i18n.strings(lang);
-    let item: Option<Paste> = sqlx::query_as(
-        r#"
-        SELECT title, content, created_at, expires_at, language, views, max_views, is_public, original_duration
-        FROM pastes
-        WHERE token = ? AND expires_at > strftime('%s','now')
-        "#,
+) -> Result<Response, AppError> {
+    let config = state.config.load_full();
+    if !config.paste.show_recent_on_index {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    }
+    let (lang, _) = select_page_language(&headers, &params);
+    let strings = state.i18n.load().strings(lang);
+    let recent = recent_items(&state, &config, &strings).await;
+    Ok(Html(RecentTemplate { strings, recent }.render()?).into_response())
+}
+
+/// `GET /api/v1/slug-available?slug=...`: whether a custom slug could be
+/// had right now, by the same rules creating a paste applies. htmx gets the
+/// line the form shows next to the field instead.
+#[utoipa::path(
+    get,
+    path = "/api/v1/slug-available",
+    params(("slug" = String, Query, description = "e.g. `meeting-notes`")),
+    responses(
+        (status = 200, description = "Whether the slug is free, and why not", body = SlugAvailability),
+        (status = 429, description = "Too many checks from this client", body = ApiError),
     )
-    .bind(&token)
-    .fetch_optional(&state.pool)
-    .await
-    .unwrap_or(None);
+)]
+pub async fn api_slug_available(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Response, AppError> {
+    if !state.slug_limiter.check(ip, state.clock.now()) {
+        audit::refused(&state, ip, "slug_check", Refusal::RateLimit, None);
+        return Ok(json_error(
+            StatusCode::TOO_MANY_REQUESTS,
+            "too_many_requests",
+        ));
+    }
+    let raw = params.get("slug").map(String::as_str).unwrap_or_default();
+    let case_insensitive = state.config.load().paste.case_insensitive_tokens;
+    let result = match slug::validate_slug(raw, case_insensitive) {
+        Ok(slug) => match db::token_taken(&state.pool, &slug, case_insensitive).await {
+            Ok(true) => Err(SlugError::Taken),
+            Ok(false) => Ok(slug),
+            Err(_) => return Ok(json_error(StatusCode::INTERNAL_SERVER_ERROR, "internal")),
+        },
+        Err(err) => Err(err),
+    };
+    if !headers.contains_key("hx-request") {
+        return Ok(axum::Json(SlugAvailability {
+            available: result.is_ok(),
+            reason: result.err().map(|err| err.code().to_string()),
+        })
+        .into_response());
+    }
+    // The field was cleared: nothing to say.
+    if raw.trim().is_empty() {
+        return Ok(Html(String::new()).into_response());
+    }
+    let (lang, _) = select_page_language(&headers, &params);
+    let strings = state.i18n.load().strings(lang);
+    let body = SlugStatusTemplate {
+        available: result.is_ok(),
+        message: match result {
+            Ok(_) => strings.slug_available,
+            Err(err) => slug_message(err, &strings),
+        },
+    }
+    .render()?;
+    Ok(Html(body).into_response())
+}
 
-    if let Some(ref p) = item {
-        let new_views = p.views + 1;
-        sqlx::query("UPDATE pastes SET views = ? WHERE token = ?")
-            .bind(new_views)
-            .bind(&token)
-            .execute(&state.pool)
-            .await
-            .ok();
-        if let Some(max) = p.max_views {
-            if max > 0 && new_views >= max {
-                sqlx::query("DELETE FROM pastes WHERE token = ?")
-                    .bind(&token)
-                    .execute(&state.pool)
-                    .await
-                    .ok();
-            }
-        }
+/// Why a slug cannot be had, in words.
+fn slug_message(err: SlugError, strings: &Strings) -> String {
+    match err {
+        SlugError::Length => strings.slug_length.replace(
+            "{}",
+            &format!(
+                "{}-{}",
+                slug::SLUG_LENGTHS.start(),
+                slug::SLUG_LENGTHS.end()
+            ),
+        ),
+        SlugError::InvalidCharset => strings.slug_invalid_charset.clone(),
+        SlugError::Reserved => strings.slug_reserved.clone(),
+        SlugError::Taken => strings.slug_taken.clone(),
     }
+}
 
-    let response_body = match item {
-        Some(item) => {
-            let language_label = build_language_options(&strings, Some(&item.language))
-                .into_iter()
-                .find(|opt| opt.value == item.language)
-                .map(|opt| opt.label)
-                .unwrap_or_else(|| item.language.clone());
-
-            let remaining_views = if let Some(max) = item.max_views {
-                let remaining = (max - item.views - 1).max(0);
-                if remaining == 0 {
-                    Some(strings.detail_zero_views.clone())
-                } else {
-                    Some(
-                        strings
-                            .detail_remaining_views
-                            .replace("{}", &remaining.to_string()),
-                    )
-                }
-            } else {
-                None
-            };
+fn file_message(err: FileError, strings: &Strings) -> String {
+    match err {
+        FileError::TooMany => strings
+            .files_too_many
+            .replace("{}", &files::MAX_FILES.to_string()),
+        FileError::InvalidName => strings.filename_invalid.clone(),
+        FileError::DuplicateName => strings.filename_duplicate.clone(),
+        FileError::Encrypted => strings.files_e2e.clone(),
+    }
+}
 
-            let body = DetailTemplate {
-                item,
-                strings,
-                token,
-                language_label,
-                remaining_views,
+/// The files of a create request after the first, named, with contents
+/// normalized like the first and a language left blank worked out from
+/// the filename, then the content. Also returns the first file's name.
+fn checked_files(
+    config: &AppConfig,
+    form: &PasteForm,
+) -> Result<(Option<String>, Vec<NewFile>), FileError> {
+    let trim = is_checked(form.trim_trailing.as_deref());
+    let extra = form
+        .file_content
+        .iter()
+        .enumerate()
+        .map(|(index, content)| FileInput {
+            filename: form.file_name.get(index).cloned().unwrap_or_default(),
+            language: form
+                .file_language
+                .get(index)
+                .filter(|language| !language.trim().is_empty())
+                .cloned(),
+            content: normalize_content(content, trim),
+        })
+        .collect();
+    let (filename, extra) = files::name_files(form.filename.as_deref(), extra)?;
+    if is_checked(form.is_e2e.as_deref()) && !extra.is_empty() {
+        return Err(FileError::Encrypted);
+    }
+    let languages = &config.languages;
+    let files = extra
+        .into_iter()
+        .map(|file| {
+            let language = match file.language {
+                Some(language) => normalize_language(languages, Some(language)),
+                None => split_extension(&file.filename)
+                    .1
+                    .and_then(|extension| language_for_extension(languages, extension))
+                    .unwrap_or_else(|| "auto".to_string()),
+            };
+            NewFile {
+                filename: file.filename,
+                language,
+                content: file.content,
             }
-            .render()
-            .unwrap();
-            Html(body).into_response()
-        }
-        None => {
-            let max_id: i64 = sqlx::query_scalar("SELECT MAX(id) FROM pastes")
-                .fetch_one(&state.pool)
-                .await
-                .unwrap_or(Some(0))
-                .unwrap_or(0);
-            let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM pastes")
-                .fetch_one(&state.pool)
-                .await
-                .unwrap_or(0);
-            let faded = (max_id - count).max(0);
-            let faded_count = strings.stat_faded.replace("{}", &faded.to_string());
+        })
+        .collect();
+    Ok((filename, files))
+}
 
-            let body = NotFoundTemplate {
-                strings,
-                faded_count,
-            }
-            .render()
-            .unwrap();
-            (StatusCode::NOT_FOUND, Html(body)).into_response()
-        }
-    };
+pub async fn create_paste(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ClientIp(ip): ClientIp,
+    Form(form): Form<PasteForm>,
+) -> Result<Response, AppError> {
+    let json = wants_json(&headers);
+    store_paste(&state, &headers, ip, form, json).await
+}
 
-    let mut response = response_body;
-    if let Some(cookie) = set_cookie {
-        response.headers_mut().insert(SET_COOKIE, cookie);
+/// `POST /api/v1/paste`: create a paste from a JSON body. Answers in JSON
+/// whatever the `Accept` header says.
+#[utoipa::path(
+    post,
+    path = "/api/v1/paste",
+    request_body = ApiPasteForm,
+    responses(
+        (status = 201, description = "Created", body = CreatedPasteResponse,
+            headers(("Location" = String, description = "The new paste, `/p/{token}`"))),
+        (status = 400, description = "Invalid paste, or captcha failed", body = ApiError),
+        (status = 403, description = "Client is banned"),
+        (status = 409, description = "The slug is taken", body = ApiError),
+        (status = 413, description = "Content longer than `max_content_length`", body = ApiError),
+        (status = 500, description = "Storing the paste failed", body = ApiError),
+        (status = 503, description = "Server is read-only", body = ApiError),
+    )
+)]
+pub async fn api_create_paste(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ClientIp(ip): ClientIp,
+    form: Result<axum::Json<ApiPasteForm>, JsonRejection>,
+) -> Result<Response, AppError> {
+    match form {
+        Ok(axum::Json(form)) => store_paste(&state, &headers, ip, form.into(), true).await,
+        // `limit_paste_body` answers these with `content_too_long`.
+        Err(rejection) if rejection.status() == StatusCode::PAYLOAD_TOO_LARGE => {
+            Ok(rejection.into_response())
+        }
+        Err(_) => Ok(json_error(StatusCode::BAD_REQUEST, "bad_request")),
     }
-    response
 }
 
-pub async fn view_paste_raw(
+/// `POST /api/v1/paste/validate`: run the checks creating the paste would,
+/// without storing anything. `content` may be left out in favour of
+/// `content_length`, so a large paste need not be uploaded twice.
+#[utoipa::path(
+    post,
+    path = "/api/v1/paste/validate",
+    request_body = ApiValidateForm,
+    responses(
+        (status = 200, description = "Each check and whether it passed", body = PasteValidation),
+        (status = 400, description = "The body does not decode", body = ApiError),
+        (status = 413, description = "Content longer than `max_content_length`", body = ApiError),
+    )
+)]
+pub async fn api_validate_paste(
     State(state): State<AppState>,
-    Path(token): Path<String>,
-) -> impl IntoResponse {
-    db::cleanup_expired(&state.pool).await;
-    db::enforce_size_limit(&state.pool, state.config.paste.max_pastes, 0).await;
-    let item: Option<RawPaste> = sqlx::query_as(
-        r#"
-        SELECT content, views, max_views
-        FROM pastes
-        WHERE token = ? AND expires_at > strftime('%s','now')
-        "#,
-    )
-    .bind(&token)
-    .fetch_optional(&state.pool)
-    .await
-    .unwrap_or(None);
-
-    if let Some(ref p) = item {
-        let new_views = p.views + 1;
-        sqlx::query("UPDATE pastes SET views = ? WHERE token = ?")
-            .bind(new_views)
-            .bind(&token)
-            .execute(&state.pool)
-            .await
-            .ok();
-        if let Some(max) = p.max_views {
-            if max > 0 && new_views >= max {
-                sqlx::query("DELETE FROM pastes WHERE token = ?")
-                    .bind(&token)
-                    .execute(&state.pool)
-                    .await
-                    .ok();
-            }
+    headers: HeaderMap,
+    body: Result<axum::Json<serde_json::Value>, JsonRejection>,
+) -> Result<Response, AppError> {
+    let mut body = match body {
+        Ok(axum::Json(body)) => body,
+        Err(rejection) if rejection.status() == StatusCode::PAYLOAD_TOO_LARGE => {
+            return Ok(rejection.into_response());
         }
+        Err(_) => return Ok(json_error(StatusCode::BAD_REQUEST, "bad_request")),
+    };
+    let has_content = body.get("content").is_some();
+    if let Some(fields) = body.as_object_mut() {
+        fields.entry("content").or_insert("".into());
     }
+    let Ok(form) = serde_json::from_value::<ApiValidateForm>(body) else {
+        return Ok(json_error(StatusCode::BAD_REQUEST, "bad_request"));
+    };
+    let mut paste = PasteForm::from(form.paste);
+    let content_length = if has_content {
+        prepare_content(&mut paste).chars().count()
+    } else {
+        form.content_length.unwrap_or(0)
+    };
 
-    match item {
-        Some(item) => {
-            let mut headers = HeaderMap::new();
-            headers.insert(
-                CONTENT_TYPE,
-                HeaderValue::from_static("text/plain; charset=utf-8"),
-            );
-            let filename = format!("paste-{}.txt", token);
-            let disposition = format!("inline; filename=\"{}\"", filename);
-            headers.insert(
-                CONTENT_DISPOSITION,
-                HeaderValue::from_str(&disposition).unwrap(),
-            );
-            (headers, item.content).into_response()
-        }
-        None => StatusCode::NOT_FOUND.into_response(),
-    }
+    let config = state.config.load_full();
+    let (lang, _) = select_language(&headers, None);
+    let strings = state.i18n.load().strings(lang);
+    let now = state.clock.now();
+    let (checked, rejections) =
+        validate_paste_request(&state, &config, &strings, &paste, content_length, now).await;
+    let checks = PASTE_CHECKS
+        .iter()
+        .map(|check| {
+            let rejection = rejections
+                .iter()
+                .find(|rejection| rejection.check == *check);
+            ValidationCheck {
+                check: check.to_string(),
+                ok: rejection.is_none(),
+                error: rejection.map(|rejection| rejection.code.clone()),
+                message: rejection.map(|rejection| rejection.message.clone()),
+            }
+        })
+        .collect();
+    Ok(axum::Json(PasteValidation {
+        valid: rejections.is_empty(),
+        checks,
+        language: checked.language,
+        expires_at: now + checked.expires_in,
+        slug: checked.slug,
+        is_public: checked.is_public,
+    })
+    .into_response())
 }
 
-pub async fn explore(
-    State(state): State<AppState>,
-    headers: HeaderMap,
-    Query(params): Query<HashMap<String, String>>,
-) -> impl IntoResponse {
-    db::cleanup_expired(&state.pool).await;
-    let (lang, set_cookie) = select_language(&headers, &params);
-    let strings = state.i18n.strings(lang);
-
-    let pastes: Vec<PublicPaste> = sqlx::query_as(
-        r#"
-        SELECT token, title, content, created_at, expires_at, language, original_duration
-        FROM pastes
-        WHERE is_public = 1 AND max_views IS NULL AND expires_at > strftime('%s','now')
-        ORDER BY created_at DESC
-        LIMIT 100
-        "#,
-    )
-    .fetch_all(&state.pool)
-    .await
-    .unwrap_or_default();
-
-    let total = pastes.len() as i64;
+/// A refused paste: `{"error": code, "message": ...}` for JSON clients,
+/// the bare message for the form and htmx.
+fn create_error(json: bool, status: StatusCode, code: &str, message: String) -> Response {
+    if json {
+        let body = ApiError {
+            error: code.to_string(),
+            message: Some(message),
+            request_id: None,
+        };
+        (status, axum::Json(body)).into_response()
+    } else {
+        (status, Html(message)).into_response()
+    }
+}
 
-    let max_expires_secs = state
-        .config
-        .paste
-        .expires_options_secs
-        .iter()
-        .copied()
-        .max()
-        .unwrap_or(86400 * 7);
+/// `path` as a full URL: under `base_url` when one is configured, else
+/// under the host the request was made to.
+fn public_url(config: &ServerConfig, headers: &HeaderMap, path: &str) -> String {
+    if config.base_url.is_some() {
+        return absolute_url(config, path);
+    }
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+    match header("host") {
+        Some(host) => format!(
+            "{}://{}{}",
+            header("x-forwarded-proto").unwrap_or("http"),
+            host,
+            path
+        ),
+        None => path.to_string(),
+    }
+}
 
-    let body = ExploreTemplate {
-        strings,
-        pastes,
-        total,
-        now_ts: now_ts(),
-        max_expires_secs,
+/// `path` prefixed with `base_url` when one is configured.
+fn absolute_url(config: &ServerConfig, path: &str) -> String {
+    match &config.base_url {
+        Some(base_url) => format!("{}{}", base_url.trim_end_matches('/'), path),
+        None => path.to_string(),
     }
-    .render()
-    .unwrap();
+}
 
-    let mut response = Html(body).into_response();
-    if let Some(cookie) = set_cookie {
-        response.headers_mut().insert(SET_COOKIE, cookie);
+/// Take the content out of `form` as it will be stored. Ciphertext must be
+/// kept byte for byte; anything else has its line endings normalized.
+fn prepare_content(form: &mut PasteForm) -> String {
+    if is_checked(form.is_e2e.as_deref()) {
+        std::mem::take(&mut form.content)
+    } else {
+        normalize_content(&form.content, is_checked(form.trim_trailing.as_deref()))
     }
-    response
 }
 
-pub async fn api_explore(
-    State(state): State<AppState>,
-    Query(query): Query<ExploreQuery>,
-) -> impl IntoResponse {
-    db::cleanup_expired(&state.pool).await;
-    let offset = query.offset.unwrap_or(0);
+/// The checks `validate_paste_request` makes, in order, by the name the
+/// dry run reports them under.
+pub const PASTE_CHECKS: [&str; 6] = [
+    "content_length",
+    "files",
+    "expires_at",
+    "slug",
+    "visible_after",
+    "capacity",
+];
 
-    let paste: Option<PublicPaste> = sqlx::query_as(
-        r#"
-        SELECT token, title, content, created_at, expires_at, language, original_duration
-        FROM pastes
-        WHERE is_public = 1 AND max_views IS NULL AND expires_at > strftime('%s','now')
-        ORDER BY created_at DESC
-        LIMIT 1 OFFSET ?
-        "#,
-    )
-    .bind(offset)
-    .fetch_optional(&state.pool)
-    .await
-    .unwrap_or(None);
+/// A create request as it would be stored.
+#[derive(Debug, PartialEq)]
+pub struct CheckedPaste {
+    pub expires_in: i64,
+    pub token_length: usize,
+    pub slug: Option<String>,
+    /// Unknown languages fall back to `auto` rather than being refused.
+    pub language: String,
+    pub max_views: Option<i64>,
+    pub destroy_after: Option<i64>,
+    pub is_public: bool,
+    pub visible_after: Option<i64>,
+    /// The first file's name and the files after it.
+    pub filename: Option<String>,
+    pub files: Vec<NewFile>,
+}
 
-    let total: i64 = sqlx::query_scalar(
-        "SELECT COUNT(*) FROM pastes WHERE is_public = 1 AND max_views IS NULL AND expires_at > strftime('%s','now')"
-    )
-    .fetch_one(&state.pool)
-    .await
-    .unwrap_or(0);
+/// A rule a create request breaks, and how creating it is refused.
+#[derive(Debug, PartialEq)]
+pub struct PasteRejection {
+    /// One of `PASTE_CHECKS`.
+    pub check: &'static str,
+    pub status: StatusCode,
+    pub code: String,
+    pub message: String,
+}
 
-    match paste {
-        Some(p) => {
-            let json = serde_json::json!({
-                "token": p.token,
-                "title": p.title,
-                "content": p.content,
-                "created_at": p.created_at,
-                "expires_at": p.expires_at,
-                "language": p.language,
-                "index": offset,
-                "total": total
-            });
-            axum::Json(json).into_response()
+/// Every check creating a paste of `content_length` characters makes
+/// before it is stored, bar the captcha, without changing anything.
+/// Returns the paste as it would be stored and each rule it breaks, in
+/// `PASTE_CHECKS` order; creating it answers with the first.
+pub async fn validate_paste_request(
+    state: &AppState,
+    config: &AppConfig,
+    strings: &Strings,
+    form: &PasteForm,
+    content_length: usize,
+    now: i64,
+) -> (CheckedPaste, Vec<PasteRejection>) {
+    let mut rejections = Vec::new();
+    let mut reject = |check, status, code: &str, message| {
+        rejections.push(PasteRejection {
+            check,
+            status,
+            code: code.to_string(),
+            message,
+        })
+    };
+    let content_too_long = |max: i64| strings.content_too_long.replace("{}", &max.to_string());
+    // Size limits apply to all the files together.
+    let files = checked_files(config, form);
+    let content_length = content_length
+        + files.as_ref().map_or(0, |(_, files)| {
+            files.iter().map(|file| file.content.chars().count()).sum()
+        });
+    if content_length > config.paste.max_content_length {
+        reject(
+            "content_length",
+            StatusCode::BAD_REQUEST,
+            "content_too_long",
+            content_too_long(config.paste.max_content_length as i64),
+        );
+    }
+    let (filename, files) = files.unwrap_or_else(|err| {
+        reject(
+            "files",
+            StatusCode::BAD_REQUEST,
+            err.code(),
+            file_message(err, strings),
+        );
+        (None, Vec::new())
+    });
+    // An absolute expiry wins over the relative choice; renewals then give
+    // the same span again.
+    let expires_in = match form.expires_at_abs.as_deref().map(str::trim) {
+        Some(value) if !value.is_empty() => match resolve_expires_at_abs(value, now, &config.paste)
+        {
+            Some(expires_at) => expires_at - now,
+            None => {
+                reject(
+                    "expires_at",
+                    StatusCode::BAD_REQUEST,
+                    "expires_at_invalid",
+                    strings.expires_at_invalid.clone(),
+                );
+                normalize_expires_in(form.expires_in, &config.paste)
+            }
+        },
+        _ => normalize_expires_in(form.expires_in, &config.paste),
+    };
+    let slug = match form.slug.as_deref().map(str::trim) {
+        Some(raw) if !raw.is_empty() => {
+            let taken = |slug: String| async {
+                match db::token_taken(&state.pool, &slug, config.paste.case_insensitive_tokens)
+                    .await
+                {
+                    Ok(true) => Err(SlugError::Taken),
+                    // Storing it would still catch a slug taken meanwhile.
+                    _ => Ok(slug),
+                }
+            };
+            let result = match slug::validate_slug(raw, config.paste.case_insensitive_tokens) {
+                Ok(slug) => taken(slug).await,
+                Err(err) => Err(err),
+            };
+            match result {
+                Ok(slug) => Some(slug),
+                Err(err) => {
+                    let status = if err == SlugError::Taken {
+                        StatusCode::CONFLICT
+                    } else {
+                        StatusCode::BAD_REQUEST
+                    };
+                    reject(
+                        "slug",
+                        status,
+                        &format!("slug_{}", err.code()),
+                        slug_message(err, strings),
+                    );
+                    None
+                }
+            }
         }
-        None => StatusCode::NOT_FOUND.into_response(),
+        _ => None,
+    };
+    let is_e2e = is_checked(form.is_e2e.as_deref());
+    // The server cannot see inside client-side encrypted content, so it
+    // does not highlight it.
+    let language = if is_e2e {
+        "plaintext".to_string()
+    } else {
+        normalize_language(&config.languages, form.language.clone())
+    };
+    let max_views = normalize_max_views(form.max_views.clone(), &config.paste);
+    let destroy_after =
+        normalize_destroy_after(form.destroy_after_first_view_secs.clone(), &config.paste);
+    let is_public = form.is_public.as_ref().map(|s| s == "on").unwrap_or(false)
+        && max_views.is_none()
+        && destroy_after.is_none();
+    let visible_after = normalize_visible_after(form.visible_after.clone(), &config.server, now);
+    if visible_after.is_some_and(|ts| ts >= now + expires_in) {
+        reject(
+            "visible_after",
+            StatusCode::BAD_REQUEST,
+            "visible_after_invalid",
+            strings.visible_after_invalid.clone(),
+        );
     }
+    // Eviction makes room for anything smaller than the whole instance.
+    if content_length as i64 > config.paste.max_total_content_length {
+        reject(
+            "capacity",
+            StatusCode::BAD_REQUEST,
+            "content_too_long",
+            content_too_long(config.paste.max_total_content_length),
+        );
+    }
+    let checked = CheckedPaste {
+        expires_in,
+        token_length: normalize_token_length(form.token_length, &config.paste),
+        slug,
+        language,
+        max_views,
+        destroy_after,
+        is_public,
+        visible_after,
+        filename,
+        files,
+    };
+    (checked, rejections)
 }
 
-// Helper functions moved from main.rs
-
-pub fn select_language(
+/// Validate and store a paste, then answer the way the client asked: 201
+/// with `Location` and a JSON body for `json` clients, the result fragment
+/// with `HX-Push-Url` for htmx, and a 303 to the paste for a plain form.
+async fn store_paste(
+    state: &AppState,
     headers: &HeaderMap,
-    params: &HashMap<String, String>,
-) -> (Lang, Option<HeaderValue>) {
-    if let Some(lang) = params.get("lang") {
-        match lang.as_str() {
-            "zh" => {
-                return (
-                    Lang::Zh,
-                    Some(HeaderValue::from_static(
-                        "lang=zh; Path=/; Max-Age=31536000",
-                    )),
-                );
-            }
-            "en" => {
-                return (
-                    Lang::En,
-                    Some(HeaderValue::from_static(
-                        "lang=en; Path=/; Max-Age=31536000",
-                    )),
-                );
+    ip: IpAddr,
+    mut form: PasteForm,
+    json: bool,
+) -> Result<Response, AppError> {
+    let state = state.clone();
+    let config = state.config.load_full();
+    cleanup_expired(&state).await;
+    let (lang, _) = select_language(headers, None);
+    let strings = state.i18n.load().strings(lang);
+    if let (Some(verifier), Some(captcha)) = (&state.captcha, &config.captcha) {
+        let response = form
+            .turnstile_response
+            .as_deref()
+            .or(form.hcaptcha_response.as_deref());
+        if !captcha::check(verifier.as_ref(), response, captcha.fail_open).await {
+            audit::refused(&state, ip, "create", Refusal::Captcha, None);
+            return Ok(create_error(
+                json,
+                StatusCode::BAD_REQUEST,
+                "captcha_failed",
+                strings.captcha_failed,
+            ));
+        }
+    }
+    let is_e2e = is_checked(form.is_e2e.as_deref());
+    let content = prepare_content(&mut form);
+    let content_length = content.chars().count();
+    let now = state.clock.now();
+    let client = client_hash(&state.signing_key, ip);
+    let (checked, rejections) =
+        validate_paste_request(&state, &config, &strings, &form, content_length, now).await;
+    if let Some(rejection) = rejections.into_iter().next() {
+        if rejection.code == "content_too_long" {
+            let size = SubmittedSize::of(&content, content_length, &checked.files);
+            audit::refused(&state, ip, "create", Refusal::Size, Some(size.bytes));
+            if !json {
+                let limit = if rejection.check == "capacity" {
+                    config.paste.max_total_content_length
+                } else {
+                    config.paste.max_content_length as i64
+                };
+                return too_long_response(&state, headers, strings, rejection.status, size, limit);
             }
-            _ => {}
         }
+        return Ok(create_error(
+            json,
+            rejection.status,
+            &rejection.code,
+            rejection.message,
+        ));
     }
-
-    if let Some(cookie) = headers.get(COOKIE).and_then(|v| v.to_str().ok()) {
-        for part in cookie.split(';') {
-            let part = part.trim();
-            if part.starts_with("lang=") {
-                match part.strip_prefix("lang=") {
-                    Some("zh") => return (Lang::Zh, None),
-                    Some("en") => return (Lang::En, None),
-                    _ => {}
+    let CheckedPaste {
+        expires_in,
+        token_length,
+        slug,
+        language,
+        max_views,
+        destroy_after,
+        is_public,
+        visible_after,
+        filename,
+        files,
+    } = checked;
+    let expires_at = now + expires_in;
+    // Creators are recognised by a signed cookie so they can later mint
+    // share links for their own pastes.
+    let known_owner = verify_owner_cookie(headers, &state.signing_key);
+    let (owner_id, owner_cookie) = match known_owner.clone() {
+        Some(id) => (id, None),
+        None => {
+            let id = generate_token(OWNER_ID_LENGTH);
+            let cookie = owner_cookie(&state.signing_key, &config.server, headers, &id);
+            (id, cookie)
+        }
+    };
+    let title = if is_e2e {
+        normalize_e2e_title(form.title)
+    } else {
+        normalize_title(form.title, &content, &language)
+    };
+    let author = normalize_author(form.author, &config.paste);
+    // Remembered so the form offers the same nickname next time.
+    let author_cookie = author.as_ref().and_then(|author| {
+        let value = utf8_percent_encode(author, NON_ALPHANUMERIC);
+        HeaderValue::from_str(&format!(
+            "author={}; Path=/; Max-Age=31536000; SameSite=Lax",
+            value
+        ))
+        .ok()
+    });
+    let forked_from = match form
+        .forked_from
+        .as_deref()
+        .and_then(|token| normalize_token(token, &config.paste))
+    {
+        Some(token) => Some(canonical_token(&state, token).await),
+        None => None,
+    };
+    // Kept for the too-long card, should storing find it over the limit.
+    let submitted = SubmittedSize::of(&content, content_length, &files);
+    let new_paste = NewPaste {
+        title,
+        content,
+        expires_at,
+        original_duration: expires_in,
+        token_length,
+        slug,
+        language: language.clone(),
+        max_views,
+        is_public,
+        is_e2e,
+        owner_id: Some(owner_id.clone()),
+        destroy_after_first_view_secs: destroy_after,
+        visible_after,
+        author,
+        forked_from,
+        case_insensitive_token: config.paste.case_insensitive_tokens,
+        filename,
+        files,
+        client_hash: Some(client),
+    };
+    // An owner retrying in a loop gets the paste they already have rather
+    // than one copy per attempt. Only a known owner can repeat, so the
+    // manage link never goes to anyone else.
+    let window = config.paste.repeat_window_secs;
+    if window > 0
+        && known_owner.is_some()
+        && let Ok(Some(repeat)) = db::find_repeat(&state.pool, &new_paste, now - window, now).await
+    {
+        return created_response(&state, &config, headers, strings, json, repeat, true).await;
+    }
+    let policy = config.paste.eviction_policy;
+    let created = match db::create_paste_checked(
+        &state.pool,
+        new_paste,
+        state.cipher.as_ref(),
+        config.paste.max_pastes,
+        config.paste.max_total_content_length,
+        policy,
+        now,
+    )
+    .await
+    {
+        Ok((created, evicted)) => {
+            for sweep in evicted {
+                for token in &sweep.tokens {
+                    state.paste_cache.forget(token);
                 }
+                state.metrics.record_policy_evictions(policy, sweep.deleted);
+                state.maintenance.record(now, sweep);
             }
+            created
+        }
+        Err(db::CreateError::TooLarge) if !json => {
+            let bytes = Some(submitted.bytes);
+            audit::refused(&state, ip, "create", Refusal::Size, bytes);
+            let size = submitted;
+            let limit = config.paste.max_total_content_length;
+            let status = StatusCode::BAD_REQUEST;
+            return too_long_response(&state, headers, strings, status, size, limit);
         }
+        Err(db::CreateError::TooLarge) => {
+            let bytes = Some(submitted.bytes);
+            audit::refused(&state, ip, "create", Refusal::Size, bytes);
+            let message = strings
+                .content_too_long
+                .replace("{}", &config.paste.max_total_content_length.to_string());
+            return Ok(create_error(
+                json,
+                StatusCode::BAD_REQUEST,
+                "content_too_long",
+                message,
+            ));
+        }
+        Err(db::CreateError::SlugTaken) => {
+            return Ok(create_error(
+                json,
+                StatusCode::CONFLICT,
+                "slug_taken",
+                strings.slug_taken,
+            ));
+        }
+        Err(db::CreateError::Database(err)) => {
+            eprintln!("Failed to create paste: {}", err);
+            return Ok(create_error(
+                json,
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "internal",
+                "Failed".to_string(),
+            ));
+        }
+    };
+    let submitted = SubmittedPaste {
+        token: created.token,
+        manage_token: created.manage_token,
+        expires_at,
+        language,
+        max_views,
+    };
+    let mut response =
+        created_response(&state, &config, headers, strings, json, submitted, false).await?;
+    for cookie in [owner_cookie, author_cookie].into_iter().flatten() {
+        response.headers_mut().append(SET_COOKIE, cookie);
     }
+    Ok(response)
+}
 
-    let is_zh = headers
-        .get("accept-language")
-        .and_then(|value| value.to_str().ok())
-        .map(|value| value.to_lowercase().contains("zh"))
-        .unwrap_or(true);
-    if is_zh {
-        (Lang::Zh, None)
-    } else {
-        (Lang::En, None)
+/// What creating `paste` answers with: its token and management link as
+/// JSON, the result card for htmx, or a redirect to it. A `reused` paste,
+/// handed back for a repeated submission, is answered with 200 rather
+/// than 201 and says so.
+async fn created_response(
+    state: &AppState,
+    config: &AppConfig,
+    headers: &HeaderMap,
+    strings: Strings,
+    json: bool,
+    paste: SubmittedPaste,
+    reused: bool,
+) -> Result<Response, AppError> {
+    let now = state.clock.now();
+    let path = format!("/p/{}", paste.token);
+    if json {
+        let manage_path = format!("/m/{}", paste.manage_token);
+        let body = CreatedPasteResponse {
+            url: absolute_url(&config.server, &path),
+            manage_url: absolute_url(&config.server, &manage_path),
+            token: paste.token,
+            manage_token: paste.manage_token,
+            expires_at: paste.expires_at,
+            reused,
+        };
+        let status = if reused {
+            StatusCode::OK
+        } else {
+            StatusCode::CREATED
+        };
+        let mut response = (status, axum::Json(body)).into_response();
+        if let Ok(location) = HeaderValue::from_str(&absolute_url(&config.server, &path)) {
+            response.headers_mut().insert(LOCATION, location);
+        }
+        return Ok(response);
     }
-}
+    let expires_in_text = format_duration(paste.expires_at, now, &strings);
 
-pub fn build_expires_options(config: &PasteConfig, strings: &Strings) -> Vec<ExpiresOption> {
-    config
-        .expires_options_secs
-        .iter()
-        .map(|value| ExpiresOption {
-            value: *value,
-            label: format_expires_label(*value, strings),
-            selected: *value == config.default_expires_secs,
-        })
-        .collect()
-}
+    let language_label = language_label(&config.languages, &strings, &paste.language);
 
-pub fn build_token_length_options(config: &PasteConfig, strings: &Strings) -> Vec<TokenLengthOption> {
-    config
-        .token_lengths
-        .iter()
-        .map(|value| TokenLengthOption {
-            value: *value,
-            label: strings.token_length_label.replace("{}", &value.to_string()),
-            selected: *value == config.default_token_length,
-        })
-        .collect()
+    let max_id: i64 = sqlx::query_scalar("SELECT MAX(id) FROM pastes")
+        .fetch_one(&state.pool)
+        .await
+        .unwrap_or(Some(0))
+        .unwrap_or(0);
+    let total_pastes = strings.plural(&strings.stat_total_pastes, max_id);
+
+    let remaining_views = paste
+        .max_views
+        .map(|max| strings.plural(&strings.detail_remaining_views, max));
+
+    let response = if headers.contains_key("hx-request") {
+        let body = ResultTemplate {
+            path: path.clone(),
+            manage_path: format!("/m/{}", paste.manage_token),
+            expires_in: expires_in_text,
+            strings,
+            language_label,
+            remaining_views,
+            total_pastes,
+            qr_svg: config
+                .paste
+                .show_qr
+                .then(|| qr::svg(&public_url(&config.server, headers, &path)))
+                .flatten(),
+            reused,
+        }
+        .render()?;
+        let mut response = Html(body).into_response();
+        if let Ok(path) = HeaderValue::from_str(&path) {
+            response.headers_mut().insert("hx-push-url", path);
+        }
+        // Refreshes "your recent moments" on the index.
+        response
+            .headers_mut()
+            .insert("HX-Trigger", HeaderValue::from_static("pasteCreated"));
+        response
+    } else {
+        Redirect::to(&path).into_response()
+    };
+    Ok(response)
 }
 
-pub fn build_language_options(strings: &Strings, selected_lang: Option<&str>) -> Vec<LanguageOption> {
-    let target = selected_lang.unwrap_or("auto");
-    vec![
-        LanguageOption { value: "auto".to_string(), label: strings.language_auto.clone(), selected: target == "auto" },
-        LanguageOption { value: "plaintext".to_string(), label: strings.language_plaintext.clone(), selected: target == "plaintext" },
-        LanguageOption { value: "rust".to_string(), label: strings.language_rust.clone(), selected: target == "rust" },
-        LanguageOption { value: "python".to_string(), label: strings.language_python.clone(), selected: target == "python" },
-        LanguageOption { value: "javascript".to_string(), label: strings.language_javascript.clone(), selected: target == "javascript" },
-        LanguageOption { value: "typescript".to_string(), label: strings.language_typescript.clone(), selected: target == "typescript" },
-        LanguageOption { value: "go".to_string(), label: strings.language_go.clone(), selected: target == "go" },
-        LanguageOption { value: "java".to_string(), label: strings.language_java.clone(), selected: target == "java" },
-        LanguageOption { value: "cpp".to_string(), label: strings.language_cpp.clone(), selected: target == "cpp" },
-        LanguageOption { value: "html".to_string(), label: strings.language_html.clone(), selected: target == "html" },
-        LanguageOption { value: "css".to_string(), label: strings.language_css.clone(), selected: target == "css" },
-        LanguageOption { value: "json".to_string(), label: strings.language_json.clone(), selected: target == "json" },
-        LanguageOption { value: "yaml".to_string(), label: strings.language_yaml.clone(), selected: target == "yaml" },
-        LanguageOption { value: "sql".to_string(), label: strings.language_sql.clone(), selected: target == "sql" },
-        LanguageOption { value: "bash".to_string(), label: strings.language_bash.clone(), selected: target == "bash" },
-    ]
+pub async fn view_paste(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ClientIp(ip): ClientIp,
+    PasteToken(token): PasteToken,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Response, AppError> {
+    let (lang, set_cookie) = select_page_language(&headers, &params);
+    let strings = state.i18n.load().strings(lang);
+    let (token, extension) = split_extension(&token);
+    let Some(token) = normalize_token(token, &state.config.load().paste) else {
+        return not_found_page(&state, strings, String::new());
+    };
+    let (item, owner_preview, receipt) = open_paste(&state, &headers, ip, &token, |_| true).await;
+
+    let validators = item
+        .as_ref()
+        .map(|item| page_validators(item, &token, owner_preview, state.clock.now(), &strings));
+    let mut response = match item {
+        Some(_)
+            if validators
+                .as_ref()
+                .is_some_and(|v| etag_matches(&headers, v)) =>
+        {
+            StatusCode::NOT_MODIFIED.into_response()
+        }
+        Some(mut item) => {
+            // `/p/{token}.rs` highlights as Rust whatever was stored.
+            if let Some(language) = extension.and_then(|extension| {
+                language_for_extension(&state.config.load().languages, extension)
+            }) && !item.is_e2e
+            {
+                item.language = language;
+                item.detected_language = None;
+            }
+            // The owner also sees how the views spread over the days.
+            let daily_views = if owner_preview {
+                Some(
+                    db::list_daily_views(&state.pool, &token)
+                        .await
+                        .unwrap_or_default(),
+                )
+            } else {
+                None
+            };
+            let neighbors = db::paste_neighbors(&state.pool, &token, state.clock.now())
+                .await
+                .unwrap_or(None);
+            forget_dead_parent(&state, &mut item).await;
+            // Only pastes explore lists take reactions.
+            let reactions = if item.is_listed(state.clock.now()) {
+                let counts = db::reaction_counts(&state.pool, &[&token])
+                    .await
+                    .unwrap_or_default();
+                Some(reactions_from(
+                    counts.get(&token).map_or(&[][..], Vec::as_slice),
+                    reacted_mask(&headers, &state.signing_key, &token),
+                ))
+            } else {
+                None
+            };
+            let extras = DetailExtras {
+                daily_views,
+                neighbors,
+                reactions,
+                // `?view=code` keeps a prose paste in the code box.
+                force_code: params.get("view").map(String::as_str) == Some("code"),
+                shared: false,
+            };
+            render_detail(&state, item, strings, token, extras)?
+        }
+        None => render_not_found(&state, strings).await?,
+    };
+    response
+        .headers_mut()
+        .extend(validators.unwrap_or_default());
+    if let Some(cookie) = set_cookie {
+        response.headers_mut().insert(SET_COOKIE, cookie);
+    }
+    if let Some(cookie) = receipt {
+        response.headers_mut().append(SET_COOKIE, cookie);
+    }
+    Ok(response)
 }
 
-pub fn format_expires_label(secs: i64, strings: &Strings) -> String {
-    if secs >= 86400 && secs % 86400 == 0 {
-        let days = secs / 86400;
-        if days == 1 { strings.expires_days_one.clone() } else { strings.expires_days_many.replace("{}", &days.to_string()) }
-    } else if secs >= 3600 && secs % 3600 == 0 {
-        let hours = secs / 3600;
-        if hours == 1 { strings.expires_hours_one.clone() } else { strings.expires_hours_many.replace("{}", &hours.to_string()) }
-    } else if secs >= 60 && secs % 60 == 0 {
-        let minutes = secs / 60;
-        if minutes == 1 { strings.expires_minutes_one.clone() } else { strings.expires_minutes_many.replace("{}", &minutes.to_string()) }
+/// `HEAD /p/{token}`: the status and headers `GET` would answer with, 304
+/// included, from the paste's metadata alone. The page is not rendered,
+/// so there is no `Content-Length`, and no view is counted; the `ETag` is
+/// the one the page would get with the `GET`'s own view counted.
+pub async fn head_paste(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    PasteToken(token): PasteToken,
+    Query(params): Query<HashMap<String, String>>,
+) -> Response {
+    let (token, _) = split_extension(&token);
+    let Some(token) = normalize_token(token, &state.config.load().paste) else {
+        return (StatusCode::NOT_FOUND, Html("")).into_response();
+    };
+    let now = state.clock.now();
+    let Some(meta) = db::fetch_scheduled_paste_meta(&state.pool, &token, now)
+        .await
+        .unwrap_or(None)
+    else {
+        return (StatusCode::NOT_FOUND, Html("")).into_response();
+    };
+    let owner_preview = is_owner(&headers, &state.signing_key, meta.owner_id.as_deref());
+    // Until it goes live, a scheduled paste exists only for its owner.
+    if !owner_preview && meta.visible_after.is_some_and(|ts| ts > now) {
+        return (StatusCode::NOT_FOUND, Html("")).into_response();
+    }
+    let burns = meta.max_views.is_some() || meta.destroy_after_first_view_secs.is_some();
+    let mut validators = HeaderMap::new();
+    // As `page_validators` has it.
+    if owner_preview || burns {
+        expiry_header(&mut validators, meta.expires_at);
     } else {
-        if secs == 1 { strings.expires_seconds_one.clone() } else { strings.expires_seconds_many.replace("{}", &secs.to_string()) }
+        let (lang, _) = select_page_language(&headers, &params);
+        let strings = state.i18n.load().strings(lang);
+        let version = DetailVersion {
+            token: &token,
+            content_hash: meta.content_hash.as_deref(),
+            views: meta.views + 1,
+            is_public: meta.is_public,
+            created_at: meta.created_at,
+            expires_at: meta.expires_at,
+        };
+        validators = detail_validators(&version, now, &strings);
     }
+    let status = if etag_matches(&headers, &validators) {
+        StatusCode::NOT_MODIFIED
+    } else {
+        StatusCode::OK
+    };
+    (status, validators, Html("")).into_response()
 }
 
-pub fn normalize_expires_in(expires_in: Option<i64>, config: &PasteConfig) -> i64 {
-    let value = expires_in.unwrap_or(config.default_expires_secs);
-    if config.expires_options_secs.contains(&value) { value } else { config.default_expires_secs }
+/// The validators of the detail page of `paste`. Burn-on-read and
+/// self-destructing pastes, and the creator's preview with its exact
+/// counts, are always answered in full and get no `ETag`.
+fn page_validators(
+    paste: &Paste,
+    token: &str,
+    owner_preview: bool,
+    now: i64,
+    strings: &Strings,
+) -> HeaderMap {
+    let burns = paste.burns();
+    if owner_preview || burns {
+        let mut headers = HeaderMap::new();
+        expiry_header(&mut headers, paste.expires_at);
+        return headers;
+    }
+    detail_validators(&DetailVersion::of(paste, token), now, strings)
 }
 
-pub fn normalize_token_length(token_length: Option<usize>, config: &PasteConfig) -> usize {
-    let value = token_length.unwrap_or(config.default_token_length);
-    if config.token_lengths.contains(&value) { value } else { config.default_token_length }
+/// What the detail page shows a visitor that can change, for its `ETag`.
+struct DetailVersion<'a> {
+    token: &'a str,
+    content_hash: Option<&'a str>,
+    /// Counting the view being answered.
+    views: i64,
+    is_public: bool,
+    created_at: i64,
+    expires_at: i64,
 }
 
-pub fn normalize_language(language: Option<String>) -> String {
-    let value = language.unwrap_or_else(|| "auto".to_string()).to_lowercase();
-    if is_allowed_language(&value) { value } else { "auto".to_string() }
+impl<'a> DetailVersion<'a> {
+    fn of(paste: &'a Paste, token: &'a str) -> Self {
+        DetailVersion {
+            token,
+            content_hash: paste.content_hash.as_deref(),
+            views: paste.views,
+            is_public: paste.is_public,
+            created_at: paste.created_at,
+            expires_at: paste.expires_at,
+        }
+    }
 }
 
-pub fn is_allowed_language(value: &str) -> bool {
-    matches!(value, "auto" | "plaintext" | "rust" | "python" | "javascript" | "typescript" | "go" | "java" | "cpp" | "html" | "css" | "json" | "yaml" | "sql" | "bash")
+/// A weak `ETag` over the page as shown in `strings`' locale (the view
+/// counter in its buckets, the relative times) and `X-Mayfile-Expires-At`,
+/// so going back to the page gets 304 until something on it moves.
+fn detail_validators(version: &DetailVersion, now: i64, strings: &Strings) -> HeaderMap {
+    let fingerprint = [
+        version.token,
+        version.content_hash.unwrap_or_default(),
+        &shown_views(version.views, strings),
+        &strings.lang,
+        if version.is_public {
+            "public"
+        } else {
+            "private"
+        },
+        &version.expires_at.to_string(),
+        &format_duration(version.expires_at, now, strings),
+        &format_relative_time(version.created_at, now, strings),
+    ]
+    .join("\n");
+    let mut headers = HeaderMap::new();
+    let etag = format!("W/\"{}\"", &sha256_hex(&fingerprint)[..32]);
+    headers.insert(
+        ETAG,
+        HeaderValue::from_str(&etag).expect("hex is a valid header"),
+    );
+    expiry_header(&mut headers, version.expires_at);
+    headers
 }
 
-pub fn normalize_title(title: Option<String>, content: &str) -> String {
-    let trimmed = title.unwrap_or_default().trim().to_string();
-    if !trimmed.is_empty() { return trimmed; }
-    let first_line = content.lines().next().unwrap_or("").trim();
-    if first_line.is_empty() { "Untitled".to_string() } else { first_line.chars().take(80).collect() }
+/// The view counter as visitors see it: exact below ten, then rounded
+/// down to one significant digit ("40+ views"), so that a view or two
+/// rarely changes the page.
+fn shown_views(views: i64, strings: &Strings) -> String {
+    if views < 10 {
+        return strings.plural(&strings.detail_views, views);
+    }
+    let step = 10_i64.pow(views.ilog10());
+    strings.plural_at_least(&strings.detail_views, views / step * step)
 }
 
-pub fn normalize_max_views(max_views: Option<String>) -> Option<i64> {
-    max_views.and_then(|s| s.parse::<i64>().ok()).filter(|&v| v > 0)
+/// Whether the request's `If-None-Match` names the `ETag` in `validators`,
+/// compared weakly as `GET` allows.
+fn etag_matches(headers: &HeaderMap, validators: &HeaderMap) -> bool {
+    let (Some(wanted), Some(etag)) = (
+        headers.get(IF_NONE_MATCH).and_then(|v| v.to_str().ok()),
+        validators.get(ETAG).and_then(|v| v.to_str().ok()),
+    ) else {
+        return false;
+    };
+    let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    wanted
+        .split(',')
+        .any(|tag| tag.trim() == "*" || opaque(tag) == opaque(etag))
 }
 
-pub fn format_duration(expires_at: i64, strings: &Strings) -> String {
-    let remaining = expires_at - now_ts();
-    if remaining <= 0 { return strings.duration_expired.clone(); }
-    if remaining < 60 { return strings.duration_seconds.replace("{}", &remaining.to_string()); }
-    if remaining < 3600 { return strings.duration_minutes.replace("{}", &(remaining / 60).to_string()); }
-    if remaining < 86400 { return strings.duration_hours.replace("{}", &(remaining / 3600).to_string()); }
-    strings.duration_days.replace("{}", &(remaining / 86400).to_string())
-}
+/// Load `token` for a page that shows its content, counting a view the
+/// way the detail page does. Its creator (the signed `owner` cookie) looks
+/// without spending one, and may see it before it goes live. A paste
+/// `shown` turns away is treated as missing and keeps its views. Also
+/// returns whether this is the creator's preview and the receipt cookie
+/// that lets a following raw view ride on this one.
+async fn open_paste(
+    state: &AppState,
+    headers: &HeaderMap,
+    ip: IpAddr,
+    token: &str,
+    shown: fn(&Paste) -> bool,
+) -> (Option<Paste>, bool, Option<HeaderValue>) {
+    let now = state.clock.now();
+    let mut item = load_paste(state, token, now).await.filter(shown);
 
-#[cfg(test)]
+    // The creator may look at their own paste without spending its views.
+    let owner_preview = item
+        .as_ref()
+        .is_some_and(|p| is_owner(headers, &state.signing_key, p.owner_id.as_deref()));
+    // Until it goes live, a scheduled paste exists only for its owner.
+    if !owner_preview {
+        item = item.filter(|p| !p.is_scheduled(now));
+    }
+    let mut receipt = None;
+    let mut unpaid = false;
+    if let Some(ref mut p) = item
+        && !owner_preview
+    {
+        let config = state.config.load();
+        let client = client_hash(&state.signing_key, ip);
+        if batches_views(state, p, now) {
+            let view = PendingView {
+                client_hash: client.clone(),
+                raw: false,
+                at: now,
+            };
+            state.paste_cache.count_view(token, view);
+            p.views += 1;
+        } else {
+            let rules = view_rules(&config);
+            match db::consume_view(&state.pool, token, &rules, Some(&client), false, now).await {
+                Ok(Some(count)) => {
+                    p.views = count.views;
+                    p.expires_at = count.expires_at;
+                    p.auto_renewals = count.auto_renewals;
+                    p.first_viewed_at = count.first_viewed_at;
+                }
+                // Another reader may have spent the last view since the
+                // paste was loaded, so one that burns is not shown unpaid.
+                _ => unpaid = p.burns(),
+            }
+        }
+        // Let the raw view that usually follows ride on this one: once,
+        // from this client, and soon.
+        if p.max_views.is_some() && !unpaid && !config.paste.raw_counts_as_view {
+            let exp = (now + VIEW_RECEIPT_SECS).min(p.expires_at);
+            let nonce = generate_token(VIEW_RECEIPT_NONCE_LENGTH);
+            let cookie = format!(
+                "viewed={}; Path=/r/{}; Max-Age={}; HttpOnly; SameSite=Lax",
+                view_receipt(&state.signing_key, token, &client, &nonce, exp),
+                token,
+                (exp - now).max(0)
+            );
+            receipt = HeaderValue::from_str(&cookie).ok();
+        }
+    }
+    if unpaid {
+        return (None, false, None);
+    }
+    (item, owner_preview, receipt)
+}
+
+/// How long a view receipt from the page lets a raw read skip counting.
+const VIEW_RECEIPT_SECS: i64 = 60;
+const VIEW_RECEIPT_NONCE_LENGTH: usize = 16;
+
+/// `token` from the paste cache while `[paste.cache]` is on, loaded and
+/// kept on a miss if it qualifies. A hit touches the database not at all;
+/// a miss first sweeps expired pastes and applies `max_pastes`, as every
+/// lookup used to. Scheduled pastes are included.
+async fn load_paste(state: &AppState, token: &str, now: i64) -> Option<Paste> {
+    let config = state.config.load();
+    if config.paste.cache.is_some()
+        && let Some(paste) = state.paste_cache.get(token, now)
+    {
+        return Some(paste);
+    }
+    cleanup_expired(state).await;
+    enforce_size_limit(state, 0).await;
+    let paste = db::fetch_paste(&state.pool, token, state.cipher.as_ref(), now)
+        .await
+        .unwrap_or(None)?;
+    if let Some(cache) = &config.paste.cache
+        && PasteCache::admits(&paste, now)
+    {
+        state.paste_cache.insert(token, &paste, cache);
+    }
+    Some(paste)
+}
+
+/// Whether a view of `paste` is counted in memory and written with the
+/// next batch rather than at once.
+fn batches_views(state: &AppState, paste: &Paste, now: i64) -> bool {
+    state.config.load().paste.cache.is_some() && PasteCache::admits(paste, now)
+}
+
+/// `GET /p/{token}/print`: the title, a line of metadata and the content
+/// with line numbers, for paper. No scripts, so the numbers are written
+/// out here. Counts a view like the detail page; client-side encrypted
+/// pastes cannot be shown without a script and are a 404.
+pub async fn view_paste_print(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ClientIp(ip): ClientIp,
+    PasteToken(token): PasteToken,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Response, AppError> {
+    let (lang, set_cookie) = select_page_language(&headers, &params);
+    let strings = state.i18n.load().strings(lang);
+    let Some(token) = normalize_token(&token, &state.config.load().paste) else {
+        return not_found_page(&state, strings, String::new());
+    };
+    let (item, _, receipt) = open_paste(&state, &headers, ip, &token, |p| !p.is_e2e).await;
+    let Some(mut item) = item else {
+        return render_not_found(&state, strings).await;
+    };
+    let config = state.config.load_full();
+    let languages = &config.languages;
+    let language = match &item.detected_language {
+        Some(detected) if item.language == "auto" => detected.clone(),
+        _ => item.language.clone(),
+    };
+    let mut files = vec![PrintFile::new(
+        item.filename.take(),
+        language_label(languages, &strings, &language),
+        &item.content,
+    )];
+    for file in std::mem::take(&mut item.files) {
+        let label = language_label(languages, &strings, &file.language);
+        files.push(PrintFile::new(Some(file.filename), label, &file.content));
+    }
+    let offset = parse_utc_offset(&config.server.display_timezone).unwrap_or(0);
+    let body = PrintTemplate {
+        created: format_iso8601(item.created_at, offset),
+        expires: format_iso8601(item.expires_at, offset),
+        views: strings.plural(&strings.detail_views, item.views),
+        byline: byline(item.author.as_deref(), &config.paste, &strings),
+        title: item.title,
+        files,
+        strings,
+    }
+    .render()?;
+    let mut response = Html(body).into_response();
+    for cookie in [set_cookie, receipt].into_iter().flatten() {
+        response.headers_mut().append(SET_COOKIE, cookie);
+    }
+    Ok(response)
+}
+
+/// `HEAD /p/{token}/print`: whether `GET` would find the page, from the
+/// paste's metadata alone. Nothing is rendered and no view is counted.
+pub async fn head_paste_print(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    PasteToken(token): PasteToken,
+) -> Response {
+    let Some(token) = normalize_token(&token, &state.config.load().paste) else {
+        return (StatusCode::NOT_FOUND, Html("")).into_response();
+    };
+    let now = state.clock.now();
+    let found = db::fetch_scheduled_paste_meta(&state.pool, &token, now)
+        .await
+        .unwrap_or(None)
+        .filter(|meta| !meta.is_e2e)
+        .is_some_and(|meta| {
+            meta.visible_after.is_none_or(|ts| ts <= now)
+                || is_owner(&headers, &state.signing_key, meta.owner_id.as_deref())
+        });
+    let status = if found {
+        StatusCode::OK
+    } else {
+        StatusCode::NOT_FOUND
+    };
+    (status, Html("")).into_response()
+}
+
+/// `GET /diff/{old}/{new}`: what changed from one paste to another, line
+/// by line, and `/diff/{old}/{new}.patch` for the unified diff as
+/// `text/x-diff`. Looking never spends a view.
+pub async fn view_diff(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path((old, new)): Path<(String, String)>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Response, AppError> {
+    let (lang, set_cookie) = select_page_language(&headers, &params);
+    let strings = state.i18n.load().strings(lang);
+    let (new, patch) = match new.strip_suffix(".patch") {
+        Some(new) => (new.to_string(), true),
+        None => (new, false),
+    };
+    let config = state.config.load_full();
+    let load = async |raw: &str| {
+        let token = normalize_token(raw, &config.paste)?;
+        let token = canonical_token(&state, token).await;
+        let paste = db::fetch_paste(
+            &state.pool,
+            &token,
+            state.cipher.as_ref(),
+            state.clock.now(),
+        )
+        .await
+        .unwrap_or(None)
+        .filter(|p| may_diff(p, &headers, &state.signing_key, state.clock.now()))?;
+        Some((token, paste))
+    };
+    let (Some((old_token, old_paste)), Some((new_token, new_paste))) =
+        (load(&old).await, load(&new).await)
+    else {
+        if patch {
+            return Ok(raw_not_found(&state, &headers, &params, false));
+        }
+        return not_found_page(&state, strings, String::new());
+    };
+
+    let size = old_paste.content.chars().count() + new_paste.content.chars().count();
+    if size > config.paste.max_diff_chars {
+        let message = strings
+            .diff_too_large
+            .replace("{}", &config.paste.max_diff_chars.to_string());
+        if patch {
+            return Ok((
+                StatusCode::UNPROCESSABLE_ENTITY,
+                [(CONTENT_TYPE, "text/plain; charset=utf-8")],
+                message,
+            )
+                .into_response());
+        }
+        return Ok(error_page(
+            &state,
+            strings,
+            StatusCode::UNPROCESSABLE_ENTITY,
+            message,
+        ));
+    }
+
+    if patch {
+        let body = diff::unified(
+            &old_paste.content,
+            &new_paste.content,
+            &format!("a/{}", old_token),
+            &format!("b/{}", new_token),
+        );
+        return Ok(([(CONTENT_TYPE, "text/x-diff; charset=utf-8")], body).into_response());
+    }
+    let hunks = diff::hunks(&old_paste.content, &new_paste.content);
+    let count = |kind: &str| {
+        hunks
+            .iter()
+            .flat_map(|hunk| &hunk.lines)
+            .filter(|line| line.kind == kind)
+            .count()
+    };
+    let body = DiffTemplate {
+        announcement: announcement(&state, &strings),
+        added: count("added"),
+        removed: count("removed"),
+        strings,
+        old_token,
+        old_title: old_paste.title,
+        new_token,
+        new_title: new_paste.title,
+        hunks,
+    }
+    .render()?;
+    let mut response = Html(body).into_response();
+    if let Some(cookie) = set_cookie {
+        response.headers_mut().insert(SET_COOKIE, cookie);
+    }
+    Ok(response)
+}
+
+/// Drop `forked_from` once the original is gone, so the page does not link
+/// a diff that cannot be shown.
+async fn forget_dead_parent(state: &AppState, item: &mut Paste) {
+    if let Some(parent) = &item.forked_from
+        && !db::is_alive(&state.pool, parent, state.clock.now())
+            .await
+            .unwrap_or(false)
+    {
+        item.forked_from = None;
+    }
+}
+
+/// Whether a paste may be diffed: it is public and live, or the requester
+/// created it. Sealed pastes are never readable here.
+fn may_diff(paste: &Paste, headers: &HeaderMap, key: &[u8], now: i64) -> bool {
+    !paste.is_e2e
+        && (paste.is_public && !paste.is_scheduled(now)
+            || is_owner(headers, key, paste.owner_id.as_deref()))
+}
+
+/// Mint a time-limited signed link to a paste. Only its creator may ask.
+pub async fn share_paste(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    PasteToken(token): PasteToken,
+    Query(query): Query<ShareQuery>,
+) -> impl IntoResponse {
+    if let Err(response) = require_owner(&state, &headers, &token).await {
+        return response;
+    }
+
+    let max_ttl = max_expires_secs(&state.config.load().paste);
+    let ttl = query.ttl.unwrap_or(3600).clamp(1, max_ttl);
+    let exp = state.clock.now() + ttl;
+    let sig = sign_share_link(&state.signing_key, &token, exp);
+    axum::Json(serde_json::json!({
+        "url": format!("/s/{}?exp={}&sig={}", token, exp, sig),
+        "expires_at": exp,
+    }))
+    .into_response()
+}
+
+/// Render a paste reached through a signed link or a named share link. A
+/// signed link grants access but does not count as a view; a named link
+/// spends its own budget and the page never reveals the paste's token,
+/// which would get around it. The paste's own expiry still applies.
+pub async fn view_shared_paste(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    PasteToken(token): PasteToken,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Response, AppError> {
+    let now = state.clock.now();
+    let (lang, set_cookie) = select_page_language(&headers, &params);
+    let strings = state.i18n.load().strings(lang);
+
+    // `/s/` serves both signed links (`?exp=..&sig=..` on a paste token) and
+    // named share links, which carry their own token.
+    let shared = !params.contains_key("sig");
+    let paste_token = if !shared {
+        let exp = params.get("exp").and_then(|exp| exp.parse::<i64>().ok());
+        match (exp, params.get("sig")) {
+            (Some(exp), Some(sig))
+                if verify_share_link(&state.signing_key, &token, exp, sig, now) =>
+            {
+                Some(token)
+            }
+            _ => None,
+        }
+    } else {
+        db::consume_share_link(&state.pool, &token, now)
+            .await
+            .unwrap_or(None)
+    };
+    let item = match &paste_token {
+        Some(paste_token) => db::fetch_paste(&state.pool, paste_token, state.cipher.as_ref(), now)
+            .await
+            .unwrap_or(None)
+            .filter(|p| !p.is_scheduled(now)),
+        None => None,
+    };
+
+    let mut response = match item {
+        Some(mut item) => {
+            forget_dead_parent(&state, &mut item).await;
+            let extras = DetailExtras {
+                shared,
+                ..DetailExtras::default()
+            };
+            render_detail(
+                &state,
+                item,
+                strings,
+                paste_token.unwrap_or_default(),
+                extras,
+            )?
+        }
+        None => render_not_found(&state, strings).await?,
+    };
+    if let Some(cookie) = set_cookie {
+        response.headers_mut().insert(SET_COOKIE, cookie);
+    }
+    Ok(response)
+}
+
+/// `HEAD /s/{token}`: whether `GET` would show the paste. A named link is
+/// looked up without spending its budget.
+pub async fn head_shared_paste(
+    State(state): State<AppState>,
+    PasteToken(token): PasteToken,
+    Query(params): Query<HashMap<String, String>>,
+) -> Response {
+    let now = state.clock.now();
+    let paste_token = match params.get("sig") {
+        Some(sig) => params
+            .get("exp")
+            .and_then(|exp| exp.parse::<i64>().ok())
+            .filter(|exp| verify_share_link(&state.signing_key, &token, *exp, sig, now))
+            .map(|_| token),
+        None => db::share_link_target(&state.pool, &token, now)
+            .await
+            .unwrap_or(None),
+    };
+    let found = match paste_token {
+        Some(paste_token) => db::fetch_paste_meta(&state.pool, &paste_token, now)
+            .await
+            .unwrap_or(None)
+            .is_some(),
+        None => false,
+    };
+    let status = if found {
+        StatusCode::OK
+    } else {
+        StatusCode::NOT_FOUND
+    };
+    (status, Html("")).into_response()
+}
+
+/// Create a named share link with its own view budget.
+pub async fn create_share_link(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    PasteToken(token): PasteToken,
+    axum::Json(form): axum::Json<ShareLinkForm>,
+) -> impl IntoResponse {
+    if let Err(response) = require_owner(&state, &headers, &token).await {
+        return response;
+    }
+    let label: String = form
+        .label
+        .unwrap_or_default()
+        .trim()
+        .chars()
+        .take(64)
+        .collect();
+    let max_views = form.max_views.filter(|max| *max > 0);
+    match db::insert_share_link(&state.pool, &token, &label, max_views, state.clock.now()).await {
+        Ok(Some(link)) => (StatusCode::CREATED, axum::Json(link)).into_response(),
+        Ok(None) => json_error(StatusCode::NOT_FOUND, "not_found"),
+        Err(_) => json_error(StatusCode::INTERNAL_SERVER_ERROR, "internal"),
+    }
+}
+
+/// List a paste's share links with their view counts.
+pub async fn list_share_links(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    PasteToken(token): PasteToken,
+) -> impl IntoResponse {
+    if let Err(response) = require_owner(&state, &headers, &token).await {
+        return response;
+    }
+    match db::list_share_links(&state.pool, &token).await {
+        Ok(links) => axum::Json(links).into_response(),
+        Err(_) => json_error(StatusCode::INTERNAL_SERVER_ERROR, "internal"),
+    }
+}
+
+pub async fn revoke_share_link(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path((token, share_token)): Path<(String, String)>,
+) -> impl IntoResponse {
+    let token = canonical_token(&state, token).await;
+    if let Err(response) = require_owner(&state, &headers, &token).await {
+        return response;
+    }
+    match db::delete_share_link(&state.pool, &token, &share_token).await {
+        Ok(true) => StatusCode::NO_CONTENT.into_response(),
+        Ok(false) => json_error(StatusCode::NOT_FOUND, "not_found"),
+        Err(_) => json_error(StatusCode::INTERNAL_SERVER_ERROR, "internal"),
+    }
+}
+
+/// Link a paste to others as related, replacing the links it had, and
+/// answer with the new list. For its creator, by the `owner` cookie or the
+/// paste's manage token. Burn-on-read pastes can neither link nor be
+/// linked to, so a link never hints at a view budget.
+pub async fn set_related_links(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    PasteToken(token): PasteToken,
+    axum::Json(form): axum::Json<RelatedLinksForm>,
+) -> impl IntoResponse {
+    let config = state.config.load_full();
+    let now = state.clock.now();
+    let Some(token) = normalize_token(&token, &config.paste) else {
+        return json_error(StatusCode::NOT_FOUND, "not_found");
+    };
+    let source: Option<(Option<String>, Option<String>, bool)> = sqlx::query_as(
+        r#"
+        SELECT owner_id, manage_token, max_views IS NOT NULL FROM pastes
+        WHERE token = ? AND expires_at > ?
+        "#,
+    )
+    .bind(&token)
+    .bind(now)
+    .fetch_optional(&state.pool)
+    .await
+    .unwrap_or(None);
+    let Some((owner_id, manage_token, burn_on_read)) = source else {
+        return json_error(StatusCode::NOT_FOUND, "not_found");
+    };
+    let manager = form.manage_token.is_some() && form.manage_token == manage_token;
+    if !manager && !is_owner(&headers, &state.signing_key, owner_id.as_deref()) {
+        return json_error(StatusCode::FORBIDDEN, "forbidden");
+    }
+    if burn_on_read {
+        return json_error(StatusCode::BAD_REQUEST, "burn_on_read");
+    }
+    let mut targets: Vec<String> = Vec::new();
+    for raw in &form.tokens {
+        let Some(target) = normalize_token(raw, &config.paste) else {
+            return json_error(StatusCode::BAD_REQUEST, "related_not_found");
+        };
+        let target = canonical_token(&state, target).await;
+        if target == token {
+            return json_error(StatusCode::BAD_REQUEST, "related_not_found");
+        }
+        if !targets.contains(&target) {
+            targets.push(target);
+        }
+    }
+    if targets.len() > config.paste.max_related_links {
+        return json_error(StatusCode::BAD_REQUEST, "too_many_related");
+    }
+    for target in &targets {
+        match db::link_target(&state.pool, target, now).await {
+            Ok(Some(false)) => {}
+            Ok(Some(true)) => return json_error(StatusCode::BAD_REQUEST, "burn_on_read"),
+            Ok(None) => return json_error(StatusCode::BAD_REQUEST, "related_not_found"),
+            Err(_) => return json_error(StatusCode::INTERNAL_SERVER_ERROR, "internal"),
+        }
+    }
+    if db::set_related_links(&state.pool, &token, &targets)
+        .await
+        .is_err()
+    {
+        return json_error(StatusCode::INTERNAL_SERVER_ERROR, "internal");
+    }
+    state.paste_cache.invalidate(&token);
+    match db::related_links(&state.pool, &token, now).await {
+        Ok(links) => axum::Json(links).into_response(),
+        Err(_) => json_error(StatusCode::INTERNAL_SERVER_ERROR, "internal"),
+    }
+}
+
+/// Check that the request comes from the creator of an alive paste,
+/// producing the JSON error to return otherwise.
+async fn require_owner(state: &AppState, headers: &HeaderMap, token: &str) -> Result<(), Response> {
+    let owner_id: Option<Option<String>> =
+        sqlx::query_scalar("SELECT owner_id FROM pastes WHERE token = ? AND expires_at > ?")
+            .bind(token)
+            .bind(state.clock.now())
+            .fetch_optional(&state.pool)
+            .await
+            .unwrap_or(None);
+    match owner_id {
+        None => Err(json_error(StatusCode::NOT_FOUND, "not_found")),
+        Some(owner_id) if !is_owner(headers, &state.signing_key, owner_id.as_deref()) => {
+            Err(json_error(StatusCode::FORBIDDEN, "forbidden"))
+        }
+        Some(_) => Ok(()),
+    }
+}
+
+fn json_error(status: StatusCode, code: &str) -> Response {
+    let body = ApiError {
+        error: code.to_string(),
+        message: None,
+        request_id: None,
+    };
+    (status, axum::Json(body)).into_response()
+}
+
+/// Render the detail page. `item.views` must already include the current
+/// view if it was counted.
+/// What the detail page shows beyond the paste itself when it is opened
+/// at `/p/{token}` rather than through a share link.
+#[derive(Default)]
+struct DetailExtras {
+    /// Per-day views, for the owner's preview only.
+    daily_views: Option<Vec<DailyViews>>,
+    neighbors: Option<Neighbors>,
+    reactions: Option<Vec<Reaction>>,
+    /// Keep a paste that reads as prose in the code box.
+    force_code: bool,
+    /// Opened through a named share link, whose budget the paste token
+    /// would get around.
+    shared: bool,
+}
+
+fn render_detail(
+    state: &AppState,
+    mut item: Paste,
+    strings: Strings,
+    token: String,
+    extras: DetailExtras,
+) -> Result<Response, AppError> {
+    let now = state.clock.now();
+    let DetailExtras {
+        daily_views,
+        neighbors,
+        reactions,
+        force_code,
+        shared,
+    } = extras;
+    let config = &state.config.load_full();
+    let languages = &config.languages;
+    let stats = paste_stats(&item).map(|stats| stats_label(stats, &strings));
+    let files = std::mem::take(&mut item.files)
+        .into_iter()
+        .map(|file| DetailFile {
+            language_label: language_label(languages, &strings, &file.language),
+            highlight_language: file.language,
+            filename: file.filename,
+            content: file.content,
+        })
+        .collect();
+    let syntax_error = item
+        .syntax_error
+        .take()
+        .filter(|_| item.syntax_valid == Some(false))
+        .map(|message| {
+            let checked = match &item.detected_language {
+                Some(detected) if item.language == "auto" => detected,
+                _ => &item.language,
+            };
+            let language = language_label(languages, &strings, checked);
+            let label = match item.syntax_error_line {
+                Some(line) => strings
+                    .syntax_invalid_at
+                    .replacen("{}", &language, 1)
+                    .replacen("{}", &line.to_string(), 1),
+                None => strings.syntax_invalid.replace("{}", &language),
+            };
+            (label, message)
+        });
+    let label = language_label(languages, &strings, &item.language);
+    let (language_label, highlight_language) = match &item.detected_language {
+        Some(detected) if item.language == "auto" => (
+            format!(
+                "{} ({})",
+                label,
+                language_label(languages, &strings, detected)
+            ),
+            detected.clone(),
+        ),
+        _ => (label, item.language.clone()),
+    };
+    let prose_fits = !item.is_e2e && detect::reads_as_prose(&highlight_language, &item.content);
+
+    let remaining_views = if let Some(max) = item.max_views {
+        let remaining = (max - item.views).max(0);
+        if remaining == 0 {
+            Some(strings.detail_zero_views.clone())
+        } else {
+            Some(strings.plural(&strings.detail_remaining_views, remaining))
+        }
+    } else {
+        None
+    };
+
+    let self_destruct = match (item.destroy_after_first_view_secs, item.first_viewed_at) {
+        (Some(_), Some(_)) => Some(
+            strings
+                .detail_destroys_in
+                .replace("{}", &format_duration(item.expires_at, now, &strings)),
+        ),
+        (Some(secs), None) => Some(
+            strings
+                .detail_destroy_after
+                .replace("{}", &format_expires_label(secs, &strings)),
+        ),
+        _ => None,
+    };
+
+    let scheduled = scheduled_label(item.visible_after, config, &strings, now);
+    let offset = parse_utc_offset(&config.server.display_timezone).unwrap_or(0);
+    let expires = format_iso8601(item.expires_at, offset);
+    let renewal = renewal(
+        item.is_public && item.max_views.is_none() && item.destroy_after_first_view_secs.is_none(),
+        item.original_duration,
+        item.expires_at,
+        now,
+    );
+    // The creator sees the exact count.
+    let views = if daily_views.is_some() {
+        strings.plural(&strings.detail_views, item.views)
+    } else {
+        shown_views(item.views, &strings)
+    };
+    let created = display_time(item.created_at, now, config, &strings);
+    let byline = byline(item.author.as_deref(), &config.paste, &strings);
+    let announcement = announcement(state, &strings);
+    let has_about = state.about.load().get(&strings.lang).is_some();
+    let body = DetailTemplate {
+        item,
+        strings,
+        token: if shared { String::new() } else { token },
+        shared,
+        language_label,
+        highlight_language,
+        remaining_views,
+        self_destruct,
+        scheduled,
+        expires,
+        renewal,
+        views,
+        created,
+        owner_preview: daily_views.is_some(),
+        daily_views: daily_views.unwrap_or_default(),
+        neighbors,
+        byline,
+        reactions,
+        files,
+        stats,
+        syntax_error,
+        prose_mode: prose_fits && !force_code,
+        prose_fits,
+        announcement,
+        has_about,
+    }
+    .render()?;
+    Ok(Html(body).into_response())
+}
+
+/// Counts over the paste's own content and its further files; `None` for
+/// a client-side encrypted paste, whose content is ciphertext here.
+fn paste_stats(paste: &Paste) -> Option<ContentStats> {
+    if paste.is_e2e {
+        return None;
+    }
+    let stats = std::iter::once(paste.content.as_str())
+        .chain(paste.files.iter().map(|file| file.content.as_str()))
+        .map(content_stats)
+        .fold(ContentStats::default(), |total, file| ContentStats {
+            lines: total.lines + file.lines,
+            words: total.words + file.words,
+            chars: total.chars + file.chars,
+            bytes: total.bytes + file.bytes,
+        });
+    Some(stats)
+}
+
+/// "12 lines · 80 words · 412 characters · 530 bytes".
+fn stats_label(stats: ContentStats, strings: &Strings) -> String {
+    [
+        strings.plural(&strings.stats_lines, stats.lines),
+        strings.plural(&strings.stats_words, stats.words),
+        strings.plural(&strings.stats_chars, stats.chars),
+        strings.plural(&strings.stats_bytes, stats.bytes),
+    ]
+    .join(" · ")
+}
+
+/// "by ~nickname" for a signed paste, unless `allow_author` is off.
+fn byline(author: Option<&str>, config: &PasteConfig, strings: &Strings) -> Option<String> {
+    author
+        .filter(|_| config.allow_author)
+        .map(|author| strings.by_author.replace("{}", author))
+}
+
+/// Files built into the binary from `assets/`. Replaced by a `ServeDir`
+/// when `server.assets_dir` is set.
+pub async fn serve_asset(
+    Path(path): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Response {
+    let Some(file) = Assets::get(&path) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    // Only a URL carrying the current version can be cached for good; an
+    // unversioned or outdated one must pick up the next upgrade.
+    let cache_control = if params.get("v").map(String::as_str) == assets::version(&path) {
+        "public, max-age=31536000, immutable"
+    } else {
+        "public, max-age=300"
+    };
+    (
+        [
+            (CONTENT_TYPE, file.metadata.mimetype().to_string()),
+            (CACHE_CONTROL, cache_control.to_string()),
+        ],
+        file.data,
+    )
+        .into_response()
+}
+
+fn announcement(state: &AppState, strings: &Strings) -> Option<Announcement> {
+    state
+        .config
+        .load()
+        .announcement
+        .as_ref()
+        .and_then(|announcement| announcement.for_lang(&strings.lang, state.clock.now()))
+}
+
+pub async fn about_page(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Response, AppError> {
+    let (lang, set_cookie) = select_page_language(&headers, &params);
+    let strings = state.i18n.load().strings(lang);
+    let about = state.about.load_full();
+    let Some(body) = about.get(&strings.lang) else {
+        return render_not_found(&state, strings).await;
+    };
+
+    let body = AboutTemplate {
+        announcement: announcement(&state, &strings),
+        body: body.to_string(),
+        strings,
+    }
+    .render()?;
+
+    let mut response = Html(body).into_response();
+    if let Some(cookie) = set_cookie {
+        response.headers_mut().insert(SET_COOKIE, cookie);
+    }
+    Ok(response)
+}
+
+/// `?month=YYYY-MM`, or the current month in `display_timezone`. `None`
+/// if the month does not parse.
+fn requested_month(params: &HashMap<String, String>, offset: i32, now: i64) -> Option<Month> {
+    match params.get("month") {
+        Some(month) => Month::parse(month),
+        None => Some(Month::containing(now, offset)),
+    }
+}
+
+/// The listed pastes created in `month`, grouped by local day, with the
+/// neighbouring months worth linking to.
+async fn archive_month(
+    state: &AppState,
+    config: &AppConfig,
+    strings: &Strings,
+    month: Month,
+) -> Result<ArchiveMonth, sqlx::Error> {
+    let now = state.clock.now();
+    let offset = parse_utc_offset(&config.server.display_timezone).unwrap_or(0);
+    let start = month.start(offset);
+    let mut pastes =
+        db::list_public_between(&state.pool, start, month.next().start(offset), now).await?;
+    for paste in &mut pastes {
+        paste.language_label = language_label(&config.languages, strings, &paste.language);
+        paste.time = format_iso8601(paste.created_at, offset)[11..16].to_string();
+        paste.author = paste.author.take().filter(|_| config.paste.allow_author);
+    }
+    let oldest = db::oldest_public_created_at(&state.pool, now).await?;
+    let earlier = oldest.is_some_and(|oldest| oldest < start);
+    let later = month.next().start(offset) <= now;
+    Ok(ArchiveMonth {
+        month: month.to_string(),
+        total: pastes.len() as i64,
+        days: archive::group_by_day(pastes, offset),
+        earlier: earlier.then(|| month.prev().to_string()),
+        later: later.then(|| month.next().to_string()),
+    })
+}
+
+/// Public pastes by month, grouped by day in `display_timezone`.
+pub async fn archive_page(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Response, AppError> {
+    let config = state.config.load_full();
+    let offset = parse_utc_offset(&config.server.display_timezone).unwrap_or(0);
+    let Some(month) = requested_month(&params, offset, state.clock.now()) else {
+        return Ok(bad_request(&state, &headers));
+    };
+    let (lang, set_cookie) = select_page_language(&headers, &params);
+    let strings = state.i18n.load().strings(lang);
+    let archive = match archive_month(&state, &config, &strings, month).await {
+        Ok(archive) => archive,
+        Err(err) => {
+            eprintln!("Failed to load the archive: {}", err);
+            ArchiveMonth {
+                month: month.to_string(),
+                total: 0,
+                days: Vec::new(),
+                earlier: None,
+                later: None,
+            }
+        }
+    };
+
+    let body = ArchiveTemplate {
+        announcement: announcement(&state, &strings),
+        month: archive.month,
+        total: strings.plural(&strings.archive_count, archive.total),
+        days: archive
+            .days
+            .into_iter()
+            .map(|day| {
+                let count = strings.plural(&strings.archive_count, day.count);
+                (day, count)
+            })
+            .collect(),
+        earlier: archive.earlier,
+        later: archive.later,
+        strings,
+    }
+    .render()?;
+
+    let mut response = Html(body).into_response();
+    if let Some(cookie) = set_cookie {
+        response.headers_mut().insert(SET_COOKIE, cookie);
+    }
+    Ok(response)
+}
+
+/// `GET /api/v1/archive`: what `/archive` shows, as JSON.
+#[utoipa::path(
+    get,
+    path = "/api/v1/archive",
+    params(
+        ("month" = Option<String>, Query, description = "`YYYY-MM`; the current month by default"),
+        ("lang" = Option<String>, Query, description = "`zh`, `en` or `ja`, for `language_label`"),
+    ),
+    responses(
+        (status = 200, description = "The month's pastes by day", body = ArchiveMonth),
+        (status = 400, description = "`month` is not `YYYY-MM`", body = ApiError),
+        (status = 500, description = "Reading the pastes failed", body = ApiError),
+    )
+)]
+pub async fn api_archive(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+) -> Response {
+    let config = state.config.load_full();
+    let offset = parse_utc_offset(&config.server.display_timezone).unwrap_or(0);
+    let Some(month) = requested_month(&params, offset, state.clock.now()) else {
+        return json_error(StatusCode::BAD_REQUEST, "bad_request");
+    };
+    let (lang, _) = select_page_language(&headers, &params);
+    let strings = state.i18n.load().strings(lang);
+    match archive_month(&state, &config, &strings, month).await {
+        Ok(archive) => axum::Json(archive).into_response(),
+        Err(_) => json_error(StatusCode::INTERNAL_SERVER_ERROR, "internal"),
+    }
+}
+
+async fn render_not_found(state: &AppState, strings: Strings) -> Result<Response, AppError> {
+    let max_id: i64 = sqlx::query_scalar("SELECT MAX(id) FROM pastes")
+        .fetch_one(&state.pool)
+        .await
+        .unwrap_or(Some(0))
+        .unwrap_or(0);
+    let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM pastes")
+        .fetch_one(&state.pool)
+        .await
+        .unwrap_or(0);
+    let faded = (max_id - count).max(0);
+    let faded_count = strings.stat_faded.replace("{}", &faded.to_string());
+    not_found_page(state, strings, faded_count)
+}
+
+fn not_found_page(
+    state: &AppState,
+    strings: Strings,
+    faded_count: String,
+) -> Result<Response, AppError> {
+    let body = NotFoundTemplate {
+        announcement: announcement(state, &strings),
+        has_about: state.about.load().get(&strings.lang).is_some(),
+        strings,
+        faded_count,
+    }
+    .render()?;
+    Ok((StatusCode::NOT_FOUND, Html(body)).into_response())
+}
+
+/// A styled page with `message` under the status code, or the bare
+/// fallback page should even that fail to render.
+fn error_page(state: &AppState, strings: Strings, status: StatusCode, message: String) -> Response {
+    let page = ErrorTemplate {
+        announcement: announcement(state, &strings),
+        has_about: state.about.load().get(&strings.lang).is_some(),
+        strings,
+        status: status.as_u16(),
+        message,
+    }
+    .render();
+    match page {
+        Ok(body) => (status, Html(body)).into_response(),
+        Err(err) => {
+            eprintln!("Failed to render error page: {}", err);
+            (status, Html(FALLBACK_PAGE)).into_response()
+        }
+    }
+}
+
+/// `axum::Form`, except that a body which does not decode gets the
+/// translated `bad_request` message instead of axum's plain-text rejection,
+/// and a repeated field fills a `Vec`, as the files of a multi-file paste
+/// do.
+pub struct Form<T>(pub T);
+
+impl<T: DeserializeOwned> FromRequest<AppState> for Form<T> {
+    type Rejection = Response;
+
+    async fn from_request(request: Request, state: &AppState) -> Result<Self, Self::Rejection> {
+        let headers = request.headers().clone();
+        match RawForm::from_request(request, state).await {
+            Ok(RawForm(body)) => serde_html_form::from_bytes(&body)
+                .map(Form)
+                .map_err(|_| bad_request(state, &headers)),
+            // `limit_paste_body` answers these with `content_too_long`.
+            Err(rejection) if rejection.status() == StatusCode::PAYLOAD_TOO_LARGE => {
+                Err(rejection.into_response())
+            }
+            Err(_) => Err(bad_request(state, &headers)),
+        }
+    }
+}
+
+/// A paste token from the path, as stored: under `case_insensitive_tokens`
+/// the paste with the same letters in any case, so handlers and queries
+/// can keep comparing tokens exactly.
+pub struct PasteToken(pub String);
+
+impl FromRequestParts<AppState> for PasteToken {
+    type Rejection = Response;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let Path(token) = Path::<String>::from_request_parts(parts, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        // Trailing punctuation is dropped first, as the handlers would.
+        let normalized = normalize_token(&token, &state.config.load().paste);
+        Ok(PasteToken(match normalized {
+            Some(token) => canonical_token(state, token).await,
+            None => token,
+        }))
+    }
+}
+
+/// `token` as stored, when `case_insensitive_tokens` lets it differ in
+/// case; otherwise, or if no paste matches, unchanged.
+pub async fn canonical_token(state: &AppState, token: String) -> String {
+    if !state.config.load().paste.case_insensitive_tokens {
+        return token;
+    }
+    match db::token_by_key(&state.pool, &token).await {
+        Ok(Some(stored)) => stored,
+        _ => token,
+    }
+}
+
+/// 400 for a request that could not be decoded: JSON for clients that asked
+/// for it, the bare message for htmx to swap in, the error page otherwise.
+fn bad_request(state: &AppState, headers: &HeaderMap) -> Response {
+    if wants_json(headers) {
+        return json_error(StatusCode::BAD_REQUEST, "bad_request");
+    }
+    let (lang, _) = select_language(headers, None);
+    let strings = state.i18n.load().strings(lang);
+    let message = strings.bad_request.clone();
+    if headers.contains_key("hx-request") {
+        return (StatusCode::BAD_REQUEST, Html(message)).into_response();
+    }
+    error_page(state, strings, StatusCode::BAD_REQUEST, message)
+}
+
+/// Serve the content as stored. `?crlf=1` converts line endings to CRLF
+/// for Windows tools.
+pub async fn view_paste_raw(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ClientIp(ip): ClientIp,
+    Path(token): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+) -> impl IntoResponse {
+    let (token, extension) = split_extension(&token);
+    serve_raw(&state, &headers, ip, token, extension, None, &params).await
+}
+
+/// `/r/{token}/{filename}`: one file of a multi-file paste, served like
+/// `view_paste_raw` and counting a view the same way.
+pub async fn view_paste_file_raw(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ClientIp(ip): ClientIp,
+    Path((token, filename)): Path<(String, String)>,
+    Query(params): Query<HashMap<String, String>>,
+) -> impl IntoResponse {
+    serve_raw(&state, &headers, ip, &token, None, Some(&filename), &params).await
+}
+
+/// The paste `token`, or its file `filename`, as plain text. `extension`
+/// picks the content type, as in `/r/{token}.rs`.
+async fn serve_raw(
+    state: &AppState,
+    headers: &HeaderMap,
+    ip: IpAddr,
+    token: &str,
+    extension: Option<&str>,
+    filename: Option<&str>,
+    params: &HashMap<String, String>,
+) -> Response {
+    let Some(token) = normalize_token(token, &state.config.load().paste) else {
+        return raw_not_found(state, headers, params, false);
+    };
+    let token = canonical_token(state, token).await;
+    let now = state.clock.now();
+    // With `[paste.cache]` on, raw reads share the page's cache.
+    let (item, batched) = if state.config.load().paste.cache.is_some() {
+        let paste = load_paste(state, &token, now)
+            .await
+            .filter(|p| !p.is_scheduled(now));
+        let batched = paste.as_ref().is_some_and(|p| PasteCache::admits(p, now));
+        (paste.and_then(|p| raw_from_paste(p, filename)), batched)
+    } else {
+        cleanup_expired(state).await;
+        enforce_size_limit(state, 0).await;
+        (load_raw(state, &token, filename).await, false)
+    };
+
+    // A viewer holding a receipt from the detail page has already paid,
+    // for one raw read.
+    let client = client_hash(&state.signing_key, ip);
+    let already_viewed = !state.config.load().paste.raw_counts_as_view
+        && get_cookie(headers, "viewed")
+            .and_then(|receipt| {
+                verify_view_receipt(&state.signing_key, &token, &client, &receipt, now)
+            })
+            .is_some_and(|(nonce, exp)| state.spent_receipts.take(&nonce, exp, now));
+    if let Some(raw) = &item
+        && !already_viewed
+    {
+        let config = state.config.load();
+        if batched {
+            let view = PendingView {
+                client_hash: client,
+                raw: true,
+                at: now,
+            };
+            state.paste_cache.count_view(&token, view);
+        } else {
+            let rules = view_rules(&config);
+            let counted =
+                db::consume_view(&state.pool, &token, &rules, Some(&client), true, now).await;
+            // As on the page: a paste that burns is served only once its
+            // view is paid for.
+            if raw.burns() && !matches!(counted, Ok(Some(_))) {
+                return raw_not_found(state, headers, params, true);
+            }
+        }
+    }
+
+    match item {
+        Some(mut item) => {
+            let config = state.config.load();
+            let render = renders_html(&config.paste, &item, params);
+            let languages = &config.languages;
+            let language = raw_language(languages, extension, &mut item);
+            convert_raw(&mut item, &language, params);
+            let mut headers = raw_headers(languages, &token, filename, &item, &language);
+            if render {
+                html_page_headers(&mut headers);
+            }
+            if already_viewed {
+                let spent = format!("viewed=; Path=/r/{}; Max-Age=0", token);
+                if let Ok(cookie) = HeaderValue::from_str(&spent) {
+                    headers.insert(SET_COOKIE, cookie);
+                }
+            }
+            if item.content.len() < RAW_STREAM_MIN_BYTES {
+                return (headers, item.content).into_response();
+            }
+            headers.insert(CONTENT_LENGTH, HeaderValue::from(item.content.len()));
+            (headers, chunked_body(item.content)).into_response()
+        }
+        None => {
+            let gone = db::paste_gone(&state.pool, &token, state.clock.now())
+                .await
+                .unwrap_or(false);
+            raw_not_found(state, headers, params, gone)
+        }
+    }
+}
+
+/// Raw content at least this long is sent in `RAW_STREAM_CHUNK_BYTES`
+/// pieces rather than as one body.
+const RAW_STREAM_MIN_BYTES: usize = 1024 * 1024;
+const RAW_STREAM_CHUNK_BYTES: usize = 64 * 1024;
+
+/// `content` as a streamed body of slices into the one buffer, so the
+/// response starts at once and nothing is copied on the way out.
+fn chunked_body(content: String) -> Body {
+    let content = Bytes::from(content);
+    let chunks = (0..content.len())
+        .step_by(RAW_STREAM_CHUNK_BYTES)
+        .map(move |start| {
+            let end = (start + RAW_STREAM_CHUNK_BYTES).min(content.len());
+            Ok::<_, std::convert::Infallible>(content.slice(start..end))
+        });
+    Body::from_stream(futures_util::stream::iter(chunks))
+}
+
+/// `HEAD /r/{token}`: the headers `GET` would send, from the paste's
+/// metadata alone. Nothing is decrypted or rendered and no view is
+/// counted. Only `?pretty=1`, `?minify=1` and `?crlf=1` load the content,
+/// since the converted body has to exist to be measured and hashed.
+pub async fn head_paste_raw(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(token): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Response {
+    let (token, extension) = split_extension(&token);
+    head_raw(&state, &headers, token, extension, None, &params).await
+}
+
+/// `HEAD /r/{token}/{filename}`: as `head_paste_raw`, for one file. The
+/// file is loaded to be measured, but no view is counted.
+pub async fn head_paste_file_raw(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path((token, filename)): Path<(String, String)>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Response {
+    head_raw(&state, &headers, &token, None, Some(&filename), &params).await
+}
+
+async fn head_raw(
+    state: &AppState,
+    headers: &HeaderMap,
+    token: &str,
+    extension: Option<&str>,
+    filename: Option<&str>,
+    params: &HashMap<String, String>,
+) -> Response {
+    let Some(token) = normalize_token(token, &state.config.load().paste) else {
+        return raw_not_found(state, headers, params, false);
+    };
+    let token = canonical_token(state, token).await;
+    let item = if filename.is_some() || converts_raw(params) {
+        load_raw(state, &token, filename)
+            .await
+            .map(|item| (item, None))
+    } else {
+        db::fetch_paste_meta(&state.pool, &token, state.clock.now())
+            .await
+            .unwrap_or(None)
+            .map(|meta| {
+                let item = RawPaste {
+                    content: String::new(),
+                    is_encrypted: false,
+                    nonce: None,
+                    is_e2e: meta.is_e2e,
+                    content_hash: meta.content_hash,
+                    language: meta.language,
+                    detected_language: meta.detected_language,
+                    filename: meta.filename,
+                    expires_at: meta.expires_at,
+                    max_views: meta.max_views,
+                    destroy_after_first_view_secs: meta.destroy_after_first_view_secs,
+                };
+                (item, Some(meta.content_length))
+            })
+    };
+    let Some((mut item, length)) = item else {
+        let gone = db::paste_gone(&state.pool, &token, state.clock.now())
+            .await
+            .unwrap_or(false);
+        return raw_not_found(state, headers, params, gone);
+    };
+    let config = state.config.load();
+    let render = renders_html(&config.paste, &item, params);
+    let languages = &config.languages;
+    let language = raw_language(languages, extension, &mut item);
+    convert_raw(&mut item, &language, params);
+    let mut headers = raw_headers(languages, &token, filename, &item, &language);
+    if render {
+        html_page_headers(&mut headers);
+    }
+    let length = length.unwrap_or(item.content.len() as i64);
+    headers.insert(CONTENT_LENGTH, HeaderValue::from(length.max(0)));
+    headers.into_response()
+}
+
+/// The paste `token`, or its file `filename`, decrypted. Does not count a
+/// view.
+async fn load_raw(state: &AppState, token: &str, filename: Option<&str>) -> Option<RawPaste> {
+    let item: Option<RawPaste> = sqlx::query_as(
+        r#"
+        SELECT content, is_encrypted, nonce, is_e2e, content_hash, language, detected_language,
+               filename, expires_at, max_views, destroy_after_first_view_secs
+        FROM pastes
+        WHERE token = ?1 AND expires_at > ?2
+          AND (visible_after IS NULL OR visible_after <= ?2)
+        "#,
+    )
+    .bind(token)
+    .bind(state.clock.now())
+    .fetch_optional(&state.pool)
+    .await
+    .unwrap_or(None)
+    .and_then(|mut p: RawPaste| {
+        p.content = open_content(
+            state.cipher.as_ref(),
+            p.content,
+            p.is_encrypted,
+            p.nonce.as_deref(),
+        )?;
+        Some(p)
+    });
+    // Another file stands in for the content; the first is the paste's own.
+    match (item, filename) {
+        (Some(item), Some(filename)) if item.filename.as_deref() != Some(filename) => {
+            let files = db::paste_files(&state.pool, token, state.cipher.as_ref())
+                .await
+                .unwrap_or_default();
+            files
+                .into_iter()
+                .find(|file| file.filename == filename)
+                .map(|file| file_raw(item, file))
+        }
+        (item, _) => item,
+    }
+}
+
+/// `/r/` content out of a paste loaded whole, or out of its file
+/// `filename`.
+fn raw_from_paste(paste: Paste, filename: Option<&str>) -> Option<RawPaste> {
+    let item = RawPaste {
+        content: paste.content,
+        is_encrypted: paste.is_encrypted,
+        nonce: paste.nonce,
+        is_e2e: paste.is_e2e,
+        content_hash: paste.content_hash,
+        language: paste.language,
+        detected_language: paste.detected_language,
+        filename: paste.filename,
+        expires_at: paste.expires_at,
+        max_views: paste.max_views,
+        destroy_after_first_view_secs: paste.destroy_after_first_view_secs,
+    };
+    match filename {
+        Some(filename) if item.filename.as_deref() != Some(filename) => paste
+            .files
+            .into_iter()
+            .find(|file| file.filename == filename)
+            .map(|file| file_raw(item, file)),
+        _ => Some(item),
+    }
+}
+
+/// Another file of the paste `item` standing in for its content.
+fn file_raw(item: RawPaste, file: PasteFile) -> RawPaste {
+    RawPaste {
+        content_hash: Some(sha256_hex(&file.content)),
+        content: file.content,
+        language: file.language,
+        detected_language: None,
+        filename: Some(file.filename),
+        ..item
+    }
+}
+
+/// The language `/r/` serves `item` as: the one `extension` hints at,
+/// else what `auto` was detected as, else the one it was stored with.
+fn raw_language(
+    languages: &[LanguageConfig],
+    extension: Option<&str>,
+    item: &mut RawPaste,
+) -> String {
+    let hinted = extension.and_then(|extension| language_for_extension(languages, extension));
+    match (hinted, item.detected_language.take()) {
+        (Some(hinted), _) => hinted,
+        (None, Some(detected)) if item.language == "auto" => detected,
+        _ => std::mem::take(&mut item.language),
+    }
+}
+
+fn raw_flag(params: &HashMap<String, String>, name: &str) -> bool {
+    params.get(name).is_some_and(|value| value == "1")
+}
+
+/// Whether the query asks `/r/` to change the content on the way out.
+fn converts_raw(params: &HashMap<String, String>) -> bool {
+    ["pretty", "minify", "crlf"]
+        .iter()
+        .any(|name| raw_flag(params, name))
+}
+
+/// Apply `?pretty=1`, `?minify=1` and `?crlf=1`, rehashing what changed.
+fn convert_raw(item: &mut RawPaste, language: &str, params: &HashMap<String, String>) {
+    let flag = |name: &str| raw_flag(params, name);
+    let mut converted = false;
+    // Valid JSON can be had re-indented or minified; anything else
+    // is served as stored.
+    if !item.is_e2e
+        && language == "json"
+        && (flag("pretty") || flag("minify"))
+        && let Some(content) = syntax::reformat_json(&item.content, flag("pretty"))
+    {
+        item.content = content;
+        converted = true;
+    }
+    if !item.is_e2e && flag("crlf") {
+        item.content = normalize_content(&item.content, false).replace('\n', "\r\n");
+        converted = true;
+    }
+    if converted {
+        item.content_hash = Some(sha256_hex(&item.content));
+    }
+}
+
+/// What `/r/` sends with the content of `item`, for `GET` and `HEAD` alike.
+fn raw_headers(
+    languages: &[LanguageConfig],
+    token: &str,
+    filename: Option<&str>,
+    item: &RawPaste,
+    language: &str,
+) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    // Client-side encrypted content is served verbatim as opaque bytes
+    let (content_type, extension) = if item.is_e2e {
+        headers.insert("X-Mayfile-E2E", HeaderValue::from_static("1"));
+        ("application/octet-stream".to_string(), "bin".to_string())
+    } else {
+        languages
+            .iter()
+            .find(|option| option.value == language)
+            .map(|option| (option.content_type.clone(), option.extension.clone()))
+            .unwrap_or_else(|| ("text/plain; charset=utf-8".to_string(), "txt".to_string()))
+    };
+    if let Ok(content_type) = HeaderValue::from_str(&content_type) {
+        headers.insert(CONTENT_TYPE, content_type);
+    }
+    if let Some(hash) = item
+        .content_hash
+        .as_deref()
+        .and_then(|hash| HeaderValue::from_str(hash).ok())
+    {
+        headers.insert("X-Content-SHA256", hash);
+    }
+    if let Some(etag) = item
+        .content_hash
+        .as_deref()
+        .and_then(|hash| HeaderValue::from_str(&format!("\"{}\"", hash)).ok())
+    {
+        headers.insert(ETAG, etag);
+    }
+    expiry_header(&mut headers, item.expires_at);
+    let disposition = match filename.and(item.filename.as_deref()) {
+        Some(filename) => format!(
+            "inline; filename*=UTF-8''{}",
+            utf8_percent_encode(filename, NON_ALPHANUMERIC)
+        ),
+        None => format!("inline; filename=\"paste-{}.{}\"", token, extension),
+    };
+    headers.insert(
+        CONTENT_DISPOSITION,
+        HeaderValue::from_str(&disposition).unwrap(),
+    );
+    headers
+}
+
+/// What a rendered HTML paste may do: show itself with inline styles and
+/// images, in an origin of its own so it cannot read the instance's
+/// cookies, and nothing else; no scripts, requests, forms or framing.
+const RENDERED_HTML_CSP: &str = "default-src 'none'; style-src 'unsafe-inline'; img-src data:; form-action 'none'; frame-ancestors 'none'; sandbox";
+
+/// Whether `/r/` serves `item` as a page: `?render=1` on an HTML paste
+/// (stored as or detected as `html`), with `allow_html_rendering` on.
+/// Otherwise the parameter is ignored.
+fn renders_html(config: &PasteConfig, item: &RawPaste, params: &HashMap<String, String>) -> bool {
+    let language = match item.language.as_str() {
+        "auto" => item.detected_language.as_deref().unwrap_or("auto"),
+        language => language,
+    };
+    config.allow_html_rendering && !item.is_e2e && language == "html" && raw_flag(params, "render")
+}
+
+/// Turn the headers of raw content into those of a locked-down page.
+fn html_page_headers(headers: &mut HeaderMap) {
+    headers.insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static("text/html; charset=utf-8"),
+    );
+    headers.insert(
+        CONTENT_SECURITY_POLICY,
+        HeaderValue::from_static(RENDERED_HTML_CSP),
+    );
+}
+
+/// `X-Mayfile-Expires-At`: when the paste expires, in ISO 8601 UTC.
+fn expiry_header(headers: &mut HeaderMap, expires_at: i64) {
+    if let Ok(expires) = HeaderValue::from_str(&format_iso8601(expires_at, 0)) {
+        headers.insert("X-Mayfile-Expires-At", expires);
+    }
+}
+
+/// 404 for `/r/`: a line of text in the request's language, or JSON for
+/// clients that ask for it. `gone` means the token did name a paste once,
+/// so scripts can tell `expired` from `not_found`. The `X-Request-Id` is
+/// repeated in the body, to quote when asking for help.
+fn raw_not_found(
+    state: &AppState,
+    headers: &HeaderMap,
+    params: &HashMap<String, String>,
+    gone: bool,
+) -> Response {
+    let (lang, _) = select_page_language(headers, params);
+    let strings = state.i18n.load().strings(lang);
+    let request_id = request_id(headers);
+    let (code, message) = if gone {
+        ("expired", strings.not_found_desc)
+    } else {
+        ("not_found", strings.raw_never_existed)
+    };
+    let mut response = if wants_json(headers) {
+        let body = ApiError {
+            error: code.to_string(),
+            message: Some(message),
+            request_id: Some(request_id.clone()),
+        };
+        (StatusCode::NOT_FOUND, axum::Json(body)).into_response()
+    } else {
+        let body = format!(
+            "{}\n{}\n",
+            message,
+            strings.request_id_label.replace("{}", &request_id)
+        );
+        (
+            StatusCode::NOT_FOUND,
+            [(CONTENT_TYPE, "text/plain; charset=utf-8")],
+            body,
+        )
+            .into_response()
+    };
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert("X-Request-Id", value);
+    }
+    response
+}
+
+/// The `X-Request-Id` a proxy in front already gave the request, if it is
+/// usable, or a fresh one.
+fn request_id(headers: &HeaderMap) -> String {
+    headers
+        .get("x-request-id")
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| (1..=128).contains(&id.len()) && id.bytes().all(|b| b.is_ascii_graphic()))
+        .map(str::to_string)
+        .unwrap_or_else(|| generate_token(16))
+}
+
+pub async fn explore(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Response, AppError> {
+    cleanup_expired(&state).await;
+    let (lang, set_cookie) = select_page_language(&headers, &params);
+    let strings = state.i18n.load().strings(lang);
+
+    let config = state.config.load();
+    let expiring = params.get("expiring").is_some_and(|value| value == "1");
+    let filter = PublicFilter {
+        expiring_below: expiring.then_some(config.paste.explore_expiring_fraction),
+    };
+    let mut pastes = db::list_public_pastes(
+        &state.pool,
+        state.cipher.as_ref(),
+        Some(config.paste.explore_preview_chars),
+        &filter,
+        EXPLORE_PAGE_SIZE,
+        0,
+        state.clock.now(),
+    )
+    .await
+    .unwrap_or_default();
+    let now = state.clock.now();
+    for paste in &mut pastes {
+        paste.created = display_time(paste.created_at, now, &config, &strings);
+        paste.life_stage = life_stage(paste.original_duration, paste.expires_at, now);
+        paste.remaining = format_duration(paste.expires_at, now, &strings);
+        paste.byline = byline(paste.author.as_deref(), &config.paste, &strings);
+    }
+    // Which ones this client reacted to is only known under `/p/{token}`.
+    let tokens: Vec<&str> = pastes.iter().map(|paste| paste.token.as_str()).collect();
+    let counts = db::reaction_counts(&state.pool, &tokens)
+        .await
+        .unwrap_or_default();
+    for paste in &mut pastes {
+        let counts = counts.get(&paste.token).map_or(&[][..], Vec::as_slice);
+        paste.reactions = reactions_from(counts, 0);
+    }
+
+    let shown = pastes.len() as i64;
+    // A paste can expire between the list and a cached count.
+    let total = state
+        .public_counts
+        .get(&state.pool, &filter, now)
+        .await
+        .unwrap_or(0)
+        .max(shown);
+    let count = strings
+        .explore_count
+        .replacen("{}", &shown.to_string(), 1)
+        .replacen("{}", &total.to_string(), 1);
+    let pastes = pastes
+        .into_iter()
+        .map(|paste| ExploreCard {
+            life_fraction: life_fraction(paste.original_duration, paste.expires_at, now),
+            renewal: renewal(
+                paste.renewable,
+                paste.original_duration,
+                paste.expires_at,
+                now,
+            ),
+            paste,
+        })
+        .collect();
+
+    let max_expires_secs = max_expires_secs(&config.paste);
+
+    let body = ExploreTemplate {
+        announcement: announcement(&state, &strings),
+        strings,
+        pastes,
+        count,
+        expiring,
+        now_ts: now,
+        max_expires_secs,
+    }
+    .render()?;
+
+    let mut response = Html(body).into_response();
+    if let Some(cookie) = set_cookie {
+        response.headers_mut().insert(SET_COOKIE, cookie);
+    }
+    Ok(response)
+}
+
+/// The most pastes the explore page loads at once.
+const EXPLORE_PAGE_SIZE: i64 = 100;
+
+/// `GET /api/v1/explore` (also `/api/explore`): one public paste at a
+/// time, by position.
+#[utoipa::path(
+    get,
+    path = "/api/v1/explore",
+    params(
+        ("offset" = Option<i64>, Query, description = "Position of the paste, from 0"),
+        ("full" = Option<u8>, Query, description = "1 for the whole content instead of a preview"),
+        ("expiring" = Option<u8>, Query, description = "1 for pastes close to expiring only"),
+        ("lang" = Option<String>, Query, description = "`zh`, `en` or `ja`"),
+        ("preview_chars" = Option<i64>, Query,
+            description = "`content` cut to this many characters, with `content_truncated`"),
+        ("fields" = Option<String>, Query,
+            description = "Comma-separated names of the fields to include; all by default"),
+    ),
+    responses(
+        (status = 200, description = "The paste at `offset`", body = ExplorePaste),
+        (status = 400, description = "Unknown name in `fields`, or a negative `preview_chars`",
+            body = ApiError),
+        (status = 404, description = "No paste at `offset`", body = ApiError),
+    )
+)]
+pub async fn api_explore(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<ExploreQuery>,
+) -> impl IntoResponse {
+    let now = state.clock.now();
+    let (lang, _) = select_language(&headers, query.lang.as_deref());
+    let strings = state.i18n.load().strings(lang);
+    let fields = match query.fields.as_deref().map(parse_explore_fields) {
+        None => None,
+        Some(Ok(fields)) => Some(fields),
+        Some(Err(unknown)) => {
+            let message = strings
+                .explore_fields_invalid
+                .replacen("{}", &unknown, 1)
+                .replacen("{}", &EXPLORE_FIELDS.join(", "), 1);
+            let body = ApiError {
+                error: "invalid_fields".to_string(),
+                message: Some(message),
+                request_id: None,
+            };
+            return (StatusCode::BAD_REQUEST, axum::Json(body)).into_response();
+        }
+    };
+    if query.preview_chars.is_some_and(|chars| chars < 0) {
+        return json_error(StatusCode::BAD_REQUEST, "invalid_preview_chars");
+    }
+    cleanup_expired(&state).await;
+    let offset = query.offset.unwrap_or(0);
+
+    let full = query.full == Some(1);
+    // Without any field that shows the content, none is read.
+    let skip_content = fields.as_ref().is_some_and(|fields| {
+        !fields
+            .iter()
+            .any(|field| ["truncated", "preview", "content", "content_truncated"].contains(field))
+    });
+    let preview_chars = if skip_content {
+        Some(0)
+    } else if query.preview_chars.is_some() {
+        query.preview_chars
+    } else if full {
+        None
+    } else {
+        Some(state.config.load().paste.explore_preview_chars)
+    };
+    let filter = PublicFilter {
+        expiring_below: (query.expiring == Some(1))
+            .then(|| state.config.load().paste.explore_expiring_fraction),
+    };
+
+    let paste = db::list_public_pastes(
+        &state.pool,
+        state.cipher.as_ref(),
+        preview_chars,
+        &filter,
+        1,
+        offset,
+        now,
+    )
+    .await
+    .unwrap_or_default()
+    .into_iter()
+    .next();
+
+    let total = state
+        .public_counts
+        .get(&state.pool, &filter, now)
+        .await
+        .unwrap_or(0);
+
+    match paste {
+        Some(p) => {
+            let config = state.config.load();
+            let stage = life_stage(p.original_duration, p.expires_at, now);
+            let renewal = renewal(p.renewable, p.original_duration, p.expires_at, now);
+            let (content, preview, content_truncated) = if query.preview_chars.is_some() {
+                (Some(p.preview), None, Some(p.truncated))
+            } else if full {
+                (Some(p.preview), None, None)
+            } else {
+                (None, Some(p.preview), None)
+            };
+            let paste = ExplorePaste {
+                created: display_time(p.created_at, now, &config, &strings),
+                life_stage_label: stage.label(&strings).to_string(),
+                remaining: format_duration(p.expires_at, now, &strings),
+                language_label: language_label(&config.languages, &strings, &p.language),
+                lang: strings.lang,
+                token: p.token,
+                title: p.title,
+                content_length: p.content_length,
+                truncated: p.truncated,
+                created_at: p.created_at,
+                expires_at: p.expires_at,
+                life_stage: stage,
+                life_fraction: life_fraction(p.original_duration, p.expires_at, now),
+                original_duration: p.original_duration,
+                renew_eligible: renewal.renew_eligible,
+                renew_available_at: renewal.renew_available_at,
+                language: p.language,
+                author: p.author.filter(|_| config.paste.allow_author),
+                preview,
+                content,
+                content_truncated,
+                index: offset,
+                total,
+            };
+            match fields {
+                Some(fields) => axum::Json(paste.select(&fields)).into_response(),
+                None => axum::Json(paste).into_response(),
+            }
+        }
+        None => json_error(StatusCode::NOT_FOUND, "not_found"),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/p/{token}/meta",
+    params(
+        ("token" = String, Path),
+        ("lang" = Option<String>, Query, description = "`zh`, `en` or `ja`"),
+    ),
+    responses(
+        (status = 200, description = "Metadata; does not count as a view", body = PasteMeta),
+        (status = 404, description = "No such paste", body = ApiError),
+    )
+)]
+pub async fn api_paste_meta(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    PasteToken(token): PasteToken,
+    Query(params): Query<HashMap<String, String>>,
+) -> impl IntoResponse {
+    let now = state.clock.now();
+    match db::fetch_paste_meta(&state.pool, &token, now)
+        .await
+        .unwrap_or(None)
+    {
+        Some(mut meta) => {
+            let (lang, _) = select_page_language(&headers, &params);
+            let strings = state.i18n.load().strings(lang);
+            let config = state.config.load();
+            meta.created = display_time(meta.created_at, now, &config, &strings);
+            meta.language_label = language_label(&config.languages, &strings, &meta.language);
+            meta.author = meta.author.filter(|_| config.paste.allow_author);
+            meta.lang = strings.lang;
+            meta.renewal = renewal(
+                meta.is_public
+                    && meta.max_views.is_none()
+                    && meta.destroy_after_first_view_secs.is_none(),
+                meta.original_duration,
+                meta.expires_at,
+                now,
+            );
+            meta.related = db::related_links(&state.pool, &token, now)
+                .await
+                .unwrap_or_default();
+            meta.stats = db::fetch_paste(&state.pool, &token, state.cipher.as_ref(), now)
+                .await
+                .unwrap_or(None)
+                .as_ref()
+                .and_then(paste_stats);
+            axum::Json(meta).into_response()
+        }
+        None => json_error(StatusCode::NOT_FOUND, "not_found"),
+    }
+}
+
+/// The emoji a listed paste can be reacted to with, in display order. A
+/// client's reactions to one paste are a bitmask over this list.
+pub const REACTIONS: [&str; 3] = ["❤️", "🔥", "👀"];
+
+/// Which of `REACTIONS` this client left on `token`, from its signed
+/// `reacted` cookie.
+fn reacted_mask(headers: &HeaderMap, key: &[u8], token: &str) -> u8 {
+    get_cookie(headers, "reacted")
+        .and_then(|value| {
+            let (mask, sig) = value.split_once('.')?;
+            let mask: u8 = mask.parse().ok()?;
+            verify_reaction_receipt(key, token, mask, sig).then_some(mask)
+        })
+        .unwrap_or(0)
+}
+
+/// One entry per `REACTIONS` emoji, zeros included.
+fn reactions_from(counts: &[(String, i64)], mask: u8) -> Vec<Reaction> {
+    REACTIONS
+        .iter()
+        .enumerate()
+        .map(|(i, emoji)| Reaction {
+            emoji: emoji.to_string(),
+            count: counts
+                .iter()
+                .find(|(stored, _)| stored == emoji)
+                .map_or(0, |(_, count)| *count),
+            reacted: mask & (1 << i) != 0,
+        })
+        .collect()
+}
+
+/// React to a listed paste with one of `REACTIONS`, or take the reaction
+/// back if this client already left it. Answers with the updated buttons
+/// for htmx, the counts for JSON clients, and a redirect to the paste
+/// otherwise.
+pub async fn react_to_paste(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    headers: HeaderMap,
+    PasteToken(token): PasteToken,
+    Form(form): Form<ReactForm>,
+) -> Result<Response, AppError> {
+    let json = wants_json(&headers);
+    let (lang, _) = select_language(&headers, None);
+    let strings = state.i18n.load().strings(lang);
+    if !state.reaction_limiter.check(ip, state.clock.now()) {
+        audit::refused(&state, ip, "reaction", Refusal::RateLimit, None);
+        if json {
+            return Ok(json_error(
+                StatusCode::TOO_MANY_REQUESTS,
+                "too_many_requests",
+            ));
+        }
+        return Ok((StatusCode::TOO_MANY_REQUESTS, Html(strings.react_too_many)).into_response());
+    }
+    let Some(index) = REACTIONS.iter().position(|emoji| *emoji == form.emoji) else {
+        return Ok(bad_request(&state, &headers));
+    };
+    let mask = reacted_mask(&headers, &state.signing_key, &token);
+    let bit = 1 << index;
+    let counts = match db::toggle_reaction(
+        &state.pool,
+        &token,
+        &form.emoji,
+        mask & bit == 0,
+        state.clock.now(),
+    )
+    .await
+    {
+        Ok(Some(counts)) => counts,
+        Ok(None) if json => return Ok(json_error(StatusCode::NOT_FOUND, "not_found")),
+        Ok(None) => return Ok(StatusCode::NOT_FOUND.into_response()),
+        Err(err) => {
+            eprintln!("Failed to record reaction: {}", err);
+            return Ok(StatusCode::INTERNAL_SERVER_ERROR.into_response());
+        }
+    };
+    let mask = mask ^ bit;
+    let reactions = reactions_from(&counts, mask);
+
+    let mut response = if json {
+        axum::Json(PasteReactions { reactions }).into_response()
+    } else if headers.contains_key("hx-request") {
+        let body = ReactionsTemplate {
+            strings,
+            token: token.clone(),
+            reactions,
+        }
+        .render()?;
+        Html(body).into_response()
+    } else {
+        Redirect::to(&format!("/p/{}", token)).into_response()
+    };
+    let cookie = format!(
+        "reacted={}.{}; Path=/p/{}; Max-Age=31536000; HttpOnly; SameSite=Lax",
+        mask,
+        reaction_receipt(&state.signing_key, &token, mask),
+        token
+    );
+    if let Ok(cookie) = HeaderValue::from_str(&cookie) {
+        response.headers_mut().append(SET_COOKIE, cookie);
+    }
+    Ok(response)
+}
+
+/// Report a paste for review. Each client counts once per paste; enough
+/// distinct reports unpublish it until an admin decides.
+pub async fn report_paste(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    headers: HeaderMap,
+    PasteToken(token): PasteToken,
+    Form(form): Form<ReportForm>,
+) -> impl IntoResponse {
+    let (lang, _) = select_language(&headers, None);
+    let strings = state.i18n.load().strings(lang);
+    if !state.report_limiter.check(ip, state.clock.now()) {
+        audit::refused(&state, ip, "report", Refusal::RateLimit, None);
+        return (StatusCode::TOO_MANY_REQUESTS, Html(strings.report_too_many)).into_response();
+    }
+
+    // htmx sends the answer to `hx-prompt` as a header rather than a field
+    let reason = form
+        .reason
+        .or_else(|| {
+            headers
+                .get("hx-prompt")
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        })
+        .map(|reason| reason.trim().chars().take(500).collect::<String>())
+        .filter(|reason| !reason.is_empty());
+    let reporter = sha256_hex(&ip.to_string());
+    let reported = db::insert_report(
+        &state.pool,
+        &token,
+        &reporter,
+        reason.as_deref(),
+        state.config.load().paste.report_threshold,
+        state.clock.now(),
+    )
+    .await;
+    // Enough reports unpublish the paste.
+    state.paste_cache.invalidate(&token);
+    if let Ok(Some((reports, true))) = reported {
+        audit::report_threshold(&state, &token, reports);
+    }
+    match reported {
+        Ok(Some(_)) => Html(format!(
+            r#"<span class="report-done">{}</span>"#,
+            strings.report_thanks
+        ))
+        .into_response(),
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+pub async fn admin_list_reports(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    if let Err(response) = require_admin(&state, &headers) {
+        return response;
+    }
+    match db::list_reported_pastes(&state.pool).await {
+        Ok(pastes) => axum::Json(pastes).into_response(),
+        Err(_) => json_error(StatusCode::INTERNAL_SERVER_ERROR, "internal"),
+    }
+}
+
+/// Dismiss a paste's reports and republish it if they had unpublished it.
+pub async fn admin_clear_reports(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(token): Path<String>,
+) -> impl IntoResponse {
+    if let Err(response) = require_admin(&state, &headers) {
+        return response;
+    }
+    match db::clear_reports(&state.pool, &token).await {
+        Ok(true) => admin_action_done(&headers),
+        Ok(false) => json_error(StatusCode::NOT_FOUND, "not_found"),
+        Err(_) => json_error(StatusCode::INTERNAL_SERVER_ERROR, "internal"),
+    }
+}
+
+/// Agree with the reports and delete the paste.
+pub async fn admin_uphold_reports(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(token): Path<String>,
+) -> impl IntoResponse {
+    if let Err(response) = require_admin(&state, &headers) {
+        return response;
+    }
+    let deleted = db::delete_paste(&state.pool, &token, state.clock.now()).await;
+    state.paste_cache.forget(&token);
+    match deleted {
+        Ok(true) => admin_action_done(&headers),
+        Ok(false) => json_error(StatusCode::NOT_FOUND, "not_found"),
+        Err(_) => json_error(StatusCode::INTERNAL_SERVER_ERROR, "internal"),
+    }
+}
+
+/// The moderation page. `?token=` signs in once and is swapped for a
+/// session cookie; without it the page offers a sign-in form.
+pub async fn admin_page(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Response, AppError> {
+    let (lang, _) = select_page_language(&headers, &params);
+    let strings = state.i18n.load().strings(lang);
+    let Some(admin_token) = state.config.load().server.admin_token.clone() else {
+        return render_not_found(&state, strings).await;
+    };
+
+    if let Some(token) = params.get("token") {
+        if sha256_hex(token) != sha256_hex(&admin_token) {
+            return render_admin(AdminTemplate {
+                strings,
+                authorized: false,
+                login_failed: true,
+                reported: Vec::new(),
+                recent: Vec::new(),
+                storage: String::new(),
+                usage: None,
+                tokens: String::new(),
+                tokens_saturated: false,
+            });
+        }
+        // Secure only over TLS, or a plain-HTTP deployment never gets it back.
+        let secure = if over_tls(&state.config.load().server, &headers) {
+            "; Secure"
+        } else {
+            ""
+        };
+        let cookie = format!(
+            "admin={}; Path=/; Max-Age=86400; HttpOnly; SameSite=Strict{}",
+            admin_session(&state.signing_key, &admin_token),
+            secure
+        );
+        let mut response = Redirect::to("/admin").into_response();
+        if let Ok(cookie) = HeaderValue::from_str(&cookie) {
+            response.headers_mut().insert(SET_COOKIE, cookie);
+        }
+        return Ok(response);
+    }
+
+    if !is_admin(&state, &headers) {
+        let mut response = render_admin(AdminTemplate {
+            strings,
+            authorized: false,
+            login_failed: false,
+            reported: Vec::new(),
+            recent: Vec::new(),
+            storage: String::new(),
+            usage: None,
+            tokens: String::new(),
+            tokens_saturated: false,
+        })?;
+        *response.status_mut() = StatusCode::UNAUTHORIZED;
+        return Ok(response);
+    }
+
+    let reported = db::list_reported_pastes(&state.pool)
+        .await
+        .unwrap_or_default();
+    let recent = db::list_public_pastes(
+        &state.pool,
+        state.cipher.as_ref(),
+        Some(0),
+        &PublicFilter::default(),
+        20,
+        0,
+        state.clock.now(),
+    )
+    .await
+    .unwrap_or_default();
+    let used = db::total_content_length(&state.pool).await;
+    let storage = strings
+        .admin_storage
+        .replacen("{}", &used.to_string(), 1)
+        .replacen(
+            "{}",
+            &state
+                .config
+                .load()
+                .paste
+                .max_total_content_length
+                .to_string(),
+            1,
+        );
+    let usage = storage_usage(&state).await.ok().map(|mut usage| {
+        for paste in &mut usage.largest {
+            paste.expires_in = format_duration(paste.expires_at, state.clock.now(), &strings);
+        }
+        usage
+    });
+    let space = TokenSpace::new(&state.config.load().paste, metrics::token_collisions());
+    let tokens = strings
+        .admin_tokens
+        .replacen("{}", &space.min_token_length.to_string(), 1)
+        .replacen("{}", &format!("{:.2}", space.occupancy * 100.0), 1)
+        .replacen("{}", &space.collisions.to_string(), 1);
+    render_admin(AdminTemplate {
+        strings,
+        authorized: true,
+        login_failed: false,
+        reported,
+        recent,
+        storage,
+        usage,
+        tokens,
+        tokens_saturated: space.saturated,
+    })
+}
+
+/// Pastes listed as the largest on the admin page.
+const LARGEST_PASTES_SHOWN: i64 = 10;
+
+async fn storage_usage(state: &AppState) -> Result<StorageUsage, sqlx::Error> {
+    let (public, private) = db::usage_by_visibility(&state.pool).await?;
+    let config = state.config.load();
+    Ok(StorageUsage {
+        content_length: public.content_length + private.content_length,
+        max_total_content_length: config.paste.max_total_content_length,
+        by_language: db::usage_by_language(&state.pool).await?,
+        public,
+        private,
+        largest: db::largest_pastes(&state.pool, LARGEST_PASTES_SHOWN).await?,
+        files: db::file_sizes(&config.paste.db_path),
+    })
+}
+
+/// `GET /admin/api/storage`: what the admin page's storage tables show.
+pub async fn admin_storage(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    if let Err(response) = require_admin(&state, &headers) {
+        return response;
+    }
+    match storage_usage(&state).await {
+        Ok(usage) => axum::Json(usage).into_response(),
+        Err(_) => json_error(StatusCode::INTERNAL_SERVER_ERROR, "internal"),
+    }
+}
+
+/// Readiness probe: the schema report once the database passes
+/// `db::self_check`, 503 with the reason otherwise.
+pub async fn readyz(State(state): State<AppState>) -> Response {
+    schema_response(&state).await
+}
+
+pub async fn admin_schema(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    if let Err(response) = require_admin(&state, &headers) {
+        return response;
+    }
+    schema_response(&state).await
+}
+
+async fn schema_response(state: &AppState) -> Response {
+    match db::self_check(&state.pool).await {
+        Ok(report) => axum::Json(report).into_response(),
+        Err(message) => {
+            let body = ApiError {
+                error: "self_check_failed".to_string(),
+                message: Some(message),
+                request_id: None,
+            };
+            (StatusCode::SERVICE_UNAVAILABLE, axum::Json(body)).into_response()
+        }
+    }
+}
+
+fn render_admin(template: AdminTemplate) -> Result<Response, AppError> {
+    Ok(Html(template.render()?).into_response())
+}
+
+pub async fn admin_delete_paste(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(token): Path<String>,
+) -> impl IntoResponse {
+    if let Err(response) = require_admin(&state, &headers) {
+        return response;
+    }
+    let deleted = db::delete_paste(&state.pool, &token, state.clock.now()).await;
+    state.paste_cache.forget(&token);
+    match deleted {
+        Ok(true) => admin_action_done(&headers),
+        Ok(false) => json_error(StatusCode::NOT_FOUND, "not_found"),
+        Err(_) => json_error(StatusCode::INTERNAL_SERVER_ERROR, "internal"),
+    }
+}
+
+pub async fn admin_unpublish_paste(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(token): Path<String>,
+) -> impl IntoResponse {
+    if let Err(response) = require_admin(&state, &headers) {
+        return response;
+    }
+    let unpublished = db::unpublish_paste(&state.pool, &token).await;
+    state.paste_cache.invalidate(&token);
+    match unpublished {
+        Ok(true) => admin_action_done(&headers),
+        Ok(false) => json_error(StatusCode::NOT_FOUND, "not_found"),
+        Err(_) => json_error(StatusCode::INTERNAL_SERVER_ERROR, "internal"),
+    }
+}
+
+/// Protect a paste from eviction when the size limits are reached. It still
+/// expires as usual.
+pub async fn admin_pin_paste(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(token): Path<String>,
+) -> impl IntoResponse {
+    set_pinned(&state, &headers, &token, true).await
+}
+
+pub async fn admin_unpin_paste(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(token): Path<String>,
+) -> impl IntoResponse {
+    set_pinned(&state, &headers, &token, false).await
+}
+
+async fn set_pinned(state: &AppState, headers: &HeaderMap, token: &str, pinned: bool) -> Response {
+    if let Err(response) = require_admin(state, headers) {
+        return response;
+    }
+    match db::set_pinned(&state.pool, token, pinned).await {
+        Ok(true) => admin_action_done(headers),
+        Ok(false) => json_error(StatusCode::NOT_FOUND, "not_found"),
+        Err(_) => json_error(StatusCode::INTERNAL_SERVER_ERROR, "internal"),
+    }
+}
+
+/// Make a paste the paste of the day on the index until cleared. Only
+/// pastes listed on explore can be chosen.
+pub async fn admin_feature_paste(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(token): Path<String>,
+) -> impl IntoResponse {
+    if let Err(response) = require_admin(&state, &headers) {
+        return response;
+    }
+    match db::set_featured(&state.pool, &token, state.clock.now()).await {
+        Ok(true) => {
+            state.featured.invalidate();
+            admin_action_done(&headers)
+        }
+        Ok(false) => json_error(StatusCode::NOT_FOUND, "not_found"),
+        Err(_) => json_error(StatusCode::INTERNAL_SERVER_ERROR, "internal"),
+    }
+}
+
+/// Go back to the daily pick.
+pub async fn admin_clear_featured(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    if let Err(response) = require_admin(&state, &headers) {
+        return response;
+    }
+    match db::clear_featured(&state.pool).await {
+        Ok(_) => {
+            state.featured.invalidate();
+            admin_action_done(&headers)
+        }
+        Err(_) => json_error(StatusCode::INTERNAL_SERVER_ERROR, "internal"),
+    }
+}
+
+/// Delete pastes matching a set of filters, e.g. after a spam wave. A dry
+/// run (the default) only reports how many would go.
+pub async fn admin_purge(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    axum::Json(filter): axum::Json<PurgeFilter>,
+) -> impl IntoResponse {
+    if let Err(response) = require_admin(&state, &headers) {
+        return response;
+    }
+    let content_regex = match filter.content_regex.as_deref().map(regex::Regex::new) {
+        Some(Ok(regex)) => Some(regex),
+        Some(Err(_)) => return json_error(StatusCode::BAD_REQUEST, "invalid_regex"),
+        None => None,
+    };
+    let purged = db::purge_pastes(
+        &state.pool,
+        state.cipher.as_ref(),
+        &filter,
+        content_regex.as_ref(),
+        state.clock.now(),
+    )
+    .await;
+    state.paste_cache.clear();
+    match purged {
+        Ok((matched, deleted)) => {
+            state.metrics.record_evictions(deleted as u64);
+            audit::purge(&state, &filter, matched, deleted);
+            axum::Json(serde_json::json!({
+                "matched": matched,
+                "deleted": deleted,
+                "dry_run": !filter.confirm,
+                "evictions_total": state.metrics.evictions(),
+            }))
+            .into_response()
+        }
+        Err(_) => json_error(StatusCode::INTERNAL_SERVER_ERROR, "internal"),
+    }
+}
+
+/// `GET /admin/api/events?token=..`: the latest events for one paste,
+/// newest first. Works for pastes that are already gone.
+pub async fn admin_list_events(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+) -> impl IntoResponse {
+    if let Err(response) = require_admin(&state, &headers) {
+        return response;
+    }
+    let Some(token) = params.get("token") else {
+        return json_error(StatusCode::BAD_REQUEST, "missing_token");
+    };
+    match db::list_events(&state.pool, token, ADMIN_EVENTS_LIMIT).await {
+        Ok(events) => axum::Json(events).into_response(),
+        Err(_) => json_error(StatusCode::INTERNAL_SERVER_ERROR, "internal"),
+    }
+}
+
+/// Events `/admin/api/events` returns at most.
+const ADMIN_EVENTS_LIMIT: i64 = 100;
+
+/// `GET /admin/api/maintenance`: the latest cleanup and eviction passes
+/// that deleted pastes, newest first, with the tokens they took.
+pub async fn admin_maintenance(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    if let Err(response) = require_admin(&state, &headers) {
+        return response;
+    }
+    axum::Json(state.maintenance.recent()).into_response()
+}
+
+pub async fn admin_list_bans(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    if let Err(response) = require_admin(&state, &headers) {
+        return response;
+    }
+    match db::list_bans(&state.pool).await {
+        Ok(bans) => axum::Json(bans).into_response(),
+        Err(_) => json_error(StatusCode::INTERNAL_SERVER_ERROR, "internal"),
+    }
+}
+
+pub async fn admin_add_ban(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    axum::Json(form): axum::Json<BanForm>,
+) -> impl IntoResponse {
+    if let Err(response) = require_admin(&state, &headers) {
+        return response;
+    }
+    let Ok(cidr) = form.cidr.parse::<Cidr>() else {
+        return json_error(StatusCode::BAD_REQUEST, "invalid_cidr");
+    };
+    match db::insert_ban(&state.pool, &cidr, form.reason.as_deref(), form.expires_at).await {
+        Ok(ban) => {
+            audit::ban_added(&state, &ban);
+            (StatusCode::CREATED, axum::Json(ban)).into_response()
+        }
+        Err(_) => json_error(StatusCode::INTERNAL_SERVER_ERROR, "internal"),
+    }
+}
+
+pub async fn admin_remove_ban(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<i64>,
+) -> impl IntoResponse {
+    if let Err(response) = require_admin(&state, &headers) {
+        return response;
+    }
+    match db::delete_ban(&state.pool, id).await {
+        Ok(true) => {
+            audit::ban_removed(&state, id);
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Ok(false) => json_error(StatusCode::NOT_FOUND, "not_found"),
+        Err(_) => json_error(StatusCode::INTERNAL_SERVER_ERROR, "internal"),
+    }
+}
+
+/// Webhook deliveries given up on after `max_attempts`.
+pub async fn admin_list_dead_webhooks(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    if let Err(response) = require_admin(&state, &headers) {
+        return response;
+    }
+    match db::list_dead_webhooks(&state.pool).await {
+        Ok(deliveries) => axum::Json(deliveries).into_response(),
+        Err(_) => json_error(StatusCode::INTERNAL_SERVER_ERROR, "internal"),
+    }
+}
+
+/// Queue a dead-lettered delivery again with a fresh set of attempts.
+pub async fn admin_retry_webhook(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<i64>,
+) -> impl IntoResponse {
+    if let Err(response) = require_admin(&state, &headers) {
+        return response;
+    }
+    match db::retry_webhook(&state.pool, id, state.clock.now()).await {
+        Ok(true) => StatusCode::NO_CONTENT.into_response(),
+        Ok(false) => json_error(StatusCode::NOT_FOUND, "not_found"),
+        Err(_) => json_error(StatusCode::INTERNAL_SERVER_ERROR, "internal"),
+    }
+}
+
+/// Every alive paste as JSON Lines, streamed batch by batch.
+pub async fn admin_export(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<ExportQuery>,
+) -> Response {
+    if let Err(response) = require_admin(&state, &headers) {
+        return response;
+    }
+    let stream = backup::export(
+        state.pool.clone(),
+        state.cipher.clone(),
+        query.include_plaintext,
+        state.clock.now(),
+    );
+    (
+        [
+            (CONTENT_TYPE, "application/x-ndjson"),
+            (
+                CONTENT_DISPOSITION,
+                "attachment; filename=\"mayfile-export.jsonl\"",
+            ),
+        ],
+        Body::from_stream(stream),
+    )
+        .into_response()
+}
+
+/// Restore an uploaded backup, as `mayfile import` does.
+pub async fn admin_import(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<ImportQuery>,
+    body: String,
+) -> Response {
+    if let Err(response) = require_admin(&state, &headers) {
+        return response;
+    }
+    let max_total = state.config.load().paste.max_total_content_length;
+    let lines: Vec<std::io::Result<String>> =
+        body.lines().map(|line| Ok(line.to_string())).collect();
+    let summary = backup::import(
+        &state.pool,
+        state.cipher.as_ref(),
+        lines,
+        query.overwrite,
+        max_total,
+        state.clock.now(),
+    )
+    .await;
+    state.paste_cache.clear();
+    axum::Json(summary).into_response()
+}
+
+/// Re-read the configuration, for hosts where sending SIGHUP is awkward.
+pub async fn admin_reload(State(state): State<AppState>, headers: HeaderMap) -> impl IntoResponse {
+    if let Err(response) = require_admin(&state, &headers) {
+        return response;
+    }
+    match config::reload(&state) {
+        Ok(summary) => axum::Json(summary).into_response(),
+        Err(errors) => (
+            StatusCode::BAD_REQUEST,
+            axum::Json(serde_json::json!({ "error": "invalid_config", "errors": errors })),
+        )
+            .into_response(),
+    }
+}
+
+/// Take a database snapshot now, outside the `[backup]` schedule.
+pub async fn admin_backup(State(state): State<AppState>, headers: HeaderMap) -> impl IntoResponse {
+    if let Err(response) = require_admin(&state, &headers) {
+        return response;
+    }
+    match backup::backup_now(&state).await {
+        Some(Ok(snapshot)) => axum::Json(snapshot).into_response(),
+        Some(Err(_)) => json_error(StatusCode::INTERNAL_SERVER_ERROR, "internal"),
+        None => json_error(StatusCode::NOT_FOUND, "not_found"),
+    }
+}
+
+/// Room for the other form fields and their names on top of the content.
+const FORM_OVERHEAD: usize = 64 * 1024;
+
+/// The largest body a paste of `max_content_length` characters can need:
+/// four UTF-8 bytes per character, each percent-encoded to three.
+fn paste_body_limit(config: &PasteConfig) -> usize {
+    config.max_content_length.saturating_mul(12) + FORM_OVERHEAD
+}
+
+/// Middleware for paste-creating routes: bodies that cannot hold a valid
+/// paste are refused from `Content-Length` alone, and any other body stops
+/// being read once it passes the limit. The limit follows reloads of
+/// `max_content_length`. Either way the client gets `body_too_large`
+/// rather than the extractor's plain 413.
+pub async fn limit_paste_body(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    request: Request,
+    next: Next,
+) -> Response {
+    let limit = paste_body_limit(&state.config.load().paste);
+    let declared = request
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<usize>().ok());
+    if let Some(length) = declared.filter(|length| *length > limit) {
+        return body_too_large(&state, ip, request.headers(), length);
+    }
+    let headers = request.headers().clone();
+    let request = request.map(|body| Body::new(Limited::new(body, limit)));
+    let response = next.run(request).await;
+    if response.status() == StatusCode::PAYLOAD_TOO_LARGE {
+        return body_too_large(&state, ip, &headers, limit);
+    }
+    response
+}
+
+/// 413 with the too-long card for a body of at least `bytes`, or a JSON
+/// error for clients that asked for JSON.
+fn body_too_large(state: &AppState, ip: IpAddr, headers: &HeaderMap, bytes: usize) -> Response {
+    audit::refused(state, ip, "create", Refusal::Size, Some(bytes));
+    if wants_json(headers) {
+        return json_error(StatusCode::PAYLOAD_TOO_LARGE, "content_too_long");
+    }
+    let (lang, _) = select_language(headers, None);
+    let strings = state.i18n.load().strings(lang);
+    let size = SubmittedSize { chars: None, bytes };
+    let limit = state.config.load().paste.max_content_length as i64;
+    too_long_response(
+        state,
+        headers,
+        strings,
+        StatusCode::PAYLOAD_TOO_LARGE,
+        size,
+        limit,
+    )
+    .unwrap_or_else(IntoResponse::into_response)
+}
+
+/// What was sent, for the too-long card: the characters the limits count
+/// and the bytes received, or only the bytes of a body too large to read.
+struct SubmittedSize {
+    chars: Option<usize>,
+    bytes: usize,
+}
+
+impl SubmittedSize {
+    /// `content`, `content_length` characters long, and the further
+    /// `files`, as the limits count them.
+    fn of(content: &str, content_length: usize, files: &[NewFile]) -> Self {
+        let chars = files
+            .iter()
+            .map(|file| file.content.chars().count())
+            .sum::<usize>();
+        let bytes = files.iter().map(|file| file.content.len()).sum::<usize>();
+        SubmittedSize {
+            chars: Some(content_length + chars),
+            bytes: content.len() + bytes,
+        }
+    }
+}
+
+/// Content over `limit` characters, with what was sent next to it: the
+/// result-slot card for htmx, the card on a page of its own for a plain
+/// form post.
+fn too_long_response(
+    state: &AppState,
+    headers: &HeaderMap,
+    strings: Strings,
+    status: StatusCode,
+    size: SubmittedSize,
+    limit: i64,
+) -> Result<Response, AppError> {
+    let bytes = format_bytes(size.bytes);
+    let sent = match size.chars {
+        Some(chars) => strings
+            .too_long_size
+            .replacen("{}", &chars.to_string(), 1)
+            .replacen("{}", &bytes, 1),
+        None => strings.too_long_body.replace("{}", &bytes),
+    };
+    let limit = strings.too_long_limit.replace("{}", &limit.to_string());
+    let bytes_hint = size.chars != Some(size.bytes);
+    let body = if headers.contains_key("hx-request") {
+        TooLongTemplate {
+            strings,
+            size: sent,
+            limit,
+            bytes_hint,
+        }
+        .render()?
+    } else {
+        TooLongPageTemplate {
+            announcement: announcement(state, &strings),
+            has_about: state.about.load().get(&strings.lang).is_some(),
+            strings,
+            size: sent,
+            limit,
+            bytes_hint,
+        }
+        .render()?
+    };
+    Ok((status, Html(body)).into_response())
+}
+
+/// Middleware for paste-creating routes: banned clients are turned away
+/// before the handler runs at all.
+pub async fn reject_banned(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    request: Request,
+    next: Next,
+) -> Response {
+    if db::is_banned(&state.pool, ip, state.clock.now())
+        .await
+        .unwrap_or(false)
+    {
+        audit::refused(&state, ip, "create", Refusal::Blocklist, None);
+        return (StatusCode::FORBIDDEN, "Forbidden").into_response();
+    }
+    next.run(request).await
+}
+
+fn wants_json(headers: &HeaderMap) -> bool {
+    headers
+        .get(ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.contains("application/json"))
+}
+
+/// How long clients are asked to wait out maintenance before retrying.
+const MAINTENANCE_RETRY_AFTER_SECS: u64 = 300;
+
+/// Middleware for the whole router: `server.maintenance` answers every
+/// route outside `/admin` and `/assets` with the maintenance page, and
+/// `server.read_only` refuses anything but GET and HEAD outside `/admin`.
+/// Both follow reloads and `POST /admin/api/mode`.
+pub async fn service_mode(
+    State(state): State<AppState>,
+    Query(params): Query<HashMap<String, String>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let (read_only, maintenance) = {
+        let server = &state.config.load().server;
+        (server.read_only, server.maintenance)
+    };
+    let path = request.uri().path();
+    if path == "/admin"
+        || path.starts_with("/admin/")
+        || path == "/readyz"
+        || !(read_only || maintenance)
+    {
+        return Ok(next.run(request).await);
+    }
+    let headers = request.headers();
+    if maintenance && !path.starts_with("/assets/") {
+        let (lang, set_cookie) = select_page_language(headers, &params);
+        let strings = state.i18n.load().strings(lang);
+        let body = MaintenanceTemplate {
+            announcement: announcement(&state, &strings),
+            strings,
+        }
+        .render()?;
+        let mut response = (StatusCode::SERVICE_UNAVAILABLE, Html(body)).into_response();
+        response
+            .headers_mut()
+            .insert(RETRY_AFTER, HeaderValue::from(MAINTENANCE_RETRY_AFTER_SECS));
+        if let Some(cookie) = set_cookie {
+            response.headers_mut().insert(SET_COOKIE, cookie);
+        }
+        return Ok(response);
+    }
+    if read_only && !matches!(*request.method(), Method::GET | Method::HEAD) {
+        if wants_json(headers) {
+            return Ok(json_error(StatusCode::SERVICE_UNAVAILABLE, "read_only"));
+        }
+        let (lang, _) = select_language(headers, None);
+        let message = state.i18n.load().strings(lang).read_only;
+        return Ok((StatusCode::SERVICE_UNAVAILABLE, Html(message)).into_response());
+    }
+    Ok(next.run(request).await)
+}
+
+/// Bodies smaller than this go out uncompressed.
+pub const COMPRESS_MIN_BYTES: u16 = 1024;
+
+/// Middleware around the compression layer. A compressed body is no
+/// longer the bytes a strong `ETag` names, so the tag is made weak. A
+/// `HEAD` body is empty and never compressed, so it gets the `Vary` its
+/// `GET` would carry unless it is known to be small.
+pub async fn compression_headers(request: Request, next: Next) -> Response {
+    let head = request.method() == Method::HEAD;
+    let mut response = next.run(request).await;
+    let headers = response.headers_mut();
+    if headers.contains_key(CONTENT_ENCODING)
+        && let Some(tag) = headers.get(ETAG)
+        && !tag.as_bytes().starts_with(b"W/")
+        && let Ok(weak) = HeaderValue::from_bytes(&[b"W/", tag.as_bytes()].concat())
+    {
+        headers.insert(ETAG, weak);
+    }
+    let length = headers
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok()?.parse::<u64>().ok());
+    if head
+        && !headers.contains_key(VARY)
+        && length.is_none_or(|length| length >= COMPRESS_MIN_BYTES as u64)
+    {
+        headers.insert(VARY, HeaderValue::from_static("accept-encoding"));
+    }
+    response
+}
+
+/// Flip `server.read_only` or `server.maintenance` on the running
+/// config, e.g. `{"read_only": true}`. The config file is untouched, so the
+/// next reload or restart goes back to what it says.
+pub async fn admin_set_mode(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    axum::Json(mode): axum::Json<ServiceMode>,
+) -> impl IntoResponse {
+    if let Err(response) = require_admin(&state, &headers) {
+        return response;
+    }
+    // Retried against whatever a concurrent reload stores meanwhile, so
+    // neither change is lost.
+    let previous = state.config.rcu(|config| {
+        let mut config = AppConfig::clone(config);
+        if let Some(read_only) = mode.read_only {
+            config.server.read_only = read_only;
+        }
+        if let Some(maintenance) = mode.maintenance {
+            config.server.maintenance = maintenance;
+        }
+        config
+    });
+    let current = ServiceMode {
+        read_only: Some(mode.read_only.unwrap_or(previous.server.read_only)),
+        maintenance: Some(mode.maintenance.unwrap_or(previous.server.maintenance)),
+    };
+    axum::Json(current).into_response()
+}
+
+/// Reply to a successful admin action: htmx swaps the affected row out for
+/// the empty body, a plain form post goes back to the page, API clients get
+/// 204.
+fn admin_action_done(headers: &HeaderMap) -> Response {
+    if headers.contains_key("hx-request") {
+        return Html("").into_response();
+    }
+    let is_form = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/x-www-form-urlencoded"));
+    if is_form {
+        Redirect::to("/admin").into_response()
+    } else {
+        StatusCode::NO_CONTENT.into_response()
+    }
+}
+
+/// Whether the request carries the admin token, either as an
+/// `Authorization: Bearer` header or as the session cookie `/admin` sets.
+fn is_admin(state: &AppState, headers: &HeaderMap) -> bool {
+    admin_credential(state, headers).is_some()
+}
+
+/// How the request carries the admin token: `bearer` or `session`.
+fn admin_credential(state: &AppState, headers: &HeaderMap) -> Option<&'static str> {
+    let config = state.config.load();
+    let expected = config.server.admin_token.as_deref()?;
+    let bearer = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    // Compare digests so the check takes the same time however much matches
+    if bearer.is_some_and(|bearer| sha256_hex(bearer) == sha256_hex(expected)) {
+        return Some("bearer");
+    }
+    get_cookie(headers, "admin")
+        .is_some_and(|session| verify_admin_session(&state.signing_key, expected, &session))
+        .then_some("session")
+}
+
+/// Middleware for the whole router: with `server.log_security_events`
+/// set, every request to `/admin` or under it is logged with how it
+/// authenticated and how it was answered. Query strings are left out, as
+/// `/admin?token=` carries the token itself.
+pub async fn audit_admin(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let path = request.uri().path();
+    if !(path == "/admin" || path.starts_with("/admin/"))
+        || !state.config.load().server.log_security_events
+    {
+        return next.run(request).await;
+    }
+    let method = request.method().clone();
+    let path = path.to_string();
+    let credential = admin_credential(&state, request.headers());
+    let response = next.run(request).await;
+    audit::admin_call(&state, &method, &path, credential, response.status());
+    response
+}
+
+/// Admin routes pretend not to exist when no token is configured.
+#[allow(clippy::result_large_err)]
+fn require_admin(state: &AppState, headers: &HeaderMap) -> Result<(), Response> {
+    if state.config.load().server.admin_token.is_none() {
+        return Err(json_error(StatusCode::NOT_FOUND, "not_found"));
+    }
+    if is_admin(state, headers) {
+        Ok(())
+    } else {
+        Err(json_error(StatusCode::UNAUTHORIZED, "unauthorized"))
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/p/{token}/hash",
+    params(("token" = String, Path)),
+    responses(
+        (status = 200, description = "Hex SHA-256 of the content", body = String,
+            content_type = "text/plain"),
+        (status = 404, description = "No such paste", body = ApiError),
+    )
+)]
+pub async fn api_paste_hash(
+    State(state): State<AppState>,
+    PasteToken(token): PasteToken,
+) -> impl IntoResponse {
+    match db::fetch_paste_meta(&state.pool, &token, state.clock.now())
+        .await
+        .unwrap_or(None)
+        .and_then(|meta| meta.content_hash)
+    {
+        Some(hash) => hash.into_response(),
+        None => json_error(StatusCode::NOT_FOUND, "not_found"),
+    }
+}
+
+/// `GET /api/v1/stats/daily?days=30`: creations, expiries and views per day
+/// in the display timezone, oldest first, with quiet days as zeros.
+#[utoipa::path(
+    get,
+    path = "/api/v1/stats/daily",
+    params(("days" = Option<i64>, Query, description = "Days to cover, 30 by default")),
+    responses(
+        (status = 200, description = "One entry per day, oldest first", body = Vec<DailyActivity>),
+        (status = 400, description = "`days` is not a number", body = ApiError),
+        (status = 500, description = "Reading the event log failed", body = ApiError),
+    )
+)]
+pub async fn api_stats_daily(
+    State(state): State<AppState>,
+    Query(params): Query<HashMap<String, String>>,
+) -> impl IntoResponse {
+    let days = match params.get("days").map(|days| days.parse::<i64>()) {
+        None => 30,
+        Some(Ok(days)) => days.clamp(1, activity::MAX_DAYS),
+        Some(Err(_)) => return json_error(StatusCode::BAD_REQUEST, "invalid_days"),
+    };
+    let offset = parse_utc_offset(&state.config.load().server.display_timezone).unwrap_or(0);
+    match state
+        .activity
+        .get(&state.pool, days, offset, state.clock.now())
+        .await
+    {
+        Ok(activity) => axum::Json(activity).into_response(),
+        Err(_) => json_error(StatusCode::INTERNAL_SERVER_ERROR, "internal"),
+    }
+}
+
+/// `GET /api/v1/stats/languages?days=30`: pastes created per day in each
+/// configured language and `other`, in the display timezone, oldest first.
+#[utoipa::path(
+    get,
+    path = "/api/v1/stats/languages",
+    params(("days" = Option<i64>, Query, description = "Days to cover, 30 by default")),
+    responses(
+        (status = 200, description = "Counts per language and day", body = LanguageTimeline),
+        (status = 400, description = "`days` is not a number", body = ApiError),
+        (status = 500, description = "Reading the event log failed", body = ApiError),
+    )
+)]
+pub async fn api_stats_languages(
+    State(state): State<AppState>,
+    Query(params): Query<HashMap<String, String>>,
+) -> impl IntoResponse {
+    let days = match params.get("days").map(|days| days.parse::<i64>()) {
+        None => 30,
+        Some(Ok(days)) => days.clamp(1, activity::MAX_DAYS),
+        Some(Err(_)) => return json_error(StatusCode::BAD_REQUEST, "invalid_days"),
+    };
+    let config = state.config.load();
+    let offset = parse_utc_offset(&config.server.display_timezone).unwrap_or(0);
+    let mut languages: Vec<String> = config
+        .languages
+        .iter()
+        .map(|language| language.value.clone())
+        .collect();
+    match state
+        .activity
+        .get_languages(&state.pool, days, offset, state.clock.now())
+        .await
+    {
+        Ok(timeline) => {
+            let days = timeline
+                .into_iter()
+                .map(|day| activity::bucket_languages(day, &languages))
+                .collect();
+            languages.push("other".to_string());
+            axum::Json(LanguageTimeline { languages, days }).into_response()
+        }
+        Err(_) => json_error(StatusCode::INTERNAL_SERVER_ERROR, "internal"),
+    }
+}
+
+/// Days the index sparkline covers.
+const SPARKLINE_DAYS: i64 = 7;
+
+/// Tallest sparkline bar, in pixels.
+const SPARKLINE_HEIGHT: i64 = 24;
+
+/// Bars scaled to the busiest day; quiet days keep a sliver so the line
+/// stays readable.
+fn sparkline(activity: &[DailyActivity]) -> Vec<ActivityBar> {
+    let busiest = activity
+        .iter()
+        .map(|day| day.created)
+        .max()
+        .unwrap_or(0)
+        .max(1);
+    activity
+        .iter()
+        .map(|day| ActivityBar {
+            date: day.date.clone(),
+            created: day.created,
+            height: (day.created * SPARKLINE_HEIGHT / busiest).max(1),
+        })
+        .collect()
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/p/{token}/neighbors",
+    params(("token" = String, Path)),
+    responses(
+        (status = 200, description = "The public pastes created just before and after",
+            body = PasteNeighbors),
+        (status = 404, description = "Explore does not list this paste", body = ApiError),
+    )
+)]
+pub async fn api_paste_neighbors(
+    State(state): State<AppState>,
+    PasteToken(token): PasteToken,
+) -> impl IntoResponse {
+    match db::paste_neighbors(&state.pool, &token, state.clock.now()).await {
+        Ok(Some(Neighbors { prev, next })) => {
+            let config = state.config.load();
+            let url = |neighbor: &Option<Neighbor>| {
+                neighbor
+                    .as_ref()
+                    .map(|neighbor| absolute_url(&config.server, &format!("/p/{}", neighbor.token)))
+            };
+            axum::Json(PasteNeighbors {
+                prev_url: url(&prev),
+                next_url: url(&next),
+                prev,
+                next,
+            })
+            .into_response()
+        }
+        Ok(None) => json_error(StatusCode::NOT_FOUND, "not_found"),
+        Err(_) => json_error(StatusCode::INTERNAL_SERVER_ERROR, "internal"),
+    }
+}
+
+/// Row counts plus how eviction and backups have gone since startup.
+#[utoipa::path(
+    get,
+    path = "/api/v1/stats",
+    responses(
+        (status = 200, description = "Counts, evictions, backups and capacity", body = ApiStats),
+        (status = 500, description = "Reading the database failed", body = ApiError),
+    )
+)]
+pub async fn api_stats(State(state): State<AppState>) -> impl IntoResponse {
+    match (
+        db::stats(&state.pool).await,
+        db::daily_events(&state.pool).await,
+    ) {
+        (Ok(stats), Ok(daily)) => axum::Json(ApiStats {
+            capacity: Capacity::new(
+                stats.pastes,
+                stats.content_length,
+                &state.config.load().paste,
+            ),
+            stats,
+            evictions: state.metrics.evictions(),
+            evictions_by_policy: state.metrics.evictions_by_policy(),
+            backup: state.metrics.backup_status(),
+            webhooks: state.metrics.webhook_status(),
+            cache: state.paste_cache.status(),
+            daily,
+            tokens: TokenSpace::new(&state.config.load().paste, metrics::token_collisions()),
+            busy_retries: metrics::busy_retries(),
+        })
+        .into_response(),
+        _ => json_error(StatusCode::INTERNAL_SERVER_ERROR, "internal"),
+    }
+}
+
+/// What creating a paste may ask for here, from the config and a
+/// briefly cached count of what is stored.
+#[utoipa::path(
+    get,
+    path = "/api/v1/limits",
+    responses(
+        (status = 200, description = "Limits, options and capacity headroom", body = ApiLimits),
+        (status = 500, description = "Reading the database failed", body = ApiError),
+    )
+)]
+pub async fn api_limits(State(state): State<AppState>) -> impl IntoResponse {
+    let Ok((pastes, content_length)) = state.usage.get(&state.pool, state.clock.now()).await else {
+        return json_error(StatusCode::INTERNAL_SERVER_ERROR, "internal");
+    };
+    let config = state.config.load();
+    let paste = &config.paste;
+    let mut token_lengths = paste.token_lengths.clone();
+    token_lengths.push(paste.default_token_length);
+    token_lengths.sort_unstable();
+    token_lengths.dedup();
+    let capacity = Capacity::new(pastes, content_length, paste);
+    axum::Json(ApiLimits {
+        max_content_length: paste.max_content_length,
+        max_title_length: MAX_TITLE_CHARS,
+        max_author_length: if paste.allow_author {
+            MAX_AUTHOR_CHARS
+        } else {
+            0
+        },
+        expiry: ExpiryLimits {
+            options: paste.expires_options_secs.clone(),
+            default: paste.default_expires_secs,
+            max_secs: max_expires_secs(paste),
+        },
+        tokens: TokenLimits {
+            lengths: token_lengths,
+            default: paste.default_token_length,
+            case_insensitive: paste.case_insensitive_tokens,
+            slug_min_length: *slug::SLUG_LENGTHS.start(),
+            slug_max_length: *slug::SLUG_LENGTHS.end(),
+        },
+        languages: std::iter::once("auto".to_string())
+            .chain(
+                config
+                    .languages
+                    .iter()
+                    .map(|language| language.value.clone()),
+            )
+            .collect(),
+        max_views_limit: paste.max_views_limit,
+        destroy_after_options: DESTROY_AFTER_OPTIONS_SECS
+            .into_iter()
+            .filter(|secs| *secs <= max_expires_secs(paste))
+            .collect(),
+        features: FeatureLimits {
+            custom_slugs: true,
+            never_expire: false,
+            burn_after_reading: true,
+            public: true,
+            author: paste.allow_author,
+            related_links: paste.max_related_links > 0,
+            html_rendering: paste.allow_html_rendering,
+            read_only: config.server.read_only,
+        },
+        headroom: Headroom {
+            pastes: (capacity.max_pastes - pastes).max(0),
+            content_length: (capacity.max_content_length - content_length).max(0),
+        },
+        capacity,
+    })
+    .into_response()
+}
+
+/// `GET /api/v1/openapi.json`: the OpenAPI document for `/api/v1`.
+pub async fn api_openapi() -> impl IntoResponse {
+    axum::Json(ApiDoc::openapi())
+}
+
+// Helper functions moved from main.rs
+
+/// Drop expired pastes, and events older than the retention window.
+async fn cleanup_expired(state: &AppState) {
+    let (retention, webhooks) = {
+        let config = state.config.load();
+        (config.paste.event_retention_secs, config.webhooks.is_some())
+    };
+    let now = state.clock.now();
+    let expired = db::cleanup_expired(&state.pool, retention, webhooks, now).await;
+    for token in &expired.tokens {
+        state.paste_cache.forget(token);
+    }
+    state.maintenance.record(now, expired);
+}
+
+/// Apply `max_pastes`, keeping `reserve` slots free, and count what goes.
+async fn enforce_size_limit(state: &AppState, reserve: i64) {
+    let (max, policy) = {
+        let config = state.config.load();
+        (config.paste.max_pastes, config.paste.eviction_policy)
+    };
+    let now = state.clock.now();
+    let evicted = db::enforce_size_limit(&state.pool, max, reserve, policy, now).await;
+    state
+        .metrics
+        .record_policy_evictions(policy, evicted.deleted);
+    for token in &evicted.tokens {
+        state.paste_cache.forget(token);
+    }
+    state.maintenance.record(now, evicted);
+}
+
+/// The locale for a request: `requested` (the `lang` query parameter) wins
+/// and comes with a cookie remembering it, then the `lang` cookie, then
+/// `Accept-Language`, then Chinese.
+pub fn select_language(
+    headers: &HeaderMap,
+    requested: Option<&str>,
+) -> (Lang, Option<HeaderValue>) {
+    if let Some(lang) = requested.and_then(Lang::from_code) {
+        let cookie = format!("lang={}; Path=/; Max-Age=31536000", lang.code());
+        let cookie = HeaderValue::from_str(&cookie).expect("locale codes are ASCII");
+        return (lang, Some(cookie));
+    }
+
+    if let Some(cookie) = headers.get(COOKIE).and_then(|v| v.to_str().ok()) {
+        for part in cookie.split(';') {
+            if let Some(lang) = part.trim().strip_prefix("lang=").and_then(Lang::from_code) {
+                return (lang, None);
+            }
+        }
+    }
+
+    let lang = match headers
+        .get("accept-language")
+        .and_then(|value| value.to_str().ok())
+    {
+        Some(accept) => accept_language(accept).unwrap_or(Lang::En),
+        None => Lang::Zh,
+    };
+    (lang, None)
+}
+
+/// The most preferred locale in an `Accept-Language` header, by `q` and
+/// then order, matching on the primary subtag: `ja-JP` is `ja`.
+fn accept_language(header: &str) -> Option<Lang> {
+    let mut tags: Vec<(f32, Lang)> = header
+        .split(',')
+        .filter_map(|entry| {
+            let mut parts = entry.split(';');
+            let tag = parts.next()?.trim().to_lowercase();
+            let lang = Lang::from_code(tag.split('-').next()?)?;
+            let q = parts
+                .find_map(|param| param.trim().strip_prefix("q="))
+                .map_or(Some(1.0), |q| q.trim().parse::<f32>().ok())?;
+            (q > 0.0).then_some((q, lang))
+        })
+        .collect();
+    // Stable, so equal weights keep the header's order
+    tags.sort_by(|a, b| b.0.total_cmp(&a.0));
+    tags.first().map(|(_, lang)| *lang)
+}
+
+/// `select_language` for a page's query parameters. `langonly`, or `lang`
+/// with `remember=0`, renders this one response in that language without
+/// touching the cookie, for links shared as "view this in English";
+/// `langonly` goes before `lang`. Otherwise it is `select_language` with
+/// `lang`.
+pub fn select_page_language(
+    headers: &HeaderMap,
+    params: &HashMap<String, String>,
+) -> (Lang, Option<HeaderValue>) {
+    let forget = params.get("remember").is_some_and(|value| value == "0");
+    let once = params
+        .get("langonly")
+        .filter(|lang| Lang::from_code(lang).is_some())
+        .or(params.get("lang").filter(|_| forget));
+    if let Some(lang) = once {
+        let (lang, _) = select_language(headers, Some(lang));
+        return (lang, None);
+    }
+    select_language(headers, params.get("lang").map(String::as_str))
+}
+
+/// Value of the named cookie, if the request carries it.
+pub fn get_cookie(headers: &HeaderMap, name: &str) -> Option<String> {
+    let cookie = headers.get(COOKIE)?.to_str().ok()?;
+    cookie.split(';').find_map(|part| {
+        let (key, value) = part.trim().split_once('=')?;
+        (key == name).then(|| value.to_string())
+    })
+}
+
+/// Length of the random id in an `owner` cookie.
+const OWNER_ID_LENGTH: usize = 24;
+
+/// The owner id in the request's `owner` cookie, `{id}.{signature}`, if the
+/// signature holds. A missing, unsigned or forged cookie counts as none.
+pub fn verify_owner_cookie(headers: &HeaderMap, key: &[u8]) -> Option<String> {
+    let cookie = get_cookie(headers, "owner")?;
+    let (owner_id, signature) = cookie.split_once('.')?;
+    verify_owner_signature(key, owner_id, signature).then(|| owner_id.to_string())
+}
+
+/// The `Set-Cookie` handing out `owner_id`, Secure when the request came
+/// in over TLS.
+fn owner_cookie(
+    key: &[u8],
+    config: &ServerConfig,
+    headers: &HeaderMap,
+    owner_id: &str,
+) -> Option<HeaderValue> {
+    let secure = if over_tls(config, headers) {
+        "; Secure"
+    } else {
+        ""
+    };
+    let cookie = format!(
+        "owner={}.{}; Path=/; Max-Age=31536000; HttpOnly; SameSite=Lax{}",
+        owner_id,
+        owner_signature(key, owner_id),
+        secure
+    );
+    HeaderValue::from_str(&cookie).ok()
+}
+
+/// Whether clients reach this server over HTTPS: `base_url` says so, or
+/// the proxy in front sets `X-Forwarded-Proto: https`.
+fn over_tls(config: &ServerConfig, headers: &HeaderMap) -> bool {
+    config
+        .base_url
+        .as_deref()
+        .is_some_and(|base_url| base_url.starts_with("https://"))
+        || headers
+            .get("x-forwarded-proto")
+            .is_some_and(|proto| proto.as_bytes() == b"https")
+}
+
+/// Whether the request carries the signed `owner` cookie a paste was
+/// created with.
+pub fn is_owner(headers: &HeaderMap, key: &[u8], owner_id: Option<&str>) -> bool {
+    match (verify_owner_cookie(headers, key), owner_id) {
+        (Some(cookie), Some(owner_id)) => cookie == owner_id,
+        _ => false,
+    }
+}
+
+/// `POST /mine/rotate`: swap the caller's owner id for a fresh one and move
+/// their pastes over to it, so a leaked cookie stops working. Without a
+/// valid cookie there is nothing to move and this just hands out a new one.
+pub async fn rotate_owner(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    let config = state.config.load();
+    let owner_id = generate_token(OWNER_ID_LENGTH);
+    let moved = match verify_owner_cookie(&headers, &state.signing_key) {
+        Some(old) => db::reassign_owner(&state.pool, &old, &owner_id).await,
+        None => Ok(0),
+    };
+    state.paste_cache.clear();
+    let Ok(moved) = moved else {
+        return json_error(StatusCode::INTERNAL_SERVER_ERROR, "internal");
+    };
+    let mut response = if wants_json(&headers) {
+        axum::Json(serde_json::json!({ "moved": moved })).into_response()
+    } else {
+        Redirect::to("/").into_response()
+    };
+    if let Some(cookie) = owner_cookie(&state.signing_key, &config.server, &headers, &owner_id) {
+        response.headers_mut().insert(SET_COOKIE, cookie);
+    }
+    response
+}
+
+pub fn build_expires_options(config: &PasteConfig, strings: &Strings) -> Vec<ExpiresOption> {
+    config
+        .expires_options_secs
+        .iter()
+        .map(|value| ExpiresOption {
+            value: *value,
+            label: format_expires_label(*value, strings),
+            selected: *value == config.default_expires_secs,
+        })
+        .collect()
+}
+
+/// Timers offered for self-destructing after the first view, in seconds.
+const DESTROY_AFTER_OPTIONS_SECS: [i64; 4] = [60, 600, 3600, 86400];
+
+pub fn build_destroy_after_options(config: &PasteConfig, strings: &Strings) -> Vec<ExpiresOption> {
+    let max = max_expires_secs(config);
+    DESTROY_AFTER_OPTIONS_SECS
+        .iter()
+        .filter(|value| **value <= max)
+        .map(|value| ExpiresOption {
+            value: *value,
+            label: format_expires_label(*value, strings),
+            selected: false,
+        })
+        .collect()
+}
+
+pub fn build_token_length_options(
+    config: &PasteConfig,
+    strings: &Strings,
+) -> Vec<TokenLengthOption> {
+    config
+        .token_lengths
+        .iter()
+        .map(|value| TokenLengthOption {
+            value: *value,
+            label: strings.token_length_label.replace("{}", &value.to_string()),
+            selected: *value == config.default_token_length,
+        })
+        .collect()
+}
+
+pub fn build_language_options(
+    languages: &[LanguageConfig],
+    strings: &Strings,
+    selected_lang: Option<&str>,
+) -> Vec<LanguageOption> {
+    let target = selected_lang.unwrap_or("auto");
+    let auto = LanguageOption {
+        value: "auto".to_string(),
+        label: strings.language_auto.clone(),
+        selected: target == "auto",
+    };
+    std::iter::once(auto)
+        .chain(languages.iter().map(|language| LanguageOption {
+            value: language.value.clone(),
+            label: language.label(strings),
+            selected: target == language.value,
+        }))
+        .collect()
+}
+
+/// Display name of a language value, or the value itself if unknown.
+fn language_label(languages: &[LanguageConfig], strings: &Strings, language: &str) -> String {
+    if language == "auto" {
+        return strings.language_auto.clone();
+    }
+    languages
+        .iter()
+        .find(|option| option.value == language)
+        .map(|option| option.label(strings))
+        .unwrap_or_else(|| language.to_string())
+}
+
+pub fn format_expires_label(secs: i64, strings: &Strings) -> String {
+    if secs >= 86400 && secs % 86400 == 0 {
+        strings.plural(&strings.expires_days, secs / 86400)
+    } else if secs >= 3600 && secs % 3600 == 0 {
+        strings.plural(&strings.expires_hours, secs / 3600)
+    } else if secs >= 60 && secs % 60 == 0 {
+        strings.plural(&strings.expires_minutes, secs / 60)
+    } else {
+        strings.plural(&strings.expires_seconds, secs)
+    }
+}
+
+/// The settings views are counted under.
+fn view_rules(config: &AppConfig) -> db::ViewRules<'_> {
+    db::ViewRules {
+        popularity: config.paste.popularity.as_ref(),
+        max_expires_secs: max_expires_secs(&config.paste),
+        webhooks: config.webhooks.is_some(),
+    }
+}
+
+pub fn max_expires_secs(config: &PasteConfig) -> i64 {
+    config
+        .expires_options_secs
+        .iter()
+        .copied()
+        .max()
+        .unwrap_or(86400 * 7)
+}
+
+pub fn normalize_expires_in(expires_in: Option<i64>, config: &PasteConfig) -> i64 {
+    let value = expires_in.unwrap_or(config.default_expires_secs);
+    if config.expires_options_secs.contains(&value) {
+        value
+    } else {
+        config.default_expires_secs
+    }
+}
+
+/// An RFC 3339 expiry in the future, brought in to the longest expiry
+/// option from `now`.
+pub fn resolve_expires_at_abs(value: &str, now: i64, config: &PasteConfig) -> Option<i64> {
+    parse_rfc3339(value)
+        .filter(|ts| *ts > now)
+        .map(|ts| ts.min(now + max_expires_secs(config)))
+}
+
+pub fn normalize_token_length(token_length: Option<usize>, config: &PasteConfig) -> usize {
+    let value = token_length.unwrap_or(config.default_token_length);
+    if config.token_lengths.contains(&value) {
+        value
+    } else {
+        config.default_token_length
+    }
+}
+
+/// Short names clients commonly send, mapped to the value they stand for.
+const LANGUAGE_ALIASES: &[(&str, &str)] = &[
+    ("js", "javascript"),
+    ("ts", "typescript"),
+    ("py", "python"),
+    ("c++", "cpp"),
+    ("cxx", "cpp"),
+    ("sh", "bash"),
+    ("shell", "bash"),
+    ("zsh", "bash"),
+    ("yml", "yaml"),
+];
+
+/// The canonical value for `name`: a configured alias wins over a built-in
+/// one, and anything else is returned lowercased as is.
+pub fn resolve_language_alias(languages: &[LanguageConfig], name: &str) -> String {
+    let name = name.trim().to_lowercase();
+    if let Some(language) = languages
+        .iter()
+        .find(|language| language.aliases.contains(&name))
+    {
+        return language.value.clone();
+    }
+    match LANGUAGE_ALIASES.iter().find(|(alias, _)| *alias == name) {
+        Some((_, value)) => value.to_string(),
+        None => name,
+    }
+}
+
+pub fn normalize_language(languages: &[LanguageConfig], language: Option<String>) -> String {
+    let value = resolve_language_alias(languages, language.as_deref().unwrap_or("auto"));
+    if is_allowed_language(languages, &value) {
+        value
+    } else {
+        "auto".to_string()
+    }
+}
+
+pub fn is_allowed_language(languages: &[LanguageConfig], value: &str) -> bool {
+    value == "auto" || languages.iter().any(|language| language.value == value)
+}
+
+/// Longest title kept, in characters.
+const MAX_TITLE_CHARS: usize = 80;
+
+const MAX_AUTHOR_CHARS: usize = 32;
+
+/// `text` on one line of at most `max` characters: line breaks and other
+/// control characters become spaces, and the ends are trimmed.
+fn clean_line(text: &str, max: usize) -> String {
+    let line: String = text
+        .chars()
+        .map(|c| match c {
+            '\u{2028}' | '\u{2029}' => ' ',
+            c if c.is_control() => ' ',
+            c => c,
+        })
+        .collect();
+    let kept: String = line.trim().chars().take(max).collect();
+    kept.trim_end().to_string()
+}
+
+/// The nickname to sign a paste with, cleaned like a title; `None` if it
+/// is empty or `allow_author` is off. A leading `~` is dropped, since the
+/// pages add one.
+pub fn normalize_author(author: Option<String>, config: &PasteConfig) -> Option<String> {
+    if !config.allow_author {
+        return None;
+    }
+    let author = author.unwrap_or_default();
+    let author = clean_line(
+        author.trim_start().trim_start_matches('~'),
+        MAX_AUTHOR_CHARS,
+    );
+    (!author.is_empty()).then_some(author)
+}
+
+/// Comment openers dropped from the start of a line when deriving a title,
+/// longest first so `///` is not read as `//` followed by text.
+const COMMENT_MARKERS: &[&str] = &["<!--", "/**", "///", "//!", "/*", "//", "--", "#"];
+
+/// `text` without the comment opener it starts with, if any. A lone `*`
+/// only counts as one when it leads a block comment line, so `**bold**`
+/// keeps its stars.
+fn strip_comment_marker(text: &str) -> Option<&str> {
+    if let Some(rest) = text.strip_prefix('*')
+        && (rest.is_empty() || rest.starts_with(char::is_whitespace))
+    {
+        return Some(rest);
+    }
+    COMMENT_MARKERS
+        .iter()
+        .find_map(|marker| text.strip_prefix(marker))
+}
+
+/// Languages in which a line starting with `#` is code (attributes,
+/// preprocessor directives) rather than a comment.
+const HASH_IS_CODE: &[&str] = &["rust", "cpp", "c"];
+
+/// The words of a content line worth using as a title, or `None` for a
+/// shebang, a license or editor header, code where `#` is not a comment,
+/// or a line that is only markers and punctuation.
+fn title_text(line: &str, language: &str) -> Option<String> {
+    let mut text = line.trim();
+    if text.starts_with("#!") && !text.starts_with("#![") {
+        return None;
+    }
+    if text.starts_with('#') && (HASH_IS_CODE.contains(&language) || text.starts_with("#[")) {
+        return None;
+    }
+    while let Some(rest) = strip_comment_marker(text) {
+        text = rest.trim_start();
+    }
+    let text = text
+        .trim_end()
+        .trim_end_matches("*/")
+        .trim_end_matches("-->")
+        .trim();
+    let header = text.starts_with("SPDX-License-Identifier:")
+        || text.starts_with("-*-")
+        || text.starts_with("vim:");
+    (!header && text.chars().any(char::is_alphanumeric)).then(|| text.to_string())
+}
+
+/// The submitted title, cleaned; failing that, the first line of `content`
+/// with words in it, comment markers stripped; failing that, "Untitled".
+pub fn normalize_title(title: Option<String>, content: &str, language: &str) -> String {
+    let title = clean_line(&title.unwrap_or_default(), MAX_TITLE_CHARS);
+    if !title.is_empty() {
+        return title;
+    }
+    content
+        .lines()
+        .filter_map(|line| title_text(&clean_line(line, usize::MAX), language))
+        .map(|text| clean_line(&text, MAX_TITLE_CHARS))
+        .next()
+        .unwrap_or_else(|| "Untitled".to_string())
+}
+
+/// Title for a client-side encrypted paste: only an explicit plaintext label
+/// is used, never anything derived from the ciphertext.
+pub fn normalize_e2e_title(title: Option<String>) -> String {
+    let title = clean_line(&title.unwrap_or_default(), MAX_TITLE_CHARS);
+    if title.is_empty() {
+        "Encrypted paste".to_string()
+    } else {
+        title
+    }
+}
+
+/// Store line endings as LF. With `trim_trailing`, also drop whitespace at
+/// the end of each line and blank lines at the end, keeping the final line
+/// break if there was one.
+pub fn normalize_content(content: &str, trim_trailing: bool) -> String {
+    let content = content.replace("\r\n", "\n").replace('\r', "\n");
+    if !trim_trailing {
+        return content;
+    }
+    let lines: Vec<&str> = content.lines().map(str::trim_end).collect();
+    let mut trimmed = lines.join("\n").trim_end_matches('\n').to_string();
+    if content.ends_with('\n') && !trimmed.is_empty() {
+        trimmed.push('\n');
+    }
+    trimmed
+}
+
+/// HTML checkboxes submit "on"; API clients tend to send "1" or "true".
+pub fn is_checked(value: Option<&str>) -> bool {
+    matches!(value, Some("on" | "1" | "true"))
+}
+
+/// A positive view budget no larger than `max_views_limit`. A number too
+/// long to parse asks for the limit.
+pub fn normalize_max_views(max_views: Option<String>, config: &PasteConfig) -> Option<i64> {
+    let views = match max_views?.trim().parse::<i64>() {
+        Ok(views) => views,
+        Err(err) if *err.kind() == IntErrorKind::PosOverflow => i64::MAX,
+        Err(_) => return None,
+    };
+    (views > 0).then(|| views.min(config.max_views_limit))
+}
+
+/// Split a trailing `.rs`-style extension off a token path segment. A
+/// lone trailing dot is left for `normalize_token` to drop.
+pub fn split_extension(raw: &str) -> (&str, Option<&str>) {
+    let trimmed = raw.trim_end_matches(TOKEN_TRAILING_ARTIFACTS);
+    match trimmed.rsplit_once('.') {
+        Some((token, extension))
+            if !token.is_empty()
+                && (1..=10).contains(&extension.len())
+                && extension.bytes().all(|b| b.is_ascii_alphanumeric()) =>
+        {
+            (token, Some(extension))
+        }
+        _ => (raw, None),
+    }
+}
+
+/// The configured language a link extension such as `rs` or `yml` names,
+/// by its `/r/` extension first and then as an alias.
+pub fn language_for_extension(languages: &[LanguageConfig], extension: &str) -> Option<String> {
+    let extension = extension.to_lowercase();
+    if let Some(language) = languages
+        .iter()
+        .find(|language| language.extension == extension)
+    {
+        return Some(language.value.clone());
+    }
+    let value = resolve_language_alias(languages, &extension);
+    languages
+        .iter()
+        .any(|language| language.value == value)
+        .then_some(value)
+}
+
+/// What people tend to carry along when copying a link out of a sentence or
+/// a chat message.
+const TOKEN_TRAILING_ARTIFACTS: &[char] =
+    &['.', ',', ';', ':', '!', '?', ')', ']', '}', '>', '"', '\''];
+
+/// The paste token a path segment most likely means: trailing punctuation
+/// dropped, then checked against `token_lengths` and the token alphabet,
+/// or taken as a custom slug. `None` for anything neither `generate_token`
+/// nor a creator could have produced.
+pub fn normalize_token(raw: &str, config: &PasteConfig) -> Option<String> {
+    let min = config.token_lengths.iter().min().copied().unwrap_or(1);
+    let max = config
+        .token_lengths
+        .iter()
+        .max()
+        .copied()
+        .unwrap_or(usize::MAX);
+    check_token(raw, min..=max).or_else(|| {
+        slug::validate_slug(
+            raw.trim_end_matches(TOKEN_TRAILING_ARTIFACTS),
+            config.case_insensitive_tokens,
+        )
+        .ok()
+    })
+}
+
+/// Like `normalize_token`, for the secret in a management link.
+pub fn normalize_manage_token(raw: &str) -> Option<String> {
+    check_token(raw, db::MANAGE_TOKEN_LENGTH..=db::MANAGE_TOKEN_LENGTH)
+}
+
+fn check_token(raw: &str, lengths: RangeInclusive<usize>) -> Option<String> {
+    let token = raw.trim_end_matches(TOKEN_TRAILING_ARTIFACTS);
+    (lengths.contains(&token.len()) && token.bytes().all(|b| b.is_ascii_alphanumeric()))
+        .then(|| token.to_string())
+}
+
+/// A positive self-destruct timer, capped at the longest expiry option.
+pub fn normalize_destroy_after(secs: Option<String>, config: &PasteConfig) -> Option<i64> {
+    secs.and_then(|s| s.parse::<i64>().ok())
+        .filter(|&v| v > 0)
+        .map(|v| v.min(max_expires_secs(config)))
+}
+
+/// A go-live time in the future, as a Unix timestamp or a local
+/// `YYYY-MM-DDTHH:MM` in `display_timezone`. Anything else, including a
+/// time already past, means visible right away.
+pub fn normalize_visible_after(
+    value: Option<String>,
+    config: &ServerConfig,
+    now: i64,
+) -> Option<i64> {
+    let value = value?;
+    let value = value.trim();
+    let ts = match value.parse::<i64>() {
+        Ok(ts) => ts,
+        Err(_) => {
+            let offset = parse_utc_offset(&config.display_timezone).unwrap_or(0);
+            parse_local_datetime(value, offset)?
+        }
+    };
+    (ts > now).then_some(ts)
+}
+
+/// "Scheduled, goes live at ..." for a `visible_after` still to come.
+fn scheduled_label(
+    visible_after: Option<i64>,
+    config: &AppConfig,
+    strings: &Strings,
+    now: i64,
+) -> Option<String> {
+    let ts = visible_after.filter(|ts| *ts > now)?;
+    let offset = parse_utc_offset(&config.server.display_timezone).unwrap_or(0);
+    Some(
+        strings
+            .detail_scheduled
+            .replace("{}", &format_iso8601(ts, offset)),
+    )
+}
+
+/// How long ago `created_at` was, e.g. "3 minutes ago".
+pub fn format_relative_time(created_at: i64, now: i64, strings: &Strings) -> String {
+    let elapsed = (now - created_at).max(0);
+    if elapsed < 60 {
+        strings.created_just_now.clone()
+    } else if elapsed < 3600 {
+        strings.plural(&strings.created_minutes, elapsed / 60)
+    } else if elapsed < 86400 {
+        strings.plural(&strings.created_hours, elapsed / 3600)
+    } else {
+        strings.plural(&strings.created_days, elapsed / 86400)
+    }
+}
+
+/// `ts` relative to now and in `server.display_timezone`.
+pub fn display_time(ts: i64, now: i64, config: &AppConfig, strings: &Strings) -> DisplayTime {
+    let offset = parse_utc_offset(&config.server.display_timezone).unwrap_or(0);
+    DisplayTime {
+        relative: format_relative_time(ts, now, strings),
+        iso: format_iso8601(ts, offset),
+    }
+}
+
+/// Where a paste is in its life: the share of `original_duration` left
+/// until `expires_at`, above one half vibrant and above one quarter fading.
+pub fn life_stage(original_duration: i64, expires_at: i64, now: i64) -> LifeStage {
+    let remaining = expires_at - now;
+    if original_duration <= 0 || remaining * 4 <= original_duration {
+        LifeStage::Dying
+    } else if remaining * 2 <= original_duration {
+        LifeStage::Fading
+    } else {
+        LifeStage::Vibrant
+    }
+}
+
+/// The share of `original_duration` left until `expires_at`, in [0, 1].
+/// A renewed paste can have more time left than it started with; it
+/// still counts as full.
+pub fn life_fraction(original_duration: i64, expires_at: i64, now: i64) -> f64 {
+    if original_duration <= 0 {
+        return 0.0;
+    }
+    ((expires_at - now) as f64 / original_duration as f64).clamp(0.0, 1.0)
+}
+
+/// Whether a paste can be renewed at `now`. Private, view-limited and
+/// self-destructing pastes (`renewable` false) never can; the rest once
+/// less than half of `original_duration` is left, until they expire.
+/// `renew_paste` and every page with a renew button go by this.
+pub fn renewal(renewable: bool, original_duration: i64, expires_at: i64, now: i64) -> Renewal {
+    if !renewable {
+        return Renewal::default();
+    }
+    // The first second with less than half left.
+    let available_at = expires_at - original_duration / 2 + 1;
+    Renewal {
+        renew_eligible: available_at <= now && now < expires_at,
+        renew_available_at: Some(available_at),
+    }
+}
+
+pub fn format_duration(expires_at: i64, now: i64, strings: &Strings) -> String {
+    let remaining = expires_at - now;
+    if remaining <= 0 {
+        return strings.duration_expired.clone();
+    }
+    if remaining < 60 {
+        return strings.plural(&strings.duration_seconds, remaining);
+    }
+    if remaining < 3600 {
+        return strings.plural(&strings.duration_minutes, remaining / 60);
+    }
+    if remaining < 86400 {
+        return strings.plural(&strings.duration_hours, remaining / 3600);
+    }
+    strings.plural(&strings.duration_days, remaining / 86400)
+}
+
+#[cfg(test)]
 mod tests {
     use super::*;
+    use crate::crypto::ContentCipher;
+    use crate::test_support::{body_string, test_config, test_state, update_config};
+    use crate::utils::now_ts;
+    use proptest::prelude::*;
+    use std::collections::BTreeMap;
+    use std::net::Ipv4Addr;
+    use std::sync::Arc;
+
+    fn local_client() -> ClientIp {
+        ClientIp(Ipv4Addr::LOCALHOST.into())
+    }
+
+    #[test]
+    fn test_select_page_language() {
+        let headers = |cookie: Option<&str>, accept: Option<&str>| {
+            let mut headers = HeaderMap::new();
+            if let Some(cookie) = cookie {
+                headers.insert(COOKIE, HeaderValue::from_str(cookie).unwrap());
+            }
+            if let Some(accept) = accept {
+                headers.insert("accept-language", HeaderValue::from_str(accept).unwrap());
+            }
+            headers
+        };
+        let params = |pairs: &[(&str, &str)]| -> HashMap<String, String> {
+            pairs
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect()
+        };
+        let remembered = |lang: &str| Some(format!("lang={}; Path=/; Max-Age=31536000", lang));
+        let cookie = Some("lang=zh");
+        let accept = Some("en-US,en;q=0.9");
+        for (pairs, cookie, accept, lang, set_cookie) in [
+            // No parameter: the cookie, then Accept-Language, then Chinese
+            (&[][..], None, None, Lang::Zh, None),
+            (&[], None, accept, Lang::En, None),
+            (&[], cookie, accept, Lang::Zh, None),
+            (&[("remember", "0")], cookie, accept, Lang::Zh, None),
+            (&[("remember", "1")], None, accept, Lang::En, None),
+            // `lang` wins and is remembered unless `remember=0`
+            (&[("lang", "en")], cookie, None, Lang::En, remembered("en")),
+            (
+                &[("lang", "en"), ("remember", "1")],
+                cookie,
+                None,
+                Lang::En,
+                remembered("en"),
+            ),
+            (
+                &[("lang", "en"), ("remember", "0")],
+                cookie,
+                None,
+                Lang::En,
+                None,
+            ),
+            (
+                &[("lang", "zh"), ("remember", "0")],
+                None,
+                accept,
+                Lang::Zh,
+                None,
+            ),
+            (&[("lang", "fr")], cookie, accept, Lang::Zh, None),
+            (
+                &[("lang", "fr"), ("remember", "0")],
+                None,
+                accept,
+                Lang::En,
+                None,
+            ),
+            // `langonly` wins over everything and is never remembered
+            (&[("langonly", "en")], cookie, None, Lang::En, None),
+            (&[("langonly", "zh")], None, accept, Lang::Zh, None),
+            (
+                &[("langonly", "zh"), ("remember", "1")],
+                None,
+                accept,
+                Lang::Zh,
+                None,
+            ),
+            (
+                &[("langonly", "zh"), ("lang", "en")],
+                None,
+                accept,
+                Lang::Zh,
+                None,
+            ),
+            (&[("langonly", "fr")], cookie, accept, Lang::Zh, None),
+            // Japanese goes through the same paths
+            (&[], Some("lang=ja"), accept, Lang::Ja, None),
+            (&[], None, Some("ja-JP,en;q=0.5"), Lang::Ja, None),
+            (&[("lang", "ja")], cookie, None, Lang::Ja, remembered("ja")),
+            (&[("langonly", "ja")], cookie, None, Lang::Ja, None),
+            (
+                &[("langonly", "fr"), ("lang", "en")],
+                cookie,
+                None,
+                Lang::En,
+                remembered("en"),
+            ),
+        ] {
+            let (selected, set) = select_page_language(&headers(cookie, accept), &params(pairs));
+            let set = set.map(|value| value.to_str().unwrap().to_string());
+            assert_eq!(
+                (selected, set),
+                (lang, set_cookie),
+                "{:?} {:?} {:?}",
+                pairs,
+                cookie,
+                accept
+            );
+        }
+    }
+
+    #[test]
+    fn test_is_allowed_language() {
+        let config = test_config();
+        assert!(is_allowed_language(&config.languages, "rust"));
+        assert!(is_allowed_language(&config.languages, "auto"));
+        assert!(!is_allowed_language(&config.languages, "malicious"));
+    }
+
+    #[test]
+    fn test_life_stage_thresholds() {
+        let now = 10_000;
+        for (left, expected) in [
+            (1000, LifeStage::Vibrant),
+            (501, LifeStage::Vibrant),
+            (500, LifeStage::Fading),
+            (251, LifeStage::Fading),
+            (250, LifeStage::Dying),
+            (0, LifeStage::Dying),
+            (-5, LifeStage::Dying),
+        ] {
+            assert_eq!(life_stage(1000, now + left, now), expected, "{}", left);
+        }
+        assert_eq!(life_stage(0, now + 100, now), LifeStage::Dying);
+    }
+
+    #[test]
+    fn test_life_fraction_clamps() {
+        let now = 10_000;
+        assert_eq!(life_fraction(1000, now + 1000, now), 1.0);
+        assert_eq!(life_fraction(1000, now + 250, now), 0.25);
+        assert_eq!(life_fraction(1000, now, now), 0.0);
+        assert_eq!(life_fraction(1000, now - 5, now), 0.0);
+        // Renewed past its original duration
+        assert_eq!(life_fraction(1000, now + 1800, now), 1.0);
+        assert_eq!(life_fraction(1000, now + i64::MAX / 2, now), 1.0);
+        assert_eq!(life_fraction(0, now + 100, now), 0.0);
+    }
+
+    #[test]
+    fn test_renewal_opens_at_half_life() {
+        let expires_at = 10_000;
+        let at = |now| renewal(true, 1000, expires_at, now);
+        // Exactly half left is not yet less than half.
+        assert!(!at(expires_at - 500).renew_eligible);
+        assert!(at(expires_at - 499).renew_eligible);
+        assert_eq!(at(0).renew_available_at, Some(expires_at - 499));
+        assert!(at(expires_at - 1).renew_eligible);
+        assert!(!at(expires_at).renew_eligible);
+        // An odd duration's half rounds down, so 500 left of 1001 is not
+        // yet less.
+        assert!(!renewal(true, 1001, expires_at, expires_at - 500).renew_eligible);
+        assert!(renewal(true, 1001, expires_at, expires_at - 499).renew_eligible);
+
+        // Renewed just now, it waits out another half before the next.
+        let renewed = renewal(true, 1000, expires_at + 1000, expires_at);
+        assert!(!renewed.renew_eligible);
+        assert_eq!(renewed.renew_available_at, Some(expires_at + 501));
+        // Renewed for popularity past its original duration, likewise.
+        let boosted = renewal(true, 1000, expires_at + 5000, expires_at);
+        assert_eq!(boosted.renew_available_at, Some(expires_at + 4501));
+
+        assert_eq!(
+            renewal(false, 1000, expires_at, expires_at - 1),
+            Renewal::default()
+        );
+    }
+
+    #[test]
+    fn test_accept_language() {
+        for (header, expected) in [
+            ("ja", Some(Lang::Ja)),
+            ("ja-JP,ja;q=0.9,en;q=0.8", Some(Lang::Ja)),
+            ("en-US,en;q=0.9,ja;q=0.8", Some(Lang::En)),
+            ("zh-CN", Some(Lang::Zh)),
+            ("ZH-tw", Some(Lang::Zh)),
+            ("en;q=0.5, ja;q=0.7", Some(Lang::Ja)),
+            ("fr, ja;q=0.1", Some(Lang::Ja)),
+            ("ja;q=0, en;q=0.2", Some(Lang::En)),
+            ("fr-FR, de", None),
+            ("", None),
+        ] {
+            assert_eq!(accept_language(header), expected, "{}", header);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_plural_strings() {
+        let state = test_state().await;
+        let i18n = state.i18n.load();
+        let (en, zh) = (i18n.strings(Lang::En), i18n.strings(Lang::Zh));
+        for (secs, en_label, zh_label) in [
+            (60, "1 min", "1 分钟"),
+            (120, "2 min", "2 分钟"),
+            (3600, "1 hour", "1 小时"),
+            (7200, "2 hours", "2 小时"),
+            (86400, "1 day", "1 天"),
+            (604800, "7 days", "7 天"),
+        ] {
+            assert_eq!(format_expires_label(secs, &en), en_label);
+            assert_eq!(format_expires_label(secs, &zh), zh_label);
+        }
+        assert_eq!(format_relative_time(0, 60, &en), "1 minute ago");
+        assert_eq!(format_relative_time(0, 120, &en), "2 minutes ago");
+        assert_eq!(format_relative_time(0, 120, &zh), "2 分钟前");
+        assert_eq!(en.plural(&en.detail_remaining_views, 1), "1 gaze remaining");
+        assert_eq!(
+            en.plural(&en.detail_remaining_views, 3),
+            "3 gazes remaining"
+        );
+        assert_eq!(zh.plural(&zh.detail_remaining_views, 1), "余下 1 次相遇");
+        assert_eq!(format_duration(3 * 86400 + 30, 0, &en), "~ 3 days");
+
+        // Japanese has no plural forms, and the count goes first
+        let ja = i18n.strings(Lang::Ja);
+        assert_eq!(format_expires_label(3600, &ja), "1 時間");
+        assert_eq!(format_expires_label(7200, &ja), "2 時間");
+        assert_eq!(format_relative_time(0, 60, &ja), "1 分前");
+        assert_eq!(format_relative_time(0, 120, &ja), "2 分前");
+        assert_eq!(ja.plural(&ja.detail_remaining_views, 1), "残り 1 回の閲覧");
+        assert_eq!(format_duration(3 * 86400 + 30, 0, &ja), "約 3 日");
+
+        // A plain string is every form; tables pick by the locale's rule.
+        let forms: HashMap<String, Plural> = toml::from_str(
+            r#"
+            plain = "{} files"
+            table = { one = "{} файл", few = "{} файла", many = "{} файлов", other = "{} файла" }
+            "#,
+        )
+        .unwrap();
+        let mut ru = en.clone();
+        ru.lang = "ru".to_string();
+        assert_eq!(en.plural(&forms["plain"], 1), "1 files");
+        for (n, expected) in [
+            (1, "1 файл"),
+            (3, "3 файла"),
+            (5, "5 файлов"),
+            (11, "11 файлов"),
+            (21, "21 файл"),
+            (22, "22 файла"),
+        ] {
+            assert_eq!(ru.plural(&forms["table"], n), expected);
+        }
+        assert_eq!(plural_category("pl", 1), "one");
+        assert_eq!(plural_category("pl", 24), "few");
+        assert_eq!(plural_category("pl", 21), "many");
+        assert_eq!(plural_category("zh", 1), "other");
+    }
+
+    #[tokio::test]
+    async fn test_format_relative_time() {
+        let state = test_state().await;
+        let i18n = state.i18n.load();
+        let now = 1_000_000;
+        let cases = [
+            (0, "just now", "刚刚"),
+            (59, "just now", "刚刚"),
+            (60, "1 minute ago", "1 分钟前"),
+            (119, "1 minute ago", "1 分钟前"),
+            (120, "2 minutes ago", "2 分钟前"),
+            (3599, "59 minutes ago", "59 分钟前"),
+            (3600, "1 hour ago", "1 小时前"),
+            (7200, "2 hours ago", "2 小时前"),
+            (86399, "23 hours ago", "23 小时前"),
+            (86400, "1 day ago", "1 天前"),
+            (172800, "2 days ago", "2 天前"),
+            // Clock skew never reads as the future.
+            (-30, "just now", "刚刚"),
+        ];
+        for (elapsed, en, zh) in cases {
+            let created_at = now - elapsed;
+            assert_eq!(
+                format_relative_time(created_at, now, &i18n.strings(Lang::En)),
+                en
+            );
+            assert_eq!(
+                format_relative_time(created_at, now, &i18n.strings(Lang::Zh)),
+                zh
+            );
+        }
+
+        update_config(&state, |config| {
+            config.server.display_timezone = "+08:00".to_string();
+        });
+        let created = display_time(0, now_ts(), &state.config.load(), &i18n.strings(Lang::En));
+        assert_eq!(created.iso, "1970-01-01T08:00:00+08:00");
+        assert_eq!(created.relative, format!("{} days ago", now_ts() / 86400));
+    }
+
+    #[test]
+    fn test_language_aliases() {
+        let mut languages = test_config().languages;
+        let cases = [
+            ("js", "javascript"),
+            ("ts", "typescript"),
+            ("py", "python"),
+            ("c++", "cpp"),
+            ("CXX", "cpp"),
+            ("sh", "bash"),
+            ("shell", "bash"),
+            ("zsh", "bash"),
+            ("yml", "yaml"),
+            ("Rust", "rust"),
+            ("auto", "auto"),
+            ("brainfuck", "auto"),
+        ];
+        for (name, expected) in cases {
+            assert_eq!(
+                normalize_language(&languages, Some(name.to_string())),
+                expected,
+                "{}",
+                name
+            );
+        }
+        assert_eq!(normalize_language(&languages, None), "auto");
+
+        languages.retain(|language| language.value != "yaml");
+        languages[0].aliases = vec!["text".to_string()];
+        assert_eq!(
+            normalize_language(&languages, Some("yml".to_string())),
+            "auto"
+        );
+        assert_eq!(resolve_language_alias(&languages, "Text"), "plaintext");
+    }
+
+    #[tokio::test]
+    async fn test_configured_languages() {
+        let state = test_state().await;
+        update_config(&state, |config| {
+            config.languages = vec![LanguageConfig {
+                value: "toml".to_string(),
+                extension: "toml".to_string(),
+                content_type: "application/toml".to_string(),
+                label_key: None,
+                aliases: Vec::new(),
+                labels: BTreeMap::from([("en".to_string(), "TOML".to_string())]),
+            }];
+            // The same content is created under each language in turn.
+            config.paste.repeat_window_secs = 0;
+        });
+        assert!(crate::config::validate_config(&state.config.load()).is_ok());
+
+        let create = |language: &str| PasteForm {
+            content: "[server]\nport = 8080\n".to_string(),
+            language: Some(language.to_string()),
+            ..Default::default()
+        };
+        let response = create_paste(
+            State(state.clone()),
+            HeaderMap::new(),
+            local_client(),
+            Form(create("rust")),
+        )
+        .await
+        .into_response();
+        let location = response.headers()[LOCATION].to_str().unwrap().to_string();
+        let token = location.trim_start_matches("/p/").to_string();
+        let language: String = sqlx::query_scalar("SELECT language FROM pastes WHERE token = ?")
+            .bind(&token)
+            .fetch_one(&state.pool)
+            .await
+            .unwrap();
+        assert_eq!(language, "auto");
+
+        let response = create_paste(
+            State(state.clone()),
+            HeaderMap::new(),
+            local_client(),
+            Form(create("toml")),
+        )
+        .await
+        .into_response();
+        let location = response.headers()[LOCATION].to_str().unwrap().to_string();
+        let token = location.trim_start_matches("/p/").to_string();
+        let params = HashMap::from([("lang".to_string(), "en".to_string())]);
+        let response = view_paste(
+            State(state.clone()),
+            HeaderMap::new(),
+            local_client(),
+            PasteToken(token.clone()),
+            Query(params),
+        )
+        .await
+        .into_response();
+        let body = body_string(response).await;
+        assert!(body.contains(r#"<span id="language-label-val">TOML</span>"#));
+        assert!(body.contains(r#"data-language="toml""#));
+
+        let response = view_paste_raw(
+            State(state.clone()),
+            HeaderMap::new(),
+            local_client(),
+            Path(token.clone()),
+            Query(HashMap::new()),
+        )
+        .await
+        .into_response();
+        assert_eq!(response.headers()[CONTENT_TYPE], "application/toml");
+        assert!(
+            response.headers()[CONTENT_DISPOSITION]
+                .to_str()
+                .unwrap()
+                .ends_with(".toml\"")
+        );
+
+        update_config(&state, |config| {
+            config.languages[0].value = "auto".to_string();
+            config.languages.push(config.languages[0].clone());
+        });
+        let errors = crate::config::validate_config(&state.config.load()).unwrap_err();
+        assert_eq!(errors.len(), 2);
+    }
+
+    #[test]
+    fn test_normalize_title() {
+        assert_eq!(
+            normalize_title(Some("Test".to_string()), "content", "auto"),
+            "Test"
+        );
+        assert_eq!(
+            normalize_title(
+                None,
+                "First line
+Second line",
+                "auto"
+            ),
+            "First line"
+        );
+        assert_eq!(normalize_title(None, "", "auto"), "Untitled");
+    }
+
+    #[test]
+    fn test_titles_skip_file_headers() {
+        let title = |content: &str, language: &str| normalize_title(None, content, language);
+        assert_eq!(
+            title(
+                "#!/usr/bin/env bash\n# Rotate the nightly backups\nset -e\n",
+                "bash"
+            ),
+            "Rotate the nightly backups"
+        );
+        assert_eq!(
+            title(
+                "// SPDX-License-Identifier: MIT\n//! Token bucket rate limiter.\n",
+                "rust"
+            ),
+            "Token bucket rate limiter."
+        );
+        assert_eq!(
+            title(
+                "#![allow(dead_code)]\n#[derive(Debug)]\nstruct Point { x: i32 }\n",
+                "rust"
+            ),
+            "struct Point { x: i32 }"
+        );
+        assert_eq!(
+            title(
+                "/**\n * Copyright 2024 Example Corp.\n */\npackage main;\n",
+                "java"
+            ),
+            "Copyright 2024 Example Corp."
+        );
+        assert_eq!(
+            title("/* reset.css */\n*, *::before {\n", "css"),
+            "reset.css"
+        );
+        assert_eq!(
+            title("#include <stdio.h>\n\nint main(void) {\n", "cpp"),
+            "int main(void) {"
+        );
+        assert_eq!(
+            title("-- Monthly revenue by region\nSELECT 1;\n", "sql"),
+            "Monthly revenue by region"
+        );
+        assert_eq!(
+            title("<!-- Landing page -->\n<html>\n", "html"),
+            "Landing page"
+        );
+        assert_eq!(
+            title(
+                "#!/usr/bin/env python3\n# -*- coding: utf-8 -*-\n\"\"\"Fetch feeds.\"\"\"\n",
+                "python"
+            ),
+            "\"\"\"Fetch feeds.\"\"\""
+        );
+        assert_eq!(
+            title("---\n## Release notes\n\nv2 ships today\n", "auto"),
+            "Release notes"
+        );
+        assert_eq!(title("**Bold** start\n", "auto"), "**Bold** start");
+        assert_eq!(title("{\n  [\n});\n", "json"), "Untitled");
+        assert_eq!(title("#!/bin/sh\n#\n//\n", "auto"), "Untitled");
+        let long = format!("# {}\n", "w".repeat(100));
+        assert_eq!(title(&long, "auto").chars().count(), 80);
+    }
+
+    #[test]
+    fn test_normalize_e2e_title() {
+        assert_eq!(normalize_e2e_title(None), "Encrypted paste");
+        assert_eq!(normalize_e2e_title(Some("  label ".to_string())), "label");
+    }
+
+    #[test]
+    fn test_titles_stay_on_one_line() {
+        assert_eq!(
+            normalize_title(Some(" two\r\nlines ".to_string()), "", "auto"),
+            "two  lines"
+        );
+        assert_eq!(
+            normalize_title(Some("\t\n ".to_string()), "body", "auto"),
+            "body"
+        );
+        assert_eq!(normalize_title(None, "\u{2028}", "auto"), "Untitled");
+        let long = "蜉".repeat(100);
+        assert_eq!(normalize_title(Some(long), "", "auto").chars().count(), 80);
+        // Trailing space left by the cut is dropped.
+        let cut = format!("{} b", "a".repeat(79));
+        assert_eq!(normalize_title(Some(cut), "", "auto"), "a".repeat(79));
+    }
+
+    #[test]
+    fn test_normalize_max_views() {
+        let config = test_config().paste;
+        let views = |value: &str| normalize_max_views(Some(value.to_string()), &config);
+        assert_eq!(views("3"), Some(3));
+        assert_eq!(views(" 3 "), Some(3));
+        assert_eq!(views(&i64::MAX.to_string()), Some(config.max_views_limit));
+        assert_eq!(views(&"9".repeat(40)), Some(config.max_views_limit));
+        for refused in ["0", "-5", &"-9".repeat(20), "many", ""] {
+            assert_eq!(views(refused), None, "{}", refused);
+        }
+        assert_eq!(normalize_max_views(None, &config), None);
+    }
+
+    #[test]
+    fn test_normalize_author() {
+        let mut config = test_config().paste;
+        let author =
+            |value: &str, config: &PasteConfig| normalize_author(Some(value.to_string()), config);
+        assert_eq!(author(" ~licheam ", &config).as_deref(), Some("licheam"));
+        assert_eq!(author("a\nb\u{7}", &config).as_deref(), Some("a b"));
+        assert_eq!(author("~", &config), None);
+        assert_eq!(
+            author(&"蜉".repeat(50), &config).unwrap().chars().count(),
+            32
+        );
+        assert_eq!(normalize_author(None, &config), None);
+        config.allow_author = false;
+        assert_eq!(author("licheam", &config), None);
+    }
+
+    fn title_is_clean(title: &str) -> bool {
+        !title.is_empty()
+            && title.chars().count() <= MAX_TITLE_CHARS
+            && !title
+                .chars()
+                .any(|c| c.is_control() || c == '\u{2028}' || c == '\u{2029}')
+            && title.trim() == title
+    }
+
+    proptest! {
+        #[test]
+        fn prop_expiry_is_an_option_or_the_default(expires_in in any::<Option<i64>>()) {
+            let config = test_config().paste;
+            let secs = normalize_expires_in(expires_in, &config);
+            prop_assert!(
+                config.expires_options_secs.contains(&secs) || secs == config.default_expires_secs
+            );
+        }
+
+        #[test]
+        fn prop_token_length_is_configured(length in any::<Option<usize>>()) {
+            let config = test_config().paste;
+            let length = normalize_token_length(length, &config);
+            prop_assert!(
+                config.token_lengths.contains(&length) || length == config.default_token_length
+            );
+        }
+
+        #[test]
+        fn prop_language_is_allowed(
+            language in proptest::option::of(prop_oneof![
+                any::<String>(),
+                Just("rust".to_string()),
+                Just("JS".to_string()),
+                Just("auto".to_string()),
+            ])
+        ) {
+            let languages = test_config().languages;
+            let language = normalize_language(&languages, language);
+            prop_assert!(language == "auto" || is_allowed_language(&languages, &language));
+        }
+
+        #[test]
+        fn prop_titles_are_one_capped_line(
+            title in proptest::option::of(any::<String>()),
+            content in any::<String>(),
+        ) {
+            prop_assert!(title_is_clean(&normalize_title(title.clone(), &content, "auto")));
+            prop_assert!(title_is_clean(&normalize_e2e_title(title)));
+        }
+
+        #[test]
+        fn prop_max_views_is_positive_and_capped(
+            max_views in proptest::option::of(prop_oneof![
+                any::<String>(),
+                "-?[0-9]{1,30}",
+                " ?[0-9]{1,8} ?",
+            ])
+        ) {
+            let config = test_config().paste;
+            if let Some(views) = normalize_max_views(max_views, &config) {
+                prop_assert!((1..=config.max_views_limit).contains(&views));
+            }
+        }
+    }
+
+    #[test]
+    fn test_normalize_content() {
+        let mixed = "one\r\ntwo\rthree  \n\tfour\t\r\n\r\n  \n";
+        assert_eq!(
+            normalize_content(mixed, false),
+            "one\ntwo\nthree  \n\tfour\t\n\n  \n"
+        );
+        assert_eq!(normalize_content(mixed, true), "one\ntwo\nthree\n\tfour\n");
+        assert_eq!(normalize_content("a \r\n\r\nb", true), "a\n\nb");
+        assert_eq!(normalize_content(" \r\n\n", true), "");
+    }
+
+    #[tokio::test]
+    async fn test_line_endings_are_normalized() {
+        let state = test_state().await;
+        let form = PasteForm {
+            content: "fn main() {  \r\n    run();\r}\r\n\r\n".to_string(),
+            trim_trailing: Some("on".to_string()),
+            ..Default::default()
+        };
+        let response = create_paste(
+            State(state.clone()),
+            HeaderMap::new(),
+            local_client(),
+            Form(form),
+        )
+        .await
+        .into_response();
+        let location = response.headers()[LOCATION].to_str().unwrap().to_string();
+        let token = location.trim_start_matches("/p/").to_string();
+        let stored: String = sqlx::query_scalar("SELECT content FROM pastes WHERE token = ?")
+            .bind(&token)
+            .fetch_one(&state.pool)
+            .await
+            .unwrap();
+        assert_eq!(stored, "fn main() {\n    run();\n}\n");
+
+        let params = HashMap::from([("crlf".to_string(), "1".to_string())]);
+        let response = view_paste_raw(
+            State(state.clone()),
+            HeaderMap::new(),
+            local_client(),
+            Path(token.clone()),
+            Query(params),
+        )
+        .await
+        .into_response();
+        let hash = response.headers()["X-Content-SHA256"].clone();
+        let body = body_string(response).await;
+        assert_eq!(body, "fn main() {\r\n    run();\r\n}\r\n");
+        assert_eq!(hash, sha256_hex(&body).as_str());
+    }
 
     #[test]
-    fn test_is_allowed_language() {
-        assert!(is_allowed_language("rust"));
-        assert!(is_allowed_language("auto"));
-        assert!(!is_allowed_language("malicious"));
+    fn test_normalize_token() {
+        let config = test_config().paste;
+        assert_eq!(config.token_lengths, [2, 4, 6]);
+        for (raw, expected) in [
+            ("aB3d", "aB3d"),
+            ("aB3d.", "aB3d"),
+            ("aB3d).", "aB3d"),
+            ("aB3d!\"", "aB3d"),
+            ("aB3d>,", "aB3d"),
+            ("x9", "x9"),
+            ("meeting-notes", "meeting-notes"),
+            ("meeting-notes).", "meeting-notes"),
+        ] {
+            assert_eq!(
+                normalize_token(raw, &config).as_deref(),
+                Some(expected),
+                "{}",
+                raw
+            );
+        }
+        for raw in [
+            "../../etc/passwd",
+            "\0",
+            "a",
+            "aB3d5f7",
+            "aB-3d",
+            "aB3d.x",
+            "ab cd",
+            "é3d",
+            "",
+            "-notes",
+            "notes--",
+        ] {
+            assert_eq!(normalize_token(raw, &config), None, "{}", raw);
+        }
+
+        let manage = "a".repeat(db::MANAGE_TOKEN_LENGTH);
+        assert_eq!(
+            normalize_manage_token(&format!("{}).", manage)),
+            Some(manage.clone())
+        );
+        assert_eq!(normalize_manage_token(&manage[1..]), None);
     }
 
     #[test]
-    fn test_normalize_title() {
-        assert_eq!(normalize_title(Some("Test".to_string()), "content"), "Test");
-        assert_eq!(normalize_title(None, "First line
-Second line"), "First line");
-        assert_eq!(normalize_title(None, ""), "Untitled");
+    fn test_split_extension() {
+        for (raw, expected) in [
+            ("aB3d.rs", ("aB3d", Some("rs"))),
+            ("aB3d.json).", ("aB3d", Some("json"))),
+            ("aB3d.", ("aB3d.", None)),
+            ("aB3d", ("aB3d", None)),
+            (".rs", (".rs", None)),
+            ("aB3d.x-y", ("aB3d.x-y", None)),
+        ] {
+            assert_eq!(split_extension(raw), expected, "{}", raw);
+        }
+        let config = test_config();
+        let languages = &config.languages;
+        assert_eq!(
+            language_for_extension(languages, "RS").as_deref(),
+            Some("rust")
+        );
+        assert_eq!(
+            language_for_extension(languages, "yml").as_deref(),
+            Some("yaml")
+        );
+        assert_eq!(language_for_extension(languages, "xyz"), None);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_tokens_skip_the_database() {
+        let state = test_state().await;
+        // Anything reaching the pool from here on would panic.
+        state.pool.close().await;
+        let response = view_paste(
+            State(state.clone()),
+            HeaderMap::new(),
+            local_client(),
+            PasteToken("../../etc/passwd".to_string()),
+            Query(HashMap::new()),
+        )
+        .await
+        .into_response();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert!(body_string(response).await.contains("404"));
+        let response = view_paste_raw(
+            State(state.clone()),
+            HeaderMap::new(),
+            local_client(),
+            Path("%00".to_string()),
+            Query(HashMap::new()),
+        )
+        .await
+        .into_response();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let response = renew_paste(
+            State(state.clone()),
+            HeaderMap::new(),
+            PasteToken("x".to_string()),
+        )
+        .await
+        .into_response();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let response = manage_page(
+            State(state.clone()),
+            HeaderMap::new(),
+            Path("short".to_string()),
+            Query(HashMap::new()),
+        )
+        .await
+        .into_response();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let response = manage_regenerate(
+            State(state.clone()),
+            HeaderMap::new(),
+            Path("short".to_string()),
+        )
+        .await
+        .into_response();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_absolute_expiry() {
+        let state = test_state().await;
+        let create = |expires_at_abs: String| {
+            let mut headers = HeaderMap::new();
+            headers.insert("accept-language", HeaderValue::from_static("en"));
+            let form = PasteForm {
+                content: format!("release notes until {}", expires_at_abs),
+                expires_in: Some(3600),
+                expires_at_abs: Some(expires_at_abs),
+                ..Default::default()
+            };
+            create_paste(State(state.clone()), headers, local_client(), Form(form))
+        };
+        let stored = async |response: Response| {
+            let location = response.headers()[LOCATION].to_str().unwrap().to_string();
+            let token = location.trim_start_matches("/p/").to_string();
+            let row: (i64, i64, i64) = sqlx::query_as(
+                "SELECT created_at, expires_at, original_duration FROM pastes WHERE token = ?",
+            )
+            .bind(&token)
+            .fetch_one(&state.pool)
+            .await
+            .unwrap();
+            (token, row)
+        };
+
+        // The offset suffix is honoured, and the span is kept for renewals.
+        let target = now_ts() + 7200;
+        let response = create(format_iso8601(target, -(9 * 3600 + 30 * 60)))
+            .await
+            .into_response();
+        let (token, (created_at, expires_at, original_duration)) = stored(response).await;
+        assert_eq!(expires_at, target);
+        assert!((created_at + original_duration - target).abs() <= 1);
+        let response = view_paste(
+            State(state.clone()),
+            HeaderMap::new(),
+            local_client(),
+            PasteToken(token),
+            Query(HashMap::new()),
+        )
+        .await
+        .into_response();
+        assert!(
+            body_string(response)
+                .await
+                .contains(&format_iso8601(target, 0))
+        );
+
+        // Too far ahead is brought in to the longest option.
+        let max = max_expires_secs(&state.config.load().paste);
+        let response = create(format_iso8601(now_ts() + max * 10, 8 * 3600))
+            .await
+            .into_response();
+        let (_, (created_at, expires_at, _)) = stored(response).await;
+        assert!((expires_at - created_at - max).abs() <= 1);
+
+        for value in ["2000-01-01T00:00:00Z", "end of friday"] {
+            let response = create(value.to_string()).await.into_response();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+            assert_eq!(
+                body_string(response).await,
+                "The expiry time must be a valid date and time in the future."
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_auto_language_is_detected() {
+        let state = test_state().await;
+        let form = PasteForm {
+            content: "fn main() {\n    let mut x = 1;\n    println!(\"{}\", x);\n}\n".to_string(),
+            language: Some("auto".to_string()),
+            ..Default::default()
+        };
+        let response = create_paste(
+            State(state.clone()),
+            HeaderMap::new(),
+            local_client(),
+            Form(form),
+        )
+        .await
+        .into_response();
+        let location = response.headers()[LOCATION].to_str().unwrap().to_string();
+        let token = location.trim_start_matches("/p/").to_string();
+        let (language, detected): (String, Option<String>) =
+            sqlx::query_as("SELECT language, detected_language FROM pastes WHERE token = ?")
+                .bind(&token)
+                .fetch_one(&state.pool)
+                .await
+                .unwrap();
+        assert_eq!(language, "auto");
+        assert_eq!(detected.as_deref(), Some("rust"));
+
+        let params = HashMap::from([("lang".to_string(), "en".to_string())]);
+        let response = view_paste(
+            State(state.clone()),
+            HeaderMap::new(),
+            local_client(),
+            PasteToken(token),
+            Query(params),
+        )
+        .await
+        .into_response();
+        let body = body_string(response).await;
+        assert!(body.contains("Auto (Rust)"));
+        assert!(body.contains(r#"data-language="rust""#));
+    }
+
+    #[tokio::test]
+    async fn test_e2e_paste_is_opaque() {
+        let state = test_state().await;
+        let form = PasteForm {
+            content: "bm90IHJlYWxseSBjaXBoZXJ0ZXh0".to_string(),
+            language: Some("rust".to_string()),
+            is_public: Some("on".to_string()),
+            is_e2e: Some("1".to_string()),
+            ..Default::default()
+        };
+        let response = create_paste(
+            State(state.clone()),
+            HeaderMap::new(),
+            local_client(),
+            Form(form),
+        )
+        .await
+        .into_response();
+        let location = response.headers()[LOCATION].to_str().unwrap().to_string();
+        let token = location.trim_start_matches("/p/").to_string();
+
+        let (title, language): (String, String) =
+            sqlx::query_as("SELECT title, language FROM pastes WHERE token = ?")
+                .bind(&token)
+                .fetch_one(&state.pool)
+                .await
+                .unwrap();
+        assert_eq!(title, "Encrypted paste");
+        assert_eq!(language, "plaintext");
+
+        let response = view_paste_raw(
+            State(state.clone()),
+            HeaderMap::new(),
+            local_client(),
+            Path(token.clone()),
+            Query(HashMap::new()),
+        )
+        .await
+        .into_response();
+        assert_eq!(response.headers()[CONTENT_TYPE], "application/octet-stream");
+        assert_eq!(response.headers()["X-Mayfile-E2E"], "1");
+        assert_eq!(body_string(response).await, "bm90IHJlYWxseSBjaXBoZXJ0ZXh0");
+
+        // Public or not, sealed pastes never show up in explore.
+        let pastes = db::list_public_pastes(
+            &state.pool,
+            None,
+            None,
+            &PublicFilter::default(),
+            10,
+            0,
+            now_ts(),
+        )
+        .await
+        .unwrap();
+        assert!(pastes.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_encrypted_paste_round_trip() {
+        let mut state = test_state().await;
+        state.cipher = Some(
+            ContentCipher::from_hex(
+                "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f",
+            )
+            .unwrap(),
+        );
+
+        let form = PasteForm {
+            title: Some("Secret".to_string()),
+            content: "hello encrypted world".to_string(),
+            is_public: Some("on".to_string()),
+            ..Default::default()
+        };
+        let response = create_paste(
+            State(state.clone()),
+            HeaderMap::new(),
+            local_client(),
+            Form(form),
+        )
+        .await
+        .into_response();
+        let location = response.headers()[LOCATION].to_str().unwrap().to_string();
+        let token = location.trim_start_matches("/p/").to_string();
+
+        let (stored, is_encrypted): (String, bool) =
+            sqlx::query_as("SELECT content, is_encrypted FROM pastes WHERE token = ?")
+                .bind(&token)
+                .fetch_one(&state.pool)
+                .await
+                .unwrap();
+        assert!(is_encrypted);
+        assert!(!stored.contains("hello"));
+
+        let response = view_paste(
+            State(state.clone()),
+            HeaderMap::new(),
+            local_client(),
+            PasteToken(token.clone()),
+            Query(HashMap::new()),
+        )
+        .await
+        .into_response();
+        assert!(
+            body_string(response)
+                .await
+                .contains("hello encrypted world")
+        );
+
+        let response = view_paste_raw(
+            State(state.clone()),
+            HeaderMap::new(),
+            local_client(),
+            Path(token.clone()),
+            Query(HashMap::new()),
+        )
+        .await
+        .into_response();
+        let hash = response.headers()["X-Content-SHA256"]
+            .to_str()
+            .unwrap()
+            .to_string();
+        let body = body_string(response).await;
+        assert_eq!(body, "hello encrypted world");
+        assert_eq!(hash, crate::utils::sha256_hex(&body));
+
+        let response = api_paste_hash(State(state.clone()), PasteToken(token.clone()))
+            .await
+            .into_response();
+        assert_eq!(body_string(response).await, hash);
+
+        let response = explore(
+            State(state.clone()),
+            HeaderMap::new(),
+            Query(HashMap::new()),
+        )
+        .await
+        .into_response();
+        assert!(
+            body_string(response)
+                .await
+                .contains("hello encrypted world")
+        );
+
+        let meta = db::fetch_paste_meta(&state.pool, &token, now_ts())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(meta.content_length, "hello encrypted world".len() as i64);
+    }
+
+    #[tokio::test]
+    async fn test_share_link_is_owner_only_and_signed() {
+        let state = test_state().await;
+        let form = PasteForm {
+            content: "shared secret".to_string(),
+            max_views: Some("1".to_string()),
+            ..Default::default()
+        };
+        let response = create_paste(
+            State(state.clone()),
+            HeaderMap::new(),
+            local_client(),
+            Form(form),
+        )
+        .await
+        .into_response();
+        let token = response.headers()[LOCATION]
+            .to_str()
+            .unwrap()
+            .trim_start_matches("/p/")
+            .to_string();
+        let cookie = response.headers()[SET_COOKIE].to_str().unwrap();
+        let owner = cookie.split(';').next().unwrap().to_string();
+
+        let response = share_paste(
+            State(state.clone()),
+            HeaderMap::new(),
+            PasteToken(token.clone()),
+            Query(ShareQuery { ttl: None }),
+        )
+        .await
+        .into_response();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(COOKIE, HeaderValue::from_str(&owner).unwrap());
+        let response = share_paste(
+            State(state.clone()),
+            headers.clone(),
+            PasteToken(token.clone()),
+            Query(ShareQuery { ttl: Some(60) }),
+        )
+        .await
+        .into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let json: serde_json::Value = serde_json::from_str(&body_string(response).await).unwrap();
+        let url = json["url"].as_str().unwrap();
+        let query = url.split_once('?').unwrap().1;
+        let mut params: HashMap<String, String> = query
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+
+        // Viewing through the link twice does not burn the single view.
+        for _ in 0..2 {
+            let response = view_shared_paste(
+                State(state.clone()),
+                HeaderMap::new(),
+                PasteToken(token.clone()),
+                Query(params.clone()),
+            )
+            .await
+            .into_response();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        params.insert("exp".to_string(), (now_ts() + 7200).to_string());
+        let response = view_shared_paste(
+            State(state.clone()),
+            HeaderMap::new(),
+            PasteToken(token.clone()),
+            Query(params),
+        )
+        .await
+        .into_response();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_named_share_links_have_own_budget() {
+        let state = test_state().await;
+        let form = PasteForm {
+            content: "leaky".to_string(),
+            max_views: Some("1".to_string()),
+            ..Default::default()
+        };
+        let response = create_paste(
+            State(state.clone()),
+            HeaderMap::new(),
+            local_client(),
+            Form(form),
+        )
+        .await
+        .into_response();
+        let token = response.headers()[LOCATION]
+            .to_str()
+            .unwrap()
+            .trim_start_matches("/p/")
+            .to_string();
+        let owner = response.headers()[SET_COOKIE].to_str().unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            COOKIE,
+            HeaderValue::from_str(owner.split(';').next().unwrap()).unwrap(),
+        );
+
+        let form = ShareLinkForm {
+            label: Some("alice".to_string()),
+            max_views: Some(1),
+        };
+        let response = create_share_link(
+            State(state.clone()),
+            HeaderMap::new(),
+            PasteToken(token.clone()),
+            axum::Json(form),
+        )
+        .await
+        .into_response();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let form = ShareLinkForm {
+            label: Some("alice".to_string()),
+            max_views: Some(1),
+        };
+        let response = create_share_link(
+            State(state.clone()),
+            headers.clone(),
+            PasteToken(token.clone()),
+            axum::Json(form),
+        )
+        .await
+        .into_response();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let link: serde_json::Value = serde_json::from_str(&body_string(response).await).unwrap();
+        let share_token = link["share_token"].as_str().unwrap().to_string();
+
+        let view = |share_token: String| {
+            view_shared_paste(
+                State(state.clone()),
+                HeaderMap::new(),
+                PasteToken(share_token),
+                Query(HashMap::new()),
+            )
+        };
+        let response = view(share_token.clone()).await.into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        // The paste token would read the paste past the link's budget.
+        let body = body_string(response).await;
+        assert!(body.contains("leaky"));
+        for link in ["/r/", "/p/", "fork="] {
+            assert!(!body.contains(&format!("{}{}", link, token)));
+        }
+        assert_eq!(
+            view(share_token.clone()).await.into_response().status(),
+            StatusCode::NOT_FOUND
+        );
+
+        // The paste's own single view is still available.
+        let views: i64 = sqlx::query_scalar("SELECT views FROM pastes WHERE token = ?")
+            .bind(&token)
+            .fetch_one(&state.pool)
+            .await
+            .unwrap();
+        assert_eq!(views, 0);
+
+        let response = list_share_links(
+            State(state.clone()),
+            headers.clone(),
+            PasteToken(token.clone()),
+        )
+        .await
+        .into_response();
+        let links: serde_json::Value = serde_json::from_str(&body_string(response).await).unwrap();
+        assert_eq!(links[0]["label"], "alice");
+        assert_eq!(links[0]["views"], 1);
+
+        // The manage page lists it too, with what is left of its budget.
+        let manage_token: String =
+            sqlx::query_scalar("SELECT manage_token FROM pastes WHERE token = ?")
+                .bind(&token)
+                .fetch_one(&state.pool)
+                .await
+                .unwrap();
+        let mut english = HeaderMap::new();
+        english.insert("accept-language", HeaderValue::from_static("en"));
+        let response = manage_page(
+            State(state.clone()),
+            english,
+            Path(manage_token),
+            Query(HashMap::new()),
+        )
+        .await
+        .into_response();
+        let body = body_string(response).await;
+        assert!(body.contains("alice"));
+        assert!(body.contains(&format!("/s/{}", share_token)));
+        assert!(body.contains("1 view"));
+        assert!(body.contains("0 gazes remaining"));
+
+        let response = revoke_share_link(
+            State(state.clone()),
+            headers.clone(),
+            Path((token.clone(), share_token.clone())),
+        )
+        .await
+        .into_response();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let response = list_share_links(State(state.clone()), headers, PasteToken(token))
+            .await
+            .into_response();
+        assert_eq!(body_string(response).await, "[]");
+    }
+
+    /// Open the detail page, then the raw view with whatever cookies the
+    /// detail page handed out, and return the paste's view count.
+    async fn views_after_page_then_raw(raw_counts_as_view: bool, send_receipt: bool) -> i64 {
+        let state = test_state().await;
+        update_config(&state, |config| {
+            config.paste.raw_counts_as_view = raw_counts_as_view;
+        });
+        let form = PasteForm {
+            content: "burn".to_string(),
+            max_views: Some("3".to_string()),
+            ..Default::default()
+        };
+        let response = create_paste(
+            State(state.clone()),
+            HeaderMap::new(),
+            local_client(),
+            Form(form),
+        )
+        .await
+        .into_response();
+        let token = response.headers()[LOCATION]
+            .to_str()
+            .unwrap()
+            .trim_start_matches("/p/")
+            .to_string();
+
+        let response = view_paste(
+            State(state.clone()),
+            HeaderMap::new(),
+            local_client(),
+            PasteToken(token.clone()),
+            Query(HashMap::new()),
+        )
+        .await
+        .into_response();
+        let mut headers = HeaderMap::new();
+        if send_receipt {
+            for cookie in response.headers().get_all(SET_COOKIE) {
+                let pair = cookie.to_str().unwrap().split(';').next().unwrap();
+                headers.append(COOKIE, HeaderValue::from_str(pair).unwrap());
+            }
+        }
+        let response = view_paste_raw(
+            State(state.clone()),
+            headers,
+            local_client(),
+            Path(token.clone()),
+            Query(HashMap::new()),
+        )
+        .await
+        .into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        sqlx::query_scalar("SELECT views FROM pastes WHERE token = ?")
+            .bind(&token)
+            .fetch_one(&state.pool)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_raw_counts_as_view_toggle() {
+        assert_eq!(views_after_page_then_raw(true, true).await, 2);
+        assert_eq!(views_after_page_then_raw(false, true).await, 1);
+        // Without the receipt from the page view, raw still counts.
+        assert_eq!(views_after_page_then_raw(false, false).await, 2);
+    }
+
+    #[tokio::test]
+    async fn test_owner_preview_does_not_burn() {
+        let state = test_state().await;
+        let form = PasteForm {
+            content: "once".to_string(),
+            max_views: Some("1".to_string()),
+            ..Default::default()
+        };
+        let response = create_paste(
+            State(state.clone()),
+            HeaderMap::new(),
+            local_client(),
+            Form(form),
+        )
+        .await
+        .into_response();
+        let token = response.headers()[LOCATION]
+            .to_str()
+            .unwrap()
+            .trim_start_matches("/p/")
+            .to_string();
+        let owner = response.headers()[SET_COOKIE].to_str().unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            COOKIE,
+            HeaderValue::from_str(owner.split(';').next().unwrap()).unwrap(),
+        );
+
+        let view = |headers: HeaderMap, params: HashMap<String, String>| {
+            view_paste(
+                State(state.clone()),
+                headers,
+                local_client(),
+                PasteToken(token.clone()),
+                Query(params),
+            )
+        };
+        let banner = state.i18n.load().strings(Lang::Zh).detail_owner_preview;
+        for _ in 0..3 {
+            let response = view(headers.clone(), HashMap::new()).await.into_response();
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = body_string(response).await;
+            assert!(body.contains(&banner));
+        }
+
+        // Knowing the owner id is not being the owner.
+        let owner_id: String = sqlx::query_scalar("SELECT owner_id FROM pastes WHERE token = ?")
+            .bind(&token)
+            .fetch_one(&state.pool)
+            .await
+            .unwrap();
+        let params = HashMap::from([("secret".to_string(), owner_id)]);
+        let response = view(HeaderMap::new(), params.clone()).await.into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(!body_string(response).await.contains(&banner));
+        let response = view(HeaderMap::new(), params).await.into_response();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_views_are_shown_and_broken_down_for_the_owner() {
+        let state = test_state().await;
+        let form = PasteForm {
+            content: "counted".to_string(),
+            ..Default::default()
+        };
+        let response = create_paste(
+            State(state.clone()),
+            HeaderMap::new(),
+            local_client(),
+            Form(form),
+        )
+        .await
+        .into_response();
+        let token = response.headers()[LOCATION]
+            .to_str()
+            .unwrap()
+            .trim_start_matches("/p/")
+            .to_string();
+        let owner = response.headers()[SET_COOKIE].to_str().unwrap();
+        let mut owner_headers = HeaderMap::new();
+        owner_headers.insert(
+            COOKIE,
+            HeaderValue::from_str(owner.split(';').next().unwrap()).unwrap(),
+        );
+        let view = |headers: HeaderMap| async {
+            let response = view_paste(
+                State(state.clone()),
+                headers,
+                local_client(),
+                PasteToken(token.clone()),
+                Query(HashMap::new()),
+            )
+            .await
+            .into_response();
+            body_string(response).await
+        };
+        let strings = state.i18n.load().strings(Lang::Zh);
+
+        view(HeaderMap::new()).await;
+        let body = view(HeaderMap::new()).await;
+        assert!(body.contains(&strings.plural(&strings.detail_views, 2)));
+        assert!(!body.contains(&strings.detail_views_by_day));
+
+        let body = view(owner_headers).await;
+        assert!(body.contains(&strings.plural(&strings.detail_views, 2)));
+        assert!(body.contains(&strings.detail_views_by_day));
+        let daily = db::list_daily_views(&state.pool, &token).await.unwrap();
+        assert!(body.contains(&format!("<span>{}</span><span>2</span>", daily[0].day)));
+    }
+
+    #[tokio::test]
+    async fn test_reports_unpublish_and_admin_review() {
+        let state = test_state().await;
+        update_config(&state, |config| {
+            config.server.admin_token = Some("sesame".to_string());
+        });
+        let form = PasteForm {
+            content: "spam".to_string(),
+            is_public: Some("on".to_string()),
+            ..Default::default()
+        };
+        let response = create_paste(
+            State(state.clone()),
+            HeaderMap::new(),
+            local_client(),
+            Form(form),
+        )
+        .await
+        .into_response();
+        let token = response.headers()[LOCATION]
+            .to_str()
+            .unwrap()
+            .trim_start_matches("/p/")
+            .to_string();
+        let is_public = |state: AppState, token: String| async move {
+            sqlx::query_scalar::<_, bool>("SELECT is_public FROM pastes WHERE token = ?")
+                .bind(&token)
+                .fetch_optional(&state.pool)
+                .await
+                .unwrap()
+        };
+
+        // The same client reporting twice still counts once.
+        for last_octet in [1, 1, 2, 3] {
+            let response = report_paste(
+                State(state.clone()),
+                ClientIp([10, 0, 0, last_octet].into()),
+                HeaderMap::new(),
+                PasteToken(token.clone()),
+                Form(ReportForm {
+                    reason: Some("spam".to_string()),
+                }),
+            )
+            .await
+            .into_response();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        assert_eq!(is_public(state.clone(), token.clone()).await, Some(false));
+
+        let response = admin_list_reports(State(state.clone()), HeaderMap::new())
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let mut admin = HeaderMap::new();
+        admin.insert(AUTHORIZATION, HeaderValue::from_static("Bearer sesame"));
+        let response = admin_list_reports(State(state.clone()), admin.clone())
+            .await
+            .into_response();
+        let reported: serde_json::Value =
+            serde_json::from_str(&body_string(response).await).unwrap();
+        assert_eq!(reported[0]["token"], token.as_str());
+        assert_eq!(reported[0]["reports"], 3);
+        assert_eq!(reported[0]["flagged"], true);
+        assert_eq!(reported[0]["pinned"], false);
+
+        let response =
+            admin_clear_reports(State(state.clone()), admin.clone(), Path(token.clone()))
+                .await
+                .into_response();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(is_public(state.clone(), token.clone()).await, Some(true));
+
+        let response = admin_uphold_reports(State(state.clone()), admin, Path(token.clone()))
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(is_public(state.clone(), token.clone()).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_reports_are_rate_limited() {
+        let state = test_state().await;
+        let mut statuses = Vec::new();
+        for _ in 0..=state.config.load().paste.reports_per_hour {
+            let response = report_paste(
+                State(state.clone()),
+                ClientIp([10, 0, 0, 9].into()),
+                HeaderMap::new(),
+                PasteToken("missing".to_string()),
+                Form(ReportForm { reason: None }),
+            )
+            .await
+            .into_response();
+            statuses.push(response.status());
+        }
+        assert_eq!(statuses.first(), Some(&StatusCode::NOT_FOUND));
+        assert_eq!(statuses.last(), Some(&StatusCode::TOO_MANY_REQUESTS));
+    }
+
+    #[tokio::test]
+    async fn test_reactions_are_rate_limited() {
+        let state = test_state().await;
+        let mut statuses = Vec::new();
+        for _ in 0..=state.config.load().paste.reactions_per_minute {
+            let response = react_to_paste(
+                State(state.clone()),
+                ClientIp([10, 0, 0, 9].into()),
+                HeaderMap::new(),
+                PasteToken("missing".to_string()),
+                Form(ReactForm {
+                    emoji: REACTIONS[0].to_string(),
+                }),
+            )
+            .await
+            .into_response();
+            statuses.push(response.status());
+        }
+        assert_eq!(statuses.first(), Some(&StatusCode::NOT_FOUND));
+        assert_eq!(statuses.last(), Some(&StatusCode::TOO_MANY_REQUESTS));
+    }
+
+    struct MockVerifier(Result<bool, String>);
+
+    impl captcha::CaptchaVerifier for MockVerifier {
+        fn verify<'a>(&'a self, response: &'a str) -> captcha::VerifyFuture<'a> {
+            let outcome = self.0.clone().map(|ok| ok && response == "token");
+            Box::pin(async move { outcome })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_captcha_gates_creation() {
+        let create = |outcome: Result<bool, String>, fail_open: bool, response: Option<&str>| {
+            let response = response.map(str::to_string);
+            async move {
+                let mut state = test_state().await;
+                update_config(&state, |config| {
+                    config.captcha = Some(CaptchaConfig {
+                        provider: CaptchaProvider::Turnstile,
+                        site_key: "site".to_string(),
+                        secret: "secret".to_string(),
+                        timeout_secs: 1,
+                        fail_open,
+                    });
+                });
+                state.captcha = Some(Arc::new(MockVerifier(outcome)));
+                let form = PasteForm {
+                    content: "hello".to_string(),
+                    turnstile_response: response,
+                    ..Default::default()
+                };
+                create_paste(State(state), HeaderMap::new(), local_client(), Form(form))
+                    .await
+                    .into_response()
+                    .status()
+            }
+        };
+        assert_eq!(
+            create(Ok(true), false, Some("token")).await,
+            StatusCode::SEE_OTHER
+        );
+        assert_eq!(create(Ok(true), false, None).await, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            create(Ok(true), false, Some("forged")).await,
+            StatusCode::BAD_REQUEST
+        );
+        let outage = || Err("timed out".to_string());
+        assert_eq!(
+            create(outage(), true, Some("token")).await,
+            StatusCode::SEE_OTHER
+        );
+        assert_eq!(
+            create(outage(), false, Some("token")).await,
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[tokio::test]
+    async fn test_announcement_is_escaped_and_expires() {
+        let state = test_state().await;
+        let render = |state: AppState, lang: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert("accept-language", HeaderValue::from_str(lang).unwrap());
+            async move {
+                let response = view_paste(
+                    State(state),
+                    headers,
+                    local_client(),
+                    PasteToken("missing".to_string()),
+                    Query(HashMap::new()),
+                )
+                .await
+                .into_response();
+                body_string(response).await
+            }
+        };
+
+        update_config(&state, |config| {
+            config.announcement = Some(AnnouncementConfig {
+                severity: Severity::Warn,
+                expires_at: Some(now_ts() + 3600),
+                texts: BTreeMap::from([
+                    ("zh".to_string(), "维护 <b>通知</b>".to_string()),
+                    (
+                        "en".to_string(),
+                        "Maintenance <script>alert(1)</script>".to_string(),
+                    ),
+                ]),
+            });
+        });
+        let body = render(state.clone(), "en").await;
+        assert!(body.contains("announcement-warn"));
+        assert!(body.contains("Maintenance &#60;script&#62;"));
+        assert!(!body.contains("<script>alert"));
+        assert!(
+            render(state.clone(), "zh")
+                .await
+                .contains("维护 &#60;b&#62;")
+        );
+        // Japanese has no text of its own and gets the English one
+        assert!(
+            render(state.clone(), "ja")
+                .await
+                .contains("Maintenance &#60;script&#62;")
+        );
+        update_config(&state, |config| {
+            let texts = &mut config.announcement.as_mut().unwrap().texts;
+            texts.insert("ja".to_string(), "メンテナンス".to_string());
+        });
+        assert!(render(state.clone(), "ja").await.contains("メンテナンス"));
+
+        update_config(&state, |config| {
+            config.announcement.as_mut().unwrap().expires_at = Some(now_ts() - 1);
+        });
+        assert!(!render(state, "en").await.contains("announcement-warn"));
+    }
+
+    #[tokio::test]
+    async fn test_about_page_hidden_without_file() {
+        let state = test_state().await;
+        let about = |state: AppState| async move {
+            about_page(State(state), HeaderMap::new(), Query(HashMap::new()))
+                .await
+                .into_response()
+        };
+        assert_eq!(about(state.clone()).await.status(), StatusCode::NOT_FOUND);
+
+        state.about.store(Arc::new(AboutPages {
+            pages: HashMap::from([(
+                "zh".to_string(),
+                crate::markdown::render("# 联系我们\n\n<b>hi</b>"),
+            )]),
+        }));
+        let response = about(state.clone()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = body_string(response).await;
+        assert!(body.contains("<h1>联系我们</h1>"));
+        assert!(body.contains("&lt;b&gt;hi&lt;/b&gt;"));
+
+        let response = index(
+            State(state),
+            HeaderMap::new(),
+            Query(IndexQuery {
+                lang: None,
+                fork: None,
+            }),
+        )
+        .await
+        .into_response();
+        assert!(body_string(response).await.contains(r#"href="/about""#));
     }
 }
@@ -1,55 +1,4464 @@
+mod activity;
+mod api;
+mod archive;
+mod assets;
+mod audit;
+mod backup;
+mod captcha;
+mod cli;
 mod config;
+mod counts;
+mod crypto;
 mod db;
+mod detect;
+mod diff;
+mod error;
+mod featured;
+mod files;
 mod handlers;
+mod maintenance;
+mod markdown;
+mod metrics;
 mod models;
+mod net;
+mod paste_cache;
+mod qr;
+mod rate_limit;
+mod receipts;
+mod slug;
+mod syntax;
+#[cfg(test)]
+mod test_support;
+mod text_stats;
 mod utils;
+mod webhooks;
 
+use arc_swap::ArcSwap;
 use axum::{
     Router,
-    routing::{get, post},
+    extract::DefaultBodyLimit,
+    middleware,
+    routing::{delete, get, post},
+};
+use clap::Parser;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tower_http::compression::{
+    CompressionLayer, Predicate,
+    predicate::{NotForContentType, SizeAbove},
 };
-use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
-use std::fs;
 use tower_http::services::ServeDir;
 
-use crate::models::AppState;
-use crate::utils::resolve_path;
+use crate::models::{AppConfig, AppState};
 
 #[tokio::main]
 async fn main() {
-    let config = config::load_config();
-    let i18n = config::load_i18n(&config.i18n);
-    let db_path = resolve_path(&config.paste.db_path);
-    
-    if let Some(parent) = db_path.parent() {
-        fs::create_dir_all(parent).unwrap();
-    }
-    
-    let connect_options = SqliteConnectOptions::new()
-        .filename(db_path)
-        .create_if_missing(true);
-        
-    let pool = SqlitePoolOptions::new()
-        .max_connections(5)
-        .connect_with(connect_options)
-        .await
-        .expect("Failed to connect to database");
-
-    db::ensure_schema(&pool).await;
+    let cli = cli::Cli::parse();
+    let config_path = cli.config_path().unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    });
+    let mut config = config::load_config(&config_path).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    });
+    cli.apply_overrides(&mut config);
+
+    match cli.command.unwrap_or(cli::Command::Serve) {
+        cli::Command::Serve => run_server(config, config_path).await,
+        cli::Command::CheckConfig => match cli::check_config(&config) {
+            Ok(()) => println!("{}: OK", config_path),
+            Err(errors) => {
+                for error in errors {
+                    eprintln!("Invalid configuration: {}", error);
+                }
+                std::process::exit(1);
+            }
+        },
+        cli::Command::Cleanup => {
+            let pool = db::connect(&config.paste.db_path).await;
+            let report = cli::cleanup(&pool, &config, utils::now_ts()).await;
+            println!(
+                "Removed {} expired and {} evicted pastes",
+                report.expired, report.evicted
+            );
+        }
+        cli::Command::Export {
+            include_plaintext,
+            output,
+        } => {
+            let cipher = cli_cipher(&config);
+            let pool = db::connect(&config.paste.db_path).await;
+            let result = match output {
+                Some(path) => match std::fs::File::create(&path) {
+                    Ok(file) => {
+                        let mut file = std::io::BufWriter::new(file);
+                        cli::export(pool, cipher, include_plaintext, utils::now_ts(), &mut file)
+                            .await
+                    }
+                    Err(err) => Err(format!("Failed to create {}: {}", path, err)),
+                },
+                None => {
+                    let mut out = std::io::stdout();
+                    cli::export(pool, cipher, include_plaintext, utils::now_ts(), &mut out).await
+                }
+            };
+            if let Err(err) = result {
+                eprintln!("Export failed: {}", err);
+                std::process::exit(1);
+            }
+        }
+        cli::Command::Import { file, overwrite } => {
+            let cipher = cli_cipher(&config);
+            let pool = db::connect(&config.paste.db_path).await;
+            match cli::import(
+                &pool,
+                cipher.as_ref(),
+                &file,
+                overwrite,
+                &config.paste,
+                utils::now_ts(),
+            )
+            .await
+            {
+                Ok(summary) => println!(
+                    "Imported {} pastes, skipped {}, failed {}",
+                    summary.inserted, summary.skipped, summary.failed
+                ),
+                Err(err) => {
+                    eprintln!("Import failed: {}", err);
+                    std::process::exit(1);
+                }
+            }
+        }
+        cli::Command::Stats => {
+            let pool = db::connect(&config.paste.db_path).await;
+            let stats = db::stats(&pool).await.expect("Failed to read stats");
+            println!("{}", cli::format_stats(&stats));
+        }
+    }
+}
+
+/// Validates the config before a maintenance command touches the database,
+/// so a bad key is reported instead of panicking.
+fn cli_cipher(config: &AppConfig) -> Option<crypto::ContentCipher> {
+    if let Err(errors) = cli::check_config(config) {
+        for error in errors {
+            eprintln!("Invalid configuration: {}", error);
+        }
+        std::process::exit(1);
+    }
+    config.paste.encryption_key.as_deref().map(|key| {
+        crypto::ContentCipher::from_hex(key).unwrap_or_else(|err| {
+            eprintln!("Invalid configuration: {}", err);
+            std::process::exit(1);
+        })
+    })
+}
+
+async fn run_server(config: AppConfig, config_path: String) {
+    if let Err(errors) = config::validate_config(&config) {
+        for error in errors {
+            eprintln!("Invalid configuration: {}", error);
+        }
+        std::process::exit(1);
+    }
+    if let Some(warning) = config::token_saturation_warning(&config.paste) {
+        eprintln!("{}", warning);
+    }
+    audit::init();
+    let cipher = config
+        .paste
+        .encryption_key
+        .as_deref()
+        .map(|key| crypto::ContentCipher::from_hex(key).unwrap());
+    let i18n = config::load_i18n(&config.i18n).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    });
+    let about = config::load_about(config.about.as_ref());
+
+    let pool = db::connect(&config.paste.db_path).await;
+    match db::self_check(&pool).await {
+        Ok(report) => {
+            println!("Database schema version {}", report.version);
+            for (table, columns) in &report.tables {
+                println!("  {}: {}", table, columns.join(", "));
+            }
+        }
+        Err(err) => {
+            eprintln!("Database self-check failed: {}", err);
+            std::process::exit(1);
+        }
+    }
+    db::backfill_content_hashes(&pool, cipher.as_ref()).await;
+    let signing_key = match config.server.signing_key.as_deref() {
+        Some(key) => crypto::signing_key(Some(key)).unwrap(),
+        None => db::stored_signing_key(&pool)
+            .await
+            .expect("Failed to load the signing key"),
+    };
+
+    let captcha = config.captcha.as_ref().map(|captcha| {
+        Arc::new(captcha::SiteVerifier::new(captcha)) as Arc<dyn captcha::CaptchaVerifier>
+    });
+    let report_limiter = Arc::new(rate_limit::RateLimiter::new(
+        config.paste.reports_per_hour,
+        3600,
+    ));
+    let reaction_limiter = Arc::new(rate_limit::RateLimiter::new(
+        config.paste.reactions_per_minute,
+        60,
+    ));
 
     let addr = format!("{}:{}", config.server.host, config.server.port);
-    let app = Router::new()
+    let state = AppState {
+        pool,
+        config: Arc::new(ArcSwap::from_pointee(config)),
+        i18n: Arc::new(ArcSwap::from_pointee(i18n)),
+        config_path,
+        cipher,
+        signing_key,
+        report_limiter,
+        reaction_limiter,
+        slug_limiter: Arc::new(rate_limit::RateLimiter::new(slug::CHECKS_PER_MINUTE, 60)),
+        metrics: Arc::new(metrics::Metrics::default()),
+        captcha,
+        about: Arc::new(ArcSwap::from_pointee(about)),
+        activity: Arc::new(activity::ActivityCache::default()),
+        featured: Arc::new(featured::FeaturedCache::default()),
+        public_counts: Arc::new(counts::PublicCountCache::default()),
+        usage: Arc::new(counts::UsageCache::default()),
+        maintenance: Arc::new(maintenance::MaintenanceLog::default()),
+        paste_cache: Arc::new(paste_cache::PasteCache::default()),
+        spent_receipts: Arc::new(receipts::SpentReceipts::default()),
+        clock: Arc::new(utils::SystemClock),
+    };
+    #[cfg(unix)]
+    tokio::spawn(reload_on_sighup(state.clone()));
+    tokio::spawn(backup::run_schedule(state.clone()));
+    tokio::spawn(webhooks::run_sender(state.clone()));
+    tokio::spawn(activity::run_rollup(state.clone()));
+    tokio::spawn(paste_cache::run_flusher(state.clone()));
+    let app = build_router(state);
+
+    let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
+    println!("Listening on {}", addr);
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .unwrap();
+}
+
+/// `kill -HUP` re-reads the configuration, like `POST /admin/api/reload`.
+#[cfg(unix)]
+async fn reload_on_sighup(state: AppState) {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut hangups = signal(SignalKind::hangup()).expect("Failed to listen for SIGHUP");
+    while hangups.recv().await.is_some() {
+        if let Err(errors) = config::reload(&state) {
+            for error in errors {
+                eprintln!("Reload failed: {}", error);
+            }
+        }
+    }
+}
+
+fn build_router(state: AppState) -> Router {
+    let router = Router::new()
         .route("/", get(handlers::index))
-        .route("/paste", post(handlers::create_paste))
-        .route("/p/{token}", get(handlers::view_paste))
+        .route(
+            "/paste",
+            post(handlers::create_paste)
+                .route_layer(middleware::from_fn_with_state(
+                    state.clone(),
+                    handlers::reject_banned,
+                ))
+                .route_layer(middleware::from_fn_with_state(
+                    state.clone(),
+                    handlers::limit_paste_body,
+                ))
+                .layer(DefaultBodyLimit::disable()),
+        )
+        .route(
+            "/api/v1/paste",
+            post(handlers::api_create_paste)
+                .route_layer(middleware::from_fn_with_state(
+                    state.clone(),
+                    handlers::reject_banned,
+                ))
+                .route_layer(middleware::from_fn_with_state(
+                    state.clone(),
+                    handlers::limit_paste_body,
+                ))
+                .layer(DefaultBodyLimit::disable()),
+        )
+        .route(
+            "/api/v1/paste/validate",
+            post(handlers::api_validate_paste)
+                .route_layer(middleware::from_fn_with_state(
+                    state.clone(),
+                    handlers::limit_paste_body,
+                ))
+                .layer(DefaultBodyLimit::disable()),
+        )
+        .route(
+            "/p/{token}",
+            get(handlers::view_paste).head(handlers::head_paste),
+        )
+        .route("/diff/{old}/{new}", get(handlers::view_diff))
         .route("/p/{token}/renew", post(handlers::renew_paste))
-        .route("/r/{token}", get(handlers::view_paste_raw))
+        .route("/p/{token}/share", get(handlers::share_paste))
+        .route("/p/{token}/report", post(handlers::report_paste))
+        .route("/p/{token}/react", post(handlers::react_to_paste))
+        .route("/p/{token}/related", post(handlers::set_related_links))
+        .route(
+            "/p/{token}/print",
+            get(handlers::view_paste_print).head(handlers::head_paste_print),
+        )
+        .route(
+            "/p/{token}/links",
+            get(handlers::list_share_links).post(handlers::create_share_link),
+        )
+        .route(
+            "/p/{token}/links/{share_token}",
+            delete(handlers::revoke_share_link),
+        )
+        .route("/m/{manage_token}", get(handlers::manage_page))
+        .route("/mine/rotate", post(handlers::rotate_owner))
+        .route("/m/{manage_token}/views", get(handlers::manage_views))
+        .route("/m/{manage_token}/delete", post(handlers::manage_delete))
+        .route("/m/{manage_token}/renew", post(handlers::manage_renew))
+        .route("/m/{manage_token}/publish", post(handlers::manage_publish))
+        .route(
+            "/m/{manage_token}/unpublish",
+            post(handlers::manage_unpublish),
+        )
+        .route(
+            "/m/{manage_token}/regenerate",
+            post(handlers::manage_regenerate),
+        )
+        .route(
+            "/s/{token}",
+            get(handlers::view_shared_paste).head(handlers::head_shared_paste),
+        )
+        .route(
+            "/r/{token}",
+            get(handlers::view_paste_raw).head(handlers::head_paste_raw),
+        )
+        .route(
+            "/r/{token}/{filename}",
+            get(handlers::view_paste_file_raw).head(handlers::head_paste_file_raw),
+        )
+        .route("/readyz", get(handlers::readyz))
+        .route("/about", get(handlers::about_page))
         .route("/explore", get(handlers::explore))
+        .route("/archive", get(handlers::archive_page))
+        .route("/api/v1/archive", get(handlers::api_archive))
+        .route("/api/v1/explore", get(handlers::api_explore))
+        // Kept from before the API was versioned.
         .route("/api/explore", get(handlers::api_explore))
-        .nest_service("/assets", ServeDir::new("assets"))
-        .with_state(AppState { pool, config, i18n });
+        .route("/api/v1/p/{token}/meta", get(handlers::api_paste_meta))
+        .route("/api/v1/p/{token}/hash", get(handlers::api_paste_hash))
+        .route(
+            "/api/v1/p/{token}/neighbors",
+            get(handlers::api_paste_neighbors),
+        )
+        .route("/api/v1/recent", get(handlers::api_recent))
+        .route("/api/v1/recent/mine", get(handlers::api_own_recent))
+        .route("/api/v1/slug-available", get(handlers::api_slug_available))
+        .route("/api/v1/stats", get(handlers::api_stats))
+        .route("/api/v1/stats/daily", get(handlers::api_stats_daily))
+        .route(
+            "/api/v1/stats/languages",
+            get(handlers::api_stats_languages),
+        )
+        .route("/api/v1/limits", get(handlers::api_limits))
+        .route("/api/v1/openapi.json", get(handlers::api_openapi))
+        .route("/admin", get(handlers::admin_page))
+        .route("/admin/api/backup", post(handlers::admin_backup))
+        .route("/admin/api/storage", get(handlers::admin_storage))
+        .route("/admin/api/schema", get(handlers::admin_schema))
+        .route("/admin/api/export", get(handlers::admin_export))
+        .route(
+            "/admin/api/import",
+            post(handlers::admin_import).layer(DefaultBodyLimit::disable()),
+        )
+        .route("/admin/api/reload", post(handlers::admin_reload))
+        .route("/admin/api/mode", post(handlers::admin_set_mode))
+        .route("/admin/api/reports", get(handlers::admin_list_reports))
+        .route(
+            "/admin/api/reports/{token}/clear",
+            post(handlers::admin_clear_reports),
+        )
+        .route(
+            "/admin/api/reports/{token}/uphold",
+            post(handlers::admin_uphold_reports),
+        )
+        .route("/admin/api/purge", post(handlers::admin_purge))
+        .route(
+            "/admin/api/featured",
+            delete(handlers::admin_clear_featured),
+        )
+        .route(
+            "/admin/api/p/{token}/feature",
+            post(handlers::admin_feature_paste),
+        )
+        .route("/admin/api/events", get(handlers::admin_list_events))
+        .route("/admin/api/maintenance", get(handlers::admin_maintenance))
+        .route(
+            "/admin/api/bans",
+            get(handlers::admin_list_bans).post(handlers::admin_add_ban),
+        )
+        .route("/admin/api/bans/{id}", delete(handlers::admin_remove_ban))
+        .route(
+            "/admin/api/webhooks/dead",
+            get(handlers::admin_list_dead_webhooks),
+        )
+        .route(
+            "/admin/api/webhooks/{id}/retry",
+            post(handlers::admin_retry_webhook),
+        )
+        .route(
+            "/admin/api/p/{token}/delete",
+            post(handlers::admin_delete_paste),
+        )
+        .route(
+            "/admin/api/p/{token}/unpublish",
+            post(handlers::admin_unpublish_paste),
+        )
+        .route("/admin/api/p/{token}/pin", post(handlers::admin_pin_paste))
+        .route(
+            "/admin/api/p/{token}/unpin",
+            post(handlers::admin_unpin_paste),
+        );
+    let router = match &state.config.load().server.assets_dir {
+        Some(dir) => router.nest_service("/assets", ServeDir::new(dir)),
+        None => router.route("/assets/{*path}", get(handlers::serve_asset)),
+    };
+    let router = router
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            handlers::service_mode,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            handlers::audit_admin,
+        ));
+    let router = if state.config.load().server.compression {
+        router
+            .layer(compression_layer())
+            .layer(middleware::from_fn(handlers::compression_headers))
+    } else {
+        router
+    };
+    router.with_state(state)
+}
 
-    let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
-    println!("Listening on {}", addr);
-    axum::serve(listener, app).await.unwrap();
-}
\ No newline at end of file
+/// Responses under a kilobyte are not worth the CPU. Streamed bodies
+/// (the admin export) go out as they are produced.
+fn compression_layer() -> CompressionLayer<impl Predicate> {
+    let predicate = SizeAbove::new(handlers::COMPRESS_MIN_BYTES)
+        .and(NotForContentType::GRPC)
+        .and(NotForContentType::IMAGES)
+        .and(NotForContentType::SSE)
+        .and(NotForContentType::const_new("application/x-ndjson"));
+    CompressionLayer::new()
+        .no_deflate()
+        .no_zstd()
+        .compress_when(predicate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Lang;
+    use crate::test_support::{
+        MockClock, PasteBuilder, body_string, send, test_state, update_config,
+    };
+    use crate::utils::now_ts;
+    use axum::body::Body;
+    use axum::extract::ConnectInfo;
+    use axum::http::{Request, StatusCode, header};
+    use tower::ServiceExt;
+
+    async fn admin_state() -> AppState {
+        let state = test_state().await;
+        update_config(&state, |config| {
+            config.server.admin_token = Some("sesame".to_string());
+        });
+        state
+    }
+
+    #[tokio::test]
+    async fn test_admin_requires_token() {
+        let app = build_router(admin_state().await);
+        let response = app
+            .clone()
+            .oneshot(Request::get("/admin").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::get("/admin?token=wrong")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert!(!response.headers().contains_key(header::SET_COOKIE));
+
+        let response = app
+            .oneshot(
+                Request::post("/admin/api/p/abcd/delete")
+                    .header(header::COOKIE, "admin=forged")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_admin_page_form_deletes_paste() {
+        let state = admin_state().await;
+        let token = PasteBuilder::new()
+            .title("Spam")
+            .content("buy now")
+            .public(true)
+            .insert(&state.pool)
+            .await
+            .token;
+        let app = build_router(state.clone());
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::get("/admin?token=sesame")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        let cookie = response.headers()[header::SET_COOKIE].to_str().unwrap();
+        // Plain HTTP here: a Secure cookie would never come back.
+        assert!(!cookie.contains("Secure"));
+        let session = cookie.split(';').next().unwrap().to_string();
+        let response = app
+            .clone()
+            .oneshot(
+                Request::get("/admin?token=sesame")
+                    .header("x-forwarded-proto", "https")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let cookie = response.headers()[header::SET_COOKIE].to_str().unwrap();
+        assert!(cookie.ends_with("; Secure"));
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::get("/admin")
+                    .header(header::COOKIE, &session)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let action = format!("/admin/api/p/{}/delete", token);
+        assert!(String::from_utf8_lossy(&body).contains(&action));
+
+        let response = app
+            .oneshot(
+                Request::post(&action)
+                    .header(header::COOKIE, &session)
+                    .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert_eq!(response.headers()[header::LOCATION], "/admin");
+        assert!(
+            db::fetch_paste_meta(&state.pool, &token, now_ts())
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_banned_clients_cannot_create_pastes() {
+        let app = build_router(admin_state().await);
+        let ban = |body: &'static str| {
+            Request::post("/admin/api/bans")
+                .header(header::AUTHORIZATION, "Bearer sesame")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body))
+                .unwrap()
+        };
+        let response = app
+            .clone()
+            .oneshot(ban(r#"{"cidr": "203.0.113.0/24"}"#))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let response = app
+            .clone()
+            .oneshot(ban(r#"{"cidr": "2001:db8::/32", "expires_at": 1}"#))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let create = |peer: &str| {
+            let mut request = Request::post("/paste")
+                .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+                .body(Body::from("content=hello"))
+                .unwrap();
+            request
+                .extensions_mut()
+                .insert(ConnectInfo(peer.parse::<SocketAddr>().unwrap()));
+            request
+        };
+        let response = app
+            .clone()
+            .oneshot(create("203.0.113.7:4000"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = app
+            .clone()
+            .oneshot(create("198.51.100.7:4000"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        // The IPv6 ban has already expired.
+        let response = app.oneshot(create("[2001:db8::1]:4000")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    }
+
+    #[tokio::test]
+    async fn test_assets_are_served_from_the_binary() {
+        let app = build_router(test_state().await);
+        let response = app
+            .clone()
+            .oneshot(
+                Request::get("/assets/style.css")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "text/css");
+        assert_eq!(
+            response.headers()[header::CACHE_CONTROL],
+            "public, max-age=300"
+        );
+
+        let response = app
+            .oneshot(
+                Request::get("/assets/missing.css")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let state = test_state().await;
+        update_config(&state, |config| {
+            config.server.assets_dir = Some("assets".to_string());
+        });
+        let response = build_router(state)
+            .oneshot(
+                Request::get("/assets/style.css")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_pages_link_versioned_assets() {
+        let app = build_router(test_state().await);
+        let response = app
+            .clone()
+            .oneshot(Request::get("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let body = crate::test_support::body_string(response).await;
+        let url = assets::asset_url("style.css");
+        assert!(url.starts_with("/assets/style.css?v="));
+        assert!(body.contains(&format!(r#"href="{}""#, url)));
+
+        let response = app
+            .oneshot(Request::get(url.as_str()).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(
+            response.headers()[header::CACHE_CONTROL],
+            "public, max-age=31536000, immutable"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_admin_reload_applies_new_config() {
+        let example = std::fs::read_to_string("config/app.toml.example").unwrap();
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let mut state = test_state().await;
+        state.config_path = file.path().to_str().unwrap().to_string();
+        update_config(&state, |config| {
+            config.server.admin_token = Some("sesame".to_string());
+        });
+        let app = build_router(state.clone());
+        let reload = || {
+            Request::post("/admin/api/reload")
+                .header(header::AUTHORIZATION, "Bearer sesame")
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let edited = example
+            .replace(
+                "expires_options_secs = [3600, 86400, 604800]",
+                "expires_options_secs = [60, 3600]",
+            )
+            .replace("port = 8080", "port = 9090")
+            .replace("compression = true", "compression = false")
+            .replace("# admin_token = \"\"", "admin_token = \"sesame\"");
+        std::fs::write(file.path(), edited).unwrap();
+        let response = app.clone().oneshot(reload()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = crate::test_support::body_string(response).await;
+        let summary: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            summary["changed"],
+            serde_json::json!(["paste.expires_options_secs"])
+        );
+        assert_eq!(
+            summary["ignored"],
+            serde_json::json!(["server.compression", "server.port"])
+        );
+        let config = state.config.load();
+        assert_eq!(config.paste.expires_options_secs, vec![60, 3600]);
+        assert_eq!(config.server.port, 8080);
+        assert!(config.server.compression);
+
+        std::fs::write(file.path(), "[server]\nport = \"nope\"").unwrap();
+        let response = app.oneshot(reload()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            state.config.load().paste.expires_options_secs,
+            vec![60, 3600]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_admin_export_streams_json_lines() {
+        let state = admin_state().await;
+        PasteBuilder::new()
+            .content("exported")
+            .insert(&state.pool)
+            .await;
+        let app = build_router(state);
+        let response = app
+            .clone()
+            .oneshot(
+                Request::get("/admin/api/export")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = app
+            .oneshot(
+                Request::get("/admin/api/export")
+                    .header(header::AUTHORIZATION, "Bearer sesame")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "application/x-ndjson"
+        );
+        let body = crate::test_support::body_string(response).await;
+        assert_eq!(body.lines().count(), 1);
+        assert!(body.contains(r#""content":"exported""#));
+    }
+
+    #[tokio::test]
+    async fn test_admin_backup_failure_shows_in_stats() {
+        let state = admin_state().await;
+        let app = build_router(state.clone());
+        let backup = || {
+            Request::post("/admin/api/backup")
+                .header(header::AUTHORIZATION, "Bearer sesame")
+                .body(Body::empty())
+                .unwrap()
+        };
+        let response = app.clone().oneshot(backup()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let file = tempfile::NamedTempFile::new().unwrap();
+        update_config(&state, |config| {
+            config.backup = Some(models::BackupConfig {
+                dir: file.path().to_str().unwrap().to_string(),
+                interval_secs: 60,
+                keep: 1,
+            });
+        });
+        let response = app.clone().oneshot(backup()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        let response = app
+            .clone()
+            .oneshot(Request::get("/api/v1/stats").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let body = crate::test_support::body_string(response).await;
+        assert!(body.contains(r#""last_failed":true"#));
+        assert!(body.contains(r#""failures":1"#));
+
+        let dir = tempfile::tempdir().unwrap();
+        update_config(&state, |config| {
+            config.backup.as_mut().unwrap().dir = dir.path().to_str().unwrap().to_string();
+        });
+        let response = app.clone().oneshot(backup()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = crate::test_support::body_string(response).await;
+        assert!(body.contains(r#""path":"#));
+        assert!(state.metrics.backup_status().last_success_at.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_oversized_paste_bodies_are_rejected() {
+        let state = test_state().await;
+        update_config(&state, |config| config.paste.max_content_length = 100);
+        let limit = 100 * 12 + 64 * 1024;
+        let app = build_router(state);
+        // A valid paste, padded with an ignored field to exactly `len` bytes.
+        let body = |len: usize| {
+            let head = format!("content={}&pad=", "a".repeat(100));
+            format!("{}{}", head, "x".repeat(len - head.len()))
+        };
+        let create = |body: String, accept: &str| {
+            Request::post("/paste")
+                .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+                .header(header::CONTENT_LENGTH, body.len())
+                .header(header::ACCEPT, accept)
+                .body(Body::from(body))
+                .unwrap()
+        };
+
+        let response = app
+            .clone()
+            .oneshot(create(body(limit), "text/html"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+
+        let response = app
+            .clone()
+            .oneshot(create(body(limit + 1), "text/html"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let text = crate::test_support::body_string(response).await;
+        assert!(text.contains("100"), "{}", text);
+
+        let response = app
+            .clone()
+            .oneshot(create(body(limit + 1), "application/json"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let text = crate::test_support::body_string(response).await;
+        assert_eq!(text, r#"{"error":"content_too_long"}"#);
+
+        // Without a Content-Length the body is cut off while it streams in.
+        let chunks = body(limit + 1)
+            .into_bytes()
+            .chunks(1024)
+            .map(|chunk| Ok::<_, std::io::Error>(chunk.to_vec()))
+            .collect::<Vec<_>>();
+        let response = app
+            .oneshot(
+                Request::post("/paste")
+                    .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+                    .body(Body::from_stream(futures_util::stream::iter(chunks)))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn test_raw_and_api_follow_the_requested_language() {
+        let state = test_state().await;
+        let token = PasteBuilder::new()
+            .title("Hello")
+            .content("fn main() {}")
+            .insert(&state.pool)
+            .await
+            .token;
+        let app = build_router(state.clone());
+        let get = |uri: String, cookie: Option<&'static str>| {
+            let mut request = Request::get(uri);
+            if let Some(cookie) = cookie {
+                request = request.header(header::COOKIE, cookie);
+            }
+            let app = app.clone();
+            async move {
+                let response = app
+                    .oneshot(request.body(Body::empty()).unwrap())
+                    .await
+                    .unwrap();
+                let status = response.status();
+                (status, body_string(response).await)
+            }
+        };
+        let i18n = state.i18n.load();
+        let (en, zh) = (i18n.strings(Lang::En), i18n.strings(Lang::Zh));
+
+        let (status, body) = get("/r/missing?lang=en".to_string(), None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert!(body.starts_with(&en.raw_never_existed));
+        let (_, body) = get("/r/missing".to_string(), Some("lang=en")).await;
+        assert!(body.starts_with(&en.raw_never_existed));
+        let (_, body) = get("/r/missing".to_string(), None).await;
+        assert!(body.starts_with(&zh.raw_never_existed));
+
+        let (status, body) = get(format!("/api/v1/p/{}/meta?lang=en", token), None).await;
+        assert_eq!(status, StatusCode::OK);
+        let meta: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(meta["lang"], "en");
+        assert_eq!(meta["language_label"], "Plain Text");
+        let (_, body) = get(format!("/api/v1/p/{}/meta?lang=zh", token), None).await;
+        let meta: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(meta["language_label"], "纯文本");
+    }
+
+    #[tokio::test]
+    async fn test_management_link() {
+        let state = test_state().await;
+        let app = build_router(state.clone());
+        let send = |request: Request<Body>| {
+            let app = app.clone();
+            async move {
+                let response = app.oneshot(request).await.unwrap();
+                let status = response.status();
+                let location = response
+                    .headers()
+                    .get(header::LOCATION)
+                    .map(|value| value.to_str().unwrap().to_string());
+                (status, location, body_string(response).await)
+            }
+        };
+        let post = |uri: String| Request::post(uri).body(Body::empty()).unwrap();
+        let get = |uri: String| Request::get(uri).body(Body::empty()).unwrap();
+
+        let (_, _, body) = send(
+            Request::post("/paste")
+                .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+                .header("hx-request", "true")
+                .body(Body::from("content=hello&title=Mine&is_public=on"))
+                .unwrap(),
+        )
+        .await;
+        let between = |body: &str, start: &str| {
+            let rest = &body[body.find(start).unwrap() + start.len()..];
+            rest[..rest.find('"').unwrap()].to_string()
+        };
+        let token = between(&body, "value=\"/p/");
+        let manage = between(&body, "value=\"/m/");
+        assert_eq!(manage.len(), 32);
+
+        // The manage token stays off every public surface.
+        for uri in [
+            format!("/p/{}", token),
+            format!("/api/v1/p/{}/meta", token),
+            "/api/explore".to_string(),
+            "/explore".to_string(),
+        ] {
+            let (status, _, body) = send(get(uri.clone())).await;
+            assert_eq!(status, StatusCode::OK, "{}", uri);
+            assert!(!body.contains(&manage), "{}", uri);
+        }
+
+        for action in [
+            "",
+            "/delete",
+            "/renew",
+            "/publish",
+            "/unpublish",
+            "/regenerate",
+        ] {
+            let uri = format!("/m/wrong{}", action);
+            let request = if action.is_empty() {
+                get(uri)
+            } else {
+                post(uri)
+            };
+            assert_eq!(send(request).await.0, StatusCode::NOT_FOUND, "{}", action);
+        }
+
+        let (status, _, body) = send(get(format!("/m/{}", manage))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("Mine") && body.contains(&format!("/p/{}", token)));
+
+        let public = |state: AppState, token: String| async move {
+            sqlx::query_scalar::<_, bool>("SELECT is_public FROM pastes WHERE token = ?")
+                .bind(token)
+                .fetch_one(&state.pool)
+                .await
+                .unwrap()
+        };
+        let (status, location, _) = send(post(format!("/m/{}/unpublish", manage))).await;
+        assert_eq!(status, StatusCode::SEE_OTHER);
+        assert_eq!(location.unwrap(), format!("/m/{}", manage));
+        assert!(!public(state.clone(), token.clone()).await);
+        send(post(format!("/m/{}/publish", manage))).await;
+        assert!(public(state.clone(), token.clone()).await);
+
+        sqlx::query("UPDATE pastes SET expires_at = expires_at - 100 WHERE token = ?")
+            .bind(&token)
+            .execute(&state.pool)
+            .await
+            .unwrap();
+        let before = now_ts();
+        send(post(format!("/m/{}/renew", manage))).await;
+        let expires_at: i64 = sqlx::query_scalar("SELECT expires_at FROM pastes WHERE token = ?")
+            .bind(&token)
+            .fetch_one(&state.pool)
+            .await
+            .unwrap();
+        assert!(expires_at >= before + 86400);
+
+        let (_, location, _) = send(post(format!("/m/{}/regenerate", manage))).await;
+        let renewed = location.unwrap().trim_start_matches("/m/").to_string();
+        assert_ne!(renewed, manage);
+        assert_eq!(
+            send(get(format!("/m/{}", manage))).await.0,
+            StatusCode::NOT_FOUND
+        );
+
+        let (status, location, _) = send(post(format!("/m/{}/delete", renewed))).await;
+        assert_eq!(
+            (status, location.as_deref()),
+            (StatusCode::SEE_OTHER, Some("/"))
+        );
+        assert_eq!(
+            send(get(format!("/p/{}", token))).await.0,
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[tokio::test]
+    async fn test_admin_lists_paste_events() {
+        let app = build_router(admin_state().await);
+        let response = app
+            .clone()
+            .oneshot(
+                Request::post("/paste")
+                    .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+                    .body(Body::from("content=hello"))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let location = response.headers()[header::LOCATION].to_str().unwrap();
+        let token = location.trim_start_matches("/p/").to_string();
+        let get = |uri: String, admin: bool| {
+            let mut request = Request::get(uri);
+            if admin {
+                request = request.header(header::AUTHORIZATION, "Bearer sesame");
+            }
+            app.clone().oneshot(request.body(Body::empty()).unwrap())
+        };
+        get(format!("/r/{}", token), false).await.unwrap();
+
+        let response = get(format!("/admin/api/events?token={}", token), false)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = get("/admin/api/events".to_string(), true).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = get(format!("/admin/api/events?token={}", token), true)
+            .await
+            .unwrap();
+        let events: serde_json::Value = serde_json::from_str(&body_string(response).await).unwrap();
+        let events = events.as_array().unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["event_type"], "viewed");
+        assert_eq!(events[1]["event_type"], "created");
+        // Both came from the same client, which is hashed rather than stored.
+        let client = events[0]["client_hash"].as_str().unwrap();
+        assert_eq!(events[1]["client_hash"], client);
+        assert!(!client.contains("0.0.0.0"));
+    }
+
+    #[tokio::test]
+    async fn test_daily_stats_api_and_sparkline() {
+        let app = build_router(test_state().await);
+        let get = |uri: &str| {
+            let request = Request::get(uri).body(Body::empty()).unwrap();
+            let app = app.clone();
+            async move {
+                let response = app.oneshot(request).await.unwrap();
+                (response.status(), body_string(response).await)
+            }
+        };
+        let (status, body) = get("/api/v1/stats/daily?days=3").await;
+        assert_eq!(status, StatusCode::OK);
+        let days: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(days.as_array().unwrap().len(), 3);
+        assert_eq!(days[2]["created"], 0);
+        assert_eq!(
+            get("/api/v1/stats/daily?days=week").await.0,
+            StatusCode::BAD_REQUEST
+        );
+        let (_, body) = get("/api/v1/stats/daily").await;
+        let days: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(days.as_array().unwrap().len(), 30);
+
+        let (_, body) = get("/").await;
+        assert_eq!(body.matches("<rect ").count(), 7);
+    }
+
+    #[tokio::test]
+    async fn test_capacity_indicator() {
+        let state = test_state().await;
+        update_config(&state, |config| {
+            config.paste.max_pastes = 4;
+            config.paste.capacity_warning_percent = 50;
+        });
+        let app = build_router(state.clone());
+        let get = |uri: &str| {
+            let request = Request::get(uri).body(Body::empty()).unwrap();
+            let app = app.clone();
+            async move { body_string(app.oneshot(request).await.unwrap()).await }
+        };
+        let body = get("/?lang=en").await;
+        assert!(body.contains("Storage 0% full"));
+        assert!(!body.contains("class=\"capacity-warning\""));
+
+        for content in ["one", "two", "three"] {
+            PasteBuilder::new()
+                .content(content)
+                .insert(&state.pool)
+                .await;
+        }
+        let body = get("/?lang=en").await;
+        assert!(body.contains("Storage 75% full"));
+        assert!(body.contains("class=\"capacity-warning\""));
+
+        let stats: serde_json::Value = serde_json::from_str(&get("/api/v1/stats").await).unwrap();
+        assert_eq!(stats["capacity"]["pastes"], 3);
+        assert_eq!(stats["capacity"]["pastes_percent"], 75);
+        assert_eq!(stats["capacity"]["content_length"], 11);
+    }
+
+    #[tokio::test]
+    async fn test_self_destruct_countdown() {
+        let app = build_router(test_state().await);
+        let send = |request: Request<Body>| {
+            let app = app.clone();
+            async move { app.oneshot(request).await.unwrap() }
+        };
+        let get = |uri: String| Request::get(uri).body(Body::empty()).unwrap();
+        let response = send(
+            Request::post("/paste")
+                .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+                .body(Body::from(
+                    "content=hello&is_public=on&destroy_after_first_view_secs=600",
+                ))
+                .unwrap(),
+        )
+        .await;
+        let location = response.headers()[header::LOCATION].to_str().unwrap();
+        let token = location.trim_start_matches("/p/").to_string();
+        let meta = |body: String| serde_json::from_str::<serde_json::Value>(&body).unwrap();
+
+        let before = meta(body_string(send(get(format!("/api/v1/p/{}/meta", token))).await).await);
+        assert_eq!(before["destroy_after_first_view_secs"], 600);
+        assert_eq!(before["destroys_at"], serde_json::Value::Null);
+        assert_eq!(before["is_public"], false);
+
+        let page = body_string(send(get(format!("/p/{}?lang=en", token))).await).await;
+        assert!(page.contains("This moment fades in ~ "));
+        let after = meta(body_string(send(get(format!("/api/v1/p/{}/meta", token))).await).await);
+        assert_eq!(after["destroys_at"], after["expires_at"]);
+
+        let renew = send(
+            Request::post(format!("/p/{}/renew", token))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(renew.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_scheduled_paste_goes_live() {
+        let state = test_state().await;
+        let app = build_router(state.clone());
+        let send = |request: Request<Body>| {
+            let app = app.clone();
+            async move { app.oneshot(request).await.unwrap() }
+        };
+        let create = |body: String| {
+            Request::post("/paste")
+                .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+                .body(Body::from(body))
+                .unwrap()
+        };
+        let live_at = now_ts() + 600;
+        let response = send(create(format!(
+            "content=embargoed&is_public=on&expires_in=3600&visible_after={}",
+            live_at
+        )))
+        .await;
+        let token = response.headers()[header::LOCATION].to_str().unwrap()[3..].to_string();
+        let owner = response.headers()[header::SET_COOKIE]
+            .to_str()
+            .unwrap()
+            .split(';')
+            .next()
+            .unwrap()
+            .to_string();
+        let status = |uri: String| {
+            let request = Request::get(uri).body(Body::empty()).unwrap();
+            async move { send(request).await.status() }
+        };
+        let explore = || async {
+            body_string(send(Request::get("/api/v1/explore").body(Body::empty()).unwrap()).await)
+                .await
+        };
+
+        // Before going live only the owner can see it, and cleanup keeps it.
+        db::cleanup_expired(&state.pool, 86400, false, now_ts()).await;
+        for uri in [
+            format!("/p/{}", token),
+            format!("/r/{}", token),
+            format!("/api/v1/p/{}/meta", token),
+        ] {
+            assert_eq!(status(uri.clone()).await, StatusCode::NOT_FOUND, "{}", uri);
+        }
+        assert!(!explore().await.contains(&token));
+        let preview = Request::get(format!("/p/{}?lang=en", token))
+            .header(header::COOKIE, &owner)
+            .body(Body::empty())
+            .unwrap();
+        let page = body_string(send(preview).await).await;
+        assert!(page.contains("Scheduled, goes live at"));
+        let head = |cookie: Option<&str>| {
+            let mut request = Request::head(format!("/p/{}", token));
+            if let Some(cookie) = cookie {
+                request = request.header(header::COOKIE, cookie);
+            }
+            request.body(Body::empty()).unwrap()
+        };
+        assert_eq!(send(head(Some(&owner))).await.status(), StatusCode::OK);
+        assert_eq!(send(head(None)).await.status(), StatusCode::NOT_FOUND);
+
+        // Once the time passes it is reachable like any other paste.
+        sqlx::query("UPDATE pastes SET visible_after = ? WHERE token = ?")
+            .bind(now_ts() - 1)
+            .bind(&token)
+            .execute(&state.pool)
+            .await
+            .unwrap();
+        for uri in [
+            format!("/p/{}", token),
+            format!("/r/{}", token),
+            format!("/api/v1/p/{}/meta", token),
+        ] {
+            assert_eq!(status(uri.clone()).await, StatusCode::OK, "{}", uri);
+        }
+        assert!(explore().await.contains(&token));
+
+        // Going live after expiry is refused.
+        let response = send(create(format!(
+            "content=late&expires_in=3600&visible_after={}",
+            now_ts() + 7200
+        )))
+        .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_extension_in_link_hints_language() {
+        let state = test_state().await;
+        update_config(&state, |config| {
+            for language in &mut config.languages {
+                if language.value == "json" {
+                    language.content_type = "application/json".to_string();
+                }
+            }
+        });
+        let token = PasteBuilder::new()
+            .content("{\"a\": 1}")
+            .insert(&state.pool)
+            .await
+            .token;
+        let app = build_router(state);
+        let get = |uri: String| {
+            let app = app.clone();
+            async move {
+                let request = Request::get(uri).body(Body::empty()).unwrap();
+                app.oneshot(request).await.unwrap()
+            }
+        };
+
+        let response = get(format!("/r/{}.json", token)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+        assert!(
+            response.headers()[header::CONTENT_DISPOSITION]
+                .to_str()
+                .unwrap()
+                .contains(&format!("paste-{}.json", token))
+        );
+        // Unknown extensions fall back to what was stored.
+        let response = get(format!("/r/{}.xyz", token)).await;
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "text/plain; charset=utf-8"
+        );
+
+        let page = body_string(get(format!("/p/{}.rs", token)).await).await;
+        assert!(page.contains(r#"data-language="rust""#));
+        assert!(page.contains(&format!("/r/{}\"", token)));
+        let page = body_string(get(format!("/p/{}.", token)).await).await;
+        assert!(page.contains(r#"data-language="plaintext""#));
+    }
+
+    #[tokio::test]
+    async fn test_read_only_and_maintenance_modes() {
+        let state = admin_state().await;
+        let token = PasteBuilder::new()
+            .content("still here")
+            .insert(&state.pool)
+            .await
+            .token;
+        let app = build_router(state);
+        let send = |request: Request<Body>| {
+            let mut request = request;
+            request
+                .extensions_mut()
+                .insert(ConnectInfo("127.0.0.1:4000".parse::<SocketAddr>().unwrap()));
+            app.clone().oneshot(request)
+        };
+        let set_mode = |mode: &str| {
+            Request::post("/admin/api/mode")
+                .header(header::AUTHORIZATION, "Bearer sesame")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(mode.to_string()))
+                .unwrap()
+        };
+        let create = || {
+            Request::post("/paste?lang=en")
+                .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+                .header("accept-language", "en")
+                .body(Body::from("content=hello"))
+                .unwrap()
+        };
+
+        let response = send(
+            Request::post("/admin/api/mode")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(r#"{"read_only": true}"#))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = send(set_mode(r#"{"read_only": true}"#)).await.unwrap();
+        assert_eq!(
+            body_string(response).await,
+            r#"{"read_only":true,"maintenance":false}"#
+        );
+        let response = send(create()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(body_string(response).await.contains("read-only"));
+        let response = send(
+            Request::post(format!("/p/{}/renew", token))
+                .header(header::ACCEPT, "application/json")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body_string(response).await, r#"{"error":"read_only"}"#);
+        let response = send(
+            Request::get(format!("/p/{}", token))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(body_string(response).await.contains("still here"));
+        let body = body_string(
+            send(Request::get("/?lang=en").body(Body::empty()).unwrap())
+                .await
+                .unwrap(),
+        )
+        .await;
+        assert!(body.contains("read-only-notice"));
+        assert!(!body.contains("pasteForm"));
+
+        send(set_mode(r#"{"read_only": false, "maintenance": true}"#))
+            .await
+            .unwrap();
+        for path in ["/?lang=en".to_string(), format!("/p/{}?lang=en", token)] {
+            let response = send(Request::get(path).body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+            assert_eq!(response.headers()[header::RETRY_AFTER], "300");
+            assert!(body_string(response).await.contains("Down for maintenance"));
+        }
+        let response = send(
+            Request::get("/assets/style.css")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = send(set_mode(r#"{"maintenance": false}"#)).await.unwrap();
+        assert_eq!(
+            body_string(response).await,
+            r#"{"read_only":false,"maintenance":false}"#
+        );
+        let response = send(create()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    }
+
+    #[tokio::test]
+    async fn test_undecodable_form_gets_translated_page() {
+        let app = build_router(test_state().await);
+        let send = |body: &'static str, content_type: &str, extra: Option<(&str, &str)>| {
+            let mut request = Request::post("/paste")
+                .header(header::CONTENT_TYPE, content_type)
+                .header("accept-language", "en");
+            if let Some((name, value)) = extra {
+                request = request.header(name, value);
+            }
+            let mut request = request.body(Body::from(body)).unwrap();
+            request
+                .extensions_mut()
+                .insert(ConnectInfo("127.0.0.1:4000".parse::<SocketAddr>().unwrap()));
+            app.clone().oneshot(request)
+        };
+        let form = "application/x-www-form-urlencoded";
+
+        for (body, content_type) in [
+            ("title=no+content", form),
+            ("content=hi&expires_in=soon", form),
+            ("content=hi", "text/plain"),
+        ] {
+            let response = send(body, content_type, None).await.unwrap();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", body);
+            let page = body_string(response).await;
+            assert!(page.contains("<title>400 - Mayfile</title>"), "{}", page);
+            assert!(page.contains("could not be understood"));
+        }
+
+        let response = send("title=x", form, Some(("accept", "application/json")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(body_string(response).await, r#"{"error":"bad_request"}"#);
+
+        let response = send("title=x", form, Some(("hx-request", "true")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert!(
+            body_string(response)
+                .await
+                .starts_with("The request could not")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_create_answers_each_client_type() {
+        let state = test_state().await;
+        let app = build_router(state.clone());
+        let send = |request: Request<Body>| {
+            let mut request = request;
+            request
+                .extensions_mut()
+                .insert(ConnectInfo("127.0.0.1:4000".parse::<SocketAddr>().unwrap()));
+            app.clone().oneshot(request)
+        };
+        let api_create = |body: &str| {
+            Request::post("/api/v1/paste")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
+
+        let response = send(api_create(r#"{"content": "hello", "title": "Hi"}"#))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let location = response.headers()[header::LOCATION]
+            .to_str()
+            .unwrap()
+            .to_string();
+        let body: serde_json::Value = serde_json::from_str(&body_string(response).await).unwrap();
+        let token = body["token"].as_str().unwrap();
+        assert_eq!(location, format!("/p/{}", token));
+        assert_eq!(body["url"], location);
+        assert!(body["manage_token"].as_str().is_some_and(|t| !t.is_empty()));
+        assert_eq!(
+            body["manage_url"],
+            format!("/m/{}", body["manage_token"].as_str().unwrap())
+        );
+        assert!(body["expires_at"].as_i64().unwrap() > now_ts());
+
+        update_config(&state, |config| {
+            config.server.base_url = Some("https://paste.example.com/".to_string());
+        });
+        let response = send(api_create(r#"{"content": "again"}"#)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let location = response.headers()[header::LOCATION].to_str().unwrap();
+        assert!(location.starts_with("https://paste.example.com/p/"));
+
+        let response = send(api_create(r#"{"title": "no content"}"#))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert!(body_string(response).await.contains("bad_request"));
+
+        let response = send(
+            Request::post("/paste")
+                .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+                .header("hx-request", "true")
+                .body(Body::from("content=hello"))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let pushed = response.headers()["hx-push-url"].to_str().unwrap();
+        assert!(pushed.starts_with("/p/"));
+        assert!(!response.headers().contains_key(header::LOCATION));
+
+        let response = send(
+            Request::post("/paste")
+                .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+                .body(Body::from("content=hello"))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        let location = response.headers()[header::LOCATION].to_str().unwrap();
+        assert!(location.starts_with("/p/"));
+    }
+
+    #[tokio::test]
+    async fn test_openapi_document_describes_create_and_explore() {
+        let app = build_router(test_state().await);
+        let response = app
+            .oneshot(
+                Request::get("/api/v1/openapi.json")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = body_string(response).await;
+        serde_json::from_str::<utoipa::openapi::OpenApi>(&body).unwrap();
+        let spec: serde_json::Value = serde_json::from_str(&body).unwrap();
+        let schema = |name: &str| format!("#/components/schemas/{}", name);
+        let json_schema = |content: &serde_json::Value| {
+            content["content"]["application/json"]["schema"]["$ref"].clone()
+        };
+
+        let create = &spec["paths"]["/api/v1/paste"]["post"];
+        assert_eq!(json_schema(&create["requestBody"]), schema("ApiPasteForm"));
+        let responses = &create["responses"];
+        assert_eq!(
+            json_schema(&responses["201"]),
+            schema("CreatedPasteResponse")
+        );
+        assert!(responses["201"]["headers"]["Location"].is_object());
+        for status in ["400", "413", "500", "503"] {
+            assert_eq!(
+                json_schema(&responses[status]),
+                schema("ApiError"),
+                "{}",
+                status
+            );
+        }
+
+        let explore = &spec["paths"]["/api/v1/explore"]["get"];
+        assert_eq!(
+            json_schema(&explore["responses"]["200"]),
+            schema("ExplorePaste")
+        );
+        assert_eq!(
+            json_schema(&explore["responses"]["404"]),
+            schema("ApiError")
+        );
+        let parameters: Vec<_> = explore["parameters"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|parameter| parameter["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            parameters,
+            [
+                "offset",
+                "full",
+                "expiring",
+                "lang",
+                "preview_chars",
+                "fields"
+            ]
+        );
+
+        let schemas = &spec["components"]["schemas"];
+        for (name, field) in [
+            ("ApiPasteForm", "content"),
+            ("ApiPasteForm", "expires_at"),
+            ("CreatedPasteResponse", "manage_token"),
+            ("ExplorePaste", "life_stage"),
+            ("ExplorePaste", "preview"),
+            ("ApiError", "error"),
+            ("ApiError", "message"),
+        ] {
+            assert!(
+                schemas[name]["properties"][field].is_object(),
+                "{}.{}",
+                name,
+                field
+            );
+        }
+        assert_eq!(
+            schemas["ApiPasteForm"]["required"],
+            serde_json::json!(["content"])
+        );
+    }
+
+    fn form_post(path: &str, body: &str) -> Request<Body> {
+        Request::post(path)
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    fn get(path: &str) -> Request<Body> {
+        Request::get(path).body(Body::empty()).unwrap()
+    }
+
+    fn location(response: &axum::response::Response) -> String {
+        response.headers()[header::LOCATION]
+            .to_str()
+            .unwrap()
+            .to_string()
+    }
+
+    #[tokio::test]
+    async fn test_form_create_then_view() {
+        let app = build_router(test_state().await);
+        let response = send(
+            &app,
+            form_post(
+                "/paste",
+                "title=Greeting&content=hello+from+the+form&language=rust",
+            ),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        let path = location(&response);
+        assert!(path.starts_with("/p/"));
+        let owner = response.headers()[header::SET_COOKIE].to_str().unwrap();
+        assert!(owner.starts_with("owner="));
+
+        let response = send(&app, get(&format!("{}?lang=en", path))).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = body_string(response).await;
+        assert!(body.contains("Greeting"));
+        assert!(body.contains("hello from the form"));
+        assert!(body.contains(r#"data-language="rust""#));
+    }
+
+    #[tokio::test]
+    async fn test_burn_after_reading_is_consumed() {
+        let app = build_router(test_state().await);
+        for prefix in ["/p/", "/r/"] {
+            let response = send(&app, form_post("/paste", "content=read+once&max_views=1")).await;
+            let token = location(&response).trim_start_matches("/p/").to_string();
+            // Without the owner cookie, like anyone the link is passed to.
+            let response = send(&app, get(&format!("{}{}", prefix, token))).await;
+            assert_eq!(response.status(), StatusCode::OK, "{}", prefix);
+            assert!(body_string(response).await.contains("read once"));
+            let response = send(&app, get(&format!("{}{}", prefix, token))).await;
+            assert_eq!(response.status(), StatusCode::NOT_FOUND, "{}", prefix);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_burning_paste_is_not_served_unless_its_view_is_counted() {
+        let state = test_state().await;
+        let app = build_router(state.clone());
+        let limited = PasteBuilder::new().max_views(1).insert(&state.pool).await;
+        let timed = PasteBuilder::new()
+            .destroy_after_first_view(60)
+            .insert(&state.pool)
+            .await;
+        let plain = PasteBuilder::new().insert(&state.pool).await;
+        // Stands in for a view that cannot be written, or a reader who got
+        // there first: the paste loads but its view is not counted.
+        sqlx::query(
+            "CREATE TRIGGER no_views BEFORE UPDATE OF views ON pastes BEGIN SELECT RAISE(ABORT, 'busy'); END",
+        )
+        .execute(&state.pool)
+        .await
+        .unwrap();
+        for prefix in ["/p/", "/r/"] {
+            for paste in [&limited, &timed] {
+                let response = send(&app, get(&format!("{}{}", prefix, paste.token))).await;
+                assert_eq!(response.status(), StatusCode::NOT_FOUND, "{}", prefix);
+            }
+            let response = send(&app, get(&format!("{}{}", prefix, plain.token))).await;
+            assert_eq!(response.status(), StatusCode::OK, "{}", prefix);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_view_receipt_is_single_use_short_lived_and_per_client() {
+        let clock = MockClock::new();
+        let state = AppState {
+            clock: clock.clone(),
+            ..test_state().await
+        };
+        update_config(&state, |config| config.paste.raw_counts_as_view = false);
+        let app = build_router(state.clone());
+        let created = PasteBuilder::new().max_views(10).insert(&state.pool).await;
+        let token = created.token;
+        let fetch = async |path: &str, peer: &str, cookie: Option<&str>| {
+            let mut request = get(path);
+            if let Some(cookie) = cookie {
+                request.headers_mut().insert(
+                    header::COOKIE,
+                    header::HeaderValue::from_str(cookie).unwrap(),
+                );
+            }
+            let peer: SocketAddr = peer.parse().unwrap();
+            request.extensions_mut().insert(ConnectInfo(peer));
+            let response = send(&app, request).await;
+            assert_eq!(response.status(), StatusCode::OK, "{}", path);
+            response
+                .headers()
+                .get_all(header::SET_COOKIE)
+                .iter()
+                .map(|cookie| cookie.to_str().unwrap().to_string())
+                .find(|cookie| cookie.starts_with("viewed="))
+        };
+        let views = async || -> i64 {
+            sqlx::query_scalar("SELECT views FROM pastes WHERE token = ?")
+                .bind(&token)
+                .fetch_one(&state.pool)
+                .await
+                .unwrap()
+        };
+        let page = format!("/p/{}", token);
+        let raw = format!("/r/{}", token);
+        let receipt = fetch(&page, "10.0.0.1:1", None).await.unwrap();
+        assert!(receipt.contains("Max-Age=60;"));
+        let receipt = receipt.split(';').next().unwrap().to_string();
+        assert_eq!(views().await, 1);
+
+        // Passed to another client, the receipt is worth nothing.
+        fetch(&raw, "10.0.0.2:1", Some(&receipt)).await;
+        assert_eq!(views().await, 2);
+        // Its own client rides on it once, and is told to drop it.
+        let cleared = fetch(&raw, "10.0.0.1:1", Some(&receipt)).await.unwrap();
+        assert!(cleared.starts_with("viewed=;"));
+        assert_eq!(views().await, 2);
+        fetch(&raw, "10.0.0.1:1", Some(&receipt)).await;
+        assert_eq!(views().await, 3);
+
+        // Nor does a fresh one last.
+        let receipt = fetch(&page, "10.0.0.1:1", None).await.unwrap();
+        let receipt = receipt.split(';').next().unwrap().to_string();
+        clock.advance(60);
+        fetch(&raw, "10.0.0.1:1", Some(&receipt)).await;
+        assert_eq!(views().await, 5);
+    }
+
+    #[tokio::test]
+    async fn test_missing_paste_is_not_found() {
+        let app = build_router(test_state().await);
+        let response = send(&app, get("/p/zzzz?lang=en")).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let content_type = response.headers()[header::CONTENT_TYPE].to_str().unwrap();
+        assert!(content_type.starts_with("text/html"));
+        assert!(body_string(response).await.contains("<html"));
+
+        let response = send(&app, get("/r/zzzz?lang=en")).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "text/plain; charset=utf-8"
+        );
+
+        // Not even a well-formed token.
+        let response = send(&app, get("/p/..")).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_language_choice_is_remembered_in_a_cookie() {
+        let app = build_router(test_state().await);
+        let response = send(&app, get("/?lang=en")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let cookie = response.headers()[header::SET_COOKIE].to_str().unwrap();
+        assert!(cookie.starts_with("lang=en;"));
+        assert!(body_string(response).await.contains(r#"<html lang="en""#));
+
+        // The cookie then wins over Accept-Language and is not set again.
+        let response = send(
+            &app,
+            Request::get("/")
+                .header(header::COOKIE, "lang=en")
+                .header(header::ACCEPT_LANGUAGE, "zh-CN")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert!(!response.headers().contains_key(header::SET_COOKIE));
+        assert!(body_string(response).await.contains(r#"<html lang="en""#));
+
+        let response = send(
+            &app,
+            Request::get("/")
+                .header(header::ACCEPT_LANGUAGE, "zh-CN")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert!(!response.headers().contains_key(header::SET_COOKIE));
+        assert!(body_string(response).await.contains(r#"<html lang="zh"#));
+    }
+
+    #[tokio::test]
+    async fn test_raw_endpoint_headers() {
+        let app = build_router(test_state().await);
+        let content = "fn main() {}\n";
+        let response = send(
+            &app,
+            form_post("/paste", "content=fn+main%28%29+%7B%7D%0A&language=rust"),
+        )
+        .await;
+        let token = location(&response).trim_start_matches("/p/").to_string();
+
+        let response = send(&app, get(&format!("/r/{}", token))).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let headers = response.headers();
+        assert_eq!(headers[header::CONTENT_TYPE], "text/plain; charset=utf-8");
+        assert_eq!(
+            headers[header::CONTENT_DISPOSITION],
+            format!("inline; filename=\"paste-{}.rs\"", token).as_str()
+        );
+        assert_eq!(
+            headers["x-content-sha256"],
+            crate::utils::sha256_hex(content).as_str()
+        );
+        assert!(!headers.contains_key("x-mayfile-e2e"));
+        assert_eq!(body_string(response).await, content);
+
+        let response = send(&app, get(&format!("/r/{}?crlf=1", token))).await;
+        assert_eq!(body_string(response).await, "fn main() {}\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_public_pastes_link_their_neighbors() {
+        let state = test_state().await;
+        for (token, created_at) in [("older", 100), ("middle", 200), ("newer", 300)] {
+            PasteBuilder::new()
+                .token(token)
+                .title(token)
+                .public(true)
+                .created_at(created_at)
+                .insert(&state.pool)
+                .await;
+        }
+        PasteBuilder::new()
+            .token("private")
+            .created_at(150)
+            .insert(&state.pool)
+            .await;
+        let app = build_router(state);
+
+        let response = send(&app, get("/api/v1/p/older/neighbors")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_str(&body_string(response).await).unwrap();
+        assert!(body["prev"].is_null());
+        assert!(body["prev_url"].is_null());
+        assert_eq!(body["next"]["token"], "middle");
+        assert_eq!(body["next"]["title"], "middle");
+        assert_eq!(body["next_url"], "/p/middle");
+
+        let response = send(&app, get("/api/v1/p/private/neighbors")).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert!(body_string(response).await.contains("not_found"));
+
+        let page = body_string(send(&app, get("/p/middle?lang=en")).await).await;
+        assert!(page.contains(r#"href="/p/older" rel="prev""#));
+        assert!(page.contains(r#"href="/p/newer" rel="next""#));
+        let page = body_string(send(&app, get("/p/private?lang=en")).await).await;
+        assert!(!page.contains(r#"rel="prev""#));
+        assert!(!page.contains(r#"rel="next""#));
+    }
+
+    #[tokio::test]
+    async fn test_diff_between_a_paste_and_its_fork() {
+        let state = test_state().await;
+        PasteBuilder::new()
+            .token("orig")
+            .title("v1")
+            .content("a\nb\nc\n")
+            .public(true)
+            .insert(&state.pool)
+            .await;
+        let app = build_router(state.clone());
+        let create = |body: &str, cookie: Option<&str>| {
+            let mut request = form_post("/paste", body);
+            request.headers_mut().insert(
+                header::ACCEPT,
+                header::HeaderValue::from_static("application/json"),
+            );
+            if let Some(cookie) = cookie {
+                request
+                    .headers_mut()
+                    .insert(header::COOKIE, cookie.parse().unwrap());
+            }
+            request
+        };
+        let with_cookie = |path: &str, cookie: &str| {
+            Request::get(path)
+                .header(header::COOKIE, cookie)
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let response = send(&app, create("content=a%0AB%0Ac%0A&forked_from=orig", None)).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let owner = response.headers()[header::SET_COOKIE]
+            .to_str()
+            .unwrap()
+            .split(';')
+            .next()
+            .unwrap()
+            .to_string();
+        let body: serde_json::Value = serde_json::from_str(&body_string(response).await).unwrap();
+        let fork = body["token"].as_str().unwrap().to_string();
+        let diff = format!("/diff/orig/{}", fork);
+
+        let page = body_string(send(&app, get(&format!("/p/{}?lang=en", fork))).await).await;
+        assert!(page.contains(&format!(r#"href="{}""#, diff)));
+
+        // The fork is private: only its creator may diff it.
+        let response = send(&app, get(&diff)).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let response = send(&app, with_cookie(&diff, &owner)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let page = body_string(response).await;
+        assert!(page.contains(r#"<span class="diff-line removed">-b</span>"#));
+        assert!(page.contains(r#"<span class="diff-line added">+B</span>"#));
+
+        let response = send(&app, with_cookie(&format!("{}.patch", diff), &owner)).await;
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "text/x-diff; charset=utf-8"
+        );
+        assert_eq!(
+            body_string(response).await,
+            format!(
+                "--- a/orig\n+++ b/{}\n@@ -1,3 +1,3 @@\n a\n-b\n+B\n c\n",
+                fork
+            )
+        );
+
+        // Diffing a burn-on-read paste spends none of its views.
+        let response = send(&app, create("content=x&max_views=1", Some(&owner))).await;
+        let body: serde_json::Value = serde_json::from_str(&body_string(response).await).unwrap();
+        let burn = body["token"].as_str().unwrap().to_string();
+        let response = send(&app, with_cookie(&format!("/diff/orig/{}", burn), &owner)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let paste = db::fetch_paste(&state.pool, &burn, None, now_ts())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(paste.views, 0);
+
+        update_config(&state, |config| config.paste.max_diff_chars = 10);
+        let response = send(&app, with_cookie(&diff, &owner)).await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        db::delete_paste(&state.pool, "orig", now_ts())
+            .await
+            .unwrap();
+        let page = body_string(send(&app, get(&format!("/p/{}?lang=en", fork))).await).await;
+        assert!(!page.contains("/diff/"));
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_agrees_with_creating() {
+        let state = test_state().await;
+        update_config(&state, |config| {
+            config.paste.max_content_length = 30;
+            config.paste.max_total_content_length = 20;
+        });
+        PasteBuilder::new()
+            .token("taken-one")
+            .insert(&state.pool)
+            .await;
+        let app = build_router(state.clone());
+        let post = |path: &str, body: &serde_json::Value| {
+            Request::post(path)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
+        let validate = async |body: &serde_json::Value| -> serde_json::Value {
+            let response = send(&app, post("/api/v1/paste/validate", body)).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            serde_json::from_str(&body_string(response).await).unwrap()
+        };
+        let far = now_ts() + 10 * 365 * 86400;
+
+        for (body, error) in [
+            (serde_json::json!({"content": "hello"}), None),
+            (
+                serde_json::json!({"content": "hola", "language": "klingon"}),
+                None,
+            ),
+            (
+                serde_json::json!({"content": "x".repeat(25)}),
+                Some("content_too_long"),
+            ),
+            (
+                serde_json::json!({"content": "x".repeat(31), "expires_at": "never"}),
+                Some("content_too_long"),
+            ),
+            (
+                serde_json::json!({"content": "hi", "expires_at": "never"}),
+                Some("expires_at_invalid"),
+            ),
+            (
+                serde_json::json!({"content": "hi", "slug": "api"}),
+                Some("slug_reserved"),
+            ),
+            (
+                serde_json::json!({"content": "hi", "slug": "taken-one"}),
+                Some("slug_taken"),
+            ),
+            (
+                serde_json::json!({"content": "hi", "visible_after": far}),
+                Some("visible_after_invalid"),
+            ),
+        ] {
+            let report = validate(&body).await;
+            let first_error = report["checks"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|check| check["ok"] == false)
+                .map(|check| check["error"].as_str().unwrap().to_string());
+            assert_eq!(first_error.as_deref(), error, "{}", body);
+            assert_eq!(report["valid"], error.is_none());
+
+            let response = send(&app, post("/api/v1/paste", &body)).await;
+            match error {
+                None => assert_eq!(response.status(), StatusCode::CREATED, "{}", body),
+                Some(error) => {
+                    let created: serde_json::Value =
+                        serde_json::from_str(&body_string(response).await).unwrap();
+                    assert_eq!(created["error"], error, "{}", body);
+                }
+            }
+        }
+
+        // A large paste can be checked by its length alone, and checking
+        // stores nothing.
+        let report = validate(&serde_json::json!({"content_length": 25})).await;
+        assert_eq!(report["valid"], false);
+        assert_eq!(report["checks"][5]["check"], "capacity");
+        assert_eq!(report["checks"][5]["ok"], false);
+        let count = async || -> i64 {
+            sqlx::query_scalar("SELECT COUNT(*) FROM pastes")
+                .fetch_one(&state.pool)
+                .await
+                .unwrap()
+        };
+        let before = count().await;
+        let report = validate(&serde_json::json!({
+            "content_length": 5,
+            "slug": "fresh-one",
+            "language": "klingon",
+            "max_views": 3,
+            "is_public": true,
+        }))
+        .await;
+        assert_eq!(report["valid"], true);
+        assert_eq!(report["slug"], "fresh-one");
+        assert_eq!(report["language"], "auto");
+        assert_eq!(report["is_public"], false);
+        assert_eq!(count().await, before);
+    }
+
+    #[tokio::test]
+    async fn test_custom_slugs_and_their_availability() {
+        let state = test_state().await;
+        PasteBuilder::new()
+            .token("private-notes")
+            .insert(&state.pool)
+            .await;
+        let app = build_router(state);
+        let check = async |slug: &str| -> serde_json::Value {
+            let response = send(&app, get(&format!("/api/v1/slug-available?slug={}", slug))).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            serde_json::from_str(&body_string(response).await).unwrap()
+        };
+
+        assert_eq!(
+            check("meeting-notes").await,
+            serde_json::json!({"available": true, "reason": null})
+        );
+        // A private paste holding the slug shows up only as "taken".
+        assert_eq!(
+            check("private-notes").await,
+            serde_json::json!({"available": false, "reason": "taken"})
+        );
+        for (slug, reason) in [
+            ("api", "reserved"),
+            ("Meeting_Notes", "invalid_charset"),
+            ("ab", "length"),
+            ("", "length"),
+        ] {
+            assert_eq!(check(slug).await["reason"], reason, "{}", slug);
+        }
+
+        let mut request = get("/api/v1/slug-available?slug=private-notes&lang=en");
+        request
+            .headers_mut()
+            .insert("hx-request", header::HeaderValue::from_static("true"));
+        let body = body_string(send(&app, request).await).await;
+        assert!(body.contains("slug-status"));
+        assert!(body.contains("Already taken"));
+
+        let create = |body: &str| {
+            let mut request = form_post("/paste", body);
+            request.headers_mut().insert(
+                header::ACCEPT,
+                header::HeaderValue::from_static("application/json"),
+            );
+            request
+        };
+        let response = send(&app, create("content=agenda&slug=meeting-notes")).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(location(&response), "/p/meeting-notes");
+        let page = body_string(send(&app, get("/p/meeting-notes")).await).await;
+        assert!(page.contains("agenda"));
+        assert_eq!(check("meeting-notes").await["reason"], "taken");
+
+        let response = send(&app, create("content=again&slug=meeting-notes")).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        assert!(body_string(response).await.contains("slug_taken"));
+        let response = send(&app, create("content=again&slug=admin")).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert!(body_string(response).await.contains("slug_reserved"));
+    }
+
+    #[tokio::test]
+    async fn test_reactions_toggle_per_client() {
+        let state = test_state().await;
+        PasteBuilder::new()
+            .token("listed")
+            .public(true)
+            .insert(&state.pool)
+            .await;
+        PasteBuilder::new()
+            .token("private")
+            .insert(&state.pool)
+            .await;
+        let app = build_router(state);
+        let heart = "emoji=%E2%9D%A4%EF%B8%8F";
+        let fire = "emoji=%F0%9F%94%A5";
+        let react = |token: &str, body: &str, cookie: Option<&str>| {
+            let mut request = form_post(&format!("/p/{}/react", token), body);
+            request.headers_mut().insert(
+                header::ACCEPT,
+                header::HeaderValue::from_static("application/json"),
+            );
+            if let Some(cookie) = cookie {
+                request
+                    .headers_mut()
+                    .insert(header::COOKIE, cookie.parse().unwrap());
+            }
+            request
+        };
+        let counts = |body: &str| -> Vec<i64> {
+            let body: serde_json::Value = serde_json::from_str(body).unwrap();
+            body["reactions"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|reaction| reaction["count"].as_i64().unwrap())
+                .collect()
+        };
+        let cookie = |response: &axum::response::Response| -> String {
+            let value = response.headers()[header::SET_COOKIE].to_str().unwrap();
+            assert!(value.contains("Path=/p/listed"));
+            value.split(';').next().unwrap().to_string()
+        };
+
+        let response = send(&app, react("listed", heart, None)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let first = cookie(&response);
+        assert_eq!(counts(&body_string(response).await), [1, 0, 0]);
+
+        let response = send(&app, react("listed", fire, Some(&first))).await;
+        let second = cookie(&response);
+        assert_eq!(counts(&body_string(response).await), [1, 1, 0]);
+
+        // The same client sending the heart again takes it back.
+        let response = send(&app, react("listed", heart, Some(&second))).await;
+        let body = body_string(response).await;
+        assert_eq!(counts(&body), [0, 1, 0]);
+        assert!(body.contains(r#""reacted":false"#));
+
+        // A cookie that was not signed for this paste counts as none.
+        let response = send(&app, react("listed", fire, Some("reacted=2.forged"))).await;
+        assert_eq!(counts(&body_string(response).await), [0, 2, 0]);
+
+        let page = body_string(send(&app, get("/p/listed?lang=en")).await).await;
+        assert!(page.contains(r#"action="/p/listed/react""#));
+        let page = body_string(send(&app, get("/explore?lang=en")).await).await;
+        assert!(page.contains(r#"action="/p/listed/react""#));
+
+        let response = send(&app, react("private", heart, None)).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let page = body_string(send(&app, get("/p/private?lang=en")).await).await;
+        assert!(!page.contains("/react"));
+
+        let response = send(&app, react("listed", "emoji=%F0%9F%92%A9", None)).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = send(&app, form_post("/p/listed/react", fire)).await;
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert_eq!(location(&response), "/p/listed");
+    }
+
+    #[tokio::test]
+    async fn test_index_features_a_public_paste() {
+        let state = admin_state().await;
+        let app = build_router(state.clone());
+        let page = body_string(send(&app, get("/?lang=en")).await).await;
+        assert!(!page.contains("featured-paste"));
+
+        for (token, title) in [("first", "Daily pick"), ("second", "Chosen one")] {
+            PasteBuilder::new()
+                .token(token)
+                .title(title)
+                .public(true)
+                .insert(&state.pool)
+                .await;
+        }
+        state.featured.invalidate();
+        let page = body_string(send(&app, get("/?lang=en")).await).await;
+        assert!(page.contains("Paste of the day"));
+        assert!(page.contains(r#"href="/p/first""#) || page.contains(r#"href="/p/second""#));
+
+        let admin = |method: &str, path: &str| {
+            Request::builder()
+                .method(method)
+                .uri(path)
+                .header(header::AUTHORIZATION, "Bearer sesame")
+                .body(Body::empty())
+                .unwrap()
+        };
+        let response = send(&app, admin("POST", "/admin/api/p/missing/feature")).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let response = send(&app, admin("POST", "/admin/api/p/second/feature")).await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let page = body_string(send(&app, get("/?lang=en")).await).await;
+        assert!(page.contains(r#"href="/p/second" class="featured-link""#));
+        assert!(page.contains("Chosen one"));
+
+        let response = send(&app, admin("DELETE", "/admin/api/featured")).await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let response = send(
+            &app,
+            Request::delete("/admin/api/featured")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_index_lists_latest_public_pastes() {
+        let state = test_state().await;
+        let now = now_ts();
+        for (token, title, age) in [("old", "Oldest", 600), ("new", "Newest", 60)] {
+            PasteBuilder::new()
+                .token(token)
+                .title(title)
+                .language("rust")
+                .public(true)
+                .created_at(now - age)
+                .insert(&state.pool)
+                .await;
+        }
+        PasteBuilder::new()
+            .title("Secret")
+            .insert(&state.pool)
+            .await;
+        update_config(&state, |config| config.paste.recent_on_index_count = 1);
+        let app = build_router(state.clone());
+
+        let page = body_string(send(&app, get("/?lang=en")).await).await;
+        assert!(page.contains(r#"hx-get="/api/v1/recent?lang=en""#));
+        assert!(page.contains(r#"<a href="/p/new" class="recent-title">Newest</a>"#));
+        assert!(!page.contains(r#"<a href="/p/old" class="recent-title">"#));
+
+        update_config(&state, |config| config.paste.recent_on_index_count = 5);
+        let response = send(&app, get("/api/v1/recent?lang=en")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let fragment = body_string(response).await;
+        assert!(fragment.find("Newest").unwrap() < fragment.find("Oldest").unwrap());
+        assert!(fragment.contains("Rust"));
+        assert!(fragment.contains("1 minute ago"));
+        assert!(!fragment.contains("Secret"));
+        assert!(!fragment.contains("<html"));
+
+        update_config(&state, |config| config.paste.show_recent_on_index = false);
+        let page = body_string(send(&app, get("/?lang=en")).await).await;
+        assert!(!page.contains("recent-pastes"));
+        let response = send(&app, get("/api/v1/recent")).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_archive_groups_a_month_by_day() {
+        let state = test_state().await;
+        update_config(&state, |config| {
+            config.server.display_timezone = "+08:00".to_string()
+        });
+        // 2024-06-02T07:00, 2024-06-01T18:00 and 2024-05-31T10:00 at +08:00.
+        for (token, created_at) in [("c", 1717282800), ("b", 1717236000), ("a", 1717120800)] {
+            PasteBuilder::new()
+                .token(token)
+                .title(&format!("Paste {}", token))
+                .public(true)
+                .created_at(created_at)
+                .insert(&state.pool)
+                .await;
+        }
+        PasteBuilder::new()
+            .title("Private")
+            .created_at(1717282800)
+            .insert(&state.pool)
+            .await;
+        let app = build_router(state);
+
+        let response = send(&app, get("/api/v1/archive?month=2024-06&lang=en")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_str(&body_string(response).await).unwrap();
+        assert_eq!(body["month"], "2024-06");
+        assert_eq!(body["total"], 2);
+        assert_eq!(body["days"][0]["date"], "2024-06-02");
+        assert_eq!(body["days"][0]["pastes"][0]["token"], "c");
+        assert_eq!(body["days"][0]["pastes"][0]["language_label"], "Plain Text");
+        assert_eq!(body["days"][1]["date"], "2024-06-01");
+        assert_eq!(body["days"][1]["count"], 1);
+        assert_eq!(body["earlier"], "2024-05");
+        assert_eq!(body["later"], "2024-07");
+
+        let page = body_string(send(&app, get("/archive?month=2024-06&lang=en")).await).await;
+        assert!(page.contains("2 pastes"));
+        assert!(page.contains(r#"<time datetime="2024-06-02">"#));
+        assert!(page.contains("Paste b"));
+        assert!(!page.contains("Paste a"));
+        assert!(!page.contains("Private"));
+        assert!(page.contains("18:00"));
+        assert!(page.contains(r#"href="/archive?month=2024-05""#));
+
+        let response = send(&app, get("/api/v1/archive?month=2024-05")).await;
+        let body: serde_json::Value = serde_json::from_str(&body_string(response).await).unwrap();
+        assert_eq!(body["total"], 1);
+        assert!(body["earlier"].is_null());
+
+        let page = body_string(send(&app, get("/archive?month=2023-01&lang=en")).await).await;
+        assert!(page.contains("Nothing public from this month is still alive."));
+
+        let response = send(&app, get("/api/v1/archive?month=2024-13")).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response = send(&app, get("/archive?month=june")).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response = send(&app, get("/archive")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_author_signs_public_pastes() {
+        let state = test_state().await;
+        let app = build_router(state.clone());
+        let response = send(
+            &app,
+            form_post(
+                "/paste",
+                "content=hi&is_public=on&author=%3Cb%3E%E8%9C%89%3C%2Fb%3E",
+            ),
+        )
+        .await;
+        let cookies: Vec<_> = response
+            .headers()
+            .get_all(header::SET_COOKIE)
+            .iter()
+            .collect();
+        assert!(cookies.iter().any(|cookie| {
+            cookie
+                .to_str()
+                .unwrap()
+                .starts_with("author=%3Cb%3E%E8%9C%89%3C%2Fb%3E;")
+        }));
+        let token = location(&response).trim_start_matches("/p/").to_string();
+
+        let page = body_string(send(&app, get(&format!("/p/{}?lang=en", token))).await).await;
+        assert!(page.contains("by ~&#60;b&#62;蜉&#60;/b&#62;"));
+        assert!(!page.contains("<b>蜉"));
+        let meta = body_string(send(&app, get(&format!("/api/v1/p/{}/meta", token))).await).await;
+        assert!(meta.contains(r#""author":"<b>蜉</b>""#));
+        let explore = body_string(send(&app, get("/api/v1/explore")).await).await;
+        assert!(explore.contains(r#""author":"<b>蜉</b>""#));
+
+        let private = PasteBuilder::new().insert(&state.pool).await.token;
+        sqlx::query("UPDATE pastes SET author = 'someone' WHERE token = ?")
+            .bind(&private)
+            .execute(&state.pool)
+            .await
+            .unwrap();
+        let meta = body_string(send(&app, get(&format!("/api/v1/p/{}/meta", private))).await).await;
+        assert!(!meta.contains("author"));
+
+        let index = Request::get("/?lang=en")
+            .header(header::COOKIE, "author=%E8%9C%89")
+            .body(Body::empty())
+            .unwrap();
+        let page = body_string(send(&app, index).await).await;
+        assert!(page.contains(r#"name="author""#));
+        assert!(page.contains(r#"value="蜉""#));
+
+        update_config(&state, |config| config.paste.allow_author = false);
+        let page = body_string(send(&app, get(&format!("/p/{}?lang=en", token))).await).await;
+        assert!(!page.contains("byline"));
+        let page = body_string(send(&app, get("/?lang=en")).await).await;
+        assert!(!page.contains(r#"name="author""#));
+        let response = send(&app, form_post("/paste", "content=hi&author=anon")).await;
+        assert!(
+            !response
+                .headers()
+                .get_all(header::SET_COOKIE)
+                .iter()
+                .any(|cookie| cookie.to_str().unwrap().starts_with("author="))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_multi_file_pastes() {
+        let state = test_state().await;
+        let app = build_router(state.clone());
+        let create = |body: &str| {
+            let mut request = form_post("/paste", body);
+            request.headers_mut().insert(
+                header::ACCEPT,
+                header::HeaderValue::from_static("application/json"),
+            );
+            request
+        };
+
+        // Repeated fields add files; an empty row is dropped.
+        let response = send(
+            &app,
+            create(
+                "content=fn+main()+{}&filename=main.rs\
+                 &file_name=notes.py&file_content=%23+Notes\
+                 &file_name=&file_content=",
+            ),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let body: serde_json::Value = serde_json::from_str(&body_string(response).await).unwrap();
+        let token = body["token"].as_str().unwrap().to_string();
+        let paste = db::fetch_paste(&state.pool, &token, None, now_ts())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(paste.filename.as_deref(), Some("main.rs"));
+        assert_eq!(paste.files.len(), 1);
+        assert_eq!(paste.files[0].filename, "notes.py");
+        assert_eq!(paste.files[0].language, "python");
+
+        let page = body_string(send(&app, get(&format!("/p/{}?lang=en", token))).await).await;
+        assert!(page.contains(&format!(r#"href="/r/{}/notes.py""#, token)));
+        assert!(page.contains("# Notes"));
+
+        let response = send(&app, get(&format!("/r/{}/notes.py", token))).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CONTENT_DISPOSITION],
+            "inline; filename*=UTF-8''notes%2Epy"
+        );
+        assert_eq!(body_string(response).await, "# Notes");
+        let response = send(&app, get(&format!("/r/{}/main.rs", token))).await;
+        assert_eq!(body_string(response).await, "fn main() {}");
+        let response = send(&app, get(&format!("/r/{}/missing.txt?lang=en", token))).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        // The JSON API takes a `files` array.
+        let response = send(
+            &app,
+            Request::post("/api/v1/paste")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(
+                    r#"{"content": "a", "files": [{"filename": "b.txt", "content": "b"}]}"#,
+                ))
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let body: serde_json::Value = serde_json::from_str(&body_string(response).await).unwrap();
+        let token = body["token"].as_str().unwrap();
+        let response = send(&app, get(&format!("/r/{}/b.txt", token))).await;
+        assert_eq!(body_string(response).await, "b");
+        let response = send(&app, get(&format!("/r/{}/file-1", token))).await;
+        assert_eq!(body_string(response).await, "a");
+
+        // Size limits apply to all the files together.
+        update_config(&state, |config| config.paste.max_content_length = 10);
+        let response = send(
+            &app,
+            create("content=123456&file_name=a&file_content=123456&lang=en"),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = serde_json::from_str(&body_string(response).await).unwrap();
+        assert_eq!(body["error"], "content_too_long");
+
+        let response = send(
+            &app,
+            create("content=1&filename=a&file_name=a&file_content=2"),
+        )
+        .await;
+        let body: serde_json::Value = serde_json::from_str(&body_string(response).await).unwrap();
+        assert_eq!(body["error"], "filename_duplicate");
+
+        // Files go with their paste.
+        let paste_id: i64 = sqlx::query_scalar("SELECT id FROM pastes WHERE token = ?")
+            .bind(token)
+            .fetch_one(&state.pool)
+            .await
+            .unwrap();
+        db::delete_paste(&state.pool, token, now_ts())
+            .await
+            .unwrap();
+        let files: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM paste_files WHERE paste_id = ?")
+            .bind(paste_id)
+            .fetch_one(&state.pool)
+            .await
+            .unwrap();
+        assert_eq!(files, 0);
+    }
+
+    #[tokio::test]
+    async fn test_related_links() {
+        let state = test_state().await;
+        update_config(&state, |config| config.paste.max_related_links = 2);
+        for (token, title) in [("abcde", "Alpha"), ("fghij", "Beta"), ("klmno", "Gamma")] {
+            PasteBuilder::new()
+                .token(token)
+                .title(title)
+                .insert(&state.pool)
+                .await;
+        }
+        PasteBuilder::new()
+            .token("burnt")
+            .max_views(1)
+            .insert(&state.pool)
+            .await;
+        let app = build_router(state.clone());
+        let mut request = form_post("/paste", "content=source");
+        request.headers_mut().insert(
+            header::ACCEPT,
+            header::HeaderValue::from_static("application/json"),
+        );
+        let response = send(&app, request).await;
+        let owner = response.headers()[header::SET_COOKIE]
+            .to_str()
+            .unwrap()
+            .split(';')
+            .next()
+            .unwrap()
+            .to_string();
+        let body: serde_json::Value = serde_json::from_str(&body_string(response).await).unwrap();
+        let token = body["token"].as_str().unwrap().to_string();
+        let manage_token = body["manage_token"].as_str().unwrap().to_string();
+        let link = |token: &str, body: serde_json::Value, cookie: Option<&str>| {
+            let mut request = Request::post(format!("/p/{}/related", token))
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap();
+            if let Some(cookie) = cookie {
+                request
+                    .headers_mut()
+                    .insert(header::COOKIE, cookie.parse().unwrap());
+            }
+            request
+        };
+        let error = async |response: axum::response::Response| {
+            let body: serde_json::Value =
+                serde_json::from_str(&body_string(response).await).unwrap();
+            body["error"].clone()
+        };
+
+        // Only the creator may link: by cookie or manage token.
+        let tokens = serde_json::json!({"tokens": ["abcde", "fghij"]});
+        let response = send(&app, link(&token, tokens.clone(), None)).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = send(&app, link(&token, tokens.clone(), Some("owner=someone"))).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = send(&app, link(&token, tokens, Some(&owner))).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let by_manager =
+            serde_json::json!({"tokens": ["fghij", "abcde"], "manage_token": manage_token});
+        let response = send(&app, link(&token, by_manager, None)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_str(&body_string(response).await).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!([
+                {"token": "fghij", "title": "Beta"},
+                {"token": "abcde", "title": "Alpha"},
+            ])
+        );
+
+        // The cap, missing targets and burn-on-read pastes.
+        let three = serde_json::json!({"tokens": ["abcde", "fghij", "klmno"]});
+        let response = send(&app, link(&token, three, Some(&owner))).await;
+        assert_eq!(error(response).await, "too_many_related");
+        let missing = serde_json::json!({"tokens": ["zzzzz"]});
+        let response = send(&app, link(&token, missing, Some(&owner))).await;
+        assert_eq!(error(response).await, "related_not_found");
+        let burnt = serde_json::json!({"tokens": ["burnt"]});
+        let response = send(&app, link(&token, burnt, Some(&owner))).await;
+        assert_eq!(error(response).await, "burn_on_read");
+
+        // A target gone since shows as a tombstone, on the page and in
+        // the metadata.
+        sqlx::query("UPDATE pastes SET expires_at = 0 WHERE token = 'abcde'")
+            .execute(&state.pool)
+            .await
+            .unwrap();
+        let page = body_string(send(&app, get(&format!("/p/{}?lang=en", token))).await).await;
+        assert!(page.contains(r#"<a href="/p/fghij">Beta</a>"#));
+        assert!(page.contains(r#"<li class="tombstone">abcde · expired</li>"#));
+        let response = send(&app, get(&format!("/api/v1/p/{}/meta", token))).await;
+        let meta: serde_json::Value = serde_json::from_str(&body_string(response).await).unwrap();
+        assert_eq!(
+            meta["related"][1],
+            serde_json::json!({"token": "abcde", "title": null})
+        );
+    }
+
+    #[tokio::test]
+    async fn test_result_fragment_qr() {
+        let state = test_state().await;
+        let app = build_router(state.clone());
+        let create = || {
+            let mut request = form_post("/paste", "content=hello");
+            let headers = request.headers_mut();
+            headers.insert("hx-request", header::HeaderValue::from_static("true"));
+            headers.insert(
+                header::HOST,
+                header::HeaderValue::from_static("paste.example"),
+            );
+            request
+        };
+
+        let fragment = body_string(send(&app, create()).await).await;
+        assert!(!fragment.contains("result-qr"));
+
+        update_config(&state, |config| config.paste.show_qr = true);
+        let fragment = body_string(send(&app, create()).await).await;
+        let start = fragment.find(r#"<div class="result-qr""#).unwrap();
+        let qr = &fragment[start..];
+        let svg = &qr[qr.find('>').unwrap() + 1..qr.find("</svg>").unwrap() + "</svg>".len()];
+        assert!(
+            svg.starts_with(r#"<svg xmlns="http://www.w3.org/2000/svg""#),
+            "{}",
+            svg
+        );
+        // Emitted as markup, not escaped text.
+        assert!(!qr.contains("&lt;svg"));
+        assert!(svg.contains("<path"));
+    }
+
+    #[tokio::test]
+    async fn test_print_view() {
+        let state = test_state().await;
+        let token = PasteBuilder::new()
+            .title("Review")
+            .content("fn main() {\n    <b>\n}")
+            .max_views(2)
+            .insert(&state.pool)
+            .await
+            .token;
+        let app = build_router(state.clone());
+        let response = send(&app, form_post("/paste", "content=c2VhbGVk&is_e2e=on")).await;
+        let sealed = location(&response).trim_start_matches("/p/").to_string();
+        let views = async || {
+            db::fetch_paste(&state.pool, &token, None, now_ts())
+                .await
+                .unwrap()
+                .map(|paste| paste.views)
+        };
+
+        let response = send(&app, get(&format!("/p/{}/print?lang=en", token))).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let page = body_string(response).await;
+        assert!(page.contains("<h1>Review</h1>"));
+        assert!(page.contains(
+            r#"<span class="line"><span class="line-number">2</span>    &#60;b&#62;</span>"#
+        ));
+        assert!(!page.contains("<script"));
+        assert!(page.contains("print.css"));
+        // It spends a view like the detail page.
+        assert_eq!(views().await, Some(1));
+
+        let detail = body_string(send(&app, get(&format!("/p/{}?lang=en", token))).await).await;
+        assert!(detail.contains(&format!(r#"href="/p/{}/print""#, token)));
+        assert_eq!(views().await, None);
+
+        let response = send(&app, get(&format!("/p/{}/print", sealed))).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_reader_mode() {
+        let state = test_state().await;
+        let prose = PasteBuilder::new()
+            .content("第一段。\n\n第二段。")
+            .language("plaintext")
+            .insert(&state.pool)
+            .await
+            .token;
+        let code = PasteBuilder::new()
+            .content("fn main() {}")
+            .language("rust")
+            .insert(&state.pool)
+            .await
+            .token;
+        let app = build_router(state);
+
+        let page = body_string(send(&app, get(&format!("/p/{}?lang=en", prose))).await).await;
+        assert!(page.contains(r#"<div class="code-wrapper prose">"#));
+        assert!(!page.contains(r#"id="line-numbers""#));
+        assert!(page.contains(&format!(r#"href="/p/{}?view=code""#, prose)));
+
+        let page =
+            body_string(send(&app, get(&format!("/p/{}?view=code&lang=en", prose))).await).await;
+        assert!(!page.contains("code-wrapper prose"));
+        assert!(page.contains(r#"id="line-numbers""#));
+        assert!(page.contains("Reader view"));
+
+        let page = body_string(send(&app, get(&format!("/p/{}?lang=en", code))).await).await;
+        assert!(!page.contains("code-wrapper prose"));
+        assert!(!page.contains("?view=code"));
+        assert!(!page.contains("Reader view"));
+    }
+
+    #[tokio::test]
+    async fn test_content_stats_everywhere() {
+        let state = test_state().await;
+        let app = build_router(state);
+        let mut request = form_post("/paste", "content=one+two%0A%E4%B8%89%E5%9B%9B");
+        request
+            .headers_mut()
+            .insert("hx-request", header::HeaderValue::from_static("true"));
+        let body = body_string(send(&app, request).await).await;
+        let between = |start: &str| {
+            let rest = &body[body.find(start).unwrap() + start.len()..];
+            rest[..rest.find('"').unwrap()].to_string()
+        };
+        let (token, manage) = (between("value=\"/p/"), between("value=\"/m/"));
+        let label = "2 lines · 4 words · 10 characters · 14 bytes";
+
+        let page = body_string(send(&app, get(&format!("/p/{}?lang=en", token))).await).await;
+        assert!(page.contains(&format!(r#"<div class="paste-stats">{}</div>"#, label)));
+        let page = body_string(send(&app, get(&format!("/m/{}?lang=en", manage))).await).await;
+        assert!(page.contains(label));
+        let meta: serde_json::Value = serde_json::from_str(
+            &body_string(send(&app, get(&format!("/api/v1/p/{}/meta", token))).await).await,
+        )
+        .unwrap();
+        assert_eq!(
+            meta["stats"],
+            serde_json::json!({"lines": 2, "words": 4, "chars": 10, "bytes": 14})
+        );
+
+        let response = send(&app, form_post("/paste", "content=c2VhbGVk&is_e2e=on")).await;
+        let sealed = location(&response).trim_start_matches("/p/").to_string();
+        let page = body_string(send(&app, get(&format!("/p/{}", sealed))).await).await;
+        assert!(!page.contains("paste-stats"));
+        let meta: serde_json::Value = serde_json::from_str(
+            &body_string(send(&app, get(&format!("/api/v1/p/{}/meta", sealed))).await).await,
+        )
+        .unwrap();
+        assert!(meta["stats"].is_null());
+    }
+
+    #[tokio::test]
+    async fn test_json_and_yaml_syntax() {
+        let state = test_state().await;
+        let app = build_router(state);
+        let create = async |content: &str, language: &str| {
+            let body =
+                serde_html_form::to_string([("content", content), ("language", language)]).unwrap();
+            let response = send(&app, form_post("/paste", &body)).await;
+            location(&response).trim_start_matches("/p/").to_string()
+        };
+        let page = async |token: &str| {
+            body_string(send(&app, get(&format!("/p/{}?lang=en", token))).await).await
+        };
+        let raw = async |uri: String| body_string(send(&app, get(&uri)).await).await;
+
+        let valid = create(r#"{"b": 1.50, "a": [1, 2]}"#, "json").await;
+        assert!(!page(&valid).await.contains("syntax-invalid"));
+        let pretty = raw(format!("/r/{}?pretty=1", valid)).await;
+        assert_eq!(
+            pretty,
+            "{\n  \"b\": 1.50,\n  \"a\": [\n    1,\n    2\n  ]\n}\n"
+        );
+        assert_eq!(
+            raw(format!("/r/{}?minify=1", valid)).await,
+            r#"{"b":1.50,"a":[1,2]}"#
+        );
+        // The stored content is untouched.
+        assert_eq!(
+            raw(format!("/r/{}", valid)).await,
+            r#"{"b": 1.50, "a": [1, 2]}"#
+        );
+
+        let broken = "{\n  \"a\": 1,\n  \"b\": \n}";
+        let invalid = create(broken, "json").await;
+        assert!(page(&invalid).await.contains("Invalid JSON at line 4"));
+        assert_eq!(raw(format!("/r/{}?pretty=1", invalid)).await, broken);
+
+        let yaml = create("a: 1\nb: [2, 3\n", "yaml").await;
+        assert!(page(&yaml).await.contains("syntax-invalid"));
+        let yaml = create("a: 1\n---\nb: 2\n", "yaml").await;
+        assert!(!page(&yaml).await.contains("syntax-invalid"));
+
+        // Other languages are neither checked nor reformatted.
+        let text = create("{\"a\": 1}", "plaintext").await;
+        assert!(!page(&text).await.contains("syntax-invalid"));
+        assert_eq!(raw(format!("/r/{}?pretty=1", text)).await, "{\"a\": 1}");
+    }
+
+    #[tokio::test]
+    async fn test_own_recent_pastes() {
+        let state = test_state().await;
+        let app = build_router(state);
+        let page = body_string(send(&app, get("/?lang=en")).await).await;
+        assert!(page.contains(r#"hx-get="/api/v1/recent/mine?lang=en""#));
+        assert!(!page.contains("Your recent moments"));
+
+        let mut request = form_post("/paste", "content=a&title=First");
+        request
+            .headers_mut()
+            .insert("hx-request", header::HeaderValue::from_static("true"));
+        let response = send(&app, request).await;
+        assert_eq!(response.headers()["HX-Trigger"], "pasteCreated");
+        let owner = response.headers()[header::SET_COOKIE]
+            .to_str()
+            .unwrap()
+            .split(';')
+            .next()
+            .unwrap()
+            .to_string();
+        let with_owner = |uri: &str| {
+            Request::get(uri)
+                .header(header::COOKIE, &owner)
+                .body(Body::empty())
+                .unwrap()
+        };
+        for title in ["Second", "Third", "Fourth", "Fifth", "Sixth"] {
+            let request = Request::post("/paste")
+                .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+                .header(header::COOKIE, &owner)
+                .body(Body::from(format!("content={0}&title={0}", title)))
+                .unwrap();
+            send(&app, request).await;
+        }
+
+        let page = body_string(send(&app, with_owner("/?lang=en")).await).await;
+        assert!(page.contains("Your recent moments"));
+        assert!(page.contains(r#"class="recent-title">Sixth</a>"#));
+        // Only the latest five.
+        assert!(!page.contains(r#"class="recent-title">First</a>"#));
+
+        let fragment =
+            body_string(send(&app, with_owner("/api/v1/recent/mine?lang=en")).await).await;
+        assert!(fragment.find(">Sixth<").unwrap() < fragment.find(">Second<").unwrap());
+        let fragment = body_string(send(&app, get("/api/v1/recent/mine")).await).await;
+        assert!(fragment.trim().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_owner_cookie_is_signed_and_rotates() {
+        let app = build_router(test_state().await);
+        let set_cookie = |response: &axum::response::Response| {
+            response.headers()[header::SET_COOKIE]
+                .to_str()
+                .unwrap()
+                .to_string()
+        };
+        let response = send(&app, form_post("/paste", "content=mine")).await;
+        let path = location(&response);
+        let cookie = set_cookie(&response);
+        assert!(cookie.contains("; HttpOnly; SameSite=Lax"));
+        assert!(!cookie.contains("Secure"));
+        let owner = cookie.split(';').next().unwrap().to_string();
+        let (id, _) = owner.trim_start_matches("owner=").split_once('.').unwrap();
+
+        let preview = async |cookie: &str| {
+            let request = Request::get(format!("{}?lang=en", path))
+                .header(header::COOKIE, cookie)
+                .body(Body::empty())
+                .unwrap();
+            body_string(send(&app, request).await)
+                .await
+                .contains("Owner preview")
+        };
+        assert!(preview(&owner).await);
+        // A bare id, or one with a forged signature, is no owner at all.
+        assert!(!preview(&format!("owner={}", id)).await);
+        assert!(!preview(&format!("owner={}.{}", id, "0".repeat(64))).await);
+
+        let mut request = form_post("/paste", "content=other");
+        request.headers_mut().insert(
+            "x-forwarded-proto",
+            header::HeaderValue::from_static("https"),
+        );
+        assert!(set_cookie(&send(&app, request).await).contains("; Secure"));
+
+        let rotate = Request::post("/mine/rotate")
+            .header(header::COOKIE, &owner)
+            .header(header::ACCEPT, "application/json")
+            .body(Body::empty())
+            .unwrap();
+        let response = send(&app, rotate).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let rotated = set_cookie(&response).split(';').next().unwrap().to_string();
+        assert_eq!(body_string(response).await, r#"{"moved":1}"#);
+        assert_ne!(rotated, owner);
+        assert!(preview(&rotated).await);
+        assert!(!preview(&owner).await);
+    }
+
+    #[tokio::test]
+    async fn test_no_webhooks_are_queued_without_the_section() {
+        let state = test_state().await;
+        assert!(state.config.load().webhooks.is_none());
+        PasteBuilder::new()
+            .token("burns")
+            .max_views(1)
+            .insert(&state.pool)
+            .await;
+        PasteBuilder::new()
+            .token("stale")
+            .expires_in(-1)
+            .insert(&state.pool)
+            .await;
+        let app = build_router(state.clone());
+        assert_eq!(send(&app, get("/r/burns")).await.status(), StatusCode::OK);
+        assert_eq!(
+            send(&app, get("/r/stale")).await.status(),
+            StatusCode::NOT_FOUND
+        );
+        let queued: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM webhook_queue")
+            .fetch_one(&state.pool)
+            .await
+            .unwrap();
+        assert_eq!(queued, 0);
+    }
+
+    #[tokio::test]
+    async fn test_admin_retries_dead_webhooks() {
+        let state = admin_state().await;
+        PasteBuilder::new()
+            .token("gone")
+            .max_views(1)
+            .insert(&state.pool)
+            .await;
+        let rules = db::ViewRules {
+            webhooks: true,
+            ..db::ViewRules::default()
+        };
+        db::consume_view(&state.pool, "gone", &rules, None, false, now_ts())
+            .await
+            .unwrap();
+        let due = db::due_webhooks(&state.pool, now_ts(), 10).await.unwrap();
+        assert_eq!(due.len(), 1);
+        db::fail_webhook(&state.pool, due[0].id, "HTTP 503", 0, true)
+            .await
+            .unwrap();
+        let app = build_router(state);
+        let admin = |request: axum::http::request::Builder| {
+            request
+                .header(header::AUTHORIZATION, "Bearer sesame")
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let response = send(&app, get("/admin/api/webhooks/dead")).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = send(&app, admin(Request::get("/admin/api/webhooks/dead"))).await;
+        let dead: serde_json::Value = serde_json::from_str(&body_string(response).await).unwrap();
+        assert_eq!(dead[0]["token"], "gone");
+        assert_eq!(dead[0]["event"], "burned");
+        assert_eq!(dead[0]["last_error"], "HTTP 503");
+
+        let retry = format!("/admin/api/webhooks/{}/retry", due[0].id);
+        let response = send(&app, admin(Request::post(&retry))).await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let response = send(&app, admin(Request::post(&retry))).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let response = send(&app, admin(Request::get("/admin/api/webhooks/dead"))).await;
+        assert_eq!(body_string(response).await, "[]");
+
+        let response = send(&app, get("/api/v1/stats")).await;
+        let stats: serde_json::Value = serde_json::from_str(&body_string(response).await).unwrap();
+        assert_eq!(stats["webhooks"]["delivered"], 0);
+    }
+
+    #[tokio::test]
+    async fn test_view_timeline_outlives_burn() {
+        let state = test_state().await;
+        let created = PasteBuilder::new()
+            .max_views(3)
+            .created_at(now_ts() - 2 * 3600)
+            .insert(&state.pool)
+            .await;
+        let app = build_router(state.clone());
+        let timeline_uri = format!("/m/{}/views", created.manage_token);
+        let timeline = |uri: String| {
+            let app = app.clone();
+            async move {
+                let response = send(&app, get(&uri)).await;
+                assert_eq!(response.status(), StatusCode::OK);
+                serde_json::from_str::<serde_json::Value>(&body_string(response).await).unwrap()
+            }
+        };
+
+        let before = timeline(timeline_uri.clone()).await;
+        assert_eq!(before["hours"].as_array().unwrap().len(), 3);
+        assert!(
+            before["hours"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .all(|hour| hour["page"] == 0)
+        );
+        assert_eq!(before["deleted_at"], serde_json::Value::Null);
+
+        // One page view, then two raw fetches; the last burns it
+        for uri in ["/p/", "/r/", "/r/"] {
+            let response = send(&app, get(&format!("{}{}", uri, created.token))).await;
+            assert_eq!(response.status(), StatusCode::OK, "{}", uri);
+        }
+        assert!(
+            db::fetch_paste(&state.pool, &created.token, None, now_ts())
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        let after = timeline(timeline_uri.clone()).await;
+        let hours = after["hours"].as_array().unwrap();
+        assert_eq!(hours.len(), 3);
+        assert_eq!(hours[0]["at"], (now_ts() / 3600 - 2) * 3600);
+        assert_eq!(hours[2]["page"], 1);
+        assert_eq!(hours[2]["raw"], 2);
+        assert_eq!(after["page_views"], 1);
+        assert_eq!(after["raw_views"], 2);
+        assert!(after["deleted_at"].is_i64());
+        let capped = timeline(format!("{}?hours=1", timeline_uri)).await;
+        assert_eq!(capped["hours"].as_array().unwrap().len(), 1);
+        assert_eq!(capped["raw_views"], 2);
+
+        let response = send(&app, get(&format!("/m/{}/views", "x".repeat(32)))).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        // Gone once the event log's retention window passes
+        db::cleanup_expired(&state.pool, -1, false, now_ts()).await;
+        let response = send(&app, get(&timeline_uri)).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_admin_storage_usage() {
+        let state = admin_state().await;
+        PasteBuilder::new()
+            .title("Big one")
+            .language("go")
+            .content(&"x".repeat(40))
+            .insert(&state.pool)
+            .await;
+        let app = build_router(state);
+        let admin = |uri: &str| {
+            Request::get(uri)
+                .header(header::AUTHORIZATION, "Bearer sesame")
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let response = send(&app, get("/admin/api/storage")).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = send(&app, admin("/admin/api/storage")).await;
+        let usage: serde_json::Value = serde_json::from_str(&body_string(response).await).unwrap();
+        assert_eq!(usage["content_length"], 40);
+        assert_eq!(usage["by_language"][0]["language"], "go");
+        assert_eq!(usage["private"]["content_length"], 40);
+        assert_eq!(usage["largest"][0]["title"], "Big one");
+        assert!(usage["largest"][0].get("expires_in").is_none());
+        assert!(usage["files"].get("wal").is_some());
+
+        let page = body_string(send(&app, admin("/admin?lang=en")).await).await;
+        assert!(page.contains("Largest pastes"));
+        assert!(page.contains("<td>go</td>"));
+    }
+
+    #[tokio::test]
+    async fn test_explore_preview_chars_and_fields() {
+        let state = test_state().await;
+        PasteBuilder::new()
+            .title("Card")
+            .content("日本語のテキスト")
+            .public(true)
+            .insert(&state.pool)
+            .await;
+        let app = build_router(state);
+        let explore = |query: &'static str| {
+            let app = app.clone();
+            async move {
+                let response = send(&app, get(&format!("/api/v1/explore?{}", query))).await;
+                let status = response.status();
+                let body = body_string(response).await;
+                (
+                    status,
+                    serde_json::from_str::<serde_json::Value>(&body).unwrap(),
+                )
+            }
+        };
+
+        let (_, paste) = explore("preview_chars=3").await;
+        assert_eq!(paste["content"], "日本語");
+        assert_eq!(paste["content_truncated"], true);
+        assert!(paste.get("preview").is_none());
+        let (_, paste) = explore("preview_chars=8").await;
+        assert_eq!(paste["content"], "日本語のテキスト");
+        assert_eq!(paste["content_truncated"], false);
+        let (_, paste) = explore("").await;
+        assert!(paste.get("content_truncated").is_none());
+
+        let (status, paste) = explore("fields=token,title").await;
+        assert_eq!(status, StatusCode::OK);
+        let keys: Vec<&String> = paste.as_object().unwrap().keys().collect();
+        assert_eq!(keys, ["title", "token"]);
+        let (_, paste) = explore("fields=content,content_truncated&preview_chars=1").await;
+        assert_eq!(
+            paste,
+            serde_json::json!({"content": "日", "content_truncated": true})
+        );
+
+        let (status, error) = explore("fields=title,body&lang=en").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(error["error"], "invalid_fields");
+        let message = error["message"].as_str().unwrap();
+        assert!(message.contains("\"body\""), "{}", message);
+        assert!(
+            message.contains("content_truncated, index, total"),
+            "{}",
+            message
+        );
+        let (status, error) = explore("preview_chars=-1").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(error["error"], "invalid_preview_chars");
+    }
+
+    #[tokio::test]
+    async fn test_explore_life_fraction_of_renewed_paste() {
+        let state = test_state().await;
+        // Renewed to twice the time it started with
+        PasteBuilder::new()
+            .token("renewed")
+            .public(true)
+            .lifespan(3600)
+            .expires_in(7200)
+            .insert(&state.pool)
+            .await;
+        let app = build_router(state);
+
+        let response = send(&app, get("/api/v1/explore?fields=token,life_fraction")).await;
+        let paste: serde_json::Value = serde_json::from_str(&body_string(response).await).unwrap();
+        assert_eq!(
+            paste,
+            serde_json::json!({"token": "renewed", "life_fraction": 1.0})
+        );
+
+        let page = body_string(send(&app, get("/explore")).await).await;
+        assert!(page.contains(r#"data-life-fraction="1""#));
+        assert!(page.contains("width: 100%"));
+    }
+
+    #[tokio::test]
+    async fn test_explore_totals_agree() {
+        let state = test_state().await;
+        // Seconds left out of a 1000 second lifespan.
+        for left in [900, 100, 50] {
+            PasteBuilder::new()
+                .public(true)
+                .lifespan(1000)
+                .expires_in(left)
+                .insert(&state.pool)
+                .await;
+        }
+        // Neither is listed, so neither is counted
+        PasteBuilder::new()
+            .public(true)
+            .max_views(1)
+            .insert(&state.pool)
+            .await;
+        PasteBuilder::new().insert(&state.pool).await;
+        let app = build_router(state);
+
+        let page = body_string(send(&app, get("/explore?expiring=1&lang=en")).await).await;
+        assert!(page.contains("Showing 2 of 2"));
+        let response = send(&app, get("/api/v1/explore?expiring=1&fields=total")).await;
+        let body: serde_json::Value = serde_json::from_str(&body_string(response).await).unwrap();
+        assert_eq!(body["total"], 2);
+
+        let page = body_string(send(&app, get("/explore?lang=en")).await).await;
+        assert!(page.contains("Showing 3 of 3"));
+        let response = send(&app, get("/api/v1/explore?fields=total")).await;
+        let body: serde_json::Value = serde_json::from_str(&body_string(response).await).unwrap();
+        assert_eq!(body["total"], 3);
+        let index = body_string(send(&app, get("/")).await).await;
+        assert!(index.contains(r#"<span class="explore-count">(3)</span>"#));
+    }
+
+    #[tokio::test]
+    async fn test_index_renders_in_every_locale() {
+        let state = test_state().await;
+        let created = PasteBuilder::new().insert(&state.pool).await;
+        let app = build_router(state);
+        for (lang, create, new_paste) in [
+            (Lang::En, "Create", "New Paste"),
+            (Lang::Zh, "创建", "新建"),
+            (Lang::Ja, "作成", "新規作成"),
+        ] {
+            let response = send(&app, get(&format!("/?lang={}", lang.code()))).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = body_string(response).await;
+            assert!(body.contains(&format!(r#"<html lang="{}""#, lang.code())));
+            assert!(body.contains(create), "{}", lang.code());
+            for other in Lang::ALL {
+                let link = format!(
+                    r#"href="?lang={}&remember=1" class="lang-link">{}</a>"#,
+                    other.code(),
+                    other.native_name()
+                );
+                assert!(body.contains(&link), "{}", link);
+            }
+
+            let path = format!("/p/{}?langonly={}", created.token, lang.code());
+            let body = body_string(send(&app, get(&path)).await).await;
+            assert!(body.contains(new_paste), "{}", lang.code());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pastes_expire_by_the_app_clock() {
+        let clock = MockClock::new();
+        let state = AppState {
+            clock: clock.clone(),
+            ..test_state().await
+        };
+        let created = PasteBuilder::new()
+            .public(true)
+            .expires_in(60)
+            .insert(&state.pool)
+            .await;
+        let app = build_router(state);
+        let paths = [
+            format!("/p/{}", created.token),
+            format!("/r/{}", created.token),
+            format!("/api/v1/p/{}/meta", created.token),
+        ];
+        for path in &paths {
+            assert_eq!(
+                send(&app, get(path)).await.status(),
+                StatusCode::OK,
+                "{}",
+                path
+            );
+        }
+
+        clock.advance(61);
+        for path in &paths {
+            assert_eq!(
+                send(&app, get(path)).await.status(),
+                StatusCode::NOT_FOUND,
+                "{}",
+                path
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_token_case_follows_the_configured_mode() {
+        for case_insensitive in [false, true] {
+            let state = test_state().await;
+            update_config(&state, |config| {
+                config.paste.case_insensitive_tokens = case_insensitive;
+            });
+            PasteBuilder::new()
+                .token("Ab3cDe")
+                .title("Exact")
+                .insert(&state.pool)
+                .await;
+            let app = build_router(state);
+
+            let response = send(&app, get("/p/Ab3cDe")).await;
+            assert!(body_string(response).await.contains("Exact"));
+            let found = if case_insensitive {
+                StatusCode::OK
+            } else {
+                StatusCode::NOT_FOUND
+            };
+            for path in ["/p/ab3cde", "/r/AB3CDE", "/api/v1/p/aB3cDe/meta"] {
+                let response = send(&app, get(path)).await;
+                assert_eq!(response.status(), found, "{} {}", case_insensitive, path);
+            }
+
+            // The slug check agrees: capitals are an error, or the lowercase
+            // slug, which the paste above now holds in another case
+            let response = send(&app, get("/api/v1/slug-available?slug=AB3CDE")).await;
+            let body: serde_json::Value =
+                serde_json::from_str(&body_string(response).await).unwrap();
+            let reason = if case_insensitive {
+                "taken"
+            } else {
+                "invalid_charset"
+            };
+            assert_eq!(body["reason"], reason, "{}", case_insensitive);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_raw_not_found_says_why() {
+        let state = test_state().await;
+        let expired = PasteBuilder::new()
+            .expires_in(-60)
+            .insert(&state.pool)
+            .await
+            .token;
+        let en = state.i18n.load().strings(Lang::En);
+        let app = build_router(state);
+
+        let response = send(&app, get(&format!("/r/{}?lang=en", expired))).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let id = response.headers()["x-request-id"]
+            .to_str()
+            .unwrap()
+            .to_string();
+        let body = body_string(response).await;
+        assert_eq!(body, format!("{}\nRequest ID: {}\n", en.not_found_desc, id));
+
+        let response = send(&app, get("/r/neverwas?lang=en")).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let body = body_string(response).await;
+        assert!(body.starts_with(&format!("{}\nRequest ID: ", en.raw_never_existed)));
+
+        for (token, code) in [(expired.as_str(), "expired"), ("neverwas", "not_found")] {
+            let request = Request::get(format!("/r/{}?lang=en", token))
+                .header(header::ACCEPT, "application/json")
+                .header("x-request-id", "req-42")
+                .body(Body::empty())
+                .unwrap();
+            let response = send(&app, request).await;
+            assert_eq!(response.status(), StatusCode::NOT_FOUND);
+            assert_eq!(response.headers()["x-request-id"], "req-42");
+            let body: serde_json::Value =
+                serde_json::from_str(&body_string(response).await).unwrap();
+            assert_eq!(body["error"], code);
+            assert_eq!(body["request_id"], "req-42");
+            assert!(body["message"].as_str().is_some_and(|m| !m.is_empty()));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_head_sends_the_headers_get_would() {
+        let state = test_state().await;
+        let token = PasteBuilder::new()
+            .content("fn main() {}\n// héllo\n")
+            .insert(&state.pool)
+            .await
+            .token;
+        let pool = state.pool.clone();
+        let app = build_router(state);
+        let head = |path: &str| Request::head(path).body(Body::empty()).unwrap();
+        let views = async || -> i64 {
+            sqlx::query_scalar("SELECT views FROM pastes WHERE token = ?")
+                .bind(&token)
+                .fetch_one(&pool)
+                .await
+                .unwrap()
+        };
+        // Headers that differ between any two responses, body or not.
+        let comparable = |response: &axum::response::Response| {
+            let mut headers = response.headers().clone();
+            headers.remove(header::SET_COOKIE);
+            headers.remove(header::DATE);
+            headers
+        };
+
+        for path in [format!("/r/{}", token), format!("/r/{}.rs", token)] {
+            let before = views().await;
+            let response = send(&app, head(&path)).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            let head_headers = comparable(&response);
+            assert!(body_string(response).await.is_empty());
+            assert_eq!(views().await, before);
+
+            let response = send(&app, get(&path)).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(comparable(&response), head_headers);
+            assert!(head_headers.contains_key(header::ETAG));
+            assert!(head_headers.contains_key("x-mayfile-expires-at"));
+            let length: usize = head_headers[header::CONTENT_LENGTH]
+                .to_str()
+                .unwrap()
+                .parse()
+                .unwrap();
+            assert_eq!(body_string(response).await.len(), length);
+        }
+
+        let before = views().await;
+        let response = send(&app, head(&format!("/p/{}", token))).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let mut head_headers = comparable(&response);
+        assert!(body_string(response).await.is_empty());
+        assert_eq!(views().await, before);
+        let response = send(&app, get(&format!("/p/{}", token))).await;
+        let mut get_headers = comparable(&response);
+        // Only GET renders the page to measure it.
+        get_headers.remove(header::CONTENT_LENGTH);
+        head_headers.remove(header::CONTENT_LENGTH);
+        assert_eq!(get_headers, head_headers);
+        assert!(
+            get_headers[header::ETAG]
+                .to_str()
+                .unwrap()
+                .starts_with("W/\"")
+        );
+
+        for path in ["/p/neverwas", "/r/neverwas"] {
+            let response = send(&app, head(path)).await;
+            assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_head_spends_no_views() {
+        let state = test_state().await;
+        let token = PasteBuilder::new()
+            .content("fn main() {}")
+            .file("notes.md", "# notes")
+            .insert(&state.pool)
+            .await
+            .token;
+        let link = db::insert_share_link(&state.pool, &token, "once", Some(1), now_ts())
+            .await
+            .unwrap()
+            .unwrap();
+        let exp = now_ts() + 600;
+        let sig = crypto::sign_share_link(&state.signing_key, &token, exp);
+        let pool = state.pool.clone();
+        let app = build_router(state);
+        let head = |path: &str| Request::head(path).body(Body::empty()).unwrap();
+        let counts = async || -> (i64, i64) {
+            sqlx::query_as(
+                r#"
+                SELECT pastes.views, share_links.views FROM pastes
+                JOIN share_links ON share_links.paste_id = pastes.id
+                WHERE pastes.token = ?
+                "#,
+            )
+            .bind(&token)
+            .fetch_one(&pool)
+            .await
+            .unwrap()
+        };
+
+        for path in [
+            format!("/p/{}/print", token),
+            format!("/r/{}/notes.md", token),
+            format!("/s/{}", link.share_token),
+            format!("/s/{}?exp={}&sig={}", token, exp, sig),
+        ] {
+            let response = send(&app, head(&path)).await;
+            assert_eq!(response.status(), StatusCode::OK, "{}", path);
+            assert!(body_string(response).await.is_empty());
+            assert_eq!(counts().await, (0, 0), "{}", path);
+        }
+        let response = send(&app, head(&format!("/r/{}/notes.md", token))).await;
+        assert_eq!(response.headers()[header::CONTENT_LENGTH], "7");
+
+        // The link's one view is still there for GET, and then it is spent.
+        let path = format!("/s/{}", link.share_token);
+        assert_eq!(send(&app, get(&path)).await.status(), StatusCode::OK);
+        assert_eq!(
+            send(&app, head(&path)).await.status(),
+            StatusCode::NOT_FOUND
+        );
+        for path in [
+            "/p/neverwas/print".to_string(),
+            format!("/r/{}/missing.md", token),
+            format!("/s/{}?exp={}&sig=forged", token, exp),
+        ] {
+            let response = send(&app, head(&path)).await;
+            assert_eq!(response.status(), StatusCode::NOT_FOUND, "{}", path);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_raw_is_compressed_when_accepted() {
+        use std::io::Read;
+
+        let state = test_state().await;
+        let content = "2024-05-01 12:00:00 INFO request served\n".repeat(200);
+        let token = PasteBuilder::new()
+            .content(&content)
+            .insert(&state.pool)
+            .await
+            .token;
+        let small = PasteBuilder::new()
+            .content("tiny")
+            .insert(&state.pool)
+            .await
+            .token;
+        let app = build_router(state.clone());
+        let raw = |token: &str, encoding: Option<&str>| {
+            let mut request = Request::get(format!("/r/{}", token));
+            if let Some(encoding) = encoding {
+                request = request.header(header::ACCEPT_ENCODING, encoding);
+            }
+            request.body(Body::empty()).unwrap()
+        };
+        let bytes = async |response: axum::response::Response| {
+            axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap()
+        };
+
+        let response = send(&app, raw(&token, None)).await;
+        assert!(!response.headers().contains_key(header::CONTENT_ENCODING));
+        let etag = response.headers()[header::ETAG]
+            .to_str()
+            .unwrap()
+            .to_string();
+        assert!(etag.starts_with('"'));
+        assert_eq!(bytes(response).await, content.as_bytes());
+
+        let response = send(&app, raw(&token, Some("gzip"))).await;
+        assert_eq!(response.headers()[header::CONTENT_ENCODING], "gzip");
+        // The compressed body is not the bytes the strong tag named.
+        assert_eq!(response.headers()[header::ETAG], format!("W/{}", etag));
+        let compressed = bytes(response).await;
+        assert!(compressed.len() < content.len());
+        let mut decompressed = String::new();
+        flate2::read::GzDecoder::new(&compressed[..])
+            .read_to_string(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, content);
+
+        let response = send(&app, raw(&token, Some("br"))).await;
+        assert_eq!(response.headers()[header::CONTENT_ENCODING], "br");
+
+        let response = send(&app, raw(&small, Some("gzip"))).await;
+        assert!(!response.headers().contains_key(header::CONTENT_ENCODING));
+        assert_eq!(bytes(response).await, "tiny".as_bytes());
+
+        update_config(&state, |config| config.server.compression = false);
+        let app = build_router(state);
+        let response = send(&app, raw(&token, Some("gzip, br"))).await;
+        assert!(!response.headers().contains_key(header::CONTENT_ENCODING));
+        assert_eq!(bytes(response).await, content.as_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_large_raw_is_streamed() {
+        use http_body_util::BodyExt;
+
+        let state = test_state().await;
+        let content = "0123456789abcdef".repeat(200_000);
+        let large = PasteBuilder::new()
+            .content(&content)
+            .insert(&state.pool)
+            .await
+            .token;
+        let small = PasteBuilder::new()
+            .content("short")
+            .insert(&state.pool)
+            .await
+            .token;
+        let app = build_router(state);
+
+        let response = send(&app, get(&format!("/r/{}", large))).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CONTENT_LENGTH],
+            content.len().to_string()
+        );
+        let mut body = response.into_body();
+        // A buffered body knows its size; a streamed one arrives in pieces.
+        assert_eq!(axum::body::HttpBody::size_hint(&body).exact(), None);
+        let mut received = Vec::new();
+        let mut frames = 0;
+        while let Some(frame) = body.frame().await {
+            received.extend_from_slice(&frame.unwrap().into_data().unwrap());
+            frames += 1;
+        }
+        assert!(frames > 1);
+        assert_eq!(received, content.as_bytes());
+
+        let response = send(&app, get(&format!("/r/{}", small))).await;
+        assert_eq!(
+            axum::body::HttpBody::size_hint(response.body()).exact(),
+            Some(5)
+        );
+        assert_eq!(body_string(response).await, "short");
+    }
+
+    #[tokio::test]
+    async fn test_hot_pastes_are_served_from_memory() {
+        let state = admin_state().await;
+        update_config(&state, |config| {
+            config.paste.cache = Some(crate::models::CacheConfig {
+                max_entries: 16,
+                max_bytes: 1024 * 1024,
+                flush_secs: 5,
+            });
+        });
+        let hot = PasteBuilder::new()
+            .public(true)
+            .content("everyone is reading this")
+            .insert(&state.pool)
+            .await
+            .token;
+        let burn = PasteBuilder::new()
+            .max_views(1000)
+            .insert(&state.pool)
+            .await
+            .token;
+        let app = build_router(state.clone());
+        let stored_views = async |token: &str| -> i64 {
+            sqlx::query_scalar("SELECT views FROM pastes WHERE token = ?")
+                .bind(token)
+                .fetch_one(&state.pool)
+                .await
+                .unwrap()
+        };
+
+        for i in 0..50 {
+            let path = if i % 2 == 0 { "p" } else { "r" };
+            let response = send(&app, get(&format!("/{}/{}", path, hot))).await;
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        let body = body_string(send(&app, get(&format!("/r/{}", hot))).await).await;
+        assert_eq!(body, "everyone is reading this");
+        // One lookup loaded the paste; the other fifty views wrote nothing.
+        let status = state.paste_cache.status();
+        assert_eq!((status.hits, status.misses), (50, 1));
+        assert_eq!(stored_views(&hot).await, 0);
+
+        paste_cache::flush(&state).await;
+        assert_eq!(stored_views(&hot).await, 51);
+        let raw: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM events WHERE token = ? AND event_type = 'viewed' AND raw = 1",
+        )
+        .bind(&hot)
+        .fetch_one(&state.pool)
+        .await
+        .unwrap();
+        assert_eq!(raw, 26);
+
+        // Burn-on-read pastes are counted at once and never kept.
+        for expected in 1..=3 {
+            send(&app, get(&format!("/p/{}", burn))).await;
+            assert_eq!(stored_views(&burn).await, expected);
+        }
+        send(&app, get(&format!("/p/{}", hot))).await;
+        let status = state.paste_cache.status();
+        assert_eq!((status.hits, status.misses), (50, 5));
+
+        let request = Request::post(format!("/admin/api/p/{}/delete", hot))
+            .header(header::AUTHORIZATION, "Bearer sesame")
+            .body(Body::empty())
+            .unwrap();
+        send(&app, request).await;
+        let response = send(&app, get(&format!("/p/{}", hot))).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_deleted_paste_takes_its_pending_views_along() {
+        let state = admin_state().await;
+        update_config(&state, |config| {
+            config.paste.cache = Some(crate::models::CacheConfig {
+                max_entries: 16,
+                max_bytes: 1024 * 1024,
+                flush_secs: 5,
+            });
+        });
+        let app = build_router(state.clone());
+        let delete_by_manage_link = async |paste: &crate::models::CreatedPaste| {
+            let path = format!("/m/{}/delete", paste.manage_token);
+            send(&app, Request::post(path).body(Body::empty()).unwrap()).await
+        };
+        let delete_by_admin = async |paste: &crate::models::CreatedPaste| {
+            let request = Request::post(format!("/admin/api/p/{}/delete", paste.token))
+                .header(header::AUTHORIZATION, "Bearer sesame")
+                .body(Body::empty())
+                .unwrap();
+            send(&app, request).await
+        };
+        for by_admin in [false, true] {
+            let hot = || PasteBuilder::new().public(true).token("meeting-notes");
+            let old = hot().insert(&state.pool).await;
+            for _ in 0..3 {
+                send(&app, get("/p/meeting-notes")).await;
+            }
+            let response = if by_admin {
+                delete_by_admin(&old).await
+            } else {
+                delete_by_manage_link(&old).await
+            };
+            assert!(response.status().is_success() || response.status().is_redirection());
+
+            // The slug is taken again before the views are written.
+            let new = hot().insert(&state.pool).await;
+            paste_cache::flush(&state).await;
+            let views: i64 = sqlx::query_scalar("SELECT views FROM pastes WHERE token = ?")
+                .bind(&new.token)
+                .fetch_one(&state.pool)
+                .await
+                .unwrap();
+            assert_eq!(views, 0, "deleted by admin: {}", by_admin);
+            delete_by_admin(&new).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_evicted_pastes_leave_the_cache() {
+        let state = test_state().await;
+        update_config(&state, |config| {
+            config.paste.max_pastes = 1;
+            config.paste.cache = Some(crate::models::CacheConfig {
+                max_entries: 16,
+                max_bytes: 1024 * 1024,
+                flush_secs: 5,
+            });
+        });
+        let hot = PasteBuilder::new()
+            .public(true)
+            .insert(&state.pool)
+            .await
+            .token;
+        let app = build_router(state.clone());
+        for _ in 0..2 {
+            let response = send(&app, get(&format!("/p/{}", hot))).await;
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        assert_eq!(state.paste_cache.status().hits, 1);
+
+        // Creating a paste past `max_pastes` evicts the cached one.
+        let response = send(&app, form_post("/paste", "content=newcomer")).await;
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        let response = send(&app, get(&format!("/p/{}", hot))).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_detail_page_answers_304_until_it_changes() {
+        let state = test_state().await;
+        let token = PasteBuilder::new()
+            .public(true)
+            .views(40)
+            .lifespan(86400)
+            .expires_in(3600)
+            .insert(&state.pool)
+            .await
+            .token;
+        let burn = PasteBuilder::new()
+            .max_views(100)
+            .insert(&state.pool)
+            .await
+            .token;
+        let app = build_router(state);
+        let page = |token: &str, etag: Option<&str>| {
+            let mut request = Request::get(format!("/p/{}?lang=en", token));
+            if let Some(etag) = etag {
+                request = request.header(header::IF_NONE_MATCH, etag);
+            }
+            request.body(Body::empty()).unwrap()
+        };
+
+        let response = send(&app, page(&token, None)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let etag = response.headers()[header::ETAG]
+            .to_str()
+            .unwrap()
+            .to_string();
+        assert!(etag.starts_with("W/\""));
+        // The counter moves in buckets, so the next views change nothing.
+        assert!(body_string(response).await.contains("40+ views"));
+        for _ in 0..2 {
+            let response = send(&app, page(&token, Some(&etag))).await;
+            assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+            assert_eq!(response.headers()[header::ETAG], etag.as_str());
+            assert!(body_string(response).await.is_empty());
+        }
+        let head = Request::head(format!("/p/{}?lang=en", token))
+            .header(header::IF_NONE_MATCH, &etag)
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(send(&app, head).await.status(), StatusCode::NOT_MODIFIED);
+        // Another locale is another page.
+        let response = send(&app, page(&format!("{}?lang=zh", token), Some(&etag))).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let renew = Request::post(format!("/p/{}/renew", token))
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(send(&app, renew).await.status(), StatusCode::OK);
+        let response = send(&app, page(&token, Some(&etag))).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_ne!(response.headers()[header::ETAG], etag.as_str());
+
+        // Burn-on-read pastes spend a view on every request, so there is
+        // nothing to revalidate.
+        let response = send(&app, page(&burn, None)).await;
+        assert!(!response.headers().contains_key(header::ETAG));
+        let response = send(&app, page(&burn, Some("*"))).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_readyz_and_admin_schema_report_the_schema() {
+        let state = admin_state().await;
+        update_config(&state, |config| config.server.maintenance = true);
+        let app = build_router(state.clone());
+
+        let response = send(&app, get("/readyz")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let report: serde_json::Value = serde_json::from_str(&body_string(response).await).unwrap();
+        assert_eq!(report["version"], db::SCHEMA_VERSION);
+        assert!(
+            report["tables"]["pastes"]
+                .as_array()
+                .unwrap()
+                .contains(&"token".into())
+        );
+
+        let response = send(&app, get("/admin/api/schema")).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let request = Request::get("/admin/api/schema")
+            .header(header::AUTHORIZATION, "Bearer sesame")
+            .body(Body::empty())
+            .unwrap();
+        let response = send(&app, request).await;
+        let admin: serde_json::Value = serde_json::from_str(&body_string(response).await).unwrap();
+        assert_eq!(admin, report);
+
+        sqlx::query("ALTER TABLE pastes DROP COLUMN author")
+            .execute(&state.pool)
+            .await
+            .unwrap();
+        let response = send(&app, get("/readyz")).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let error: serde_json::Value = serde_json::from_str(&body_string(response).await).unwrap();
+        assert_eq!(error["error"], "self_check_failed");
+        assert_eq!(error["message"], "Table pastes is missing columns: author");
+    }
+
+    #[tokio::test]
+    async fn test_crowded_tokens_are_reported() {
+        let state = admin_state().await;
+        let app = build_router(state.clone());
+        let stats = || async {
+            let body = body_string(send(&app, get("/api/v1/stats")).await).await;
+            serde_json::from_str::<serde_json::Value>(&body).unwrap()
+        };
+        let admin_page = || async {
+            let request = Request::get("/admin?lang=en")
+                .header(header::AUTHORIZATION, "Bearer sesame")
+                .body(Body::empty())
+                .unwrap();
+            body_string(send(&app, request).await).await
+        };
+
+        // The example offers two-character tokens: 1000 pastes would take
+        // a quarter of the 3844 there are.
+        let tokens = &stats().await["tokens"];
+        assert_eq!(tokens["min_token_length"], 2);
+        assert_eq!(tokens["saturated"], true);
+        assert!((tokens["occupancy"].as_f64().unwrap() - 1000.0 / 3844.0).abs() < 1e-9);
+        assert!(
+            admin_page()
+                .await
+                .contains("Shortest tokens (2 characters): 26.01% taken at max_pastes")
+        );
+        assert!(admin_page().await.contains("class=\"capacity-warning\""));
+        let warning = config::token_saturation_warning(&state.config.load().paste).unwrap();
+        assert!(warning.contains("26.01% of the 2-character tokens"));
+
+        update_config(&state, |config| {
+            config.paste.default_token_length = 8;
+            config.paste.token_lengths = vec![8, 12];
+        });
+        assert_eq!(stats().await["tokens"]["saturated"], false);
+        assert!(!admin_page().await.contains("class=\"capacity-warning\""));
+        assert!(config::token_saturation_warning(&state.config.load().paste).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_repeated_submission_gets_the_same_paste() {
+        let clock = MockClock::new();
+        let state = AppState {
+            clock: clock.clone(),
+            ..test_state().await
+        };
+        let app = build_router(state.clone());
+        let create = async |body: serde_json::Value, peer: &str, cookie: Option<&str>| {
+            let mut request = Request::post("/api/v1/paste")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap();
+            if let Some(cookie) = cookie {
+                request.headers_mut().insert(
+                    header::COOKIE,
+                    header::HeaderValue::from_str(cookie).unwrap(),
+                );
+            }
+            let peer: SocketAddr = peer.parse().unwrap();
+            request.extensions_mut().insert(ConnectInfo(peer));
+            let response = send(&app, request).await;
+            let status = response.status();
+            let owner = response.headers().get(header::SET_COOKIE).map(|cookie| {
+                cookie
+                    .to_str()
+                    .unwrap()
+                    .split(';')
+                    .next()
+                    .unwrap()
+                    .to_string()
+            });
+            let body: serde_json::Value =
+                serde_json::from_str(&body_string(response).await).unwrap();
+            (status, body, owner)
+        };
+        let retry = serde_json::json!({ "content": "retry me" });
+
+        let (status, first, owner) = create(retry.clone(), "127.0.0.1:4000", None).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(first["reused"], false);
+        let owner = owner.unwrap();
+        let (status, again, _) = create(retry.clone(), "127.0.0.1:4001", Some(&owner)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(again["reused"], true);
+        assert_eq!(again["token"], first["token"]);
+        assert_eq!(again["manage_token"], first["manage_token"]);
+
+        // Someone else behind the same address gets a paste of their own,
+        // never the first one's manage link.
+        let (status, neighbour, _) = create(retry.clone(), "127.0.0.1:4002", None).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_ne!(neighbour["token"], first["token"]);
+        assert_ne!(neighbour["manage_token"], first["manage_token"]);
+
+        // Other settings, other content, or the same from another address
+        // is a new paste.
+        let titled = serde_json::json!({ "content": "retry me", "title": "Titled" });
+        let (status, titled, _) = create(titled, "127.0.0.1:4000", Some(&owner)).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_ne!(titled["token"], first["token"]);
+        let other = serde_json::json!({ "content": "retry me not" });
+        let (status, other, _) = create(other, "127.0.0.1:4000", Some(&owner)).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_ne!(other["token"], first["token"]);
+        let (status, elsewhere, _) = create(retry.clone(), "10.0.0.2:4000", Some(&owner)).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_ne!(elsewhere["token"], first["token"]);
+
+        let mut request = form_post("/paste", "content=retry+me");
+        request
+            .headers_mut()
+            .insert("hx-request", header::HeaderValue::from_static("true"));
+        request
+            .headers_mut()
+            .insert("accept-language", header::HeaderValue::from_static("en"));
+        request.headers_mut().insert(
+            header::COOKIE,
+            header::HeaderValue::from_str(&owner).unwrap(),
+        );
+        let page = body_string(send(&app, request).await).await;
+        assert!(page.contains(&format!("/p/{}", first["token"].as_str().unwrap())));
+        assert!(page.contains("You sent this same content moments ago"));
+
+        clock.advance(61);
+        let (status, later, _) = create(retry.clone(), "127.0.0.1:4000", Some(&owner)).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(later["reused"], false);
+        assert_ne!(later["token"], first["token"]);
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM pastes")
+            .fetch_one(&state.pool)
+            .await
+            .unwrap();
+        assert_eq!(count, 6);
+
+        update_config(&state, |config| config.paste.repeat_window_secs = 0);
+        let (status, _, _) = create(retry, "127.0.0.1:4000", Some(&owner)).await;
+        assert_eq!(status, StatusCode::CREATED);
+    }
+
+    #[tokio::test]
+    async fn test_language_timeline_api() {
+        let state = test_state().await;
+        PasteBuilder::new()
+            .language("rust")
+            .insert(&state.pool)
+            .await;
+        PasteBuilder::new()
+            .language("rust")
+            .insert(&state.pool)
+            .await;
+        // Logged before events recorded a language.
+        sqlx::query(
+            "INSERT INTO events (event_type, token, created_at) VALUES ('created', 't', ?)",
+        )
+        .bind(now_ts())
+        .execute(&state.pool)
+        .await
+        .unwrap();
+        let app = build_router(state);
+
+        let response = send(&app, get("/api/v1/stats/languages?days=2")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let timeline: serde_json::Value =
+            serde_json::from_str(&body_string(response).await).unwrap();
+        let languages = timeline["languages"].as_array().unwrap();
+        assert_eq!(languages.first().unwrap(), "plaintext");
+        assert_eq!(languages.last().unwrap(), "other");
+        let days = timeline["days"].as_array().unwrap();
+        assert_eq!(days.len(), 2);
+        assert_eq!(days[0]["partial"], false);
+        assert_eq!(days[0]["counts"]["rust"], 0);
+        assert_eq!(days[1]["partial"], true);
+        assert_eq!(days[1]["counts"]["rust"], 2);
+        assert_eq!(days[1]["counts"]["other"], 1);
+        assert_eq!(
+            days[1]["counts"].as_object().unwrap().len(),
+            languages.len()
+        );
+
+        let response = send(&app, get("/api/v1/stats/languages?days=week")).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_renew_button_follows_the_same_rule_as_renewing() {
+        use crate::utils::Clock;
+        let clock = MockClock::new();
+        let state = AppState {
+            clock: clock.clone(),
+            ..test_state().await
+        };
+        let created = PasteBuilder::new()
+            .public(true)
+            .lifespan(1000)
+            .expires_in(1000)
+            .insert(&state.pool)
+            .await;
+        let burn = PasteBuilder::new()
+            .public(true)
+            .destroy_after_first_view(60)
+            .insert(&state.pool)
+            .await;
+        let app = build_router(state);
+        let meta = |token: String| {
+            let app = app.clone();
+            async move {
+                let response = send(&app, get(&format!("/api/v1/p/{}/meta", token))).await;
+                serde_json::from_str::<serde_json::Value>(&body_string(response).await).unwrap()
+            }
+        };
+        let renew = |token: String| {
+            let app = app.clone();
+            async move {
+                let request = Request::post(format!("/p/{}/renew", token))
+                    .body(Body::empty())
+                    .unwrap();
+                send(&app, request).await
+            }
+        };
+
+        let fresh = meta(created.token.clone()).await;
+        assert_eq!(fresh["original_duration"], 1000);
+        assert_eq!(fresh["renew_eligible"], false);
+        let available_at = fresh["renew_available_at"].as_i64().unwrap();
+        let page = body_string(send(&app, get(&format!("/p/{}", created.token))).await).await;
+        assert!(page.contains(&format!(
+            r#"data-renew-at="{}" style="display: none;""#,
+            available_at
+        )));
+        assert_eq!(
+            renew(created.token.clone()).await.status(),
+            StatusCode::BAD_REQUEST
+        );
+
+        clock.advance(available_at - clock.now());
+        assert_eq!(meta(created.token.clone()).await["renew_eligible"], true);
+        let response = send(&app, get("/api/v1/explore?fields=token,renew_eligible")).await;
+        let listed = body_string(response).await;
+        assert!(listed.contains(r#""renew_eligible":true"#), "{}", listed);
+        let response = renew(created.token.clone()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let trigger: serde_json::Value =
+            serde_json::from_str(response.headers()["HX-Trigger"].to_str().unwrap()).unwrap();
+        let renewed = meta(created.token.clone()).await;
+        assert_eq!(renewed["renew_eligible"], false);
+        assert_eq!(
+            trigger["renewed"]["renew_at"],
+            renewed["renew_available_at"]
+        );
+        assert_eq!(
+            renewed["renew_available_at"].as_i64().unwrap(),
+            clock.now() + 501
+        );
+        assert_eq!(
+            renew(created.token.clone()).await.status(),
+            StatusCode::BAD_REQUEST
+        );
+
+        let never = meta(burn.token.clone()).await;
+        assert_eq!(never["renew_eligible"], false);
+        assert!(never["renew_available_at"].is_null());
+        let page = body_string(send(&app, get(&format!("/p/{}", burn.token))).await).await;
+        assert!(!page.contains(r#"id="renew-btn""#));
+    }
+
+    #[tokio::test]
+    async fn test_html_rendering_is_opt_in_and_locked_down() {
+        let state = test_state().await;
+        let page = PasteBuilder::new()
+            .language("html")
+            .content("<h1>Hi</h1><script>alert(1)</script>")
+            .max_views(4)
+            .insert(&state.pool)
+            .await;
+        let text = PasteBuilder::new()
+            .content("<b>not a page</b>")
+            .insert(&state.pool)
+            .await;
+        let app = build_router(state.clone());
+        let views = || async {
+            sqlx::query_scalar::<_, i64>("SELECT views FROM pastes WHERE token = ?")
+                .bind(&page.token)
+                .fetch_one(&state.pool)
+                .await
+                .unwrap()
+        };
+        let rendered = format!("/r/{}?render=1", page.token);
+
+        // Off by default: the parameter changes nothing.
+        let response = send(&app, get(&rendered)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(
+            !response.headers()[header::CONTENT_TYPE]
+                .to_str()
+                .unwrap()
+                .starts_with("text/html")
+        );
+        assert!(
+            !response
+                .headers()
+                .contains_key(header::CONTENT_SECURITY_POLICY)
+        );
+        assert_eq!(views().await, 1);
+
+        update_config(&state, |config| config.paste.allow_html_rendering = true);
+        let response = send(&app, get(&rendered)).await;
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "text/html; charset=utf-8"
+        );
+        let csp = response.headers()[header::CONTENT_SECURITY_POLICY]
+            .to_str()
+            .unwrap();
+        assert!(csp.contains("default-src 'none'") && csp.ends_with("sandbox"));
+        assert!(!csp.contains("script-src"));
+        assert!(body_string(response).await.contains("<h1>Hi</h1>"));
+        assert_eq!(views().await, 2);
+        let head = Request::head(&rendered).body(Body::empty()).unwrap();
+        let response = send(&app, head).await;
+        assert!(
+            response
+                .headers()
+                .contains_key(header::CONTENT_SECURITY_POLICY)
+        );
+        assert_eq!(views().await, 2);
+
+        // Without the parameter, or for other languages, it stays text.
+        let response = send(&app, get(&format!("/r/{}", page.token))).await;
+        assert!(
+            !response
+                .headers()
+                .contains_key(header::CONTENT_SECURITY_POLICY)
+        );
+        let response = send(&app, get(&format!("/r/{}?render=1", text.token))).await;
+        assert!(
+            !response
+                .headers()
+                .contains_key(header::CONTENT_SECURITY_POLICY)
+        );
+
+        // The last allowed view burns it, rendered or not.
+        assert_eq!(send(&app, get(&rendered)).await.status(), StatusCode::OK);
+        assert_eq!(
+            send(&app, get(&rendered)).await.status(),
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[tokio::test]
+    async fn test_too_long_content_gets_a_card_with_its_size() {
+        let state = test_state().await;
+        update_config(&state, |config| config.paste.max_content_length = 10);
+        let app = build_router(state);
+        let create = |body: &str, lang: &str, htmx: bool| {
+            let mut request = form_post("/paste", body);
+            let headers = request.headers_mut();
+            headers.insert(header::ACCEPT_LANGUAGE, lang.parse().unwrap());
+            if htmx {
+                headers.insert("hx-request", "true".parse().unwrap());
+            }
+            request
+        };
+        // 13 characters, 15 bytes.
+        let accented = "content=h%C3%A9llo+w%C3%B6rld%21%21";
+
+        let response = send(&app, create(accented, "en", true)).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let card = body_string(response).await;
+        assert!(
+            card.starts_with(r#"<div class="result-card result-error""#),
+            "{}",
+            card
+        );
+        assert!(card.contains("You sent 13 characters (15 B as received)."));
+        assert!(card.contains("The limit is 10 characters."));
+        assert!(card.contains("not bytes"));
+        assert!(card.contains("Go back and trim it"));
+
+        let response = send(&app, create(accented, "zh", false)).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let page = body_string(response).await;
+        assert!(page.starts_with("<!doctype html>"));
+        assert!(page.contains("你提交了 13 个字符（服务器收到 15 B）。"));
+        assert!(page.contains("上限为 10 个字符。"));
+        assert!(page.contains(r#"class="result-card result-error""#));
+        assert!(page.contains(">返回</a>"));
+
+        // Plain ASCII has as many bytes as characters, so no hint.
+        let response = send(&app, create("content=abcdefghijkl", "zh", true)).await;
+        let card = body_string(response).await;
+        assert!(card.contains("你提交了 12 个字符（服务器收到 12 B）。"));
+        assert!(!card.contains("too-long-hint"));
+
+        // A body refused unread reports only what it declared.
+        let mut request = create("content=a", "en", true);
+        request
+            .headers_mut()
+            .insert(header::CONTENT_LENGTH, (2 * 1024 * 1024).into());
+        let response = send(&app, request).await;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let card = body_string(response).await;
+        assert!(
+            card.contains("The form you sent was at least 2.0 MiB"),
+            "{}",
+            card
+        );
+        let mut request = create("content=a", "zh", false);
+        request
+            .headers_mut()
+            .insert(header::CONTENT_LENGTH, (2 * 1024 * 1024).into());
+        let page = body_string(send(&app, request).await).await;
+        assert!(page.contains("提交的表单至少有 2.0 MiB"));
+        assert!(page.contains("上限为 10 个字符。"));
+    }
+
+    #[tokio::test]
+    async fn test_limits_track_the_config() {
+        let state = test_state().await;
+        update_config(&state, |config| {
+            config.paste.max_content_length = 500;
+            config.paste.expires_options_secs = vec![600, 7200];
+            config.paste.default_expires_secs = 600;
+            config.paste.token_lengths = vec![12, 6];
+            config.paste.default_token_length = 8;
+            config.paste.allow_author = false;
+            config.paste.max_pastes = 10;
+            config.languages.truncate(2);
+        });
+        PasteBuilder::new()
+            .content("hello")
+            .insert(&state.pool)
+            .await;
+        let app = build_router(state.clone());
+        let limits = || async {
+            let body = body_string(send(&app, get("/api/v1/limits")).await).await;
+            serde_json::from_str::<serde_json::Value>(&body).unwrap()
+        };
+
+        let json = limits().await;
+        assert_eq!(json["max_content_length"], 500);
+        assert_eq!(json["max_author_length"], 0);
+        assert_eq!(json["expiry"]["options"], serde_json::json!([600, 7200]));
+        assert_eq!(json["expiry"]["max_secs"], 7200);
+        assert_eq!(json["tokens"]["lengths"], serde_json::json!([6, 8, 12]));
+        // Timers longer than the longest expiry are not offered.
+        assert_eq!(
+            json["destroy_after_options"],
+            serde_json::json!([60, 600, 3600])
+        );
+        let config = state.config.load();
+        let languages: Vec<_> = std::iter::once("auto")
+            .chain(
+                config
+                    .languages
+                    .iter()
+                    .map(|language| language.value.as_str()),
+            )
+            .collect();
+        assert_eq!(json["languages"], serde_json::json!(languages));
+        assert_eq!(json["features"]["author"], false);
+        assert_eq!(json["features"]["never_expire"], false);
+        assert_eq!(json["headroom"]["pastes"], 9);
+        assert_eq!(json["capacity"]["pastes"], 1);
+
+        // The count is cached briefly; the config is not.
+        PasteBuilder::new().insert(&state.pool).await;
+        update_config(&state, |config| config.paste.allow_author = true);
+        let json = limits().await;
+        assert_eq!(json["headroom"]["pastes"], 9);
+        assert_eq!(json["features"]["author"], true);
+        assert_eq!(json["max_author_length"], 32);
+    }
+
+    #[tokio::test]
+    async fn test_admin_maintenance_lists_what_was_deleted() {
+        let state = admin_state().await;
+        update_config(&state, |config| config.paste.max_pastes = 2);
+        PasteBuilder::new()
+            .token("stale")
+            .expires_in(-10)
+            .insert(&state.pool)
+            .await;
+        PasteBuilder::new()
+            .token("soon")
+            .content("12345")
+            .expires_in(100)
+            .insert(&state.pool)
+            .await;
+        PasteBuilder::new()
+            .token("later")
+            .expires_in(1000)
+            .insert(&state.pool)
+            .await;
+        let app = build_router(state);
+        let admin = Request::get("/admin/api/maintenance")
+            .header(header::AUTHORIZATION, "Bearer sesame")
+            .body(Body::empty())
+            .unwrap();
+
+        let response = send(&app, get("/admin/api/maintenance")).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = send(&app, form_post("/paste", "content=newcomer")).await;
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        let runs: serde_json::Value =
+            serde_json::from_str(&body_string(send(&app, admin).await).await).unwrap();
+        assert_eq!(runs.as_array().unwrap().len(), 2);
+        assert_eq!(runs[0]["reason"], "max_pastes");
+        assert_eq!(runs[0]["policy"], "soonest_expiring");
+        assert_eq!(runs[0]["deleted"], 1);
+        assert_eq!(runs[0]["tokens"], serde_json::json!(["soon"]));
+        assert_eq!(runs[0]["reclaimed_length"], 5);
+        assert_eq!(runs[1]["reason"], "expired");
+        assert_eq!(runs[1]["tokens"], serde_json::json!(["stale"]));
+        assert!(runs[1]["policy"].is_null());
+    }
+}
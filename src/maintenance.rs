@@ -0,0 +1,79 @@
+//! The latest cleanup and eviction passes that deleted anything, kept in
+//! memory so an operator asked where a paste went can look it up through
+//! `GET /admin/api/maintenance`. Reset on restart.
+
+use crate::models::{MaintenanceRun, Sweep};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+/// Runs kept; older ones are dropped.
+pub const MAINTENANCE_RUNS: usize = 50;
+
+/// Tokens kept per run. `deleted` still counts every paste.
+pub const MAINTENANCE_TOKENS: usize = 100;
+
+#[derive(Default)]
+pub struct MaintenanceLog {
+    runs: Mutex<VecDeque<MaintenanceRun>>,
+}
+
+impl MaintenanceLog {
+    /// Keep `sweep`, run at `at`, unless it deleted nothing.
+    pub fn record(&self, at: i64, mut sweep: Sweep) {
+        if sweep.deleted == 0 {
+            return;
+        }
+        let tokens_truncated = sweep.tokens.len() > MAINTENANCE_TOKENS;
+        sweep.tokens.truncate(MAINTENANCE_TOKENS);
+        let mut runs = self.runs.lock().unwrap();
+        if runs.len() == MAINTENANCE_RUNS {
+            runs.pop_front();
+        }
+        runs.push_back(MaintenanceRun {
+            at,
+            sweep,
+            tokens_truncated,
+        });
+    }
+
+    /// The runs kept, newest first.
+    pub fn recent(&self) -> Vec<MaintenanceRun> {
+        self.runs.lock().unwrap().iter().rev().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::EvictionPolicy;
+
+    fn sweep(count: usize) -> Sweep {
+        let removed = (0..count).map(|i| (format!("t{}", i), 10)).collect();
+        Sweep::new("max_pastes", Some(EvictionPolicy::OldestCreated), removed)
+    }
+
+    #[test]
+    fn test_runs_and_their_tokens_are_capped() {
+        let log = MaintenanceLog::default();
+        log.record(1, sweep(0));
+        assert!(log.recent().is_empty());
+
+        log.record(2, sweep(MAINTENANCE_TOKENS + 5));
+        let run = &log.recent()[0];
+        assert_eq!(run.sweep.deleted, MAINTENANCE_TOKENS as u64 + 5);
+        assert_eq!(
+            run.sweep.reclaimed_length,
+            (MAINTENANCE_TOKENS as i64 + 5) * 10
+        );
+        assert_eq!(run.sweep.tokens.len(), MAINTENANCE_TOKENS);
+        assert!(run.tokens_truncated);
+
+        for at in 3..3 + MAINTENANCE_RUNS as i64 {
+            log.record(at, sweep(1));
+        }
+        let runs = log.recent();
+        assert_eq!(runs.len(), MAINTENANCE_RUNS);
+        assert_eq!(runs[0].at, 2 + MAINTENANCE_RUNS as i64);
+        assert!(runs.iter().all(|run| !run.tokens_truncated));
+    }
+}
@@ -0,0 +1,78 @@
+//! Markdown to HTML for server-rendered pages.
+
+use pulldown_cmark::{CowStr, Event, Options, Parser, Tag, html};
+
+/// Render `source` to HTML that is safe to embed without further escaping:
+/// raw HTML in the source is shown as text and links or images with
+/// scriptable URLs are neutralised.
+pub fn render(source: &str) -> String {
+    let options = Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH;
+    let events = Parser::new_ext(source, options).map(|event| match event {
+        Event::Html(raw) | Event::InlineHtml(raw) => Event::Text(raw),
+        Event::Start(Tag::Link {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) => Event::Start(Tag::Link {
+            link_type,
+            dest_url: safe_url(dest_url),
+            title,
+            id,
+        }),
+        Event::Start(Tag::Image {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) => Event::Start(Tag::Image {
+            link_type,
+            dest_url: safe_url(dest_url),
+            title,
+            id,
+        }),
+        event => event,
+    });
+    let mut output = String::new();
+    html::push_html(&mut output, events);
+    output
+}
+
+/// Keep relative URLs and the schemes a page can link to; anything else
+/// (`javascript:`, `data:`, ...) becomes an inert `#`.
+fn safe_url(url: CowStr<'_>) -> CowStr<'_> {
+    let scheme = url
+        .split_once(':')
+        .map(|(scheme, _)| scheme)
+        .filter(|scheme| !scheme.contains(['/', '?', '#']));
+    match scheme {
+        None => url,
+        Some(scheme)
+            if ["http", "https", "mailto"]
+                .iter()
+                .any(|allowed| scheme.eq_ignore_ascii_case(allowed)) =>
+        {
+            url
+        }
+        Some(_) => CowStr::Borrowed("#"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_is_sanitized() {
+        let html = render(
+            "# Hi\n\n<script>alert(1)</script>\n\n[a](javascript:alert(1)) [b](https://example.com) [c](/p/abc)",
+        );
+        assert!(html.contains("<h1>Hi</h1>"));
+        assert!(!html.contains("<script>"));
+        assert!(html.contains("&lt;script&gt;"));
+        assert!(html.contains(r##"<a href="#">a</a>"##));
+        assert!(html.contains(r#"<a href="https://example.com">b</a>"#));
+        assert!(html.contains(r#"<a href="/p/abc">c</a>"#));
+        assert!(!render("<img src=x onerror=alert(1)>").contains("<img"));
+    }
+}
@@ -0,0 +1,107 @@
+//! Process-wide counters, reset on restart.
+
+use crate::models::{BackupStatus, EvictionPolicy, WebhookStatus};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering};
+
+#[derive(Default)]
+pub struct Metrics {
+    evictions: AtomicU64,
+    /// Indexed by `EvictionPolicy as usize`.
+    policy_evictions: [AtomicU64; EvictionPolicy::ALL.len()],
+    backup_failures: AtomicU64,
+    last_backup_failed: AtomicBool,
+    /// Unix time of the last successful backup, 0 if none yet.
+    last_backup_at: AtomicI64,
+    webhooks_delivered: AtomicU64,
+    webhooks_failed: AtomicU64,
+    webhooks_dead_lettered: AtomicU64,
+}
+
+impl Metrics {
+    /// Record pastes removed before their natural expiry.
+    pub fn record_evictions(&self, count: u64) {
+        self.evictions.fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Record pastes evicted by a size limit under `policy`.
+    pub fn record_policy_evictions(&self, policy: EvictionPolicy, count: u64) {
+        self.record_evictions(count);
+        self.policy_evictions[policy as usize].fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub fn evictions(&self) -> u64 {
+        self.evictions.load(Ordering::Relaxed)
+    }
+
+    pub fn evictions_by_policy(&self) -> BTreeMap<&'static str, u64> {
+        EvictionPolicy::ALL
+            .iter()
+            .map(|policy| {
+                let count = self.policy_evictions[*policy as usize].load(Ordering::Relaxed);
+                (policy.name(), count)
+            })
+            .collect()
+    }
+
+    pub fn record_backup(&self, succeeded: bool, now: i64) {
+        self.last_backup_failed.store(!succeeded, Ordering::Relaxed);
+        if succeeded {
+            self.last_backup_at.store(now, Ordering::Relaxed);
+        } else {
+            self.backup_failures.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn backup_status(&self) -> BackupStatus {
+        let last_success_at = self.last_backup_at.load(Ordering::Relaxed);
+        BackupStatus {
+            last_success_at: (last_success_at > 0).then_some(last_success_at),
+            last_failed: self.last_backup_failed.load(Ordering::Relaxed),
+            failures: self.backup_failures.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Record one delivery attempt; `dead_lettered` when it was the last.
+    pub fn record_webhook(&self, delivered: bool, dead_lettered: bool) {
+        if delivered {
+            self.webhooks_delivered.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.webhooks_failed.fetch_add(1, Ordering::Relaxed);
+        }
+        if dead_lettered {
+            self.webhooks_dead_lettered.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn webhook_status(&self) -> WebhookStatus {
+        WebhookStatus {
+            delivered: self.webhooks_delivered.load(Ordering::Relaxed),
+            failed: self.webhooks_failed.load(Ordering::Relaxed),
+            dead_lettered: self.webhooks_dead_lettered.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Writes retried because SQLite reported the database busy or locked.
+/// Kept outside `Metrics` since `db` has no `AppState` to reach it through.
+static BUSY_RETRIES: AtomicU64 = AtomicU64::new(0);
+
+pub fn record_busy_retry() {
+    BUSY_RETRIES.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn busy_retries() -> u64 {
+    BUSY_RETRIES.load(Ordering::Relaxed)
+}
+
+/// Generated tokens drawn again because another paste already had them.
+static TOKEN_COLLISIONS: AtomicU64 = AtomicU64::new(0);
+
+pub fn record_token_collision() {
+    TOKEN_COLLISIONS.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn token_collisions() -> u64 {
+    TOKEN_COLLISIONS.load(Ordering::Relaxed)
+}
@@ -1,6 +1,22 @@
+use crate::activity::ActivityCache;
+use crate::assets::filters;
+use crate::captcha::CaptchaVerifier;
+use crate::counts::{PublicCountCache, UsageCache};
+use crate::crypto::ContentCipher;
+use crate::featured::FeaturedCache;
+use crate::maintenance::MaintenanceLog;
+use crate::metrics::Metrics;
+use crate::paste_cache::PasteCache;
+use crate::rate_limit::RateLimiter;
+use crate::receipts::SpentReceipts;
+use crate::utils::{Clock, token_occupancy};
+use arc_swap::ArcSwap;
 use askama::Template;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use utoipa::ToSchema;
 
 #[derive(Clone, FromRow)]
 pub struct Paste {
@@ -13,59 +29,1251 @@ pub struct Paste {
     pub max_views: Option<i64>,
     pub is_public: bool,
     pub original_duration: i64,
+    pub is_encrypted: bool,
+    pub nonce: Option<String>,
+    pub is_e2e: bool,
+    pub owner_id: Option<String>,
+    /// What `auto` was detected as on creation.
+    pub detected_language: Option<String>,
+    /// Times the expiry was extended for popularity.
+    pub auto_renewals: i64,
+    /// Fade this long after the first view.
+    pub destroy_after_first_view_secs: Option<i64>,
+    /// When that timer was armed.
+    pub first_viewed_at: Option<i64>,
+    /// Only its owner can reach the paste before this.
+    pub visible_after: Option<i64>,
+    /// Nickname the paste was signed with.
+    pub author: Option<String>,
+    /// Token of the paste this one was forked from; cleared for the page
+    /// once that paste is gone.
+    pub forked_from: Option<String>,
+    /// Name of the first file; always set once there are more.
+    pub filename: Option<String>,
+    /// Whether a JSON or YAML paste parsed on creation; `None` when it was
+    /// not checked.
+    pub syntax_valid: Option<bool>,
+    /// The parser's message and line where it first failed.
+    pub syntax_error: Option<String>,
+    pub syntax_error_line: Option<i64>,
+    /// SHA-256 of the content; `None` until backfilled.
+    pub content_hash: Option<String>,
+    /// The files after the first, decrypted.
+    #[sqlx(skip)]
+    pub files: Vec<PasteFile>,
+    /// Pastes its owner linked to as related.
+    #[sqlx(skip)]
+    pub related: Vec<RelatedLink>,
+}
+
+/// How much text a paste holds, from `text_stats::content_stats`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, ToSchema)]
+pub struct ContentStats {
+    pub lines: i64,
+    /// CJK characters count a word each.
+    pub words: i64,
+    pub chars: i64,
+    pub bytes: i64,
+}
+
+impl Paste {
+    /// Created ahead of a `visible_after` that has not come yet.
+    pub fn is_scheduled(&self, now: i64) -> bool {
+        self.visible_after.is_some_and(|ts| ts > now)
+    }
+
+    /// Whether explore lists it: public, live, readable and without a
+    /// view limit.
+    pub fn is_listed(&self, now: i64) -> bool {
+        self.is_public
+            && self.max_views.is_none()
+            && !self.is_e2e
+            && self.expires_at > now
+            && !self.is_scheduled(now)
+    }
+
+    /// Whether viewing it spends something: a view limit or the first view
+    /// arming a self-destruct timer.
+    pub fn burns(&self) -> bool {
+        self.max_views.is_some() || self.destroy_after_first_view_secs.is_some()
+    }
+}
+
+/// A file of a multi-file paste after the first, which is the paste's own
+/// content.
+#[derive(Clone, Debug, FromRow)]
+pub struct PasteFile {
+    pub filename: String,
+    /// A language `auto` was detected as when the file was stored.
+    pub language: String,
+    pub content: String,
+    pub nonce: Option<String>,
+}
+
+/// A further file to store with a new paste.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NewFile {
+    pub filename: String,
+    pub language: String,
+    pub content: String,
+}
+
+/// A paste's counters right after a view was counted.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ViewCount {
+    pub views: i64,
+    pub expires_at: i64,
+    pub auto_renewals: i64,
+    pub first_viewed_at: Option<i64>,
+}
+
+/// A view the paste cache counted, waiting to be written.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PendingView {
+    pub client_hash: String,
+    /// A fetch of `/r/` rather than of the paste page.
+    pub raw: bool,
+    pub at: i64,
+}
+
+/// Column values for a paste about to be inserted; the token is generated.
+#[derive(Clone, Default)]
+pub struct NewPaste {
+    pub title: String,
+    pub content: String,
+    pub expires_at: i64,
+    pub original_duration: i64,
+    pub token_length: usize,
+    /// Custom slug to use as the token instead of a generated one.
+    pub slug: Option<String>,
+    pub language: String,
+    pub max_views: Option<i64>,
+    pub is_public: bool,
+    pub is_e2e: bool,
+    pub owner_id: Option<String>,
+    pub destroy_after_first_view_secs: Option<i64>,
+    pub visible_after: Option<i64>,
+    pub author: Option<String>,
+    pub forked_from: Option<String>,
+    /// Count a token differing only in case as taken, for
+    /// `case_insensitive_tokens`.
+    pub case_insensitive_token: bool,
+    /// Name of the first file, the paste's own content.
+    pub filename: Option<String>,
+    /// Files after the first.
+    pub files: Vec<NewFile>,
+    /// Keyed hash of the creator's address, for the event log.
+    pub client_hash: Option<String>,
+}
+
+/// A paste as the create response describes it: one just stored, or the
+/// one `db::find_repeat` found for a repeated submission.
+#[derive(Clone, Debug, FromRow)]
+pub struct SubmittedPaste {
+    pub token: String,
+    pub manage_token: String,
+    pub expires_at: i64,
+    pub language: String,
+    pub max_views: Option<i64>,
+}
+
+/// The tokens `create_paste_checked` generated: the public one and the secret one
+/// that opens `/m/{manage_token}`.
+#[derive(Clone, Debug)]
+pub struct CreatedPaste {
+    pub token: String,
+    pub manage_token: String,
+}
+
+/// What the management page shows about a paste.
+#[derive(Clone, FromRow)]
+pub struct ManagedPaste {
+    pub token: String,
+    pub title: String,
+    pub created_at: i64,
+    pub expires_at: i64,
+    pub views: i64,
+    pub max_views: Option<i64>,
+    pub is_public: bool,
+    /// Unpublished after reports; the owner cannot publish it again.
+    pub flagged: bool,
+    pub destroy_after_first_view_secs: Option<i64>,
+    pub visible_after: Option<i64>,
 }
 
 #[derive(Clone, FromRow)]
 pub struct RawPaste {
     pub content: String,
+    pub is_encrypted: bool,
+    pub nonce: Option<String>,
+    pub is_e2e: bool,
+    pub content_hash: Option<String>,
+    pub language: String,
+    pub detected_language: Option<String>,
+    pub filename: Option<String>,
+    pub expires_at: i64,
+    pub max_views: Option<i64>,
+    pub destroy_after_first_view_secs: Option<i64>,
+}
+
+impl RawPaste {
+    /// As `Paste::burns`.
+    pub fn burns(&self) -> bool {
+        self.max_views.is_some() || self.destroy_after_first_view_secs.is_some()
+    }
+}
+
+/// Everything that may be disclosed about a paste without serving its content.
+#[derive(Clone, FromRow, Serialize, ToSchema)]
+pub struct PasteMeta {
+    pub title: String,
+    pub language: String,
+    pub created_at: i64,
+    pub expires_at: i64,
     pub views: i64,
     pub max_views: Option<i64>,
+    pub is_public: bool,
+    pub is_e2e: bool,
+    pub content_length: i64,
+    pub content_hash: Option<String>,
+    pub original_duration: i64,
+    #[sqlx(skip)]
+    #[serde(flatten)]
+    pub renewal: Renewal,
+    pub destroy_after_first_view_secs: Option<i64>,
+    /// When the paste fades, once its first view armed the timer.
+    pub destroys_at: Option<i64>,
+    /// The nickname of a public paste; never shown for private ones.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    /// For `HEAD /r/{token}`, which picks the content type as `GET` does.
+    #[serde(skip)]
+    pub detected_language: Option<String>,
+    /// For `HEAD /p/{token}`, which tells the creator's preview apart.
+    #[serde(skip)]
+    pub owner_id: Option<String>,
+    /// For `HEAD /p/{token}`, where only the creator sees a scheduled paste.
+    #[serde(skip)]
+    pub visible_after: Option<i64>,
+    #[serde(skip)]
+    pub filename: Option<String>,
+    #[sqlx(skip)]
+    pub created: DisplayTime,
+    /// The locale `created` and `language_label` are in.
+    #[sqlx(skip)]
+    pub lang: String,
+    #[sqlx(skip)]
+    pub language_label: String,
+    /// Pastes its owner linked to as related.
+    #[sqlx(skip)]
+    pub related: Vec<RelatedLink>,
+    /// Counts over all its files; `None` for a client-side encrypted paste,
+    /// whose content the server cannot read.
+    #[sqlx(skip)]
+    pub stats: Option<ContentStats>,
+}
+
+/// A paste another links to as related, by the token it was given.
+#[derive(Clone, Debug, Deserialize, FromRow, PartialEq, Serialize, ToSchema)]
+pub struct RelatedLink {
+    pub token: String,
+    /// `None` once the paste has expired or is gone; the page shows a
+    /// tombstone in its place.
+    pub title: Option<String>,
+}
+
+/// A paste explore lists, as a link to it.
+#[derive(Clone, Debug, Deserialize, FromRow, PartialEq, Serialize, ToSchema)]
+pub struct Neighbor {
+    pub token: String,
+    pub title: String,
+}
+
+/// A paste explore lists, read without its content, for the index's
+/// latest list and the archive.
+#[derive(Clone, Debug, Deserialize, FromRow, Serialize, ToSchema)]
+pub struct ListedPaste {
+    pub token: String,
+    pub title: String,
+    pub language: String,
+    pub created_at: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    #[sqlx(skip)]
+    pub language_label: String,
+    /// Local `HH:MM`, for the archive page.
+    #[sqlx(skip)]
+    #[serde(skip)]
+    pub time: String,
+}
+
+/// One local day of the archive, newest paste first.
+#[derive(Deserialize, Serialize, ToSchema)]
+pub struct ArchiveDay {
+    /// `YYYY-MM-DD` in `display_timezone`.
+    pub date: String,
+    pub count: i64,
+    pub pastes: Vec<ListedPaste>,
+}
+
+/// A run of changed lines and the context around them in a diff.
+pub struct DiffHunk {
+    /// `@@ -1,3 +1,4 @@`.
+    pub header: String,
+    pub lines: Vec<DiffLine>,
+}
+
+pub struct DiffLine {
+    /// `added`, `removed` or `context`, also the line's CSS class.
+    pub kind: &'static str,
+    /// `+`, `-` or a space, as in the patch.
+    pub sign: String,
+    pub text: String,
+}
+
+/// One row of the latest list, as the index shows it.
+pub struct RecentItem {
+    pub token: String,
+    pub title: String,
+    pub language_label: String,
+    /// "3 minutes ago".
+    pub age: String,
+}
+
+/// One emoji under a listed paste and how many left it.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize, ToSchema)]
+pub struct Reaction {
+    pub emoji: String,
+    pub count: i64,
+    /// Whether this client's reaction is among them.
+    pub reacted: bool,
+}
+
+/// The reaction buttons under a listed paste, also swapped in by htmx
+/// after a click.
+#[derive(Template)]
+#[template(path = "reactions.html")]
+pub struct ReactionsTemplate {
+    pub strings: Strings,
+    pub token: String,
+    pub reactions: Vec<Reaction>,
+}
+
+/// What the create form shows next to the slug field as the creator types.
+#[derive(Template)]
+#[template(path = "slug_status.html")]
+pub struct SlugStatusTemplate {
+    pub available: bool,
+    pub message: String,
+}
+
+/// The pastes explore lists on either side of one, in creation order;
+/// `None` at either end.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Neighbors {
+    /// Created earlier.
+    pub prev: Option<Neighbor>,
+    /// Created later.
+    pub next: Option<Neighbor>,
+}
+
+/// A timestamp as shown to people: relative ("3 minutes ago") and absolute
+/// in `server.display_timezone`.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize, ToSchema)]
+pub struct DisplayTime {
+    pub relative: String,
+    pub iso: String,
+}
+
+/// A paste with open reports, as listed for admins.
+#[derive(Clone, Serialize)]
+pub struct ReportedPaste {
+    pub token: String,
+    pub title: String,
+    pub flagged: bool,
+    pub pinned: bool,
+    pub reports: i64,
+    pub reasons: Vec<String>,
+}
+
+#[derive(Clone, FromRow, Serialize)]
+pub struct BannedIp {
+    pub id: i64,
+    pub cidr: String,
+    pub reason: Option<String>,
+    pub created_at: i64,
+    pub expires_at: Option<i64>,
+}
+
+/// What happened to a paste, as recorded in the `events` table.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EventType {
+    Created,
+    Viewed,
+    /// Removed by its owner, an admin or a purge.
+    Deleted,
+    Expired,
+    /// Removed to make room under `max_pastes` or `max_total_content_length`.
+    Evicted,
+    /// Deleted by the view that used up its `max_views`.
+    Burned,
+}
+
+impl EventType {
+    pub fn name(self) -> &'static str {
+        match self {
+            EventType::Created => "created",
+            EventType::Viewed => "viewed",
+            EventType::Deleted => "deleted",
+            EventType::Expired => "expired",
+            EventType::Evicted => "evicted",
+            EventType::Burned => "burned",
+        }
+    }
+}
+
+/// One row of the event log.
+#[derive(Clone, Debug, FromRow, PartialEq, Serialize)]
+pub struct Event {
+    pub event_type: String,
+    pub token: String,
+    pub created_at: i64,
+    /// Keyed hash of the client address, when a client caused the event.
+    pub client_hash: Option<String>,
+}
+
+/// Views of one paste per hour, for `/m/{manage_token}/views`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ViewTimeline {
+    pub token: String,
+    pub created_at: i64,
+    /// When it was deleted, by its views running out or otherwise.
+    pub deleted_at: Option<i64>,
+    /// Oldest first, one entry per hour up to now or its deletion.
+    pub hours: Vec<HourlyViews>,
+    /// Totals over `hours`.
+    pub page_views: i64,
+    pub raw_views: i64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct HourlyViews {
+    /// Unix time the hour starts at.
+    pub at: i64,
+    pub page: i64,
+    pub raw: i64,
+}
+
+/// Activity on one day in the display timezone, for `/api/v1/stats/daily`.
+#[derive(Clone, Debug, PartialEq, Serialize, ToSchema)]
+pub struct DailyActivity {
+    pub date: String,
+    pub created: i64,
+    pub expired: i64,
+    pub views: i64,
+    pub burns: i64,
+    pub evictions: i64,
+    /// Content created that day, in bytes, before any encryption at rest.
+    pub bytes_added: i64,
+}
+
+/// Counts behind one `DailyActivity`, for the local day numbered `day`
+/// from the epoch. Rolled up into `daily_stats` once the day is over.
+#[derive(Clone, Copy, Debug, Default, FromRow, PartialEq)]
+pub struct DayCounts {
+    pub day: i64,
+    pub created: i64,
+    pub expired: i64,
+    pub views: i64,
+    pub burns: i64,
+    pub evictions: i64,
+    pub bytes_added: i64,
+}
+
+/// Pastes created in `language` on the local day numbered `day`, as
+/// logged or rolled up.
+#[derive(Clone, Debug, FromRow, PartialEq)]
+pub struct DayLanguageCount {
+    pub day: i64,
+    pub language: String,
+    pub created: i64,
+}
+
+/// Pastes created per language on one day in the display timezone, for
+/// `/api/v1/stats/languages`.
+#[derive(Clone, Debug, PartialEq, Serialize, ToSchema)]
+pub struct DailyLanguages {
+    pub date: String,
+    /// Today, whose counts are still growing.
+    pub partial: bool,
+    pub counts: BTreeMap<String, i64>,
+}
+
+/// One bar of the index sparkline.
+pub struct ActivityBar {
+    pub date: String,
+    pub created: i64,
+    /// In pixels, scaled to the busiest day shown.
+    pub height: i64,
+}
+
+/// The paste of the day, as the index shows it.
+pub struct FeaturedPaste {
+    pub token: String,
+    pub title: String,
+    pub language_label: String,
+    pub preview: String,
+    /// Whether `preview` stops short of the content.
+    pub truncated: bool,
+}
+
+/// Events of each type on one UTC day (`YYYY-MM-DD`).
+#[derive(Clone, Debug, FromRow, PartialEq, Serialize, ToSchema)]
+pub struct DailyEvents {
+    pub day: String,
+    pub created: i64,
+    pub viewed: i64,
+    pub deleted: i64,
+    pub expired: i64,
+    pub evicted: i64,
+}
+
+/// How often a paste was viewed on one UTC day (`YYYY-MM-DD`).
+#[derive(Clone, Debug, FromRow, PartialEq, Serialize)]
+pub struct DailyViews {
+    pub day: String,
+    pub count: i64,
+}
+
+/// A named link to a paste with its own view budget.
+#[derive(Clone, FromRow, Serialize)]
+pub struct ShareLink {
+    pub share_token: String,
+    pub label: String,
+    pub max_views: Option<i64>,
+    pub views: i64,
+    pub created_at: i64,
 }
 
 #[derive(Clone, FromRow)]
 pub struct PublicPaste {
     pub token: String,
     pub title: String,
-    pub content: String,
+    pub preview: String,
+    pub content_length: i64,
+    pub truncated: bool,
     pub created_at: i64,
     pub expires_at: i64,
     pub language: String,
     pub original_duration: i64,
+    pub is_encrypted: bool,
+    pub nonce: Option<String>,
+    pub pinned: bool,
+    pub author: Option<String>,
+    /// Not self-destructing; listed pastes are never view-limited.
+    pub renewable: bool,
+    /// "by ~nickname" for the explore card.
+    #[sqlx(skip)]
+    pub byline: Option<String>,
+    #[sqlx(skip)]
+    pub reactions: Vec<Reaction>,
+    #[sqlx(skip)]
+    pub created: DisplayTime,
+    #[sqlx(skip)]
+    pub life_stage: LifeStage,
+    /// Localized time left, e.g. "~ 3 hours".
+    #[sqlx(skip)]
+    pub remaining: String,
+}
+
+/// Whether the renew button works yet, from `handlers::renewal`.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize, ToSchema)]
+pub struct Renewal {
+    pub renew_eligible: bool,
+    /// When less than half the original lifetime is left and renewing
+    /// starts to work; `None` for pastes that can never be renewed.
+    pub renew_available_at: Option<i64>,
+}
+
+/// How much of its lifespan a paste has left, as the explore cards show it.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum LifeStage {
+    /// More than half left.
+    #[default]
+    Vibrant,
+    /// More than a quarter left.
+    Fading,
+    Dying,
+}
+
+impl LifeStage {
+    pub fn name(self) -> &'static str {
+        match self {
+            LifeStage::Vibrant => "vibrant",
+            LifeStage::Fading => "fading",
+            LifeStage::Dying => "dying",
+        }
+    }
+
+    pub fn label(self, strings: &Strings) -> &str {
+        match self {
+            LifeStage::Vibrant => &strings.life_vibrant,
+            LifeStage::Fading => &strings.life_fading,
+            LifeStage::Dying => &strings.life_dying,
+        }
+    }
 }
 
-#[derive(Clone, Deserialize)]
+#[derive(Clone, Deserialize, Serialize)]
 pub struct AppConfig {
     pub server: ServerConfig,
     pub paste: PasteConfig,
     pub i18n: I18nConfig,
+    /// Require a CAPTCHA to create pastes when present.
+    pub captcha: Option<CaptchaConfig>,
+    /// Site-wide banner shown on every page when present.
+    pub announcement: Option<AnnouncementConfig>,
+    /// Markdown files served at `/about`.
+    pub about: Option<AboutConfig>,
+    /// Periodic database snapshots when present.
+    pub backup: Option<BackupConfig>,
+    /// POSTs expired and burned pastes to a URL when present.
+    pub webhooks: Option<WebhookConfig>,
+    /// Languages offered besides `auto`, in menu order.
+    #[serde(default = "default_languages")]
+    pub languages: Vec<LanguageConfig>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct LanguageConfig {
+    /// Stored with the paste and handed to the highlighter.
+    pub value: String,
+    /// Used in the `/r/` download name.
+    #[serde(default = "default_language_extension")]
+    pub extension: String,
+    /// Served by `/r/`.
+    #[serde(default = "default_language_content_type")]
+    pub content_type: String,
+    /// Locale key holding the label, e.g. `language_rust`.
+    pub label_key: Option<String>,
+    /// Other names clients may send for this language, on top of the
+    /// built-in ones such as `js` or `yml`.
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    /// Labels given inline per locale code, e.g. `en = "TOML"`; they win
+    /// over `label_key`.
+    #[serde(flatten)]
+    pub labels: BTreeMap<String, String>,
+}
+
+impl LanguageConfig {
+    /// Inline label for the locale, else the `label_key` entry, else the
+    /// value itself.
+    pub fn label(&self, strings: &Strings) -> String {
+        self.labels
+            .get(&strings.lang)
+            .cloned()
+            .or_else(|| {
+                self.label_key
+                    .as_ref()
+                    .and_then(|key| strings.labels.get(key).cloned())
+            })
+            .unwrap_or_else(|| self.value.clone())
+    }
+}
+
+fn default_language_extension() -> String {
+    "txt".to_string()
+}
+
+fn default_language_content_type() -> String {
+    "text/plain; charset=utf-8".to_string()
+}
+
+fn default_languages() -> Vec<LanguageConfig> {
+    [
+        ("plaintext", "txt"),
+        ("rust", "rs"),
+        ("python", "py"),
+        ("javascript", "js"),
+        ("typescript", "ts"),
+        ("go", "go"),
+        ("java", "java"),
+        ("cpp", "cpp"),
+        ("html", "html"),
+        ("css", "css"),
+        ("json", "json"),
+        ("yaml", "yaml"),
+        ("sql", "sql"),
+        ("bash", "sh"),
+    ]
+    .into_iter()
+    .map(|(value, extension)| LanguageConfig {
+        value: value.to_string(),
+        extension: extension.to_string(),
+        content_type: default_language_content_type(),
+        label_key: Some(format!("language_{}", value)),
+        aliases: Vec::new(),
+        labels: BTreeMap::new(),
+    })
+    .collect()
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct BackupConfig {
+    /// Directory the snapshots are written to.
+    pub dir: String,
+    #[serde(default = "default_backup_interval_secs")]
+    pub interval_secs: u64,
+    /// Snapshots kept; older ones are deleted after each backup.
+    #[serde(default = "default_backup_keep")]
+    pub keep: usize,
+}
+
+fn default_backup_interval_secs() -> u64 {
+    86400
+}
+
+fn default_backup_keep() -> usize {
+    7
 }
 
-#[derive(Clone, Deserialize)]
+#[derive(Clone, Deserialize, Serialize)]
+pub struct WebhookConfig {
+    /// Receives a JSON `{event, token, at}` per expired or burned paste.
+    pub url: String,
+    /// Deliveries that fail this many times are dead-lettered until an
+    /// admin retries them.
+    #[serde(default = "default_webhook_max_attempts")]
+    pub max_attempts: i64,
+    /// Deliveries in flight at once.
+    #[serde(default = "default_webhook_concurrency")]
+    pub concurrency: usize,
+    /// Wait before the first retry; each further retry waits twice as long.
+    #[serde(default = "default_webhook_backoff_secs")]
+    pub backoff_secs: i64,
+    #[serde(default = "default_webhook_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+fn default_webhook_max_attempts() -> i64 {
+    8
+}
+
+fn default_webhook_concurrency() -> usize {
+    4
+}
+
+fn default_webhook_backoff_secs() -> i64 {
+    10
+}
+
+fn default_webhook_timeout_secs() -> u64 {
+    10
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct AboutConfig {
+    /// Used for any locale without its own file.
+    pub path: Option<String>,
+    /// A file per locale code, e.g. `zh = "config/about.zh.md"`.
+    #[serde(flatten)]
+    pub locales: BTreeMap<String, String>,
+}
+
+/// The `/about` page per locale code, already rendered to HTML. A locale
+/// whose file is missing has no page.
+#[derive(Clone, Default, PartialEq)]
+pub struct AboutPages {
+    pub pages: HashMap<String, String>,
+}
+
+impl AboutPages {
+    pub fn get(&self, lang: &str) -> Option<&str> {
+        self.pages.get(lang).map(String::as_str)
+    }
+}
+
+#[derive(Clone, Copy, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    #[default]
+    Info,
+    Warn,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct AnnouncementConfig {
+    #[serde(default)]
+    pub severity: Severity,
+    /// Unix timestamp after which the banner stops showing.
+    pub expires_at: Option<i64>,
+    /// The text per locale code, e.g. `en = "Maintenance tonight"`.
+    #[serde(flatten)]
+    pub texts: BTreeMap<String, String>,
+}
+
+/// What the page templates need to render the announcement banner.
+pub struct Announcement {
+    pub text: String,
+    pub class: &'static str,
+}
+
+impl AnnouncementConfig {
+    /// The banner for `lang`, falling back to the English text, or else
+    /// any text there is.
+    /// `None` once expired or when there is nothing to say.
+    pub fn for_lang(&self, lang: &str, now: i64) -> Option<Announcement> {
+        if self.expires_at.is_some_and(|expires_at| expires_at <= now) {
+            return None;
+        }
+        let text = self
+            .texts
+            .get(lang)
+            .or_else(|| self.texts.get("en"))
+            .or_else(|| self.texts.values().next())
+            .map(|text| text.trim())
+            .filter(|text| !text.is_empty())?;
+        let class = match self.severity {
+            Severity::Info => "announcement-info",
+            Severity::Warn => "announcement-warn",
+        };
+        Some(Announcement {
+            text: text.to_string(),
+            class,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CaptchaProvider {
+    Turnstile,
+    Hcaptcha,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct CaptchaConfig {
+    pub provider: CaptchaProvider,
+    pub site_key: String,
+    pub secret: String,
+    #[serde(default = "default_captcha_timeout_secs")]
+    pub timeout_secs: u64,
+    /// Let pastes through when the provider cannot be reached.
+    #[serde(default)]
+    pub fail_open: bool,
+}
+
+fn default_captcha_timeout_secs() -> u64 {
+    5
+}
+
+/// What the index page needs to render a CAPTCHA widget.
+pub struct CaptchaWidget {
+    pub script: &'static str,
+    pub class: &'static str,
+    pub site_key: String,
+}
+
+/// How full the instance is against its size limits, for the index and
+/// `/api/v1/stats`.
+#[derive(Clone, Debug, PartialEq, Serialize, ToSchema)]
+pub struct Capacity {
+    pub pastes: i64,
+    pub max_pastes: i64,
+    pub pastes_percent: i64,
+    pub content_length: i64,
+    pub max_content_length: i64,
+    pub content_percent: i64,
+}
+
+impl Capacity {
+    pub fn new(pastes: i64, content_length: i64, config: &PasteConfig) -> Self {
+        let percent = |used: i64, max: i64| if max > 0 { used * 100 / max } else { 100 };
+        Capacity {
+            pastes,
+            max_pastes: config.max_pastes,
+            pastes_percent: percent(pastes, config.max_pastes),
+            content_length,
+            max_content_length: config.max_total_content_length,
+            content_percent: percent(content_length, config.max_total_content_length),
+        }
+    }
+
+    /// The fuller of the two limits, which is the one evictions follow.
+    pub fn percent(&self) -> i64 {
+        self.pastes_percent.max(self.content_percent)
+    }
+}
+
+/// Share of the shortest tokens taken past which a new paste collides
+/// often enough to warn about.
+pub const TOKEN_SATURATION: f64 = 0.001;
+
+/// How crowded generated tokens get if `max_pastes` pastes all use the
+/// shortest length offered, for `/api/v1/stats` and the admin page.
+#[derive(Clone, Debug, PartialEq, Serialize, ToSchema)]
+pub struct TokenSpace {
+    /// The shortest of `token_lengths` and `default_token_length`.
+    pub min_token_length: usize,
+    /// `max_pastes` over the number of tokens of that length.
+    pub occupancy: f64,
+    /// `occupancy` is above `TOKEN_SATURATION`.
+    pub saturated: bool,
+    /// Generated tokens drawn again because they were taken, since startup.
+    pub collisions: u64,
+}
+
+impl TokenSpace {
+    pub fn new(config: &PasteConfig, collisions: u64) -> Self {
+        let min_token_length = config
+            .token_lengths
+            .iter()
+            .copied()
+            .chain([config.default_token_length])
+            .min()
+            .unwrap_or_default();
+        let occupancy = token_occupancy(config.max_pastes, min_token_length);
+        TokenSpace {
+            min_token_length,
+            occupancy,
+            saturated: occupancy > TOKEN_SATURATION,
+            collisions,
+        }
+    }
+}
+
+impl CaptchaConfig {
+    pub fn widget(&self) -> CaptchaWidget {
+        let (script, class) = match self.provider {
+            CaptchaProvider::Turnstile => (
+                "https://challenges.cloudflare.com/turnstile/v0/api.js",
+                "cf-turnstile",
+            ),
+            CaptchaProvider::Hcaptcha => ("https://js.hcaptcha.com/1/api.js", "h-captcha"),
+        };
+        CaptchaWidget {
+            script,
+            class,
+            site_key: self.site_key.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Deserialize, Serialize)]
 pub struct ServerConfig {
     pub host: String,
     pub port: u16,
+    /// Hex key for signing share links and owner cookies; if unset, one is
+    /// generated on first start and kept in the database.
+    pub signing_key: Option<String>,
+    /// Bearer token for `/admin/api/*`; those routes are disabled if unset.
+    pub admin_token: Option<String>,
+    /// Reverse proxies (addresses or CIDR ranges) whose `X-Forwarded-For`
+    /// is believed when working out the client address.
+    #[serde(default)]
+    pub trusted_proxies: Vec<String>,
+    /// Serve `/assets/` from this directory instead of the copy built into
+    /// the binary, so stylesheet edits show up without a rebuild.
+    pub assets_dir: Option<String>,
+    /// Fixed UTC offset (`+08:00`, `UTC`) absolute times are shown in.
+    #[serde(default = "default_display_timezone")]
+    pub display_timezone: String,
+    /// Public origin such as `https://paste.example.com`, for absolute
+    /// links in `Location` headers and API responses.
+    pub base_url: Option<String>,
+    /// Refuse every write outside `/admin` with 503 while pages still load.
+    #[serde(default)]
+    pub read_only: bool,
+    /// Answer everything outside `/admin` and `/assets` with the
+    /// maintenance page.
+    #[serde(default)]
+    pub maintenance: bool,
+    /// Log admin calls, bans, purges and refused requests as JSON lines
+    /// under `audit::TARGET`.
+    #[serde(default)]
+    pub log_security_events: bool,
+    /// Gzip or brotli responses for clients that accept it; off when a
+    /// proxy in front already compresses. Read once at startup.
+    #[serde(default = "default_compression")]
+    pub compression: bool,
 }
 
-#[derive(Clone, Deserialize)]
+fn default_display_timezone() -> String {
+    "UTC".to_string()
+}
+
+fn default_compression() -> bool {
+    true
+}
+
+#[derive(Clone, Deserialize, Serialize)]
 pub struct PasteConfig {
     pub db_path: String,
     pub default_expires_secs: i64,
     pub expires_options_secs: Vec<i64>,
     pub default_token_length: usize,
     pub token_lengths: Vec<usize>,
+    /// Treat tokens differing only in case as the same paste: `/p/ab3`
+    /// finds `Ab3`, and a new token or slug may not take another's letters
+    /// in a different case. Off, lookups compare bytes exactly.
+    #[serde(default)]
+    pub case_insensitive_tokens: bool,
     pub max_content_length: usize,
     pub max_total_content_length: i64,
     pub max_pastes: i64,
+    #[serde(default = "default_explore_preview_chars")]
+    pub explore_preview_chars: i64,
+    /// Share of its lifespan a public paste has left, below which
+    /// `/explore?expiring=1` lists it.
+    #[serde(default = "default_explore_expiring_fraction")]
+    pub explore_expiring_fraction: f64,
+    /// 32-byte hex key; when set, new paste content is encrypted at rest.
+    pub encryption_key: Option<String>,
+    /// Whether `/r/` counts against `max_views` for a viewer who already
+    /// spent a view on the detail page.
+    #[serde(default = "default_raw_counts_as_view")]
+    pub raw_counts_as_view: bool,
+    /// Let `/r/{token}?render=1` serve HTML pastes as pages, locked down
+    /// by a CSP without scripts. Off by default: it lets anyone host pages
+    /// on the instance's domain.
+    #[serde(default)]
+    pub allow_html_rendering: bool,
+    /// Distinct reports after which a public paste is unpublished for review.
+    #[serde(default = "default_report_threshold")]
+    pub report_threshold: i64,
+    /// Reports a single client may file per hour.
+    #[serde(default = "default_reports_per_hour")]
+    pub reports_per_hour: u32,
+    /// Reaction clicks a single client may make per minute.
+    #[serde(default = "default_reactions_per_minute")]
+    pub reactions_per_minute: u32,
+    #[serde(default)]
+    pub eviction_policy: EvictionPolicy,
+    /// Extend public pastes that draw many views; off when absent.
+    pub popularity: Option<PopularityConfig>,
+    /// Keep hot public pastes in memory and count their views in batches;
+    /// off when absent.
+    pub cache: Option<CacheConfig>,
+    /// Share of either size limit, in percent, above which the create form
+    /// warns that new pastes may be evicted early.
+    #[serde(default = "default_capacity_warning_percent")]
+    pub capacity_warning_percent: i64,
+    /// How long entries in the event log are kept.
+    #[serde(default = "default_event_retention_secs")]
+    pub event_retention_secs: i64,
+    /// A creator repeating the request for a paste they made this many
+    /// seconds ago or less gets that paste back instead of a copy; 0 turns
+    /// this off.
+    #[serde(default = "default_repeat_window_secs")]
+    pub repeat_window_secs: i64,
+    /// Largest `max_views` a paste may ask for; larger requests get this.
+    #[serde(default = "default_max_views_limit")]
+    pub max_views_limit: i64,
+    /// List the latest public pastes under the create form.
+    #[serde(default = "default_show_recent_on_index")]
+    pub show_recent_on_index: bool,
+    /// How many that list shows.
+    #[serde(default = "default_recent_on_index_count")]
+    pub recent_on_index_count: i64,
+    /// Put a QR code of the new paste's link on the result card.
+    #[serde(default)]
+    pub show_qr: bool,
+    /// Let pastes be signed with a nickname. When off, none is stored or
+    /// shown.
+    #[serde(default = "default_allow_author")]
+    pub allow_author: bool,
+    /// Most characters two pastes may hold together to be diffed.
+    #[serde(default = "default_max_diff_chars")]
+    pub max_diff_chars: usize,
+    /// Most related pastes one paste may link to; 0 turns linking off.
+    #[serde(default = "default_max_related_links")]
+    pub max_related_links: usize,
 }
 
-#[derive(Clone, Deserialize)]
+/// `[paste.popularity]`: every `views` views within its original lifespan
+/// push a public paste's expiry back by `bonus_secs`, at most
+/// `max_extensions` times and never past the longest expiry option.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct PopularityConfig {
+    pub views: i64,
+    pub bonus_secs: i64,
+    #[serde(default = "default_max_extensions")]
+    pub max_extensions: i64,
+}
+
+fn default_max_extensions() -> i64 {
+    3
+}
+
+/// `[paste.cache]`: public pastes without a view limit or self-destruct
+/// timer are kept in memory, at most `max_entries` of them holding
+/// `max_bytes` of content, and their views are written every
+/// `flush_secs`.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct CacheConfig {
+    #[serde(default = "default_cache_max_entries")]
+    pub max_entries: usize,
+    #[serde(default = "default_cache_max_bytes")]
+    pub max_bytes: usize,
+    #[serde(default = "default_cache_flush_secs")]
+    pub flush_secs: u64,
+}
+
+fn default_cache_max_entries() -> usize {
+    256
+}
+
+fn default_cache_max_bytes() -> usize {
+    32 * 1024 * 1024
+}
+
+fn default_cache_flush_secs() -> u64 {
+    5
+}
+
+/// Which pastes go first when `max_pastes` or `max_total_content_length`
+/// is reached.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EvictionPolicy {
+    #[default]
+    SoonestExpiring,
+    OldestCreated,
+    LargestFirst,
+    LeastViewed,
+}
+
+impl EvictionPolicy {
+    pub const ALL: [EvictionPolicy; 4] = [
+        EvictionPolicy::SoonestExpiring,
+        EvictionPolicy::OldestCreated,
+        EvictionPolicy::LargestFirst,
+        EvictionPolicy::LeastViewed,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            EvictionPolicy::SoonestExpiring => "soonest_expiring",
+            EvictionPolicy::OldestCreated => "oldest_created",
+            EvictionPolicy::LargestFirst => "largest_first",
+            EvictionPolicy::LeastViewed => "least_viewed",
+        }
+    }
+}
+
+/// What one cleanup or eviction pass deleted.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Sweep {
+    /// `expired`, `max_pastes` or `max_total_content_length`.
+    pub reason: &'static str,
+    /// The order pastes were evicted in; none for expiry.
+    pub policy: Option<EvictionPolicy>,
+    pub deleted: u64,
+    /// Characters freed, as counted against `max_total_content_length`.
+    pub reclaimed_length: i64,
+    /// Every token deleted, until the maintenance log caps them.
+    pub tokens: Vec<String>,
+}
+
+impl Sweep {
+    /// A pass that deleted `removed`, each token with its length.
+    pub fn new(
+        reason: &'static str,
+        policy: Option<EvictionPolicy>,
+        removed: Vec<(String, i64)>,
+    ) -> Self {
+        Sweep {
+            reason,
+            policy,
+            deleted: removed.len() as u64,
+            reclaimed_length: removed.iter().map(|(_, length)| length).sum(),
+            tokens: removed.into_iter().map(|(token, _)| token).collect(),
+        }
+    }
+}
+
+/// A sweep as the maintenance log keeps it, with when it ran.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct MaintenanceRun {
+    pub at: i64,
+    #[serde(flatten)]
+    pub sweep: Sweep,
+    /// `tokens` was cut short; `deleted` still counts them all.
+    pub tokens_truncated: bool,
+}
+
+fn default_explore_preview_chars() -> i64 {
+    500
+}
+
+fn default_explore_expiring_fraction() -> f64 {
+    0.25
+}
+
+fn default_reactions_per_minute() -> u32 {
+    30
+}
+
+fn default_allow_author() -> bool {
+    true
+}
+
+fn default_max_diff_chars() -> usize {
+    200_000
+}
+
+fn default_max_related_links() -> usize {
+    10
+}
+
+fn default_show_recent_on_index() -> bool {
+    true
+}
+
+fn default_recent_on_index_count() -> i64 {
+    5
+}
+
+fn default_raw_counts_as_view() -> bool {
+    true
+}
+
+fn default_capacity_warning_percent() -> i64 {
+    90
+}
+
+fn default_event_retention_secs() -> i64 {
+    86400 * 30
+}
+
+fn default_repeat_window_secs() -> i64 {
+    60
+}
+
+fn default_max_views_limit() -> i64 {
+    1_000_000
+}
+
+fn default_report_threshold() -> i64 {
+    3
+}
+
+fn default_reports_per_hour() -> u32 {
+    5
+}
+
+#[derive(Clone, Deserialize, Serialize)]
 pub struct I18nConfig {
-    pub zh: String,
-    pub en: String,
+    /// The strings file per locale code, e.g. `ja = "config/i18n/ja.toml"`.
+    /// A locale left out uses the built-in strings.
+    #[serde(flatten)]
+    pub locales: BTreeMap<String, String>,
 }
 
 #[allow(dead_code)]
-#[derive(Clone, Deserialize)]
+#[derive(Clone, Deserialize, Serialize)]
 pub struct Strings {
     pub lang: String,
     pub app_title: String,
@@ -92,41 +1300,24 @@ pub struct Strings {
     pub not_found_title: String,
     pub not_found_desc: String,
     pub content_too_long: String,
+    pub captcha_failed: String,
     pub aria_short_link: String,
     pub duration_expired: String,
-    pub duration_seconds: String,
-    pub duration_minutes: String,
-    pub duration_hours: String,
-    pub duration_days: String,
-    pub expires_seconds_one: String,
-    pub expires_seconds_many: String,
-    pub expires_minutes_one: String,
-    pub expires_minutes_many: String,
-    pub expires_hours_one: String,
-    pub expires_hours_many: String,
-    pub expires_days_one: String,
-    pub expires_days_many: String,
+    pub duration_seconds: Plural,
+    pub duration_minutes: Plural,
+    pub duration_hours: Plural,
+    pub duration_days: Plural,
+    pub expires_seconds: Plural,
+    pub expires_minutes: Plural,
+    pub expires_hours: Plural,
+    pub expires_days: Plural,
     pub token_length_label: String,
     pub language_auto: String,
-    pub language_plaintext: String,
-    pub language_rust: String,
-    pub language_python: String,
-    pub language_javascript: String,
-    pub language_typescript: String,
-    pub language_go: String,
-    pub language_java: String,
-    pub language_cpp: String,
-    pub language_html: String,
-    pub language_css: String,
-    pub language_json: String,
-    pub language_yaml: String,
-    pub language_sql: String,
-    pub language_bash: String,
     pub label_burn: String,
     pub label_burn_views: String,
-    pub detail_remaining_views: String,
+    pub detail_remaining_views: Plural,
     pub detail_zero_views: String,
-    pub stat_total_pastes: String,
+    pub stat_total_pastes: Plural,
     pub stat_faded: String,
     pub label_public: String,
     pub label_public_tooltip: String,
@@ -145,26 +1336,325 @@ pub struct Strings {
     pub button_renew: String,
     pub renew_success: String,
     pub button_fork: String,
+    pub explore_view_full: String,
+    pub e2e_decrypting: String,
+    pub e2e_missing_key: String,
+    pub e2e_decrypt_failed: String,
+    pub detail_owner_preview: String,
+    pub button_report: String,
+    pub report_prompt: String,
+    pub report_thanks: String,
+    pub report_too_many: String,
+    pub admin_title: String,
+    pub admin_token_label: String,
+    pub admin_sign_in: String,
+    pub admin_sign_in_failed: String,
+    pub admin_reported: String,
+    pub admin_recent: String,
+    pub admin_storage: String,
+    pub admin_reports: String,
+    pub admin_unpublished: String,
+    pub admin_delete: String,
+    pub admin_unpublish: String,
+    pub admin_dismiss: String,
+    pub admin_empty: String,
+    pub about_title: String,
+    pub admin_pinned: String,
+    pub admin_pin: String,
+    pub admin_unpin: String,
+    pub admin_usage_heading: String,
+    pub admin_usage_language: String,
+    pub admin_usage_visibility: String,
+    pub admin_usage_public: String,
+    pub admin_usage_private: String,
+    pub admin_usage_pastes: String,
+    pub admin_usage_characters: String,
+    pub admin_usage_largest: String,
+    pub admin_usage_expires: String,
+    pub admin_usage_files: String,
+    pub admin_usage_db_file: String,
+    pub admin_usage_wal_file: String,
+    pub admin_usage_bytes: String,
+    pub admin_tokens: String,
+    pub admin_tokens_saturated: String,
+    pub explore_fields_invalid: String,
+    pub raw_never_existed: String,
+    pub request_id_label: String,
+    pub label_trim_trailing: String,
+    pub label_trim_trailing_tooltip: String,
+    pub detail_views: Plural,
+    pub detail_views_by_day: String,
+    pub created_just_now: String,
+    pub created_minutes: Plural,
+    pub created_hours: Plural,
+    pub created_days: Plural,
+    pub detail_created_label: String,
+    pub explore_expiring: String,
+    pub explore_all: String,
+    pub detail_extended: String,
+    pub label_destroy_after: String,
+    pub detail_destroy_after: String,
+    pub detail_destroys_in: String,
+    pub label_visible_after: String,
+    pub detail_scheduled: String,
+    pub visible_after_invalid: String,
+    pub label_expires_at_abs: String,
+    pub detail_expires_at: String,
+    pub expires_at_invalid: String,
+    pub result_manage_label: String,
+    pub result_manage_hint: String,
+    pub manage_title: String,
+    pub manage_public: String,
+    pub manage_private: String,
+    pub manage_publish: String,
+    pub manage_unpublish: String,
+    pub manage_renew: String,
+    pub manage_delete: String,
+    pub manage_delete_confirm: String,
+    pub manage_regenerate: String,
+    pub manage_regenerate_hint: String,
+    pub manage_share_links: String,
+    pub manage_share_unlabeled: String,
+    pub index_activity: String,
+    pub index_capacity: String,
+    pub index_capacity_warning: String,
+    pub read_only: String,
+    pub maintenance_title: String,
+    pub maintenance_desc: String,
+    pub bad_request: String,
+    pub neighbors_label: String,
+    pub neighbor_prev: String,
+    pub neighbor_next: String,
+    pub featured_heading: String,
+    pub recent_heading: String,
+    pub placeholder_author: String,
+    pub label_author_tooltip: String,
+    /// "by ~{}".
+    pub by_author: String,
+    pub reactions_label: String,
+    pub react_too_many: String,
+    pub placeholder_slug: String,
+    pub slug_available: String,
+    /// "{}" is the shortest and longest slug.
+    pub slug_length: String,
+    pub slug_invalid_charset: String,
+    pub slug_reserved: String,
+    pub slug_taken: String,
+    pub diff_title: String,
+    pub diff_identical: String,
+    /// "{}" is `max_diff_chars`.
+    pub diff_too_large: String,
+    pub diff_patch: String,
+    pub diff_with_original: String,
+    pub result_qr_label: String,
+    pub result_reused: String,
+    pub too_long_title: String,
+    /// The characters counted, then the bytes received.
+    pub too_long_size: String,
+    pub too_long_body: String,
+    pub too_long_limit: String,
+    pub too_long_bytes_hint: String,
+    pub too_long_trim: String,
+    pub too_long_back: String,
+    pub detail_print: String,
+    pub detail_view_code: String,
+    pub detail_view_prose: String,
+    pub stats_lines: Plural,
+    pub stats_words: Plural,
+    pub stats_chars: Plural,
+    pub stats_bytes: Plural,
+    /// "{}" is the language.
+    pub syntax_invalid: String,
+    /// The language, then the line.
+    pub syntax_invalid_at: String,
+    pub own_recent_heading: String,
+    pub detail_related: String,
+    pub related_gone: String,
+    pub archive_title: String,
+    pub archive_empty: String,
+    pub archive_count: Plural,
+    pub archive_earlier: String,
+    pub archive_later: String,
+    pub placeholder_filename: String,
+    pub button_add_file: String,
+    pub files_too_many: String,
+    pub filename_invalid: String,
+    pub filename_duplicate: String,
+    pub files_e2e: String,
+    /// Any other keys, such as the language labels `languages` refers to
+    /// by `label_key`.
+    #[serde(flatten)]
+    pub labels: HashMap<String, String>,
+}
+
+impl Strings {
+    /// `forms` for the count `n` under this locale's plural rule, with `{}`
+    /// replaced by `n`.
+    pub fn plural(&self, forms: &Plural, n: i64) -> String {
+        forms
+            .form(plural_category(&self.lang, n))
+            .replace("{}", &n.to_string())
+    }
+
+    /// Like `plural`, for a count of `n` or more: "40+ views".
+    pub fn plural_at_least(&self, forms: &Plural, n: i64) -> String {
+        forms
+            .form(plural_category(&self.lang, n))
+            .replace("{}", &format!("{}+", n))
+    }
+}
+
+/// A string that varies with a count. In the locale files it is either a
+/// plain string, used for every count, or a table of CLDR plural forms:
+/// `{ one = "1 day", other = "{} days" }`. Only `other` is required.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(from = "PluralSource")]
+pub struct Plural {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub one: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub few: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub many: Option<String>,
+    pub other: String,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PluralSource {
+    Single(String),
+    Forms {
+        one: Option<String>,
+        few: Option<String>,
+        many: Option<String>,
+        other: String,
+    },
+}
+
+impl From<PluralSource> for Plural {
+    fn from(source: PluralSource) -> Self {
+        match source {
+            PluralSource::Single(other) => Plural {
+                one: None,
+                few: None,
+                many: None,
+                other,
+            },
+            PluralSource::Forms {
+                one,
+                few,
+                many,
+                other,
+            } => Plural {
+                one,
+                few,
+                many,
+                other,
+            },
+        }
+    }
+}
+
+impl Plural {
+    /// The form for a plural category, falling back to `other`.
+    pub fn form(&self, category: &str) -> &str {
+        let form = match category {
+            "one" => &self.one,
+            "few" => &self.few,
+            "many" => &self.many,
+            _ => &None,
+        };
+        form.as_deref().unwrap_or(&self.other)
+    }
+
+    /// The forms as a JSON object for scripts, which pick one with
+    /// `Intl.PluralRules`.
+    pub fn to_json(&self) -> String {
+        // Keep `</script>` in a translation from ending the script early.
+        serde_json::to_string(self)
+            .unwrap_or_default()
+            .replace('<', "\\u003c")
+    }
+}
+
+/// The CLDR plural category of a whole number `n` in `lang`. Languages
+/// without a rule here use the English one.
+pub fn plural_category(lang: &str, n: i64) -> &'static str {
+    let n = n.unsigned_abs();
+    let (last, last_two) = (n % 10, n % 100);
+    match lang {
+        "zh" | "ja" | "ko" | "vi" | "th" | "id" => "other",
+        "fr" | "pt" if n <= 1 => "one",
+        "ru" | "uk" | "be" => match (last, last_two) {
+            (1, 11) => "many",
+            (1, _) => "one",
+            (2..=4, 12..=14) => "many",
+            (2..=4, _) => "few",
+            _ => "many",
+        },
+        "pl" => match (n, last, last_two) {
+            (1, _, _) => "one",
+            (_, 2..=4, 12..=14) => "many",
+            (_, 2..=4, _) => "few",
+            _ => "many",
+        },
+        _ if n == 1 => "one",
+        _ => "other",
+    }
 }
 
 #[derive(Clone)]
 pub struct I18n {
-    pub zh: Strings,
-    pub en: Strings,
+    /// Strings for every one of `Lang::ALL`.
+    pub locales: HashMap<Lang, Strings>,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 pub enum Lang {
     Zh,
     En,
+    Ja,
+}
+
+impl Lang {
+    /// Every locale, in the order the language switcher lists them.
+    pub const ALL: [Lang; 3] = [Lang::En, Lang::Zh, Lang::Ja];
+
+    /// The code used in `?lang=`, the `lang` cookie and `[i18n]`.
+    pub fn code(self) -> &'static str {
+        match self {
+            Lang::Zh => "zh",
+            Lang::En => "en",
+            Lang::Ja => "ja",
+        }
+    }
+
+    pub fn from_code(code: &str) -> Option<Lang> {
+        Lang::ALL.into_iter().find(|lang| lang.code() == code)
+    }
+
+    /// The locale's name in its own language, for the switcher.
+    pub fn native_name(self) -> &'static str {
+        match self {
+            Lang::Zh => "中文",
+            Lang::En => "English",
+            Lang::Ja => "日本語",
+        }
+    }
+
+    /// The built-in strings file.
+    pub fn default_strings(self) -> &'static str {
+        match self {
+            Lang::Zh => crate::assets::DEFAULT_ZH,
+            Lang::En => crate::assets::DEFAULT_EN,
+            Lang::Ja => crate::assets::DEFAULT_JA,
+        }
+    }
 }
 
 impl I18n {
     pub fn strings(&self, lang: Lang) -> Strings {
-        match lang {
-            Lang::Zh => self.zh.clone(),
-            Lang::En => self.en.clone(),
-        }
+        self.locales[&lang].clone()
     }
 }
 
@@ -174,10 +1664,35 @@ impl I18n {
 pub struct IndexTemplate {
     pub strings: Strings,
     pub expires_options: Vec<ExpiresOption>,
+    pub destroy_after_options: Vec<ExpiresOption>,
     pub token_length_options: Vec<TokenLengthOption>,
     pub language_options: Vec<LanguageOption>,
     pub total_pastes: String,
     pub public_count: i64,
+    /// Creations over the last week, for the sparkline.
+    pub activity: Vec<ActivityBar>,
+    /// The paste of the day, if anything is public.
+    pub featured: Option<FeaturedPaste>,
+    /// `show_recent_on_index`: show the latest list, polled for updates.
+    pub show_recent: bool,
+    pub recent: Vec<RecentItem>,
+    /// The visitor's own latest pastes, by the `owner` cookie; `None` for
+    /// a visitor who has none.
+    pub own_recent: Option<Vec<RecentPaste>>,
+    /// "Storage 68% full ..." for the footer.
+    pub capacity: String,
+    /// Past `capacity_warning_percent`: warn on the create form.
+    pub capacity_warning: bool,
+    /// `server.read_only`: show a notice instead of the create form.
+    pub read_only: bool,
+    pub captcha: Option<CaptchaWidget>,
+    pub announcement: Option<Announcement>,
+    pub has_about: bool,
+
+    /// `allow_author`: offer the nickname field.
+    pub allow_author: bool,
+    /// The nickname used last time, from the `author` cookie.
+    pub author: Option<String>,
 
     // Fork data
     pub fork_title: Option<String>,
@@ -185,6 +1700,31 @@ pub struct IndexTemplate {
     pub fork_token: Option<String>,
 }
 
+/// The latest public pastes, for the index and its polls.
+#[derive(Template)]
+#[template(path = "recent.html")]
+pub struct RecentTemplate {
+    pub strings: Strings,
+    pub recent: Vec<RecentItem>,
+}
+
+/// The visitor's own latest pastes, for the index and its refresh after
+/// creating one.
+#[derive(Template)]
+#[template(path = "own_recent.html")]
+pub struct OwnRecentTemplate {
+    pub strings: Strings,
+    pub own_recent: Option<Vec<RecentPaste>>,
+}
+
+/// A paste in "your recent moments".
+pub struct RecentPaste {
+    pub token: String,
+    pub title: String,
+    /// "~ 3 hours" left.
+    pub expires_in: String,
+}
+
 #[derive(Deserialize)]
 pub struct IndexQuery {
     pub lang: Option<String>,
@@ -197,19 +1737,173 @@ pub struct DetailTemplate {
     pub item: Paste,
     pub strings: Strings,
     pub token: String,
+    /// Opened through a named share link: nothing on the page links the
+    /// paste by its own token.
+    pub shared: bool,
     pub language_label: String,
+    /// Language handed to the highlighter: `auto` only if nothing was
+    /// detected.
+    pub highlight_language: String,
     pub remaining_views: Option<String>,
+    /// The self-destruct timer: how long it will give, or, once armed,
+    /// how long is left.
+    pub self_destruct: Option<String>,
+    /// "Scheduled, goes live at ..." while the owner previews it early.
+    pub scheduled: Option<String>,
+    /// `expires_at` in `display_timezone`, next to the countdown.
+    pub expires: String,
+    pub renewal: Renewal,
+    /// The view count, e.g. "3 views".
+    pub views: String,
+    pub created: DisplayTime,
+    pub owner_preview: bool,
+    /// Per-day views, only filled in for the owner.
+    pub daily_views: Vec<DailyViews>,
+    /// Links to the public pastes on either side; only for a paste
+    /// explore lists.
+    pub neighbors: Option<Neighbors>,
+    /// "by ~nickname", unless `allow_author` is off.
+    pub byline: Option<String>,
+    /// Reaction buttons; only for a paste explore lists.
+    pub reactions: Option<Vec<Reaction>>,
+    /// The files after the first, stacked below it.
+    pub files: Vec<DetailFile>,
+    /// "12 lines · 80 words · ...", unless the paste is client-side
+    /// encrypted.
+    pub stats: Option<String>,
+    /// "Invalid JSON at line 12" and the parser's message, when the
+    /// creation check failed.
+    pub syntax_error: Option<(String, String)>,
+    /// Show the content wrapped at a readable width rather than in the
+    /// code box.
+    pub prose_mode: bool,
+    /// The content reads as prose, so the page offers to switch between
+    /// the two.
+    pub prose_fits: bool,
+    pub announcement: Option<Announcement>,
+    pub has_about: bool,
+}
+
+/// A further file as the detail page shows it.
+pub struct DetailFile {
+    pub filename: String,
+    pub language_label: String,
+    pub highlight_language: String,
+    pub content: String,
+}
+
+#[derive(Template)]
+#[template(path = "print.html")]
+pub struct PrintTemplate {
+    pub strings: Strings,
+    pub title: String,
+    /// `created_at` and `expires_at` in `display_timezone`.
+    pub created: String,
+    pub expires: String,
+    pub views: String,
+    pub byline: Option<String>,
+    /// The paste's own content first, then any further files.
+    pub files: Vec<PrintFile>,
+}
+
+/// A file on the print page, split into lines to number.
+pub struct PrintFile {
+    pub filename: Option<String>,
+    pub language_label: String,
+    pub lines: Vec<String>,
+    /// Digits in the last line number, to line the content up.
+    pub number_width: usize,
+}
+
+impl PrintFile {
+    pub fn new(filename: Option<String>, language_label: String, content: &str) -> Self {
+        let lines: Vec<String> = content.lines().map(str::to_string).collect();
+        PrintFile {
+            filename,
+            language_label,
+            number_width: lines.len().max(1).to_string().len(),
+            lines,
+        }
+    }
+}
+
+#[derive(Template)]
+#[template(path = "diff.html")]
+pub struct DiffTemplate {
+    pub strings: Strings,
+    pub announcement: Option<Announcement>,
+    pub old_token: String,
+    pub old_title: String,
+    pub new_token: String,
+    pub new_title: String,
+    pub hunks: Vec<DiffHunk>,
+    pub added: usize,
+    pub removed: usize,
 }
 
 #[derive(Template)]
 #[template(path = "item.html")]
 pub struct ResultTemplate {
     pub path: String,
+    /// Shown only here, right after creation.
+    pub manage_path: String,
     pub expires_in: String,
     pub strings: Strings,
     pub language_label: String,
     pub remaining_views: Option<String>,
     pub total_pastes: String,
+    /// The link as an inline `<svg>` QR code, when `show_qr` is on.
+    pub qr_svg: Option<String>,
+    /// The paste is one the client created moments ago with the same
+    /// content, handed back instead of a copy.
+    pub reused: bool,
+}
+
+#[derive(Template)]
+#[template(path = "manage.html")]
+pub struct ManageTemplate {
+    pub strings: Strings,
+    pub item: ManagedPaste,
+    pub manage_token: String,
+    /// The view count, e.g. "3 views".
+    pub views: String,
+    pub expires_in: String,
+    pub created: DisplayTime,
+    pub remaining_views: Option<String>,
+    /// Burn-after-reading, self-destructing and reported pastes cannot be
+    /// published.
+    pub can_publish: bool,
+    /// Self-destructing pastes cannot be renewed.
+    pub can_renew: bool,
+    /// "Scheduled, goes live at ..." until it does.
+    pub scheduled: Option<String>,
+    /// As on the detail page.
+    pub stats: Option<String>,
+    pub share_links: Vec<ManagedShareLink>,
+}
+
+/// A named share link on the manage page.
+pub struct ManagedShareLink {
+    pub share_token: String,
+    pub label: String,
+    /// The views it has had, e.g. "3 views".
+    pub views: String,
+    /// What is left of its `max_views`, if it has one.
+    pub remaining: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "admin.html")]
+pub struct AdminTemplate {
+    pub strings: Strings,
+    pub authorized: bool,
+    pub login_failed: bool,
+    pub reported: Vec<ReportedPaste>,
+    pub recent: Vec<PublicPaste>,
+    pub storage: String,
+    pub usage: Option<StorageUsage>,
+    pub tokens: String,
+    pub tokens_saturated: bool,
 }
 
 #[derive(Template)]
@@ -217,16 +1911,94 @@ pub struct ResultTemplate {
 pub struct NotFoundTemplate {
     pub strings: Strings,
     pub faded_count: String,
+    pub announcement: Option<Announcement>,
+    pub has_about: bool,
+}
+
+#[derive(Template)]
+#[template(path = "error.html")]
+pub struct ErrorTemplate {
+    pub strings: Strings,
+    pub status: u16,
+    pub message: String,
+    pub announcement: Option<Announcement>,
+    pub has_about: bool,
+}
+
+/// The result-slot card for content over a length limit.
+#[derive(Template)]
+#[template(path = "too_long.html")]
+pub struct TooLongTemplate {
+    pub strings: Strings,
+    /// What was sent, as the server measured it.
+    pub size: String,
+    pub limit: String,
+    /// Whether the bytes received differ from the characters counted.
+    pub bytes_hint: bool,
+}
+
+/// The same card on a page of its own, for a form posted without htmx.
+#[derive(Template)]
+#[template(path = "too_long_page.html")]
+pub struct TooLongPageTemplate {
+    pub strings: Strings,
+    pub size: String,
+    pub limit: String,
+    pub bytes_hint: bool,
+    pub announcement: Option<Announcement>,
+    pub has_about: bool,
+}
+
+#[derive(Template)]
+#[template(path = "maintenance.html")]
+pub struct MaintenanceTemplate {
+    pub strings: Strings,
+    pub announcement: Option<Announcement>,
+}
+
+#[derive(Template)]
+#[template(path = "about.html")]
+pub struct AboutTemplate {
+    pub strings: Strings,
+    /// Sanitized HTML from `markdown::render`.
+    pub body: String,
+    pub announcement: Option<Announcement>,
+}
+
+#[derive(Template)]
+#[template(path = "archive.html")]
+pub struct ArchiveTemplate {
+    pub strings: Strings,
+    pub announcement: Option<Announcement>,
+    pub month: String,
+    /// "12 pastes" for the whole month.
+    pub total: String,
+    /// Each with its count already worded.
+    pub days: Vec<(ArchiveDay, String)>,
+    pub earlier: Option<String>,
+    pub later: Option<String>,
+}
+
+/// A paste on the explore page with the share of its lifetime left, which
+/// the life bar starts from.
+pub struct ExploreCard {
+    pub paste: PublicPaste,
+    pub life_fraction: f64,
+    pub renewal: Renewal,
 }
 
 #[derive(Template)]
 #[template(path = "explore.html")]
 pub struct ExploreTemplate {
     pub strings: Strings,
-    pub pastes: Vec<PublicPaste>,
-    pub total: i64,
+    pub pastes: Vec<ExploreCard>,
+    /// "Showing X of Y", Y counting the pastes past the first page.
+    pub count: String,
+    /// Only pastes about to expire are listed.
+    pub expiring: bool,
     pub now_ts: i64,
     pub max_expires_secs: i64,
+    pub announcement: Option<Announcement>,
 }
 
 #[derive(Clone)]
@@ -250,12 +2022,80 @@ pub struct LanguageOption {
     pub selected: bool,
 }
 
+#[derive(Deserialize)]
+pub struct ShareQuery {
+    pub ttl: Option<i64>,
+}
+
+#[derive(Deserialize)]
+pub struct ShareLinkForm {
+    pub label: Option<String>,
+    pub max_views: Option<i64>,
+}
+
+/// `POST /p/{token}/related`: the tokens to link to, replacing any before.
+#[derive(Deserialize)]
+pub struct RelatedLinksForm {
+    pub tokens: Vec<String>,
+    /// The paste's manage token, for a creator without the `owner` cookie.
+    pub manage_token: Option<String>,
+}
+
+/// Which pastes a bulk purge applies to. Every filter given must match.
+#[derive(Clone, Default, Deserialize)]
+pub struct PurgeFilter {
+    pub content_regex: Option<String>,
+    pub content_contains: Option<String>,
+    pub created_before: Option<i64>,
+    pub created_after: Option<i64>,
+    pub language: Option<String>,
+    pub is_public: Option<bool>,
+    /// Content length bounds in characters, inclusive.
+    pub min_length: Option<i64>,
+    pub max_length: Option<i64>,
+    /// Without this the purge is a dry run that only counts matches.
+    #[serde(default)]
+    pub confirm: bool,
+}
+
+#[derive(Deserialize)]
+pub struct BanForm {
+    /// An address or CIDR range.
+    pub cidr: String,
+    pub reason: Option<String>,
+    pub expires_at: Option<i64>,
+}
+
+#[derive(Deserialize)]
+pub struct ReportForm {
+    pub reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ReactForm {
+    /// One of `REACTIONS`.
+    pub emoji: String,
+}
+
+/// Which public pastes explore lists, so that the pastes shown and every
+/// count of them agree.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PublicFilter {
+    /// Only pastes with less than this share of their lifespan left.
+    pub expiring_below: Option<f64>,
+}
+
 #[derive(Deserialize)]
 pub struct ExploreQuery {
     pub offset: Option<i64>,
+    pub full: Option<u8>,
+    pub preview_chars: Option<i64>,
+    pub fields: Option<String>,
+    pub expiring: Option<u8>,
+    pub lang: Option<String>,
 }
 
-#[derive(Deserialize)]
+#[derive(Default, Deserialize)]
 pub struct PasteForm {
     pub title: Option<String>,
     pub content: String,
@@ -263,12 +2103,308 @@ pub struct PasteForm {
     pub token_length: Option<usize>,
     pub language: Option<String>,
     pub max_views: Option<String>,
+    /// Seconds the paste survives after it is first opened.
+    pub destroy_after_first_view_secs: Option<String>,
+    /// Expire at this RFC 3339 time instead of after `expires_in`.
+    pub expires_at_abs: Option<String>,
+    /// Go live later: a Unix timestamp, or a local `YYYY-MM-DDTHH:MM` in
+    /// `display_timezone`.
+    pub visible_after: Option<String>,
     pub is_public: Option<String>,
+    /// Content was encrypted in the browser; the key never reaches the server.
+    pub is_e2e: Option<String>,
+    /// Strip trailing whitespace from each line and trailing blank lines.
+    pub trim_trailing: Option<String>,
+    /// Nickname to sign the paste with.
+    pub author: Option<String>,
+    /// Custom slug to use as the token, e.g. `meeting-notes`.
+    pub slug: Option<String>,
+    /// Token of the paste this one is a fork of.
+    pub forked_from: Option<String>,
+    /// Name of the first file, the content above.
+    pub filename: Option<String>,
+    /// Further files, one entry per field each, in order; an empty or
+    /// missing language is worked out from the filename.
+    #[serde(default)]
+    pub file_name: Vec<String>,
+    #[serde(default)]
+    pub file_language: Vec<String>,
+    #[serde(default)]
+    pub file_content: Vec<String>,
+    #[serde(rename = "cf-turnstile-response")]
+    pub turnstile_response: Option<String>,
+    #[serde(rename = "h-captcha-response")]
+    pub hcaptcha_response: Option<String>,
+}
+
+/// One paste in a backup file: the full row minus the internal id.
+#[derive(Clone, Debug, Deserialize, FromRow, PartialEq, Serialize)]
+pub struct BackupPaste {
+    /// Keyset cursor while exporting; not written out.
+    #[serde(skip)]
+    pub id: i64,
+    pub token: String,
+    pub title: String,
+    pub content: String,
+    /// `plain`, or `encrypted` for ciphertext under the server's
+    /// `encryption_key` (with `nonce`).
+    pub encoding: String,
+    pub nonce: Option<String>,
+    pub language: String,
+    pub created_at: i64,
+    pub expires_at: i64,
+    pub original_duration: Option<i64>,
+    pub views: i64,
+    pub max_views: Option<i64>,
+    pub is_public: bool,
+    pub is_e2e: bool,
+    pub flagged: bool,
+    pub owner_id: Option<String>,
+    pub content_hash: Option<String>,
+    /// Absent from backups written before pinning existed.
+    #[serde(default)]
+    pub pinned: bool,
+    #[serde(default)]
+    pub detected_language: Option<String>,
+    #[serde(default)]
+    pub auto_renewals: i64,
+    #[serde(default)]
+    pub manage_token: Option<String>,
+    #[serde(default)]
+    pub destroy_after_first_view_secs: Option<i64>,
+    #[serde(default)]
+    pub first_viewed_at: Option<i64>,
+    #[serde(default)]
+    pub visible_after: Option<i64>,
+    #[serde(default)]
+    pub author: Option<String>,
+    #[serde(default)]
+    pub forked_from: Option<String>,
+    #[serde(default)]
+    pub filename: Option<String>,
+    #[serde(default)]
+    pub syntax_valid: Option<bool>,
+    #[serde(default)]
+    pub syntax_error: Option<String>,
+    #[serde(default)]
+    pub syntax_error_line: Option<i64>,
+    /// The files after the first, in order. Absent from backups written
+    /// before format version 2.
+    #[serde(default)]
+    #[sqlx(skip)]
+    pub files: Vec<BackupFile>,
+}
+
+/// A further file of a paste in a backup file.
+#[derive(Clone, Debug, Deserialize, FromRow, PartialEq, Serialize)]
+pub struct BackupFile {
+    /// The paste it belongs to while exporting; not written out.
+    #[serde(skip)]
+    pub paste_id: i64,
+    pub filename: String,
+    pub language: String,
+    pub content: String,
+    /// As for the paste's own content.
+    pub encoding: String,
+    pub nonce: Option<String>,
+}
+
+/// A line of a backup file.
+#[derive(Deserialize, Serialize)]
+pub struct BackupLine {
+    pub version: u32,
+    #[serde(flatten)]
+    pub paste: BackupPaste,
+}
+
+/// Outcome of `backup::import`.
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct ImportSummary {
+    pub inserted: u64,
+    /// Token already taken (without overwrite) or already expired.
+    pub skipped: u64,
+    /// Unreadable, wrong version, or over the storage limit.
+    pub failed: u64,
+}
+
+#[derive(Deserialize)]
+pub struct ImportQuery {
+    /// Replace existing pastes with the same token instead of keeping them.
+    #[serde(default)]
+    pub overwrite: bool,
+}
+
+#[derive(Deserialize)]
+pub struct ExportQuery {
+    #[serde(default)]
+    pub include_plaintext: bool,
+}
+
+/// A database snapshot written by `backup::snapshot`.
+#[derive(Debug, Serialize)]
+pub struct Snapshot {
+    pub path: String,
+    pub size: u64,
+    pub duration_ms: u128,
+}
+
+/// How scheduled backups have been going since the process started.
+#[derive(Serialize, ToSchema)]
+pub struct BackupStatus {
+    pub last_success_at: Option<i64>,
+    pub last_failed: bool,
+    pub failures: u64,
+}
+
+/// How often `[paste.cache]` had the paste a view asked for, since the
+/// process started. Both stay 0 while it is off.
+#[derive(Serialize, ToSchema)]
+pub struct CacheStatus {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+/// How webhook deliveries have gone since the process started.
+#[derive(Serialize, ToSchema)]
+pub struct WebhookStatus {
+    pub delivered: u64,
+    /// Attempts that failed, retried or not.
+    pub failed: u64,
+    /// Deliveries given up on after `max_attempts`.
+    pub dead_lettered: u64,
+}
+
+/// A row of `webhook_queue`.
+#[derive(Clone, Debug, FromRow, Serialize)]
+pub struct WebhookDelivery {
+    pub id: i64,
+    /// `expired` or `burned`.
+    pub event: String,
+    pub token: String,
+    pub created_at: i64,
+    pub attempts: i64,
+    pub next_attempt_at: i64,
+    pub last_error: Option<String>,
+    pub dead: bool,
+}
+
+/// Where the `max_total_content_length` budget goes, for
+/// `GET /admin/api/storage` and the admin page. Lengths are counted as the
+/// budget counts them: characters of every file, or of the ciphertext when
+/// encrypted at rest.
+#[derive(Serialize)]
+pub struct StorageUsage {
+    pub content_length: i64,
+    pub max_total_content_length: i64,
+    /// Largest share first. `auto` pastes count under the language they
+    /// were detected as.
+    pub by_language: Vec<LanguageUsage>,
+    pub public: UsageTotals,
+    pub private: UsageTotals,
+    pub largest: Vec<LargestPaste>,
+    pub files: DbFileSizes,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct UsageTotals {
+    pub pastes: i64,
+    pub content_length: i64,
+}
+
+#[derive(Clone, Debug, FromRow, PartialEq, Serialize)]
+pub struct LanguageUsage {
+    pub language: String,
+    pub pastes: i64,
+    pub content_length: i64,
+}
+
+#[derive(Clone, Debug, FromRow, PartialEq, Serialize)]
+pub struct LargestPaste {
+    pub token: String,
+    pub title: String,
+    pub content_length: i64,
+    pub expires_at: i64,
+    /// Time left, for the admin page.
+    #[sqlx(skip)]
+    #[serde(skip)]
+    pub expires_in: String,
+}
+
+/// Sizes in bytes of the database and its write-ahead log on disk; `None`
+/// for a file that is not there.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct DbFileSizes {
+    pub db: Option<u64>,
+    pub wal: Option<u64>,
+}
+
+/// What `db::self_check` found: the schema version recorded in the
+/// database and the columns of each table.
+#[derive(Clone, Debug, Serialize)]
+pub struct SchemaReport {
+    pub version: i64,
+    pub tables: BTreeMap<String, Vec<String>>,
+}
+
+/// Row counts reported by `mayfile stats`.
+#[derive(FromRow, Serialize, ToSchema)]
+pub struct Stats {
+    pub pastes: i64,
+    pub public: i64,
+    /// Encrypted in the browser.
+    pub encrypted: i64,
+    pub burn_after_reading: i64,
+    /// Pastes ever created, counting ones since deleted.
+    pub created: i64,
+    pub content_length: i64,
+    pub reported: i64,
+    pub bans: i64,
+}
+
+/// `POST /admin/api/mode`: switches to flip; absent ones stay as they are.
+#[derive(Deserialize, Serialize)]
+pub struct ServiceMode {
+    pub read_only: Option<bool>,
+    pub maintenance: Option<bool>,
+}
+
+/// What `config::reload` applied and what it left for a restart.
+#[derive(Serialize)]
+pub struct ReloadSummary {
+    pub changed: Vec<String>,
+    pub ignored: Vec<String>,
 }
 
 #[derive(Clone)]
 pub struct AppState {
     pub pool: sqlx::SqlitePool,
-    pub config: AppConfig,
-    pub i18n: I18n,
+    /// Swapped by `config::reload`; read with `.load()`.
+    pub config: Arc<ArcSwap<AppConfig>>,
+    pub i18n: Arc<ArcSwap<I18n>>,
+    /// Where `config` was read from, for reloading.
+    pub config_path: String,
+    pub cipher: Option<ContentCipher>,
+    pub signing_key: Vec<u8>,
+    pub report_limiter: Arc<RateLimiter>,
+    pub reaction_limiter: Arc<RateLimiter>,
+    pub slug_limiter: Arc<RateLimiter>,
+    pub metrics: Arc<Metrics>,
+    pub captcha: Option<Arc<dyn CaptchaVerifier>>,
+    pub about: Arc<ArcSwap<AboutPages>>,
+    /// Recently computed `/api/v1/stats/daily` series.
+    pub activity: Arc<ActivityCache>,
+    /// Today's paste for the index.
+    pub featured: Arc<FeaturedCache>,
+    /// Recently taken counts of the pastes explore lists.
+    pub public_counts: Arc<PublicCountCache>,
+    /// What is stored, as `/api/v1/limits` reports it.
+    pub usage: Arc<UsageCache>,
+    /// Recent cleanup and eviction passes, for `/admin/api/maintenance`.
+    pub maintenance: Arc<MaintenanceLog>,
+    /// Hot public pastes and their views not yet written.
+    pub paste_cache: Arc<PasteCache>,
+    /// View receipts a raw read has already ridden on.
+    pub spent_receipts: Arc<SpentReceipts>,
+    /// The time every expiry and timestamp is judged by.
+    pub clock: Arc<dyn Clock>,
 }
@@ -0,0 +1,301 @@
+//! Hot public pastes kept in memory, with the views they draw counted in
+//! memory too and written every few seconds, so a paste everyone is
+//! reading costs neither a query nor a write per view. Only public pastes
+//! without a view limit or self-destruct timer are kept: burn-on-read and
+//! private pastes go to the database every time.
+
+use crate::db;
+use crate::handlers::max_expires_secs;
+use crate::models::{AppState, CacheConfig, CacheStatus, Paste, PendingView};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+#[derive(Default)]
+pub struct PasteCache {
+    entries: Mutex<Entries>,
+    /// Views not yet written, by token.
+    pending: Mutex<HashMap<String, Vec<PendingView>>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+#[derive(Default)]
+struct Entries {
+    by_token: HashMap<String, Entry>,
+    /// Content bytes held over all entries.
+    bytes: usize,
+    /// Bumped on every use; the entry with the oldest `used` goes first.
+    clock: u64,
+}
+
+struct Entry {
+    paste: Arc<Paste>,
+    /// Views counted since the paste was loaded.
+    views: i64,
+    used: u64,
+    size: usize,
+}
+
+impl PasteCache {
+    /// Whether `paste` may be kept and have its views batched.
+    pub fn admits(paste: &Paste, now: i64) -> bool {
+        paste.is_public
+            && paste.max_views.is_none()
+            && paste.destroy_after_first_view_secs.is_none()
+            && !paste.is_scheduled(now)
+    }
+
+    /// The cached paste `token` with the views counted so far, unless it
+    /// has expired since it was loaded.
+    pub fn get(&self, token: &str, now: i64) -> Option<Paste> {
+        let mut entries = self.entries.lock().unwrap();
+        entries.clock += 1;
+        let clock = entries.clock;
+        let found = match entries.by_token.get_mut(token) {
+            Some(entry) if entry.paste.expires_at > now => {
+                entry.used = clock;
+                let mut paste = Paste::clone(&entry.paste);
+                paste.views += entry.views;
+                Some(paste)
+            }
+            Some(_) => {
+                entries.remove(token);
+                None
+            }
+            None => None,
+        };
+        let counter = if found.is_some() {
+            &self.hits
+        } else {
+            &self.misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        found
+    }
+
+    /// Keep `paste`, making room by dropping the least recently used.
+    /// A paste larger than `max_bytes` on its own is not kept.
+    pub fn insert(&self, token: &str, paste: &Paste, config: &CacheConfig) {
+        let size = paste.content.len()
+            + paste
+                .files
+                .iter()
+                .map(|file| file.content.len())
+                .sum::<usize>();
+        if size > config.max_bytes {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        entries.remove(token);
+        while entries.by_token.len() >= config.max_entries
+            || entries.bytes + size > config.max_bytes
+        {
+            let Some(oldest) = entries
+                .by_token
+                .iter()
+                .min_by_key(|(_, entry)| entry.used)
+                .map(|(token, _)| token.clone())
+            else {
+                break;
+            };
+            entries.remove(&oldest);
+        }
+        entries.clock += 1;
+        let entry = Entry {
+            paste: Arc::new(paste.clone()),
+            views: 0,
+            used: entries.clock,
+            size,
+        };
+        entries.bytes += size;
+        entries.by_token.insert(token.to_string(), entry);
+    }
+
+    /// Forget `token`, after it was changed or deleted.
+    pub fn invalidate(&self, token: &str) {
+        self.entries.lock().unwrap().remove(token);
+    }
+
+    /// Forget `token` and the views counted for it, after it was deleted.
+    pub fn forget(&self, token: &str) {
+        self.invalidate(token);
+        self.pending.lock().unwrap().remove(token);
+    }
+
+    /// Forget everything, after a change that may touch any paste.
+    pub fn clear(&self) {
+        *self.entries.lock().unwrap() = Entries::default();
+    }
+
+    /// Count a view of `token` in memory; `flush` writes it.
+    pub fn count_view(&self, token: &str, view: PendingView) {
+        if let Some(entry) = self.entries.lock().unwrap().by_token.get_mut(token) {
+            entry.views += 1;
+        }
+        self.pending
+            .lock()
+            .unwrap()
+            .entry(token.to_string())
+            .or_default()
+            .push(view);
+    }
+
+    pub fn status(&self) -> CacheStatus {
+        CacheStatus {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Entries {
+    fn remove(&mut self, token: &str) {
+        if let Some(entry) = self.by_token.remove(token) {
+            self.bytes -= entry.size;
+        }
+    }
+}
+
+/// Write the views counted so far. The pastes they were for are dropped
+/// from the cache afterwards, so the next view reloads the counts and any
+/// popularity extension from the database; a paste that was deleted in
+/// the meantime thus stays in memory for at most one interval.
+pub async fn flush(state: &AppState) {
+    let pending = std::mem::take(&mut *state.paste_cache.pending.lock().unwrap());
+    if pending.is_empty() {
+        return;
+    }
+    let config = state.config.load();
+    let popularity = config.paste.popularity.as_ref();
+    let max_expires = max_expires_secs(&config.paste);
+    for (token, views) in pending {
+        if let Err(error) =
+            db::record_views(&state.pool, &token, &views, popularity, max_expires).await
+        {
+            eprintln!(
+                "Failed to record {} views of {}: {}",
+                views.len(),
+                token,
+                error
+            );
+        }
+        state.paste_cache.invalidate(&token);
+    }
+}
+
+/// Flush the counted views every `flush_secs`, following reloads.
+pub async fn run_flusher(state: AppState) {
+    loop {
+        let secs = state
+            .config
+            .load()
+            .paste
+            .cache
+            .as_ref()
+            .map_or(5, |cache| cache.flush_secs);
+        tokio::time::sleep(Duration::from_secs(secs)).await;
+        flush(&state).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{PasteBuilder, test_state};
+    use crate::utils::now_ts;
+
+    fn config(max_entries: usize, max_bytes: usize) -> CacheConfig {
+        CacheConfig {
+            max_entries,
+            max_bytes,
+            flush_secs: 5,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_least_recently_used_goes_first() {
+        let state = test_state().await;
+        let now = now_ts();
+        let mut pastes = Vec::new();
+        for content in ["aaaa", "bbbb", "cccc"] {
+            let token = PasteBuilder::new()
+                .public(true)
+                .content(content)
+                .insert(&state.pool)
+                .await
+                .token;
+            let paste = db::fetch_paste(&state.pool, &token, None, now)
+                .await
+                .unwrap()
+                .unwrap();
+            pastes.push((token, paste));
+        }
+        let cache = PasteCache::default();
+        let by_count = config(2, 1024);
+        cache.insert(&pastes[0].0, &pastes[0].1, &by_count);
+        cache.insert(&pastes[1].0, &pastes[1].1, &by_count);
+        assert!(cache.get(&pastes[0].0, now).is_some());
+        cache.insert(&pastes[2].0, &pastes[2].1, &by_count);
+        assert!(cache.get(&pastes[1].0, now).is_none());
+        assert!(cache.get(&pastes[0].0, now).is_some());
+        assert!(cache.get(&pastes[2].0, now).is_some());
+
+        // Eight bytes hold two of the four-byte pastes.
+        let cache = PasteCache::default();
+        let by_bytes = config(10, 8);
+        for (token, paste) in &pastes {
+            cache.insert(token, paste, &by_bytes);
+        }
+        assert!(cache.get(&pastes[0].0, now).is_none());
+        assert!(cache.get(&pastes[2].0, now).is_some());
+        cache.insert(&pastes[0].0, &pastes[0].1, &config(10, 3));
+        assert!(cache.get(&pastes[0].0, now).is_none());
+
+        assert!(cache.get(&pastes[2].0, pastes[2].1.expires_at).is_none());
+        let status = cache.status();
+        assert_eq!((status.hits, status.misses), (1, 3));
+    }
+
+    #[tokio::test]
+    async fn test_flush_writes_counted_views() {
+        let state = test_state().await;
+        let now = now_ts();
+        let token = PasteBuilder::new()
+            .public(true)
+            .insert(&state.pool)
+            .await
+            .token;
+        let paste = db::fetch_paste(&state.pool, &token, None, now)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(PasteCache::admits(&paste, now));
+        state.paste_cache.insert(&token, &paste, &config(10, 1024));
+        for raw in [false, true, false] {
+            let view = PendingView {
+                client_hash: "client".to_string(),
+                raw,
+                at: now,
+            };
+            state.paste_cache.count_view(&token, view);
+        }
+        assert_eq!(state.paste_cache.get(&token, now).unwrap().views, 3);
+        let stored = || async {
+            db::fetch_paste(&state.pool, &token, None, now)
+                .await
+                .unwrap()
+                .unwrap()
+                .views
+        };
+        assert_eq!(stored().await, 0);
+
+        flush(&state).await;
+        assert_eq!(stored().await, 3);
+        assert!(state.paste_cache.get(&token, now).is_none());
+        let daily = db::list_daily_views(&state.pool, &token).await.unwrap();
+        assert_eq!(daily.iter().map(|day| day.count).sum::<i64>(), 3);
+    }
+}
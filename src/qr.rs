@@ -0,0 +1,37 @@
+//! QR codes for paste links, drawn as inline SVG.
+
+use qrcode::QrCode;
+use qrcode::render::svg;
+
+/// Smallest side of the drawn code in pixels, sized for the result card.
+const SIZE: u32 = 160;
+
+/// `url` as an `<svg>` element to embed in a page, or `None` if it is too
+/// long for a QR code. The markup is generated here, never from input, so
+/// templates may emit it unescaped.
+pub fn svg(url: &str) -> Option<String> {
+    let code = QrCode::new(url.as_bytes()).ok()?;
+    let image = code
+        .render::<svg::Color>()
+        .min_dimensions(SIZE, SIZE)
+        .build();
+    // The XML declaration only belongs in a standalone file.
+    Some(
+        image
+            .trim_start_matches(r#"<?xml version="1.0" standalone="yes"?>"#)
+            .to_string(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_svg_is_an_inline_element() {
+        let image = svg("https://example.com/p/abcd").unwrap();
+        assert!(image.starts_with("<svg "), "{}", image);
+        assert!(image.ends_with("</svg>"), "{}", image);
+        assert!(svg(&"x".repeat(8000)).is_none());
+    }
+}
@@ -0,0 +1,56 @@
+//! Per-client request limits kept in memory.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+
+/// Fixed-window counter: at most `limit` hits per client per `window_secs`.
+pub struct RateLimiter {
+    limit: u32,
+    window_secs: i64,
+    hits: Mutex<HashMap<IpAddr, (i64, u32)>>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: u32, window_secs: i64) -> Self {
+        Self {
+            limit,
+            window_secs,
+            hits: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Record a hit from `ip` at `now`. Returns false once the client is
+    /// over its limit for the current window.
+    pub fn check(&self, ip: IpAddr, now: i64) -> bool {
+        let window = now - now.rem_euclid(self.window_secs);
+        let mut hits = self.hits.lock().unwrap();
+        if hits.len() > 10_000 {
+            hits.retain(|_, (start, _)| *start == window);
+        }
+        let entry = hits.entry(ip).or_insert((window, 0));
+        if entry.0 != window {
+            *entry = (window, 0);
+        }
+        entry.1 += 1;
+        entry.1 <= self.limit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn test_rate_limiter_window() {
+        let limiter = RateLimiter::new(2, 60);
+        let a = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        let b = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+        assert!(limiter.check(a, 120));
+        assert!(limiter.check(a, 130));
+        assert!(!limiter.check(a, 179));
+        assert!(limiter.check(b, 179));
+        assert!(limiter.check(a, 180));
+    }
+}
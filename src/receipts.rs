@@ -0,0 +1,36 @@
+//! View receipts already taken, kept in memory until they expire.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Nonces of the view receipts a raw read has ridden on, each with the
+/// time its receipt runs out.
+#[derive(Default)]
+pub struct SpentReceipts {
+    spent: Mutex<HashMap<String, i64>>,
+}
+
+impl SpentReceipts {
+    /// Take the receipt `nonce`, good until `exp`, at `now`. Returns false
+    /// if it was taken before.
+    pub fn take(&self, nonce: &str, exp: i64, now: i64) -> bool {
+        let mut spent = self.spent.lock().unwrap();
+        if spent.len() > 10_000 {
+            spent.retain(|_, exp| *exp > now);
+        }
+        spent.insert(nonce.to_string(), exp).is_none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_receipt_is_taken_once() {
+        let spent = SpentReceipts::default();
+        assert!(spent.take("a", 160, 100));
+        assert!(!spent.take("a", 160, 110));
+        assert!(spent.take("b", 160, 110));
+    }
+}
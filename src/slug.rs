@@ -0,0 +1,111 @@
+//! Custom slugs: a name the creator picks in place of a generated token,
+//! such as `/p/meeting-notes`.
+
+use std::ops::RangeInclusive;
+
+/// How long a slug may be.
+pub const SLUG_LENGTHS: RangeInclusive<usize> = 3..=64;
+
+/// Availability checks a single client may make per minute; the form asks
+/// as the creator types.
+pub const CHECKS_PER_MINUTE: u32 = 120;
+
+/// Names kept back because they read like part of the site rather than a
+/// paste.
+const RESERVED: &[&str] = &[
+    "about", "admin", "api", "archive", "assets", "explore", "favicon", "health", "help", "index",
+    "login", "manage", "metrics", "new", "paste", "raw", "robots", "share", "static",
+];
+
+/// Why a slug cannot be had.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SlugError {
+    /// Outside `SLUG_LENGTHS`.
+    Length,
+    /// Anything but lowercase letters, digits and inner hyphens.
+    InvalidCharset,
+    Reserved,
+    /// Another paste already has it.
+    Taken,
+}
+
+impl SlugError {
+    /// The `reason` in the availability check and the tail of the create
+    /// error code.
+    pub fn code(self) -> &'static str {
+        match self {
+            SlugError::Length => "length",
+            SlugError::InvalidCharset => "invalid_charset",
+            SlugError::Reserved => "reserved",
+            SlugError::Taken => "taken",
+        }
+    }
+}
+
+/// Check a requested slug the same way for the create path and the
+/// availability check. Whether it is taken is left to the database.
+/// Under `case_insensitive_tokens` capitals are taken as their lowercase
+/// letter, since the slug is found in any case anyway.
+pub fn validate_slug(raw: &str, case_insensitive: bool) -> Result<String, SlugError> {
+    let slug = raw.trim();
+    let lowered;
+    let slug = if case_insensitive {
+        lowered = slug.to_ascii_lowercase();
+        lowered.as_str()
+    } else {
+        slug
+    };
+    if !SLUG_LENGTHS.contains(&slug.len()) {
+        return Err(SlugError::Length);
+    }
+    if !slug
+        .bytes()
+        .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-')
+        || slug.starts_with('-')
+        || slug.ends_with('-')
+    {
+        return Err(SlugError::InvalidCharset);
+    }
+    if RESERVED.contains(&slug) {
+        return Err(SlugError::Reserved);
+    }
+    Ok(slug.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_slug() {
+        assert_eq!(
+            validate_slug(" meeting-notes ", false).as_deref(),
+            Ok("meeting-notes")
+        );
+        assert_eq!(validate_slug("ab", false), Err(SlugError::Length));
+        assert_eq!(
+            validate_slug(&"a".repeat(65), false),
+            Err(SlugError::Length)
+        );
+        for bad in ["Meeting", "notes_1", "-notes", "notes-", "日本語"] {
+            assert_eq!(
+                validate_slug(bad, false),
+                Err(SlugError::InvalidCharset),
+                "{}",
+                bad
+            );
+        }
+        assert_eq!(validate_slug("admin", false), Err(SlugError::Reserved));
+
+        // Forgiving lookups take capitals as the slug they would find
+        assert_eq!(
+            validate_slug("Meeting-Notes", true).as_deref(),
+            Ok("meeting-notes")
+        );
+        assert_eq!(validate_slug("ADMIN", true), Err(SlugError::Reserved));
+        assert_eq!(
+            validate_slug("notes_1", true),
+            Err(SlugError::InvalidCharset)
+        );
+    }
+}
@@ -0,0 +1,151 @@
+//! Parse checks for JSON and YAML pastes, and re-indenting JSON for
+//! `/r/{token}?pretty=1` and `?minify=1`. Broken documents are still
+//! stored; the check only tells the reader where they break.
+
+use serde::Deserialize;
+use serde::de::IgnoredAny;
+
+/// Larger documents are neither checked nor reformatted, so creating or
+/// fetching one stays cheap.
+pub const MAX_CHECK_BYTES: usize = 1024 * 1024;
+
+/// Where a document first fails to parse.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SyntaxError {
+    pub message: String,
+    /// 1-based, when the parser knows it.
+    pub line: Option<i64>,
+}
+
+/// Parse `content` as `language` when that is `json` or `yaml` and it is
+/// within `MAX_CHECK_BYTES`. `None` when it is not checked.
+pub fn check(language: &str, content: &str) -> Option<Result<(), SyntaxError>> {
+    if content.len() > MAX_CHECK_BYTES {
+        return None;
+    }
+    match language {
+        "json" => Some(check_json(content)),
+        "yaml" => Some(check_yaml(content)),
+        _ => None,
+    }
+}
+
+fn check_json(content: &str) -> Result<(), SyntaxError> {
+    serde_json::from_str::<IgnoredAny>(content)
+        .map(|_| ())
+        .map_err(|err| SyntaxError {
+            message: err.to_string(),
+            line: Some(err.line() as i64),
+        })
+}
+
+/// Every document of a `---` separated stream must parse.
+fn check_yaml(content: &str) -> Result<(), SyntaxError> {
+    for document in serde_yaml_ng::Deserializer::from_str(content) {
+        IgnoredAny::deserialize(document).map_err(|err| SyntaxError {
+            line: err.location().map(|location| location.line() as i64),
+            message: err.to_string(),
+        })?;
+    }
+    Ok(())
+}
+
+/// `content` re-indented by two spaces, or with all whitespace between
+/// tokens dropped when `pretty` is off. Strings, numbers and key order are
+/// kept exactly as written. `None` unless it is valid JSON within
+/// `MAX_CHECK_BYTES`.
+pub fn reformat_json(content: &str, pretty: bool) -> Option<String> {
+    check("json", content)?.ok()?;
+    let mut out = String::with_capacity(content.len());
+    let mut depth = 0;
+    let newline = |out: &mut String, depth: usize| {
+        if pretty {
+            out.push('\n');
+            out.push_str(&"  ".repeat(depth));
+        }
+    };
+    let mut chars = content.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                out.push(c);
+                while let Some(c) = chars.next() {
+                    out.push(c);
+                    match c {
+                        '\\' => out.extend(chars.next()),
+                        '"' => break,
+                        _ => {}
+                    }
+                }
+            }
+            '{' | '[' => {
+                out.push(c);
+                while chars.next_if(|c| c.is_whitespace()).is_some() {}
+                if let Some(close) = chars.next_if(|&next| matches!(next, '}' | ']')) {
+                    out.push(close);
+                } else {
+                    depth += 1;
+                    newline(&mut out, depth);
+                }
+            }
+            '}' | ']' => {
+                depth -= 1;
+                newline(&mut out, depth);
+                out.push(c);
+            }
+            ',' => {
+                out.push(c);
+                newline(&mut out, depth);
+            }
+            ':' => out.push_str(if pretty { ": " } else { ":" }),
+            c if c.is_whitespace() => {}
+            c => out.push(c),
+        }
+    }
+    if pretty {
+        out.push('\n');
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        assert_eq!(check("json", r#"{"a": [1, 2]}"#), Some(Ok(())));
+        let err = check("json", "{\n  \"a\": 1,\n  \"b\": \n}")
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(err.line, Some(4));
+        assert!(err.message.contains("line 4"), "{}", err.message);
+
+        assert_eq!(check("yaml", "a: 1\n---\nb: [2, 3]\n"), Some(Ok(())));
+        let err = check("yaml", "a: 1\nb: [2, 3\nc: 4\n")
+            .unwrap()
+            .unwrap_err();
+        assert!(err.line.is_some_and(|line| line >= 2), "{:?}", err);
+
+        assert_eq!(check("rust", "fn main() {"), None);
+        let huge = format!("[{}0]", "0,".repeat(MAX_CHECK_BYTES / 2));
+        assert_eq!(check("json", &huge), None);
+    }
+
+    #[test]
+    fn test_reformat_json() {
+        let stored = r#"{"b": 1.50, "a": ["x, \"y\"", {}, [ ]], "c": {"d": null}}"#;
+        let pretty = reformat_json(stored, true).unwrap();
+        assert_eq!(
+            pretty,
+            "{\n  \"b\": 1.50,\n  \"a\": [\n    \"x, \\\"y\\\"\",\n    {},\n    []\n  ],\n  \"c\": {\n    \"d\": null\n  }\n}\n"
+        );
+        let minified = reformat_json(&pretty, false).unwrap();
+        assert_eq!(
+            minified,
+            r#"{"b":1.50,"a":["x, \"y\"",{},[]],"c":{"d":null}}"#
+        );
+        assert_eq!(reformat_json(&minified, true).unwrap(), pretty);
+        assert_eq!(reformat_json("{\"a\": }", true), None);
+    }
+}
@@ -0,0 +1,288 @@
+//! Fixtures shared by handler tests.
+
+use crate::activity::ActivityCache;
+use crate::config::read_toml;
+use crate::counts::{PublicCountCache, UsageCache};
+use crate::crypto::ContentCipher;
+use crate::db::{self, ensure_schema};
+use crate::featured::FeaturedCache;
+use crate::maintenance::MaintenanceLog;
+use crate::metrics::Metrics;
+use crate::models::{AppConfig, AppState, CreatedPaste, I18n, Lang, NewFile, NewPaste, Strings};
+use crate::paste_cache::PasteCache;
+use crate::rate_limit::RateLimiter;
+use crate::receipts::SpentReceipts;
+use crate::utils::{Clock, SystemClock, now_ts};
+use arc_swap::ArcSwap;
+use axum::Router;
+use axum::body::Body;
+use axum::extract::ConnectInfo;
+use axum::http::Request;
+use axum::response::Response;
+use sqlx::SqlitePool;
+use sqlx::sqlite::SqlitePoolOptions;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicI64, Ordering};
+use tower::ServiceExt;
+
+/// An `AppState` backed by a fresh in-memory database, the example config and
+/// the shipped locale files.
+pub async fn test_state() -> AppState {
+    // A single, never-recycled connection: every new connection to
+    // `sqlite::memory:` would otherwise open an empty database.
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .idle_timeout(None)
+        .max_lifetime(None)
+        .connect("sqlite::memory:")
+        .await
+        .unwrap();
+    ensure_schema(&pool).await;
+    let config = test_config();
+    let reports_per_hour = config.paste.reports_per_hour;
+    let reactions_per_minute = config.paste.reactions_per_minute;
+    let i18n = I18n {
+        locales: Lang::ALL
+            .into_iter()
+            .map(|lang| (lang, test_strings(lang)))
+            .collect(),
+    };
+    AppState {
+        pool,
+        config: Arc::new(ArcSwap::from_pointee(config)),
+        i18n: Arc::new(ArcSwap::from_pointee(i18n)),
+        config_path: "config/app.toml.example".to_string(),
+        cipher: None,
+        signing_key: crate::crypto::signing_key(None).unwrap(),
+        report_limiter: Arc::new(RateLimiter::new(reports_per_hour, 3600)),
+        reaction_limiter: Arc::new(RateLimiter::new(reactions_per_minute, 60)),
+        slug_limiter: Arc::new(RateLimiter::new(crate::slug::CHECKS_PER_MINUTE, 60)),
+        metrics: Arc::new(Metrics::default()),
+        captcha: None,
+        about: Arc::new(ArcSwap::default()),
+        activity: Arc::new(ActivityCache::default()),
+        featured: Arc::new(FeaturedCache::default()),
+        public_counts: Arc::new(PublicCountCache::default()),
+        usage: Arc::new(UsageCache::default()),
+        maintenance: Arc::new(MaintenanceLog::default()),
+        paste_cache: Arc::new(PasteCache::default()),
+        spent_receipts: Arc::new(SpentReceipts::default()),
+        clock: Arc::new(SystemClock),
+    }
+}
+
+/// A clock that starts at the real time and only moves when told to.
+pub struct MockClock(AtomicI64);
+
+impl MockClock {
+    pub fn new() -> Arc<Self> {
+        Arc::new(MockClock(AtomicI64::new(now_ts())))
+    }
+
+    pub fn advance(&self, secs: i64) {
+        self.0.fetch_add(secs, Ordering::SeqCst);
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> i64 {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// The example config, which is kept valid and complete.
+pub fn test_config() -> AppConfig {
+    read_toml("config/app.toml.example")
+}
+
+/// The shipped locale file for `lang`.
+pub fn test_strings(lang: Lang) -> Strings {
+    read_toml(&format!("config/i18n/{}.toml", lang.code()))
+}
+
+/// A paste for a test to store. Starts as a private plaintext paste with an
+/// hour to live and a six-character token, so a test only sets what it is
+/// about:
+///
+/// `PasteBuilder::new().public(true).language("rust").expires_in(-60).insert(&pool).await`
+pub struct PasteBuilder {
+    paste: NewPaste,
+    cipher: Option<ContentCipher>,
+    token: Option<String>,
+    created_at: Option<i64>,
+    views: Option<i64>,
+}
+
+impl Default for PasteBuilder {
+    fn default() -> Self {
+        PasteBuilder {
+            paste: NewPaste {
+                title: "Test".to_string(),
+                content: "hello".to_string(),
+                expires_at: now_ts() + 3600,
+                original_duration: 3600,
+                token_length: 6,
+                slug: None,
+                forked_from: None,
+                filename: None,
+                files: Vec::new(),
+                language: "plaintext".to_string(),
+                ..Default::default()
+            },
+            cipher: None,
+            token: None,
+            created_at: None,
+            views: None,
+        }
+    }
+}
+
+impl PasteBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Store under this token instead of a random one.
+    pub fn token(mut self, token: &str) -> Self {
+        self.token = Some(token.to_string());
+        self
+    }
+
+    pub fn title(mut self, title: &str) -> Self {
+        self.paste.title = title.to_string();
+        self
+    }
+
+    pub fn content(mut self, content: &str) -> Self {
+        self.paste.content = content.to_string();
+        self
+    }
+
+    /// Seconds from now until it expires; negative for one already expired.
+    pub fn expires_in(mut self, secs: i64) -> Self {
+        self.paste.expires_at = now_ts() + secs;
+        self
+    }
+
+    /// The lifespan it started with, which renewals and life stages go by.
+    pub fn lifespan(mut self, secs: i64) -> Self {
+        self.paste.original_duration = secs;
+        self
+    }
+
+    pub fn max_views(mut self, max_views: i64) -> Self {
+        self.paste.max_views = Some(max_views);
+        self
+    }
+
+    pub fn public(mut self, is_public: bool) -> Self {
+        self.paste.is_public = is_public;
+        self
+    }
+
+    pub fn language(mut self, language: &str) -> Self {
+        self.paste.language = language.to_string();
+        self
+    }
+
+    pub fn destroy_after_first_view(mut self, secs: i64) -> Self {
+        self.paste.destroy_after_first_view_secs = Some(secs);
+        self
+    }
+
+    /// The client hash its `created` event records.
+    pub fn client_hash(mut self, client_hash: &str) -> Self {
+        self.paste.client_hash = Some(client_hash.to_string());
+        self
+    }
+
+    /// Add a further file, gist-style. The content so far becomes the
+    /// first file, `main.txt` unless named otherwise.
+    pub fn file(mut self, filename: &str, content: &str) -> Self {
+        self.paste
+            .filename
+            .get_or_insert_with(|| "main.txt".to_string());
+        self.paste.files.push(NewFile {
+            filename: filename.to_string(),
+            language: "plaintext".to_string(),
+            content: content.to_string(),
+        });
+        self
+    }
+
+    /// Encrypt the content at rest, as `encryption_key` does.
+    pub fn encrypted(mut self, cipher: &ContentCipher) -> Self {
+        self.cipher = Some(cipher.clone());
+        self
+    }
+
+    pub fn created_at(mut self, created_at: i64) -> Self {
+        self.created_at = Some(created_at);
+        self
+    }
+
+    /// Views already counted.
+    pub fn views(mut self, views: i64) -> Self {
+        self.views = Some(views);
+        self
+    }
+
+    /// Store it through `db::insert_paste`, then set what that leaves to
+    /// the database.
+    pub async fn insert(self, pool: &SqlitePool) -> CreatedPaste {
+        let mut created = db::insert_paste(pool, self.paste, self.cipher.as_ref())
+            .await
+            .unwrap();
+        if let Some(token) = self.token {
+            for table in ["pastes", "events"] {
+                sqlx::query(&format!("UPDATE {} SET token = ? WHERE token = ?", table))
+                    .bind(&token)
+                    .bind(&created.token)
+                    .execute(pool)
+                    .await
+                    .unwrap();
+            }
+            created.token = token;
+        }
+        sqlx::query(
+            "UPDATE pastes SET created_at = COALESCE(?, created_at), views = COALESCE(?, views) WHERE token = ?",
+        )
+        .bind(self.created_at)
+        .bind(self.views)
+        .bind(&created.token)
+        .execute(pool)
+        .await
+        .unwrap();
+        created
+    }
+}
+
+/// Change the running config in place, as a reload would.
+pub fn update_config(state: &AppState, update: impl FnOnce(&mut AppConfig)) {
+    let mut config = AppConfig::clone(&state.config.load());
+    update(&mut config);
+    state.config.store(Arc::new(config));
+}
+
+/// Send `request` through `app` as a client on the loopback address would,
+/// unless the request already names its peer.
+pub async fn send(app: &Router, mut request: Request<Body>) -> Response {
+    if request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .is_none()
+    {
+        let peer: SocketAddr = "127.0.0.1:4000".parse().unwrap();
+        request.extensions_mut().insert(ConnectInfo(peer));
+    }
+    app.clone().oneshot(request).await.unwrap()
+}
+
+/// Collect a response body into a `String`.
+pub async fn body_string(response: axum::response::Response) -> String {
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    String::from_utf8(bytes.to_vec()).unwrap()
+}
@@ -0,0 +1,66 @@
+//! Line, word, character and byte counts of a paste, for the detail page,
+//! the manage page and the metadata API.
+
+use crate::models::ContentStats;
+
+/// Ideographs and kana: each one counts as a word, as the text around
+/// them has no spaces to split on. Hangul is spaced and counts like Latin.
+fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{3040}'..='\u{30FF}'
+        | '\u{3400}'..='\u{4DBF}'
+        | '\u{4E00}'..='\u{9FFF}'
+        | '\u{F900}'..='\u{FAFF}'
+        | '\u{20000}'..='\u{2EBEF}'
+    )
+}
+
+/// Count `content` the way an editor would: lines without an extra one for
+/// a trailing newline, words as runs between whitespace that hold a letter
+/// or digit (so `don't` and `foo-bar` are one word and a lone `-` none),
+/// with every CJK character a word of its own.
+pub fn content_stats(content: &str) -> ContentStats {
+    let mut words = 0;
+    // Whether the run since the last break holds a letter or digit.
+    let mut in_word = false;
+    for c in content.chars() {
+        if is_cjk(c) {
+            words += usize::from(in_word) + 1;
+            in_word = false;
+        } else if c.is_whitespace() {
+            words += usize::from(in_word);
+            in_word = false;
+        } else {
+            in_word |= c.is_alphanumeric();
+        }
+    }
+    words += usize::from(in_word);
+    ContentStats {
+        lines: content.lines().count() as i64,
+        words: words as i64,
+        chars: content.chars().count() as i64,
+        bytes: content.len() as i64,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counts(content: &str) -> (i64, i64, i64, i64) {
+        let stats = content_stats(content);
+        (stats.lines, stats.words, stats.chars, stats.bytes)
+    }
+
+    #[test]
+    fn test_content_stats() {
+        assert_eq!(counts(""), (0, 0, 0, 0));
+        assert_eq!(counts("one line\n"), (1, 2, 9, 9));
+        assert_eq!(
+            counts("Hello, world! 你好，世界。\nSecond line"),
+            (2, 8, 32, 44)
+        );
+        assert_eq!(counts("don't foo-bar - …"), (1, 2, 17, 19));
+        assert_eq!(counts("日本語のテキスト and more"), (1, 10, 17, 33));
+    }
+}
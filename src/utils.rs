@@ -1,19 +1,27 @@
 use rand::{TryRngCore, rngs::OsRng};
+use sha2::{Digest, Sha256};
 use std::path::PathBuf;
 use std::time::{SystemTime, UNIX_EPOCH};
 
+const TOKEN_ALPHABET: &[u8] = b"0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
+
 /// Generate a random alphanumeric token of the given length.
 pub fn generate_token(length: usize) -> String {
-    let alphabet = b"0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
     let mut bytes = vec![0u8; length];
     let mut rng = OsRng;
     rng.try_fill_bytes(&mut bytes).unwrap();
     bytes
         .into_iter()
-        .map(|value| alphabet[(value % 62) as usize] as char)
+        .map(|value| TOKEN_ALPHABET[value as usize % TOKEN_ALPHABET.len()] as char)
         .collect()
 }
 
+/// Share of the tokens of `length` that `count` pastes take up: the chance
+/// a freshly generated token is already in use.
+pub fn token_occupancy(count: i64, length: usize) -> f64 {
+    count.max(0) as f64 / (TOKEN_ALPHABET.len() as f64).powi(length as i32)
+}
+
 /// Get the current timestamp in seconds.
 pub fn now_ts() -> i64 {
     SystemTime::now()
@@ -22,6 +30,166 @@ pub fn now_ts() -> i64 {
         .as_secs() as i64
 }
 
+/// Where handlers and background tasks get the current time, so every
+/// timestamp bound into SQL comes from one place and tests can move it.
+pub trait Clock: Send + Sync {
+    /// Seconds since the Unix epoch.
+    fn now(&self) -> i64;
+}
+
+/// The wall clock, via `now_ts`.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> i64 {
+        now_ts()
+    }
+}
+
+/// Hex-encoded SHA-256 digest of the given content.
+pub fn sha256_hex(content: &str) -> String {
+    hex::encode(Sha256::digest(content.as_bytes()))
+}
+
+/// Parse a fixed UTC offset such as `UTC`, `Z`, `+08:00`, `-0530` or
+/// `UTC+8` into seconds east of UTC.
+pub fn parse_utc_offset(value: &str) -> Result<i32, String> {
+    let invalid = || format!("{} is not a UTC offset such as +08:00", value);
+    let rest = value.trim();
+    let rest = rest
+        .strip_prefix("UTC")
+        .or_else(|| rest.strip_prefix("GMT"))
+        .unwrap_or(rest);
+    if rest.is_empty() || rest == "Z" {
+        return Ok(0);
+    }
+    let (sign, rest) = match rest.split_at(1) {
+        ("+", rest) => (1, rest),
+        ("-", rest) => (-1, rest),
+        _ => return Err(invalid()),
+    };
+    let (hours, minutes) = match rest.split_once(':') {
+        Some((hours, minutes)) => (hours, minutes),
+        None if rest.len() == 4 => rest.split_at(2),
+        None => (rest, "0"),
+    };
+    let digits = |text: &str| {
+        (!text.is_empty() && text.len() <= 2 && text.bytes().all(|b| b.is_ascii_digit()))
+            .then(|| text.parse::<i32>().ok())
+            .flatten()
+    };
+    match (digits(hours), digits(minutes)) {
+        (Some(hours), Some(minutes)) if hours <= 14 && minutes < 60 => {
+            Ok(sign * (hours * 3600 + minutes * 60))
+        }
+        _ => Err(invalid()),
+    }
+}
+
+/// Render a Unix timestamp as ISO 8601 at the given offset, e.g.
+/// `2024-05-01T20:30:00+08:00`, or with `Z` at UTC.
+pub fn format_iso8601(ts: i64, offset_secs: i32) -> String {
+    let local = ts + offset_secs as i64;
+    let (days, secs) = (local.div_euclid(86400), local.rem_euclid(86400));
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm).
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    let offset = if offset_secs == 0 {
+        "Z".to_string()
+    } else {
+        let sign = if offset_secs < 0 { '-' } else { '+' };
+        let abs = offset_secs.abs();
+        format!("{}{:02}:{:02}", sign, abs / 3600, abs % 3600 / 60)
+    };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}{}",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60,
+        offset
+    )
+}
+
+/// Parse a local date and time such as `2024-05-01T20:30` (seconds
+/// optional, as sent by `<input type="datetime-local">`) at the given
+/// offset into a Unix timestamp.
+pub fn parse_local_datetime(value: &str, offset_secs: i32) -> Option<i64> {
+    let (date, time) = value.trim().split_once(['T', ' '])?;
+    let number = |text: &str| {
+        (!text.is_empty() && text.bytes().all(|b| b.is_ascii_digit()))
+            .then(|| text.parse::<i64>().ok())
+            .flatten()
+    };
+    let mut date = date.splitn(3, '-').map(number);
+    let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+    let mut time = time.splitn(3, ':').map(number);
+    let (hour, minute) = (time.next()??, time.next()??);
+    let second = time.next().unwrap_or(Some(0))?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    if hour > 23 || minute > 59 || second > 59 {
+        return None;
+    }
+    // Days since 1970-01-01 from a civil date (Howard Hinnant's algorithm).
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+    let ts = days * 86400 + hour * 3600 + minute * 60 + second - offset_secs as i64;
+    // Reject dates such as February 30th that roll over.
+    (format_iso8601(ts, offset_secs)[..10] == format!("{:04}-{:02}-{:02}", year, month, day))
+        .then_some(ts)
+}
+
+/// Parse an RFC 3339 timestamp such as `2024-05-01T20:30:00+08:00` or
+/// `2024-05-01T12:30:00.5Z` into a Unix timestamp. Fractions of a second
+/// are dropped.
+pub fn parse_rfc3339(value: &str) -> Option<i64> {
+    let value = value.trim();
+    let (date, time) = value.split_at(value.find(['T', 't'])?);
+    let time = &time[1..];
+    let (time, offset) = match time.strip_suffix(['Z', 'z']) {
+        Some(time) => (time, 0),
+        None => {
+            let at = time.rfind(['+', '-'])?;
+            (&time[..at], parse_utc_offset(&time[at..]).ok()?)
+        }
+    };
+    let time = time.split_once('.').map_or(time, |(whole, _)| whole);
+    if time.len() != 8 {
+        return None;
+    }
+    parse_local_datetime(&format!("{}T{}", date, time), offset)
+}
+
+/// `bytes` for people: plain bytes below a KiB, then KiB or MiB with one
+/// decimal.
+pub fn format_bytes(bytes: usize) -> String {
+    const KIB: f64 = 1024.0;
+    let size = bytes as f64;
+    if bytes < 1024 {
+        format!("{} B", bytes)
+    } else if size < KIB * KIB {
+        format!("{:.1} KiB", size / KIB)
+    } else {
+        format!("{:.1} MiB", size / (KIB * KIB))
+    }
+}
+
 /// Resolve a relative path against the current working directory.
 pub fn resolve_path(path: &str) -> PathBuf {
     let raw = PathBuf::from(path);
@@ -36,12 +204,30 @@ pub fn resolve_path(path: &str) -> PathBuf {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_format_bytes() {
+        assert_eq!(format_bytes(0), "0 B");
+        assert_eq!(format_bytes(1023), "1023 B");
+        assert_eq!(format_bytes(1024), "1.0 KiB");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+        assert_eq!(format_bytes(5 * 1024 * 1024 + 512 * 1024), "5.5 MiB");
+    }
+
     #[test]
     fn test_generate_token_length() {
         assert_eq!(generate_token(8).len(), 8);
         assert_eq!(generate_token(16).len(), 16);
     }
 
+    proptest::proptest! {
+        #[test]
+        fn prop_generate_token_matches_request(length in 0usize..64) {
+            let token = generate_token(length);
+            proptest::prop_assert_eq!(token.chars().count(), length);
+            proptest::prop_assert!(token.chars().all(|c| c.is_ascii_alphanumeric()));
+        }
+    }
+
     #[test]
     fn test_generate_token_uniqueness() {
         let t1 = generate_token(10);
@@ -49,6 +235,80 @@ mod tests {
         assert_ne!(t1, t2);
     }
 
+    #[test]
+    fn test_sha256_hex() {
+        assert_eq!(
+            sha256_hex("abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn test_parse_utc_offset() {
+        for (value, expected) in [
+            ("UTC", 0),
+            ("Z", 0),
+            ("+08:00", 8 * 3600),
+            ("-0530", -(5 * 3600 + 30 * 60)),
+            ("UTC+8", 8 * 3600),
+            ("GMT-3", -3 * 3600),
+        ] {
+            assert_eq!(parse_utc_offset(value), Ok(expected), "{}", value);
+        }
+        for value in ["Asia/Shanghai", "+8:60", "+15", "08:00", "+"] {
+            assert!(parse_utc_offset(value).is_err(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn test_format_iso8601() {
+        assert_eq!(format_iso8601(0, 0), "1970-01-01T00:00:00Z");
+        assert_eq!(format_iso8601(951782400, 0), "2000-02-29T00:00:00Z");
+        assert_eq!(
+            format_iso8601(1714566600, 8 * 3600),
+            "2024-05-01T20:30:00+08:00"
+        );
+        assert_eq!(
+            format_iso8601(1714566600, -(9 * 3600 + 30 * 60)),
+            "2024-05-01T03:00:00-09:30"
+        );
+    }
+
+    #[test]
+    fn test_parse_local_datetime() {
+        assert_eq!(parse_local_datetime("1970-01-01T00:00", 0), Some(0));
+        assert_eq!(
+            parse_local_datetime("2024-05-01T20:30", 8 * 3600),
+            Some(1714566600)
+        );
+        assert_eq!(
+            parse_local_datetime("2000-02-29 00:00:00", 0),
+            Some(951782400)
+        );
+        for value in ["2023-02-29T00:00", "2024-13-01T00:00", "2024-05-01", "soon"] {
+            assert_eq!(parse_local_datetime(value, 0), None, "{}", value);
+        }
+    }
+
+    #[test]
+    fn test_parse_rfc3339() {
+        for value in [
+            "2024-05-01T20:30:00+08:00",
+            "2024-05-01T12:30:00Z",
+            "2024-05-01t12:30:00.250z",
+            "2024-05-01T03:00:00-09:30",
+        ] {
+            assert_eq!(parse_rfc3339(value), Some(1714566600), "{}", value);
+        }
+        for value in [
+            "2024-05-01T12:30:00",
+            "2024-05-01T12:30Z",
+            "2024-05-01 12:30:00Z",
+        ] {
+            assert_eq!(parse_rfc3339(value), None, "{}", value);
+        }
+    }
+
     #[test]
     fn test_now_ts() {
         let t1 = now_ts();
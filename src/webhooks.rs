@@ -0,0 +1,316 @@
+//! Webhooks for pastes that expire or burn after their last view. The
+//! deletion queues a row in `webhook_queue` in the same transaction, and
+//! `run_sender` drains the queue in the background, retrying failures with
+//! exponential backoff until `max_attempts` dead-letters them.
+
+use crate::db;
+use crate::models::{AppState, WebhookConfig, WebhookDelivery};
+use futures_util::{StreamExt, stream};
+use serde_json::json;
+use std::time::Duration;
+
+/// How often the queue is checked for due deliveries.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Deliveries taken from the queue per pass.
+const BATCH: i64 = 100;
+
+/// The longest a retry waits, however many attempts came before.
+const MAX_BACKOFF_SECS: i64 = 6 * 3600;
+
+/// Seconds to wait after the `attempts`-th failure: `backoff_secs`, then
+/// twice that for each further failure, capped at `MAX_BACKOFF_SECS`.
+fn backoff(backoff_secs: i64, attempts: i64) -> i64 {
+    let doublings = (attempts - 1).clamp(0, 30) as u32;
+    backoff_secs
+        .max(1)
+        .saturating_mul(1 << doublings)
+        .min(MAX_BACKOFF_SECS)
+}
+
+pub fn client(config: &WebhookConfig) -> reqwest::Client {
+    reqwest::Client::builder()
+        .timeout(Duration::from_secs(config.timeout_secs))
+        .build()
+        .expect("Failed to build HTTP client")
+}
+
+async fn post(
+    client: &reqwest::Client,
+    url: &str,
+    delivery: &WebhookDelivery,
+) -> Result<(), String> {
+    let body = json!({
+        "event": delivery.event,
+        "token": delivery.token,
+        "at": delivery.created_at,
+    });
+    let response = client
+        .post(url)
+        .json(&body)
+        .send()
+        .await
+        .map_err(|err| err.to_string())?;
+    if response.status().is_success() {
+        Ok(())
+    } else {
+        Err(format!("HTTP {}", response.status().as_u16()))
+    }
+}
+
+/// Send the deliveries due by `now`, `config.concurrency` at a time.
+/// Returns how many were delivered.
+pub async fn deliver_due(
+    state: &AppState,
+    client: &reqwest::Client,
+    config: &WebhookConfig,
+    now: i64,
+) -> Result<usize, sqlx::Error> {
+    let due = db::due_webhooks(&state.pool, now, BATCH).await?;
+    let delivered = stream::iter(due)
+        .map(|delivery| async move {
+            let result = post(client, &config.url, &delivery).await;
+            let stored = match &result {
+                Ok(()) => db::delete_webhook(&state.pool, delivery.id).await,
+                Err(err) => {
+                    let attempts = delivery.attempts + 1;
+                    let dead = attempts >= config.max_attempts;
+                    if dead {
+                        eprintln!(
+                            "Webhook for {} {} gave up after {} attempts: {}",
+                            delivery.event, delivery.token, attempts, err
+                        );
+                    }
+                    let next = now + backoff(config.backoff_secs, attempts);
+                    state.metrics.record_webhook(false, dead);
+                    db::fail_webhook(&state.pool, delivery.id, err, next, dead).await
+                }
+            };
+            if let Err(err) = stored {
+                eprintln!("Recording webhook {} failed: {}", delivery.id, err);
+            }
+            let delivered = result.is_ok();
+            if delivered {
+                state.metrics.record_webhook(true, false);
+            }
+            delivered
+        })
+        .buffer_unordered(config.concurrency.max(1))
+        .filter(|delivered| std::future::ready(*delivered))
+        .count()
+        .await;
+    Ok(delivered)
+}
+
+/// Drain the queue for as long as the process runs. Without `[webhooks]`
+/// queued deliveries are dropped, so the table does not grow; a reload
+/// that adds the section starts sending from then on.
+pub async fn run_sender(state: AppState) {
+    let mut current: Option<(String, u64, reqwest::Client)> = None;
+    loop {
+        let config = state.config.load().webhooks.clone();
+        match config {
+            Some(config) => {
+                let stale = current.as_ref().is_none_or(|(url, timeout, _)| {
+                    *url != config.url || *timeout != config.timeout_secs
+                });
+                if stale {
+                    current = Some((config.url.clone(), config.timeout_secs, client(&config)));
+                }
+                let (_, _, client) = current.as_ref().expect("client built above");
+                if let Err(err) = deliver_due(&state, client, &config, state.clock.now()).await {
+                    eprintln!("Webhook delivery failed: {}", err);
+                }
+            }
+            None => {
+                if let Err(err) = db::clear_webhooks(&state.pool).await {
+                    eprintln!("Clearing webhooks failed: {}", err);
+                }
+            }
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{PasteBuilder, test_state};
+    use crate::utils::now_ts;
+    use axum::Router;
+    use axum::extract::State;
+    use axum::http::StatusCode;
+    use axum::routing::post;
+    use serde_json::Value;
+    use std::sync::{Arc, Mutex};
+
+    /// Answers 500 to the first `failures` requests, then 200, keeping the
+    /// bodies it accepted.
+    #[derive(Clone, Default)]
+    struct Endpoint {
+        failures: usize,
+        seen: Arc<Mutex<usize>>,
+        accepted: Arc<Mutex<Vec<Value>>>,
+    }
+
+    async fn receive(
+        State(endpoint): State<Endpoint>,
+        axum::Json(body): axum::Json<Value>,
+    ) -> StatusCode {
+        let mut seen = endpoint.seen.lock().unwrap();
+        *seen += 1;
+        if *seen <= endpoint.failures {
+            return StatusCode::INTERNAL_SERVER_ERROR;
+        }
+        endpoint.accepted.lock().unwrap().push(body);
+        StatusCode::OK
+    }
+
+    async fn serve(endpoint: Endpoint) -> WebhookConfig {
+        let app = Router::new()
+            .route("/hook", post(receive))
+            .with_state(endpoint);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        WebhookConfig {
+            url: format!("http://{}/hook", addr),
+            max_attempts: 8,
+            concurrency: 4,
+            backoff_secs: 10,
+            timeout_secs: 5,
+        }
+    }
+
+    async fn burn(state: &AppState, token: &str) {
+        PasteBuilder::new()
+            .token(token)
+            .max_views(1)
+            .insert(&state.pool)
+            .await;
+        let rules = db::ViewRules {
+            webhooks: true,
+            ..db::ViewRules::default()
+        };
+        db::consume_view(&state.pool, token, &rules, None, false, now_ts())
+            .await
+            .unwrap();
+    }
+
+    #[test]
+    fn test_backoff() {
+        assert_eq!(backoff(10, 1), 10);
+        assert_eq!(backoff(10, 2), 20);
+        assert_eq!(backoff(10, 4), 80);
+        assert_eq!(backoff(10, 100), MAX_BACKOFF_SECS);
+    }
+
+    #[tokio::test]
+    async fn test_failing_endpoint_recovers_in_order() {
+        let state = test_state().await;
+        let endpoint = Endpoint {
+            failures: 3,
+            ..Endpoint::default()
+        };
+        let config = serve(endpoint.clone()).await;
+        let client = client(&config);
+
+        // The same slug burns, is created again, and then expires
+        burn(&state, "notes").await;
+        PasteBuilder::new()
+            .token("notes")
+            .expires_in(-1)
+            .insert(&state.pool)
+            .await;
+        PasteBuilder::new()
+            .token("other")
+            .expires_in(-1)
+            .insert(&state.pool)
+            .await;
+        db::cleanup_expired(&state.pool, 86400, true, now_ts()).await;
+
+        let mut now = now_ts();
+        let mut delivered = 0;
+        for _ in 0..10 {
+            delivered += deliver_due(&state, &client, &config, now).await.unwrap();
+            now += MAX_BACKOFF_SECS;
+        }
+        assert_eq!(delivered, 3);
+
+        let accepted = endpoint.accepted.lock().unwrap().clone();
+        let events = |token: &str| -> Vec<String> {
+            accepted
+                .iter()
+                .filter(|body| body["token"] == token)
+                .map(|body| body["event"].as_str().unwrap().to_string())
+                .collect()
+        };
+        assert_eq!(events("notes"), ["burned", "expired"]);
+        assert_eq!(events("other"), ["expired"]);
+
+        let status = state.metrics.webhook_status();
+        assert_eq!(status.delivered, 3);
+        assert_eq!(status.failed, 3);
+        assert_eq!(status.dead_lettered, 0);
+        assert!(
+            db::due_webhooks(&state.pool, now, BATCH)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dead_letter_and_retry() {
+        let state = test_state().await;
+        let endpoint = Endpoint {
+            failures: 2,
+            ..Endpoint::default()
+        };
+        let config = WebhookConfig {
+            max_attempts: 2,
+            ..serve(endpoint.clone()).await
+        };
+        let client = client(&config);
+        burn(&state, "gone").await;
+
+        let now = now_ts();
+        assert_eq!(deliver_due(&state, &client, &config, now).await.unwrap(), 0);
+        // Not due again until the backoff has passed
+        assert_eq!(deliver_due(&state, &client, &config, now).await.unwrap(), 0);
+        assert_eq!(*endpoint.seen.lock().unwrap(), 1);
+        let later = now + config.backoff_secs;
+        assert_eq!(
+            deliver_due(&state, &client, &config, later).await.unwrap(),
+            0
+        );
+
+        let dead = db::list_dead_webhooks(&state.pool).await.unwrap();
+        assert_eq!(dead.len(), 1);
+        assert_eq!(dead[0].token, "gone");
+        assert_eq!(dead[0].attempts, 2);
+        assert_eq!(dead[0].last_error.as_deref(), Some("HTTP 500"));
+        assert_eq!(state.metrics.webhook_status().dead_lettered, 1);
+        let far = later + MAX_BACKOFF_SECS;
+        assert_eq!(deliver_due(&state, &client, &config, far).await.unwrap(), 0);
+
+        assert!(
+            db::retry_webhook(&state.pool, dead[0].id, now)
+                .await
+                .unwrap()
+        );
+        assert!(
+            !db::retry_webhook(&state.pool, dead[0].id, now)
+                .await
+                .unwrap()
+        );
+        assert_eq!(deliver_due(&state, &client, &config, far).await.unwrap(), 1);
+        assert!(
+            db::list_dead_webhooks(&state.pool)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(endpoint.accepted.lock().unwrap()[0]["event"], "burned");
+    }
+}